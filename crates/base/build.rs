@@ -15,12 +15,19 @@ mod supabase_startup_snapshot {
     use sb_core::http::sb_core_http;
     use sb_core::http_start::sb_core_http_start;
     use sb_core::net::sb_core_net;
-    use sb_core::permissions::sb_core_permissions;
+    use sb_core::permissions::{sb_core_permissions, PermissionsOptions};
     use sb_core::runtime::sb_core_runtime;
     use sb_core::sb_core_main_js;
     use sb_core::transpiler::maybe_transpile_source;
+    use sb_crypto::sb_crypto;
+    use sb_db::sb_db;
     use sb_env::sb_env;
+    use sb_geoip::sb_geoip;
+    use sb_html::sb_html;
+    use sb_image::sb_image;
     use sb_node::deno_node;
+    use sb_publish::sb_publish;
+    use sb_redis::sb_redis;
     use sb_workers::sb_user_workers;
     use std::path::Path;
     use std::sync::Arc;
@@ -172,7 +179,7 @@ mod supabase_startup_snapshot {
         let user_agent = String::from("supabase");
         let fs = Arc::new(deno_fs::RealFs);
         let mut extensions: Vec<Extension> = vec![
-            sb_core_permissions::init_ops_and_esm(false),
+            sb_core_permissions::init_ops_and_esm(PermissionsOptions::default()),
             deno_webidl::deno_webidl::init_ops_and_esm(),
             deno_console::deno_console::init_ops_and_esm(),
             deno_url::deno_url::init_ops_and_esm(),
@@ -199,6 +206,13 @@ mod supabase_startup_snapshot {
             deno_io::deno_io::init_ops_and_esm(Some(Default::default())),
             deno_fs::deno_fs::init_ops_and_esm::<Permissions>(fs.clone()),
             sb_ai::init_ops_and_esm(),
+            sb_db::init_ops_and_esm(),
+            sb_image::init_ops_and_esm(),
+            sb_crypto::init_ops_and_esm(),
+            sb_html::init_ops_and_esm(),
+            sb_geoip::init_ops_and_esm(),
+            sb_redis::init_ops_and_esm(),
+            sb_publish::init_ops_and_esm(),
             sb_env::init_ops_and_esm(),
             sb_os::sb_os::init_ops_and_esm(),
             sb_user_workers::init_ops_and_esm(),
@@ -242,5 +256,8 @@ fn main() {
     // Main snapshot
     let runtime_snapshot_path = o.join("RUNTIME_SNAPSHOT.bin");
 
-    supabase_startup_snapshot::create_runtime_snapshot(runtime_snapshot_path)
+    supabase_startup_snapshot::create_runtime_snapshot(runtime_snapshot_path);
+
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/pool.proto").unwrap();
 }
@@ -0,0 +1,434 @@
+//! Logs every request served by the listeners, one line each, without going
+//! through the events worker.
+//!
+//! A line is written once the body of the response has been sent, or dropped
+//! because the client went away, so that it carries how many bytes of it were
+//! sent and how long serving the request took overall. Lines are written off
+//! the runtime by a thread of their own, and dropped rather than holding up
+//! requests if it falls behind.
+
+use std::convert::Infallible;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::time::Instant;
+
+use anyhow::{anyhow, Context, Error};
+use chrono::{DateTime, SecondsFormat, Utc};
+use deno_core::serde_json;
+use futures_util::TryStreamExt;
+use http::header::{HeaderName, HeaderValue, REFERER, USER_AGENT};
+use http::{Method, Request, Response, StatusCode, Uri, Version};
+use hyper::body::HttpBody;
+use hyper::Body;
+use log::error;
+use once_cell::sync::OnceCell;
+use sb_workers::context::RequestId;
+
+/// Set by the worker pool on the responses of user workers, to tell which
+/// worker served the request. It is stripped before the response is sent.
+pub const WORKER_KEY_HEADER: &str = "x-sb-worker-key";
+
+/// How many lines may wait to be written before new ones are dropped.
+const QUEUE_CAPACITY: usize = 8192;
+
+static ACCESS_LOG: OnceCell<AccessLog> = OnceCell::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// The combined log format, followed by the duration of the request in
+    /// milliseconds, the worker key and the request ID.
+    Combined,
+    /// A JSON object per line.
+    Json,
+}
+
+impl Default for AccessLogFormat {
+    fn default() -> Self {
+        Self::Combined
+    }
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "combined" => Ok(Self::Combined),
+            "json" => Ok(Self::Json),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AccessLogConfig {
+    pub format: AccessLogFormat,
+    /// Lines are written to stdout without one.
+    pub path: Option<PathBuf>,
+    /// The file is rotated once it would grow past this size. Ignored when
+    /// writing to stdout.
+    pub max_bytes: Option<u64>,
+    /// How many rotated files are kept, as `<PATH>.1` (the most recent one)
+    /// to `<PATH>.<N>`.
+    pub max_files: usize,
+}
+
+struct AccessLog {
+    format: AccessLogFormat,
+    line_tx: SyncSender<String>,
+}
+
+/// Starts writing the access log. Until then, requests aren't logged.
+pub fn init(config: AccessLogConfig) -> Result<(), Error> {
+    let sink = match config.path.as_deref() {
+        Some(path) => Sink::File(RotatingFile::open(
+            path,
+            config.max_bytes,
+            config.max_files,
+        )?),
+
+        None => Sink::Stdout(io::stdout()),
+    };
+
+    let (line_tx, line_rx) = mpsc::sync_channel(QUEUE_CAPACITY);
+
+    ACCESS_LOG
+        .set(AccessLog {
+            format: config.format,
+            line_tx,
+        })
+        .map_err(|_| anyhow!("access log is already initialized"))?;
+
+    std::thread::Builder::new()
+        .name("sb-access-log".into())
+        .spawn(move || write_lines(sink, line_rx))
+        .context("failed to start the access log writer")?;
+
+    Ok(())
+}
+
+fn write_lines(mut sink: Sink, line_rx: Receiver<String>) {
+    while let Ok(line) = line_rx.recv() {
+        let mut result = sink.write_line(&line);
+
+        // NOTE: Whatever else is queued is written before flushing.
+        while result.is_ok() {
+            match line_rx.try_recv() {
+                Ok(line) => result = sink.write_line(&line),
+                Err(_) => break,
+            }
+        }
+
+        if let Err(err) = result.and_then(|_| sink.flush()) {
+            error!("failed to write access log: {}", err);
+        }
+    }
+}
+
+enum Sink {
+    Stdout(io::Stdout),
+    File(RotatingFile),
+}
+
+impl Sink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Self::Stdout(it) => writeln!(it.lock(), "{}", line),
+            Self::File(it) => it.write_line(line),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Stdout(it) => it.flush(),
+            Self::File(it) => it.file.flush(),
+        }
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: BufWriter<File>,
+    len: u64,
+    max_bytes: Option<u64>,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_bytes: Option<u64>, max_files: usize) -> Result<Self, Error> {
+        let file = Self::open_file(path)
+            .with_context(|| format!("failed to open access log: {}", path.display()))?;
+        let len = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: BufWriter::new(file),
+            len,
+            max_bytes,
+            max_files,
+        })
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let line_len = line.len() as u64 + 1;
+
+        if self
+            .max_bytes
+            .is_some_and(|it| self.len > 0 && self.len + line_len > it)
+        {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.len += line_len;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.max_files == 0 {
+            self.file.get_ref().set_len(0)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                let from = self.rotated_path(n);
+
+                if from.exists() {
+                    fs::rename(from, self.rotated_path(n + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = BufWriter::new(Self::open_file(&self.path)?);
+        }
+
+        self.len = 0;
+
+        Ok(())
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+
+        path.push(format!(".{}", n));
+        path.into()
+    }
+}
+
+/// A request being served, logged once it is dropped. Requests that fail
+/// without a response aren't logged.
+pub(crate) struct PendingEntry {
+    client_ip: IpAddr,
+    time: DateTime<Utc>,
+    started_at: Instant,
+    method: Method,
+    uri: Uri,
+    version: Version,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    request_id: String,
+    status: Option<StatusCode>,
+    worker_key: Option<String>,
+    bytes: u64,
+}
+
+impl PendingEntry {
+    /// Returns `None` if the access log isn't enabled.
+    pub(crate) fn begin(req: &mut Request<Body>, client_ip: IpAddr) -> Option<Self> {
+        ACCESS_LOG.get()?;
+
+        let header = |name: HeaderName| {
+            req.headers()
+                .get(name)
+                .and_then(|it| it.to_str().ok())
+                .map(str::to_string)
+        };
+
+        let referer = header(REFERER);
+        let user_agent = header(USER_AGENT);
+
+        Some(Self {
+            client_ip: client_ip.to_canonical(),
+            time: Utc::now(),
+            started_at: Instant::now(),
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            version: req.version(),
+            referer,
+            user_agent,
+            request_id: RequestId::of(req).to_string(),
+            status: None,
+            worker_key: None,
+            bytes: 0,
+        })
+    }
+
+    /// Fills in the entry from the head of the response, and has it logged
+    /// once the body has been sent.
+    pub(crate) fn finish(
+        mut self,
+        res: Response<Body>,
+        maybe_worker_key: Option<HeaderValue>,
+    ) -> Response<Body> {
+        self.status = Some(res.status());
+        self.worker_key = maybe_worker_key
+            .as_ref()
+            .and_then(|it| it.to_str().ok())
+            .map(str::to_string);
+
+        let (parts, body) = res.into_parts();
+
+        if body.is_end_stream() {
+            return Response::from_parts(parts, body);
+        }
+
+        Response::from_parts(
+            parts,
+            Body::wrap_stream(body.inspect_ok(move |chunk| {
+                self.bytes += chunk.len() as u64;
+            })),
+        )
+    }
+
+    fn format(&self, format: AccessLogFormat) -> String {
+        let duration_ms = self.started_at.elapsed().as_secs_f64() * 1000.0;
+        let status = self.status.map_or(0, |it| it.as_u16());
+
+        match format {
+            AccessLogFormat::Combined => {
+                format!(
+                    "{} - - [{}] \"{} {} {:?}\" {} {} \"{}\" \"{}\" {:.3} {} {}",
+                    self.client_ip,
+                    self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+                    self.method,
+                    escape(&self.uri.to_string()),
+                    self.version,
+                    status,
+                    self.bytes,
+                    self.referer.as_deref().map_or("-".into(), escape),
+                    self.user_agent.as_deref().map_or("-".into(), escape),
+                    duration_ms,
+                    self.worker_key.as_deref().unwrap_or("-"),
+                    self.request_id,
+                )
+            }
+
+            AccessLogFormat::Json => serde_json::json!({
+                "time": self.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+                "clientIp": self.client_ip.to_string(),
+                "method": self.method.as_str(),
+                "uri": self.uri.to_string(),
+                "protocol": format!("{:?}", self.version),
+                "status": status,
+                "bytes": self.bytes,
+                "durationMs": duration_ms,
+                "referer": self.referer,
+                "userAgent": self.user_agent,
+                "workerKey": self.worker_key,
+                "requestId": self.request_id,
+            })
+            .to_string(),
+        }
+    }
+}
+
+impl Drop for PendingEntry {
+    fn drop(&mut self) {
+        let Some(log) = ACCESS_LOG.get().filter(|_| self.status.is_some()) else {
+            return;
+        };
+
+        // NOTE: The line is dropped if the writer can't keep up.
+        if let Err(TrySendError::Disconnected(_)) = log.line_tx.try_send(self.format(log.format)) {
+            error!("access log writer has exited");
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn entry() -> PendingEntry {
+        PendingEntry {
+            client_ip: "192.0.2.1".parse().unwrap(),
+            time: DateTime::parse_from_rfc3339("2024-03-05T13:55:36Z")
+                .unwrap()
+                .into(),
+            started_at: Instant::now(),
+            method: Method::GET,
+            uri: "/hello?name=\"world\"".parse().unwrap(),
+            version: Version::HTTP_11,
+            referer: None,
+            user_agent: Some("curl/8.0".into()),
+            request_id: "req-1".into(),
+            status: Some(StatusCode::NOT_FOUND),
+            worker_key: Some("worker-1".into()),
+            bytes: 42,
+        }
+    }
+
+    #[test]
+    fn test_combined_format() {
+        let line = entry().format(AccessLogFormat::Combined);
+
+        assert!(line.starts_with(concat!(
+            "192.0.2.1 - - [05/Mar/2024:13:55:36 +0000] ",
+            r#""GET /hello?name=\"world\" HTTP/1.1" 404 42 "-" "curl/8.0" "#
+        )));
+        assert!(line.ends_with(" worker-1 req-1"));
+    }
+
+    #[test]
+    fn test_json_format() {
+        let line = entry().format(AccessLogFormat::Json);
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+
+        assert_eq!(value["time"], "2024-03-05T13:55:36.000Z");
+        assert_eq!(value["status"], 404);
+        assert_eq!(value["bytes"], 42);
+        assert_eq!(value["referer"], serde_json::Value::Null);
+        assert_eq!(value["workerKey"], "worker-1");
+    }
+
+    #[test]
+    fn test_file_is_rotated_past_max_bytes() {
+        let dir = std::env::temp_dir().join(format!("sb-access-log-{}", Uuid::new_v4()));
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("access.log");
+        let mut file = RotatingFile::open(&path, Some(8), 2).unwrap();
+
+        for line in ["one", "two", "three", "four"] {
+            file.write_line(line).unwrap();
+        }
+
+        file.file.flush().unwrap();
+
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+
+        assert_eq!(read(path.clone()), "four\n");
+        assert_eq!(read(file.rotated_path(1)), "three\n");
+        assert_eq!(read(file.rotated_path(2)), "one\ntwo\n");
+        assert!(!file.rotated_path(3).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
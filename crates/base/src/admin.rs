@@ -0,0 +1,308 @@
+//! A local console for a running runtime, served on a Unix socket.
+//!
+//! Clients send one command per line, and get one line of JSON back for each
+//! of them, except for `events` which streams the events of the workers
+//! until the client hangs up. `edge-runtime attach` connects to the socket.
+
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Error};
+use deno_core::serde_json::{self, json};
+use log::error;
+use sb_core::SharedMetricSource;
+use sb_workers::context::{UserWorkerMsgs, UserWorkerMsgsSender};
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{broadcast, oneshot};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::rt_worker::main_worker::MainWorker;
+use crate::rt_worker::threads;
+
+const COMMANDS: &[&str] = &[
+    "help                show this list",
+    "workers             list the user workers",
+    "metrics             dump the request metrics",
+    "usage               show the resources used per tenant",
+    "terminate <KEY>     terminate a user worker",
+    "drain               drain the runtime",
+    "reload              boot the main workers again and swap them in",
+    "threads             map the threads polling user workers to them",
+    "events              stream the events of the workers",
+];
+
+struct AdminContext {
+    worker_pool_tx: UserWorkerMsgsSender,
+    main_workers: Vec<MainWorker>,
+    metric_src: SharedMetricSource,
+    events_tx: broadcast::Sender<String>,
+}
+
+impl AdminContext {
+    async fn call<T>(
+        &self,
+        msg_fn: impl FnOnce(oneshot::Sender<T>) -> UserWorkerMsgs,
+    ) -> Result<T, Error> {
+        let (tx, rx) = oneshot::channel();
+
+        self.worker_pool_tx
+            .send(msg_fn(tx))
+            .map_err(|_| anyhow!("user worker pool is not available"))?;
+
+        rx.await
+            .map_err(|_| anyhow!("user worker pool is not available"))
+    }
+
+    async fn run(&self, line: &str) -> Result<serde_json::Value, Error> {
+        let mut args = line.split_whitespace();
+
+        Ok(match (args.next(), args.next(), args.next()) {
+            (Some("help"), None, _) => json!({ "commands": COMMANDS }),
+
+            (Some("workers"), None, _) => {
+                serde_json::to_value(self.call(UserWorkerMsgs::List).await?)?
+            }
+
+            (Some("metrics"), None, _) => {
+                let src = &self.metric_src;
+
+                json!({
+                    "activeUserWorkers": src.active_user_workers(),
+                    "receivedRequests": src.received_requests(),
+                    "handledRequests": src.handled_requests(),
+                    "openConnections": src.active_io(),
+                    "userWorkerRequests": src.user_worker_request_stats(),
+                })
+            }
+
+            (Some("threads"), None, _) => serde_json::to_value(threads::snapshot())?,
+
+            (Some("usage"), None, _) => {
+                serde_json::to_value(self.call(UserWorkerMsgs::Usage).await?)?
+            }
+
+            (Some("terminate"), Some(key), None) => {
+                let key = Uuid::parse_str(key)?;
+
+                json!({ "terminated": self.call(|tx| UserWorkerMsgs::Terminate(key, tx)).await? })
+            }
+
+            (Some("drain"), None, _) => {
+                self.call(UserWorkerMsgs::DrainRuntime).await?;
+                json!({ "draining": true })
+            }
+
+            (Some("reload"), None, _) => {
+                for main_worker in &self.main_workers {
+                    main_worker.reload().await?;
+                }
+
+                json!({ "reloaded": true })
+            }
+
+            _ => bail!("unknown command: {} (try `help`)", line),
+        })
+    }
+}
+
+/// Binds a socket at `path` that only the owner of the process can connect
+/// to.
+///
+/// The socket is bound inside a directory of its own first, and only moved to
+/// `path` once its permissions are restricted, so there is no moment in which
+/// others could connect to it.
+fn bind_private(path: &Path) -> Result<UnixListener, Error> {
+    let dir = path.with_file_name(format!(".admin-{}", Uuid::new_v4()));
+
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+
+    let temp_path = dir.join("sock");
+    let result = UnixListener::bind(&temp_path)
+        .map_err(Error::from)
+        .and_then(|listener| {
+            std::fs::set_permissions(&temp_path, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::rename(&temp_path, path)?;
+            Ok(listener)
+        });
+
+    let _ = std::fs::remove_file(&temp_path);
+    let _ = std::fs::remove_dir(&dir);
+
+    result
+}
+
+/// Binds the socket and serves the console on it until `shutdown` is
+/// cancelled. Only the owner of the process can connect.
+pub(crate) fn serve(
+    path: PathBuf,
+    worker_pool_tx: UserWorkerMsgsSender,
+    main_workers: Vec<MainWorker>,
+    metric_src: SharedMetricSource,
+    events_tx: broadcast::Sender<String>,
+    shutdown: CancellationToken,
+) -> Result<(), Error> {
+    // NOTE: A socket left behind by a runtime that didn't exit cleanly would
+    // make binding fail. Anything else at the path is left alone.
+    if std::fs::symlink_metadata(&path).is_ok_and(|it| it.file_type().is_socket()) {
+        std::fs::remove_file(&path)?;
+    }
+
+    let listener = bind_private(&path)
+        .with_context(|| format!("failed to bind the admin socket {}", path.display()))?;
+
+    let ctx = Arc::new(AdminContext {
+        worker_pool_tx,
+        main_workers,
+        metric_src,
+        events_tx,
+    });
+
+    drop(tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                result = listener.accept() => match result {
+                    Ok((stream, _)) => drop(tokio::spawn(handle(stream, ctx.clone()))),
+                    Err(err) => error!("failed to accept admin connection: {}", err),
+                },
+
+                _ = shutdown.cancelled() => break,
+            }
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }));
+
+    Ok(())
+}
+
+async fn handle(stream: UnixStream, ctx: Arc<AdminContext>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if line == "events" {
+            stream_events(&mut writer, ctx.events_tx.subscribe()).await;
+            return;
+        }
+
+        let reply = ctx
+            .run(line)
+            .await
+            .unwrap_or_else(|err| json!({ "error": err.to_string() }));
+
+        if write_line(&mut writer, &reply.to_string()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Forwards events until the client hangs up. Events the client was too
+/// slow to take are dropped, and it is told how many.
+async fn stream_events<W>(writer: &mut W, mut events_rx: broadcast::Receiver<String>)
+where
+    W: AsyncWrite + Unpin,
+{
+    loop {
+        let line = match events_rx.recv().await {
+            Ok(it) => it,
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                json!({ "error": format!("{} events were dropped", count) }).to_string()
+            }
+
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+
+        if write_line(writer, &line).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn write_line<W>(writer: &mut W, line: &str) -> std::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\n").await
+}
+
+/// Connects to the admin socket of a running runtime. Sends `command` if
+/// given, or else the lines read from stdin, and prints what the runtime
+/// answers until it hangs up.
+pub async fn attach(path: &Path, maybe_command: Option<String>) -> Result<(), Error> {
+    let stream = UnixStream::connect(path)
+        .await
+        .with_context(|| format!("failed to connect to {}", path.display()))?;
+
+    let (mut reader, mut writer) = stream.into_split();
+    let input = tokio::spawn(async move {
+        match maybe_command {
+            Some(command) => write_line(&mut writer, &command).await?,
+            None => {
+                let _ = tokio::io::copy(&mut tokio::io::stdin(), &mut writer).await?;
+            }
+        }
+
+        // NOTE: Lets the runtime know that no more commands are coming, so
+        // that it hangs up once it has answered them.
+        writer.shutdown().await
+    });
+
+    let result = tokio::io::copy(&mut reader, &mut tokio::io::stdout()).await;
+
+    input.abort();
+    result?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bind_private_restricts_socket_to_owner() {
+        let dir = std::env::temp_dir().join(format!("sb-admin-{}", Uuid::new_v4()));
+        let path = dir.join("admin.sock");
+
+        std::fs::create_dir(&dir).unwrap();
+
+        let _listener = bind_private(&path).unwrap();
+        let metadata = std::fs::symlink_metadata(&path).unwrap();
+
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        UnixStream::connect(&path).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stream_events_reports_dropped_events() {
+        let (tx, rx) = broadcast::channel(1);
+        let mut out = Vec::new();
+
+        tx.send("first".to_string()).unwrap();
+        tx.send("second".to_string()).unwrap();
+        drop(tx);
+
+        stream_events(&mut out, rx).await;
+
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("1 events were dropped"));
+        assert_eq!(lines[1], "second");
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Error};
+use bytes::Bytes;
+use event_worker::events::WorkerEvents;
+use futures_util::future::join_all;
+use http::Method;
+use hyper::{Body, Request};
+use sb_workers::context::UserWorkerRuntimeOpts;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::rt_worker::worker_pool::{SupervisorPolicy, WorkerPoolPolicy};
+use crate::server::ServerFlags;
+use crate::testing::{ServiceOptions, ServiceSource, TestRuntime};
+
+/// How long to wait for the worker to report its shutdown once the load has
+/// been fired, and for the pool to exit after that.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+const BENCH_SERVICE: &str = "bench";
+
+/// Limits generous enough that the benchmark measures the service rather
+/// than the supervisor.
+const BENCH_TIME_LIMIT_MS: u64 = 60 * 60 * 1000;
+
+pub struct BenchOptions {
+    pub service_path: PathBuf,
+    pub import_map_path: Option<String>,
+    pub env_vars: HashMap<String, String>,
+    pub memory_limit_mb: Option<u64>,
+    pub requests: usize,
+    pub concurrency: usize,
+    pub method: String,
+    pub path: String,
+    pub body: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct LatencySummary {
+    pub min_ms: f64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencySummary {
+    fn from_samples(mut samples: Vec<Duration>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+
+        samples.sort();
+
+        let to_ms = |it: &Duration| it.as_secs_f64() * 1000.0;
+        let percentile = |pct: usize| {
+            let idx = ((samples.len() * pct) / 100).min(samples.len() - 1);
+            to_ms(&samples[idx])
+        };
+
+        Self {
+            min_ms: to_ms(&samples[0]),
+            mean_ms: samples.iter().map(to_ms).sum::<f64>() / samples.len() as f64,
+            p50_ms: percentile(50),
+            p90_ms: percentile(90),
+            p99_ms: percentile(99),
+            max_ms: to_ms(&samples[samples.len() - 1]),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    pub boot_time_ms: f64,
+    pub requests: usize,
+    pub errors: usize,
+    pub concurrency: usize,
+    pub duration_ms: f64,
+    pub requests_per_sec: f64,
+    pub latency: LatencySummary,
+    /// CPU time the worker reported when it was shut down.
+    pub cpu_time_used_ms: Option<usize>,
+    /// Heap usage of the worker when it was shut down.
+    pub worker_heap_used_bytes: Option<usize>,
+    /// Peak resident set size of the whole process.
+    pub peak_rss_bytes: Option<u64>,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_na = |it: Option<String>| it.unwrap_or_else(|| "n/a".to_string());
+
+        writeln!(f, "boot time:      {:.1}ms", self.boot_time_ms)?;
+        writeln!(
+            f,
+            "requests:       {} ({} failed, concurrency {})",
+            self.requests, self.errors, self.concurrency
+        )?;
+        writeln!(
+            f,
+            "duration:       {:.1}ms ({:.1} req/s)",
+            self.duration_ms, self.requests_per_sec
+        )?;
+        writeln!(
+            f,
+            "latency:        min {:.2}ms, mean {:.2}ms, p50 {:.2}ms, p90 {:.2}ms, p99 {:.2}ms, max {:.2}ms",
+            self.latency.min_ms,
+            self.latency.mean_ms,
+            self.latency.p50_ms,
+            self.latency.p90_ms,
+            self.latency.p99_ms,
+            self.latency.max_ms
+        )?;
+        writeln!(
+            f,
+            "cpu time:       {}",
+            or_na(self.cpu_time_used_ms.map(|it| format!("{}ms", it)))
+        )?;
+        writeln!(
+            f,
+            "worker heap:    {}",
+            or_na(
+                self.worker_heap_used_bytes
+                    .map(|it| format!("{} bytes", it))
+            )
+        )?;
+        write!(
+            f,
+            "peak rss:       {}",
+            or_na(self.peak_rss_bytes.map(|it| format!("{} bytes", it)))
+        )
+    }
+}
+
+/// Boots the service in-process and fires `requests` requests at it with up
+/// to `concurrency` of them in flight, bypassing the network.
+pub async fn run(opts: BenchOptions) -> Result<BenchReport, Error> {
+    if opts.requests == 0 || opts.concurrency == 0 {
+        bail!("both the request count and the concurrency must be positive");
+    }
+
+    let method = Method::from_bytes(opts.method.to_uppercase().as_bytes())
+        .map_err(|_| anyhow!("invalid request method: {}", opts.method))?;
+    let body = opts.body.map(Bytes::from);
+
+    let mut rt = TestRuntime::builder()
+        .with_worker_pool_policy(WorkerPoolPolicy::new(
+            SupervisorPolicy::PerWorker,
+            1,
+            ServerFlags {
+                request_wait_timeout_ms: Some(BENCH_TIME_LIMIT_MS),
+                ..Default::default()
+            },
+        ))
+        .build()
+        .await?;
+
+    let mut runtime_opts = UserWorkerRuntimeOpts {
+        worker_timeout_ms: BENCH_TIME_LIMIT_MS,
+        cpu_time_soft_limit_ms: BENCH_TIME_LIMIT_MS,
+        cpu_time_hard_limit_ms: BENCH_TIME_LIMIT_MS,
+        ..Default::default()
+    };
+
+    if let Some(memory_limit_mb) = opts.memory_limit_mb {
+        runtime_opts.memory_limit_mb = memory_limit_mb;
+    }
+
+    rt.register_with_opts(
+        BENCH_SERVICE,
+        ServiceSource::path(opts.service_path),
+        ServiceOptions {
+            env_vars: opts.env_vars,
+            import_map_path: opts.import_map_path,
+            runtime_opts,
+        },
+    )?;
+
+    let boot_started_at = Instant::now();
+    let key = rt.worker(BENCH_SERVICE).await?;
+    let boot_time = boot_started_at.elapsed();
+    let next = &AtomicUsize::new(0);
+    let (rt_ref, method, path, body, requests) =
+        (&rt, &method, opts.path.as_str(), &body, opts.requests);
+    let load_started_at = Instant::now();
+
+    // NOTE: The requests are only dispatched from here, the worker runs on
+    // threads of its own.
+    let results = join_all(
+        (0..opts.concurrency.min(opts.requests)).map(|_| async move {
+            let mut latencies = vec![];
+            let mut errors = 0;
+
+            while next.fetch_add(1, Ordering::Relaxed) < requests {
+                let started_at = Instant::now();
+                let ok = send_request(rt_ref, key, method.clone(), path, body.clone())
+                    .await
+                    .unwrap_or(false);
+
+                latencies.push(started_at.elapsed());
+
+                if !ok {
+                    errors += 1;
+                }
+            }
+
+            (latencies, errors)
+        }),
+    )
+    .await;
+
+    let load_duration = load_started_at.elapsed();
+    let mut latencies = vec![];
+    let mut errors = 0;
+
+    for (task_latencies, task_errors) in results {
+        latencies.extend(task_latencies);
+        errors += task_errors;
+    }
+
+    let _ = rt.terminate(key).await;
+
+    let maybe_shutdown_event = rt
+        .wait_for_event(
+            |it| {
+                it.metadata.execution_id == Some(key)
+                    && matches!(it.event, WorkerEvents::Shutdown(_))
+            },
+            SHUTDOWN_TIMEOUT,
+        )
+        .await
+        .and_then(|it| match it.event {
+            WorkerEvents::Shutdown(ev) => Some(ev),
+            _ => None,
+        });
+
+    rt.shutdown(SHUTDOWN_TIMEOUT).await?;
+
+    Ok(BenchReport {
+        boot_time_ms: boot_time.as_secs_f64() * 1000.0,
+        requests: latencies.len(),
+        errors,
+        concurrency: opts.concurrency,
+        duration_ms: load_duration.as_secs_f64() * 1000.0,
+        requests_per_sec: latencies.len() as f64 / load_duration.as_secs_f64(),
+        latency: LatencySummary::from_samples(latencies),
+        cpu_time_used_ms: maybe_shutdown_event.as_ref().map(|it| it.cpu_time_used),
+        worker_heap_used_bytes: maybe_shutdown_event.map(|it| it.memory_used.heap),
+        peak_rss_bytes: peak_rss_bytes(),
+    })
+}
+
+/// Returns whether the worker responded with a successful status.
+async fn send_request(
+    rt: &TestRuntime,
+    key: Uuid,
+    method: Method,
+    path: &str,
+    body: Option<Bytes>,
+) -> Result<bool, Error> {
+    let req = Request::builder()
+        .method(method)
+        .uri(format!("http://localhost{}", path))
+        .body(body.map(Body::from).unwrap_or_else(Body::empty))?;
+
+    Ok(rt.send_request(key, req).await?.status.is_success())
+}
+
+#[cfg(unix)]
+fn peak_rss_bytes() -> Option<u64> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return None;
+    }
+
+    // NOTE: `ru_maxrss` is in kilobytes on Linux but in bytes on macOS.
+    if cfg!(target_os = "macos") {
+        Some(usage.ru_maxrss as u64)
+    } else {
+        Some(usage.ru_maxrss as u64 * 1024)
+    }
+}
+
+#[cfg(not(unix))]
+fn peak_rss_bytes() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_latency_summary_of_samples() {
+        let summary =
+            LatencySummary::from_samples((1..=100).rev().map(Duration::from_millis).collect());
+
+        assert_eq!(summary.min_ms, 1.0);
+        assert_eq!(summary.max_ms, 100.0);
+        assert_eq!(summary.mean_ms, 50.5);
+        assert_eq!(summary.p50_ms, 51.0);
+        assert_eq!(summary.p90_ms, 91.0);
+        assert_eq!(summary.p99_ms, 100.0);
+    }
+
+    #[test]
+    fn test_latency_summary_of_no_samples() {
+        assert_eq!(LatencySummary::from_samples(vec![]).max_ms, 0.0);
+    }
+}
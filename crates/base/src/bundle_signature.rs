@@ -0,0 +1,184 @@
+//! Verifies the detached ed25519 signatures of eszip bundles against the keys
+//! the server trusts, before a worker evaluates any of their code.
+//!
+//! A bundle is signed over its raw bytes. The signature is shipped base64
+//! encoded, next to the bundle as `<bundle>.eszip.sig` for the main and events
+//! workers, or as the `maybeEszipSignature` option of user workers.
+//!
+//! NOTE: Only eszips carry a signature. Once keys are trusted, services booted
+//! from their sources are refused unless unsigned sources are explicitly
+//! allowed.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Error};
+use once_cell::sync::OnceCell;
+use ring::signature::{UnparsedPublicKey, ED25519, ED25519_PUBLIC_KEY_LEN};
+use sb_graph::EszipPayloadKind;
+use thiserror::Error;
+
+static TRUSTED_KEYS: OnceCell<TrustedKeys> = OnceCell::new();
+
+struct TrustedKeys {
+    keys: Vec<Vec<u8>>,
+    allow_unsigned_sources: bool,
+}
+
+#[derive(Debug, Error)]
+pub enum BundleSignatureError {
+    #[error("bundle signature is missing")]
+    Missing,
+    #[error("bundle signature is malformed")]
+    Malformed,
+    #[error("bundle signature does not match any trusted key")]
+    Mismatch,
+    #[error("bundle signature is required, but the service is not an eszip")]
+    Unsigned,
+}
+
+/// Reads the base64 ed25519 public keys in `paths` and requires every eszip
+/// booted afterwards to be signed by one of them. Services booted from their
+/// sources are refused unless `allow_unsigned_sources` is set. Can only be
+/// done once.
+pub fn trust_keys<P: AsRef<Path>>(
+    paths: impl IntoIterator<Item = P>,
+    allow_unsigned_sources: bool,
+) -> Result<(), Error> {
+    let keys = paths
+        .into_iter()
+        .map(|path| {
+            let path = path.as_ref();
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("can't read bundle key {}", path.display()))?;
+
+            match base64::decode(text.trim()) {
+                Ok(key) if key.len() == ED25519_PUBLIC_KEY_LEN => Ok(key),
+                _ => bail!("invalid ed25519 public key in {}", path.display()),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    TRUSTED_KEYS
+        .set(TrustedKeys {
+            keys,
+            allow_unsigned_sources,
+        })
+        .map_err(|_| anyhow::anyhow!("bundle keys are already trusted"))
+}
+
+/// Reads the detached signature shipped next to the eszip at `path`, if any.
+pub(crate) fn read_detached(path: &Path) -> Result<Option<String>, Error> {
+    let mut sig_path = path.as_os_str().to_owned();
+
+    sig_path.push(".sig");
+
+    match std::fs::read_to_string(&sig_path) {
+        Ok(it) => Ok(Some(it)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Checks the eszip against the trusted keys. Anything passes when no key is
+/// trusted.
+pub(crate) fn verify(
+    eszip: &EszipPayloadKind,
+    maybe_signature: Option<&str>,
+) -> Result<(), BundleSignatureError> {
+    let Some(trusted) = TRUSTED_KEYS.get() else {
+        return Ok(());
+    };
+
+    verify_with(&trusted.keys, eszip, maybe_signature)
+}
+
+/// Checks whether a service may be booted from its sources rather than from a
+/// signed eszip.
+pub(crate) fn verify_unsigned() -> Result<(), BundleSignatureError> {
+    match TRUSTED_KEYS.get() {
+        Some(trusted) if !trusted.allow_unsigned_sources => Err(BundleSignatureError::Unsigned),
+        _ => Ok(()),
+    }
+}
+
+fn verify_with(
+    keys: &[Vec<u8>],
+    eszip: &EszipPayloadKind,
+    maybe_signature: Option<&str>,
+) -> Result<(), BundleSignatureError> {
+    let Some(signature) = maybe_signature else {
+        return Err(BundleSignatureError::Missing);
+    };
+
+    let signature =
+        base64::decode(signature.trim()).map_err(|_| BundleSignatureError::Malformed)?;
+
+    let bytes: &[u8] = match eszip {
+        EszipPayloadKind::JsBufferKind(it) => &**it,
+        EszipPayloadKind::VecKind(it) => &**it,
+
+        // NOTE: A parsed eszip no longer has the bytes it was signed over.
+        EszipPayloadKind::Eszip(_) => return Err(BundleSignatureError::Mismatch),
+    };
+
+    if keys.iter().any(|key| {
+        UnparsedPublicKey::new(&ED25519, key)
+            .verify(bytes, &signature)
+            .is_ok()
+    }) {
+        Ok(())
+    } else {
+        Err(BundleSignatureError::Mismatch)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    use super::*;
+
+    #[test]
+    fn test_verify_requires_signature_of_trusted_key() {
+        let rng = SystemRandom::new();
+        let new_key_pair = || {
+            Ed25519KeyPair::from_pkcs8(Ed25519KeyPair::generate_pkcs8(&rng).unwrap().as_ref())
+                .unwrap()
+        };
+
+        let trusted = new_key_pair();
+        let untrusted = new_key_pair();
+        let keys = vec![trusted.public_key().as_ref().to_vec()];
+
+        let bundle = b"not really an eszip".to_vec();
+        let eszip = EszipPayloadKind::VecKind(bundle.clone());
+        let sign = |pair: &Ed25519KeyPair| base64::encode(pair.sign(&bundle));
+
+        assert!(verify_with(&keys, &eszip, Some(&sign(&trusted))).is_ok());
+        assert!(matches!(
+            verify_with(&keys, &eszip, Some(&sign(&untrusted))),
+            Err(BundleSignatureError::Mismatch)
+        ));
+        assert!(matches!(
+            verify_with(&keys, &eszip, None),
+            Err(BundleSignatureError::Missing)
+        ));
+        assert!(matches!(
+            verify_with(&keys, &eszip, Some("not base64!")),
+            Err(BundleSignatureError::Malformed)
+        ));
+        assert!(matches!(
+            verify_with(
+                &keys,
+                &EszipPayloadKind::VecKind(b"tampered".to_vec()),
+                Some(&sign(&trusted))
+            ),
+            Err(BundleSignatureError::Mismatch)
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+//! Checks the files of a service against the SHA-256 checksums listed in its
+//! manifest before the service is booted from them, so a partially synced or
+//! tampered deployment fails to boot rather than running.
+//!
+//! The manifest is a `checksums.json` file at the root of the service that
+//! maps paths relative to that root to hex checksums. Services without one
+//! are booted as they are, and files it doesn't list are not checked.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{Context, Error};
+use deno_core::serde_json;
+use sb_core::util::checksum;
+use thiserror::Error;
+
+pub(crate) const MANIFEST_FILE_NAME: &str = "checksums.json";
+
+#[derive(Debug)]
+pub(crate) enum ChecksumMismatch {
+    Missing(String),
+    Modified(String),
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(path) => write!(f, "{} (missing)", path),
+            Self::Modified(path) => write!(f, "{} (modified)", path),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error(
+    "service files do not match their checksum manifest: {}",
+    .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+)]
+pub(crate) struct ChecksumManifestError(Vec<ChecksumMismatch>);
+
+/// Verifies the files of the service at `service_dir` if it has a manifest.
+pub(crate) fn verify(service_dir: &Path) -> Result<(), Error> {
+    let manifest_path = service_dir.join(MANIFEST_FILE_NAME);
+    let manifest = match std::fs::read(&manifest_path) {
+        Ok(it) => serde_json::from_slice::<BTreeMap<String, String>>(&it)
+            .with_context(|| format!("invalid checksum manifest {}", manifest_path.display()))?,
+
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mismatches = manifest
+        .into_iter()
+        .filter_map(
+            |(path, expected)| match std::fs::read(service_dir.join(&path)) {
+                Ok(it) if checksum::gen(&[it]).eq_ignore_ascii_case(expected.trim()) => None,
+                Ok(_) => Some(ChecksumMismatch::Modified(path)),
+                Err(_) => Some(ChecksumMismatch::Missing(path)),
+            },
+        )
+        .collect::<Vec<_>>();
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(ChecksumManifestError(mismatches).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_verify_lists_mismatching_files() {
+        let dir = std::env::temp_dir().join(format!("checksums-{}", uuid::Uuid::new_v4()));
+
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.ts"), "export {}").unwrap();
+        std::fs::write(dir.join("lib.ts"), "tampered").unwrap();
+
+        assert!(verify(&dir).is_ok());
+
+        std::fs::write(
+            dir.join(MANIFEST_FILE_NAME),
+            serde_json::json!({
+                "index.ts": checksum::gen(&["export {}"]),
+                "lib.ts": checksum::gen(&["original"]),
+                "gone.ts": checksum::gen(&[""]),
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let err = verify(&dir).unwrap_err();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(err.is::<ChecksumManifestError>());
+        assert_eq!(
+            err.to_string(),
+            "service files do not match their checksum manifest: \
+             gone.ts (missing), lib.ts (modified)"
+        );
+    }
+}
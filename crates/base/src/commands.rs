@@ -1,11 +1,17 @@
 use crate::{
+    event_router::EventWorkerRoute,
     inspector_server::Inspector,
+    listener::ListenerConfig,
+    router::RoutingTable,
     rt_worker::{worker_ctx::TerminationToken, worker_pool::WorkerPoolPolicy},
     server::{Server, ServerFlags, ServerHealth, Tls, WorkerEntrypoints},
     InspectorOption,
 };
 use anyhow::Error;
+use ipnet::IpNet;
 use sb_graph::DecoratorType;
+use sb_workers::context::IpAccessList;
+use std::path::PathBuf;
 use tokio::sync::mpsc::Sender;
 
 #[allow(clippy::too_many_arguments)]
@@ -26,6 +32,12 @@ pub async fn start_server(
     inspector_option: Option<InspectorOption>,
     jsx_specifier: Option<String>,
     jsx_module: Option<String>,
+    ip_access_list: IpAccessList,
+    trusted_proxies: Vec<IpNet>,
+    maybe_routing_table: Option<RoutingTable>,
+    event_worker_routes: Vec<EventWorkerRoute>,
+    maybe_admin_socket_path: Option<PathBuf>,
+    listeners: Vec<ListenerConfig>,
 ) -> Result<(), Error> {
     let mut server = Server::new(
         ip,
@@ -44,6 +56,12 @@ pub async fn start_server(
         inspector_option.map(Inspector::from_option),
         jsx_specifier,
         jsx_module,
+        ip_access_list,
+        trusted_proxies,
+        maybe_routing_table,
+        event_worker_routes,
+        maybe_admin_socket_path,
+        listeners,
     )
     .await?;
 
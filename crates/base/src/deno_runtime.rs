@@ -1,7 +1,15 @@
+use crate::bundle_signature;
+use crate::checksum_manifest;
+use crate::eszip_cache::EszipCacheEntry;
 use crate::inspector_server::Inspector;
+use crate::rt_worker::resources::ResourceTracker;
 use crate::rt_worker::rt;
-use crate::rt_worker::supervisor::{CPUUsage, CPUUsageMetrics};
+use crate::rt_worker::supervisor::{CPUUsage, CPUUsageMetrics, MemoryPressure};
+use crate::rt_worker::threads::{self, WorkerTag};
 use crate::rt_worker::worker::DuplexStreamEntry;
+use crate::service_archive;
+use crate::service_storage;
+use crate::utils::send_event_if_event_worker_available;
 use crate::utils::units::{bytes_to_display, mib_to_bytes};
 
 use anyhow::{anyhow, bail, Context, Error};
@@ -13,7 +21,7 @@ use deno_core::url::Url;
 use deno_core::v8::{GCCallbackFlags, GCType, HeapStatistics, Isolate};
 use deno_core::{
     located_script_name, serde_json, serde_v8, JsRuntime, ModuleCodeString, ModuleId,
-    PollEventLoopOptions, RuntimeOptions,
+    OpMetricsSummaryTracker, PollEventLoopOptions, RuntimeOptions,
 };
 use deno_http::DefaultHttpPropertyExtractor;
 use deno_tls::deno_native_certs::load_native_certs;
@@ -22,8 +30,10 @@ use deno_tls::rustls::RootCertStore;
 use deno_tls::RootCertStoreProvider;
 use futures_util::future::poll_fn;
 use futures_util::task::AtomicWaker;
-use log::{error, trace};
+use log::{error, trace, warn};
 use once_cell::sync::{Lazy, OnceCell};
+use sb_core::channel::BoundedSender;
+use sb_core::conn_sync::ConnectionInfo;
 use sb_core::http::sb_core_http;
 use sb_core::http_start::sb_core_http_start;
 use sb_core::util::sync::AtomicFlag;
@@ -32,39 +42,57 @@ use std::borrow::Cow;
 use std::collections::HashMap;
 use std::ffi::c_void;
 use std::fmt;
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::Poll;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, Notify};
 use tokio::time::interval;
 use tokio_util::sync::CancellationToken;
 
 use crate::snapshot;
-use event_worker::events::{EventMetadata, WorkerEventWithMetadata};
+use event_worker::events::{EventMetadata, WorkerEventWithMetadata, WorkerEvents, WorkerSecrets};
 use event_worker::js_interceptors::sb_events_js_interceptors;
 use event_worker::sb_user_event_worker;
 use sb_ai::sb_ai;
 use sb_core::cache::CacheSetting;
 use sb_core::cert::ValueRootCertStoreProvider;
 use sb_core::external_memory::CustomAllocator;
-use sb_core::net::sb_core_net;
-use sb_core::permissions::{sb_core_permissions, Permissions};
+use sb_core::fetch_pool::sb_core_fetch_pool;
+use sb_core::net::{sb_core_net, PendingDuplexStreams};
+use sb_core::permissions::{sb_core_permissions, Permissions, PermissionsOptions};
 use sb_core::runtime::sb_core_runtime;
-use sb_core::{sb_core_main_js, MemCheckWaker};
+use sb_core::state_checkpoint::StateCheckpoint;
+use sb_core::{
+    sb_core_main_js, BootSignal, CpuClock, MemCheckWaker, TerminationHook, WorkerSelfInfo,
+};
+use sb_crypto::sb_crypto;
+use sb_db::sb_db;
 use sb_env::sb_env as sb_env_op;
 use sb_fs::file_system::DenoCompileFileSystem;
+use sb_fs::tmp_fs::{TmpDir, TmpDirFs};
+use sb_geoip::sb_geoip;
 use sb_graph::emitter::EmitterFactory;
 use sb_graph::import_map::load_import_map;
 use sb_graph::{
     generate_binary_eszip, include_glob_patterns_in_eszip, EszipPayloadKind, STATIC_FS_PREFIX,
 };
+use sb_html::sb_html;
+use sb_image::sb_image;
 use sb_module_loader::standalone::create_module_loader_for_standalone_from_eszip_kind;
 use sb_module_loader::RuntimeProviders;
 use sb_node::deno_node;
-use sb_workers::context::{UserWorkerMsgs, WorkerContextInitOpts, WorkerRuntimeOpts};
+use sb_publish::sb_publish;
+use sb_redis::sb_redis;
+use sb_workers::context::{
+    UserWorkerMsgs, UserWorkerMsgsSender, WorkerContextInitOpts, WorkerRuntimeOpts,
+};
 use sb_workers::sb_user_workers;
 
 const DEFAULT_ALLOC_CHECK_INT_MSEC: u64 = 1000;
+const TMP_DIR_SCAN_INTERVAL: Duration = Duration::from_secs(1);
 
 static SUPABASE_UA: Lazy<String> = Lazy::new(|| {
     let deno_version = MAYBE_DENO_VERSION.get().map(|it| &**it).unwrap_or("1.0.0");
@@ -90,6 +118,32 @@ static ALLOC_CHECK_DUR: Lazy<Duration> = Lazy::new(|| {
 
 pub static MAYBE_DENO_VERSION: OnceCell<String> = OnceCell::new();
 
+// NOTE: Name of the synthetic main module that serves the handler exported by
+// the actual one. It lives next to the actual main module so that the import
+// is resolved the same way as any other relative import.
+const HANDLER_ENTRYPOINT_NAME: &str = "__sb_handler_entrypoint__.js";
+
+fn handler_entrypoint_code(main_module_url: &Url, export_name: &str) -> String {
+    let specifier = serde_json::to_string(main_module_url.as_str()).unwrap();
+    let export_name = serde_json::to_string(export_name).unwrap();
+
+    format!(
+        r#"import * as mod from {specifier};
+
+const name = {export_name};
+const handler = mod[name];
+
+if (typeof handler === "function") {{
+  Deno.serve((req, info) => handler(req, info));
+}} else if (typeof handler?.fetch === "function") {{
+  Deno.serve((req, info) => handler.fetch(req, info));
+}} else {{
+  throw new TypeError(`main module does not export a handler named "${{name}}"`);
+}}
+"#
+    )
+}
+
 #[ctor]
 fn init_v8_platform() {
     set_v8_flags();
@@ -117,13 +171,27 @@ fn get_error_class_name(e: &AnyError) -> &'static str {
     sb_core::errors_rt::get_error_class_name(e).unwrap_or("Error")
 }
 
+/// What went into booting a worker, reported in its boot event.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BootInfo {
+    pub module_cache_hit: bool,
+    pub module_count: usize,
+    pub used_snapshot: bool,
+}
+
 #[derive(Default, Clone)]
 struct MemCheckState {
     drop_token: CancellationToken,
     limit: Option<usize>,
+    warning_threshold: Option<usize>,
+    throttle_threshold: Option<usize>,
     waker: Arc<AtomicWaker>,
     notify: Arc<Notify>,
 
+    pressure: Arc<AtomicU8>,
+    pressure_notify: Arc<Notify>,
+    total_bytes: Arc<AtomicUsize>,
+
     #[cfg(debug_assertions)]
     exceeded: Arc<AtomicFlag>,
 }
@@ -156,6 +224,22 @@ impl MemCheckState {
             .saturating_add(used_heap_bytes)
             .saturating_add(external_bytes);
 
+        let pressure = if total_bytes >= limit {
+            MemoryPressure::Critical
+        } else if self.throttle_threshold.is_some_and(|it| total_bytes >= it) {
+            MemoryPressure::Throttle
+        } else if self.warning_threshold.is_some_and(|it| total_bytes >= it) {
+            MemoryPressure::Warning
+        } else {
+            MemoryPressure::Normal
+        };
+
+        self.total_bytes.store(total_bytes, Ordering::Release);
+
+        if self.pressure.swap(pressure as u8, Ordering::AcqRel) != pressure as u8 {
+            self.pressure_notify.notify_one();
+        }
+
         if total_bytes >= limit {
             self.notify.notify_waiters();
 
@@ -175,6 +259,15 @@ impl MemCheckState {
     }
 }
 
+/// Counts the async ops that were dispatched but haven't completed yet.
+fn pending_async_ops(tracker: &OpMetricsSummaryTracker) -> usize {
+    let summary = tracker.aggregate();
+
+    summary
+        .ops_dispatched_async
+        .saturating_sub(summary.ops_completed_async) as usize
+}
+
 pub struct DenoRuntime {
     pub js_runtime: JsRuntime,
     pub env_vars: HashMap<String, String>, // TODO: does this need to be pub?
@@ -183,11 +276,14 @@ pub struct DenoRuntime {
     pub(crate) is_termination_requested: Arc<AtomicFlag>,
     pub(crate) is_terminated: Arc<AtomicFlag>,
     pub(crate) is_found_inspector_session: Arc<AtomicFlag>,
+    pub(crate) termination_hook: TerminationHook,
+    pub(crate) boot_info: BootInfo,
 
     main_module_id: ModuleId,
     maybe_inspector: Option<Inspector>,
 
     mem_check_state: Arc<MemCheckState>,
+    maybe_op_metrics: Option<Rc<OpMetricsSummaryTracker>>,
     waker: Arc<AtomicWaker>,
 }
 
@@ -217,6 +313,7 @@ impl DenoRuntime {
             events_rx,
             conf,
             maybe_eszip,
+            maybe_eszip_signature,
             maybe_entrypoint,
             maybe_decorator,
             maybe_module_code,
@@ -225,6 +322,23 @@ impl DenoRuntime {
             ..
         } = opts;
 
+        let (service_path, maybe_eszip) = match service_storage::fetch(&service_path).await? {
+            Some((dir, bin)) => (dir, Some(EszipPayloadKind::VecKind(bin))),
+            None => (service_path, maybe_eszip),
+        };
+
+        let service_path = if service_archive::is_archive(&service_path) {
+            service_archive::extract(&service_path).await?
+        } else {
+            service_path
+        };
+
+        if let Some(eszip) = maybe_eszip.as_ref() {
+            bundle_signature::verify(eszip, maybe_eszip_signature.as_deref())?;
+        } else {
+            bundle_signature::verify_unsigned()?;
+        }
+
         let base_dir_path = std::env::current_dir().map(|p| p.join(&service_path))?;
         let base_url = Url::from_directory_path(&base_dir_path).unwrap();
 
@@ -244,13 +358,18 @@ impl DenoRuntime {
             main_module_url = Url::parse(&maybe_entrypoint.unwrap())?;
         }
 
-        let mut net_access_disabled = false;
+        let mut permissions_options = PermissionsOptions::default();
         let mut allow_remote_modules = true;
 
         if is_user_worker {
             let user_conf = conf.as_user_worker().unwrap();
 
-            net_access_disabled = user_conf.net_access_disabled;
+            permissions_options = PermissionsOptions {
+                net_access_disabled: user_conf.net_access_disabled,
+                allow_ffi: user_conf.allow_ffi,
+                allow_subprocess: user_conf.allow_subprocess,
+                allow_raw_net: user_conf.allow_raw_net,
+            };
             allow_remote_modules = user_conf.allow_remote_modules;
         }
 
@@ -258,8 +377,36 @@ impl DenoRuntime {
         let only_module_code =
             maybe_module_code.is_some() && maybe_eszip.is_none() && !is_some_entry_point;
 
+        // NOTE: Only the eszips of user workers built from their service path
+        // are cached. Those are the ones booted over and over as the pool
+        // evicts and recreates workers.
+        let maybe_eszip_cache_entry = (is_user_worker
+            && !no_module_cache
+            && maybe_module_code.is_none()
+            && static_patterns.is_empty())
+        .then(|| {
+            EszipCacheEntry::new(
+                &main_module_url,
+                import_map_path.as_deref(),
+                allow_remote_modules,
+                maybe_decorator,
+                maybe_jsx_import_source_config.as_ref(),
+            )
+        });
+
+        if maybe_eszip.is_none() && !only_module_code {
+            checksum_manifest::verify(&base_dir_path)?;
+        }
+
+        let module_cache_hit = maybe_eszip.is_some() || !no_module_cache;
         let eszip = if let Some(eszip_payload) = maybe_eszip {
             eszip_payload
+        } else if let Some(eszip_payload) = maybe_eszip_cache_entry
+            .as_ref()
+            .and_then(EszipCacheEntry::load)
+        {
+            maybe_arc_import_map = load_import_map(import_map_path.clone())?.map(Arc::new);
+            eszip_payload
         } else {
             let mut emitter_factory = EmitterFactory::new();
 
@@ -307,7 +454,10 @@ impl DenoRuntime {
             )
             .await;
 
-            EszipPayloadKind::Eszip(eszip)
+            match maybe_eszip_cache_entry.as_ref() {
+                Some(entry) => entry.store(eszip),
+                None => EszipPayloadKind::Eszip(eszip),
+            }
         };
 
         // Create and populate a root cert store based on environment variable.
@@ -373,25 +523,60 @@ impl DenoRuntime {
             static_files,
             npm_snapshot,
             vfs_path,
+            module_count,
         } = rt_provider;
 
+        let maybe_tmp_dir = match conf.as_user_worker() {
+            Some(conf) if conf.tmp_dir_quota_mb > 0 => {
+                let path = std::env::temp_dir().join(format!(
+                    "sb-worker-{}",
+                    conf.key.unwrap_or_else(uuid::Uuid::new_v4)
+                ));
+
+                Some(Arc::new(
+                    TmpDir::create(path, mib_to_bytes(conf.tmp_dir_quota_mb))
+                        .context("failed to create temporary directory for user worker")?,
+                ))
+            }
+
+            _ => None,
+        };
+
         let op_fs = {
             if is_user_worker {
-                Arc::new(sb_fs::static_fs::StaticFs::new(
+                let static_fs = Arc::new(sb_fs::static_fs::StaticFs::new(
                     static_files,
                     vfs_path,
                     vfs,
                     npm_snapshot,
-                )) as Arc<dyn deno_fs::FileSystem>
+                )) as Arc<dyn deno_fs::FileSystem>;
+
+                match maybe_tmp_dir.clone() {
+                    Some(tmp_dir) => Arc::new(
+                        TmpDirFs::new(static_fs, tmp_dir)
+                            .with_service_overlay(base_dir_path.clone()),
+                    ),
+                    None => static_fs,
+                }
             } else {
                 Arc::new(DenoCompileFileSystem::from_rc(vfs)) as Arc<dyn deno_fs::FileSystem>
             }
         };
 
         let mod_code = module_code;
+        let maybe_deterministic_seed = conf.as_user_worker().and_then(|it| it.deterministic_seed);
+        let timer_resolution_ms = conf
+            .as_user_worker()
+            .map(|it| it.timer_resolution_ms)
+            .unwrap_or_default();
+        let maybe_wasi_permissions = conf.as_user_worker().map(|it| it.wasi_permissions);
+        let strict_isolation = conf
+            .as_user_worker()
+            .map(|it| it.strict_isolation)
+            .unwrap_or_default();
 
         let extensions = vec![
-            sb_core_permissions::init_ops(net_access_disabled),
+            sb_core_permissions::init_ops(permissions_options),
             deno_webidl::deno_webidl::init_ops(),
             deno_console::deno_console::init_ops(),
             deno_url::deno_url::init_ops(),
@@ -411,24 +596,34 @@ impl DenoRuntime {
                 Some(root_cert_store_provider.clone()),
                 None,
             ),
-            // TODO: support providing a custom seed for crypto
-            deno_crypto::deno_crypto::init_ops(None),
+            deno_crypto::deno_crypto::init_ops(maybe_deterministic_seed),
             deno_broadcast_channel::deno_broadcast_channel::init_ops(
                 deno_broadcast_channel::InMemoryBroadcastChannel::default(),
             ),
-            deno_net::deno_net::init_ops::<Permissions>(Some(root_cert_store_provider), None),
+            deno_net::deno_net::init_ops::<Permissions>(
+                Some(root_cert_store_provider.clone()),
+                None,
+            ),
             deno_tls::deno_tls::init_ops(),
             deno_http::deno_http::init_ops::<DefaultHttpPropertyExtractor>(),
             deno_io::deno_io::init_ops(stdio),
             deno_fs::deno_fs::init_ops::<Permissions>(op_fs.clone()),
             sb_env_op::init_ops(),
             sb_ai::init_ops(),
+            sb_db::init_ops(),
+            sb_image::init_ops(),
+            sb_crypto::init_ops(),
+            sb_html::init_ops(),
+            sb_geoip::init_ops(),
+            sb_redis::init_ops(),
+            sb_publish::init_ops(),
             sb_os::sb_os::init_ops(),
             sb_user_workers::init_ops(),
             sb_user_event_worker::init_ops(),
             sb_events_js_interceptors::init_ops(),
             sb_core_main_js::init_ops(),
             sb_core_net::init_ops(),
+            sb_core_fetch_pool::init_ops(),
             sb_core_http::init_ops(),
             sb_core_http_start::init_ops(),
             // NOTE(AndresP): Order is matters. Otherwise, it will lead to hard
@@ -441,14 +636,21 @@ impl DenoRuntime {
         let mut mem_check_state = MemCheckState::default();
 
         if conf.is_user_worker() {
-            let memory_limit =
-                mib_to_bytes(conf.as_user_worker().unwrap().memory_limit_mb) as usize;
+            let conf = conf.as_user_worker().unwrap();
+            let memory_limit = mib_to_bytes(conf.memory_limit_mb) as usize;
 
             let allocator = CustomAllocator::new(memory_limit);
 
             allocator.set_waker(mem_check_state.waker.clone());
 
-            mem_check_state.limit = Some(memory_limit);
+            mem_check_state.limit = Some(
+                conf.memory_threshold_bytes(conf.memory_termination_threshold_pct)
+                    .unwrap_or(memory_limit),
+            );
+            mem_check_state.warning_threshold =
+                conf.memory_threshold_bytes(conf.memory_warning_threshold_pct);
+            mem_check_state.throttle_threshold =
+                conf.memory_threshold_bytes(conf.memory_throttle_threshold_pct);
             create_params = Some(
                 deno_core::v8::CreateParams::default()
                     .heap_limits(mib_to_bytes(0) as usize, memory_limit)
@@ -457,6 +659,10 @@ impl DenoRuntime {
         };
 
         let mem_check_state = Arc::new(mem_check_state);
+        let maybe_op_metrics = conf
+            .is_user_worker()
+            .then(|| Rc::new(OpMetricsSummaryTracker::default()));
+
         let runtime_options = RuntimeOptions {
             extensions,
             is_main: true,
@@ -467,9 +673,13 @@ impl DenoRuntime {
             compiled_wasm_module_store: None,
             startup_snapshot: Some(snapshot::snapshot()),
             module_loader: Some(module_loader),
+            op_metrics_factory_fn: maybe_op_metrics
+                .clone()
+                .map(|it| it.op_metrics_factory_fn(|_| true)),
             ..Default::default()
         };
 
+        let used_snapshot = runtime_options.startup_snapshot.is_some();
         let mut js_runtime = JsRuntime::new(runtime_options);
         let version: Option<&str> = option_env!("GIT_V_TAG");
 
@@ -494,6 +704,14 @@ impl DenoRuntime {
                 &*SHOULD_DISABLE_DEPRECATED_API_WARNING,
                 // 6: shouldUseVerboseDeprecatedApiWarning
                 &*SHOULD_USE_VERBOSE_DEPRECATED_API_WARNING,
+                // 7: deterministicSeed
+                maybe_deterministic_seed,
+                // 8: timerResolutionMs
+                timer_resolution_ms,
+                // 9: wasiPermissions
+                maybe_wasi_permissions,
+                // 10: strictIsolation
+                strict_isolation,
             ])
         );
 
@@ -505,6 +723,8 @@ impl DenoRuntime {
             );
         }
 
+        let termination_hook = TerminationHook::default();
+
         if is_user_worker {
             js_runtime.v8_isolate().add_gc_prologue_callback(
                 mem_check_gc_prologue_callback_fn,
@@ -512,10 +732,12 @@ impl DenoRuntime {
                 GCType::ALL,
             );
 
-            js_runtime
-                .op_state()
-                .borrow_mut()
-                .put(MemCheckWaker::from(mem_check_state.waker.clone()));
+            let op_state_rc = js_runtime.op_state();
+            let mut op_state = op_state_rc.borrow_mut();
+
+            op_state.put(MemCheckWaker::from(mem_check_state.waker.clone()));
+            op_state.put(termination_hook.clone());
+            op_state.put(BootSignal::default());
         }
 
         js_runtime
@@ -531,12 +753,13 @@ impl DenoRuntime {
 
             if conf.is_events_worker() {
                 // if worker is an events worker, assert events_rx is to be available
-                op_state
-                    .put::<mpsc::UnboundedReceiver<WorkerEventWithMetadata>>(events_rx.unwrap());
+                op_state.put::<mpsc::Receiver<WorkerEventWithMetadata>>(events_rx.unwrap());
             }
 
             if conf.is_main_worker() || conf.is_user_worker() {
                 op_state.put::<HashMap<usize, CancellationToken>>(HashMap::new());
+                op_state.put::<HashMap<usize, ConnectionInfo>>(HashMap::new());
+                op_state.put(PendingDuplexStreams::default());
             }
 
             if conf.is_user_worker() {
@@ -548,21 +771,106 @@ impl DenoRuntime {
                     conf.key.map_or("".to_string(), |k| k.to_string()),
                 );
 
+                if let Some(tmp_dir) = maybe_tmp_dir.as_ref() {
+                    env_vars.insert(
+                        "TMPDIR".to_string(),
+                        tmp_dir.path().to_string_lossy().into_owned(),
+                    );
+                }
+
+                op_state.put::<WorkerSelfInfo>(WorkerSelfInfo {
+                    key: conf.key.map(|it| it.to_string()),
+                    started_at: Instant::now(),
+                    wall_clock_limit_ms: conf.worker_timeout_ms,
+                    cpu_clock: Arc::<CpuClock>::default(),
+                });
+
+                if let Some(quota) = sb_db::quota_for_worker(conf.db_connection_quota as usize) {
+                    op_state.put::<sb_db::DbQuota>(quota);
+                }
+
+                if let Some(quota) = sb_redis::quota_for_worker(conf.redis_commands_per_second) {
+                    op_state.put::<sb_redis::RedisQuota>(quota);
+                }
+
+                op_state.put::<sb_redis::RedisNamespace>(sb_redis::RedisNamespace::new(
+                    conf.redis_namespace.clone(),
+                )?);
+
+                op_state.put::<sb_image::ImageOpsPolicy>(sb_image::ImageOpsPolicy::new(
+                    conf.allow_image_ops,
+                    conf.image_transform_concurrency as usize,
+                ));
+
+                op_state.put::<sb_crypto::CryptoPolicy>(sb_crypto::CryptoPolicy::new(
+                    conf.crypto_ops_per_second,
+                    conf.crypto_keys.iter().cloned(),
+                ));
+
+                op_state.put::<sb_publish::PublishPolicy>(sb_publish::PublishPolicy {
+                    topics: conf.publish_topics.clone(),
+                });
+
+                if let Some(quota) = sb_publish::quota_for_worker(conf.publish_queue_quota as usize)
+                {
+                    op_state.put::<sb_publish::PublishQuota>(quota);
+                }
+
+                if let Some(path) = conf.state_checkpoint_path.clone() {
+                    op_state.put::<StateCheckpoint>(StateCheckpoint {
+                        path,
+                        interval_ms: conf.state_checkpoint_interval_ms.max(1),
+                    });
+                }
+
+                op_state.put::<WorkerSecrets>(conf.secrets.clone());
+
                 if let Some(events_msg_tx) = conf.events_msg_tx.clone() {
-                    op_state.put::<mpsc::UnboundedSender<WorkerEventWithMetadata>>(events_msg_tx);
+                    op_state.put::<BoundedSender<WorkerEventWithMetadata>>(events_msg_tx);
                     op_state.put::<EventMetadata>(EventMetadata {
                         service_path: conf.service_path.clone(),
                         execution_id: conf.key,
+                        labels: conf.labels.clone(),
                     });
                 }
             }
 
             op_state.put::<sb_env::EnvVars>(env_vars);
+
+            sb_core::fetch_pool::attach_shared_fetch_client(
+                &mut op_state,
+                SUPABASE_UA.as_str(),
+                root_cert_store_provider.clone(),
+                conf.as_user_worker()
+                    .and_then(|it| it.http_proxy.as_deref()),
+                conf.as_user_worker().and_then(|it| it.tls_policy.as_ref()),
+            )?;
         }
 
-        let main_module_id = js_runtime
-            .load_main_module(&main_module_url, mod_code)
-            .await?;
+        let main_module_id = match conf
+            .as_user_worker()
+            .and_then(|it| it.handler_export.as_deref())
+        {
+            Some(export_name) => {
+                let entrypoint_url = main_module_url.join(HANDLER_ENTRYPOINT_NAME)?;
+
+                js_runtime
+                    .load_main_module(
+                        &entrypoint_url,
+                        Some(ModuleCodeString::from(handler_entrypoint_code(
+                            &main_module_url,
+                            export_name,
+                        ))),
+                    )
+                    .await?
+            }
+
+            None => {
+                js_runtime
+                    .load_main_module(&main_module_url, mod_code)
+                    .await?
+            }
+        };
 
         if is_user_worker {
             drop(rt::SUPERVISOR_RT.spawn({
@@ -588,6 +896,26 @@ impl DenoRuntime {
             }));
         }
 
+        if let Some(tmp_dir) = maybe_tmp_dir {
+            // NOTE: The file system holds the only strong reference, so the
+            // directory goes away along with the isolate.
+            let tmp_dir = Arc::downgrade(&tmp_dir);
+
+            drop(rt::SUPERVISOR_RT.spawn(async move {
+                let mut int = interval(TMP_DIR_SCAN_INTERVAL);
+
+                loop {
+                    int.tick().await;
+
+                    let Some(tmp_dir) = tmp_dir.upgrade() else {
+                        break;
+                    };
+
+                    let _ = tokio::task::spawn_blocking(move || tmp_dir.rescan()).await;
+                }
+            }));
+        }
+
         Ok(Self {
             js_runtime,
             env_vars,
@@ -596,29 +924,44 @@ impl DenoRuntime {
             is_termination_requested: Arc::default(),
             is_terminated: Arc::default(),
             is_found_inspector_session: Arc::default(),
+            termination_hook,
+            boot_info: BootInfo {
+                module_cache_hit,
+                module_count,
+                used_snapshot,
+            },
 
             main_module_id,
             maybe_inspector,
 
             mem_check_state,
+            maybe_op_metrics,
             waker: Arc::default(),
         })
     }
 
     pub async fn run(
         &mut self,
-        duplex_stream_rx: mpsc::UnboundedReceiver<DuplexStreamEntry>,
+        duplex_stream_rx: mpsc::Receiver<DuplexStreamEntry>,
         maybe_cpu_usage_metrics_tx: Option<mpsc::UnboundedSender<CPUUsageMetrics>>,
         name: Option<String>,
     ) -> (Result<(), Error>, i64) {
+        let maybe_cpu_clock;
+        let maybe_boot_signal;
+
         {
             let op_state_rc = self.js_runtime.op_state();
             let mut op_state = op_state_rc.borrow_mut();
 
-            op_state.put::<mpsc::UnboundedReceiver<DuplexStreamEntry>>(duplex_stream_rx);
+            op_state.put::<mpsc::Receiver<DuplexStreamEntry>>(duplex_stream_rx);
+
+            maybe_cpu_clock = op_state
+                .try_borrow::<WorkerSelfInfo>()
+                .map(|it| it.cpu_clock.clone());
+            maybe_boot_signal = op_state.try_borrow::<BootSignal>().cloned();
 
             if self.conf.is_main_worker() {
-                op_state.put::<mpsc::UnboundedSender<UserWorkerMsgs>>(
+                op_state.put::<UserWorkerMsgsSender>(
                     self.conf.as_main_worker().unwrap().worker_pool_tx.clone(),
                 );
             }
@@ -637,6 +980,7 @@ impl DenoRuntime {
         let mut accumulated_cpu_time_ns = 0i64;
 
         let inspector = self.inspector();
+        let maybe_worker_tag = self.conf.as_user_worker().map(WorkerTag::new);
         let mod_result_rx = unsafe {
             self.js_runtime.v8_isolate().enter();
 
@@ -671,10 +1015,22 @@ impl DenoRuntime {
                 it.v8_isolate().exit();
             });
 
+            if let Some(opts) = self.conf.as_user_worker() {
+                rt::apply_worker_priority(opts.priority);
+            }
+
+            if let Some(tag) = maybe_worker_tag.as_ref() {
+                threads::enter(tag);
+            }
+
             send_cpu_metrics_fn(CPUUsageMetrics::Enter(current_thread_id));
 
             current_cpu_time_ns = get_current_cpu_time_ns().unwrap();
 
+            if let Some(clock) = maybe_cpu_clock.as_ref() {
+                clock.enter(accumulated_cpu_time_ns, current_cpu_time_ns);
+            }
+
             let top_level_await_fut = js_runtime.mod_evaluate(self.main_module_id);
             let cpu_time_after_eval_ns = get_current_cpu_time_ns().unwrap();
             let diff_cpu_time_ns = cpu_time_after_eval_ns - current_cpu_time_ns;
@@ -686,13 +1042,42 @@ impl DenoRuntime {
                 diff: diff_cpu_time_ns,
             }));
 
+            if maybe_worker_tag.is_some() {
+                threads::leave();
+            }
+
             top_level_await_fut
         };
 
+        // NOTE: The module evaluation is polled along with the event loop, so
+        // that the `onBoot` hooks run as soon as it has completed.
+        let mut mod_result_rx = Box::pin(mod_result_rx);
+        let mut maybe_mod_result = None;
+
         let is_termination_requested = self.is_termination_requested.clone();
         let is_user_worker = self.conf.is_user_worker();
         let global_waker = self.waker.clone();
         let mem_check_state = is_user_worker.then(|| self.mem_check_state.clone());
+        let maybe_priority = self.conf.as_user_worker().map(|it| it.priority);
+        let maybe_request_metrics = self
+            .conf
+            .as_user_worker()
+            .and_then(|it| it.request_metrics.clone());
+        let maybe_op_metrics = self.maybe_op_metrics.clone();
+        let mut maybe_resource_tracker = self
+            .conf
+            .as_user_worker()
+            .map(|it| ResourceTracker::new(it.resource_leak_threshold));
+        let maybe_event_sink = self.conf.as_user_worker().map(|it| {
+            (
+                it.events_msg_tx.clone(),
+                EventMetadata {
+                    service_path: it.service_path.clone(),
+                    execution_id: it.key,
+                    labels: it.labels.clone(),
+                },
+            )
+        });
 
         let poll_result = poll_fn(|cx| unsafe {
             // INVARIANT: Only can steal current task by other threads when LIFO
@@ -712,10 +1097,23 @@ impl DenoRuntime {
             });
 
             js_runtime.v8_isolate().enter();
+
+            if let Some(priority) = maybe_priority {
+                rt::apply_worker_priority(priority);
+            }
+
+            if let Some(tag) = maybe_worker_tag.as_ref() {
+                threads::enter(tag);
+            }
+
             send_cpu_metrics_fn(CPUUsageMetrics::Enter(thread_id));
 
             current_cpu_time_ns = get_current_cpu_time_ns().unwrap();
 
+            if let Some(clock) = maybe_cpu_clock.as_ref() {
+                clock.enter(accumulated_cpu_time_ns, current_cpu_time_ns);
+            }
+
             let wait_for_inspector = if inspector.is_some() {
                 let inspector = js_runtime.inspector();
                 let inspector_ref = inspector.borrow();
@@ -740,17 +1138,60 @@ impl DenoRuntime {
                     Cow::Borrowed(waker)
                 };
 
-                js_runtime.poll_event_loop(
+                if let Some(metrics) = maybe_request_metrics.as_ref() {
+                    metrics.enter_event_loop();
+                }
+
+                let poll_result = js_runtime.poll_event_loop(
                     &mut std::task::Context::from_waker(waker.as_ref()),
                     PollEventLoopOptions {
                         wait_for_inspector,
                         pump_v8_message_loop: true,
                     },
-                )
+                );
+
+                if let Some(metrics) = maybe_request_metrics.as_ref() {
+                    metrics.leave_event_loop(
+                        maybe_op_metrics
+                            .as_deref()
+                            .map(pending_async_ops)
+                            .unwrap_or_default(),
+                    );
+
+                    let maybe_leak = maybe_resource_tracker
+                        .as_mut()
+                        .and_then(|it| it.sample(&js_runtime.op_state().borrow(), metrics));
+
+                    if let (Some(leak), Some((events_msg_tx, metadata))) =
+                        (maybe_leak, maybe_event_sink.as_ref())
+                    {
+                        warn!(
+                            "open resources grew after {} requests in a row: {:?}",
+                            leak.consecutive_requests, leak.open_resources
+                        );
+                        send_event_if_event_worker_available(
+                            events_msg_tx.clone(),
+                            WorkerEvents::ResourceLeak(leak),
+                            metadata.clone(),
+                        );
+                    }
+                }
+
+                poll_result
             } else {
                 Poll::Pending
             };
 
+            if maybe_mod_result.is_none() {
+                if let Poll::Ready(result) = mod_result_rx.as_mut().poll(cx) {
+                    if let (Ok(_), Some(signal)) = (&result, maybe_boot_signal.as_ref()) {
+                        signal.complete();
+                    }
+
+                    maybe_mod_result = Some(result);
+                }
+            }
+
             let cpu_time_after_poll_ns = get_current_cpu_time_ns().unwrap();
             let diff_cpu_time_ns = if need_pool_event_loop {
                 cpu_time_after_poll_ns - current_cpu_time_ns
@@ -765,6 +1206,10 @@ impl DenoRuntime {
                 diff: diff_cpu_time_ns,
             }));
 
+            if maybe_worker_tag.is_some() {
+                threads::leave();
+            }
+
             if is_user_worker {
                 let mem_state = mem_check_state.as_ref().unwrap();
                 let total_malloced_bytes = mem_state.check(js_runtime.v8_isolate().as_mut());
@@ -798,13 +1243,20 @@ impl DenoRuntime {
 
         let result = match poll_result {
             Err(err) => Err(anyhow!("event loop error: {}", err)),
-            Ok(_) => match mod_result_rx.await {
-                Err(e) => {
-                    error!("{}", e.to_string());
-                    Err(e)
+            Ok(_) => {
+                let mod_result = match maybe_mod_result.take() {
+                    Some(result) => result,
+                    None => mod_result_rx.await,
+                };
+
+                match mod_result {
+                    Err(e) => {
+                        error!("{}", e.to_string());
+                        Err(e)
+                    }
+                    Ok(_) => Ok(()),
                 }
-                Ok(_) => Ok(()),
-            },
+            }
         };
 
         self.is_terminated.raise();
@@ -841,6 +1293,33 @@ impl DenoRuntime {
         }));
     }
 
+    pub fn add_memory_pressure_callback<C>(&self, mut cb: C)
+    where
+        C: FnMut(MemoryPressure, usize) + Send + 'static,
+    {
+        let pressure = self.mem_check_state.pressure.clone();
+        let total_bytes = self.mem_check_state.total_bytes.clone();
+        let notify = self.mem_check_state.pressure_notify.clone();
+        let drop_token = self.mem_check_state.drop_token.clone();
+
+        drop(rt::SUPERVISOR_RT.spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = notify.notified() => {
+                        cb(
+                            MemoryPressure::from(pressure.load(Ordering::Acquire)),
+                            total_bytes.load(Ordering::Acquire),
+                        );
+                    }
+
+                    _ = drop_token.cancelled() => {
+                        break;
+                    }
+                }
+            }
+        }));
+    }
+
     fn wait_for_inspector_session(&mut self) {
         if let Some(inspector) = self.maybe_inspector.as_ref() {
             let inspector_impl = self.js_runtime.inspector();
@@ -905,14 +1384,16 @@ extern "C" fn mem_check_gc_prologue_callback_fn(
 #[cfg(test)]
 mod test {
     use crate::deno_runtime::DenoRuntime;
+    use crate::eszip_cache::EszipCacheEntry;
+    use crate::rt_worker::supervisor::MemoryPressure;
     use crate::rt_worker::worker::DuplexStreamEntry;
     use deno_config::JsxImportSourceConfig;
     use deno_core::{FastString, ModuleCodeString, PollEventLoopOptions};
     use sb_graph::emitter::EmitterFactory;
     use sb_graph::{generate_binary_eszip, EszipPayloadKind};
     use sb_workers::context::{
-        MainWorkerRuntimeOpts, UserWorkerMsgs, UserWorkerRuntimeOpts, WorkerContextInitOpts,
-        WorkerRuntimeOpts,
+        user_worker_msgs_channel, MainWorkerRuntimeOpts, UserWorkerRuntimeOpts,
+        WorkerContextInitOpts, WorkerRuntimeOpts,
     };
     use serial_test::serial;
     use std::collections::HashMap;
@@ -929,7 +1410,7 @@ mod test {
     #[tokio::test]
     #[serial]
     async fn test_module_code_no_eszip() {
-        let (worker_pool_tx, _) = mpsc::unbounded_channel::<UserWorkerMsgs>();
+        let (worker_pool_tx, _) = user_worker_msgs_channel();
 
         DenoRuntime::new(
             WorkerContextInitOpts {
@@ -940,6 +1421,7 @@ mod test {
                 events_rx: None,
                 timing: None,
                 maybe_eszip: None,
+                maybe_eszip_signature: None,
                 maybe_entrypoint: None,
                 maybe_decorator: None,
                 maybe_module_code: Some(FastString::from(String::from(
@@ -965,7 +1447,7 @@ mod test {
     #[serial]
     #[allow(clippy::arc_with_non_send_sync)]
     async fn test_eszip_with_source_file() {
-        let (worker_pool_tx, _) = mpsc::unbounded_channel::<UserWorkerMsgs>();
+        let (worker_pool_tx, _) = user_worker_msgs_channel();
         let mut file = File::create("./test_cases/eszip-source-test.ts").unwrap();
         file.write_all(b"import isEven from \"npm:is-even\"; globalThis.isTenEven = isEven(9);")
             .unwrap();
@@ -987,6 +1469,7 @@ mod test {
                 events_rx: None,
                 timing: None,
                 maybe_eszip: Some(EszipPayloadKind::VecKind(eszip_code)),
+                maybe_eszip_signature: None,
                 maybe_entrypoint: None,
                 maybe_decorator: None,
                 maybe_module_code: None,
@@ -1035,7 +1518,7 @@ mod test {
     #[serial]
     #[allow(clippy::arc_with_non_send_sync)]
     async fn test_create_eszip_from_graph() {
-        let (worker_pool_tx, _) = mpsc::unbounded_channel::<UserWorkerMsgs>();
+        let (worker_pool_tx, _) = user_worker_msgs_channel();
         let file = PathBuf::from("./test_cases/eszip-silly-test/index.ts");
         let service_path = PathBuf::from("./test_cases/eszip-silly-test");
         let emitter_factory = Arc::new(EmitterFactory::new());
@@ -1054,6 +1537,7 @@ mod test {
                 events_rx: None,
                 timing: None,
                 maybe_eszip: Some(EszipPayloadKind::VecKind(eszip_code)),
+                maybe_eszip_signature: None,
                 maybe_entrypoint: None,
                 maybe_decorator: None,
                 maybe_module_code: None,
@@ -1105,7 +1589,7 @@ mod test {
         static_patterns: Vec<String>,
         maybe_jsx_import_source_config: Option<JsxImportSourceConfig>,
     ) -> DenoRuntime {
-        let (worker_pool_tx, _) = mpsc::unbounded_channel::<UserWorkerMsgs>();
+        let (worker_pool_tx, _) = user_worker_msgs_channel();
 
         DenoRuntime::new(
             WorkerContextInitOpts {
@@ -1119,6 +1603,7 @@ mod test {
                 events_rx: None,
                 timing: None,
                 maybe_eszip: None,
+                maybe_eszip_signature: None,
                 maybe_entrypoint: None,
                 maybe_decorator: None,
                 maybe_module_code: None,
@@ -1189,6 +1674,32 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_user_runtime_caches_service_eszip() {
+        let main_module_url = Url::from_file_path(
+            std::env::current_dir()
+                .unwrap()
+                .join("./test_cases/std_user_worker/index.ts"),
+        )
+        .unwrap();
+
+        let entry = EszipCacheEntry::new(&main_module_url, None, true, None, None);
+
+        for _ in 0..2 {
+            let _ = create_runtime(
+                Some("./test_cases/std_user_worker"),
+                None,
+                Some(WorkerRuntimeOpts::UserWorker(Default::default())),
+                vec![],
+                None,
+            )
+            .await;
+
+            assert!(entry.load().is_some());
+        }
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_main_rt_fs() {
@@ -1543,7 +2054,7 @@ mod test {
         let mut user_rt =
             create_basic_user_runtime("./test_cases/array_buffers", 20, 1000, &[]).await;
 
-        let (_tx, duplex_stream_rx) = mpsc::unbounded_channel::<DuplexStreamEntry>();
+        let (_tx, duplex_stream_rx) = mpsc::channel::<DuplexStreamEntry>(1);
         let (result, _) = user_rt.run(duplex_stream_rx, None, None).await;
 
         assert!(result.is_ok(), "expected no errors");
@@ -1558,7 +2069,7 @@ mod test {
         let mut user_rt =
             create_basic_user_runtime("./test_cases/array_buffers", 15, 1000, &[]).await;
 
-        let (_tx, duplex_stream_rx) = mpsc::unbounded_channel::<DuplexStreamEntry>();
+        let (_tx, duplex_stream_rx) = mpsc::channel::<DuplexStreamEntry>(1);
         let (result, _) = user_rt.run(duplex_stream_rx, None, None).await;
 
         match result {
@@ -1571,13 +2082,72 @@ mod test {
         };
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_termination_hook_runs_before_unload_listeners() {
+        let mut user_rt =
+            create_basic_user_runtime("./test_cases/termination_hook", 20, 1000, &[]).await;
+
+        let termination_hook = user_rt.termination_hook.clone();
+
+        termination_hook.request();
+
+        let (_tx, duplex_stream_rx) = mpsc::channel::<DuplexStreamEntry>(1);
+        let (result, _) = user_rt.run(duplex_stream_rx, None, None).await;
+
+        assert!(result.is_ok(), "expected no errors");
+
+        if timeout(Duration::from_secs(1), termination_hook.completed())
+            .await
+            .is_err()
+        {
+            panic!("termination hook was not completed within the given time");
+        }
+
+        let is_cleaned_up_global = user_rt
+            .js_runtime
+            .execute_script(
+                "<anon>",
+                ModuleCodeString::from("globalThis.isCleanedUp;".to_string()),
+            )
+            .unwrap();
+
+        let is_cleaned_up = user_rt.to_value::<deno_core::serde_json::Value>(&is_cleaned_up_global);
+
+        assert_eq!(is_cleaned_up.unwrap().to_string(), "true");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_memory_pressure_callback_invoked() {
+        let (pressure_tx, mut pressure_rx) = mpsc::unbounded_channel::<MemoryPressure>();
+        let mut user_rt =
+            create_basic_user_runtime("./test_cases/array_buffers", 20, 1000, &[]).await;
+
+        user_rt.add_memory_pressure_callback(move |pressure, _| {
+            let _ = pressure_tx.send(pressure);
+        });
+
+        let (_tx, duplex_stream_rx) = mpsc::channel::<DuplexStreamEntry>(1);
+        let (result, _) = user_rt.run(duplex_stream_rx, None, None).await;
+
+        assert!(result.is_ok(), "expected no errors");
+
+        let pressure = timeout(Duration::from_secs(1), pressure_rx.recv())
+            .await
+            .expect("memory pressure callback was not invoked within the given time")
+            .unwrap();
+
+        assert!(pressure >= MemoryPressure::Warning);
+    }
+
     async fn test_mem_check_above_limit(
         path: &str,
         static_patterns: &[&str],
         memory_limit_mb: u64,
         worker_timeout_ms: u64,
     ) {
-        let (_duplex_stream_tx, duplex_stream_rx) = mpsc::unbounded_channel::<DuplexStreamEntry>();
+        let (_duplex_stream_tx, duplex_stream_rx) = mpsc::channel::<DuplexStreamEntry>(1);
         let (callback_tx, mut callback_rx) = mpsc::unbounded_channel::<()>();
         let mut user_rt =
             create_basic_user_runtime(path, memory_limit_mb, worker_timeout_ms, static_patterns)
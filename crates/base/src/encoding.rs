@@ -0,0 +1,91 @@
+//! Negotiates the content encoding a client accepts, so that workers can
+//! compress their responses in it without parsing `Accept-Encoding`
+//! themselves.
+
+use http::header::ACCEPT_ENCODING;
+use http::HeaderMap;
+
+/// The encodings a worker can produce, in the order they are preferred when
+/// the client weighs them the same.
+const SUPPORTED_ENCODINGS: &[&str] = &["br", "gzip", "deflate"];
+
+pub(crate) const IDENTITY: &str = "identity";
+
+/// Picks the encoding the client weighs highest among the supported ones, or
+/// `identity` if it accepts none of them.
+pub(crate) fn negotiate(headers: &HeaderMap) -> &'static str {
+    let mut weights = [None; SUPPORTED_ENCODINGS.len()];
+    let mut maybe_wildcard_weight = None;
+
+    for value in headers.get_all(ACCEPT_ENCODING) {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+
+        for item in value.split(',') {
+            let mut params = item.split(';');
+            let coding = params.next().unwrap_or_default().trim();
+            let weight = match params.find_map(|it| it.trim().strip_prefix("q=")) {
+                Some(it) => match it.trim().parse::<f32>() {
+                    Ok(it) => it,
+                    Err(_) => continue,
+                },
+
+                None => 1.0,
+            };
+
+            if coding == "*" {
+                maybe_wildcard_weight = Some(weight);
+            } else if let Some(idx) = SUPPORTED_ENCODINGS
+                .iter()
+                .position(|it| it.eq_ignore_ascii_case(coding))
+            {
+                weights[idx] = Some(weight);
+            }
+        }
+    }
+
+    let mut best: Option<(&'static str, f32)> = None;
+
+    for (encoding, maybe_weight) in SUPPORTED_ENCODINGS.iter().zip(weights) {
+        let Some(weight) = maybe_weight.or(maybe_wildcard_weight) else {
+            continue;
+        };
+
+        if weight > 0.0 && best.map_or(true, |(_, it)| weight > it) {
+            best = Some((encoding, weight));
+        }
+    }
+
+    best.map_or(IDENTITY, |(it, _)| it)
+}
+
+#[cfg(test)]
+mod test {
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn negotiate_str(value: &str) -> &'static str {
+        let mut headers = HeaderMap::new();
+
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_str(value).unwrap());
+        negotiate(&headers)
+    }
+
+    #[test]
+    fn test_negotiate_prefers_highest_weight() {
+        assert_eq!(negotiate_str("gzip, deflate, br"), "br");
+        assert_eq!(negotiate_str("gzip;q=1.0, br;q=0.5"), "gzip");
+        assert_eq!(negotiate_str("deflate, GZIP;q=0.9"), "deflate");
+        assert_eq!(negotiate_str("*;q=0.1, gzip;q=0"), "br");
+    }
+
+    #[test]
+    fn test_negotiate_falls_back_to_identity() {
+        assert_eq!(negotiate(&HeaderMap::new()), IDENTITY);
+        assert_eq!(negotiate_str("zstd, identity"), IDENTITY);
+        assert_eq!(negotiate_str("br;q=0, gzip;q=0"), IDENTITY);
+        assert_eq!(negotiate_str("*;q=0"), IDENTITY);
+    }
+}
@@ -0,0 +1,207 @@
+//! Keeps the eszip built for a service on disk, so that booting the same
+//! service again skips building its module graph and transpiling it.
+//!
+//! NOTE: This stops short of snapshotting the isolate once the modules are
+//! evaluated. Services call `Deno.serve` (or open other resources) at the top
+//! level, which leaves ops pending that V8 cannot serialize into a snapshot,
+//! so module evaluation still runs on every boot.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Error};
+use deno_config::JsxImportSourceConfig;
+use deno_core::serde_json;
+use eszip::EszipV2;
+use log::{debug, error};
+use sb_core::cache::deno_dir::DenoDir;
+use sb_core::util::checksum;
+use sb_graph::{DecoratorType, EszipPayloadKind};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// How far apart two writes to a file may be and still get the same
+/// modification time, on the coarsest file systems we care about.
+const MTIME_GRANULARITY: Duration = Duration::from_secs(1);
+
+/// A local file the eszip was built from, as it was at the time.
+#[derive(Serialize, Deserialize, PartialEq)]
+struct FileStamp {
+    path: PathBuf,
+    len: u64,
+    modified_ns: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified_ns = metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_nanos() as u64;
+
+        Some(Self {
+            path: path.to_path_buf(),
+            len: metadata.len(),
+            modified_ns,
+        })
+    }
+
+    fn is_fresh(&self) -> bool {
+        Self::of(&self.path).as_ref() == Some(self)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    files: Vec<FileStamp>,
+    checksum: String,
+}
+
+/// Where the eszip of a service is kept. Everything that changes how the
+/// eszip is built goes into the name of the entry.
+pub(crate) struct EszipCacheEntry {
+    dir: PathBuf,
+    name: String,
+    import_map_path: Option<PathBuf>,
+    /// When the entry was looked up, i.e. before the eszip was built.
+    created_at: SystemTime,
+}
+
+impl EszipCacheEntry {
+    pub(crate) fn new(
+        main_module_url: &Url,
+        import_map_path: Option<&str>,
+        allow_remote_modules: bool,
+        maybe_decorator: Option<DecoratorType>,
+        maybe_jsx_import_source_config: Option<&JsxImportSourceConfig>,
+    ) -> Self {
+        // NOTE: Each part is prefixed with its length, so that moving bytes
+        // from one part to the next never yields the same name.
+        let name = checksum::gen(
+            &[
+                env!("CARGO_PKG_VERSION"),
+                main_module_url.as_str(),
+                import_map_path.unwrap_or_default(),
+                &allow_remote_modules.to_string(),
+                &format!("{:?}", maybe_decorator),
+                &format!("{:?}", maybe_jsx_import_source_config),
+            ]
+            .map(|it| format!("{}:{}", it.len(), it)),
+        );
+
+        Self {
+            dir: DenoDir::new(None)
+                .map(|it| it.service_eszip_folder_path())
+                .unwrap_or_else(|_| std::env::temp_dir().join("sb_service_eszip")),
+            name,
+            import_map_path: import_map_path.map(PathBuf::from).filter(|it| it.is_file()),
+            created_at: SystemTime::now(),
+        }
+    }
+
+    fn eszip_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.eszip", self.name))
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.json", self.name))
+    }
+
+    /// Returns the cached eszip, unless any of the local files it was built
+    /// from has changed since.
+    pub(crate) fn load(&self) -> Option<EszipPayloadKind> {
+        let manifest = std::fs::read(self.manifest_path()).ok()?;
+        let manifest = serde_json::from_slice::<Manifest>(&manifest).ok()?;
+
+        if !manifest.files.iter().all(FileStamp::is_fresh) {
+            debug!("service eszip is stale: {}", self.name);
+            return None;
+        }
+
+        let bin = std::fs::read(self.eszip_path()).ok()?;
+
+        // The eszip may have been replaced after the manifest was read.
+        if checksum::gen(&[&bin]) != manifest.checksum {
+            return None;
+        }
+
+        Some(EszipPayloadKind::VecKind(bin))
+    }
+
+    /// Writes `eszip` to the cache and hands it back as a payload. Failing to
+    /// write it only costs a rebuild on the next boot.
+    pub(crate) fn store(&self, eszip: EszipV2) -> EszipPayloadKind {
+        let mut files = eszip
+            .specifiers()
+            .iter()
+            .filter_map(|it| Url::parse(it).ok())
+            .filter(|it| it.scheme() == "file")
+            .filter_map(|it| it.to_file_path().ok())
+            .collect::<Vec<_>>();
+
+        files.extend(self.import_map_path.clone());
+
+        let bin = eszip.into_bytes();
+
+        if let Err(err) = self.write(&files, &bin) {
+            error!("failed to cache service eszip: {}", err);
+        }
+
+        EszipPayloadKind::VecKind(bin)
+    }
+
+    fn write(&self, files: &[PathBuf], bin: &[u8]) -> Result<(), Error> {
+        let manifest = Manifest {
+            files: files
+                .iter()
+                .map(|it| FileStamp::of(it.as_path()))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| anyhow::anyhow!("a module of the service is not readable"))?,
+            checksum: checksum::gen(&[bin]),
+        };
+
+        // The files are only stamped once the eszip is built. A file changed
+        // while it was being built may have gone into the eszip as it was
+        // before, so the stamps are only trusted for files that were last
+        // modified before the build started.
+        let built_after = self
+            .created_at
+            .checked_sub(MTIME_GRANULARITY)
+            .and_then(|it| it.duration_since(UNIX_EPOCH).ok())
+            .map(|it| it.as_nanos() as u64)
+            .unwrap_or_default();
+
+        if manifest
+            .files
+            .iter()
+            .any(|it| it.modified_ns >= built_after)
+        {
+            bail!("a module of the service changed while it was being built");
+        }
+
+        std::fs::create_dir_all(&self.dir)?;
+
+        // NOTE: Written to temporary files first, so that concurrent boots
+        // never read a partially written entry.
+        write_atomically(&self.eszip_path(), bin)?;
+        write_atomically(&self.manifest_path(), &serde_json::to_vec(&manifest)?)?;
+
+        Ok(())
+    }
+}
+
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    let tmp_path = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+
+    std::fs::write(&tmp_path, contents)?;
+
+    if let Err(err) = std::fs::rename(&tmp_path, path) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(err.into());
+    }
+
+    Ok(())
+}
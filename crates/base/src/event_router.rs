@@ -0,0 +1,174 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+use deno_core::serde_json;
+use event_worker::events::WorkerEventWithMetadata;
+use event_worker::schema::EVENT_TYPES;
+use log::error;
+use sb_core::channel::{self, BoundedSender, ChannelKind};
+use tokio::sync::broadcast;
+
+/// An events worker that receives only the events of the given types, e.g.
+/// `Log=./logs` or `Shutdown,MemoryWarning=./billing`.
+#[derive(Debug, Clone)]
+pub struct EventWorkerRoute {
+    pub event_types: HashSet<String>,
+    pub service_path: String,
+}
+
+impl FromStr for EventWorkerRoute {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((event_types, service_path)) = s.split_once('=') else {
+            bail!("expected <EVENT_TYPES>=<PATH>: {}", s);
+        };
+
+        let event_types = event_types
+            .split(',')
+            .map(str::trim)
+            .filter(|it| !it.is_empty())
+            .map(|it| {
+                if !EVENT_TYPES.contains(&it) {
+                    bail!("unknown event type: {}", it);
+                }
+
+                Ok(it.to_string())
+            })
+            .collect::<Result<HashSet<_>, _>>()?;
+
+        if event_types.is_empty() || service_path.is_empty() {
+            bail!("expected <EVENT_TYPES>=<PATH>: {}", s);
+        }
+
+        Ok(Self {
+            event_types,
+            service_path: service_path.to_string(),
+        })
+    }
+}
+
+/// Returns the sender that events should be sent to. Events go to the first
+/// route that takes their type, and to `maybe_fallback` otherwise. They are
+/// also copied as JSON to `maybe_tap`, while it has subscribers.
+pub(crate) fn spawn(
+    routes: Vec<(HashSet<String>, BoundedSender<WorkerEventWithMetadata>)>,
+    maybe_fallback: Option<BoundedSender<WorkerEventWithMetadata>>,
+    maybe_tap: Option<broadcast::Sender<String>>,
+) -> Option<BoundedSender<WorkerEventWithMetadata>> {
+    if routes.is_empty() && maybe_tap.is_none() {
+        return maybe_fallback;
+    }
+
+    let (tx, mut rx) = channel::channel::<WorkerEventWithMetadata>(ChannelKind::WorkerEvent);
+
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            if let Some(tap) = maybe_tap.as_ref().filter(|it| it.receiver_count() > 0) {
+                match serde_json::to_string(&event) {
+                    Ok(it) => {
+                        let _ = tap.send(it);
+                    }
+
+                    Err(err) => error!("failed to serialize event: {}", err),
+                }
+            }
+
+            let event_type = event.event.event_type();
+            let maybe_tx = routes
+                .iter()
+                .find(|(event_types, _)| event_types.contains(event_type))
+                .map(|(_, tx)| tx)
+                .or(maybe_fallback.as_ref());
+
+            if let Some(tx) = maybe_tx {
+                let _ = tx.send(event);
+            }
+        }
+    });
+
+    Some(tx)
+}
+
+#[cfg(test)]
+mod test {
+    use event_worker::events::{
+        EventLoopCompletedEvent, EventMetadata, LogEvent, LogLevel, WorkerEvents,
+    };
+
+    use super::*;
+
+    fn log_event(msg: &str) -> WorkerEventWithMetadata {
+        WorkerEventWithMetadata {
+            event: WorkerEvents::Log(LogEvent {
+                msg: msg.to_string(),
+                level: LogLevel::Info,
+            }),
+            metadata: EventMetadata::default(),
+        }
+    }
+
+    #[test]
+    fn test_parse_event_worker_route() {
+        let route = EventWorkerRoute::from_str("Shutdown, MemoryWarning=./billing").unwrap();
+
+        assert_eq!(route.service_path, "./billing");
+        assert_eq!(
+            route.event_types,
+            HashSet::from(["Shutdown".to_string(), "MemoryWarning".to_string()])
+        );
+
+        assert!(EventWorkerRoute::from_str("./billing").is_err());
+        assert!(EventWorkerRoute::from_str("=./billing").is_err());
+        assert!(EventWorkerRoute::from_str("Log=").is_err());
+        assert!(EventWorkerRoute::from_str("Unknown=./billing").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_events_are_routed_by_type() {
+        let (log_tx, mut log_rx) = channel::channel(ChannelKind::WorkerEvent);
+        let (shutdown_tx, mut shutdown_rx) = channel::channel(ChannelKind::WorkerEvent);
+        let (fallback_tx, mut fallback_rx) = channel::channel(ChannelKind::WorkerEvent);
+
+        let tx = spawn(
+            vec![
+                (HashSet::from(["Shutdown".to_string()]), shutdown_tx),
+                (HashSet::from(["Log".to_string()]), log_tx),
+            ],
+            Some(fallback_tx),
+            None,
+        )
+        .unwrap();
+
+        tx.send(log_event("hello")).unwrap();
+        tx.send(WorkerEventWithMetadata {
+            event: WorkerEvents::EventLoopCompleted(EventLoopCompletedEvent { cpu_time_used: 0 }),
+            metadata: EventMetadata::default(),
+        })
+        .unwrap();
+
+        drop(tx);
+
+        let Some(WorkerEventWithMetadata {
+            event: WorkerEvents::Log(LogEvent { msg, .. }),
+            ..
+        }) = log_rx.recv().await
+        else {
+            panic!("expected a log event");
+        };
+
+        assert_eq!(msg, "hello");
+        assert!(matches!(
+            fallback_rx.recv().await,
+            Some(WorkerEventWithMetadata {
+                event: WorkerEvents::EventLoopCompleted(_),
+                ..
+            })
+        ));
+
+        assert!(log_rx.recv().await.is_none());
+        assert!(shutdown_rx.recv().await.is_none());
+        assert!(fallback_rx.recv().await.is_none());
+    }
+}
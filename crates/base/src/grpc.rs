@@ -0,0 +1,398 @@
+//! Serves the operations of the user worker pool over gRPC, for orchestration
+//! systems to manage the runtime without going through the main worker. The
+//! service is defined in `proto/pool.proto`.
+
+use std::net::SocketAddr;
+
+use anyhow::{bail, Error};
+use deno_core::serde_json;
+use event_worker::events::WorkerRequestStats;
+use log::error;
+use sb_core::SharedMetricSource;
+use sb_workers::context::{UserWorkerInfo, UserWorkerMsgs, UserWorkerMsgsSender, WorkerExitStatus};
+use sb_workers::errors::WorkerError;
+use sb_workers::UserWorkerCreateOptions;
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use crate::server::TokenDigest;
+
+/// Options that hand a worker capabilities of the host. They can only be
+/// given by the main worker, not by gRPC clients.
+const PRIVILEGED_OPTIONS: &[&str] = &[
+    "allowFfi",
+    "allowSubprocess",
+    "allowRawNet",
+    "cryptoKeys",
+    "stateCheckpointPath",
+];
+
+pub mod proto {
+    tonic::include_proto!("edge_runtime.pool.v1");
+}
+
+use proto::pool_server::{Pool, PoolServer};
+
+struct PoolService {
+    worker_pool_tx: UserWorkerMsgsSender,
+    metric_src: SharedMetricSource,
+}
+
+impl PoolService {
+    async fn call<T>(
+        &self,
+        msg_fn: impl FnOnce(oneshot::Sender<T>) -> UserWorkerMsgs,
+    ) -> Result<T, Status> {
+        let (tx, rx) = oneshot::channel();
+
+        self.worker_pool_tx
+            .send(msg_fn(tx))
+            .map_err(|_| Status::unavailable("user worker pool is not available"))?;
+
+        rx.await
+            .map_err(|_| Status::unavailable("user worker pool is not available"))
+    }
+}
+
+#[tonic::async_trait]
+impl Pool for PoolService {
+    async fn create_worker(
+        &self,
+        req: Request<proto::CreateWorkerRequest>,
+    ) -> Result<Response<proto::CreateWorkerResponse>, Status> {
+        let worker_options = create_options(req.into_inner())
+            .and_then(|it| it.into_worker_options(None))
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+
+        let result = self
+            .call(|tx| UserWorkerMsgs::Create(worker_options, tx))
+            .await?;
+
+        match result {
+            Ok(it) => Ok(Response::new(proto::CreateWorkerResponse {
+                key: it.key.to_string(),
+            })),
+
+            Err(err) => match err.downcast_ref() {
+                Some(
+                    err @ (WorkerError::BootQueueFull
+                    | WorkerError::BootQueueTimedOut
+                    | WorkerError::WorkersThrottled
+                    | WorkerError::LiveWorkersSaturated),
+                ) => Err(Status::resource_exhausted(err.to_string())),
+
+                Some(err @ WorkerError::RuntimeDraining) => {
+                    Err(Status::unavailable(err.to_string()))
+                }
+
+                _ => Err(Status::internal(err.to_string())),
+            },
+        }
+    }
+
+    async fn terminate_worker(
+        &self,
+        req: Request<proto::TerminateWorkerRequest>,
+    ) -> Result<Response<proto::TerminateWorkerResponse>, Status> {
+        let req = req.into_inner();
+        let terminated = if req.key.is_empty() {
+            if req.labels.is_empty() {
+                return Err(Status::invalid_argument("either key or labels must be set"));
+            }
+
+            self.call(|tx| UserWorkerMsgs::TerminateByLabels(req.labels, tx))
+                .await?
+        } else {
+            let key = Uuid::parse_str(&req.key)
+                .map_err(|err| Status::invalid_argument(err.to_string()))?;
+
+            self.call(|tx| UserWorkerMsgs::Terminate(key, tx)).await? as usize
+        };
+
+        Ok(Response::new(proto::TerminateWorkerResponse {
+            terminated: terminated as u64,
+        }))
+    }
+
+    async fn list_workers(
+        &self,
+        req: Request<proto::ListWorkersRequest>,
+    ) -> Result<Response<proto::ListWorkersResponse>, Status> {
+        let selector = req.into_inner().labels;
+        let workers = self
+            .call(|tx| UserWorkerMsgs::LookupByLabels(selector, tx))
+            .await?;
+
+        Ok(Response::new(proto::ListWorkersResponse {
+            workers: workers.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn get_metrics(
+        &self,
+        _req: Request<proto::GetMetricsRequest>,
+    ) -> Result<Response<proto::GetMetricsResponse>, Status> {
+        let src = &self.metric_src;
+
+        Ok(Response::new(proto::GetMetricsResponse {
+            active_user_workers: src.active_user_workers() as u64,
+            received_requests: src.received_requests() as u64,
+            handled_requests: src.handled_requests() as u64,
+            open_connections: src.active_io() as u64,
+            user_worker_requests: src
+                .user_worker_request_stats()
+                .into_iter()
+                .map(|(key, stats)| (key, stats.into()))
+                .collect(),
+        }))
+    }
+
+    async fn drain(
+        &self,
+        _req: Request<proto::DrainRequest>,
+    ) -> Result<Response<proto::DrainResponse>, Status> {
+        self.call(UserWorkerMsgs::DrainRuntime).await?;
+
+        Ok(Response::new(proto::DrainResponse {}))
+    }
+}
+
+/// Merges the typed fields of the request into its JSON options.
+fn create_options(req: proto::CreateWorkerRequest) -> Result<UserWorkerCreateOptions, Error> {
+    let mut options = if req.options_json.is_empty() {
+        serde_json::Map::new()
+    } else {
+        serde_json::from_str(&req.options_json)?
+    };
+
+    if let Some(name) = PRIVILEGED_OPTIONS
+        .iter()
+        .find(|it| options.contains_key(**it))
+    {
+        bail!("option can't be set over gRPC: {}", name);
+    }
+
+    let mut set = |name: &str, value: serde_json::Value| {
+        let _ = options.insert(name.to_string(), value);
+    };
+
+    set("servicePath", req.service_path.into());
+
+    if !req.env_vars.is_empty() {
+        set(
+            "envVars",
+            serde_json::to_value(req.env_vars.into_iter().collect::<Vec<_>>())?,
+        );
+    }
+
+    if !req.labels.is_empty() {
+        set("labels", serde_json::to_value(req.labels)?);
+    }
+
+    if req.force_create {
+        set("forceCreate", true.into());
+    }
+
+    for (name, maybe_value) in [
+        ("memoryLimitMb", req.memory_limit_mb),
+        ("workerTimeoutMs", req.worker_timeout_ms),
+        ("cpuTimeSoftLimitMs", req.cpu_time_soft_limit_ms),
+        ("cpuTimeHardLimitMs", req.cpu_time_hard_limit_ms),
+    ] {
+        if let Some(value) = maybe_value {
+            set(name, value.into());
+        }
+    }
+
+    Ok(UserWorkerCreateOptions::from_partial(options.into())?)
+}
+
+impl From<WorkerRequestStats> for proto::RequestStats {
+    fn from(value: WorkerRequestStats) -> Self {
+        Self {
+            request_count: value.request_count as u64,
+            error_count: value.error_count as u64,
+            latency_p50_ms: value.latency_p50_ms,
+            latency_p95_ms: value.latency_p95_ms,
+            bytes_in: value.bytes_in,
+            bytes_out: value.bytes_out,
+            dispatch_latency_p50_ms: value.dispatch_latency_p50_ms,
+            dispatch_latency_p95_ms: value.dispatch_latency_p95_ms,
+            queue_depth: value.queue_depth as u64,
+            pending_ops: value.pending_ops as u64,
+            event_loop_lag_ms: value.event_loop_lag_ms,
+            open_resources: value.open_resources as u64,
+            active_connections: value.active_connections as u64,
+            rejected_connections: value.rejected_connections as u64,
+            boot_time_ms: value.boot_time_ms,
+        }
+    }
+}
+
+impl From<UserWorkerInfo> for proto::Worker {
+    fn from(value: UserWorkerInfo) -> Self {
+        let (exit_status, exit_detail) = match value.exit_status {
+            None => (String::new(), String::new()),
+            Some(WorkerExitStatus::Normal) => ("normal".into(), String::new()),
+            Some(WorkerExitStatus::WithUncaughtException(ev)) => {
+                ("uncaughtException".into(), ev.exception)
+            }
+            Some(WorkerExitStatus::WithPanic(msg)) => ("panic".into(), msg),
+        };
+
+        Self {
+            key: value.key,
+            service_path: value.service_path,
+            is_retired: value.is_retired,
+            is_throttled: value.is_throttled,
+            is_busy: value.is_busy,
+            in_flight_requests: value.in_flight_requests as u64,
+            boot_time_ms: value.boot_time_ms,
+            labels: value.labels,
+            stats: Some(value.stats.into()),
+            exit_status,
+            exit_detail,
+        }
+    }
+}
+
+/// Checks the bearer token of a call, if the server requires one.
+fn authorize(maybe_token: Option<TokenDigest>, req: Request<()>) -> Result<Request<()>, Status> {
+    let Some(token) = maybe_token else {
+        return Ok(req);
+    };
+
+    let is_authorized = req
+        .metadata()
+        .get("authorization")
+        .and_then(|it| it.to_str().ok())
+        .and_then(|it| it.strip_prefix("Bearer "))
+        .is_some_and(|it| TokenDigest::of(it) == token);
+
+    if is_authorized {
+        Ok(req)
+    } else {
+        Err(Status::unauthenticated("missing or invalid bearer token"))
+    }
+}
+
+/// Binds the address and serves the pool on it until `shutdown` is
+/// cancelled. Without a token, only loopback addresses can be bound.
+pub(crate) fn serve(
+    addr: SocketAddr,
+    maybe_token: Option<TokenDigest>,
+    worker_pool_tx: UserWorkerMsgsSender,
+    metric_src: SharedMetricSource,
+    shutdown: CancellationToken,
+) -> Result<(), Error> {
+    if maybe_token.is_none() && !addr.ip().is_loopback() {
+        bail!(
+            "cannot serve gRPC on {} without a token: only loopback addresses are served without one",
+            addr
+        );
+    }
+
+    let incoming = TcpIncoming::new(addr, true, None).map_err(|err| anyhow::anyhow!(err))?;
+    let service = PoolServer::with_interceptor(
+        PoolService {
+            worker_pool_tx,
+            metric_src,
+        },
+        move |req| authorize(maybe_token, req),
+    );
+
+    drop(tokio::spawn(async move {
+        let result = tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming_shutdown(incoming, shutdown.cancelled())
+            .await;
+
+        if let Err(err) = result {
+            error!("gRPC server failed: {}", err);
+        }
+    }));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_create_options_prefer_typed_fields() {
+        let options = create_options(proto::CreateWorkerRequest {
+            service_path: "./test_cases/main".to_string(),
+            env_vars: HashMap::from([("FOO".to_string(), "bar".to_string())]),
+            memory_limit_mb: Some(150),
+            options_json: r#"{ "memoryLimitMb": 1024, "verifyJwt": false }"#.to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let value = serde_json::to_value(options).unwrap();
+
+        assert_eq!(value["servicePath"], "./test_cases/main");
+        assert_eq!(value["memoryLimitMb"], 150);
+        assert_eq!(value["workerTimeoutMs"], 5 * 60 * 1000);
+        assert_eq!(value["verifyJwt"], false);
+        assert_eq!(value["envVars"], serde_json::json!([["FOO", "bar"]]));
+    }
+
+    #[test]
+    fn test_create_options_refuse_privileged_options() {
+        let result = create_options(proto::CreateWorkerRequest {
+            service_path: "./test_cases/main".to_string(),
+            options_json: r#"{ "allowSubprocess": true }"#.to_string(),
+            ..Default::default()
+        });
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_calls_require_the_token() {
+        let token = Some(TokenDigest::of("secret"));
+        let with_header = |value: &str| {
+            let mut req = Request::new(());
+
+            req.metadata_mut()
+                .insert("authorization", value.parse().unwrap());
+            req
+        };
+
+        assert!(authorize(None, Request::new(())).is_ok());
+        assert!(authorize(token, Request::new(())).is_err());
+        assert!(authorize(token, with_header("Bearer nope")).is_err());
+        assert!(authorize(token, with_header("Bearer secret")).is_ok());
+    }
+
+    #[test]
+    fn test_create_options_require_service_path() {
+        assert!(create_options(proto::CreateWorkerRequest::default()).is_err());
+    }
+
+    #[test]
+    fn test_worker_reports_its_panic() {
+        let worker = proto::Worker::from(UserWorkerInfo {
+            key: "key".to_string(),
+            service_path: "./test_cases/main".to_string(),
+            is_retired: true,
+            is_throttled: false,
+            is_busy: false,
+            in_flight_requests: 0,
+            boot_time_ms: 0,
+            labels: HashMap::new(),
+            stats: WorkerRequestStats::default(),
+            exit_status: Some(WorkerExitStatus::WithPanic("boom".to_string())),
+        });
+
+        assert_eq!(worker.exit_status, "panic");
+        assert_eq!(worker.exit_detail, "boom");
+    }
+}
@@ -1,14 +1,34 @@
 extern crate core;
 
+pub mod access_log;
+pub mod bench;
+pub mod bundle_signature;
 pub mod commands;
 pub mod deno_runtime;
+pub mod event_router;
+pub mod listener;
 pub mod macros;
+pub mod router;
 pub mod rt_worker;
 pub mod server;
+pub mod service_storage;
 pub mod snapshot;
+pub mod testing;
 pub mod utils;
 
+#[cfg(feature = "grpc")]
+pub mod grpc;
+
+#[cfg(unix)]
+pub mod admin;
+
+mod checksum_manifest;
+mod encoding;
+mod eszip_cache;
 mod inspector_server;
+mod proxy_protocol;
+mod self_test;
+mod service_archive;
 mod timeout;
 
 pub use inspector_server::InspectorOption;
@@ -0,0 +1,88 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Error};
+use ipnet::IpNet;
+use sb_workers::context::IpAccessList;
+
+/// A listener served next to the main one, with a main worker, a routing
+/// table and an access list of its own, e.g.
+/// `127.0.0.1:9001,main=./admin,routes=./admin.json,allow-cidr=10.0.0.0/8`.
+///
+/// Without `main`, its requests are handed to the main worker of the main
+/// listener. The access list of the main listener doesn't apply to it.
+/// `proxy-protocol=true` makes it read the address of the client from the
+/// PROXY protocol header of the connections coming from a `trusted-proxy`.
+#[derive(Debug, Clone)]
+pub struct ListenerConfig {
+    pub addr: SocketAddr,
+    pub maybe_main_service_path: Option<String>,
+    pub maybe_routes_path: Option<String>,
+    pub ip_access_list: IpAccessList,
+    pub proxy_protocol: bool,
+    /// Peers the PROXY protocol header is read from. Connections from other
+    /// peers are served with their own address.
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+impl FromStr for ListenerConfig {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',').map(str::trim);
+        let addr = parts
+            .next()
+            .unwrap_or_default()
+            .parse::<SocketAddr>()
+            .with_context(|| format!("expected <ADDR>[,<KEY>=<VALUE>...]: {}", s))?;
+
+        let mut config = Self {
+            addr,
+            maybe_main_service_path: None,
+            maybe_routes_path: None,
+            ip_access_list: IpAccessList::default(),
+            proxy_protocol: false,
+            trusted_proxies: vec![],
+        };
+
+        for part in parts.filter(|it| !it.is_empty()) {
+            let Some((key, value)) = part.split_once('=').filter(|(_, it)| !it.is_empty()) else {
+                bail!("expected <KEY>=<VALUE>: {}", part);
+            };
+
+            match key {
+                "main" => config.maybe_main_service_path = Some(value.to_string()),
+                "routes" => config.maybe_routes_path = Some(value.to_string()),
+                "allow-cidr" => config.ip_access_list.allow.push(
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid CIDR: {}", value))?,
+                ),
+                "deny-cidr" => config.ip_access_list.deny.push(
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid CIDR: {}", value))?,
+                ),
+                "proxy-protocol" => {
+                    config.proxy_protocol = value
+                        .parse()
+                        .with_context(|| format!("expected true or false: {}", value))?
+                }
+                "trusted-proxy" => config.trusted_proxies.push(
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid CIDR: {}", value))?,
+                ),
+                _ => bail!("unknown listener option: {}", key),
+            }
+        }
+
+        match (config.proxy_protocol, config.trusted_proxies.is_empty()) {
+            (true, true) => bail!("proxy-protocol=true needs a trusted-proxy: {}", s),
+            (false, false) => bail!("trusted-proxy needs proxy-protocol=true: {}", s),
+            _ => {}
+        }
+
+        Ok(config)
+    }
+}
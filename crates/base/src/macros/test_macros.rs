@@ -25,6 +25,12 @@ macro_rules! integration_test_listen_fut {
             None,
             Some("https://esm.sh/preact".to_string()),
             Some("jsx-runtime".to_string()),
+            Default::default(),
+            vec![],
+            None,
+            vec![],
+            None,
+            vec![],
         )
         .boxed()
     }};
@@ -0,0 +1,195 @@
+//! Reads the PROXY protocol header load balancers send ahead of a
+//! connection, to learn the address of the client behind them.
+//!
+//! Both the text (v1) and the binary (v2) versions are understood. The
+//! header is read without reading past it, so that the rest of the stream
+//! is left as is for the HTTP server.
+//!
+//! NOTE: Anyone can send the header, so it is only read from the load
+//! balancers the listener trusts.
+//!
+//! https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str;
+
+use anyhow::{bail, Context, Error};
+use ipnet::IpNet;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LEN: usize = 16;
+
+const V2_COMMAND_LOCAL: u8 = 0x20;
+const V2_COMMAND_PROXY: u8 = 0x21;
+
+const V2_FAMILY_TCP4: u8 = 0x11;
+const V2_FAMILY_TCP6: u8 = 0x21;
+
+/// The shortest header of either version, `PROXY UNKNOWN\r\n`.
+const MIN_HEADER_LEN: usize = 15;
+
+/// Whether a connection from `peer` comes from one of `trusted_proxies`, and
+/// so starts with the header.
+pub(crate) fn is_trusted(trusted_proxies: &[IpNet], peer: IpAddr) -> bool {
+    let peer = peer.to_canonical();
+
+    trusted_proxies.iter().any(|it| it.contains(&peer))
+}
+
+/// Reads the header off `io`, and returns the address of the client it
+/// carries. That is `None` if the load balancer connected on its own behalf,
+/// e.g. for a health check, or if the client isn't on TCP.
+pub(crate) async fn read_header<I>(io: &mut I) -> Result<Option<SocketAddr>, Error>
+where
+    I: AsyncRead + Unpin,
+{
+    let mut buf = vec![0; MIN_HEADER_LEN];
+
+    io.read_exact(&mut buf).await?;
+
+    if buf.starts_with(V2_SIGNATURE) {
+        buf.resize(V2_HEADER_LEN, 0);
+        io.read_exact(&mut buf[MIN_HEADER_LEN..]).await?;
+
+        let len = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+        let mut addrs = vec![0; len];
+
+        io.read_exact(&mut addrs).await?;
+
+        return parse_v2(buf[12], buf[13], &addrs);
+    }
+
+    if !buf.starts_with(V1_PREFIX) {
+        bail!("missing PROXY protocol header");
+    }
+
+    while !buf.ends_with(b"\r\n") {
+        if buf.len() == V1_MAX_LEN {
+            bail!("PROXY protocol header is too long");
+        }
+
+        buf.push(io.read_u8().await?);
+    }
+
+    parse_v1(&buf[V1_PREFIX.len()..buf.len() - 2])
+}
+
+fn parse_v1(line: &[u8]) -> Result<Option<SocketAddr>, Error> {
+    let line = str::from_utf8(line).context("invalid PROXY protocol header")?;
+    let mut parts = line.split(' ');
+
+    match parts.next() {
+        Some("TCP4" | "TCP6") => {}
+        Some("UNKNOWN") => return Ok(None),
+        _ => bail!("invalid PROXY protocol header: {}", line),
+    }
+
+    let (Some(ip), Some(_), Some(port), Some(_), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        bail!("invalid PROXY protocol header: {}", line);
+    };
+
+    Ok(Some(SocketAddr::new(
+        ip.parse::<IpAddr>()
+            .with_context(|| format!("invalid PROXY protocol header: {}", line))?,
+        port.parse::<u16>()
+            .with_context(|| format!("invalid PROXY protocol header: {}", line))?,
+    )))
+}
+
+fn parse_v2(command: u8, family: u8, addrs: &[u8]) -> Result<Option<SocketAddr>, Error> {
+    match command {
+        V2_COMMAND_LOCAL => return Ok(None),
+        V2_COMMAND_PROXY => {}
+        _ => bail!("unknown PROXY protocol command: {:#x}", command),
+    }
+
+    let (ip, rest) = match family {
+        V2_FAMILY_TCP4 if addrs.len() >= 12 => {
+            let ip: [u8; 4] = addrs[..4].try_into().unwrap();
+
+            (IpAddr::from(Ipv4Addr::from(ip)), &addrs[8..])
+        }
+
+        V2_FAMILY_TCP6 if addrs.len() >= 36 => {
+            let ip: [u8; 16] = addrs[..16].try_into().unwrap();
+
+            (IpAddr::from(Ipv6Addr::from(ip)), &addrs[32..])
+        }
+
+        V2_FAMILY_TCP4 | V2_FAMILY_TCP6 => bail!("truncated PROXY protocol header"),
+        _ => return Ok(None),
+    };
+
+    Ok(Some(SocketAddr::new(
+        ip,
+        u16::from_be_bytes([rest[0], rest[1]]),
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn read(mut input: &[u8]) -> (Result<Option<SocketAddr>, Error>, &[u8]) {
+        let result = read_header(&mut input).await;
+
+        (result, input)
+    }
+
+    #[tokio::test]
+    async fn test_v1_header_is_read_up_to_its_end() {
+        let (result, rest) = read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /").await;
+
+        assert_eq!(result.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+
+        let (result, rest) = read(b"PROXY UNKNOWN\r\nGET /").await;
+
+        assert_eq!(result.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn test_v2_header_is_read_up_to_its_end() {
+        let mut input = V2_SIGNATURE.to_vec();
+
+        input.extend_from_slice(&[V2_COMMAND_PROXY, V2_FAMILY_TCP4, 0, 12]);
+        input.extend_from_slice(&[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb]);
+        input.extend_from_slice(b"GET /");
+
+        let (result, rest) = read(&input).await;
+
+        assert_eq!(result.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[test]
+    fn test_header_is_only_trusted_from_trusted_proxies() {
+        let trusted_proxies = ["10.0.0.0/8".parse().unwrap()];
+
+        assert!(is_trusted(&trusted_proxies, "10.1.2.3".parse().unwrap()));
+        assert!(is_trusted(
+            &trusted_proxies,
+            "::ffff:10.1.2.3".parse().unwrap()
+        ));
+        assert!(!is_trusted(&trusted_proxies, "192.0.2.1".parse().unwrap()));
+        assert!(!is_trusted(&[], "10.1.2.3".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_connection_without_header_is_refused() {
+        let (result, _) = read(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+
+        assert!(result.is_err());
+    }
+}
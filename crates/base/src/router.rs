@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+
+use anyhow::{anyhow, Context, Error};
+use deno_core::serde_json;
+use futures_util::Stream;
+use http::header::{CONTENT_TYPE, HOST};
+use http::uri::PathAndQuery;
+use http::{Request, Response, StatusCode, Uri};
+use hyper::Body;
+use log::error;
+use sb_workers::context::{
+    UserWorkerMsgs, UserWorkerMsgsSender, UserWorkerRuntimeOpts, WorkerContextInitOpts,
+    WorkerRuntimeOpts,
+};
+use serde::Deserialize;
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+
+/// A route of the [`RoutingTable`]. All the conditions that are given must
+/// hold for a request to match.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Route {
+    /// Host name of the request, without the port. `*.example.com` matches
+    /// any subdomain of `example.com`.
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Matched against whole path segments, i.e. `/api` matches `/api` and
+    /// `/api/users`, but not `/apis`.
+    #[serde(default)]
+    pub path_prefix: Option<String>,
+    /// Headers that must be present with exactly these values.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub service_path: String,
+    /// Removes `path_prefix` from the path before the request reaches the
+    /// worker.
+    #[serde(default)]
+    pub strip_prefix: bool,
+    #[serde(default)]
+    pub import_map_path: Option<String>,
+    #[serde(default)]
+    pub env_vars: HashMap<String, String>,
+    /// See [`UserWorkerRuntimeOpts::handler_export`].
+    #[serde(default)]
+    pub handler_export: Option<String>,
+}
+
+impl Route {
+    fn matches(&self, req: &Request<Body>) -> bool {
+        if let Some(expected) = self.host.as_deref() {
+            let Some(host) = request_host(req).map(str::to_ascii_lowercase) else {
+                return false;
+            };
+
+            let expected = expected.to_ascii_lowercase();
+            let matched = match expected.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|it| it.len() > 1 && it.ends_with('.')),
+                None => host == expected,
+            };
+
+            if !matched {
+                return false;
+            }
+        }
+
+        if let Some(prefix) = self.path_prefix.as_deref() {
+            if strip_path_prefix(req.uri().path(), prefix).is_none() {
+                return false;
+            }
+        }
+
+        self.headers.iter().all(|(name, value)| {
+            req.headers()
+                .get(name.as_str())
+                .is_some_and(|it| it.as_bytes() == value.as_bytes())
+        })
+    }
+}
+
+/// Routes evaluated by the listener before a request would be handed to the
+/// main worker. The first matching route wins, and requests that match none
+/// of them go to the main worker as usual.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RoutingTable {
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    /// Mounts every subdirectory of this directory under its name, i.e.
+    /// `/foo/bar` is served by `<services_dir>/foo`. Checked after `routes`.
+    /// The subdirectories are listed once, when the table is loaded.
+    #[serde(default)]
+    pub services_dir: Option<PathBuf>,
+}
+
+impl RoutingTable {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("can't read routing table ({})", path.display()))?;
+
+        serde_json::from_str(&content)
+            .with_context(|| format!("invalid routing table ({})", path.display()))
+    }
+}
+
+/// Lists the subdirectories of `services_dir` as routes, keyed by their name.
+fn mount_services(services_dir: &Path) -> Result<HashMap<String, Arc<Route>>, Error> {
+    let mut mounts = HashMap::new();
+    let entries = std::fs::read_dir(services_dir)
+        .with_context(|| format!("can't read services directory ({})", services_dir.display()))?;
+
+    for entry in entries {
+        let service_path = entry?.path();
+        let Some(name) = service_path.file_name().and_then(|it| it.to_str()) else {
+            continue;
+        };
+
+        // NOTE: Hidden directories are not services.
+        if name.starts_with('.') || !service_path.is_dir() {
+            continue;
+        }
+
+        mounts.insert(
+            name.to_string(),
+            Arc::new(Route {
+                path_prefix: Some(format!("/{}", name)),
+                service_path: service_path.to_string_lossy().into_owned(),
+                ..Default::default()
+            }),
+        );
+    }
+
+    Ok(mounts)
+}
+
+pub(crate) struct Router {
+    routes: Vec<Arc<Route>>,
+    mounts: HashMap<String, Arc<Route>>,
+    worker_pool_tx: UserWorkerMsgsSender,
+    no_module_cache: bool,
+}
+
+impl Router {
+    pub(crate) fn new(
+        table: RoutingTable,
+        worker_pool_tx: UserWorkerMsgsSender,
+        no_module_cache: bool,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            routes: table.routes.into_iter().map(Arc::new).collect(),
+            mounts: table
+                .services_dir
+                .as_deref()
+                .map(mount_services)
+                .transpose()?
+                .unwrap_or_default(),
+            worker_pool_tx,
+            no_module_cache,
+        })
+    }
+
+    pub(crate) fn route(&self, req: &Request<Body>) -> Option<Arc<Route>> {
+        if let Some(route) = self.routes.iter().find(|it| it.matches(req)) {
+            return Some(route.clone());
+        }
+
+        let name = req.uri().path().split('/').nth(1)?;
+
+        self.mounts.get(name).cloned()
+    }
+
+    /// Sends `req` to the worker of `route`, booting it first if the pool has
+    /// no live worker for its service path.
+    pub(crate) async fn dispatch(
+        &self,
+        route: Arc<Route>,
+        req: Request<Body>,
+        conn_token: Option<CancellationToken>,
+    ) -> Response<Body> {
+        match self.dispatch_inner(&route, req, conn_token).await {
+            Ok(res) => res,
+            Err(err) => {
+                error!(
+                    "failed to route request to user worker (service path: {}): {:#}",
+                    route.service_path, err
+                );
+
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::json!({ "msg": err.to_string() }).to_string(),
+                    ))
+                    .unwrap()
+            }
+        }
+    }
+
+    async fn dispatch_inner(
+        &self,
+        route: &Route,
+        mut req: Request<Body>,
+        conn_token: Option<CancellationToken>,
+    ) -> Result<Response<Body>, Error> {
+        if route.strip_prefix {
+            if let Some(prefix) = route.path_prefix.as_deref() {
+                *req.uri_mut() = strip_uri_prefix(req.uri(), prefix)?;
+            }
+        }
+
+        let (tx, rx) = oneshot::channel();
+
+        self.worker_pool_tx
+            .send(UserWorkerMsgs::Create(
+                WorkerContextInitOpts {
+                    service_path: PathBuf::from(&route.service_path),
+                    no_module_cache: self.no_module_cache,
+                    import_map_path: route.import_map_path.clone(),
+                    env_vars: route.env_vars.clone(),
+                    events_rx: None,
+                    timing: None,
+                    conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts {
+                        handler_export: route.handler_export.clone(),
+                        ..Default::default()
+                    }),
+                    maybe_eszip: None,
+                    maybe_eszip_signature: None,
+                    maybe_module_code: None,
+                    maybe_entrypoint: None,
+                    maybe_decorator: None,
+                    static_patterns: vec![],
+                    maybe_jsx_import_source_config: None,
+                },
+                tx,
+            ))
+            .map_err(|_| anyhow!("user worker pool is not available"))?;
+
+        let key = rx.await??.key;
+        let (tx, rx) = oneshot::channel();
+
+        self.worker_pool_tx
+            .send(UserWorkerMsgs::SendRequest(key, req, tx, conn_token))
+            .map_err(|_| anyhow!("user worker pool is not available"))?;
+
+        let (res, req_end_tx) = rx.await??;
+        let (parts, body) = res.into_parts();
+
+        Ok(Response::from_parts(
+            parts,
+            Body::wrap_stream(NotifyOnDrop {
+                inner: body,
+                req_end_tx,
+            }),
+        ))
+    }
+}
+
+/// Tells the pool that the request is over once the response body has been
+/// consumed or dropped.
+struct NotifyOnDrop {
+    inner: Body,
+    req_end_tx: mpsc::UnboundedSender<()>,
+}
+
+impl Drop for NotifyOnDrop {
+    fn drop(&mut self) {
+        let _ = self.req_end_tx.send(());
+    }
+}
+
+impl Stream for NotifyOnDrop {
+    type Item = <Body as Stream>::Item;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+fn request_host(req: &Request<Body>) -> Option<&str> {
+    let host = match req.headers().get(HOST) {
+        Some(value) => value.to_str().ok()?,
+        None => req.uri().host()?,
+    };
+
+    // NOTE: Bracketed IPv6 literals carry colons of their own.
+    Some(match host.rfind(':') {
+        Some(idx) if !host[idx..].contains(']') => &host[..idx],
+        _ => host,
+    })
+}
+
+fn strip_path_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let prefix = prefix.trim_end_matches('/');
+    let rest = path.strip_prefix(prefix)?;
+
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+fn strip_uri_prefix(uri: &Uri, prefix: &str) -> Result<Uri, Error> {
+    let rest = strip_path_prefix(uri.path(), prefix).unwrap_or(uri.path());
+    let path = if rest.is_empty() { "/" } else { rest };
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    };
+
+    let mut parts = uri.clone().into_parts();
+
+    parts.path_and_query = Some(PathAndQuery::try_from(path_and_query)?);
+
+    Ok(Uri::from_parts(parts)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mount_services_lists_subdirectories() {
+        let mounts = mount_services(Path::new("./test_cases/services")).unwrap();
+
+        assert_eq!(mounts.len(), 1);
+        assert_eq!(
+            mounts["hello"].service_path,
+            Path::new("./test_cases/services")
+                .join("hello")
+                .to_string_lossy()
+        );
+        assert_eq!(mounts["hello"].path_prefix.as_deref(), Some("/hello"));
+    }
+}
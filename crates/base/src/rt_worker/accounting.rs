@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use event_worker::events::{EventMetadata, UsageReportEvent, WorkerUsage};
+use sb_workers::context::TenantUsage;
+use tokio::time::Instant;
+
+use super::scheduler::TENANT_LABEL;
+
+/// Aggregates the resources used by user workers per tenant, so operators
+/// can bill tenants without reconstructing usage from raw events.
+///
+/// Usage is accrued once a worker exits.
+pub struct Accounting {
+    totals: HashMap<String, WorkerUsage>,
+    unreported: HashMap<String, WorkerUsage>,
+    last_reported_at: Instant,
+}
+
+impl Default for Accounting {
+    fn default() -> Self {
+        Self {
+            totals: HashMap::new(),
+            unreported: HashMap::new(),
+            last_reported_at: Instant::now(),
+        }
+    }
+}
+
+impl Accounting {
+    pub fn accrue(&mut self, tenant: String, usage: WorkerUsage) {
+        *self.totals.entry(tenant.clone()).or_default() += usage;
+        *self.unreported.entry(tenant).or_default() += usage;
+    }
+
+    pub fn usage(&self) -> Vec<TenantUsage> {
+        self.totals
+            .iter()
+            .map(|(tenant, usage)| TenantUsage {
+                tenant: tenant.clone(),
+                usage: *usage,
+            })
+            .collect()
+    }
+
+    /// Returns a report for every tenant that accrued usage since the
+    /// previous call.
+    pub fn take_reports(&mut self) -> Vec<UsageReportEvent> {
+        let period_ms = self.last_reported_at.elapsed().as_millis() as u64;
+
+        self.last_reported_at = Instant::now();
+        self.unreported
+            .drain()
+            .map(|(tenant, usage)| UsageReportEvent {
+                tenant,
+                period_ms,
+                usage,
+            })
+            .collect()
+    }
+}
+
+/// Returns the tenant the usage of a worker is billed to. It follows the
+/// same rule as the dispatch scheduler.
+pub fn tenant_of(metadata: &EventMetadata) -> String {
+    metadata
+        .labels
+        .get(TENANT_LABEL)
+        .cloned()
+        .or_else(|| metadata.service_path.clone())
+        .unwrap_or_default()
+}
+
+pub fn worker_usage(
+    cpu_time_ms: u64,
+    memory_limit_mb: u64,
+    lifetime: Duration,
+    request_count: u64,
+    egress_bytes: u64,
+) -> WorkerUsage {
+    WorkerUsage {
+        cpu_time_ms,
+        memory_gb_seconds: memory_limit_mb as f64 / 1024.0 * lifetime.as_secs_f64(),
+        request_count,
+        egress_bytes,
+        worker_count: 1,
+    }
+}
@@ -0,0 +1,377 @@
+//! Lets several runtimes share their warm user workers, so that a request for
+//! a service that isn't booted here can be served by a peer that already has
+//! it booted instead of paying for another cold boot.
+//!
+//! Peers are given as a static list. Each runtime polls the others for the
+//! services they have booted, and the pool forwards a request to one of them
+//! when it has no worker of its own for the service.
+//!
+//! Polls and forwarded requests are signed with a key the peers share, and
+//! forwarded requests carry the address of the client they came from.
+
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Error};
+use deno_core::serde_json;
+use http::header::HeaderValue;
+use http::{Request, Response, Uri};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client};
+use log::debug;
+use sb_core::conn_sync::ConnectionInfo;
+use sb_core::signing::{self, INTERNAL_HEADER_PREFIX};
+use serde::{Deserialize, Serialize};
+
+pub use sb_core::signing::SigningKey;
+
+/// Set on the requests forwarded to a peer. The peer boots the service
+/// itself rather than forwarding them again. The listener only keeps it on
+/// requests coming from a peer.
+pub const CLUSTER_FORWARDED_HEADER: &str = "x-sb-cluster-forwarded";
+
+/// Address of the client a forwarded request came from.
+pub const CLUSTER_CLIENT_HEADER: &str = "x-sb-cluster-client";
+
+/// Method and path the signature of a peer request is good for, so that it
+/// can't be moved to another one.
+const CLUSTER_TARGET_HEADER: &str = "x-sb-cluster-target";
+
+/// When a peer request was signed, in seconds since the Unix epoch.
+const CLUSTER_TIMESTAMP_HEADER: &str = "x-sb-cluster-timestamp";
+
+/// How old a signed peer request can get before it is turned away, which
+/// also covers clocks of peers that are a bit apart.
+const MAX_PEER_REQUEST_AGE: Duration = Duration::from_secs(30);
+
+/// Path the listener answers the polls of the peers on.
+pub const CLUSTER_SERVICES_PATH: &str = "/_internal/cluster/services";
+
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    pub peers: Vec<SocketAddr>,
+    pub poll_interval: Duration,
+    /// Shared by all the peers.
+    pub key: SigningKey,
+}
+
+/// The body of the response to a poll.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterServices {
+    pub services: Vec<String>,
+}
+
+struct ClusterInner {
+    peers: Vec<SocketAddr>,
+    key: SigningKey,
+    client: Client<HttpConnector>,
+    /// Services each peer had booted when it was last polled.
+    warm_services: RwLock<HashMap<SocketAddr, HashSet<String>>>,
+}
+
+#[derive(Clone)]
+pub struct Cluster {
+    inner: Arc<ClusterInner>,
+}
+
+impl Cluster {
+    /// Starts polling the peers. The polling stops once every handle of the
+    /// cluster has been dropped.
+    pub fn start(config: ClusterConfig) -> Self {
+        let inner = Arc::new(ClusterInner {
+            peers: config.peers,
+            key: config.key,
+            client: Client::new(),
+            warm_services: RwLock::default(),
+        });
+
+        drop(tokio::spawn(poll_peers(
+            Arc::downgrade(&inner),
+            config.poll_interval,
+        )));
+
+        Self { inner }
+    }
+
+    /// Returns the first peer, in the order they were given, that has the
+    /// service booted.
+    pub fn warm_peer(&self, service_path: &str) -> Option<SocketAddr> {
+        let warm_services = self.inner.warm_services.read().unwrap();
+
+        self.inner.peers.iter().copied().find(|peer| {
+            warm_services
+                .get(peer)
+                .is_some_and(|it| it.contains(service_path))
+        })
+    }
+
+    pub fn is_warm(&self, peer: SocketAddr, service_path: &str) -> bool {
+        self.inner
+            .warm_services
+            .read()
+            .unwrap()
+            .get(&peer)
+            .is_some_and(|it| it.contains(service_path))
+    }
+
+    /// Stops treating the service as booted on the peer until the next poll
+    /// says otherwise.
+    pub fn forget(&self, peer: SocketAddr, service_path: &str) {
+        if let Some(services) = self.inner.warm_services.write().unwrap().get_mut(&peer) {
+            services.remove(service_path);
+        }
+    }
+
+    /// Sends the request to the listener of the peer, which dispatches it as
+    /// if it had come from the client it came from here.
+    pub async fn forward(
+        &self,
+        peer: SocketAddr,
+        mut req: Request<Body>,
+    ) -> Result<Response<Body>, Error> {
+        let client_addr = req
+            .extensions()
+            .get::<ConnectionInfo>()
+            .map(|it| it.remote_addr)
+            .ok_or_else(|| anyhow!("request has no client address"))?;
+
+        let path_and_query = req
+            .uri()
+            .path_and_query()
+            .map_or("/", |it| it.as_str())
+            .to_string();
+
+        *req.uri_mut() = Uri::builder()
+            .scheme("http")
+            .authority(peer.to_string())
+            .path_and_query(path_and_query)
+            .build()?;
+
+        req.headers_mut()
+            .insert(CLUSTER_FORWARDED_HEADER, HeaderValue::from_static("1"));
+        req.headers_mut().insert(
+            CLUSTER_CLIENT_HEADER,
+            HeaderValue::from_str(&client_addr.to_string())?,
+        );
+
+        sign(&self.inner.key, &mut req)?;
+
+        Ok(self.inner.client.request(req).await?)
+    }
+}
+
+/// Tells if the request was forwarded by a peer.
+pub fn is_forwarded(req: &Request<Body>) -> bool {
+    req.headers().contains_key(CLUSTER_FORWARDED_HEADER)
+}
+
+/// Signs a request sent to a peer. Internal headers other than those of the
+/// cluster are dropped, as the peer would not trust them anyway.
+fn sign(key: &SigningKey, req: &mut Request<Body>) -> Result<(), Error> {
+    let names = req
+        .headers()
+        .keys()
+        .filter(|it| {
+            let name = it.as_str();
+
+            name.starts_with(INTERNAL_HEADER_PREFIX)
+                && name != CLUSTER_FORWARDED_HEADER
+                && name != CLUSTER_CLIENT_HEADER
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    for name in names {
+        req.headers_mut().remove(name);
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let target = format!(
+        "{} {}",
+        req.method(),
+        req.uri().path_and_query().map_or("/", |it| it.as_str())
+    );
+
+    req.headers_mut()
+        .insert(CLUSTER_TARGET_HEADER, HeaderValue::from_str(&target)?);
+    req.headers_mut()
+        .insert(CLUSTER_TIMESTAMP_HEADER, HeaderValue::from(now));
+
+    signing::sign_with(key, req);
+
+    Ok(())
+}
+
+/// What a peer vouches for in a request it signed.
+pub struct PeerRequest {
+    /// The client the request came from, if the peer forwarded it.
+    pub client_addr: Option<SocketAddr>,
+}
+
+/// Checks that the request was signed by a peer, recently and for the
+/// method and path it is for.
+pub fn authenticate<B>(key: &SigningKey, req: &Request<B>) -> Option<PeerRequest> {
+    let headers = req.headers();
+    let pairs = headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)));
+
+    if !signing::verify_with(key, pairs) {
+        return None;
+    }
+
+    let target = format!(
+        "{} {}",
+        req.method(),
+        req.uri().path_and_query().map_or("/", |it| it.as_str())
+    );
+
+    if headers.get(CLUSTER_TARGET_HEADER)?.as_bytes() != target.as_bytes() {
+        return None;
+    }
+
+    let signed_at = headers
+        .get(CLUSTER_TIMESTAMP_HEADER)?
+        .to_str()
+        .ok()?
+        .parse::<u64>()
+        .ok()?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+
+    if now.abs_diff(signed_at) > MAX_PEER_REQUEST_AGE.as_secs() {
+        return None;
+    }
+
+    let client_addr = match headers.get(CLUSTER_CLIENT_HEADER) {
+        Some(value) => Some(value.to_str().ok()?.parse::<SocketAddr>().ok()?),
+        None => None,
+    };
+
+    Some(PeerRequest { client_addr })
+}
+
+async fn poll_peers(inner: Weak<ClusterInner>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+
+        for peer in inner.peers.iter().copied() {
+            let services = match tokio::time::timeout(
+                interval.period(),
+                poll(&inner.client, &inner.key, peer),
+            )
+            .await
+            {
+                Ok(Ok(it)) => it.services.into_iter().collect(),
+                Ok(Err(err)) => {
+                    debug!("failed to poll cluster peer {}: {}", peer, err);
+                    HashSet::new()
+                }
+
+                Err(_) => {
+                    debug!("cluster peer {} did not respond in time", peer);
+                    HashSet::new()
+                }
+            };
+
+            let _ = inner.warm_services.write().unwrap().insert(peer, services);
+        }
+    }
+}
+
+async fn poll(
+    client: &Client<HttpConnector>,
+    key: &SigningKey,
+    peer: SocketAddr,
+) -> Result<ClusterServices, Error> {
+    let uri = Uri::builder()
+        .scheme("http")
+        .authority(peer.to_string())
+        .path_and_query(CLUSTER_SERVICES_PATH)
+        .build()?;
+
+    let mut req = Request::get(uri).body(Body::empty())?;
+
+    sign(key, &mut req)?;
+
+    let res = client.request(req).await?;
+
+    if !res.status().is_success() {
+        return Err(anyhow!("unexpected status: {}", res.status()));
+    }
+
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+
+    Ok(serde_json::from_slice(&body)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn signed_request(key: &SigningKey, client_addr: Option<&str>) -> Request<Body> {
+        let mut req = Request::post("http://127.0.0.1:9999/hello?x=1")
+            .header("x-sb-client-ip", "10.0.0.1")
+            .body(Body::empty())
+            .unwrap();
+
+        if let Some(addr) = client_addr {
+            req.headers_mut()
+                .insert(CLUSTER_CLIENT_HEADER, HeaderValue::from_str(addr).unwrap());
+        }
+
+        sign(key, &mut req).unwrap();
+        req
+    }
+
+    #[test]
+    fn test_authenticate_peer_request() {
+        let key = SigningKey::new(b"cluster");
+        let req = signed_request(&key, Some("192.0.2.1:4321"));
+
+        // Internal headers of the sender aren't passed on.
+        assert!(!req.headers().contains_key("x-sb-client-ip"));
+
+        let peer_req = authenticate(&key, &req).unwrap();
+
+        assert_eq!(
+            peer_req.client_addr,
+            Some("192.0.2.1:4321".parse().unwrap())
+        );
+
+        assert!(authenticate(&SigningKey::new(b"other"), &req).is_none());
+    }
+
+    #[test]
+    fn test_authenticate_rejects_tampered_requests() {
+        let key = SigningKey::new(b"cluster");
+
+        let mut req = signed_request(&key, Some("192.0.2.1:4321"));
+        req.headers_mut().insert(
+            CLUSTER_CLIENT_HEADER,
+            HeaderValue::from_static("203.0.113.1:1"),
+        );
+        assert!(authenticate(&key, &req).is_none());
+
+        let mut req = signed_request(&key, None);
+        *req.uri_mut() = Uri::from_static("http://127.0.0.1:9999/other");
+        assert!(authenticate(&key, &req).is_none());
+
+        let mut req = signed_request(&key, None);
+        req.headers_mut()
+            .insert(CLUSTER_TIMESTAMP_HEADER, HeaderValue::from(1u64));
+        signing::sign_with(&key, &mut req);
+        assert!(authenticate(&key, &req).is_none());
+    }
+}
@@ -0,0 +1,119 @@
+use http::header::{
+    ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+    ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+};
+use http::{HeaderValue, Method, Request, Response, StatusCode};
+use hyper::Body;
+use sb_workers::context::CorsPolicy;
+
+fn allowed_origin(policy: &CorsPolicy, origin: &HeaderValue) -> Option<HeaderValue> {
+    let origin_str = origin.to_str().ok()?;
+
+    if policy.allowed_origins.iter().any(|it| it == origin_str) {
+        return Some(origin.clone());
+    }
+
+    // NOTE: A policy allowing credentials can't have a wildcard, see
+    // `CorsPolicy::validate`.
+    if policy.allowed_origins.iter().any(|it| it == "*") {
+        return Some(HeaderValue::from_static("*"));
+    }
+
+    None
+}
+
+fn join(values: &[String]) -> Option<HeaderValue> {
+    HeaderValue::from_str(&values.join(", ")).ok()
+}
+
+/// Answers a CORS preflight request on behalf of the worker. Returns `None`
+/// if `req` is not a preflight request.
+pub(crate) fn preflight(policy: &CorsPolicy, req: &Request<Body>) -> Option<Response<Body>> {
+    let headers = req.headers();
+
+    if req.method() != Method::OPTIONS || !headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD) {
+        return None;
+    }
+
+    let origin = headers.get(ORIGIN)?;
+    let mut res = Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header(
+            VARY,
+            "Origin, Access-Control-Request-Method, Access-Control-Request-Headers",
+        )
+        .body(Body::empty())
+        .unwrap();
+
+    // NOTE: A disallowed origin still gets an answer, just one without any
+    // of the headers that would let the browser go on with the request.
+    let Some(allowed_origin) = allowed_origin(policy, origin) else {
+        return Some(res);
+    };
+
+    let res_headers = res.headers_mut();
+
+    res_headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allowed_origin);
+
+    if let Some(methods) = join(&policy.allowed_methods) {
+        res_headers.insert(ACCESS_CONTROL_ALLOW_METHODS, methods);
+    }
+
+    if policy.allowed_headers.is_empty() {
+        // Allow whatever the browser asked for.
+        if let Some(requested) = headers.get(ACCESS_CONTROL_REQUEST_HEADERS) {
+            res_headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, requested.clone());
+        }
+    } else if let Some(allowed) = join(&policy.allowed_headers) {
+        res_headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allowed);
+    }
+
+    if let Some(max_age) = policy.max_age_secs {
+        res_headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+    }
+
+    if policy.allow_credentials {
+        res_headers.insert(
+            ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+
+    Some(res)
+}
+
+/// Adds the CORS headers to the response of an actual (non-preflight)
+/// request, unless the worker has already set them itself.
+pub(crate) fn apply(
+    policy: &CorsPolicy,
+    maybe_origin: Option<&HeaderValue>,
+    res: &mut Response<Body>,
+) {
+    let Some(origin) = maybe_origin else {
+        return;
+    };
+
+    let headers = res.headers_mut();
+
+    if headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN) {
+        return;
+    }
+
+    let Some(allowed_origin) = allowed_origin(policy, origin) else {
+        return;
+    };
+
+    if allowed_origin != "*" {
+        headers.append(VARY, HeaderValue::from_static("Origin"));
+    }
+
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allowed_origin);
+
+    if policy.allow_credentials {
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+}
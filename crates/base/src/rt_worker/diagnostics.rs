@@ -0,0 +1,271 @@
+//! Diagnostic bundles of the user workers that the supervisor terminates for
+//! hitting a limit, for postmortems of the kill.
+//!
+//! The bundle is captured from the interrupt that terminates the isolate, so
+//! its stack is the one the isolate was paused at. Along with it come the
+//! heap statistics, the last lines the worker logged and the resources it
+//! held open, which back the async ops it still had pending.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use deno_core::{serde_json, v8, JsRuntime, OpState};
+use event_worker::events::{ShutdownReason, WorkerLogTail};
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::resources::count_open_resources;
+
+const STACK_FRAME_LIMIT: usize = 32;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeapDiagnostics {
+    pub used_heap_size: usize,
+    pub total_heap_size: usize,
+    pub heap_size_limit: usize,
+    pub external_memory: usize,
+    pub malloced_memory: usize,
+    pub number_of_native_contexts: usize,
+    pub number_of_detached_contexts: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostics {
+    pub heap: HeapDiagnostics,
+    /// Innermost frame first. Empty if the isolate wasn't running JavaScript
+    /// when it was interrupted.
+    pub stack: Vec<String>,
+    pub logs: Vec<String>,
+    /// Open resources by their name.
+    pub resources: BTreeMap<String, usize>,
+}
+
+/// Where the supervisor finds what the interrupt captured.
+#[derive(Clone, Default)]
+pub(crate) struct DiagnosticsCapture(Arc<Mutex<Option<Diagnostics>>>);
+
+impl DiagnosticsCapture {
+    pub(crate) fn take(&self) -> Option<Diagnostics> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+/// Kept in a slot of the isolate, for the interrupt to reach the op state.
+struct DiagnosticsProbe {
+    op_state: Weak<RefCell<OpState>>,
+    capture: DiagnosticsCapture,
+}
+
+/// Starts keeping the last `log_lines` lines the worker logs, and makes the
+/// interrupts that terminate the isolate capture its diagnostics.
+pub(crate) fn install(js_runtime: &mut JsRuntime, log_lines: usize) -> DiagnosticsCapture {
+    let op_state = js_runtime.op_state();
+    let capture = DiagnosticsCapture::default();
+
+    op_state.borrow_mut().put(WorkerLogTail::new(log_lines));
+
+    js_runtime.v8_isolate().set_slot(DiagnosticsProbe {
+        op_state: Rc::downgrade(&op_state),
+        capture: capture.clone(),
+    });
+
+    capture
+}
+
+/// Captures the diagnostics of the isolate if they were asked for. Must be
+/// called from an interrupt.
+pub(crate) fn capture(isolate: &mut v8::Isolate) {
+    let Some((op_state, capture)) = isolate
+        .get_slot::<DiagnosticsProbe>()
+        .map(|it| (it.op_state.clone(), it.capture.clone()))
+    else {
+        return;
+    };
+
+    let mut heap_stats = v8::HeapStatistics::default();
+
+    isolate.get_heap_statistics(&mut heap_stats);
+
+    let stack = if isolate.in_context() {
+        let scope = &mut v8::HandleScope::new(isolate);
+        let context = scope.get_current_context();
+        let scope = &mut v8::ContextScope::new(scope, context);
+
+        current_stack(scope)
+    } else {
+        vec![]
+    };
+
+    let mut logs = vec![];
+    let mut resources = BTreeMap::new();
+
+    // NOTE: The interrupt may run while an op holds the op state, in which
+    // case the bundle goes without what is kept there.
+    if let Some(op_state) = op_state.upgrade() {
+        if let Ok(op_state) = op_state.try_borrow() {
+            if let Some(tail) = op_state.try_borrow::<WorkerLogTail>() {
+                logs = tail.lines();
+            }
+
+            resources = count_open_resources(&op_state.resource_table);
+        }
+    }
+
+    *capture.0.lock().unwrap() = Some(Diagnostics {
+        heap: HeapDiagnostics {
+            used_heap_size: heap_stats.used_heap_size(),
+            total_heap_size: heap_stats.total_heap_size(),
+            heap_size_limit: heap_stats.heap_size_limit(),
+            external_memory: heap_stats.external_memory(),
+            malloced_memory: heap_stats.malloced_memory(),
+            number_of_native_contexts: heap_stats.number_of_native_contexts(),
+            number_of_detached_contexts: heap_stats.number_of_detached_contexts(),
+        },
+        stack,
+        logs,
+        resources,
+    });
+}
+
+fn current_stack(scope: &mut v8::HandleScope) -> Vec<String> {
+    let Some(stack) = v8::StackTrace::current_stack_trace(scope, STACK_FRAME_LIMIT) else {
+        return vec![];
+    };
+
+    let mut frames = vec![];
+
+    for index in 0..stack.get_frame_count() {
+        let Some(frame) = stack.get_frame(scope, index) else {
+            continue;
+        };
+
+        let function_name = frame
+            .get_function_name(scope)
+            .map(|it| it.to_rust_string_lossy(scope))
+            .filter(|it| !it.is_empty());
+
+        let script_name = frame
+            .get_script_name(scope)
+            .map(|it| it.to_rust_string_lossy(scope))
+            .unwrap_or_else(|| "<anonymous>".to_string());
+
+        frames.push(format_frame(
+            function_name.as_deref(),
+            &script_name,
+            frame.get_line_number(),
+            frame.get_column(),
+        ));
+    }
+
+    frames
+}
+
+fn format_frame(function_name: Option<&str>, script_name: &str, line: usize, col: usize) -> String {
+    match function_name {
+        Some(name) => format!("at {} ({}:{}:{})", name, script_name, line, col),
+        None => format!("at {}:{}:{}", script_name, line, col),
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Bundle<'a> {
+    key: Uuid,
+    service_path: Option<&'a str>,
+    reason: &'a ShutdownReason,
+    captured_at_ms: u128,
+    #[serde(flatten)]
+    diagnostics: Diagnostics,
+}
+
+/// Writes the bundle of the worker into `dir` as JSON, and returns its path.
+pub(crate) async fn write(
+    dir: &Path,
+    key: Uuid,
+    service_path: Option<&str>,
+    reason: &ShutdownReason,
+    diagnostics: Diagnostics,
+) -> Result<PathBuf, Error> {
+    let captured_at_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+
+    let path = dir.join(format!("{}-{:?}-{}.json", key, reason, captured_at_ms));
+    let bundle = serde_json::to_vec_pretty(&Bundle {
+        key,
+        service_path,
+        reason,
+        captured_at_ms,
+        diagnostics,
+    })?;
+
+    tokio::fs::create_dir_all(dir).await?;
+    tokio::fs::write(&path, bundle).await?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_frame() {
+        assert_eq!(
+            format_frame(Some("handler"), "file:///src/index.ts", 12, 5),
+            "at handler (file:///src/index.ts:12:5)"
+        );
+        assert_eq!(
+            format_frame(None, "file:///src/index.ts", 1, 1),
+            "at file:///src/index.ts:1:1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_bundle() {
+        let dir = std::env::temp_dir().join(format!("sb-diagnostics-{}", Uuid::new_v4()));
+        let key = Uuid::new_v4();
+        let path = write(
+            &dir,
+            key,
+            Some("./examples/hello"),
+            &ShutdownReason::CPUTime,
+            Diagnostics {
+                heap: HeapDiagnostics {
+                    used_heap_size: 1,
+                    total_heap_size: 2,
+                    heap_size_limit: 3,
+                    external_memory: 0,
+                    malloced_memory: 0,
+                    number_of_native_contexts: 1,
+                    number_of_detached_contexts: 0,
+                },
+                stack: vec!["at loop (file:///src/index.ts:3:7)".to_string()],
+                logs: vec!["[Info] spinning".to_string()],
+                resources: BTreeMap::from([("fetchRequest".to_string(), 2)]),
+            },
+        )
+        .await
+        .unwrap();
+
+        let bundle: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(path.starts_with(&dir));
+        assert_eq!(bundle["key"], key.to_string());
+        assert_eq!(bundle["reason"], "CPUTime");
+        assert_eq!(bundle["heap"]["heapSizeLimit"], 3);
+        assert_eq!(bundle["resources"]["fetchRequest"], 2);
+        assert_eq!(bundle["logs"][0], "[Info] spinning");
+    }
+}
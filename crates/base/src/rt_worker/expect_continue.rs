@@ -0,0 +1,142 @@
+use std::future::pending;
+use std::pin::Pin;
+use std::task::Poll;
+
+use futures_util::{stream, StreamExt};
+use http::header::EXPECT;
+use http::HeaderMap;
+use hyper::Body;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::oneshot;
+
+// NOTE: `HTTP/1.x 100`
+const STATUS_LINE_PREFIX_LEN: usize = 12;
+
+pub(crate) fn expects_continue(headers: &HeaderMap) -> bool {
+    headers
+        .get(EXPECT)
+        .and_then(|it| it.to_str().ok())
+        .is_some_and(|it| it.trim().eq_ignore_ascii_case("100-continue"))
+}
+
+/// Holds `body` back until `continue_rx` fires. If the worker answers
+/// without asking for the body, it's never polled, so the listener doesn't
+/// send `100 Continue` to the client either.
+pub(crate) fn gate(body: Body, continue_rx: oneshot::Receiver<()>) -> Body {
+    let mut maybe_body = Some(body);
+
+    Body::wrap_stream(
+        stream::once(async move {
+            if continue_rx.await.is_err() {
+                // NOTE: The worker has sent its final response instead, and
+                // it closes the connection without reading the body.
+                pending::<()>().await;
+            }
+        })
+        .flat_map(move |_| maybe_body.take().unwrap_or_default()),
+    )
+}
+
+/// Watches the status line of the first response read from the worker
+/// connection, and tells whether it's `100 Continue`.
+pub(crate) struct ContinueSniffer<S> {
+    inner: S,
+    head: Vec<u8>,
+    maybe_continue_tx: Option<oneshot::Sender<()>>,
+}
+
+impl<S> ContinueSniffer<S> {
+    pub(crate) fn new(inner: S, maybe_continue_tx: Option<oneshot::Sender<()>>) -> Self {
+        Self {
+            inner,
+            head: Vec::new(),
+            maybe_continue_tx,
+        }
+    }
+
+    fn sniff(&mut self, read: &[u8]) {
+        if self.maybe_continue_tx.is_none() {
+            return;
+        }
+
+        let len = std::cmp::min(STATUS_LINE_PREFIX_LEN - self.head.len(), read.len());
+
+        self.head.extend_from_slice(&read[..len]);
+
+        if self.head.len() < STATUS_LINE_PREFIX_LEN && !read.is_empty() {
+            return;
+        }
+
+        let tx = self.maybe_continue_tx.take().unwrap();
+        let head = &self.head;
+
+        if head.len() == STATUS_LINE_PREFIX_LEN
+            && head.starts_with(b"HTTP/1.")
+            && head[8] == b' '
+            && &head[9..] == b"100"
+        {
+            let _ = tx.send(());
+        }
+    }
+}
+
+impl<S> AsyncRead for ContinueSniffer<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+
+        match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                self.sniff(&buf.filled()[filled..]);
+                Poll::Ready(Ok(()))
+            }
+
+            v => v,
+        }
+    }
+}
+
+impl<S> AsyncWrite for ContinueSniffer<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
@@ -0,0 +1,430 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll};
+
+use anyhow::{anyhow, Context, Error};
+use fastwebsockets::{FragmentCollector, Frame, OpCode, Role, WebSocket};
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use http::{Method, StatusCode, Uri};
+use http_utils::utils::emit_status_code;
+use hyper::upgrade::{OnUpgrade, Upgraded};
+use hyper::{Body, Request, Response};
+use log::{debug, error};
+use once_cell::sync::Lazy;
+use sb_core::channel::{self, ChannelKind};
+use sb_core::hibernation::{
+    self, OutgoingMessage, ATTACHMENT_HEADER, CLOSE_CODE_HEADER, EVENT_HEADER, HIBERNATE_HEADER,
+    ID_HEADER, MAX_ATTACHMENT_BYTES,
+};
+use sb_core::signing::SIGNATURE_HEADER;
+use sb_workers::context::WorkerRequestMsg;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf, ReadHalf, WriteHalf};
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+use crate::router::{Route, Router};
+
+use super::main_worker::MainWorker;
+
+/// Close code reported when the connection went away without a close frame.
+const ABNORMAL_CLOSURE: u16 = 1006;
+
+/// Close code reported when the close frame had no code in it.
+const NO_STATUS_RECEIVED: u16 = 1005;
+
+static MAIN_WORKER: Lazy<Mutex<Option<MainWorker>>> = Lazy::new(Mutex::default);
+
+/// Sets the main worker that events of held connections are sent through,
+/// unless they were routed around it. That is the one of the main listener,
+/// even for connections that came in on another listener.
+pub(crate) fn set_main_worker(main_worker: MainWorker) {
+    *MAIN_WORKER.lock().unwrap() = Some(main_worker);
+}
+
+/// How the events of a connection get back to its service, which is the way
+/// its upgrade request came in.
+#[derive(Clone)]
+pub(crate) enum Reentry {
+    MainWorker,
+    Route {
+        router: Arc<Router>,
+        route: Arc<Route>,
+        /// The uri before the route stripped its prefix.
+        uri: Uri,
+    },
+}
+
+impl Reentry {
+    async fn send(&self, mut req: Request<Body>) -> Result<Response<Body>, Error> {
+        match self {
+            Self::Route { router, route, uri } => {
+                *req.uri_mut() = uri.clone();
+
+                Ok(router.dispatch(route.clone(), req, None).await)
+            }
+
+            Self::MainWorker => {
+                let (tx, _in_flight) = MAIN_WORKER
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .context("main worker is not available")?
+                    .sender();
+
+                let (res_tx, res_rx) = oneshot::channel();
+
+                tx.send(WorkerRequestMsg {
+                    req,
+                    res_tx,
+                    conn_token: None,
+                })
+                .map_err(|_| anyhow!("main worker is not accepting requests"))?;
+
+                Ok(res_rx.await??)
+            }
+        }
+    }
+}
+
+/// What is kept of an upgrade request, to complete the handshake if the
+/// worker asks for it and to send the events of the connection later on.
+pub(crate) struct Handshake {
+    uri: Uri,
+    headers: HeaderMap,
+    reentry: Reentry,
+}
+
+impl Handshake {
+    pub(crate) fn of(req: &mut Request<Body>) -> Self {
+        Self {
+            uri: req.uri().clone(),
+            headers: req.headers().clone(),
+            reentry: req
+                .extensions_mut()
+                .remove::<Reentry>()
+                .unwrap_or(Reentry::MainWorker),
+        }
+    }
+
+    pub(crate) fn is_requested(res: &Response<Body>) -> bool {
+        res.headers().contains_key(HIBERNATE_HEADER)
+    }
+
+    /// Completes the handshake with the client on behalf of the worker and
+    /// holds the connection from then on.
+    pub(crate) fn accept(self, upgrade: OnUpgrade, res: &Response<Body>) -> Response<Body> {
+        let attachment = res.headers().get(ATTACHMENT_HEADER).cloned();
+
+        if matches!(&attachment, Some(it) if it.len() > MAX_ATTACHMENT_BYTES) {
+            error!(
+                "websocket attachment is larger than {} bytes",
+                MAX_ATTACHMENT_BYTES
+            );
+
+            return emit_status_code(StatusCode::BAD_GATEWAY, None, true);
+        }
+
+        let mut req = Request::new(Body::empty());
+
+        *req.headers_mut() = self.headers.clone();
+        req.extensions_mut().insert(upgrade);
+
+        let (mut upgraded_res, fut) = match fastwebsockets::upgrade::upgrade(&mut req) {
+            Ok(it) => it,
+            Err(err) => {
+                debug!("invalid websocket upgrade request: {}", err);
+                return emit_status_code(StatusCode::BAD_REQUEST, None, true);
+            }
+        };
+
+        if let Some(protocol) = res.headers().get(header::SEC_WEBSOCKET_PROTOCOL) {
+            upgraded_res
+                .headers_mut()
+                .insert(header::SEC_WEBSOCKET_PROTOCOL, protocol.clone());
+        }
+
+        let conn = Connection {
+            id: Uuid::new_v4().to_string(),
+            attachment,
+            handshake: self,
+        };
+
+        let (tx, rx) = channel::channel(ChannelKind::HibernatedWebSocket);
+
+        hibernation::register(conn.id.clone(), tx);
+        tokio::spawn(async move {
+            match fut.await {
+                Ok(ws) => conn.run(ws.into_inner(), rx).await,
+                Err(err) => debug!("websocket upgrade failed: {}", err),
+            }
+
+            hibernation::unregister(&conn.id);
+        });
+
+        upgraded_res
+    }
+}
+
+struct Connection {
+    id: String,
+    attachment: Option<HeaderValue>,
+    handshake: Handshake,
+}
+
+/// A frame read from the client.
+enum Incoming {
+    Text(Vec<u8>),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Close(u16, Vec<u8>),
+}
+
+impl Connection {
+    /// Relays messages between the client and the service until either side
+    /// closes the connection. Messages are sent to the service one at a
+    /// time, in the order they came in.
+    async fn run(&self, upgraded: Upgraded, mut rx: mpsc::Receiver<OutgoingMessage>) {
+        // NOTE: Reading a frame is not cancel safe, so frames are read on a
+        // task of their own instead of being raced against the messages the
+        // worker writes.
+        let (read_half, write_half) = tokio::io::split(upgraded);
+        let mut writer = WebSocket::after_handshake(WriteOnly(write_half), Role::Server);
+        let (incoming_tx, mut incoming_rx) = mpsc::channel(1);
+        let reader = tokio::spawn(read_frames(read_half, incoming_tx));
+
+        let mut inflight: Option<Pin<Box<dyn Future<Output = ()> + Send + '_>>> = None;
+
+        loop {
+            tokio::select! {
+                Some(msg) = rx.recv() => {
+                    let (frame, is_close) = match msg {
+                        OutgoingMessage::Text(it) => (Frame::text(it.into_bytes().into()), false),
+                        OutgoingMessage::Binary(it) => (Frame::binary(it.to_vec().into()), false),
+                        OutgoingMessage::Close(code, reason) => {
+                            (Frame::close(code, reason.as_bytes()), true)
+                        }
+                    };
+
+                    if writer.write_frame(frame).await.is_err() || is_close {
+                        break;
+                    }
+                }
+
+                _ = async { inflight.as_mut().unwrap().await }, if inflight.is_some() => {
+                    inflight = None;
+                }
+
+                incoming = incoming_rx.recv(), if inflight.is_none() => {
+                    let incoming = match incoming {
+                        Some(Ok(it)) => it,
+                        Some(Err(err)) => {
+                            debug!("websocket connection {} failed: {}", self.id, err);
+                            self.dispatch("close", vec![], Some(ABNORMAL_CLOSURE)).await;
+                            break;
+                        }
+
+                        None => break,
+                    };
+
+                    match incoming {
+                        Incoming::Text(it) => {
+                            inflight = Some(Box::pin(self.dispatch("text", it, None)));
+                        }
+
+                        Incoming::Binary(it) => {
+                            inflight = Some(Box::pin(self.dispatch("binary", it, None)));
+                        }
+
+                        Incoming::Ping(it) => {
+                            if writer.write_frame(Frame::pong(it.into())).await.is_err() {
+                                break;
+                            }
+                        }
+
+                        Incoming::Close(code, reason) => {
+                            let reply = if code == NO_STATUS_RECEIVED {
+                                Frame::close_raw(vec![].into())
+                            } else {
+                                Frame::close(code, &[])
+                            };
+
+                            let _ = writer.write_frame(reply).await;
+
+                            self.dispatch("close", reason, Some(code)).await;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        reader.abort();
+    }
+
+    /// Sends an event of the connection to its service as a request of its
+    /// own, carrying the headers of the upgrade request.
+    async fn dispatch(&self, event: &'static str, body: Vec<u8>, close_code: Option<u16>) {
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri(self.handshake.uri.clone())
+            .body(Body::from(body))
+            .unwrap();
+
+        let headers = req.headers_mut();
+
+        for (name, value) in self.handshake.headers.iter() {
+            if !is_handshake_only(name) {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+
+        headers.insert(ID_HEADER, HeaderValue::from_str(&self.id).unwrap());
+        headers.insert(EVENT_HEADER, HeaderValue::from_static(event));
+
+        if let Some(attachment) = self.attachment.clone() {
+            headers.insert(ATTACHMENT_HEADER, attachment);
+        }
+
+        if let Some(code) = close_code {
+            headers.insert(CLOSE_CODE_HEADER, HeaderValue::from(code));
+        }
+
+        match self.handshake.reentry.send(req).await {
+            Ok(res) if res.status().is_success() => {
+                let _ = hyper::body::to_bytes(res.into_body()).await;
+            }
+
+            Ok(res) => {
+                debug!(
+                    "websocket {} event of connection {} was not handled: {}",
+                    event,
+                    self.id,
+                    res.status()
+                );
+            }
+
+            Err(err) => {
+                error!(
+                    "failed to send websocket {} event of connection {}: {:#}",
+                    event, self.id, err
+                );
+            }
+        }
+    }
+}
+
+fn is_handshake_only(name: &HeaderName) -> bool {
+    name == header::CONNECTION
+        || name == header::UPGRADE
+        || name == header::CONTENT_LENGTH
+        || name == header::TRANSFER_ENCODING
+        || name == SIGNATURE_HEADER
+        || name.as_str().starts_with("sec-websocket-")
+}
+
+/// Reads frames from the client until the connection closes or fails. Pings
+/// and close frames are answered by the writer, since the reader can't write.
+async fn read_frames(
+    read_half: ReadHalf<Upgraded>,
+    tx: mpsc::Sender<Result<Incoming, fastwebsockets::WebSocketError>>,
+) {
+    let mut ws = WebSocket::after_handshake(ReadOnly(read_half), Role::Server);
+
+    ws.set_auto_close(false);
+    ws.set_auto_pong(false);
+
+    let mut ws = FragmentCollector::new(ws);
+
+    loop {
+        let incoming = ws.read_frame().await.map(|frame| match frame.opcode {
+            OpCode::Text => Some(Incoming::Text(frame.payload.to_vec())),
+            OpCode::Binary => Some(Incoming::Binary(frame.payload.to_vec())),
+            OpCode::Ping => Some(Incoming::Ping(frame.payload.to_vec())),
+            OpCode::Close => Some(match &frame.payload[..] {
+                [hi, lo, reason @ ..] => {
+                    Incoming::Close(u16::from_be_bytes([*hi, *lo]), reason.to_vec())
+                }
+
+                _ => Incoming::Close(NO_STATUS_RECEIVED, vec![]),
+            }),
+
+            _ => None,
+        });
+
+        let is_last = matches!(incoming, Err(_) | Ok(Some(Incoming::Close(..))));
+
+        if let Some(incoming) = incoming.transpose() {
+            if tx.send(incoming).await.is_err() {
+                return;
+            }
+        }
+
+        if is_last {
+            return;
+        }
+    }
+}
+
+/// The read half of a connection, which fails to write.
+struct ReadOnly(ReadHalf<Upgraded>);
+
+impl AsyncRead for ReadOnly {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ReadOnly {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+        _buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(io::ErrorKind::Unsupported.into()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// The write half of a connection, which reads nothing.
+struct WriteOnly(WriteHalf<Upgraded>);
+
+impl AsyncRead for WriteOnly {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut TaskContext<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for WriteOnly {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
@@ -1,28 +1,41 @@
+use crate::bundle_signature::BundleSignatureError;
+use crate::checksum_manifest::ChecksumManifestError;
 use crate::deno_runtime::DenoRuntime;
 use crate::rt_worker::supervisor::CPUUsageMetrics;
 use crate::rt_worker::worker::{DuplexStreamEntry, HandleCreationType, Worker, WorkerHandler};
 use anyhow::Error;
 use event_worker::events::{
-    BootFailureEvent, EventLoopCompletedEvent, UncaughtExceptionEvent, WorkerEvents,
+    BootFailureEvent, BootFailureReason, EventLoopCompletedEvent, UncaughtExceptionEvent,
+    WorkerEvents,
 };
 use log::error;
 use std::any::Any;
 use std::time::Duration;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::mpsc::{self, UnboundedSender};
 use tokio::sync::oneshot::Receiver;
 
 impl WorkerHandler for Worker {
     fn handle_error(&self, error: Error) -> Result<WorkerEvents, Error> {
         log::error!("{}", error);
+
+        let reason = if error.is::<BundleSignatureError>() {
+            BootFailureReason::SignatureMismatch
+        } else if error.is::<ChecksumManifestError>() {
+            BootFailureReason::ChecksumMismatch
+        } else {
+            BootFailureReason::Error
+        };
+
         Ok(WorkerEvents::BootFailure(BootFailureEvent {
             msg: error.to_string(),
+            reason,
         }))
     }
 
     fn handle_creation<'r>(
         &self,
         created_rt: &'r mut DenoRuntime,
-        duplex_stream_rx: UnboundedReceiver<DuplexStreamEntry>,
+        duplex_stream_rx: mpsc::Receiver<DuplexStreamEntry>,
         termination_event_rx: Receiver<WorkerEvents>,
         maybe_cpu_usage_metrics_tx: Option<UnboundedSender<CPUUsageMetrics>>,
         name: Option<String>,
@@ -37,10 +50,16 @@ impl WorkerHandler for Worker {
                     let err_string = err.to_string();
 
                     if err_string.ends_with("execution terminated") {
-                        Ok(termination_event_rx
-                            .await
-                            .unwrap()
-                            .with_cpu_time_used(cpu_usage_ms as usize))
+                        match termination_event_rx.await {
+                            Ok(ev) => Ok(ev.with_cpu_time_used(cpu_usage_ms as usize)),
+
+                            // NOTE: The supervisor has gone away without
+                            // reporting a reason (e.g. it panicked).
+                            Err(_) => Ok(WorkerEvents::UncaughtException(UncaughtExceptionEvent {
+                                exception: "supervisor exited unexpectedly".to_string(),
+                                cpu_time_used: cpu_usage_ms as usize,
+                            })),
+                        }
                     } else {
                         error!(
                             "runtime has escaped from the event loop unexpectedly: {}",
@@ -0,0 +1,241 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use deno_core::serde_json::{self, Value};
+use http::header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use http::{HeaderValue, Request, Response, StatusCode};
+use hyper::Body;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+use log::error;
+use tokio::time::Instant;
+
+pub use jsonwebtoken::Algorithm;
+
+/// Carries the payload of a verified token to the user worker. Its value is
+/// the payload segment of the token as is, i.e. base64url-encoded JSON.
+pub const JWT_CLAIMS_HEADER: &str = "x-sb-jwt-claims";
+
+// NOTE: A token signed with a key we don't know yet makes us fetch the key set
+// again, but not more often than this so that junk tokens can't be used to
+// hammer the JWKS endpoint.
+const JWKS_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+// NOTE: Requests wait for the key set when it has to be fetched, so a JWKS
+// endpoint that hangs must not hold them up longer than this.
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct JwtConfig {
+    pub jwks_url: String,
+    /// Algorithms tokens may be signed with. Tokens whose header names any
+    /// other are rejected, whatever keys the key set has.
+    pub algorithms: Vec<Algorithm>,
+    /// Expected `iss` claim. Not checked if `None`.
+    pub issuer: Option<String>,
+    /// Expected `aud` claim for the workers that don't specify their own. Not
+    /// checked if `None`.
+    pub audience: Option<String>,
+    /// How long the fetched key set is trusted before being fetched again.
+    pub jwks_ttl: Duration,
+}
+
+struct CachedJwks {
+    fetched_at: Instant,
+    keys: JwkSet,
+}
+
+/// Verifies the bearer token of the requests before they are dispatched to
+/// user workers, so that the isolates never spend CPU time on requests that
+/// would be rejected anyway.
+pub struct JwtVerifier {
+    config: JwtConfig,
+    client: reqwest::Client,
+    jwks: Mutex<Option<Arc<CachedJwks>>>,
+    /// Held while the key set is fetched, so that requests arriving in the
+    /// meantime wait for that fetch instead of starting their own.
+    refresh: tokio::sync::Mutex<()>,
+}
+
+impl JwtVerifier {
+    pub fn new(config: JwtConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::builder()
+                .timeout(JWKS_FETCH_TIMEOUT)
+                .build()
+                .unwrap(),
+            jwks: Mutex::default(),
+            refresh: tokio::sync::Mutex::default(),
+        }
+    }
+
+    /// Checks the token of `req` and passes its claims along in
+    /// [`JWT_CLAIMS_HEADER`]. If the token is missing or invalid, the
+    /// response to send back instead is returned.
+    pub(crate) async fn authorize(
+        &self,
+        req: &mut Request<Body>,
+        maybe_audience: Option<&str>,
+    ) -> Result<(), Response<Body>> {
+        // Whatever the client sent here must not be mistaken for verified
+        // claims.
+        req.headers_mut().remove(JWT_CLAIMS_HEADER);
+
+        let Some(token) = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|it| it.to_str().ok())
+            .and_then(|it| it.strip_prefix("Bearer "))
+            .map(str::trim)
+        else {
+            return Err(unauthorized("missing authorization header"));
+        };
+
+        let header = decode_header(token).map_err(|_| unauthorized("invalid token"))?;
+
+        if !self.config.algorithms.contains(&header.alg) {
+            return Err(unauthorized("token signed with a disallowed algorithm"));
+        }
+        let key = match self.decoding_key(header.kid.as_deref(), header.alg).await {
+            Ok(Some(key)) => key,
+            Ok(None) => return Err(unauthorized("token signed with an unknown key")),
+            Err(err) => {
+                error!("failed to fetch the json web key set: {}", err);
+                return Err(error_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "can't verify the token at the moment",
+                ));
+            }
+        };
+
+        let mut validation = Validation::new(header.alg);
+
+        if let Some(issuer) = self.config.issuer.as_deref() {
+            validation.set_issuer(&[issuer]);
+        }
+
+        match maybe_audience.or(self.config.audience.as_deref()) {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        decode::<Value>(token, &key, &validation).map_err(|_| unauthorized("invalid token"))?;
+
+        // NOTE: At this point the token is known to have three segments.
+        let payload = token.split('.').nth(1).unwrap_or_default();
+
+        if let Ok(value) = HeaderValue::from_str(payload) {
+            req.headers_mut().insert(JWT_CLAIMS_HEADER, value);
+        }
+
+        Ok(())
+    }
+
+    async fn decoding_key(
+        &self,
+        maybe_kid: Option<&str>,
+        alg: Algorithm,
+    ) -> Result<Option<DecodingKey>, Error> {
+        let is_stale = |it: &CachedJwks, dur: Duration| it.fetched_at.elapsed() >= dur;
+        let maybe_cached = self.jwks.lock().unwrap().clone();
+        let jwks = match maybe_cached {
+            Some(it) if !is_stale(&it, self.config.jwks_ttl) => it,
+            _ => self.refresh_jwks(None).await?,
+        };
+
+        let find = |keys: &JwkSet| {
+            keys.keys
+                .iter()
+                // NOTE: `KeyAlgorithm` and `Algorithm` are distinct enums that share
+                // their variant names.
+                .filter(|it| {
+                    it.common
+                        .key_algorithm
+                        .map_or(true, |k| format!("{:?}", k) == format!("{:?}", alg))
+                })
+                .find(|it| match maybe_kid {
+                    Some(kid) => it.common.key_id.as_deref() == Some(kid),
+                    None => true,
+                })
+                .map(DecodingKey::from_jwk)
+                .transpose()
+        };
+
+        if let Some(key) = find(&jwks.keys)? {
+            return Ok(Some(key));
+        }
+
+        if is_stale(&jwks, JWKS_MIN_REFRESH_INTERVAL) {
+            let jwks = self.refresh_jwks(Some(jwks.fetched_at)).await?;
+            return Ok(find(&jwks.keys)?);
+        }
+
+        Ok(None)
+    }
+
+    /// Fetches the key set, unless it was fetched since `seen_at` while
+    /// waiting for another request doing so. The cached key set is only
+    /// locked to swap it, never across the fetch.
+    async fn refresh_jwks(&self, seen_at: Option<Instant>) -> Result<Arc<CachedJwks>, Error> {
+        let _refresh = self.refresh.lock().await;
+
+        let maybe_cached = self.jwks.lock().unwrap().clone();
+
+        if let Some(jwks) = maybe_cached {
+            let is_fresh = match seen_at {
+                Some(seen_at) => jwks.fetched_at > seen_at,
+                None => jwks.fetched_at.elapsed() < self.config.jwks_ttl,
+            };
+
+            if is_fresh {
+                return Ok(jwks);
+            }
+        }
+
+        let jwks = Arc::new(self.fetch_jwks().await?);
+
+        *self.jwks.lock().unwrap() = Some(jwks.clone());
+
+        Ok(jwks)
+    }
+
+    async fn fetch_jwks(&self) -> Result<CachedJwks, Error> {
+        let res = self
+            .client
+            .get(&self.config.jwks_url)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let keys = res
+            .json::<JwkSet>()
+            .await
+            .map_err(|err| anyhow!("malformed json web key set: {}", err))?;
+
+        Ok(CachedJwks {
+            fetched_at: Instant::now(),
+            keys,
+        })
+    }
+}
+
+fn unauthorized(msg: &str) -> Response<Body> {
+    let mut res = error_response(StatusCode::UNAUTHORIZED, msg);
+
+    res.headers_mut().insert(
+        WWW_AUTHENTICATE,
+        HeaderValue::from_static("Bearer error=\"invalid_token\""),
+    );
+
+    res
+}
+
+fn error_response(status: StatusCode, msg: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::json!({ "msg": msg }).to_string()))
+        .unwrap()
+}
@@ -0,0 +1,223 @@
+//! The main worker behind the listener, which can be swapped for a new one
+//! booted from the same service without dropping the listener.
+//!
+//! Each request is handed to whichever main worker is current when it comes
+//! in. A new main worker only takes over once it has booted, and the one it
+//! replaces is terminated once the requests it was already serving are done.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{bail, Error};
+use deno_config::JsxImportSourceConfig;
+use log::{info, warn};
+use sb_core::channel::BoundedSender;
+use sb_graph::DecoratorType;
+use sb_workers::context::{MainWorkerRuntimeOpts, WorkerRequestMsg};
+use tokio::sync::{Mutex, Notify};
+
+use crate::inspector_server::Inspector;
+
+use super::worker_ctx::{create_main_worker, TerminationToken, WorkerConnectionConfig};
+
+/// What every main worker is booted from.
+#[derive(Clone)]
+pub(crate) struct MainWorkerOpts {
+    pub(crate) service_path: PathBuf,
+    pub(crate) import_map_path: Option<String>,
+    pub(crate) no_module_cache: bool,
+    pub(crate) runtime_opts: MainWorkerRuntimeOpts,
+    pub(crate) maybe_entrypoint: Option<String>,
+    pub(crate) maybe_decorator: Option<DecoratorType>,
+    pub(crate) jsx: Option<JsxImportSourceConfig>,
+    pub(crate) conn_config: WorkerConnectionConfig,
+    /// How long a replaced main worker is given to finish the requests it
+    /// was serving. Zero terminates it right away.
+    pub(crate) retire_deadline: Duration,
+}
+
+struct Generation {
+    req_tx: BoundedSender<WorkerRequestMsg>,
+    token: TerminationToken,
+    in_flight: Arc<InFlight>,
+}
+
+#[derive(Clone)]
+pub(crate) struct MainWorker(Arc<MainWorkerInner>);
+
+struct MainWorkerInner {
+    opts: MainWorkerOpts,
+    current: RwLock<Arc<Generation>>,
+    /// Held while a main worker boots or the current one terminates, so that
+    /// neither happens halfway through the other.
+    swapping: Mutex<()>,
+}
+
+impl MainWorker {
+    /// Boots the first main worker. Only it gets the inspector, since the
+    /// ones booted later can't bind its socket again.
+    pub(crate) async fn boot(
+        opts: MainWorkerOpts,
+        inspector: Option<Inspector>,
+    ) -> Result<Self, Error> {
+        let generation = Self::boot_generation(&opts, inspector).await?;
+
+        Ok(Self(Arc::new(MainWorkerInner {
+            opts,
+            current: RwLock::new(Arc::new(generation)),
+            swapping: Mutex::default(),
+        })))
+    }
+
+    async fn boot_generation(
+        opts: &MainWorkerOpts,
+        inspector: Option<Inspector>,
+    ) -> Result<Generation, Error> {
+        let token = TerminationToken::new();
+        let req_tx = create_main_worker(
+            opts.service_path.clone(),
+            opts.import_map_path.clone(),
+            opts.no_module_cache,
+            opts.runtime_opts.clone(),
+            opts.maybe_entrypoint.clone(),
+            opts.maybe_decorator,
+            Some(token.clone()),
+            inspector,
+            opts.jsx.clone(),
+            opts.conn_config,
+        )
+        .await?;
+
+        Ok(Generation {
+            req_tx,
+            token,
+            in_flight: Arc::default(),
+        })
+    }
+
+    fn current(&self) -> Arc<Generation> {
+        self.0.current.read().unwrap().clone()
+    }
+
+    /// Returns the channel of the current main worker, and a guard that keeps
+    /// the request counted as in flight on it until it is dropped.
+    pub(crate) fn sender(&self) -> (BoundedSender<WorkerRequestMsg>, InFlightGuard) {
+        let current = self.current();
+
+        (current.req_tx.clone(), current.in_flight.enter())
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.current().req_tx.is_closed()
+    }
+
+    /// Boots a new main worker from the service and swaps it in. The current
+    /// one keeps serving if the new one fails to boot.
+    pub(crate) async fn reload(&self) -> Result<(), Error> {
+        let _swapping = self.0.swapping.lock().await;
+
+        if self.current().token.inbound.is_cancelled() {
+            bail!("main worker is terminating");
+        }
+
+        let generation = Arc::new(Self::boot_generation(&self.0.opts, None).await?);
+        let retired = std::mem::replace(&mut *self.0.current.write().unwrap(), generation);
+
+        info!("main worker has been reloaded");
+
+        drop(tokio::spawn(Self::retire(
+            retired,
+            self.0.opts.retire_deadline,
+        )));
+
+        Ok(())
+    }
+
+    async fn retire(generation: Arc<Generation>, deadline: Duration) {
+        if tokio::time::timeout(deadline, generation.in_flight.drained())
+            .await
+            .is_err()
+        {
+            warn!(
+                "retired main worker still had {} requests in flight after {:?}",
+                generation.in_flight.count.load(Ordering::Acquire),
+                deadline
+            );
+        }
+
+        generation.token.cancel_and_wait().await;
+    }
+
+    /// Terminates the current main worker, and waits until it has exited.
+    pub(crate) async fn terminate(&self) {
+        let _swapping = self.0.swapping.lock().await;
+
+        self.current().token.cancel_and_wait().await;
+    }
+}
+
+/// Requests a single main worker is serving.
+#[derive(Debug, Default)]
+struct InFlight {
+    count: AtomicUsize,
+    drained: Notify,
+}
+
+impl InFlight {
+    fn enter(self: &Arc<Self>) -> InFlightGuard {
+        self.count.fetch_add(1, Ordering::AcqRel);
+        InFlightGuard(self.clone())
+    }
+
+    async fn drained(&self) {
+        loop {
+            let notified = self.drained.notified();
+
+            if self.count.load(Ordering::Acquire) == 0 {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+}
+
+pub(crate) struct InFlightGuard(Arc<InFlight>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.drained.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_flight_drains_once_every_guard_is_dropped() {
+        let in_flight = Arc::<InFlight>::default();
+        let first = in_flight.enter();
+        let second = in_flight.enter();
+
+        drop(first);
+
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), in_flight.drained())
+                .await
+                .is_err()
+        );
+
+        drop(second);
+
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), in_flight.drained())
+                .await
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use anyhow::Error;
+use async_trait::async_trait;
+use http::{Request, Response};
+use hyper::Body;
+use uuid::Uuid;
+
+/// Describes the user worker a request is being dispatched to.
+#[derive(Debug, Clone)]
+pub struct MiddlewareContext {
+    pub worker_id: Uuid,
+    pub service_path: String,
+}
+
+/// A hook into the path between the main worker and user workers.
+///
+/// Requests go through the middlewares in the order they were registered,
+/// right before being sent to the user worker. Responses go back through them
+/// in the reverse order.
+#[async_trait]
+pub trait Middleware: Send + Sync + 'static {
+    /// Returning a response skips the rest of the chain, as well as the user
+    /// worker itself. Only the middlewares that already saw the request get
+    /// to see that response.
+    async fn on_request(
+        &self,
+        _ctx: &MiddlewareContext,
+        _req: &mut Request<Body>,
+    ) -> Result<Option<Response<Body>>, Error> {
+        Ok(None)
+    }
+
+    async fn on_response(
+        &self,
+        _ctx: &MiddlewareContext,
+        _res: &mut Response<Body>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MiddlewareChain(Arc<Vec<Arc<dyn Middleware>>>);
+
+impl MiddlewareChain {
+    pub fn push(&mut self, middleware: impl Middleware) {
+        Arc::make_mut(&mut self.0).push(Arc::new(middleware));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) async fn run<F, Fut>(
+        &self,
+        ctx: &MiddlewareContext,
+        mut req: Request<Body>,
+        send: F,
+    ) -> Result<Response<Body>, Error>
+    where
+        F: FnOnce(Request<Body>) -> Fut,
+        Fut: Future<Output = Result<Response<Body>, Error>>,
+    {
+        let mut visited = 0;
+        let mut maybe_res = None;
+
+        for middleware in self.0.iter() {
+            visited += 1;
+
+            if let Some(res) = middleware.on_request(ctx, &mut req).await? {
+                maybe_res = Some(res);
+                break;
+            }
+        }
+
+        let mut res = match maybe_res {
+            Some(res) => res,
+            None => send(req).await?,
+        };
+
+        for middleware in self.0[..visited].iter().rev() {
+            middleware.on_response(ctx, &mut res).await?;
+        }
+
+        Ok(res)
+    }
+}
@@ -1,7 +1,23 @@
+pub mod accounting;
+pub mod cluster;
+pub mod cors;
+pub mod diagnostics;
+pub mod expect_continue;
+pub mod hibernation;
 pub mod implementation;
+pub mod jwt;
+pub mod main_worker;
+pub mod middleware;
+pub mod pool_state;
+pub mod request_body;
+pub mod resources;
+pub mod response_body;
 pub mod rt;
+pub mod scheduler;
 pub mod supervisor;
+pub mod threads;
 pub mod utils;
+pub mod websocket_close;
 pub mod worker;
 pub mod worker_ctx;
 pub mod worker_pool;
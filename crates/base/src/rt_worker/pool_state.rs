@@ -0,0 +1,138 @@
+//! Keeps the services registered in the user worker pool on disk across
+//! restarts, so that the control plane doesn't have to create every one of
+//! them again.
+
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Error;
+use deno_core::serde_json;
+use log::error;
+use sb_core::cache::deno_dir::DenoDir;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// When the services read from the state file are booted again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreMode {
+    /// As soon as the pool starts.
+    Eager,
+    /// Once a request is sent to the key they had before the restart.
+    Lazy,
+}
+
+impl FromStr for RestoreMode {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "eager" => Ok(Self::Eager),
+            "lazy" => Ok(Self::Lazy),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// A service registered in the pool, under the key of its worker.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PersistedService {
+    pub key: Uuid,
+    pub service_path: String,
+    /// The options the worker was created with, as `op_user_worker_create`
+    /// takes them.
+    pub options: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolState {
+    pub services: Vec<PersistedService>,
+}
+
+impl PoolState {
+    pub fn path() -> PathBuf {
+        DenoDir::new(None)
+            .map(|it| it.pool_state_file_path())
+            .unwrap_or_else(|_| std::env::temp_dir().join("sb_pool_state.json"))
+    }
+
+    /// Reads the state file. A missing or unreadable file reads as empty.
+    pub fn load(path: &Path) -> Self {
+        let Ok(contents) = std::fs::read(path) else {
+            return Self::default();
+        };
+
+        serde_json::from_slice(&contents).unwrap_or_else(|err| {
+            error!("failed to read the pool state: {}", err);
+            Self::default()
+        })
+    }
+
+    pub fn store(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let tmp_path = path.with_extension(format!("{}.tmp", Uuid::new_v4()));
+
+        // NOTE: The options carry the environment variables of the workers,
+        // so the file is only readable by the owner.
+        write_private(&tmp_path, &serde_json::to_vec(self)?)?;
+
+        if let Err(err) = std::fs::rename(&tmp_path, path) {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(err.into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    std::fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pool_state_round_trip() {
+        let path = std::env::temp_dir().join(format!("sb_pool_state_{}.json", Uuid::new_v4()));
+        let key = Uuid::new_v4();
+
+        PoolState {
+            services: vec![PersistedService {
+                key,
+                service_path: "./test_cases/main".to_string(),
+                options: serde_json::json!({ "memoryLimitMb": 150 }),
+            }],
+        }
+        .store(&path)
+        .unwrap();
+
+        let state = PoolState::load(&path);
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(state.services.len(), 1);
+        assert_eq!(state.services[0].key, key);
+        assert_eq!(state.services[0].options["memoryLimitMb"], 150);
+        assert!(PoolState::load(&path).services.is_empty());
+    }
+}
@@ -0,0 +1,365 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use deno_core::serde_json;
+use futures_util::{stream, StreamExt};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING};
+use http::{HeaderValue, Request, Response, StatusCode};
+use hyper::body::HttpBody;
+use hyper::Body;
+use log::error;
+use sb_core::util::sync::AtomicFlag;
+use sb_workers::context::RequestBodyPolicy;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+
+const READ_BUF_SIZE: usize = 64 * 1024;
+
+/// Applies `policy` to the body of `req`. If the body breaks a limit while it
+/// is being streamed to the worker, the stream ends with an error and
+/// `exceeded` is raised; if that is known before dispatching, the response to
+/// send back instead is returned.
+pub(crate) async fn apply(
+    policy: &RequestBodyPolicy,
+    spilled_bytes: &Arc<AtomicU64>,
+    exceeded: Arc<AtomicFlag>,
+    mut req: Request<Body>,
+) -> Result<Request<Body>, Response<Body>> {
+    let maybe_content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|it| it.to_str().ok())
+        .and_then(|it| it.parse::<u64>().ok());
+
+    if policy.max_bytes > 0 && maybe_content_length.is_some_and(|it| it > policy.max_bytes) {
+        return Err(payload_too_large("request body is too large"));
+    }
+
+    let mut limiter = BodyLimiter::new(policy, &req);
+
+    if req.body().is_end_stream() || (limiter.is_none() && policy.spill_threshold_bytes == 0) {
+        return Ok(req);
+    }
+
+    if policy.spill_threshold_bytes == 0 {
+        let (parts, body) = req.into_parts();
+        let body = body.map(move |chunk| {
+            let chunk = chunk.map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+            if let Some(limiter) = limiter.as_mut() {
+                limiter.check(&chunk).map_err(|msg| {
+                    exceeded.raise();
+                    io::Error::new(io::ErrorKind::InvalidData, msg)
+                })?;
+            }
+
+            Ok::<_, io::Error>(chunk)
+        });
+
+        return Ok(Request::from_parts(parts, Body::wrap_stream(body)));
+    }
+
+    let body = std::mem::take(req.body_mut());
+    let (body, len) = spill(policy, spilled_bytes, body, limiter).await?;
+    let headers = req.headers_mut();
+
+    headers.remove(TRANSFER_ENCODING);
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+    *req.body_mut() = body;
+
+    Ok(req)
+}
+
+/// Reads `body` in full, keeping up to `spill_threshold_bytes` of it in
+/// memory and the rest in a temporary file.
+async fn spill(
+    policy: &RequestBodyPolicy,
+    spilled_bytes: &Arc<AtomicU64>,
+    mut body: Body,
+    mut limiter: Option<BodyLimiter>,
+) -> Result<(Body, u64), Response<Body>> {
+    let threshold = policy.spill_threshold_bytes as usize;
+    let mut mem = BytesMut::new();
+    let mut maybe_file = None::<(tokio::fs::File, SpillGuard)>;
+    let mut len = 0u64;
+
+    while let Some(chunk) = body.data().await {
+        let Ok(mut chunk) = chunk else {
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                "failed to read request body",
+            ));
+        };
+
+        if let Some(limiter) = limiter.as_mut() {
+            limiter.check(&chunk).map_err(payload_too_large)?;
+        }
+
+        len += chunk.len() as u64;
+
+        if mem.len() < threshold {
+            let n = std::cmp::min(threshold - mem.len(), chunk.len());
+
+            mem.extend_from_slice(&chunk.split_to(n));
+        }
+
+        if chunk.is_empty() {
+            continue;
+        }
+
+        if maybe_file.is_none() {
+            let guard = SpillGuard::new(spilled_bytes.clone());
+            let file = tokio::fs::File::options()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&guard.path)
+                .await
+                .map_err(spill_failed)?;
+
+            maybe_file = Some((file, guard));
+        }
+
+        let (file, guard) = maybe_file.as_mut().unwrap();
+
+        if !guard.reserve(chunk.len() as u64, policy.disk_quota_bytes) {
+            return Err(error_response(
+                StatusCode::INSUFFICIENT_STORAGE,
+                "request body exceeds the disk quota of the worker",
+            ));
+        }
+
+        file.write_all(&chunk).await.map_err(spill_failed)?;
+    }
+
+    let mem = mem.freeze();
+    let Some((mut file, guard)) = maybe_file else {
+        return Ok((Body::from(mem), len));
+    };
+
+    file.flush().await.map_err(spill_failed)?;
+    file.rewind().await.map_err(spill_failed)?;
+
+    let rest = stream::unfold((file, guard), |(mut file, guard)| async move {
+        let mut buf = BytesMut::zeroed(READ_BUF_SIZE);
+
+        match file.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(buf.freeze()), (file, guard)))
+            }
+
+            Err(err) => Some((Err(err), (file, guard))),
+        }
+    });
+
+    Ok((
+        Body::wrap_stream(stream::once(async move { Ok::<_, io::Error>(mem) }).chain(rest)),
+        len,
+    ))
+}
+
+/// Owns a temporary file holding part of a request body, along with its
+/// share of the disk quota of the worker.
+struct SpillGuard {
+    path: PathBuf,
+    reserved: u64,
+    spilled_bytes: Arc<AtomicU64>,
+}
+
+impl SpillGuard {
+    fn new(spilled_bytes: Arc<AtomicU64>) -> Self {
+        Self {
+            path: std::env::temp_dir().join(format!("sb-request-body-{}", Uuid::new_v4())),
+            reserved: 0,
+            spilled_bytes,
+        }
+    }
+
+    fn reserve(&mut self, len: u64, quota: u64) -> bool {
+        let prev = self.spilled_bytes.fetch_add(len, Ordering::AcqRel);
+
+        if quota > 0 && prev + len > quota {
+            self.spilled_bytes.fetch_sub(len, Ordering::AcqRel);
+            return false;
+        }
+
+        self.reserved += len;
+        true
+    }
+}
+
+impl Drop for SpillGuard {
+    fn drop(&mut self) {
+        self.spilled_bytes
+            .fetch_sub(self.reserved, Ordering::AcqRel);
+
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                error!("failed to remove spilled request body: {}", err);
+            }
+        }
+    }
+}
+
+/// Keeps track of the size of a body, and of the size of its parts if it's a
+/// `multipart/form-data` one.
+struct BodyLimiter {
+    max_bytes: u64,
+    max_part_bytes: u64,
+    len: u64,
+    maybe_parts: Option<PartScanner>,
+}
+
+impl BodyLimiter {
+    fn new(policy: &RequestBodyPolicy, req: &Request<Body>) -> Option<Self> {
+        let maybe_parts = (policy.max_part_bytes > 0)
+            .then(|| multipart_boundary(req))
+            .flatten()
+            .map(PartScanner::new);
+
+        if policy.max_bytes == 0 && maybe_parts.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            max_bytes: policy.max_bytes,
+            max_part_bytes: policy.max_part_bytes,
+            len: 0,
+            maybe_parts,
+        })
+    }
+
+    fn check(&mut self, chunk: &[u8]) -> Result<(), &'static str> {
+        self.len += chunk.len() as u64;
+
+        if self.max_bytes > 0 && self.len > self.max_bytes {
+            return Err("request body is too large");
+        }
+
+        if let Some(parts) = self.maybe_parts.as_mut() {
+            if parts.feed(chunk) > self.max_part_bytes {
+                return Err("multipart body has a part that is too large");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Finds the part delimiters of a multipart body as it streams by, and
+/// measures the parts in between.
+struct PartScanner {
+    delimiter: Vec<u8>,
+    // NOTE: The tail of the previous chunk, in case a delimiter straddles two
+    // chunks.
+    window: Vec<u8>,
+    // NOTE: Offsets count the `\r\n` put in front of the body, so that the
+    // first delimiter can be matched like all the others.
+    offset: u64,
+    part_start: u64,
+}
+
+impl PartScanner {
+    fn new(boundary: String) -> Self {
+        let delimiter = format!("\r\n--{}", boundary).into_bytes();
+
+        Self {
+            window: b"\r\n".to_vec(),
+            offset: 2,
+            part_start: 0,
+            delimiter,
+        }
+    }
+
+    /// Returns the size of the largest part seen in `chunk`.
+    fn feed(&mut self, chunk: &[u8]) -> u64 {
+        let mut buf = std::mem::take(&mut self.window);
+        let buf_start = self.offset - buf.len() as u64;
+        let mut largest = 0;
+
+        buf.extend_from_slice(chunk);
+        self.offset += chunk.len() as u64;
+
+        let delimiter_len = self.delimiter.len();
+        let mut idx = 0;
+
+        while idx + delimiter_len <= buf.len() {
+            if buf[idx..idx + delimiter_len] != self.delimiter[..] {
+                idx += 1;
+                continue;
+            }
+
+            let start = buf_start + idx as u64;
+
+            if start >= self.part_start {
+                largest = largest.max(start - self.part_start);
+                self.part_start = start + delimiter_len as u64;
+            }
+
+            idx += delimiter_len;
+        }
+
+        let keep = std::cmp::min(delimiter_len - 1, buf.len());
+
+        self.window = buf.split_off(buf.len() - keep);
+
+        // NOTE: The kept tail might turn out to be the start of a delimiter,
+        // so it's not counted yet.
+        let pending = (self.offset - keep as u64).saturating_sub(self.part_start);
+
+        largest.max(pending)
+    }
+}
+
+fn multipart_boundary(req: &Request<Body>) -> Option<String> {
+    let content_type = req.headers().get(CONTENT_TYPE)?.to_str().ok()?;
+    let mut params = content_type.split(';');
+
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+
+    params.find_map(|it| {
+        let (key, value) = it.split_once('=')?;
+
+        key.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+            .filter(|it| !it.is_empty())
+    })
+}
+
+fn spill_failed(err: io::Error) -> Response<Body> {
+    error!("failed to spill request body: {}", err);
+    error_response(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "failed to buffer request body",
+    )
+}
+
+/// Response to send back when the worker couldn't respond because the body
+/// broke a limit while it was being streamed.
+pub(crate) fn exceeded_response() -> Response<Body> {
+    payload_too_large("request body exceeds the limits of the worker")
+}
+
+fn payload_too_large(msg: &str) -> Response<Body> {
+    error_response(StatusCode::PAYLOAD_TOO_LARGE, msg)
+}
+
+fn error_response(status: StatusCode, msg: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::json!({ "msg": msg }).to_string()))
+        .unwrap()
+}
@@ -0,0 +1,111 @@
+//! Keeps count of the resources a user worker holds open, such as sockets,
+//! files and timers, and tells when they keep growing from one request to the
+//! next, as they do in long-lived workers that leak them.
+
+use std::collections::BTreeMap;
+
+use deno_core::{OpState, ResourceTable};
+use event_worker::events::ResourceLeakEvent;
+use sb_core::WorkerRequestMetrics;
+
+/// Counts the open resources of the table by their name.
+pub(crate) fn count_open_resources(resource_table: &ResourceTable) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+
+    for (_, name) in resource_table.names() {
+        *counts.entry(name.into_owned()).or_insert(0) += 1;
+    }
+
+    counts
+}
+
+pub(crate) struct ResourceTracker {
+    threshold: u64,
+    last_request_count: usize,
+    last_total: Option<usize>,
+    growth_streak: u64,
+}
+
+impl ResourceTracker {
+    /// A `threshold` of zero still counts the resources, but never reports
+    /// a leak.
+    pub(crate) fn new(threshold: u64) -> Self {
+        Self {
+            threshold,
+            last_request_count: 0,
+            last_total: None,
+            growth_streak: 0,
+        }
+    }
+
+    /// Counts the open resources of the worker if it has served another
+    /// request since the last call. Returns a leak event once they have
+    /// grown after `threshold` requests in a row.
+    pub(crate) fn sample(
+        &mut self,
+        op_state: &OpState,
+        metrics: &WorkerRequestMetrics,
+    ) -> Option<ResourceLeakEvent> {
+        let request_count = metrics.request_count();
+
+        if request_count == self.last_request_count {
+            return None;
+        }
+
+        self.last_request_count = request_count;
+
+        let open_resources = count_open_resources(&op_state.resource_table);
+        let total = open_resources.values().sum();
+
+        metrics.set_open_resources(total);
+
+        self.observe(total).then(|| ResourceLeakEvent {
+            open_resources,
+            consecutive_requests: self.growth_streak,
+        })
+    }
+
+    fn observe(&mut self, total: usize) -> bool {
+        let has_grown = self.last_total.is_some_and(|it| total > it);
+
+        self.last_total = Some(total);
+
+        if !has_grown {
+            self.growth_streak = 0;
+            return false;
+        }
+
+        self.growth_streak += 1;
+
+        // NOTE: A worker that keeps leaking is reported again after as many
+        // requests, rather than after every request from then on.
+        self.threshold > 0 && self.growth_streak % self.threshold == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reports_monotonic_growth() {
+        let mut tracker = ResourceTracker::new(3);
+
+        let reports = [2, 3, 4, 5, 5, 6, 7, 8, 9, 10, 11]
+            .into_iter()
+            .map(|total| tracker.observe(total))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            reports,
+            [false, false, false, true, false, false, false, true, false, false, true]
+        );
+    }
+
+    #[test]
+    fn test_zero_threshold_never_reports() {
+        let mut tracker = ResourceTracker::new(0);
+
+        assert!((0..10).all(|total| !tracker.observe(total)));
+    }
+}
@@ -0,0 +1,62 @@
+use std::io;
+
+use deno_core::serde_json;
+use futures_util::StreamExt;
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::{Response, StatusCode};
+use hyper::Body;
+
+/// Holds the body of `res` to `max_bytes`. A response that declares a larger
+/// body up front is replaced with a 502; otherwise the body is cut off with an
+/// error as soon as it grows past the limit, which aborts the response. Either
+/// way, `on_exceeded` is called once.
+pub(crate) fn limit<F>(max_bytes: u64, res: Response<Body>, on_exceeded: F) -> Response<Body>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let maybe_content_length = res
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|it| it.to_str().ok())
+        .and_then(|it| it.parse::<u64>().ok());
+
+    if maybe_content_length.is_some_and(|it| it > max_bytes) {
+        on_exceeded();
+        return exceeded_response();
+    }
+
+    let (parts, body) = res.into_parts();
+    let mut len = 0u64;
+    let mut maybe_on_exceeded = Some(on_exceeded);
+    let body = body.map(move |chunk| {
+        let chunk = chunk.map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+
+        len += chunk.len() as u64;
+
+        if len > max_bytes {
+            if let Some(on_exceeded) = maybe_on_exceeded.take() {
+                on_exceeded();
+            }
+
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "response body exceeds the limit of the worker",
+            ));
+        }
+
+        Ok(chunk)
+    });
+
+    Response::from_parts(parts, Body::wrap_stream(body))
+}
+
+fn exceeded_response() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({ "msg": "response body exceeds the limit of the worker" })
+                .to_string(),
+        ))
+        .unwrap()
+}
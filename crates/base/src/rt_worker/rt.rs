@@ -1,12 +1,30 @@
+use std::cell::Cell;
 use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use log::{debug, warn};
 use once_cell::sync::Lazy;
+use sb_workers::context::WorkerPriority;
 
 pub const DEFAULT_PRIMARY_WORKER_POOL_SIZE: usize = 2;
 pub const DEFAULT_USER_WORKER_POOL_SIZE: usize = 1;
 
+// NOTE: Supervisors of all user workers are tasks sharing this runtime. Their
+// wall clock deadlines are driven by its timer wheel, so the number of threads
+// does not grow with the number of workers.
 pub static SUPERVISOR_RT: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
-    tokio::runtime::Builder::new_multi_thread()
+    let maybe_pool_size = std::env::var("EDGE_RUNTIME_SUPERVISOR_POOL_SIZE")
+        .ok()
+        .and_then(|it| it.parse::<usize>().ok())
+        .filter(|it| *it > 0);
+
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+
+    if let Some(pool_size) = maybe_pool_size {
+        builder.worker_threads(pool_size);
+    }
+
+    builder
         .enable_all()
         .thread_name("sb-supervisor")
         .build()
@@ -56,3 +74,284 @@ pub static USER_WORKER_RT: Lazy<tokio_util::task::LocalPoolHandle> = Lazy::new(|
         )
     })
 });
+
+static PIN_USER_WORKER_THREADS: Lazy<bool> = Lazy::new(|| {
+    std::env::var("EDGE_RUNTIME_WORKER_PIN_CORES")
+        .ok()
+        .map(|it| it == "1" || it.eq_ignore_ascii_case("true"))
+        .unwrap_or_default()
+});
+
+static NEXT_CORE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static IS_PINNED: Cell<bool> = const { Cell::new(false) };
+    static CURRENT_PRIORITY: Cell<WorkerPriority> = const { Cell::new(WorkerPriority::Interactive) };
+    static DENIED_PRIORITIES: Cell<u8> = const { Cell::new(0) };
+}
+
+/// Pins the calling user worker thread to a core, assigned round-robin, if
+/// `EDGE_RUNTIME_WORKER_PIN_CORES` is set. It is a no-op once the thread has
+/// been pinned or on platforms without thread affinity.
+pub(crate) fn maybe_pin_current_thread() {
+    if !*PIN_USER_WORKER_THREADS || IS_PINNED.with(Cell::get) {
+        return;
+    }
+
+    IS_PINNED.with(|it| it.set(true));
+
+    let cores = std::thread::available_parallelism()
+        .ok()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1);
+
+    let core = NEXT_CORE.fetch_add(1, Ordering::Relaxed) % cores;
+
+    match set_current_thread_affinity(core) {
+        Ok(()) => debug!("pinned user worker thread to core #{}", core),
+        Err(err) => warn!(
+            "failed to pin user worker thread to core #{}: {}",
+            core, err
+        ),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_current_thread_affinity(core: usize) -> std::io::Result<()> {
+    unsafe {
+        let mut set = std::mem::zeroed::<libc::cpu_set_t>();
+
+        libc::CPU_ZERO(&mut set);
+        libc::CPU_SET(core, &mut set);
+
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_current_thread_affinity(_core: usize) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "thread affinity is not supported on this platform",
+    ))
+}
+
+/// Applies the scheduling class of a user worker to the calling thread. Since
+/// user workers share the threads of the pool, this is called every time a
+/// worker is about to be polled; the syscall is skipped if the thread is
+/// already in the requested class, or if the host has denied it before.
+pub(crate) fn apply_worker_priority(priority: WorkerPriority) {
+    apply_worker_priority_with(priority, set_current_thread_priority)
+}
+
+fn apply_worker_priority_with(
+    priority: WorkerPriority,
+    set_fn: impl FnOnce(WorkerPriority) -> std::io::Result<()>,
+) {
+    let mask = 1 << priority as u8;
+
+    if CURRENT_PRIORITY.with(Cell::get) == priority || DENIED_PRIORITIES.with(Cell::get) & mask != 0
+    {
+        return;
+    }
+
+    match set_fn(priority) {
+        Ok(()) => CURRENT_PRIORITY.with(|it| it.set(priority)),
+        Err(err) if priority == WorkerPriority::Interactive => {
+            // NOTE: Never cache this one. Interactive workers would otherwise
+            // keep running in whatever class the last worker left behind.
+            warn!("failed to restore the default worker priority: {}", err);
+        }
+        Err(err) => {
+            // NOTE: The thread stays in the class it was in. Don't retry on
+            // every poll if the host doesn't allow this one.
+            DENIED_PRIORITIES.with(|it| it.set(it.get() | mask));
+            debug!("failed to apply worker priority {:?}: {}", priority, err);
+        }
+    }
+}
+
+/// How much a background worker raises the nice value of its thread.
+#[cfg(target_os = "linux")]
+const BACKGROUND_NICE_INCREMENT: libc::c_int = 10;
+
+#[cfg(target_os = "linux")]
+thread_local! {
+    static BASE_NICE: libc::c_int = current_thread_nice();
+}
+
+#[cfg(target_os = "linux")]
+fn current_thread_nice() -> libc::c_int {
+    unsafe {
+        *libc::__errno_location() = 0;
+
+        let nice = libc::getpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t);
+
+        if nice == -1 && *libc::__errno_location() != 0 {
+            0
+        } else {
+            nice
+        }
+    }
+}
+
+/// Returns the nice value a background worker may run at, if `RLIMIT_NICE`
+/// lets the thread come back down to `base` afterwards.
+#[cfg(target_os = "linux")]
+fn background_nice(base: libc::c_int) -> Option<libc::c_int> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    if unsafe { libc::getrlimit(libc::RLIMIT_NICE, &mut limit) } != 0 {
+        return None;
+    }
+
+    // NOTE: The soft limit is a ceiling of `20 - nice`.
+    let restorable = limit.rlim_cur == libc::RLIM_INFINITY
+        || limit.rlim_cur >= (20 - base).max(0) as libc::rlim_t;
+
+    restorable.then(|| (base + BACKGROUND_NICE_INCREMENT).min(19))
+}
+
+// NOTE: Neither SCHED_IDLE nor a raised nice value can be left again by an
+// unprivileged thread, and pool threads are shared with interactive workers.
+// Only SCHED_OTHER and SCHED_BATCH are used, which a thread can switch between
+// freely, and background workers are niced only when RLIMIT_NICE allows the
+// thread to return to its base nice value.
+#[cfg(target_os = "linux")]
+fn set_current_thread_priority(priority: WorkerPriority) -> std::io::Result<()> {
+    let base = BASE_NICE.with(|it| *it);
+    let (policy, nice) = match priority {
+        WorkerPriority::Interactive => (libc::SCHED_OTHER, base),
+        WorkerPriority::Batch => (libc::SCHED_BATCH, base),
+        WorkerPriority::Background => (libc::SCHED_BATCH, background_nice(base).unwrap_or(base)),
+    };
+
+    let param = libc::sched_param { sched_priority: 0 };
+
+    if unsafe { libc::sched_setscheduler(0, policy, &param) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    if nice != current_thread_nice()
+        && unsafe { libc::setpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t, nice) } != 0
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_current_thread_priority(_priority: WorkerPriority) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "worker priority is not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_denied_priority_is_not_taken_as_applied() {
+        // NOTE: Runs on a thread of its own so that the thread locals start
+        // out fresh.
+        std::thread::spawn(|| {
+            let mut calls = vec![];
+
+            apply_worker_priority_with(WorkerPriority::Background, |it| {
+                calls.push(it);
+                Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
+            });
+
+            assert_eq!(
+                CURRENT_PRIORITY.with(Cell::get),
+                WorkerPriority::Interactive
+            );
+
+            apply_worker_priority_with(WorkerPriority::Background, |it| {
+                calls.push(it);
+                Ok(())
+            });
+
+            apply_worker_priority_with(WorkerPriority::Batch, |it| {
+                calls.push(it);
+                Ok(())
+            });
+
+            assert_eq!(CURRENT_PRIORITY.with(Cell::get), WorkerPriority::Batch);
+
+            apply_worker_priority_with(WorkerPriority::Interactive, |it| {
+                calls.push(it);
+                Ok(())
+            });
+
+            assert_eq!(
+                CURRENT_PRIORITY.with(Cell::get),
+                WorkerPriority::Interactive
+            );
+            assert_eq!(
+                calls,
+                [
+                    WorkerPriority::Background,
+                    WorkerPriority::Batch,
+                    WorkerPriority::Interactive
+                ]
+            );
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_denied_interactive_priority_is_retried() {
+        std::thread::spawn(|| {
+            let mut calls = 0;
+
+            for _ in 0..2 {
+                apply_worker_priority_with(WorkerPriority::Interactive, |_| Ok(()));
+                CURRENT_PRIORITY.with(|it| it.set(WorkerPriority::Batch));
+                apply_worker_priority_with(WorkerPriority::Interactive, |_| {
+                    calls += 1;
+                    Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
+                });
+            }
+
+            assert_eq!(calls, 2);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_thread_returns_to_interactive_after_background_worker() {
+        std::thread::spawn(|| {
+            let base = current_thread_nice();
+
+            apply_worker_priority(WorkerPriority::Background);
+
+            assert_eq!(CURRENT_PRIORITY.with(Cell::get), WorkerPriority::Background);
+            assert_eq!(unsafe { libc::sched_getscheduler(0) }, libc::SCHED_BATCH);
+
+            apply_worker_priority(WorkerPriority::Interactive);
+
+            assert_eq!(
+                CURRENT_PRIORITY.with(Cell::get),
+                WorkerPriority::Interactive
+            );
+            assert_eq!(unsafe { libc::sched_getscheduler(0) }, libc::SCHED_OTHER);
+            assert_eq!(current_thread_nice(), base);
+        })
+        .join()
+        .unwrap();
+    }
+}
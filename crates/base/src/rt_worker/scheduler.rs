@@ -0,0 +1,696 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use sb_workers::context::{UserWorkerProfile, WorkerPriority};
+use sb_workers::errors::WorkerError;
+use tokio::sync::oneshot;
+
+/// Label that identifies the tenant a worker belongs to. Workers without it
+/// are scheduled as their own tenant, keyed by their service path.
+pub const TENANT_LABEL: &str = "tenant";
+
+/// Label that holds the share of slots a tenant gets relative to the others
+/// while they contend. Defaults to 1.
+pub const TENANT_WEIGHT_LABEL: &str = "tenantWeight";
+
+/// Order in which the slots of the [`Scheduler`] are handed out to those
+/// waiting for one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SchedulingPolicy {
+    /// In order of arrival.
+    Fifo,
+    /// Per tenant in weighted round robin order, so that a burst from one
+    /// tenant can't starve the others.
+    #[default]
+    Fair,
+    /// Interactive workers first, then batch and background ones. Waiters of
+    /// the same priority are served in order of arrival.
+    Priority,
+}
+
+impl FromStr for SchedulingPolicy {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fifo" => Ok(Self::Fifo),
+            "fair" => Ok(Self::Fair),
+            "priority" => Ok(Self::Priority),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl SchedulingPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Fifo => "fifo",
+            Self::Fair => "fair",
+            Self::Priority => "priority",
+        }
+    }
+}
+
+/// Identifies who is waiting for a slot, for the policies that tell waiters
+/// apart.
+#[derive(Debug, Clone)]
+pub struct Ticket {
+    pub tenant: String,
+    pub weight: u32,
+    pub priority: WorkerPriority,
+}
+
+impl Ticket {
+    pub fn new(
+        labels: &HashMap<String, String>,
+        service_path: &str,
+        priority: WorkerPriority,
+    ) -> Self {
+        let tenant = labels
+            .get(TENANT_LABEL)
+            .cloned()
+            .unwrap_or_else(|| service_path.to_string());
+
+        let weight = labels
+            .get(TENANT_WEIGHT_LABEL)
+            .and_then(|it| it.parse::<u32>().ok())
+            .unwrap_or(1);
+
+        Self {
+            tenant,
+            weight,
+            priority,
+        }
+    }
+
+    /// Returns the ticket of the requests dispatched to the given worker.
+    pub fn of(profile: &UserWorkerProfile) -> Self {
+        Self::new(&profile.labels, &profile.service_path, profile.priority)
+    }
+}
+
+type Waiter = oneshot::Sender<Permit>;
+
+/// Pops the first waiter that is still around.
+fn pop_live(waiters: &mut VecDeque<Waiter>) -> Option<Waiter> {
+    while let Some(waiter) = waiters.pop_front() {
+        if !waiter.is_closed() {
+            return Some(waiter);
+        }
+    }
+
+    None
+}
+
+struct TenantQueue {
+    weight: u32,
+    served: u32,
+    waiters: VecDeque<Waiter>,
+}
+
+enum WaitQueue {
+    Fifo(VecDeque<Waiter>),
+    Fair {
+        ring: VecDeque<String>,
+        tenants: HashMap<String, TenantQueue>,
+    },
+    Priority([VecDeque<Waiter>; 3]),
+}
+
+impl WaitQueue {
+    fn new(policy: SchedulingPolicy) -> Self {
+        match policy {
+            SchedulingPolicy::Fifo => Self::Fifo(VecDeque::new()),
+            SchedulingPolicy::Fair => Self::Fair {
+                ring: VecDeque::new(),
+                tenants: HashMap::new(),
+            },
+            SchedulingPolicy::Priority => Self::Priority(Default::default()),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Self::Fifo(waiters) => waiters.is_empty(),
+            Self::Fair { ring, .. } => ring.is_empty(),
+            Self::Priority(queues) => queues.iter().all(VecDeque::is_empty),
+        }
+    }
+
+    fn push(&mut self, ticket: Ticket, waiter: Waiter) {
+        match self {
+            Self::Fifo(waiters) => waiters.push_back(waiter),
+            Self::Fair { ring, tenants } => {
+                let queue = tenants
+                    .entry(ticket.tenant.clone())
+                    .or_insert_with(|| TenantQueue {
+                        weight: 1,
+                        served: 0,
+                        waiters: VecDeque::new(),
+                    });
+
+                // NOTE: The weight of the latest waiter wins, so that a
+                // tenant whose weight is changed doesn't keep the old one
+                // for as long as it has waiters.
+                queue.weight = ticket.weight.max(1);
+                queue.waiters.push_back(waiter);
+
+                if queue.waiters.len() == 1 {
+                    ring.push_back(ticket.tenant);
+                }
+            }
+
+            Self::Priority(queues) => {
+                let rank = match ticket.priority {
+                    WorkerPriority::Interactive => 0,
+                    WorkerPriority::Batch => 1,
+                    WorkerPriority::Background => 2,
+                };
+
+                queues[rank].push_back(waiter);
+            }
+        }
+    }
+
+    /// Takes the waiter whose turn it is.
+    fn pop(&mut self) -> Option<Waiter> {
+        match self {
+            Self::Fifo(waiters) => pop_live(waiters),
+            Self::Fair { ring, tenants } => {
+                while let Some(tenant) = ring.front().cloned() {
+                    let Some(queue) = tenants.get_mut(&tenant) else {
+                        let _ = ring.pop_front();
+                        continue;
+                    };
+
+                    let Some(waiter) = pop_live(&mut queue.waiters) else {
+                        let _ = ring.pop_front();
+                        let _ = tenants.remove(&tenant);
+                        continue;
+                    };
+
+                    queue.served += 1;
+
+                    if queue.served >= queue.weight || queue.waiters.is_empty() {
+                        queue.served = 0;
+
+                        let _ = ring.pop_front();
+
+                        if queue.waiters.is_empty() {
+                            let _ = tenants.remove(&tenant);
+                        } else {
+                            ring.push_back(tenant);
+                        }
+                    }
+
+                    return Some(waiter);
+                }
+
+                None
+            }
+
+            Self::Priority(queues) => queues.iter_mut().find_map(pop_live),
+        }
+    }
+}
+
+struct SlotsState {
+    available: usize,
+    queue: WaitQueue,
+}
+
+/// A fixed number of slots. Under contention, the slots are handed out in
+/// the order of a [`SchedulingPolicy`].
+#[derive(Clone)]
+pub struct Slots(Arc<Mutex<SlotsState>>);
+
+impl Slots {
+    pub fn new(count: usize, policy: SchedulingPolicy) -> Self {
+        Self(Arc::new(Mutex::new(SlotsState {
+            available: count,
+            queue: WaitQueue::new(policy),
+        })))
+    }
+
+    pub fn try_acquire(&self) -> Option<Permit> {
+        let mut state = self.0.lock().unwrap();
+
+        if state.available > 0 && state.queue.is_empty() {
+            state.available -= 1;
+            return Some(Permit(Some(self.clone())));
+        }
+
+        None
+    }
+
+    pub async fn acquire(&self, ticket: Ticket) -> Permit {
+        let rx = {
+            let mut state = self.0.lock().unwrap();
+
+            if state.available > 0 && state.queue.is_empty() {
+                state.available -= 1;
+                return Permit(Some(self.clone()));
+            }
+
+            let (tx, rx) = oneshot::channel();
+
+            state.queue.push(ticket, tx);
+            rx
+        };
+
+        // NOTE: The sender is only dropped without sending if the slots
+        // themselves are gone, which can't happen while we hold a clone.
+        rx.await.unwrap_or_else(|_| Permit(Some(self.clone())))
+    }
+
+    fn release(&self) {
+        let mut state = self.0.lock().unwrap();
+
+        while let Some(waiter) = state.queue.pop() {
+            match waiter.send(Permit(Some(self.clone()))) {
+                Ok(()) => return,
+                Err(mut permit) => {
+                    // NOTE: The waiter has gone away. Its permit must be
+                    // defused before dropping it since releasing it re-enters
+                    // the slots while we are still holding the lock.
+                    let _ = permit.0.take();
+                }
+            }
+        }
+
+        state.available += 1;
+    }
+}
+
+/// A slot. The slot is handed to the next waiter once dropped.
+pub struct Permit(Option<Slots>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(slots) = self.0.take() {
+            slots.release();
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Limiter {
+    slots: Slots,
+    queued: Arc<AtomicUsize>,
+    queue_size: Option<usize>,
+    queue_timeout: Duration,
+}
+
+impl Limiter {
+    fn new(
+        count: usize,
+        policy: SchedulingPolicy,
+        queue_size: Option<usize>,
+        queue_timeout: Duration,
+    ) -> Self {
+        Self {
+            slots: Slots::new(count, policy),
+            queued: Arc::default(),
+            queue_size,
+            queue_timeout,
+        }
+    }
+
+    /// Waits for a slot. Fails right away with `full` if the queue is already
+    /// full and with `timed_out` once the queue timeout elapses.
+    async fn acquire(
+        &self,
+        ticket: Ticket,
+        full: WorkerError,
+        timed_out: WorkerError,
+    ) -> Result<Permit, WorkerError> {
+        if let Some(permit) = self.slots.try_acquire() {
+            return Ok(permit);
+        }
+
+        let queued = self.queued.fetch_add(1, Ordering::AcqRel);
+        let _guard = scopeguard::guard(self.queued.clone(), |it| {
+            it.fetch_sub(1, Ordering::AcqRel);
+        });
+
+        if matches!(self.queue_size, Some(size) if queued >= size) {
+            return Err(full);
+        }
+
+        tokio::time::timeout(self.queue_timeout, self.slots.acquire(ticket))
+            .await
+            .map_err(|_| timed_out)
+    }
+}
+
+/// Bounds how many user workers can boot and how many requests can be
+/// dispatched to them at once. Both are unbounded unless limited, and those
+/// over a limit wait for their turn in the order of the policy.
+#[derive(Clone, Default)]
+pub struct Scheduler {
+    policy: SchedulingPolicy,
+    boots: Option<Limiter>,
+    requests: Option<Limiter>,
+}
+
+impl Scheduler {
+    pub fn new(policy: SchedulingPolicy) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    /// Limits the user workers booting at once to `count`. Up to
+    /// `queue_size` others wait for a slot, for at most `queue_timeout`.
+    pub fn with_boot_limit(
+        mut self,
+        count: usize,
+        queue_size: Option<usize>,
+        queue_timeout: Duration,
+    ) -> Self {
+        self.boots = Some(Limiter::new(count, self.policy, queue_size, queue_timeout));
+        self
+    }
+
+    /// Limits the requests dispatched to user workers at once to `count`. Up
+    /// to `queue_size` others wait for a slot, for at most `queue_timeout`.
+    pub fn with_request_limit(
+        mut self,
+        count: usize,
+        queue_size: Option<usize>,
+        queue_timeout: Duration,
+    ) -> Self {
+        self.requests = Some(Limiter::new(count, self.policy, queue_size, queue_timeout));
+        self
+    }
+
+    pub fn policy(&self) -> SchedulingPolicy {
+        self.policy
+    }
+
+    /// Waits for a boot slot. Returns `None` if boots are unbounded.
+    pub async fn acquire_boot(&self, ticket: Ticket) -> Result<Option<Permit>, WorkerError> {
+        match self.boots.as_ref() {
+            Some(limiter) => limiter
+                .acquire(
+                    ticket,
+                    WorkerError::BootQueueFull,
+                    WorkerError::BootQueueTimedOut,
+                )
+                .await
+                .map(Some),
+
+            None => Ok(None),
+        }
+    }
+
+    /// Waits for a dispatch slot. Returns `None` if requests are unbounded.
+    pub async fn acquire_request(&self, ticket: Ticket) -> Result<Option<Permit>, WorkerError> {
+        match self.requests.as_ref() {
+            Some(limiter) => limiter
+                .acquire(
+                    ticket,
+                    WorkerError::RequestQueueFull,
+                    WorkerError::RequestQueueTimedOut,
+                )
+                .await
+                .map(Some),
+
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ticket(tenant: &str, priority: WorkerPriority) -> Ticket {
+        Ticket {
+            tenant: tenant.to_string(),
+            weight: 1,
+            priority,
+        }
+    }
+
+    /// Queues a waiter for each ticket behind a held slot, then releases the
+    /// slot and returns the order in which the waiters got it.
+    async fn grant_order(policy: SchedulingPolicy, tickets: Vec<Ticket>) -> Vec<usize> {
+        let slots = Slots::new(1, policy);
+        let held = slots.try_acquire().unwrap();
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut handles = vec![];
+
+        for (idx, ticket) in tickets.into_iter().enumerate() {
+            let slots = slots.clone();
+            let order_tx = order_tx.clone();
+
+            handles.push(tokio::spawn(async move {
+                let _permit = slots.acquire(ticket).await;
+                order_tx.send(idx).unwrap();
+            }));
+
+            tokio::task::yield_now().await;
+        }
+
+        drop(order_tx);
+        drop(held);
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        let mut order = vec![];
+
+        while let Some(idx) = order_rx.recv().await {
+            order.push(idx);
+        }
+
+        order
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_fifo_grants_in_order_of_arrival() {
+        let order = grant_order(
+            SchedulingPolicy::Fifo,
+            vec![
+                ticket("a", WorkerPriority::Background),
+                ticket("a", WorkerPriority::Interactive),
+                ticket("b", WorkerPriority::Interactive),
+            ],
+        )
+        .await;
+
+        assert_eq!(order, vec![0, 1, 2]);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_fair_alternates_between_tenants() {
+        let order = grant_order(
+            SchedulingPolicy::Fair,
+            vec![
+                ticket("a", WorkerPriority::Interactive),
+                ticket("a", WorkerPriority::Interactive),
+                ticket("a", WorkerPriority::Interactive),
+                ticket("b", WorkerPriority::Interactive),
+            ],
+        )
+        .await;
+
+        assert_eq!(order, vec![0, 3, 1, 2]);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_fair_honours_tenant_weights() {
+        let heavy = |priority| Ticket {
+            weight: 2,
+            ..ticket("a", priority)
+        };
+
+        let order = grant_order(
+            SchedulingPolicy::Fair,
+            vec![
+                heavy(WorkerPriority::Interactive),
+                heavy(WorkerPriority::Interactive),
+                heavy(WorkerPriority::Interactive),
+                heavy(WorkerPriority::Interactive),
+                ticket("b", WorkerPriority::Interactive),
+                ticket("b", WorkerPriority::Interactive),
+            ],
+        )
+        .await;
+
+        assert_eq!(order, vec![0, 1, 4, 2, 3, 5]);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_fair_takes_the_latest_tenant_weight() {
+        let heavy = |priority| Ticket {
+            weight: 2,
+            ..ticket("a", priority)
+        };
+
+        let order = grant_order(
+            SchedulingPolicy::Fair,
+            vec![
+                ticket("a", WorkerPriority::Interactive),
+                heavy(WorkerPriority::Interactive),
+                heavy(WorkerPriority::Interactive),
+                ticket("b", WorkerPriority::Interactive),
+                ticket("b", WorkerPriority::Interactive),
+            ],
+        )
+        .await;
+
+        assert_eq!(order, vec![0, 1, 3, 2, 4]);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_request_burst_of_one_tenant_does_not_starve_another() {
+        let scheduler = Scheduler::new(SchedulingPolicy::Fair).with_request_limit(
+            1,
+            None,
+            Duration::from_secs(10),
+        );
+
+        let held = scheduler
+            .acquire_request(ticket("noisy", WorkerPriority::Interactive))
+            .await
+            .unwrap();
+
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut handles = vec![];
+
+        for tenant in ["noisy"; 8].into_iter().chain(["quiet"]) {
+            let scheduler = scheduler.clone();
+            let order_tx = order_tx.clone();
+
+            handles.push(tokio::spawn(async move {
+                let _permit = scheduler
+                    .acquire_request(ticket(tenant, WorkerPriority::Interactive))
+                    .await
+                    .unwrap();
+
+                order_tx.send(tenant).unwrap();
+            }));
+
+            tokio::task::yield_now().await;
+        }
+
+        drop(order_tx);
+        drop(held);
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        let mut order = vec![];
+
+        while let Some(tenant) = order_rx.recv().await {
+            order.push(tenant);
+        }
+
+        assert_eq!(order.len(), 9);
+        assert_eq!(order.iter().position(|it| *it == "quiet"), Some(1));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_priority_grants_interactive_first() {
+        let order = grant_order(
+            SchedulingPolicy::Priority,
+            vec![
+                ticket("a", WorkerPriority::Background),
+                ticket("a", WorkerPriority::Batch),
+                ticket("a", WorkerPriority::Interactive),
+                ticket("b", WorkerPriority::Interactive),
+            ],
+        )
+        .await;
+
+        assert_eq!(order, vec![2, 3, 1, 0]);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_boot_queue_times_out() {
+        let scheduler = Scheduler::new(SchedulingPolicy::Fifo).with_boot_limit(
+            1,
+            None,
+            Duration::from_millis(10),
+        );
+
+        let _held = scheduler
+            .acquire_boot(ticket("a", WorkerPriority::Interactive))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            scheduler
+                .acquire_boot(ticket("a", WorkerPriority::Interactive))
+                .await,
+            Err(WorkerError::BootQueueTimedOut)
+        ));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_request_queue_times_out() {
+        let scheduler = Scheduler::new(SchedulingPolicy::Fair).with_request_limit(
+            1,
+            None,
+            Duration::from_millis(10),
+        );
+
+        let _held = scheduler
+            .acquire_request(ticket("a", WorkerPriority::Interactive))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            scheduler
+                .acquire_request(ticket("b", WorkerPriority::Interactive))
+                .await,
+            Err(WorkerError::RequestQueueTimedOut)
+        ));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_boot_queue_rejects_once_full() {
+        let scheduler = Scheduler::new(SchedulingPolicy::Fifo).with_boot_limit(
+            1,
+            Some(1),
+            Duration::from_secs(10),
+        );
+
+        let held = scheduler
+            .acquire_boot(ticket("a", WorkerPriority::Interactive))
+            .await
+            .unwrap();
+
+        let queued = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move {
+                scheduler
+                    .acquire_boot(ticket("b", WorkerPriority::Interactive))
+                    .await
+                    .map(|it| it.is_some())
+            }
+        });
+
+        tokio::task::yield_now().await;
+
+        assert!(matches!(
+            scheduler
+                .acquire_boot(ticket("c", WorkerPriority::Interactive))
+                .await,
+            Err(WorkerError::BootQueueFull)
+        ));
+
+        drop(held);
+
+        assert!(matches!(queued.await.unwrap(), Ok(true)));
+    }
+}
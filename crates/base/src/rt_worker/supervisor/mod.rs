@@ -1,14 +1,16 @@
 pub mod strategy_per_request;
 pub mod strategy_per_worker;
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use cpu_timer::{CPUAlarmVal, CPUTimer};
+use cpu_timer::{CPUAlarmVal, CPUTimer, CPUTimerMode};
 use deno_core::v8::IsolateHandle;
 use enum_as_inner::EnumAsInner;
 use futures_util::task::AtomicWaker;
 use log::error;
-use sb_workers::context::{Timing, UserWorkerMsgs, UserWorkerRuntimeOpts};
+use once_cell::sync::Lazy;
+use sb_core::TerminationHook;
+use sb_workers::context::{Timing, UserWorkerMsgsSender, UserWorkerRuntimeOpts};
 use tokio::sync::{
     mpsc::{self, UnboundedReceiver},
     oneshot,
@@ -16,7 +18,7 @@ use tokio::sync::{
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
-use super::{worker_ctx::TerminationToken, worker_pool::SupervisorPolicy};
+use super::{diagnostics, worker_ctx::TerminationToken, worker_pool::SupervisorPolicy};
 
 #[repr(C)]
 pub struct IsolateInterruptData {
@@ -34,6 +36,12 @@ pub extern "C" fn handle_interrupt(
         boxed_data = Box::from_raw(data as *mut IsolateInterruptData);
     }
 
+    // NOTE: Captured before the memory usage is sent, as the supervisor
+    // looks for the diagnostics once it has received it.
+    if boxed_data.should_terminate {
+        diagnostics::capture(isolate);
+    }
+
     // log memory usage
     let mut heap_stats = deno_core::v8::HeapStatistics::default();
 
@@ -41,6 +49,7 @@ pub extern "C" fn handle_interrupt(
 
     let usage = IsolateMemoryStats {
         used_heap_size: heap_stats.used_heap_size(),
+        heap_size_limit: heap_stats.heap_size_limit(),
         external_memory: heap_stats.external_memory(),
     };
 
@@ -55,12 +64,76 @@ pub extern "C" fn handle_interrupt(
     }
 }
 
+/// Memory tiers a user worker moves through as its usage grows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum MemoryPressure {
+    Normal = 0,
+    Warning = 1,
+    Throttle = 2,
+    Critical = 3,
+}
+
+impl From<u8> for MemoryPressure {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Self::Warning,
+            2 => Self::Throttle,
+            3 => Self::Critical,
+            _ => Self::Normal,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct LowMemoryInterruptData {
+    pub memory_limit_bytes: usize,
+    pub memory_limit_tx: mpsc::UnboundedSender<()>,
+}
+
+/// Runs a full GC pass and only signals the memory limit if the heap is still
+/// above the limit afterwards.
+pub extern "C" fn handle_low_memory_interrupt(
+    isolate: &mut deno_core::v8::Isolate,
+    data: *mut std::ffi::c_void,
+) {
+    let boxed_data: Box<LowMemoryInterruptData>;
+
+    unsafe {
+        boxed_data = Box::from_raw(data as *mut LowMemoryInterruptData);
+    }
+
+    isolate.low_memory_notification();
+
+    let mut heap_stats = deno_core::v8::HeapStatistics::default();
+
+    isolate.get_heap_statistics(&mut heap_stats);
+
+    if heap_stats.used_heap_size() < boxed_data.memory_limit_bytes {
+        return;
+    }
+
+    if boxed_data.memory_limit_tx.send(()).is_err() {
+        error!(
+            "failed to send memory limit reached notification - isolate may already be terminating"
+        );
+    }
+}
+
 #[repr(C)]
 pub struct IsolateMemoryStats {
     pub used_heap_size: usize,
+    pub heap_size_limit: usize,
     pub external_memory: usize,
 }
 
+static CPU_TIMER_MODE: Lazy<CPUTimerMode> = Lazy::new(|| {
+    std::env::var("EDGE_RUNTIME_CPU_TIMER_MODE")
+        .ok()
+        .and_then(|it| it.parse::<CPUTimerMode>().ok())
+        .unwrap_or_default()
+});
+
 #[derive(Clone, Copy)]
 pub struct CPUTimerParam {
     soft_limit_ms: u64,
@@ -87,6 +160,7 @@ impl CPUTimerParam {
 
         Some((
             CPUTimer::start(
+                *CPU_TIMER_MODE,
                 if policy.is_per_worker() {
                     self.soft_limit_ms
                 } else {
@@ -127,10 +201,11 @@ pub struct Arguments {
     pub supervisor_policy: SupervisorPolicy,
     pub timing: Option<Timing>,
     pub memory_limit_rx: mpsc::UnboundedReceiver<()>,
-    pub pool_msg_tx: Option<mpsc::UnboundedSender<UserWorkerMsgs>>,
+    pub pool_msg_tx: Option<UserWorkerMsgsSender>,
     pub isolate_memory_usage_tx: oneshot::Sender<IsolateMemoryStats>,
     pub thread_safe_handle: IsolateHandle,
     pub waker: Arc<AtomicWaker>,
+    pub termination_hook: TerminationHook,
     pub tokens: Tokens,
 }
 
@@ -151,3 +226,28 @@ async fn wait_cpu_alarm(maybe_alarm: Option<&mut UnboundedReceiver<()>>) -> Opti
         None => None,
     }
 }
+
+/// Gives the isolate a chance to run its `beforeunload` listeners before the
+/// supervisor terminates it. Returns as soon as the listeners have settled,
+/// the grace period has elapsed, or the isolate hits another resource limit.
+async fn run_termination_hook(
+    hook: &TerminationHook,
+    grace_period_ms: u64,
+    waker: &AtomicWaker,
+    cpu_alarms_rx: Option<&mut UnboundedReceiver<()>>,
+    memory_limit_rx: &mut UnboundedReceiver<()>,
+) {
+    if grace_period_ms == 0 || hook.is_requested() {
+        return;
+    }
+
+    hook.request();
+    waker.wake();
+
+    tokio::select! {
+        _ = hook.completed() => {}
+        _ = tokio::time::sleep(Duration::from_millis(grace_period_ms)) => {}
+        Some(_) = wait_cpu_alarm(cpu_alarms_rx) => {}
+        Some(_) = memory_limit_rx.recv() => {}
+    }
+}
@@ -5,11 +5,12 @@ use std::thread::ThreadId;
 
 use event_worker::events::ShutdownReason;
 use log::error;
-use sb_workers::context::{Timing, TimingStatus, UserWorkerMsgs};
+use sb_workers::context::{BillingMode, Timing, TimingStatus, UserWorkerMsgs};
 use tokio::time::Instant;
 
 use crate::rt_worker::supervisor::{
-    handle_interrupt, wait_cpu_alarm, CPUUsage, CPUUsageMetrics, IsolateInterruptData, Tokens,
+    handle_interrupt, run_termination_hook, wait_cpu_alarm, CPUUsage, CPUUsageMetrics,
+    IsolateInterruptData, Tokens,
 };
 
 use super::Arguments;
@@ -26,6 +27,8 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
         pool_msg_tx,
         isolate_memory_usage_tx,
         thread_safe_handle,
+        waker,
+        termination_hook,
         tokens: Tokens {
             termination,
             supervise,
@@ -34,7 +37,9 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
     } = args;
 
     let Timing {
-        status: TimingStatus { demand, is_retired },
+        status: TimingStatus {
+            demand, is_retired, ..
+        },
         req: (mut req_start_rx, mut req_end_rx),
         ..
     } = timing.unwrap_or_default();
@@ -60,6 +65,8 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
 
     let wall_clock_limit_ms = runtime_opts.worker_timeout_ms;
     let is_wall_clock_limit_disabled = wall_clock_limit_ms == 0;
+    let is_cpu_time_billing =
+        runtime_opts.billing_mode == BillingMode::CpuTime && !cpu_timer_param.is_disabled();
 
     let wall_clock_duration = Duration::from_millis(if wall_clock_limit_ms < 1 {
         1
@@ -68,6 +75,8 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
     });
 
     let wall_clock_duration_alert = tokio::time::sleep(wall_clock_duration);
+    let grace_period_ms = runtime_opts.termination_grace_period_ms;
+    let connections = runtime_opts.request_metrics.clone().unwrap_or_default();
 
     tokio::pin!(wall_clock_duration_alert);
 
@@ -135,12 +144,12 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
                 }
             }
 
-            Some(notify) = req_start_rx.recv() => {
+            Some(start) = req_start_rx.recv() => {
                 // INVARIANT: This branch MUST not be satisfied more than once
                 // during the same request cycle.
                 assert!(!req_start_ack, "supervisor has seen request start signal twice");
 
-                notify.notify_one();
+                start.fence.notify_one();
 
                 if let Some(cpu_timer) = cpu_timer.as_ref() {
                     if let Err(ex) = cpu_timer.reset() {
@@ -164,7 +173,10 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
             }
 
             _ = &mut wall_clock_duration_alert, if !is_wall_clock_limit_disabled => {
-                if !oneshot && req_ack_count != demand.load(Ordering::Acquire) {
+                let is_in_flight = req_ack_count != demand.load(Ordering::Acquire)
+                    || connections.active_connections() > 0;
+
+                if (!oneshot || is_cpu_time_billing) && is_in_flight {
                     wall_clock_duration_alert
                         .as_mut()
                         .reset(Instant::now() + wall_clock_duration);
@@ -190,7 +202,7 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
                     .reset(Instant::now() + wall_clock_duration);
 
                 if let Some(tx) = pool_msg_tx.clone() {
-                    if tx.send(UserWorkerMsgs::Idle(key)).is_err() {
+                    if tx.send_reliable(UserWorkerMsgs::Idle(key)).is_err() {
                         error!("failed to send idle msg to pool: {:?}", key);
                     }
                 }
@@ -199,6 +211,17 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
             }
 
             Some(reason) => {
+                if !matches!(reason, ShutdownReason::CPUTime | ShutdownReason::Memory) {
+                    run_termination_hook(
+                        &termination_hook,
+                        grace_period_ms,
+                        &waker,
+                        cpu_alarms_rx.as_mut(),
+                        &mut memory_limit_rx,
+                    )
+                    .await;
+                }
+
                 let data_ptr_mut = Box::into_raw(Box::new(IsolateInterruptData {
                     should_terminate: true,
                     isolate_memory_usage_tx: Some(isolate_memory_usage_tx),
@@ -1,17 +1,27 @@
-use std::{future::pending, sync::atomic::Ordering, time::Duration};
+use std::{collections::BTreeMap, future::pending, sync::atomic::Ordering, time::Duration};
 
 #[cfg(debug_assertions)]
 use std::thread::ThreadId;
 
 use event_worker::events::ShutdownReason;
+use futures_util::future::BoxFuture;
+use futures_util::stream::FuturesUnordered;
+use futures_util::{FutureExt, StreamExt};
 use log::error;
-use sb_workers::context::{Timing, TimingStatus, UserWorkerMsgs};
+use sb_workers::context::{
+    BillingMode, RequestId, RequestStart, Timing, TimingStatus, UserWorkerMsgs,
+    UserWorkerMsgsSender,
+};
+use tokio::sync::mpsc;
+use uuid::Uuid;
 
-use crate::rt_worker::supervisor::{wait_cpu_alarm, CPUUsage, Tokens};
+use crate::rt_worker::supervisor::{run_termination_hook, wait_cpu_alarm, CPUUsage, Tokens};
 
 use super::{handle_interrupt, Arguments, CPUUsageMetrics, IsolateInterruptData};
 
-pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
+/// Also returns the request that went over the per-request CPU time limit, if
+/// that is why the worker is shut down.
+pub async fn supervise(args: Arguments) -> (ShutdownReason, i64, Option<RequestId>) {
     let Arguments {
         key,
         runtime_opts,
@@ -23,6 +33,8 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
         pool_msg_tx,
         isolate_memory_usage_tx,
         thread_safe_handle,
+        waker,
+        termination_hook,
         tokens: Tokens {
             termination,
             supervise,
@@ -31,8 +43,10 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
     } = args;
 
     let Timing {
-        status: TimingStatus { demand, is_retired },
-        req: (_, mut req_end_rx),
+        status: TimingStatus {
+            demand, is_retired, ..
+        },
+        req: (mut req_start_rx, _req_end_rx),
     } = timing.unwrap_or_default();
 
     let (cpu_timer, mut cpu_alarms_rx) = cpu_timer.unzip();
@@ -53,8 +67,29 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
     let mut wall_clock_alerts = 0;
     let mut req_ack_count = 0usize;
 
+    // NOTE: Requests may overlap on the same isolate, so the CPU time of each
+    // request is the CPU time the isolate has spent while the request was in
+    // flight.
+    let per_request_limit_ms = runtime_opts.cpu_time_per_request_limit_ms as i64;
+    let mut in_flight_reqs = InFlightRequests::default();
+
+    let busy_threshold_pct = runtime_opts.cpu_time_busy_threshold_pct as i64;
+    let busy_soft_limit_ms = if cpu_timer_param.is_disabled() {
+        0
+    } else {
+        soft_limit_ms as i64
+    };
+
+    let mut is_busy = false;
+
+    // NOTE: Upgraded connections and event streams keep the worker from being
+    // dropped early, the same way requests in flight do.
+    let connections = runtime_opts.request_metrics.clone().unwrap_or_default();
+
     let wall_clock_limit_ms = runtime_opts.worker_timeout_ms;
     let is_wall_clock_limit_disabled = wall_clock_limit_ms == 0;
+    let is_cpu_time_billing =
+        runtime_opts.billing_mode == BillingMode::CpuTime && !cpu_timer_param.is_disabled();
 
     let wall_clock_duration = Duration::from_millis(if wall_clock_limit_ms < 2 {
         2
@@ -86,12 +121,14 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
         }
     };
 
+    let grace_period_ms = runtime_opts.termination_grace_period_ms;
+
     tokio::pin!(wall_clock_duration_alert);
 
     loop {
         tokio::select! {
             _ = supervise.cancelled() => {
-                return (ShutdownReason::TerminationRequested, cpu_usage_ms);
+                return (ShutdownReason::TerminationRequested, cpu_usage_ms, None);
             }
 
             _ = async {
@@ -100,8 +137,9 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
                     None => pending().await,
                 }
             } => {
+                run_termination_hook(&termination_hook, grace_period_ms, &waker, cpu_alarms_rx.as_mut(), &mut memory_limit_rx).await;
                 terminate_fn();
-                return (ShutdownReason::TerminationRequested, cpu_usage_ms);
+                return (ShutdownReason::TerminationRequested, cpu_usage_ms, None);
             }
 
             Some(metrics) = cpu_usage_metrics_rx.recv() => {
@@ -131,22 +169,42 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
                         is_worker_entered = false;
                         cpu_usage_ms = accumulated / 1_000_000;
 
+                        if per_request_limit_ms > 0 {
+                            if let Some((seq, req_cpu_usage_ms)) = in_flight_reqs.oldest(cpu_usage_ms) {
+                                if req_cpu_usage_ms >= per_request_limit_ms {
+                                    let maybe_request_id = in_flight_reqs.request_id(seq);
+
+                                    terminate_fn();
+                                    error!("CPU time per-request limit reached. isolate: {:?}, request: {}", key, maybe_request_id.as_ref().map(ToString::to_string).unwrap_or_else(|| format!("#{}", seq)));
+                                    return (ShutdownReason::CPUTime, cpu_usage_ms, maybe_request_id);
+                                }
+                            }
+                        }
+
                         if !cpu_timer_param.is_disabled() {
                             if cpu_usage_ms >= hard_limit_ms as i64 {
                                 terminate_fn();
                                 error!("CPU time hard limit reached. isolate: {:?}", key);
-                                return (ShutdownReason::CPUTime, cpu_usage_ms);
+                                return (ShutdownReason::CPUTime, cpu_usage_ms, None);
                             } else if cpu_usage_ms >= soft_limit_ms as i64 && !cpu_time_soft_limit_reached {
                                 error!("CPU time soft limit reached. isolate: {:?}", key);
                                 cpu_time_soft_limit_reached = true;
 
-                                if req_ack_count == demand.load(Ordering::Acquire) {
+                                if req_ack_count == demand.load(Ordering::Acquire) && connections.active_connections() == 0 {
+                                    run_termination_hook(&termination_hook, grace_period_ms, &waker, cpu_alarms_rx.as_mut(), &mut memory_limit_rx).await;
                                     terminate_fn();
                                     error!("early termination due to the last request being completed. isolate: {:?}", key);
-                                    return (ShutdownReason::EarlyDrop, cpu_usage_ms);
+                                    return (ShutdownReason::EarlyDrop, cpu_usage_ms, None);
                                 }
                             }
                         }
+
+                        let is_near_limits = is_near_cpu_time_limits(busy_threshold_pct, cpu_usage_ms, busy_soft_limit_ms, per_request_limit_ms, in_flight_reqs.oldest(cpu_usage_ms));
+
+                        if is_near_limits != is_busy {
+                            is_busy = is_near_limits;
+                            send_busy(pool_msg_tx.as_ref(), key, is_busy);
+                        }
                     }
                 }
             }
@@ -157,37 +215,67 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
                         error!("CPU time soft limit reached. isolate: {:?}", key);
                         cpu_time_soft_limit_reached = true;
 
-                        if req_ack_count == demand.load(Ordering::Acquire) {
+                        if req_ack_count == demand.load(Ordering::Acquire) && connections.active_connections() == 0 {
+                            run_termination_hook(&termination_hook, grace_period_ms, &waker, cpu_alarms_rx.as_mut(), &mut memory_limit_rx).await;
                             terminate_fn();
                             error!("early termination due to the last request being completed. isolate: {:?}", key);
-                            return (ShutdownReason::EarlyDrop, cpu_usage_ms);
+                            return (ShutdownReason::EarlyDrop, cpu_usage_ms, None);
                         }
                     } else {
                         terminate_fn();
                         error!("CPU time hard limit reached. isolate: {:?}", key);
-                        return (ShutdownReason::CPUTime, cpu_usage_ms);
+                        return (ShutdownReason::CPUTime, cpu_usage_ms, None);
                     }
                 }
             }
 
-            Some(_) = req_end_rx.recv() => {
+            Some(RequestStart { fence, end_rx, request_id }) = req_start_rx.recv() => {
+                fence.notify_one();
+
+                // NOTE: The pool gives every request of this policy its own
+                // end channel.
+                if let Some(end_rx) = end_rx {
+                    in_flight_reqs.start(cpu_usage_ms, end_rx, request_id);
+                }
+            }
+
+            _ = in_flight_reqs.next_end() => {
                 req_ack_count += 1;
 
+                let is_near_limits = is_near_cpu_time_limits(busy_threshold_pct, cpu_usage_ms, busy_soft_limit_ms, per_request_limit_ms, in_flight_reqs.oldest(cpu_usage_ms));
+
+                if is_near_limits != is_busy {
+                    is_busy = is_near_limits;
+                    send_busy(pool_msg_tx.as_ref(), key, is_busy);
+                }
+
                 if !cpu_time_soft_limit_reached {
                     if let Some(tx) = pool_msg_tx.clone() {
-                        if tx.send(UserWorkerMsgs::Idle(key)).is_err() {
+                        if tx.send_reliable(UserWorkerMsgs::Idle(key)).is_err() {
                             error!("failed to send idle msg to pool: {:?}", key);
                         }
                     }
                 }
 
-                if !cpu_time_soft_limit_reached || req_ack_count != demand.load(Ordering::Acquire) {
+                if !cpu_time_soft_limit_reached || req_ack_count != demand.load(Ordering::Acquire) || connections.active_connections() > 0 {
                     continue;
                 }
 
+                run_termination_hook(&termination_hook, grace_period_ms, &waker, cpu_alarms_rx.as_mut(), &mut memory_limit_rx).await;
                 terminate_fn();
                 error!("early termination due to the last request being completed. isolate: {:?}", key);
-                return (ShutdownReason::EarlyDrop, cpu_usage_ms);
+                return (ShutdownReason::EarlyDrop, cpu_usage_ms, None);
+            }
+
+            _ = connections.connections_drained(), if cpu_time_soft_limit_reached && connections.active_connections() > 0 => {
+                if req_ack_count != demand.load(Ordering::Acquire) {
+                    continue;
+                }
+
+                run_termination_hook(&termination_hook, grace_period_ms, &waker, cpu_alarms_rx.as_mut(), &mut memory_limit_rx).await;
+                terminate_fn();
+                error!("early termination due to the last connection being closed. isolate: {:?}", key);
+                return (ShutdownReason::EarlyDrop, cpu_usage_ms, None);
             }
 
             _ = wall_clock_duration_alert.tick(), if !is_wall_clock_limit_disabled => {
@@ -198,13 +286,19 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
                     error!("wall clock duration warning. isolate: {:?}", key);
                     wall_clock_alerts += 1;
                 } else {
-                    let is_in_flight_req_exists = req_ack_count != demand.load(Ordering::Acquire);
+                    let is_in_flight_req_exists = req_ack_count != demand.load(Ordering::Acquire)
+                        || connections.active_connections() > 0;
 
+                    if is_cpu_time_billing && is_in_flight_req_exists {
+                        continue;
+                    }
+
+                    run_termination_hook(&termination_hook, grace_period_ms, &waker, cpu_alarms_rx.as_mut(), &mut memory_limit_rx).await;
                     terminate_fn();
 
                     error!("wall clock duration reached. isolate: {:?} (in_flight_req_exists = {})", key, is_in_flight_req_exists);
 
-                    return (ShutdownReason::WallClockTime, cpu_usage_ms);
+                    return (ShutdownReason::WallClockTime, cpu_usage_ms, None);
                 }
             }
 
@@ -212,8 +306,131 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
             Some(_) = memory_limit_rx.recv() => {
                 terminate_fn();
                 error!("memory limit reached for the worker. isolate: {:?}", key);
-                return (ShutdownReason::Memory, cpu_usage_ms);
+                return (ShutdownReason::Memory, cpu_usage_ms, None);
             }
         }
     }
 }
+
+/// Requests in flight on the worker, along with the CPU time the isolate had
+/// spent when each of them started and their id.
+#[derive(Default)]
+struct InFlightRequests {
+    next_seq: usize,
+    started: BTreeMap<usize, (i64, Option<RequestId>)>,
+    ends: FuturesUnordered<BoxFuture<'static, usize>>,
+}
+
+impl InFlightRequests {
+    fn start(
+        &mut self,
+        cpu_usage_ms: i64,
+        mut end_rx: mpsc::UnboundedReceiver<()>,
+        maybe_request_id: Option<RequestId>,
+    ) {
+        let seq = self.next_seq;
+
+        self.next_seq += 1;
+        self.started.insert(seq, (cpu_usage_ms, maybe_request_id));
+        self.ends.push(
+            async move {
+                let _ = end_rx.recv().await;
+                seq
+            }
+            .boxed(),
+        );
+    }
+
+    /// Waits for any of the requests in flight to end, and returns its
+    /// sequence number.
+    async fn next_end(&mut self) -> usize {
+        match self.ends.next().await {
+            Some(seq) => {
+                self.started.remove(&seq);
+                seq
+            }
+
+            None => pending().await,
+        }
+    }
+
+    /// Returns the oldest request in flight, which has been charged the most
+    /// CPU time, along with that CPU time.
+    fn oldest(&self, cpu_usage_ms: i64) -> Option<(usize, i64)> {
+        self.started
+            .first_key_value()
+            .map(|(seq, (cpu_usage_at_start_ms, _))| (*seq, cpu_usage_ms - cpu_usage_at_start_ms))
+    }
+
+    fn request_id(&self, seq: usize) -> Option<RequestId> {
+        self.started.get(&seq).and_then(|(_, it)| it.clone())
+    }
+}
+
+/// Tells whether the worker is past `threshold_pct` of its CPU time soft
+/// limit, or its oldest request in flight is past `threshold_pct` of the
+/// per-request limit. Limits of zero are not considered.
+fn is_near_cpu_time_limits(
+    threshold_pct: i64,
+    cpu_usage_ms: i64,
+    soft_limit_ms: i64,
+    per_request_limit_ms: i64,
+    oldest_req: Option<(usize, i64)>,
+) -> bool {
+    if threshold_pct <= 0 {
+        return false;
+    }
+
+    let is_near_soft_limit =
+        soft_limit_ms > 0 && cpu_usage_ms * 100 >= soft_limit_ms * threshold_pct;
+    let is_near_per_request_limit = per_request_limit_ms > 0
+        && oldest_req.is_some_and(|(_, req_cpu_usage_ms)| {
+            req_cpu_usage_ms * 100 >= per_request_limit_ms * threshold_pct
+        });
+
+    is_near_soft_limit || is_near_per_request_limit
+}
+
+fn send_busy(pool_msg_tx: Option<&UserWorkerMsgsSender>, key: Uuid, is_busy: bool) {
+    if let Some(tx) = pool_msg_tx {
+        if tx
+            .send_reliable(UserWorkerMsgs::Busy(key, is_busy))
+            .is_err()
+        {
+            error!("failed to send busy msg to pool: {:?}", key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn in_flight_requests_are_charged_until_their_own_end() {
+        let mut reqs = InFlightRequests::default();
+        let (first_end_tx, first_end_rx) = mpsc::unbounded_channel();
+        let (second_end_tx, second_end_rx) = mpsc::unbounded_channel();
+
+        reqs.start(0, first_end_rx, Some(RequestId("first".to_string())));
+        reqs.start(50, second_end_rx, None);
+
+        second_end_tx.send(()).unwrap();
+
+        assert_eq!(reqs.next_end().await, 1);
+        assert_eq!(reqs.oldest(120), Some((0, 120)));
+        assert_eq!(reqs.request_id(0), Some(RequestId("first".to_string())));
+
+        drop(first_end_tx);
+
+        assert_eq!(reqs.next_end().await, 0);
+        assert_eq!(reqs.oldest(120), None);
+    }
+
+    #[test]
+    fn near_limits_checks_the_oldest_request_in_flight() {
+        assert!(is_near_cpu_time_limits(80, 120, 0, 100, Some((0, 90))));
+        assert!(!is_near_cpu_time_limits(80, 120, 0, 100, Some((1, 70))));
+        assert!(!is_near_cpu_time_limits(80, 120, 0, 100, None));
+    }
+}
@@ -0,0 +1,225 @@
+//! Keeps track of the user worker each thread of the pool is running, so that
+//! what `top -H` or `perf` show per thread can be attributed to a worker and
+//! its tenant.
+//!
+//! User workers share the threads of the pool, so a thread is renamed after
+//! the service of the worker it last ran, as in `sb-iso-hello`. Linux keeps
+//! only 15 bytes of a thread name, so the tenant is only in the dump.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::Lazy;
+use sb_workers::context::UserWorkerRuntimeOpts;
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::scheduler::TENANT_LABEL;
+
+const THREAD_NAME_PREFIX: &str = "sb-iso-";
+const MAX_THREAD_NAME_LEN: usize = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ThreadState {
+    Running,
+    Idle,
+}
+
+/// A snapshot of a thread that polls user workers.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThreadInfo {
+    /// The id of the thread as the OS knows it, as `top -H` shows. On
+    /// platforms other than Linux it is only unique within the process.
+    pub tid: i64,
+    pub name: String,
+    pub state: ThreadState,
+    /// The worker the thread is running, or ran last if it is idle.
+    pub worker_key: Option<Uuid>,
+    pub service_path: Option<String>,
+    pub tenant: Option<String>,
+}
+
+/// What a thread records about the user worker it polls.
+pub(crate) struct WorkerTag {
+    key: Option<Uuid>,
+    service_path: Option<String>,
+    tenant: Option<String>,
+    thread_name: String,
+}
+
+impl WorkerTag {
+    pub(crate) fn new(opts: &UserWorkerRuntimeOpts) -> Self {
+        Self {
+            key: opts.key,
+            service_path: opts.service_path.clone(),
+            tenant: opts.labels.get(TENANT_LABEL).cloned(),
+            thread_name: thread_name(opts.service_path.as_deref().unwrap_or_default()),
+        }
+    }
+}
+
+type ThreadSlot = Arc<Mutex<ThreadInfo>>;
+
+static THREADS: Lazy<Mutex<HashMap<i64, ThreadSlot>>> = Lazy::new(Mutex::default);
+
+/// Registers the thread on first use, and unregisters it once it exits.
+struct Registration(ThreadSlot);
+
+impl Registration {
+    fn new() -> Self {
+        let tid = current_tid();
+        let slot = Arc::new(Mutex::new(ThreadInfo {
+            tid,
+            name: std::thread::current()
+                .name()
+                .unwrap_or_default()
+                .to_string(),
+            state: ThreadState::Idle,
+            worker_key: None,
+            service_path: None,
+            tenant: None,
+        }));
+
+        let _ = THREADS.lock().unwrap().insert(tid, slot.clone());
+
+        Self(slot)
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let tid = self.0.lock().unwrap().tid;
+        let _ = THREADS.lock().unwrap().remove(&tid);
+    }
+}
+
+thread_local! {
+    static CURRENT: Registration = Registration::new();
+}
+
+/// Marks the calling thread as running the worker. The thread is renamed if
+/// it last ran a worker of another service.
+pub(crate) fn enter(tag: &WorkerTag) {
+    CURRENT.with(|it| {
+        let mut info = it.0.lock().unwrap();
+
+        if info.worker_key != tag.key {
+            info.worker_key = tag.key;
+            info.service_path = tag.service_path.clone();
+            info.tenant = tag.tenant.clone();
+        }
+
+        if info.name != tag.thread_name {
+            set_current_thread_name(&tag.thread_name);
+            info.name = tag.thread_name.clone();
+        }
+
+        info.state = ThreadState::Running;
+    });
+}
+
+/// Marks the calling thread as done with the worker it entered.
+pub(crate) fn leave() {
+    CURRENT.with(|it| it.0.lock().unwrap().state = ThreadState::Idle);
+}
+
+/// Lists the threads that have polled user workers so far, by thread id.
+pub fn snapshot() -> Vec<ThreadInfo> {
+    let mut threads = THREADS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|it| it.lock().unwrap().clone())
+        .collect::<Vec<_>>();
+
+    threads.sort_by_key(|it| it.tid);
+    threads
+}
+
+/// Builds the name of a thread running a worker of the service, from the
+/// last component of its path cut to fit the limit of Linux.
+fn thread_name(service_path: &str) -> String {
+    let suffix = Path::new(service_path)
+        .file_name()
+        .and_then(|it| it.to_str())
+        .unwrap_or("unknown");
+
+    let mut name = String::from(THREAD_NAME_PREFIX);
+
+    for ch in suffix.chars() {
+        if name.len() + ch.len_utf8() > MAX_THREAD_NAME_LEN {
+            break;
+        }
+
+        name.push(ch);
+    }
+
+    name
+}
+
+#[cfg(target_os = "linux")]
+fn current_tid() -> i64 {
+    unsafe { libc::syscall(libc::SYS_gettid) }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn current_tid() -> i64 {
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    static NEXT_TID: AtomicI64 = AtomicI64::new(1);
+
+    NEXT_TID.fetch_add(1, Ordering::Relaxed)
+}
+
+#[cfg(target_os = "linux")]
+fn set_current_thread_name(name: &str) {
+    if let Ok(name) = std::ffi::CString::new(name) {
+        unsafe {
+            libc::prctl(libc::PR_SET_NAME, name.as_ptr());
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_current_thread_name(_name: &str) {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_thread_name_fits_linux_limit() {
+        assert_eq!(thread_name("./examples/hello"), "sb-iso-hello");
+        assert_eq!(thread_name("./services/a-long-service"), "sb-iso-a-long-s");
+        assert_eq!(thread_name(""), "sb-iso-unknown");
+        assert!(thread_name("./ünïcödé-sërvïcé").len() <= MAX_THREAD_NAME_LEN);
+    }
+
+    #[test]
+    fn test_enter_and_leave_update_snapshot() {
+        let key = Uuid::new_v4();
+        let tag = WorkerTag::new(&UserWorkerRuntimeOpts {
+            key: Some(key),
+            service_path: Some("./examples/hello".to_string()),
+            labels: HashMap::from([(TENANT_LABEL.to_string(), "acme".to_string())]),
+            ..Default::default()
+        });
+
+        let find = || {
+            snapshot()
+                .into_iter()
+                .find(|it| it.worker_key == Some(key))
+                .unwrap()
+        };
+
+        enter(&tag);
+        assert_eq!(find().state, ThreadState::Running);
+        assert_eq!(find().tenant.as_deref(), Some("acme"));
+
+        leave();
+        assert_eq!(find().state, ThreadState::Idle);
+    }
+}
@@ -1,13 +1,15 @@
+use std::collections::HashMap;
+
 use event_worker::events::{EventMetadata, WorkerEventWithMetadata};
-use sb_workers::context::{UserWorkerMsgs, WorkerRuntimeOpts};
-use tokio::sync::mpsc::UnboundedSender;
+use sb_core::channel::BoundedSender;
+use sb_workers::context::{UserWorkerMsgsSender, WorkerRuntimeOpts};
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 type WorkerCoreConfig = (
     Option<Uuid>,
-    Option<UnboundedSender<UserWorkerMsgs>>,
-    Option<UnboundedSender<WorkerEventWithMetadata>>,
+    Option<UserWorkerMsgsSender>,
+    Option<BoundedSender<WorkerEventWithMetadata>>,
     Option<CancellationToken>,
     String,
 );
@@ -35,12 +37,14 @@ pub fn get_event_metadata(conf: &WorkerRuntimeOpts) -> EventMetadata {
     let mut event_metadata = EventMetadata {
         service_path: None,
         execution_id: None,
+        labels: HashMap::new(),
     };
     if conf.is_user_worker() {
         let conf = conf.as_user_worker().unwrap();
         event_metadata = EventMetadata {
             service_path: conf.service_path.clone(),
             execution_id: conf.key,
+            labels: conf.labels.clone(),
         };
     }
 
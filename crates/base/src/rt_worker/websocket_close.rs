@@ -0,0 +1,389 @@
+//! Closing of the websocket connections relayed to a worker that goes away
+//! without closing them itself, e.g. because it was terminated.
+//!
+//! Relayed connections are copied as bytes, so the frames the worker sends
+//! are only followed closely enough to know where each of them ends. A close
+//! frame is only sent in place of the worker if the connection ended between
+//! two frames; one cut off in the middle of a frame is reset as before.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::Notify;
+
+/// The most a close frame has room for after its status code.
+pub const MAX_CLOSE_REASON_BYTES: usize = 123;
+
+const OPCODE_CLOSE: u8 = 0x8;
+
+/// Whether an endpoint may send `code` in a close frame. The codes of 1004 to
+/// 1006 and 1015 are reserved or must never be sent, and the rest below 3000
+/// are not assigned.
+pub fn is_sendable_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+/// The close frame sent to the clients of a worker that goes away without
+/// closing their websocket connections.
+#[derive(Debug, Clone, Copy)]
+pub struct WebSocketClosePolicy {
+    pub code: u16,
+    /// At most [`MAX_CLOSE_REASON_BYTES`] long.
+    pub reason: &'static str,
+}
+
+impl Default for WebSocketClosePolicy {
+    fn default() -> Self {
+        Self {
+            // Going away.
+            code: 1001,
+            reason: "",
+        }
+    }
+}
+
+impl WebSocketClosePolicy {
+    fn frame(&self) -> Bytes {
+        let reason = self.reason.as_bytes();
+        let mut frame = BytesMut::with_capacity(4 + reason.len());
+
+        debug_assert!(reason.len() <= MAX_CLOSE_REASON_BYTES);
+
+        frame.put_u8(0x80 | OPCODE_CLOSE);
+        frame.put_u8((2 + reason.len()) as u8);
+        frame.put_u16(self.code);
+        frame.put_slice(reason);
+        frame.freeze()
+    }
+}
+
+/// Websocket connections relayed to a single worker.
+#[derive(Debug, Default)]
+pub(crate) struct RelayedWebSockets {
+    open: AtomicUsize,
+    closed: AtomicUsize,
+    drained: Notify,
+}
+
+impl RelayedWebSockets {
+    pub(crate) fn open(self: &Arc<Self>) -> RelayGuard {
+        self.open.fetch_add(1, Ordering::AcqRel);
+        RelayGuard(self.clone())
+    }
+
+    /// How many connections were closed in place of the worker.
+    pub(crate) fn closed(&self) -> usize {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    /// Resolves once no connection is relayed to the worker anymore.
+    pub(crate) async fn drained(&self) {
+        loop {
+            let notified = self.drained.notified();
+
+            if self.open.load(Ordering::Acquire) == 0 {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+}
+
+/// Keeps a connection counted as relayed until it is dropped.
+pub(crate) struct RelayGuard(Arc<RelayedWebSockets>);
+
+impl RelayGuard {
+    pub(crate) fn closed_in_place_of_worker(&self) {
+        self.0.closed.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+impl Drop for RelayGuard {
+    fn drop(&mut self) {
+        if self.0.open.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.drained.notify_waiters();
+        }
+    }
+}
+
+/// Follows where the frames written by the server end.
+#[derive(Debug, Default)]
+struct FrameBoundary {
+    header: [u8; 14],
+    header_len: usize,
+    remaining: u64,
+    saw_close: bool,
+}
+
+impl FrameBoundary {
+    fn feed(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.remaining > 0 {
+                let len = data.len().min(self.remaining as usize);
+
+                self.remaining -= len as u64;
+                data = &data[len..];
+                continue;
+            }
+
+            self.header[self.header_len] = data[0];
+            self.header_len += 1;
+            data = &data[1..];
+
+            if let Some(payload_len) = self.parse_header() {
+                if self.header[0] & 0x0f == OPCODE_CLOSE {
+                    self.saw_close = true;
+                }
+
+                self.remaining = payload_len;
+                self.header_len = 0;
+            }
+        }
+    }
+
+    /// Returns the length of the payload once the whole header is in.
+    fn parse_header(&self) -> Option<u64> {
+        if self.header_len < 2 {
+            return None;
+        }
+
+        let len = self.header[1] & 0x7f;
+        let mask_len = if self.header[1] & 0x80 != 0 { 4 } else { 0 };
+        let ext_len = match len {
+            126 => 2,
+            127 => 8,
+            _ => 0,
+        };
+
+        if self.header_len < 2 + ext_len + mask_len {
+            return None;
+        }
+
+        let ext = &self.header[2..2 + ext_len];
+
+        Some(match len {
+            126 => u16::from_be_bytes([ext[0], ext[1]]) as u64,
+            127 => u64::from_be_bytes(ext.try_into().unwrap()),
+            len => len as u64,
+        })
+    }
+
+    fn is_between_frames(&self) -> bool {
+        self.header_len == 0 && self.remaining == 0
+    }
+}
+
+/// Wraps the side of a relayed websocket connection facing the worker, and
+/// reads a close frame from it in place of the worker if the worker goes
+/// away without sending one.
+pub(crate) struct CloseOnAbort<S> {
+    inner: S,
+    policy: Option<WebSocketClosePolicy>,
+    frames: FrameBoundary,
+    pending: Option<Bytes>,
+    is_ended: bool,
+    is_closed_in_place: bool,
+}
+
+impl<S> CloseOnAbort<S> {
+    pub(crate) fn new(inner: S, policy: WebSocketClosePolicy) -> Self {
+        Self::with_policy(inner, Some(policy))
+    }
+
+    /// Passes everything through, for connections that aren't websockets.
+    pub(crate) fn with_bypass(inner: S) -> Self {
+        Self::with_policy(inner, None)
+    }
+
+    fn with_policy(inner: S, policy: Option<WebSocketClosePolicy>) -> Self {
+        Self {
+            inner,
+            policy,
+            frames: FrameBoundary::default(),
+            pending: None,
+            is_ended: false,
+            is_closed_in_place: false,
+        }
+    }
+
+    pub(crate) fn is_closed_in_place(&self) -> bool {
+        self.is_closed_in_place
+    }
+
+    fn put_pending(&mut self, buf: &mut ReadBuf<'_>) {
+        if let Some(pending) = self.pending.as_mut() {
+            let len = pending.len().min(buf.remaining());
+
+            buf.put_slice(&pending.split_to(len));
+
+            if pending.is_empty() {
+                self.pending = None;
+            }
+        }
+    }
+}
+
+impl<S> AsyncRead for CloseOnAbort<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.pending.is_some() {
+            self.put_pending(buf);
+            return Poll::Ready(Ok(()));
+        }
+
+        if self.is_ended {
+            return Poll::Ready(Ok(()));
+        }
+
+        let filled = buf.filled().len();
+
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+
+        if self.policy.is_none() {
+            return Poll::Ready(Ok(()));
+        }
+
+        if buf.filled().len() > filled {
+            self.frames.feed(&buf.filled()[filled..]);
+            return Poll::Ready(Ok(()));
+        }
+
+        // NOTE: The worker is gone.
+        self.is_ended = true;
+
+        if let Some(policy) = self
+            .policy
+            .filter(|_| self.frames.is_between_frames() && !self.frames.saw_close)
+        {
+            self.is_closed_in_place = true;
+            self.pending = Some(policy.frame());
+            self.put_pending(buf);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> AsyncWrite for CloseOnAbort<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    async fn relay(worker_sends: &[u8]) -> (Vec<u8>, bool) {
+        let (mut worker, theirs) = tokio::io::duplex(1024);
+        let mut stream = CloseOnAbort::new(
+            theirs,
+            WebSocketClosePolicy {
+                code: 1001,
+                reason: "bye",
+            },
+        );
+
+        worker.write_all(worker_sends).await.unwrap();
+        drop(worker);
+
+        let mut received = vec![];
+
+        stream.read_to_end(&mut received).await.unwrap();
+        (received, stream.is_closed_in_place())
+    }
+
+    #[tokio::test]
+    async fn test_close_frame_is_sent_between_frames() {
+        // A text frame of 5 bytes, then one with a 16-bit length.
+        let mut frames = vec![
+            0x81, 0x05, b'h', b'e', b'l', b'l', b'o', 0x82, 126, 0x01, 0x00,
+        ];
+
+        frames.extend_from_slice(&[0; 256]);
+
+        let (received, is_closed_in_place) = relay(&frames).await;
+
+        assert!(is_closed_in_place);
+        assert_eq!(&received[..frames.len()], &frames[..]);
+        assert_eq!(
+            &received[frames.len()..],
+            &[0x88, 0x05, 0x03, 0xe9, b'b', b'y', b'e']
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nothing_is_sent_within_a_frame() {
+        let frames = [0x81, 0x05, b'h', b'e'];
+        let (received, is_closed_in_place) = relay(&frames).await;
+
+        assert!(!is_closed_in_place);
+        assert_eq!(received, frames);
+    }
+
+    #[test]
+    fn test_sendable_close_codes() {
+        for code in [1000, 1001, 1003, 1007, 1011, 1014, 3000, 4999] {
+            assert!(is_sendable_close_code(code), "{}", code);
+        }
+
+        for code in [999, 1004, 1005, 1006, 1015, 1016, 2999, 5000] {
+            assert!(!is_sendable_close_code(code), "{}", code);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nothing_is_sent_after_worker_closed() {
+        let frames = [0x88, 0x02, 0x03, 0xe8];
+        let (received, is_closed_in_place) = relay(&frames).await;
+
+        assert!(!is_closed_in_place);
+        assert_eq!(received, frames);
+    }
+}
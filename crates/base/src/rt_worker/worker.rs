@@ -1,4 +1,4 @@
-use crate::deno_runtime::DenoRuntime;
+use crate::deno_runtime::{BootInfo, DenoRuntime};
 use crate::inspector_server::Inspector;
 use crate::rt_worker::supervisor;
 use crate::rt_worker::utils::{get_event_metadata, parse_worker_conf};
@@ -7,22 +7,28 @@ use crate::utils::send_event_if_event_worker_available;
 use anyhow::{anyhow, Error};
 use event_worker::events::{
     EventLoopCompletedEvent, EventMetadata, ShutdownEvent, ShutdownReason, UncaughtExceptionEvent,
-    WorkerEventWithMetadata, WorkerEvents, WorkerMemoryUsed,
+    WorkerEventWithMetadata, WorkerEvents, WorkerMemoryUsed, WorkerRequestStats,
 };
 use futures_util::FutureExt;
 use log::{debug, error};
-use sb_core::{MetricSource, RuntimeMetricSource, WorkerMetricSource};
-use sb_workers::context::{UserWorkerMsgs, WorkerContextInitOpts, WorkerExit, WorkerExitStatus};
+use sb_core::channel::BoundedSender;
+use sb_core::conn_sync::ConnectionInfo;
+use sb_core::http_bridge::BridgedRequest;
+use sb_core::{MetricSource, RuntimeMetricSource, WorkerMetricSource, WorkerRequestMetrics};
+use sb_workers::context::{
+    UserWorkerMsgs, UserWorkerMsgsSender, WorkerContextInitOpts, WorkerExit, WorkerExitStatus,
+};
 use std::any::Any;
 use std::future::{pending, Future};
 use std::pin::Pin;
 use tokio::io;
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::mpsc::{self, unbounded_channel, UnboundedSender};
 use tokio::sync::oneshot::{self, Receiver, Sender};
 use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+use super::accounting;
 use super::rt;
 use super::supervisor::CPUUsageMetrics;
 use super::worker_ctx::TerminationToken;
@@ -31,8 +37,8 @@ use super::worker_pool::SupervisorPolicy;
 #[derive(Clone)]
 pub struct Worker {
     pub worker_boot_start_time: Instant,
-    pub events_msg_tx: Option<UnboundedSender<WorkerEventWithMetadata>>,
-    pub pool_msg_tx: Option<UnboundedSender<UserWorkerMsgs>>,
+    pub events_msg_tx: Option<BoundedSender<WorkerEventWithMetadata>>,
+    pub pool_msg_tx: Option<UserWorkerMsgsSender>,
     pub cancel: Option<CancellationToken>,
     pub event_metadata: EventMetadata,
     pub worker_key: Option<Uuid>,
@@ -42,14 +48,18 @@ pub struct Worker {
 }
 
 pub type HandleCreationType<'r> = Pin<Box<dyn Future<Output = Result<WorkerEvents, Error>> + 'r>>;
-pub type DuplexStreamEntry = (io::DuplexStream, Option<CancellationToken>);
+pub type DuplexStreamEntry = (
+    io::DuplexStream,
+    Option<CancellationToken>,
+    Option<ConnectionInfo>,
+);
 
 pub trait WorkerHandler: Send {
     fn handle_error(&self, error: Error) -> Result<WorkerEvents, Error>;
     fn handle_creation<'r>(
         &self,
         created_rt: &'r mut DenoRuntime,
-        duplex_stream_rx: UnboundedReceiver<DuplexStreamEntry>,
+        duplex_stream_rx: mpsc::Receiver<DuplexStreamEntry>,
         termination_event_rx: Receiver<WorkerEvents>,
         maybe_cpu_metrics_tx: Option<UnboundedSender<CPUUsageMetrics>>,
         name: Option<String>,
@@ -86,17 +96,19 @@ impl Worker {
         self.supervisor_policy = supervisor_policy.unwrap_or_default();
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn start(
         &self,
         mut opts: WorkerContextInitOpts,
         duplex_stream_pair: (
-            UnboundedSender<DuplexStreamEntry>,
-            UnboundedReceiver<DuplexStreamEntry>,
+            BoundedSender<DuplexStreamEntry>,
+            mpsc::Receiver<DuplexStreamEntry>,
         ),
-        booter_signal: Sender<Result<MetricSource, Error>>,
+        booter_signal: Sender<Result<(MetricSource, BootInfo), Error>>,
         exit: WorkerExit,
         termination_token: Option<TerminationToken>,
         inspector: Option<Inspector>,
+        maybe_bridged_request_rx: Option<mpsc::Receiver<BridgedRequest>>,
     ) {
         let worker_name = self.worker_name.clone();
         let worker_key = self.worker_key;
@@ -108,9 +120,22 @@ impl Worker {
         let pool_msg_tx = self.pool_msg_tx.clone();
 
         let method_cloner = self.clone();
+        let monitor_exit = exit.clone();
+        let monitor_pool_msg_tx = pool_msg_tx.clone();
+        let accounting_pool_msg_tx = pool_msg_tx.clone();
+        let worker_boot_start_time = self.worker_boot_start_time;
         let timing = opts.timing.take();
         let worker_kind = opts.conf.to_worker_kind();
         let maybe_main_worker_opts = opts.conf.as_main_worker().cloned();
+        let maybe_accounting_opts = opts
+            .conf
+            .as_user_worker()
+            .map(|it| (it.memory_limit_mb, it.request_metrics.clone()));
+        let secrets = opts
+            .conf
+            .as_user_worker()
+            .map(|it| it.secrets.clone())
+            .unwrap_or_default();
 
         let cancel = self.cancel.clone();
         let rt = if worker_kind.is_user_worker() {
@@ -119,7 +144,11 @@ impl Worker {
             &rt::PRIMARY_WORKER_RT
         };
 
-        let _worker_handle = rt.spawn_pinned(move || {
+        let worker_handle = rt.spawn_pinned(move || {
+            if worker_kind.is_user_worker() {
+                rt::maybe_pin_current_thread();
+            }
+
             tokio::task::spawn_local(async move {
                 let (maybe_cpu_usage_metrics_tx, maybe_cpu_usage_metrics_rx) = worker_kind
                     .is_user_worker()
@@ -128,6 +157,10 @@ impl Worker {
 
                 let result = match DenoRuntime::new(opts, inspector).await {
                     Ok(mut new_runtime) => {
+                        if let Some(rx) = maybe_bridged_request_rx {
+                            new_runtime.js_runtime.op_state().borrow_mut().put(rx);
+                        }
+
                         let metric_src = {
                             let js_runtime = &mut new_runtime.js_runtime;
                             let metric_src = WorkerMetricSource::from_js_runtime(js_runtime);
@@ -150,7 +183,7 @@ impl Worker {
                             }
                         };
 
-                        let _ = booter_signal.send(Ok(metric_src));
+                        let _ = booter_signal.send(Ok((metric_src, new_runtime.boot_info)));
 
                         // CPU TIMER
                         let (termination_event_tx, termination_event_rx) =
@@ -224,7 +257,11 @@ impl Worker {
                                                 total: 0,
                                                 heap: 0,
                                                 external: 0,
+                                                heap_limit: 0,
                                             },
+                                            request_stats: WorkerRequestStats::default(),
+                                            diagnostics_path: None,
+                                            request_id: None,
                                         },
                                     ));
                                 })
@@ -236,7 +273,7 @@ impl Worker {
                         let _guard = scopeguard::guard((), |_| {
                             worker_key.and_then(|worker_key_unwrapped| {
                                 pool_msg_tx.map(|tx| {
-                                    if let Err(err) = tx.send(UserWorkerMsgs::Shutdown(worker_key_unwrapped)) {
+                                    if let Err(err) = tx.send_reliable(UserWorkerMsgs::Shutdown(worker_key_unwrapped)) {
                                         error!(
                                             "failed to send the shutdown signal to user worker pool: {:?}",
                                             err
@@ -281,7 +318,7 @@ impl Worker {
                             };
 
                             if let Some(ev) = maybe_uncaught_exception_event {
-                                exit.set(WorkerExitStatus::WithUncaughtException(ev)).await;
+                                exit.set(WorkerExitStatus::WithUncaughtException(ev));
 
                                 if let Some(token) = supervise_cancel_token.as_ref() {
                                     token.cancel();
@@ -291,6 +328,32 @@ impl Worker {
                             result
                         };
 
+                        if let Some((tx, (memory_limit_mb, maybe_request_metrics))) =
+                            accounting_pool_msg_tx.zip(maybe_accounting_opts)
+                        {
+                            let stats = maybe_request_metrics
+                                .as_ref()
+                                .map(WorkerRequestMetrics::stats)
+                                .unwrap_or_default();
+                            let cpu_time_ms = result
+                                .as_ref()
+                                .ok()
+                                .and_then(WorkerEvents::cpu_time_used)
+                                .unwrap_or_default();
+                            let usage = accounting::worker_usage(
+                                cpu_time_ms as u64,
+                                memory_limit_mb,
+                                worker_boot_start_time.elapsed(),
+                                stats.request_count as u64,
+                                stats.bytes_out,
+                            );
+
+                            let _ = tx.send_reliable(UserWorkerMsgs::Accrue(
+                                accounting::tenant_of(&event_metadata),
+                                usage,
+                            ));
+                        }
+
                         if let Some(token) = termination_token.as_ref() {
                             if !worker_kind.is_user_worker() {
                                 let _ = termination_fut.await;
@@ -311,7 +374,7 @@ impl Worker {
                 drop(duplex_stream_tx);
 
                 match result {
-                    Ok(event) => {
+                    Ok(mut event) => {
                         match event {
                             WorkerEvents::Shutdown(ShutdownEvent { cpu_time_used, .. })
                             | WorkerEvents::UncaughtException(UncaughtExceptionEvent {
@@ -328,6 +391,10 @@ impl Worker {
                             _ => {}
                         };
 
+                        if !secrets.is_empty() {
+                            event.redact(&secrets);
+                        }
+
                         send_event_if_event_worker_available(
                             events_msg_tx.clone(),
                             event,
@@ -338,5 +405,44 @@ impl Worker {
                 };
             })
         });
+
+        // NOTE: The worker task is joined on the supervisor runtime so that a
+        // panic inside it is recorded in the exit status instead of being
+        // silently swallowed.
+        drop(rt::SUPERVISOR_RT.spawn(async move {
+            let result = match worker_handle.await {
+                Ok(inner) => inner,
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = result {
+                if err.is_panic() {
+                    let msg = panic_message(err.into_panic());
+
+                    error!("worker task panicked: {}", msg);
+                    monitor_exit.set(WorkerExitStatus::WithPanic(msg));
+
+                    // The worker may have panicked before it could notify the
+                    // pool, so make sure the pool forgets about it.
+                    if let Some((key, tx)) = worker_key.zip(monitor_pool_msg_tx) {
+                        let _ = tx.send_reliable(UserWorkerMsgs::Shutdown(key));
+                    }
+                } else {
+                    debug!("worker task was cancelled: {}", err);
+                }
+            }
+
+            monitor_exit.mark_finished();
+        }));
+    }
+}
+
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
     }
 }
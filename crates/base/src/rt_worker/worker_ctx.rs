@@ -1,49 +1,61 @@
-use crate::deno_runtime::DenoRuntime;
+use crate::bundle_signature;
+use crate::deno_runtime::{BootInfo, DenoRuntime};
 use crate::inspector_server::Inspector;
 use crate::timeout::{self, CancelOnWriteTimeout, ReadTimeoutStream};
 use crate::utils::send_event_if_event_worker_available;
-use crate::utils::units::bytes_to_display;
+use crate::utils::units::{bytes_to_display, mib_to_bytes};
 
-use crate::rt_worker::worker::{Worker, WorkerHandler};
+use crate::rt_worker::worker::{panic_message, Worker, WorkerHandler};
 use crate::rt_worker::worker_pool::WorkerPool;
 use anyhow::{anyhow, bail, Error};
 use cpu_timer::CPUTimer;
 use deno_config::JsxImportSourceConfig;
 use deno_core::{InspectorSessionProxy, LocalInspectorSession};
 use event_worker::events::{
-    BootEvent, ShutdownEvent, WorkerEventWithMetadata, WorkerEvents, WorkerMemoryUsed,
+    BootEvent, ConnectionErrorEvent, ConnectionErrorKind, EventMetadata, MemoryWarningEvent,
+    ShutdownEvent, ShutdownReason, WebSocketsClosedEvent, WorkerEventWithMetadata, WorkerEvents,
+    WorkerMemoryUsed,
 };
-use futures_util::pin_mut;
+use futures_util::{pin_mut, StreamExt};
 use http::StatusCode;
 use http_utils::io::Upgraded2;
 use http_utils::utils::{emit_status_code, get_upgrade_type};
 use hyper::client::conn::http1;
 use hyper::upgrade::OnUpgrade;
 use hyper::{Body, Request, Response};
-use log::{debug, error};
-use sb_core::{MetricSource, SharedMetricSource};
+use log::{debug, error, log, warn, Level};
+use sb_core::channel::{self, BoundedSender, ChannelKind};
+use sb_core::conn_sync::ConnectionInfo;
+use sb_core::http_bridge::BridgedRequest;
+use sb_core::{MetricSource, RequestDispatchGuard, SharedMetricSource, WorkerRequestMetrics};
 use sb_graph::{DecoratorType, EszipPayloadKind};
 use sb_workers::context::{
-    EventWorkerRuntimeOpts, MainWorkerRuntimeOpts, Timing, UserWorkerMsgs, WorkerContextInitOpts,
-    WorkerExit, WorkerKind, WorkerRequestMsg, WorkerRuntimeOpts,
+    user_worker_msgs_channel, EventWorkerRuntimeOpts, MainWorkerRuntimeOpts, RequestId, Timing,
+    UserWorkerMsgs, UserWorkerMsgsSender, UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerExit,
+    WorkerKind, WorkerRequestMsg, WorkerRuntimeOpts,
 };
-use sb_workers::errors::WorkerError;
+use sb_workers::errors::{connection_error_kind, WorkerError};
 use std::future::pending;
 use std::io::ErrorKind;
 use std::path::PathBuf;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::io::{self, copy_bidirectional};
 use tokio::net::TcpStream;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::{mpsc, oneshot, Mutex};
 use tokio::time::sleep;
 use tokio_rustls::server::TlsStream;
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+use super::diagnostics;
+use super::expect_continue::{self, ContinueSniffer};
+use super::hibernation::Handshake;
 use super::rt;
-use super::supervisor::{self, CPUTimerParam, CPUUsageMetrics};
+use super::supervisor::{self, CPUTimerParam, CPUUsageMetrics, MemoryPressure};
+use super::websocket_close::{CloseOnAbort, RelayGuard, RelayedWebSockets, WebSocketClosePolicy};
 use super::worker::DuplexStreamEntry;
 use super::worker_pool::{SupervisorPolicy, WorkerPoolPolicy};
 
@@ -94,28 +106,228 @@ impl TerminationToken {
     }
 }
 
+/// What is done with a request when the connection to the worker it is
+/// written to can't be set up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerConnectionFailurePolicy {
+    /// Answers the request with `502 Bad Gateway` right away.
+    FailFast,
+    /// Sets up a new connection up to this many more times before answering
+    /// the request with `502 Bad Gateway`.
+    Retry(usize),
+}
+
+impl Default for WorkerConnectionFailurePolicy {
+    fn default() -> Self {
+        Self::FailFast
+    }
+}
+
+/// Settings of the HTTP/1 connection requests are written to a worker over.
+///
+/// NOTE: The version of hyper in use parses at most 100 headers in a
+/// response, and doesn't let that limit be changed.
+#[derive(Debug, Clone, Copy)]
+pub struct WorkerConnectionConfig {
+    pub writev: bool,
+    pub title_case_headers: bool,
+    /// Bounds the buffer the response head of the worker is read into, and
+    /// so the size of its headers. hyper requires at least 8192 bytes.
+    pub max_buf_size: Option<usize>,
+    pub on_failure: WorkerConnectionFailurePolicy,
+    pub websocket_close: WebSocketClosePolicy,
+}
+
+impl Default for WorkerConnectionConfig {
+    fn default() -> Self {
+        Self {
+            writev: true,
+            title_case_headers: false,
+            max_buf_size: None,
+            on_failure: WorkerConnectionFailurePolicy::default(),
+            websocket_close: WebSocketClosePolicy::default(),
+        }
+    }
+}
+
+impl WorkerConnectionConfig {
+    fn builder(&self) -> http1::Builder {
+        let mut builder = http1::Builder::new();
+
+        builder
+            .writev(self.writev)
+            .title_case_headers(self.title_case_headers);
+
+        if let Some(size) = self.max_buf_size {
+            builder.max_buf_size(size);
+        }
+
+        builder
+    }
+
+    fn max_attempts(&self) -> usize {
+        match self.on_failure {
+            WorkerConnectionFailurePolicy::FailFast => 1,
+            WorkerConnectionFailurePolicy::Retry(count) => count.saturating_add(1),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn handle_request(
     worker_kind: WorkerKind,
-    duplex_stream_tx: mpsc::UnboundedSender<DuplexStreamEntry>,
+    duplex_stream_tx: BoundedSender<DuplexStreamEntry>,
+    maybe_bridged_request_tx: Option<BoundedSender<BridgedRequest>>,
     msg: WorkerRequestMsg,
     maybe_request_idle_timeout: Option<u64>,
+    conn_config: WorkerConnectionConfig,
+    events: (
+        Option<BoundedSender<WorkerEventWithMetadata>>,
+        EventMetadata,
+    ),
+    maybe_connection_limit: Option<(WorkerRequestMetrics, u64)>,
+    relayed_websockets: Arc<RelayedWebSockets>,
 ) -> Result<(), Error> {
-    let (ours, theirs) = io::duplex(1024);
     let WorkerRequestMsg {
         mut req,
         res_tx,
         conn_token,
     } = msg;
 
-    let _ = duplex_stream_tx.send((theirs, conn_token.clone()));
+    let request_id = RequestId::of(&mut req);
+
+    // NOTE: Only the main worker gets to see the connection a request came
+    // through.
+    let maybe_conn_info = req
+        .extensions_mut()
+        .remove::<ConnectionInfo>()
+        .filter(|_| worker_kind.is_main_worker());
+
     let req_upgrade_type = get_upgrade_type(req.headers());
-    let req_upgrade = req_upgrade_type
-        .clone()
-        .and_then(|it| Some(it).zip(req.extensions_mut().remove::<OnUpgrade>()));
+    let is_websocket = req_upgrade_type
+        .as_deref()
+        .is_some_and(|it| it.eq_ignore_ascii_case("websocket"));
+
+    // NOTE: Upgrades and requests expecting `100 Continue` depend on the
+    // connection, so they go over HTTP even when the bridge is on.
+    if let Some(tx) = maybe_bridged_request_tx {
+        if req_upgrade_type.is_none() && !expect_continue::expects_continue(req.headers()) {
+            return bridge_request(
+                tx,
+                req,
+                res_tx,
+                conn_token,
+                maybe_request_idle_timeout,
+                worker_kind,
+                request_id,
+                events,
+                maybe_connection_limit,
+            )
+            .await;
+        }
+    }
+
+    // NOTE: The slot is taken before the worker sees the request, and given
+    // back if the worker doesn't accept the upgrade.
+    let mut maybe_conn_guard = None;
+
+    if let Some((metrics, max)) = maybe_connection_limit.as_ref() {
+        if req_upgrade_type.is_some() {
+            let Some(guard) = metrics.open_connection(*max) else {
+                drop(res_tx.send(Ok(too_many_connections())));
+                return Ok(());
+            };
+
+            maybe_conn_guard = Some(guard);
+        }
+    }
+
+    let expects_continue = expect_continue::expects_continue(req.headers());
+    let mut attempt = 0;
 
     // send the HTTP request to the worker over duplex stream
-    let (mut request_sender, connection) =
-        http1::Builder::new().writev(true).handshake(ours).await?;
+    let (mut request_sender, connection, maybe_continue_rx) = loop {
+        attempt += 1;
+
+        let (ours, theirs) = io::duplex(1024);
+
+        match duplex_stream_tx.send((theirs, conn_token.clone(), maybe_conn_info.clone())) {
+            Ok(()) => {}
+
+            // NOTE: The worker is so far behind on accepting requests that
+            // there is no point in queueing another one.
+            Err(err) if err.is_full() => {
+                drop(res_tx.send(Ok(emit_status_code(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    None,
+                    false,
+                ))));
+
+                return Ok(());
+            }
+
+            // NOTE: The worker has stopped taking streams for good, so there
+            // is nothing to retry.
+            Err(_) => {
+                drop(res_tx.send(Ok(emit_status_code(StatusCode::BAD_GATEWAY, None, false))));
+                bail!("{} worker is no longer accepting connections", worker_kind);
+            }
+        }
+
+        // NOTE: The body is only sent once the worker asks for it, so that
+        // the client isn't told to go on with a body the worker won't read.
+        let (maybe_continue_tx, maybe_continue_rx) = if expects_continue {
+            let (tx, rx) = oneshot::channel();
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+
+        match conn_config
+            .builder()
+            .handshake(ContinueSniffer::new(ours, maybe_continue_tx))
+            .await
+        {
+            Ok((request_sender, connection)) => {
+                break (request_sender, connection, maybe_continue_rx);
+            }
+
+            Err(err) => {
+                if attempt < conn_config.max_attempts() {
+                    debug!(
+                        "retrying connection to {} worker ({}/{}): {}",
+                        worker_kind,
+                        attempt,
+                        conn_config.max_attempts(),
+                        err
+                    );
+
+                    continue;
+                }
+
+                drop(res_tx.send(Ok(emit_status_code(StatusCode::BAD_GATEWAY, None, false))));
+                return Err(err.into());
+            }
+        }
+    };
+
+    if let Some(guard) = req.extensions_mut().remove::<RequestDispatchGuard>() {
+        guard.dispatched();
+    }
+
+    let maybe_req_upgrade = req_upgrade_type
+        .as_ref()
+        .and_then(|_| req.extensions_mut().remove::<OnUpgrade>());
+
+    // NOTE: A user worker may hand a websocket connection back to the runtime
+    // instead of accepting it, so what it takes to complete the handshake is
+    // kept aside.
+    let maybe_handshake =
+        (is_websocket && worker_kind.is_user_worker()).then(|| Handshake::of(&mut req));
+
+    if let Some(rx) = maybe_continue_rx {
+        req = req.map(|body| expect_continue::gate(body, rx));
+    }
 
     let (upgrade_tx, upgrade_rx) = oneshot::channel();
 
@@ -123,27 +335,32 @@ async fn handle_request(
     tokio::task::spawn({
         async move {
             match connection.without_shutdown().await {
-                Err(e) => {
-                    error!(
-                        "error in {} worker connection: {}",
-                        worker_kind,
-                        e.message()
-                    );
-                }
+                Err(e) => report_connection_error(
+                    worker_kind,
+                    &request_id,
+                    connection_error_kind(&e),
+                    e.to_string(),
+                    events,
+                ),
 
                 Ok(parts) => {
-                    if let Some((requested, req_upgrade)) = req_upgrade {
-                        if let Ok((Some(accepted), status)) = upgrade_rx.await {
-                            if status == StatusCode::SWITCHING_PROTOCOLS && accepted == requested {
-                                tokio::spawn(relay_upgraded_request_and_response(
-                                    req_upgrade,
-                                    parts,
-                                    maybe_request_idle_timeout,
-                                ));
-
-                                return;
-                            }
-                        };
+                    if let Ok((req_upgrade, maybe_conn_guard)) = upgrade_rx.await {
+                        let maybe_websocket = is_websocket
+                            .then(|| (conn_config.websocket_close, relayed_websockets.open()));
+
+                        tokio::spawn(async move {
+                            relay_upgraded_request_and_response(
+                                req_upgrade,
+                                parts,
+                                maybe_request_idle_timeout,
+                                maybe_websocket,
+                            )
+                            .await;
+
+                            drop(maybe_conn_guard);
+                        });
+
+                        return;
                     }
 
                     if let Some(token) = conn_token {
@@ -156,18 +373,9 @@ async fn handle_request(
 
     tokio::task::yield_now().await;
 
-    let maybe_cancel_fut = async move {
-        if let Some(timeout_ms) = maybe_request_idle_timeout {
-            sleep(Duration::from_millis(timeout_ms)).await;
-        } else {
-            pending::<()>().await;
-            unreachable!()
-        }
-    };
-
     let res = tokio::select! {
         resp = request_sender.send_request(req) => resp,
-        _ = maybe_cancel_fut => {
+        _ = request_idle_timeout(maybe_request_idle_timeout) => {
             Ok(emit_status_code(http::StatusCode::GATEWAY_TIMEOUT, None, false))
         }
     };
@@ -178,11 +386,24 @@ async fn handle_request(
     };
 
     if let Some(requested) = req_upgrade_type {
-        let res_upgrade_type = get_upgrade_type(res.headers());
-        let _ = upgrade_tx.send((res_upgrade_type.clone(), res.status()));
+        if let Some(handshake) = maybe_handshake.filter(|_| Handshake::is_requested(&res)) {
+            let res = match maybe_req_upgrade {
+                Some(req_upgrade) => handshake.accept(req_upgrade, &res),
+                None => emit_status_code(StatusCode::BAD_GATEWAY, None, true),
+            };
 
-        match res_upgrade_type {
-            Some(accepted) if accepted == requested => {}
+            drop(res_tx.send(Ok(res)));
+            return Ok(());
+        }
+
+        match get_upgrade_type(res.headers()) {
+            Some(accepted) if accepted == requested => {
+                if let Some(req_upgrade) = maybe_req_upgrade {
+                    if res.status() == StatusCode::SWITCHING_PROTOCOLS {
+                        let _ = upgrade_tx.send((req_upgrade, maybe_conn_guard));
+                    }
+                }
+            }
             _ => {
                 drop(res_tx.send(Ok(emit_status_code(StatusCode::BAD_GATEWAY, None, true))));
                 return Ok(());
@@ -190,39 +411,225 @@ async fn handle_request(
         }
     }
 
-    if let Some(timeout_ms) = maybe_request_idle_timeout {
-        let headers = res.headers();
-        let is_streamed_response = !headers.contains_key(http::header::CONTENT_LENGTH);
+    let res = limit_event_stream(res, maybe_connection_limit);
+
+    drop(res_tx.send(Ok(cancel_on_write_timeout(res, maybe_request_idle_timeout))));
 
-        if is_streamed_response {
-            let duration = Duration::from_millis(timeout_ms);
-            let (parts, body) = res.into_parts();
+    Ok(())
+}
 
-            drop(res_tx.send(Ok(Response::from_parts(
-                parts,
-                Body::wrap_stream(CancelOnWriteTimeout::new(body, duration)),
+/// Takes a connection slot for the lifetime of `res` if it is an event stream,
+/// or turns it away if the worker is already serving too many of them.
+fn limit_event_stream(
+    res: Response<Body>,
+    maybe_connection_limit: Option<(WorkerRequestMetrics, u64)>,
+) -> Response<Body> {
+    match maybe_connection_limit {
+        Some((metrics, max)) if is_event_stream(&res) => match metrics.open_connection(max) {
+            Some(guard) => res.map(|body| {
+                Body::wrap_stream(body.inspect(move |_| {
+                    // NOTE: The slot is held until the stream ends or the
+                    // client goes away.
+                    let _guard = &guard;
+                }))
+            }),
+
+            None => too_many_connections(),
+        },
+
+        _ => res,
+    }
+}
+
+fn is_event_stream(res: &Response<Body>) -> bool {
+    res.headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|it| it.to_str().ok())
+        .is_some_and(|it| it.starts_with("text/event-stream"))
+}
+
+fn too_many_connections() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({ "msg": "worker is serving too many connections" }).to_string(),
+        ))
+        .unwrap()
+}
+
+/// Logs that the connection to a worker broke while it was serving
+/// `request_id`, and sends it to the event worker.
+fn report_connection_error(
+    worker_kind: WorkerKind,
+    request_id: &RequestId,
+    kind: ConnectionErrorKind,
+    msg: String,
+    events: (
+        Option<BoundedSender<WorkerEventWithMetadata>>,
+        EventMetadata,
+    ),
+) {
+    log!(
+        match kind {
+            ConnectionErrorKind::WorkerReset => Level::Warn,
+            ConnectionErrorKind::Runtime => Level::Error,
+        },
+        "connection to {} worker was {} while serving request {}: {}",
+        worker_kind,
+        kind,
+        request_id,
+        msg
+    );
+
+    let (maybe_events_msg_tx, event_metadata) = events;
+
+    send_event_if_event_worker_available(
+        maybe_events_msg_tx,
+        WorkerEvents::ConnectionError(ConnectionErrorEvent {
+            request_id: request_id.to_string(),
+            kind,
+            msg,
+        }),
+        event_metadata,
+    );
+}
+
+/// Hands `req` to the worker through `sb_core::http_bridge` instead of
+/// writing it out over a duplex stream.
+#[allow(clippy::too_many_arguments)]
+async fn bridge_request(
+    bridged_request_tx: BoundedSender<BridgedRequest>,
+    mut req: Request<Body>,
+    res_tx: oneshot::Sender<Result<Response<Body>, hyper::Error>>,
+    conn_token: Option<CancellationToken>,
+    maybe_request_idle_timeout: Option<u64>,
+    worker_kind: WorkerKind,
+    request_id: RequestId,
+    events: (
+        Option<BoundedSender<WorkerEventWithMetadata>>,
+        EventMetadata,
+    ),
+    maybe_connection_limit: Option<(WorkerRequestMetrics, u64)>,
+) -> Result<(), Error> {
+    let maybe_dispatch_guard = req.extensions_mut().remove::<RequestDispatchGuard>();
+    let (bridged_res_tx, bridged_res_rx) = oneshot::channel();
+
+    if let Err(err) = bridged_request_tx.send(BridgedRequest {
+        req,
+        res_tx: bridged_res_tx,
+        conn_token: conn_token.clone(),
+    }) {
+        if err.is_full() {
+            drop(res_tx.send(Ok(emit_status_code(
+                StatusCode::SERVICE_UNAVAILABLE,
+                None,
+                false,
             ))));
 
             return Ok(());
         }
+
+        // NOTE: Dropping `res_tx` lets the sender of the request find out why
+        // the worker went away.
+        bail!("worker is no longer accepting bridged requests");
+    }
+
+    if let Some(guard) = maybe_dispatch_guard {
+        guard.dispatched();
     }
 
-    drop(res_tx.send(Ok(res)));
+    let res = tokio::select! {
+        res = bridged_res_rx => {
+            let Ok(res) = res else {
+                // NOTE: Same as the worker resetting the connection of a
+                // request sent over a duplex stream.
+                report_connection_error(
+                    worker_kind,
+                    &request_id,
+                    ConnectionErrorKind::WorkerReset,
+                    String::from("worker closed the request without responding"),
+                    events,
+                );
+
+                return Ok(());
+            };
+
+            res
+        }
+
+        // NOTE: The worker finds out through the abort signal of the request,
+        // and the response it may still send goes nowhere.
+        _ = async {
+            match conn_token.as_ref() {
+                Some(token) => token.cancelled().await,
+                None => pending::<()>().await,
+            }
+        } => {
+            return Ok(());
+        }
+
+        _ = request_idle_timeout(maybe_request_idle_timeout) => {
+            emit_status_code(http::StatusCode::GATEWAY_TIMEOUT, None, false)
+        }
+    };
+
+    let res = limit_event_stream(res, maybe_connection_limit);
+
+    drop(res_tx.send(Ok(cancel_on_write_timeout(res, maybe_request_idle_timeout))));
+
     Ok(())
 }
 
+async fn request_idle_timeout(maybe_request_idle_timeout: Option<u64>) {
+    if let Some(timeout_ms) = maybe_request_idle_timeout {
+        sleep(Duration::from_millis(timeout_ms)).await;
+    } else {
+        pending::<()>().await;
+    }
+}
+
+/// Cuts off a streamed response whose body has had nothing new for the idle
+/// timeout.
+fn cancel_on_write_timeout(
+    res: Response<Body>,
+    maybe_request_idle_timeout: Option<u64>,
+) -> Response<Body> {
+    let Some(timeout_ms) = maybe_request_idle_timeout else {
+        return res;
+    };
+
+    if res.headers().contains_key(http::header::CONTENT_LENGTH) {
+        return res;
+    }
+
+    let duration = Duration::from_millis(timeout_ms);
+    let (parts, body) = res.into_parts();
+
+    Response::from_parts(
+        parts,
+        Body::wrap_stream(CancelOnWriteTimeout::new(body, duration)),
+    )
+}
+
 async fn relay_upgraded_request_and_response(
     downstream: OnUpgrade,
-    parts: http1::Parts<io::DuplexStream>,
+    parts: http1::Parts<ContinueSniffer<io::DuplexStream>>,
     maybe_idle_timeout: Option<u64>,
+    maybe_websocket: Option<(WebSocketClosePolicy, RelayGuard)>,
 ) {
     let upstream = Upgraded2::new(parts.io, parts.read_buf);
-    let mut upstream = if let Some(timeout_ms) = maybe_idle_timeout {
+    let upstream = if let Some(timeout_ms) = maybe_idle_timeout {
         ReadTimeoutStream::with_timeout(upstream, Duration::from_millis(timeout_ms))
     } else {
         ReadTimeoutStream::with_bypass(upstream)
     };
 
+    let (mut upstream, maybe_relay_guard) = match maybe_websocket {
+        Some((policy, guard)) => (CloseOnAbort::new(upstream, policy), Some(guard)),
+        None => (CloseOnAbort::with_bypass(upstream), None),
+    };
+
     let mut downstream = downstream.await.expect("failed to upgrade request");
 
     match copy_bidirectional(&mut upstream, &mut downstream).await {
@@ -245,16 +652,87 @@ async fn relay_upgraded_request_and_response(
         }
     }
 
+    if let Some(guard) = maybe_relay_guard {
+        if upstream.is_closed_in_place() {
+            guard.closed_in_place_of_worker();
+        }
+    }
+
     // XXX(Nyannyacha): Here you might want to emit the event metadata.
 }
 
+const LOW_MEMORY_LAST_RESORT_ALLOWANCE_MB: u64 = 16;
+
+/// Decides how far the heap limit of a worker may grow each time V8 is about
+/// to run out of it.
+struct LowMemoryAllowance {
+    multiplier: usize,
+    max_bytes: Option<usize>,
+    max_extensions: Option<u64>,
+    extensions: u64,
+    last_resort_granted: bool,
+}
+
+impl LowMemoryAllowance {
+    fn new(conf: &UserWorkerRuntimeOpts) -> Self {
+        Self {
+            multiplier: conf.low_memory_multiplier as usize,
+            max_bytes: Some(conf.low_memory_max_allowance_bytes as usize).filter(|it| *it != 0),
+            max_extensions: Some(conf.low_memory_max_extensions).filter(|it| *it != 0),
+            extensions: 0,
+            last_resort_granted: false,
+        }
+    }
+
+    /// Returns the next heap limit, and whether the allowance is exhausted so
+    /// that the worker has to be terminated.
+    fn extend(&mut self, cur: usize) -> (usize, bool) {
+        let last_resort = mib_to_bytes(LOW_MEMORY_LAST_RESORT_ALLOWANCE_MB) as usize;
+
+        self.extensions += 1;
+
+        // NOTE: The headroom given once the allowance is exhausted is carved
+        // out of the upper bound, so that the heap never grows past it.
+        let next = cur.saturating_mul(self.multiplier);
+        let next = match self.max_bytes {
+            Some(max_bytes) => next.min(max_bytes.saturating_sub(last_resort)),
+            None => next,
+        };
+
+        if next > cur && self.max_extensions.map_or(true, |it| self.extensions <= it) {
+            return (next, false);
+        }
+
+        // NOTE: Returning the current limit makes V8 abort the whole process,
+        // so a small headroom is left, once, until the supervisor terminates
+        // the isolate.
+        if self.last_resort_granted {
+            return (cur, true);
+        }
+
+        self.last_resort_granted = true;
+
+        let next = cur.saturating_add(last_resort);
+        let next = match self.max_bytes {
+            Some(max_bytes) => next.min(max_bytes.max(cur)),
+            None => next,
+        };
+
+        (next, true)
+    }
+}
+
+/// How long the websocket connections of a worker that has exited are given
+/// to be closed before they are counted.
+const WEBSOCKET_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[allow(clippy::too_many_arguments)]
 pub fn create_supervisor(
     key: Uuid,
     worker_runtime: &mut DenoRuntime,
     supervisor_policy: SupervisorPolicy,
     termination_event_tx: oneshot::Sender<WorkerEvents>,
-    pool_msg_tx: Option<UnboundedSender<UserWorkerMsgs>>,
+    pool_msg_tx: Option<UserWorkerMsgsSender>,
     cpu_usage_metrics_rx: Option<UnboundedReceiver<CPUUsageMetrics>>,
     cancel: Option<CancellationToken>,
     timing: Option<Timing>,
@@ -271,7 +749,14 @@ pub fn create_supervisor(
 
     // we assert supervisor is only run for user workers
     let conf = worker_runtime.conf.as_user_worker().unwrap().clone();
+    let maybe_diagnostics = conf.diagnostics.as_ref().map(|it| {
+        (
+            it.dir.clone(),
+            diagnostics::install(&mut worker_runtime.js_runtime, it.log_lines),
+        )
+    });
     let is_termination_requested = worker_runtime.is_termination_requested.clone();
+    let termination_hook = worker_runtime.termination_hook.clone();
 
     let giveup_process_requests_token = cancel.clone();
     let supervise_cancel_token = CancellationToken::new();
@@ -292,6 +777,61 @@ pub fn create_supervisor(
         )
     });
 
+    let memory_limit_bytes = conf
+        .memory_threshold_bytes(conf.memory_termination_threshold_pct)
+        .unwrap_or(mib_to_bytes(conf.memory_limit_mb) as usize);
+
+    worker_runtime.add_memory_pressure_callback({
+        let memory_limit_tx = memory_limit_tx.clone();
+        let thread_safe_handle = thread_safe_handle.clone();
+        let is_throttled = timing.as_ref().map(|it| it.status.is_throttled.clone());
+        let events_msg_tx = conf.events_msg_tx.clone();
+        let event_metadata = EventMetadata {
+            service_path: conf.service_path.clone(),
+            execution_id: conf.key,
+            labels: conf.labels.clone(),
+        };
+
+        let mut last_pressure = MemoryPressure::Normal;
+
+        move |pressure, memory_used| {
+            if pressure >= MemoryPressure::Warning && last_pressure < MemoryPressure::Warning {
+                warn!(
+                    "memory usage warning: {}",
+                    bytes_to_display(memory_used as u64)
+                );
+                send_event_if_event_worker_available(
+                    events_msg_tx.clone(),
+                    WorkerEvents::MemoryWarning(MemoryWarningEvent {
+                        memory_used,
+                        memory_limit: memory_limit_bytes,
+                    }),
+                    event_metadata.clone(),
+                );
+            }
+
+            if let Some(is_throttled) = is_throttled.as_ref() {
+                is_throttled.store(pressure >= MemoryPressure::Throttle, Ordering::Release);
+            }
+
+            if pressure >= MemoryPressure::Throttle && last_pressure < MemoryPressure::Throttle {
+                let data_ptr_mut = Box::into_raw(Box::new(supervisor::LowMemoryInterruptData {
+                    memory_limit_bytes,
+                    memory_limit_tx: memory_limit_tx.clone(),
+                }));
+
+                if !thread_safe_handle.request_interrupt(
+                    supervisor::handle_low_memory_interrupt,
+                    data_ptr_mut as *mut std::ffi::c_void,
+                ) {
+                    drop(unsafe { Box::from_raw(data_ptr_mut) });
+                }
+            }
+
+            last_pressure = pressure;
+        }
+    });
+
     worker_runtime.add_memory_limit_callback({
         let memory_limit_tx = memory_limit_tx.clone();
         move || {
@@ -307,17 +847,45 @@ pub fn create_supervisor(
 
     worker_runtime.js_runtime.add_near_heap_limit_callback({
         let memory_limit_tx = memory_limit_tx.clone();
+        let thread_safe_handle = thread_safe_handle.clone();
+        let mut allowance = LowMemoryAllowance::new(&conf);
         move |cur, _| {
             debug!("Low memory alert triggered: {}", bytes_to_display(cur as u64),);
 
-            if memory_limit_tx.send(()).is_err() {
-                error!("failed to send memory limit reached notification - isolate may already be terminating");
+            // NOTE: A GC can't be triggered from inside this callback, so we
+            // defer it to an interrupt. The supervisor is only notified if the
+            // heap is still above the limit after the GC pass, which lets
+            // transient allocation spikes recover.
+            let data_ptr_mut = Box::into_raw(Box::new(supervisor::LowMemoryInterruptData {
+                memory_limit_bytes,
+                memory_limit_tx: memory_limit_tx.clone(),
+            }));
+
+            if !thread_safe_handle.request_interrupt(
+                supervisor::handle_low_memory_interrupt,
+                data_ptr_mut as *mut std::ffi::c_void,
+            ) {
+                drop(unsafe { Box::from_raw(data_ptr_mut) });
+
+                if memory_limit_tx.send(()).is_err() {
+                    error!("failed to send memory limit reached notification - isolate may already be terminating");
+                }
+            }
+
+            let (next, is_exhausted) = allowance.extend(cur);
+
+            if is_exhausted {
+                error!(
+                    "low memory allowance exhausted: {}",
+                    bytes_to_display(cur as u64)
+                );
+
+                if memory_limit_tx.send(()).is_err() {
+                    error!("failed to send memory limit reached notification - isolate may already be terminating");
+                }
             }
 
-            // give an allowance on current limit (until the isolate is
-            // terminated) we do this so that oom won't end up killing the
-            // edge-runtime process
-            cur * (conf.low_memory_multiplier as usize)
+            next
         }
     });
 
@@ -329,9 +897,11 @@ pub fn create_supervisor(
     let (maybe_cpu_timer, maybe_cpu_alarms_rx) =
         cpu_timer_param.get_cpu_timer(supervisor_policy).unzip();
 
-    drop({
+    let supervisor_handle = {
         let _rt_guard = rt::SUPERVISOR_RT.enter();
         let maybe_cpu_timer_inner = maybe_cpu_timer.clone();
+        let thread_safe_handle = thread_safe_handle.clone();
+        let waker = waker.clone();
         let supervise_cancel_token_inner = supervise_cancel_token.clone();
 
         tokio::spawn(async move {
@@ -351,15 +921,19 @@ pub fn create_supervisor(
                 isolate_memory_usage_tx,
                 thread_safe_handle,
                 waker: waker.clone(),
+                termination_hook,
                 tokens,
             };
 
-            let (reason, cpu_usage_ms) = {
+            let (reason, cpu_usage_ms, maybe_request_id) = {
                 use supervisor::*;
                 match supervisor_policy {
                     SupervisorPolicy::PerWorker => strategy_per_worker::supervise(args).await,
                     SupervisorPolicy::PerRequest { oneshot, .. } => {
-                        strategy_per_request::supervise(args, oneshot).await
+                        let (reason, cpu_usage_ms) =
+                            strategy_per_request::supervise(args, oneshot).await;
+
+                        (reason, cpu_usage_ms, None)
                     }
                 }
             };
@@ -471,6 +1045,7 @@ pub fn create_supervisor(
                     total: v.used_heap_size + v.external_memory,
                     heap: v.used_heap_size,
                     external: v.external_memory,
+                    heap_limit: v.heap_size_limit,
                 },
                 Err(_) => {
                     if !supervise_cancel_token_inner.is_cancelled() {
@@ -481,20 +1056,84 @@ pub fn create_supervisor(
                         total: 0,
                         heap: 0,
                         external: 0,
+                        heap_limit: 0,
                     }
                 }
             };
 
+            let mut diagnostics_path = None;
+
+            if let Some((dir, capture)) = maybe_diagnostics {
+                let is_limit_reached = matches!(
+                    reason,
+                    ShutdownReason::WallClockTime
+                        | ShutdownReason::CPUTime
+                        | ShutdownReason::Memory
+                );
+
+                if let Some(diagnostics) = capture.take().filter(|_| is_limit_reached) {
+                    match diagnostics::write(
+                        &dir,
+                        key,
+                        conf.service_path.as_deref(),
+                        &reason,
+                        diagnostics,
+                    )
+                    .await
+                    {
+                        Ok(path) => diagnostics_path = Some(path.to_string_lossy().into_owned()),
+                        Err(err) => {
+                            error!("failed to write diagnostics. isolate: {:?}: {}", key, err)
+                        }
+                    }
+                }
+            }
+
             // send termination reason
             let termination_event = WorkerEvents::Shutdown(ShutdownEvent {
                 reason,
                 memory_used,
                 cpu_time_used: cpu_usage_ms as usize,
+                request_stats: conf
+                    .request_metrics
+                    .as_ref()
+                    .map(WorkerRequestMetrics::stats)
+                    .unwrap_or_default(),
+                diagnostics_path,
+                request_id: maybe_request_id.map(|it| it.0),
             });
 
             let _ = termination_event_tx.send(termination_event);
         })
-    });
+    };
+
+    // NOTE: If the supervisor panics, nothing enforces the limits of the
+    // worker anymore, so the isolate is terminated right away.
+    drop(rt::SUPERVISOR_RT.spawn(async move {
+        let Err(err) = supervisor_handle.await else {
+            return;
+        };
+
+        if !err.is_panic() {
+            return;
+        }
+
+        error!("supervisor panicked: {}", panic_message(err.into_panic()));
+
+        let data_ptr_mut = Box::into_raw(Box::new(supervisor::IsolateInterruptData {
+            should_terminate: true,
+            isolate_memory_usage_tx: None,
+        }));
+
+        if !thread_safe_handle.request_interrupt(
+            supervisor::handle_interrupt,
+            data_ptr_mut as *mut std::ffi::c_void,
+        ) {
+            drop(unsafe { Box::from_raw(data_ptr_mut) });
+        }
+
+        waker.wake();
+    }));
 
     Ok((maybe_cpu_timer, supervise_cancel_token))
 }
@@ -503,23 +1142,24 @@ pub struct CreateWorkerArgs(
     WorkerContextInitOpts,
     Option<SupervisorPolicy>,
     Option<TerminationToken>,
+    WorkerConnectionConfig,
 );
 
 impl From<WorkerContextInitOpts> for CreateWorkerArgs {
     fn from(val: WorkerContextInitOpts) -> Self {
-        CreateWorkerArgs(val, None, None)
+        CreateWorkerArgs(val, None, None, WorkerConnectionConfig::default())
     }
 }
 
 impl From<(WorkerContextInitOpts, SupervisorPolicy)> for CreateWorkerArgs {
     fn from(val: (WorkerContextInitOpts, SupervisorPolicy)) -> Self {
-        CreateWorkerArgs(val.0, Some(val.1), None)
+        CreateWorkerArgs(val.0, Some(val.1), None, WorkerConnectionConfig::default())
     }
 }
 
 impl<T: Into<Option<TerminationToken>>> From<(WorkerContextInitOpts, T)> for CreateWorkerArgs {
     fn from(val: (WorkerContextInitOpts, T)) -> Self {
-        CreateWorkerArgs(val.0, None, val.1.into())
+        CreateWorkerArgs(val.0, None, val.1.into(), WorkerConnectionConfig::default())
     }
 }
 
@@ -537,7 +1177,7 @@ impl
             Option<TerminationToken>,
         ),
     ) -> Self {
-        CreateWorkerArgs(val.0, Some(val.1), val.2)
+        CreateWorkerArgs(val.0, Some(val.1), val.2, WorkerConnectionConfig::default())
     }
 }
 
@@ -551,12 +1191,17 @@ impl CreateWorkerArgs {
         self.2 = Some(token);
         self
     }
+
+    pub fn with_connection_config(mut self, config: WorkerConnectionConfig) -> Self {
+        self.3 = config;
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct WorkerCtx {
     pub metric: MetricSource,
-    pub msg_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+    pub msg_tx: BoundedSender<WorkerRequestMsg>,
     pub exit: WorkerExit,
 }
 
@@ -565,14 +1210,25 @@ pub async fn create_worker<Opt: Into<CreateWorkerArgs>>(
     inspector: Option<Inspector>,
     maybe_request_idle_timeout: Option<u64>,
 ) -> Result<WorkerCtx, Error> {
-    let (duplex_stream_tx, duplex_stream_rx) = mpsc::unbounded_channel::<DuplexStreamEntry>();
+    let (duplex_stream_tx, duplex_stream_rx) =
+        channel::channel::<DuplexStreamEntry>(ChannelKind::DuplexStream);
     let (worker_boot_result_tx, worker_boot_result_rx) =
-        oneshot::channel::<Result<MetricSource, Error>>();
+        oneshot::channel::<Result<(MetricSource, BootInfo), Error>>();
 
-    let CreateWorkerArgs(worker_init_opts, maybe_supervisor_policy, maybe_termination_token) =
-        init_opts.into();
+    let CreateWorkerArgs(
+        worker_init_opts,
+        maybe_supervisor_policy,
+        maybe_termination_token,
+        conn_config,
+    ) = init_opts.into();
 
     let worker_kind = worker_init_opts.conf.to_worker_kind();
+    let (maybe_bridged_request_tx, maybe_bridged_request_rx) = worker_init_opts
+        .conf
+        .as_user_worker()
+        .is_some_and(|it| it.direct_request_bridge)
+        .then(|| channel::channel::<BridgedRequest>(ChannelKind::BridgedRequest))
+        .unzip();
     let exit = WorkerExit::default();
     let mut worker = Worker::new(&worker_init_opts)?;
 
@@ -581,6 +1237,12 @@ pub async fn create_worker<Opt: Into<CreateWorkerArgs>>(
     }
 
     let worker: Box<dyn WorkerHandler> = Box::new(worker);
+    let maybe_connection_limit = worker_init_opts.conf.as_user_worker().and_then(|it| {
+        it.request_metrics
+            .clone()
+            .map(|metrics| (metrics, it.max_connections))
+    });
+    let relayed_websockets = Arc::<RelayedWebSockets>::default();
 
     // Downcast to call the method in "Worker" since the implementation might be of worker
     // But at the end we are using the trait itself.
@@ -595,23 +1257,37 @@ pub async fn create_worker<Opt: Into<CreateWorkerArgs>>(
             exit.clone(),
             maybe_termination_token.clone(),
             inspector,
+            maybe_bridged_request_rx,
         );
 
         // create an async task waiting for requests for worker
-        let (worker_req_tx, mut worker_req_rx) = mpsc::unbounded_channel::<WorkerRequestMsg>();
+        let (worker_req_tx, mut worker_req_rx) =
+            channel::channel::<WorkerRequestMsg>(ChannelKind::WorkerRequest);
 
         let worker_req_handle: tokio::task::JoinHandle<Result<(), Error>> = tokio::task::spawn({
             let stream_tx = duplex_stream_tx;
+            let events_msg_tx = worker_struct_ref.events_msg_tx.clone();
+            let event_metadata = worker_struct_ref.event_metadata.clone();
+            let relayed_websockets = relayed_websockets.clone();
             async move {
                 while let Some(msg) = worker_req_rx.recv().await {
                     tokio::task::spawn({
                         let stream_tx_inner = stream_tx.clone();
+                        let bridged_request_tx_inner = maybe_bridged_request_tx.clone();
+                        let events = (events_msg_tx.clone(), event_metadata.clone());
+                        let connection_limit = maybe_connection_limit.clone();
+                        let relayed_websockets = relayed_websockets.clone();
                         async move {
                             if let Err(err) = handle_request(
                                 worker_kind,
                                 stream_tx_inner,
+                                bridged_request_tx_inner,
                                 msg,
                                 maybe_request_idle_timeout,
+                                conn_config,
+                                events,
+                                connection_limit,
+                                relayed_websockets,
                             )
                             .await
                             {
@@ -639,20 +1315,54 @@ pub async fn create_worker<Opt: Into<CreateWorkerArgs>>(
                 bail!(err)
             }
 
-            Ok(metric) => {
-                let elapsed = worker_struct_ref
-                    .worker_boot_start_time
-                    .elapsed()
-                    .as_millis();
+            Ok((metric, boot_info)) => {
+                let elapsed = worker_struct_ref.worker_boot_start_time.elapsed();
 
                 send_event_if_event_worker_available(
                     worker_struct_ref.events_msg_tx.clone(),
                     WorkerEvents::Boot(BootEvent {
-                        boot_time: elapsed as usize,
+                        boot_time: elapsed.as_millis() as usize,
+                        boot_time_ms: elapsed.as_secs_f64() * 1000.0,
+                        module_cache_hit: boot_info.module_cache_hit,
+                        module_count: boot_info.module_count,
+                        used_snapshot: boot_info.used_snapshot,
+                        service_path: worker_struct_ref.event_metadata.service_path.clone(),
                     }),
                     worker_struct_ref.event_metadata.clone(),
                 );
 
+                tokio::spawn({
+                    let exit = exit.clone();
+                    let events_msg_tx = worker_struct_ref.events_msg_tx.clone();
+                    let event_metadata = worker_struct_ref.event_metadata.clone();
+                    let code = conn_config.websocket_close.code;
+
+                    async move {
+                        exit.finished().await;
+
+                        // NOTE: The relays find out the worker is gone shortly
+                        // after it exits.
+                        let _ = tokio::time::timeout(
+                            WEBSOCKET_CLOSE_TIMEOUT,
+                            relayed_websockets.drained(),
+                        )
+                        .await;
+
+                        let count = relayed_websockets.closed();
+
+                        if count > 0 {
+                            send_event_if_event_worker_available(
+                                events_msg_tx,
+                                WorkerEvents::WebSocketsClosed(WebSocketsClosedEvent {
+                                    count,
+                                    code,
+                                }),
+                                event_metadata,
+                            );
+                        }
+                    }
+                });
+
                 Ok(WorkerCtx {
                     metric,
                     msg_tx: worker_req_tx,
@@ -666,13 +1376,14 @@ pub async fn create_worker<Opt: Into<CreateWorkerArgs>>(
 }
 
 pub async fn send_user_worker_request(
-    worker_request_msg_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
-    req: Request<Body>,
+    worker_request_msg_tx: BoundedSender<WorkerRequestMsg>,
+    mut req: Request<Body>,
     cancel: CancellationToken,
     exit: WorkerExit,
     conn_token: Option<CancellationToken>,
 ) -> Result<Response<Body>, Error> {
     let (res_tx, res_rx) = oneshot::channel::<Result<Response<Body>, hyper::Error>>();
+    let request_id = RequestId::of(&mut req);
     let msg = WorkerRequestMsg {
         req,
         res_tx,
@@ -687,12 +1398,16 @@ pub async fn send_user_worker_request(
         () = cancel.cancelled() => {
             bail!(exit
                 .error()
-                .await
                 .unwrap_or(anyhow!(WorkerError::RequestCancelledBySupervisor)))
         }
 
-        res = res_rx => res,
-    }?;
+        res = res_rx => match res {
+            Ok(res) => res,
+            // NOTE: Bridged requests don't get an error back when the worker
+            // goes away while serving them.
+            Err(err) => bail!(exit.error().unwrap_or(err.into())),
+        },
+    };
 
     match res {
         Ok(v) => {
@@ -701,11 +1416,11 @@ pub async fn send_user_worker_request(
         }
 
         Err(err) => {
-            if let Some(actual_error) = exit.error().await {
+            if let Some(actual_error) = exit.error() {
                 return Err(actual_error);
             }
 
-            Err(err.into())
+            Err(WorkerError::connection(&request_id, &err).into())
         }
     }
 }
@@ -722,18 +1437,21 @@ pub async fn create_main_worker(
     termination_token: Option<TerminationToken>,
     inspector: Option<Inspector>,
     jsx: Option<JsxImportSourceConfig>,
-) -> Result<mpsc::UnboundedSender<WorkerRequestMsg>, Error> {
+    conn_config: WorkerConnectionConfig,
+) -> Result<BoundedSender<WorkerRequestMsg>, Error> {
     let mut service_path = main_worker_path.clone();
     let mut maybe_eszip = None;
+    let mut maybe_eszip_signature = None;
     if let Some(ext) = main_worker_path.extension() {
         if ext == "eszip" {
             service_path = main_worker_path.parent().unwrap().to_path_buf();
+            maybe_eszip_signature = bundle_signature::read_detached(&main_worker_path)?;
             maybe_eszip = Some(EszipPayloadKind::VecKind(std::fs::read(main_worker_path)?));
         }
     }
 
     let ctx = create_worker(
-        (
+        CreateWorkerArgs::from((
             WorkerContextInitOpts {
                 service_path,
                 import_map_path,
@@ -741,6 +1459,7 @@ pub async fn create_main_worker(
                 events_rx: None,
                 timing: None,
                 maybe_eszip,
+                maybe_eszip_signature,
                 maybe_entrypoint,
                 maybe_decorator,
                 maybe_module_code: None,
@@ -750,7 +1469,8 @@ pub async fn create_main_worker(
                 maybe_jsx_import_source_config: jsx,
             },
             termination_token,
-        ),
+        ))
+        .with_connection_config(conn_config),
         inspector,
         None,
     )
@@ -767,14 +1487,17 @@ pub async fn create_events_worker(
     maybe_entrypoint: Option<String>,
     maybe_decorator: Option<DecoratorType>,
     termination_token: Option<TerminationToken>,
-) -> Result<(WorkerCtx, mpsc::UnboundedSender<WorkerEventWithMetadata>), Error> {
-    let (events_tx, events_rx) = mpsc::unbounded_channel::<WorkerEventWithMetadata>();
+) -> Result<(WorkerCtx, BoundedSender<WorkerEventWithMetadata>), Error> {
+    let (events_tx, events_rx) =
+        channel::channel::<WorkerEventWithMetadata>(ChannelKind::WorkerEvent);
 
     let mut service_path = events_worker_path.clone();
     let mut maybe_eszip = None;
+    let mut maybe_eszip_signature = None;
     if let Some(ext) = events_worker_path.extension() {
         if ext == "eszip" {
             service_path = events_worker_path.parent().unwrap().to_path_buf();
+            maybe_eszip_signature = bundle_signature::read_detached(&events_worker_path)?;
             maybe_eszip = Some(EszipPayloadKind::VecKind(std::fs::read(
                 events_worker_path,
             )?));
@@ -791,6 +1514,7 @@ pub async fn create_events_worker(
                 events_rx: Some(events_rx),
                 timing: None,
                 maybe_eszip,
+                maybe_eszip_signature,
                 maybe_entrypoint,
                 maybe_decorator,
                 maybe_module_code: None,
@@ -809,18 +1533,22 @@ pub async fn create_events_worker(
     Ok((ctx, events_tx))
 }
 
+/// How long the pool waits for the tasks of its user workers to be joined
+/// once it has been asked to terminate.
+const USER_WORKER_JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub async fn create_user_worker_pool(
     policy: WorkerPoolPolicy,
-    worker_event_sender: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+    worker_event_sender: Option<BoundedSender<WorkerEventWithMetadata>>,
     termination_token: Option<TerminationToken>,
     static_patterns: Vec<String>,
     inspector: Option<Inspector>,
     jsx: Option<JsxImportSourceConfig>,
     request_idle_timeout: Option<u64>,
-) -> Result<(SharedMetricSource, mpsc::UnboundedSender<UserWorkerMsgs>), Error> {
+    drain_token: Option<CancellationToken>,
+) -> Result<(SharedMetricSource, UserWorkerMsgsSender), Error> {
     let metric_src = SharedMetricSource::default();
-    let (user_worker_msgs_tx, mut user_worker_msgs_rx) =
-        mpsc::unbounded_channel::<UserWorkerMsgs>();
+    let (user_worker_msgs_tx, mut user_worker_msgs_rx) = user_worker_msgs_channel();
 
     let user_worker_msgs_tx_clone = user_worker_msgs_tx.clone();
 
@@ -836,8 +1564,15 @@ pub async fn create_user_worker_pool(
                 user_worker_msgs_tx_clone,
                 inspector,
                 request_idle_timeout,
+                drain_token.unwrap_or_default(),
             );
 
+            worker_pool.restore_services();
+
+            let mut usage_report_interval = worker_pool
+                .usage_report_interval()
+                .map(|dur| tokio::time::interval_at(tokio::time::Instant::now() + dur, dur));
+
             // Note: Keep this loop non-blocking. Spawn a task to run blocking calls.
             // Handle errors within tasks and log them - do not bubble up errors.
             loop {
@@ -850,8 +1585,11 @@ pub async fn create_user_worker_pool(
                         }
                     }, if !termination_requested => {
                         termination_requested = true;
+                        worker_pool.persist_state();
 
                         if worker_pool.user_workers.is_empty() {
+                            worker_pool.join_exiting_workers(USER_WORKER_JOIN_TIMEOUT).await;
+
                             if let Some(token) = token {
                                 token.outbound.cancel();
                             }
@@ -860,6 +1598,18 @@ pub async fn create_user_worker_pool(
                         }
                     }
 
+                    _ = async {
+                        match usage_report_interval.as_mut() {
+                            Some(interval) => {
+                                interval.tick().await;
+                            }
+
+                            None => pending::<()>().await,
+                        }
+                    } => {
+                        worker_pool.report_usage();
+                    }
+
                     msg = user_worker_msgs_rx.recv() => {
                         match msg {
                             None => break,
@@ -881,6 +1631,59 @@ pub async fn create_user_worker_pool(
                                 worker_pool.add_user_worker(key, profile);
                             }
 
+                            Some(UserWorkerMsgs::CreateFailed(registry_key)) => {
+                                worker_pool.create_failed(&registry_key);
+                            }
+
+                            Some(UserWorkerMsgs::Reload(service_path)) => {
+                                worker_pool.reload(&service_path);
+                            }
+
+                            Some(UserWorkerMsgs::Reloaded(service_path, maybe_key)) => {
+                                worker_pool.reloaded(&service_path, maybe_key);
+                            }
+
+                            Some(UserWorkerMsgs::Restored(key, maybe_key)) => {
+                                worker_pool.restored(&key, maybe_key);
+                            }
+
+                            Some(UserWorkerMsgs::List(tx)) => {
+                                let _ = tx.send(worker_pool.list());
+                            }
+
+                            Some(UserWorkerMsgs::Terminate(key, tx)) => {
+                                let _ = tx.send(worker_pool.terminate(&key));
+                            }
+
+                            Some(UserWorkerMsgs::LookupByLabels(selector, tx)) => {
+                                let _ = tx.send(worker_pool.lookup_by_labels(&selector));
+                            }
+
+                            Some(UserWorkerMsgs::TerminateByLabels(selector, tx)) => {
+                                let _ = tx.send(worker_pool.terminate_by_labels(&selector));
+                            }
+
+                            Some(UserWorkerMsgs::Drain(tx)) => {
+                                worker_pool.drain(tx);
+                            }
+
+                            Some(UserWorkerMsgs::DrainRuntime(tx)) => {
+                                worker_pool.drain_runtime();
+                                let _ = tx.send(());
+                            }
+
+                            Some(UserWorkerMsgs::Config(tx)) => {
+                                let _ = tx.send(worker_pool.config());
+                            }
+
+                            Some(UserWorkerMsgs::Accrue(tenant, usage)) => {
+                                worker_pool.accrue(tenant, usage);
+                            }
+
+                            Some(UserWorkerMsgs::Usage(tx)) => {
+                                let _ = tx.send(worker_pool.usage());
+                            }
+
                             Some(UserWorkerMsgs::SendRequest(key, req, res_tx, conn_token)) => {
                                 worker_pool.send_request(&key, req, res_tx, conn_token);
                             }
@@ -889,10 +1692,16 @@ pub async fn create_user_worker_pool(
                                 worker_pool.idle(&key);
                             }
 
+                            Some(UserWorkerMsgs::Busy(key, is_busy)) => {
+                                worker_pool.set_busy(&key, is_busy);
+                            }
+
                             Some(UserWorkerMsgs::Shutdown(key)) => {
                                 worker_pool.shutdown(&key);
 
                                 if termination_requested && worker_pool.user_workers.is_empty() {
+                                    worker_pool.join_exiting_workers(USER_WORKER_JOIN_TIMEOUT).await;
+
                                     if let Some(token) = token {
                                         token.outbound.cancel();
                                     }
@@ -911,3 +1720,105 @@ pub async fn create_user_worker_pool(
 
     Ok((metric_src, user_worker_msgs_tx))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_to_gone_worker_is_answered_with_bad_gateway() {
+        let (duplex_stream_tx, duplex_stream_rx) =
+            channel::channel::<DuplexStreamEntry>(ChannelKind::DuplexStream);
+        let (res_tx, res_rx) = oneshot::channel();
+
+        drop(duplex_stream_rx);
+
+        let result = handle_request(
+            WorkerKind::UserWorker,
+            duplex_stream_tx,
+            None,
+            WorkerRequestMsg {
+                req: Request::new(Body::empty()),
+                res_tx,
+                conn_token: None,
+            },
+            None,
+            WorkerConnectionConfig {
+                on_failure: WorkerConnectionFailurePolicy::Retry(3),
+                ..Default::default()
+            },
+            (None, EventMetadata::default()),
+            None,
+            Arc::default(),
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(
+            res_rx.await.unwrap().unwrap().status(),
+            StatusCode::BAD_GATEWAY
+        );
+    }
+
+    #[test]
+    fn test_low_memory_allowance_is_unbounded_by_default() {
+        let mut allowance = LowMemoryAllowance::new(&UserWorkerRuntimeOpts {
+            low_memory_multiplier: 5,
+            ..Default::default()
+        });
+
+        let mut cur = 1024;
+
+        for _ in 0..10 {
+            let (next, is_exhausted) = allowance.extend(cur);
+
+            assert_eq!(next, cur * 5);
+            assert!(!is_exhausted);
+
+            cur = next;
+        }
+    }
+
+    #[test]
+    fn test_low_memory_allowance_never_grows_past_its_bound() {
+        let max_bytes = mib_to_bytes(64) as usize;
+        let mut allowance = LowMemoryAllowance::new(&UserWorkerRuntimeOpts {
+            low_memory_multiplier: 2,
+            low_memory_max_allowance_bytes: max_bytes as u64,
+            ..Default::default()
+        });
+
+        let mut cur = mib_to_bytes(16) as usize;
+        let mut exhausted = 0;
+
+        for _ in 0..10 {
+            let (next, is_exhausted) = allowance.extend(cur);
+
+            assert!(next <= max_bytes);
+
+            if is_exhausted {
+                exhausted += 1;
+            }
+
+            cur = next;
+        }
+
+        assert_eq!(cur, max_bytes);
+        assert_eq!(exhausted, 8);
+    }
+
+    #[test]
+    fn test_low_memory_allowance_gives_last_resort_headroom_once() {
+        let mut allowance = LowMemoryAllowance::new(&UserWorkerRuntimeOpts {
+            low_memory_multiplier: 2,
+            low_memory_max_extensions: 1,
+            ..Default::default()
+        });
+
+        let headroom = mib_to_bytes(LOW_MEMORY_LAST_RESORT_ALLOWANCE_MB) as usize;
+
+        assert_eq!(allowance.extend(1024), (2048, false));
+        assert_eq!(allowance.extend(2048), (2048 + headroom, true));
+        assert_eq!(allowance.extend(2048 + headroom), (2048 + headroom, true));
+    }
+}
@@ -1,31 +1,58 @@
+use crate::access_log::WORKER_KEY_HEADER;
 use crate::inspector_server::Inspector;
-use crate::rt_worker::worker_ctx::{create_worker, send_user_worker_request};
+use crate::rt_worker::worker_ctx::{
+    create_worker, send_user_worker_request, CreateWorkerArgs, WorkerConnectionConfig,
+};
 use crate::server::ServerFlags;
+use crate::service_storage;
+use crate::utils::send_event_if_event_worker_available;
 use anyhow::{anyhow, bail, Context, Error};
+use deno_core::serde_json;
 use enum_as_inner::EnumAsInner;
-use event_worker::events::WorkerEventWithMetadata;
-use http::Request;
+use event_worker::events::{
+    EventMetadata, ReloadedEvent, ResponseBodyLimitExceededEvent, WorkerEventWithMetadata,
+    WorkerEvents, WorkerUsage,
+};
+use futures_util::TryStreamExt;
+use http::header::HeaderValue;
+use http::{Request, Response};
+use hyper::body::HttpBody;
 use hyper::Body;
-use log::error;
+use log::{error, info, warn};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use sb_core::channel::BoundedSender;
+use sb_core::conn_sync::ConnectionInfo;
 use sb_core::util::sync::AtomicFlag;
-use sb_core::SharedMetricSource;
+use sb_core::{signing, SharedMetricSource, WorkerRequestMetrics};
 use sb_workers::context::{
-    CreateUserWorkerResult, SendRequestResult, Timing, TimingStatus, UserWorkerMsgs,
-    UserWorkerProfile, WorkerContextInitOpts, WorkerRuntimeOpts,
+    CreateUserWorkerResult, DiagnosticsOptions, RequestId, RequestStart, SendRequestResult,
+    TenantUsage, Timing, TimingStatus, UserWorkerInfo, UserWorkerMsgs, UserWorkerMsgsSender,
+    UserWorkerPoolConfig, UserWorkerProfile, WorkerContextInitOpts, WorkerExit, WorkerRuntimeOpts,
 };
 use sb_workers::errors::WorkerError;
-use std::collections::{HashMap, HashSet};
+use sb_workers::UserWorkerCreateOptions;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::convert::Infallible;
+use std::net::SocketAddr;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc::UnboundedSender;
-use tokio::sync::oneshot::Sender;
+use tokio::sync::oneshot::{self, Sender};
 use tokio::sync::{mpsc, Notify, OwnedSemaphorePermit, Semaphore, TryAcquireError};
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+use super::accounting::Accounting;
+use super::cluster::{self, Cluster, ClusterConfig};
+use super::cors;
+use super::jwt::JwtVerifier;
+use super::middleware::{Middleware, MiddlewareChain, MiddlewareContext};
+use super::pool_state::{PersistedService, PoolState, RestoreMode};
+use super::request_body;
+use super::response_body;
+use super::scheduler::{Scheduler, SchedulingPolicy, Ticket, TENANT_LABEL};
 use super::worker_ctx::TerminationToken;
 
 #[derive(Debug, Clone, Copy, EnumAsInner)]
@@ -61,13 +88,39 @@ impl SupervisorPolicy {
     pub fn is_oneshot(&self) -> bool {
         matches!(self, Self::PerRequest { oneshot: true })
     }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PerWorker => "per_worker",
+            Self::PerRequest { oneshot: false } => "per_request",
+            Self::PerRequest { oneshot: true } => "oneshot",
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct WorkerPoolPolicy {
     supervisor_policy: SupervisorPolicy,
     max_parallelism: usize,
+    max_live_workers: Option<usize>,
+    server_timing: bool,
+    direct_request_bridge: bool,
+    watch: bool,
+    max_concurrent_boots: Option<usize>,
+    boot_queue_size: Option<usize>,
+    boot_queue_timeout_ms: u64,
+    max_concurrent_requests: Option<usize>,
+    request_queue_size: Option<usize>,
+    request_queue_timeout_ms: u64,
+    scheduling_policy: SchedulingPolicy,
     request_wait_timeout_ms: u64,
+    usage_report_interval_ms: Option<u64>,
+    restore_mode: Option<RestoreMode>,
+    middlewares: MiddlewareChain,
+    jwt_verifier: Option<Arc<JwtVerifier>>,
+    cluster: Option<ClusterConfig>,
+    diagnostics: Option<DiagnosticsOptions>,
+    worker_connection: WorkerConnectionConfig,
 }
 
 impl Default for WorkerPoolPolicy {
@@ -80,7 +133,25 @@ impl Default for WorkerPoolPolicy {
         Self {
             supervisor_policy: SupervisorPolicy::default(),
             max_parallelism: available_parallelism,
+            max_live_workers: None,
+            server_timing: false,
+            direct_request_bridge: false,
+            watch: false,
+            max_concurrent_boots: None,
+            boot_queue_size: None,
+            boot_queue_timeout_ms: 10000,
+            max_concurrent_requests: None,
+            request_queue_size: None,
+            request_queue_timeout_ms: 10000,
+            scheduling_policy: SchedulingPolicy::default(),
             request_wait_timeout_ms: 10000,
+            usage_report_interval_ms: None,
+            restore_mode: None,
+            middlewares: MiddlewareChain::default(),
+            jwt_verifier: None,
+            cluster: None,
+            diagnostics: None,
+            worker_connection: WorkerConnectionConfig::default(),
         }
     }
 }
@@ -92,14 +163,117 @@ impl WorkerPoolPolicy {
         server_flags: ServerFlags,
     ) -> Self {
         let default = Self::default();
+        let available_parallelism = std::thread::available_parallelism()
+            .ok()
+            .map(|it| it.get())
+            .unwrap_or(1);
 
         Self {
             supervisor_policy: supervisor.into().unwrap_or(default.supervisor_policy),
             max_parallelism: max_parallelism.into().unwrap_or(default.max_parallelism),
+            max_live_workers: server_flags
+                .max_live_workers_per_core
+                .map(|it| it.saturating_mul(available_parallelism))
+                .or(default.max_live_workers),
+            server_timing: server_flags.server_timing,
+            direct_request_bridge: server_flags.direct_request_bridge,
+            watch: server_flags.watch,
+            max_concurrent_boots: server_flags
+                .max_concurrent_boots
+                .or(default.max_concurrent_boots),
+            boot_queue_size: server_flags.boot_queue_size.or(default.boot_queue_size),
+            boot_queue_timeout_ms: server_flags
+                .boot_queue_timeout_ms
+                .unwrap_or(default.boot_queue_timeout_ms),
+            max_concurrent_requests: server_flags
+                .max_concurrent_requests
+                .or(default.max_concurrent_requests),
+            request_queue_size: server_flags
+                .request_queue_size
+                .or(default.request_queue_size),
+            request_queue_timeout_ms: server_flags
+                .request_queue_timeout_ms
+                .unwrap_or(default.request_queue_timeout_ms),
+            scheduling_policy: server_flags
+                .scheduling_policy
+                .unwrap_or(default.scheduling_policy),
             request_wait_timeout_ms: server_flags
                 .request_wait_timeout_ms
                 .unwrap_or(default.request_wait_timeout_ms),
+            usage_report_interval_ms: server_flags
+                .usage_report_interval_ms
+                .or(default.usage_report_interval_ms),
+            restore_mode: server_flags.restore_services.or(default.restore_mode),
+            middlewares: default.middlewares,
+            jwt_verifier: default.jwt_verifier,
+            cluster: default.cluster,
+            diagnostics: default.diagnostics,
+            worker_connection: server_flags.worker_connection,
+        }
+    }
+
+    /// Appends a middleware to the chain every request to a user worker goes
+    /// through. See [`Middleware`].
+    pub fn with_middleware(mut self, middleware: impl Middleware) -> Self {
+        self.middlewares.push(middleware);
+        self
+    }
+
+    fn scheduler(&self) -> Scheduler {
+        let mut scheduler = Scheduler::new(self.scheduling_policy);
+
+        if let Some(count) = self.max_concurrent_boots {
+            scheduler = scheduler.with_boot_limit(
+                count,
+                self.boot_queue_size,
+                Duration::from_millis(self.boot_queue_timeout_ms),
+            );
         }
+
+        if let Some(count) = self.max_concurrent_requests {
+            scheduler = scheduler.with_request_limit(
+                count,
+                self.request_queue_size,
+                Duration::from_millis(self.request_queue_timeout_ms),
+            );
+        }
+
+        scheduler
+    }
+
+    /// Makes requests to user workers carry a valid JWT, unless the worker
+    /// opted out with `verify_jwt`. Invalid ones are rejected before
+    /// reaching the worker.
+    pub fn with_jwt_verifier(mut self, verifier: JwtVerifier) -> Self {
+        self.jwt_verifier = Some(Arc::new(verifier));
+        self
+    }
+
+    /// Forwards the requests for services that aren't booted here to the
+    /// peers that have them booted. See [`Cluster`].
+    pub fn with_cluster(mut self, config: ClusterConfig) -> Self {
+        self.cluster = Some(config);
+        self
+    }
+
+    /// Writes a diagnostic bundle for each user worker the supervisor
+    /// terminates for hitting a limit, and points the shutdown event of the
+    /// worker to it.
+    pub fn with_diagnostics(mut self, options: DiagnosticsOptions) -> Self {
+        self.diagnostics = Some(options);
+        self
+    }
+
+    pub fn cluster(&self) -> Option<&ClusterConfig> {
+        self.cluster.as_ref()
+    }
+
+    /// The same policy for a pool that neither restores the persisted
+    /// services nor persists its own, so that it leaves the state of the
+    /// pool serving traffic alone.
+    pub(crate) fn without_restore(mut self) -> Self {
+        self.restore_mode = None;
+        self
     }
 }
 
@@ -199,35 +373,146 @@ impl ActiveWorkerRegistry {
 
 // every new worker gets a new UUID (can reuse execution_id)
 // user_workers - maintain a hashmap of (uuid - workerProfile (include service path))
-// active_workers - hashmap of (service_path - uuid)
+// active_workers - hashmap of (service_path and labels - uuid)
 // retire removed entry for uuid from active
 // shutdown removes uuid from both active and user_workers
 // create_worker returns true if an active_worker is available for service_path (force create
 // retires current one adds new one)
 // send_request is called with UUID
+
+/// How long the files of a service must stay unchanged before a replacement
+/// worker is booted for it.
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Watches the files of a service in the watch mode and keeps what is needed
+/// to boot a replacement worker for it.
+pub struct ServiceWatcher {
+    _watcher: RecommendedWatcher,
+    /// What the workers of the service were last created with, by registry
+    /// key, since workers with different labels are kept apart.
+    templates: HashMap<String, WorkerContextInitOpts>,
+    /// Replacement workers still booting for the current reload.
+    pending_reloads: usize,
+    reload_again: bool,
+}
+
+/// A request sent to a service that is not booted yet.
+pub type PendingRequest = (
+    Request<Body>,
+    Sender<Result<SendRequestResult, Error>>,
+    Option<CancellationToken>,
+);
+
+/// How many of the workers that left the pool without exiting normally are
+/// still reported when listing workers.
+const MAX_EXITED_WORKERS: usize = 64;
+
+/// Workers that have left the pool, kept until they turn out to have exited
+/// normally, so that a panic can still be reported after the fact.
+#[derive(Default)]
+pub struct ExitedWorkers(VecDeque<(UserWorkerInfo, WorkerExit)>);
+
+impl ExitedWorkers {
+    fn push(&mut self, info: UserWorkerInfo, exit: WorkerExit) {
+        self.0
+            .retain(|(_, exit)| !exit.is_finished() || !exit.status().is_normal());
+
+        if self.0.len() >= MAX_EXITED_WORKERS {
+            self.0.pop_front();
+        }
+
+        self.0.push_back((info, exit));
+    }
+
+    /// Returns the workers that are known to have exited abnormally.
+    fn abnormal(&self) -> impl Iterator<Item = UserWorkerInfo> + '_ {
+        self.0.iter().filter_map(|(info, exit)| {
+            let status = exit.status();
+
+            (!status.is_normal()).then(|| UserWorkerInfo {
+                exit_status: Some(status),
+                ..info.clone()
+            })
+        })
+    }
+}
+
+/// A service booted on a peer, which the requests sent to its key are
+/// forwarded to.
+pub struct RemoteWorker {
+    pub peer: SocketAddr,
+    /// What the service is booted with here once the peer no longer has it.
+    pub service: PersistedService,
+}
+
 pub struct WorkerPool {
     pub policy: WorkerPoolPolicy,
     pub metric_src: SharedMetricSource,
     pub user_workers: HashMap<Uuid, UserWorkerProfile>,
     pub active_workers: HashMap<String, ActiveWorkerRegistry>,
-    pub worker_pool_msgs_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    pub worker_pool_msgs_tx: UserWorkerMsgsSender,
     pub maybe_inspector: Option<Inspector>,
     pub maybe_request_idle_timeout: Option<u64>,
 
+    /// Exit handles of the workers that were shut down but whose tasks may
+    /// not have been joined yet.
+    pub exiting_workers: HashMap<Uuid, WorkerExit>,
+
+    /// Workers that have left the pool, for reporting how they exited.
+    pub exited_workers: ExitedWorkers,
+
+    /// Bounds the number of user workers that can be alive across all
+    /// services at once.
+    pub live_workers_sem: Option<Arc<Semaphore>>,
+
+    /// Bounds the number of user workers booting and of requests dispatched
+    /// to them at once.
+    pub scheduler: Scheduler,
+
+    /// Cancelled once the runtime starts draining. No new workers are booted
+    /// after that.
+    pub drain_token: CancellationToken,
+
+    /// Services watched for changes when the watch mode is enabled.
+    pub watchers: HashMap<String, ServiceWatcher>,
+
+    /// Resources used by the workers that have exited, per tenant.
+    pub accounting: Accounting,
+
+    /// Services read from the pool state that wait for a request before
+    /// being booted again, by the key of their worker before the restart.
+    pub dormant_services: HashMap<Uuid, PersistedService>,
+
+    /// Services read from the pool state that are booting again, along with
+    /// the requests sent to them in the meantime.
+    pub restoring_services: HashMap<Uuid, (PersistedService, Vec<PendingRequest>)>,
+
+    /// The peers of the runtime, when it runs as part of a cluster.
+    pub cluster: Option<Cluster>,
+
+    /// Services booted on a peer, by the key handed out for them here.
+    pub remote_workers: HashMap<Uuid, RemoteWorker>,
+
     // TODO: refactor this out of worker pool
-    pub worker_event_sender: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+    pub worker_event_sender: Option<BoundedSender<WorkerEventWithMetadata>>,
 }
 
 impl WorkerPool {
     pub(crate) fn new(
         policy: WorkerPoolPolicy,
         metric_src: SharedMetricSource,
-        worker_event_sender: Option<UnboundedSender<WorkerEventWithMetadata>>,
-        worker_pool_msgs_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+        worker_event_sender: Option<BoundedSender<WorkerEventWithMetadata>>,
+        worker_pool_msgs_tx: UserWorkerMsgsSender,
         inspector: Option<Inspector>,
         request_idle_timeout: Option<u64>,
+        drain_token: CancellationToken,
     ) -> Self {
         Self {
+            scheduler: policy.scheduler(),
+            cluster: policy.cluster.clone().map(Cluster::start),
+            live_workers_sem: policy
+                .max_live_workers
+                .map(|it| Arc::new(Semaphore::new(it))),
             policy,
             metric_src,
             worker_event_sender,
@@ -235,7 +520,15 @@ impl WorkerPool {
             active_workers: HashMap::new(),
             maybe_inspector: inspector,
             maybe_request_idle_timeout: request_idle_timeout,
+            exiting_workers: HashMap::new(),
+            exited_workers: ExitedWorkers::default(),
             worker_pool_msgs_tx,
+            drain_token,
+            watchers: HashMap::new(),
+            accounting: Accounting::default(),
+            dormant_services: HashMap::new(),
+            restoring_services: HashMap::new(),
+            remote_workers: HashMap::new(),
         }
     }
 
@@ -260,7 +553,18 @@ impl WorkerPool {
             .as_user_worker()
             .map_or(false, |it| !is_oneshot_policy && it.force_create);
 
-        if let Some(ref active_worker_uuid) = self.maybe_active_worker(&service_path, force_create)
+        let registry_key = worker_options
+            .conf
+            .as_user_worker()
+            .map_or(service_path.clone(), |it| {
+                registry_key(&service_path, &it.labels)
+            });
+
+        if self.policy.watch && !service_storage::is_remote(&service_path) {
+            self.watch_service(&service_path, &registry_key, &worker_options);
+        }
+
+        if let Some(ref active_worker_uuid) = self.maybe_active_worker(&registry_key, force_create)
         {
             if tx
                 .send(Ok(CreateUserWorkerResult {
@@ -273,6 +577,33 @@ impl WorkerPool {
             return;
         }
 
+        if let Some(remote_key) = self.maybe_remote_worker(&service_path, &worker_options) {
+            if tx
+                .send(Ok(CreateUserWorkerResult { key: remote_key }))
+                .is_err()
+            {
+                error!("main worker receiver dropped")
+            }
+            return;
+        }
+
+        if self.drain_token.is_cancelled() {
+            if tx.send(Err(anyhow!(WorkerError::RuntimeDraining))).is_err() {
+                error!("main worker receiver dropped")
+            }
+            return;
+        }
+
+        if !force_create && self.is_saturated_with_throttled_workers(&registry_key) {
+            if tx
+                .send(Err(anyhow!(WorkerError::WorkersThrottled)))
+                .is_err()
+            {
+                error!("main worker receiver dropped")
+            }
+            return;
+        }
+
         enum FlowAfterFence {
             Stop,
             Resend(Sender<Result<CreateUserWorkerResult, Error>>),
@@ -285,7 +616,7 @@ impl WorkerPool {
         let wait_fence_fut = {
             let registry = self
                 .active_workers
-                .entry(service_path.clone())
+                .entry(registry_key.clone())
                 .or_insert_with(|| ActiveWorkerRegistry::new(self.policy.max_parallelism));
 
             let sem = registry.sem.clone();
@@ -343,6 +674,13 @@ impl WorkerPool {
         let worker_pool_msgs_tx = self.worker_pool_msgs_tx.clone();
         let events_msg_tx = self.worker_event_sender.clone();
         let supervisor_policy = self.policy.supervisor_policy;
+        let direct_request_bridge = self.policy.direct_request_bridge;
+        let diagnostics = self.policy.diagnostics.clone();
+        let worker_connection = self.policy.worker_connection;
+        let live_workers_sem = self.live_workers_sem.clone();
+        let scheduler = self.scheduler.clone();
+        let wait_deadline =
+            Instant::now() + Duration::from_millis(self.policy.request_wait_timeout_ms);
 
         drop(tokio::spawn(async move {
             let (permit, tx) = match wait_fence_fut.await {
@@ -355,6 +693,7 @@ impl WorkerPool {
                         env_vars,
                         conf,
                         maybe_eszip,
+                        maybe_eszip_signature,
                         maybe_module_code,
                         maybe_entrypoint,
                         maybe_decorator,
@@ -362,28 +701,32 @@ impl WorkerPool {
                         ..
                     } = worker_options;
 
-                    if worker_pool_msgs_tx
-                        .send(UserWorkerMsgs::Create(
-                            WorkerContextInitOpts {
-                                service_path,
-                                no_module_cache,
-                                import_map_path,
-                                env_vars,
-                                events_rx: None,
-                                timing: None,
-                                conf,
-                                maybe_eszip,
-                                maybe_module_code,
-                                maybe_entrypoint,
-                                maybe_decorator,
-                                static_patterns: vec![],
-                                maybe_jsx_import_source_config,
-                            },
-                            tx,
-                        ))
-                        .is_err()
-                    {
-                        error!("main worker receiver dropped");
+                    if let Err(err) = worker_pool_msgs_tx.send(UserWorkerMsgs::Create(
+                        WorkerContextInitOpts {
+                            service_path,
+                            no_module_cache,
+                            import_map_path,
+                            env_vars,
+                            events_rx: None,
+                            timing: None,
+                            conf,
+                            maybe_eszip,
+                            maybe_eszip_signature,
+                            maybe_module_code,
+                            maybe_entrypoint,
+                            maybe_decorator,
+                            static_patterns: vec![],
+                            maybe_jsx_import_source_config,
+                        },
+                        tx,
+                    )) {
+                        let reason = anyhow!("{}", err);
+
+                        if let UserWorkerMsgs::Create(_, tx) = err.into_inner() {
+                            if tx.send(Err(reason)).is_err() {
+                                error!("main worker receiver dropped");
+                            }
+                        }
                     }
 
                     return;
@@ -392,18 +735,43 @@ impl WorkerPool {
                 FlowAfterFence::Create(permit, tx) => (permit, tx),
             };
 
+            // NOTE: Once the host is saturated with live workers, the creation
+            // is queued until one of them goes away.
+            let live_permit = match live_workers_sem {
+                Some(sem) => {
+                    match tokio::time::timeout_at(wait_deadline, sem.acquire_owned()).await {
+                        Ok(Ok(permit)) => Some(permit),
+                        _ => {
+                            if tx
+                                .send(Err(anyhow!(WorkerError::LiveWorkersSaturated)))
+                                .is_err()
+                            {
+                                error!("main worker receiver dropped");
+                            }
+
+                            return;
+                        }
+                    }
+                }
+
+                None => None,
+            };
+
             let Ok(mut user_worker_rt_opts) = worker_options.conf.into_user_worker() else {
                 return;
             };
 
-            let uuid = uuid::Uuid::new_v4();
+            let uuid = user_worker_rt_opts.key.unwrap_or_else(Uuid::new_v4);
             let cancel = CancellationToken::new();
+            let termination_token = termination_token.unwrap_or_default();
+            let metrics = WorkerRequestMetrics::default();
             let (req_start_timing_tx, req_start_timing_rx) =
-                mpsc::unbounded_channel::<Arc<Notify>>();
+                mpsc::unbounded_channel::<RequestStart>();
 
             let status = TimingStatus {
                 demand: Arc::new(AtomicUsize::new(0)),
                 is_retired: Arc::new(AtomicFlag::default()),
+                is_throttled: Arc::new(AtomicBool::new(false)),
             };
 
             let (req_end_timing_tx, req_end_timing_rx) = mpsc::unbounded_channel::<()>();
@@ -414,6 +782,24 @@ impl WorkerPool {
             user_worker_rt_opts.pool_msg_tx = Some(worker_pool_msgs_tx.clone());
             user_worker_rt_opts.events_msg_tx = events_msg_tx;
             user_worker_rt_opts.cancel = Some(cancel.clone());
+            user_worker_rt_opts.request_metrics = Some(metrics.clone());
+            user_worker_rt_opts.direct_request_bridge |= direct_request_bridge;
+            user_worker_rt_opts.diagnostics = diagnostics;
+
+            let labels = user_worker_rt_opts.labels.clone();
+            let verify_jwt = user_worker_rt_opts.verify_jwt;
+            let jwt_audience = user_worker_rt_opts.jwt_audience.clone();
+            let cors = user_worker_rt_opts.cors.clone().map(Arc::new);
+            let ip_access_list = user_worker_rt_opts
+                .ip_access_list
+                .clone()
+                .filter(|it| !it.is_empty())
+                .map(Arc::new);
+            let request_body = user_worker_rt_opts.request_body.clone().map(Arc::new);
+            let response_body_max_bytes = user_worker_rt_opts.response_body_max_bytes;
+            let priority = user_worker_rt_opts.priority;
+            let persisted_options = user_worker_rt_opts.persisted_options.clone();
+            let ticket = Ticket::new(&labels, &service_path, priority);
 
             worker_options.timing = Some(Timing {
                 status: status.clone(),
@@ -422,26 +808,67 @@ impl WorkerPool {
 
             worker_options.conf = WorkerRuntimeOpts::UserWorker(user_worker_rt_opts);
 
+            let _boot_permit = match scheduler.acquire_boot(ticket).await {
+                Ok(permit) => permit,
+                Err(err) => {
+                    if tx.send(Err(anyhow!(err))).is_err() {
+                        error!("main worker receiver dropped");
+                    }
+
+                    drop(permit);
+                    let _ = worker_pool_msgs_tx
+                        .send_reliable(UserWorkerMsgs::CreateFailed(registry_key));
+
+                    return;
+                }
+            };
+
+            let boot_started_at = Instant::now();
+
             match create_worker(
-                (worker_options, supervisor_policy, termination_token.clone()),
+                CreateWorkerArgs::from((
+                    worker_options,
+                    supervisor_policy,
+                    Some(termination_token.clone()),
+                ))
+                .with_connection_config(worker_connection),
                 inspector,
                 request_idle_timeout,
             )
             .await
             {
                 Ok(ctx) => {
+                    let boot_duration = boot_started_at.elapsed();
+
+                    metrics.record_boot(boot_duration);
+
                     let profile = UserWorkerProfile {
                         worker_request_msg_tx: ctx.msg_tx,
                         timing_tx_pair: (req_start_timing_tx, req_end_timing_tx),
                         service_path,
                         permit: permit.map(Arc::new),
+                        live_permit: live_permit.map(Arc::new),
                         status: status.clone(),
                         exit: ctx.exit,
+                        metrics,
+                        boot_duration,
+                        termination: termination_token.inbound.clone(),
+                        labels,
+                        verify_jwt,
+                        jwt_audience,
+                        cors,
+                        ip_access_list,
+                        request_body,
+                        spilled_body_bytes: Arc::default(),
+                        response_body_max_bytes,
+                        priority,
+                        persisted_options,
+                        is_busy: false,
                         cancel,
                     };
 
                     if worker_pool_msgs_tx
-                        .send(UserWorkerMsgs::Created(uuid, profile))
+                        .send_reliable(UserWorkerMsgs::Created(uuid, profile))
                         .is_err()
                     {
                         error!("user worker msgs receiver dropped")
@@ -458,53 +885,502 @@ impl WorkerPool {
                     } else {
                         error!("An error has occured")
                     }
+
+                    // NOTE: The permit must be released before the pool wakes
+                    // up the requests waiting on it.
+                    drop(permit);
+
+                    if worker_pool_msgs_tx
+                        .send_reliable(UserWorkerMsgs::CreateFailed(registry_key))
+                        .is_err()
+                    {
+                        error!("user worker msgs receiver dropped")
+                    }
+                }
+            }
+        }));
+    }
+
+    /// Wakes up the requests that were waiting on the permit held by a worker
+    /// that failed to boot, so they can retry the creation right away.
+    pub fn create_failed(&mut self, registry_key: &String) {
+        if let Some(registry) = self.active_workers.get(registry_key) {
+            let (notify_tx, _) = registry.notify_pair.clone();
+
+            for _ in 0..notify_tx.receiver_count() {
+                let _ = notify_tx.send(None);
+            }
+        }
+    }
+
+    fn watch_service(
+        &mut self,
+        service_path: &str,
+        registry_key: &str,
+        worker_options: &WorkerContextInitOpts,
+    ) {
+        let Some(template) = reload_template(worker_options) else {
+            return;
+        };
+
+        if let Some(watcher) = self.watchers.get_mut(service_path) {
+            let _ = watcher.templates.insert(registry_key.to_string(), template);
+            return;
+        }
+
+        let (change_tx, change_rx) = mpsc::unbounded_channel();
+        let watched_path = service_path.to_string();
+        let maybe_watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+                Ok(ev) if ev.kind.is_create() || ev.kind.is_modify() || ev.kind.is_remove() => {
+                    let _ = change_tx.send(());
+                }
+
+                Ok(_) => {}
+                Err(err) => warn!("failed to watch {}: {}", watched_path, err),
+            })
+            .and_then(|mut it| {
+                it.watch(&worker_options.service_path, RecursiveMode::Recursive)
+                    .map(|_| it)
+            });
+
+        match maybe_watcher {
+            Ok(watcher) => {
+                let worker_pool_msgs_tx = self.worker_pool_msgs_tx.clone();
+                let service_path = service_path.to_string();
+
+                // NOTE: Ends once the watcher is dropped along with the
+                // sender it owns.
+                drop(tokio::spawn(debounce(change_rx, RELOAD_DEBOUNCE, {
+                    let service_path = service_path.clone();
+                    move || {
+                        let _ =
+                            worker_pool_msgs_tx.send(UserWorkerMsgs::Reload(service_path.clone()));
+                    }
+                })));
+
+                self.watchers.insert(
+                    service_path,
+                    ServiceWatcher {
+                        _watcher: watcher,
+                        templates: HashMap::from([(registry_key.to_string(), template)]),
+                        pending_reloads: 0,
+                        reload_again: false,
+                    },
+                );
+            }
+
+            Err(err) => warn!("failed to watch {}: {}", service_path, err),
+        }
+    }
+
+    /// Stops watching a service once none of its workers are left. The next
+    /// creation watches it again.
+    fn unwatch_if_unused(&mut self, service_path: &str) {
+        if self
+            .watchers
+            .get(service_path)
+            .is_some_and(|it| it.pending_reloads == 0)
+            && !self
+                .user_workers
+                .values()
+                .any(|it| it.service_path == service_path)
+        {
+            let _ = self.watchers.remove(service_path);
+        }
+    }
+
+    /// Boots a replacement worker for each registry key of the service after
+    /// its files changed. The workers currently serving it are retired once
+    /// their replacement is up.
+    pub fn reload(&mut self, service_path: &str) {
+        let Some(watcher) = self.watchers.get(service_path) else {
+            return;
+        };
+
+        if watcher.pending_reloads > 0 {
+            if let Some(watcher) = self.watchers.get_mut(service_path) {
+                watcher.reload_again = true;
+            }
+
+            return;
+        }
+
+        // NOTE: Without a running worker, the next request boots one from
+        // the fresh files anyway.
+        let replacements = watcher
+            .templates
+            .iter()
+            .filter(|(key, _)| {
+                self.user_workers.values().any(|it| {
+                    it.service_path == service_path
+                        && registry_key(&it.service_path, &it.labels) == **key
+                        && !it.status.is_retired.is_raised()
+                })
+            })
+            .filter_map(|(_, template)| reload_template(template))
+            .collect::<Vec<_>>();
+
+        if replacements.is_empty() {
+            return;
+        }
+
+        if let Some(watcher) = self.watchers.get_mut(service_path) {
+            watcher.pending_reloads = replacements.len();
+        }
+
+        for mut worker_options in replacements {
+            if let Some(conf) = worker_options.conf.as_user_worker_mut() {
+                conf.force_create = true;
+            }
+
+            let worker_pool_msgs_tx = self.worker_pool_msgs_tx.clone();
+            let service_path = service_path.to_string();
+
+            drop(tokio::spawn(async move {
+                let (tx, rx) = oneshot::channel();
+
+                if worker_pool_msgs_tx
+                    .send(UserWorkerMsgs::Create(worker_options, tx))
+                    .is_err()
+                {
+                    // NOTE: Lets the watcher pick up the next change.
+                    let _ = worker_pool_msgs_tx
+                        .send_reliable(UserWorkerMsgs::Reloaded(service_path, None));
+                    return;
+                }
+
+                let maybe_key = match rx.await {
+                    Ok(Ok(res)) => Some(res.key),
+                    Ok(Err(err)) => {
+                        error!("failed to reload {}: {}", service_path, err);
+                        None
+                    }
+
+                    Err(_) => None,
+                };
+
+                let _ = worker_pool_msgs_tx
+                    .send_reliable(UserWorkerMsgs::Reloaded(service_path, maybe_key));
+            }));
+        }
+    }
+
+    pub fn reloaded(&mut self, service_path: &str, maybe_key: Option<Uuid>) {
+        let reload_again = match self.watchers.get_mut(service_path) {
+            Some(watcher) => {
+                watcher.pending_reloads = watcher.pending_reloads.saturating_sub(1);
+                watcher.pending_reloads == 0 && std::mem::take(&mut watcher.reload_again)
+            }
+
+            None => false,
+        };
+
+        if let Some(key) = maybe_key {
+            let replaced = self
+                .user_workers
+                .get(&key)
+                .and_then(|it| {
+                    self.active_workers
+                        .get(&registry_key(&it.service_path, &it.labels))
+                })
+                .map(|it| {
+                    it.workers
+                        .iter()
+                        .map(|it| it.0)
+                        .filter(|it| *it != key)
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+
+            for it in replaced.iter() {
+                self.retire(it);
+            }
+
+            info!(
+                "reloaded {} ({} worker(s) replaced)",
+                service_path,
+                replaced.len()
+            );
+
+            if let Some(profile) = self.user_workers.get(&key) {
+                send_event_if_event_worker_available(
+                    self.worker_event_sender.clone(),
+                    WorkerEvents::Reloaded(ReloadedEvent { replaced }),
+                    EventMetadata {
+                        service_path: Some(service_path.to_string()),
+                        execution_id: Some(key),
+                        labels: profile.labels.clone(),
+                    },
+                );
+            }
+        }
+
+        if reload_again {
+            self.reload(service_path);
+        }
+    }
+
+    /// Reads the services persisted when the runtime last shut down, and
+    /// boots them again or waits for a request to do so, depending on the
+    /// restore mode.
+    pub fn restore_services(&mut self) {
+        let Some(mode) = self.policy.restore_mode else {
+            return;
+        };
+
+        for service in PoolState::load(&PoolState::path()).services {
+            match mode {
+                RestoreMode::Eager => self.restore(service, vec![]),
+                RestoreMode::Lazy => {
+                    let _ = self.dormant_services.insert(service.key, service);
                 }
             }
+        }
+    }
+
+    fn restore(&mut self, service: PersistedService, pending: Vec<PendingRequest>) {
+        let worker_options =
+            serde_json::from_value::<UserWorkerCreateOptions>(service.options.clone())
+                .map_err(Error::from)
+                .and_then(|it| it.into_worker_options(None));
+
+        let mut worker_options = match worker_options {
+            Ok(it) => it,
+            Err(err) => {
+                error!(
+                    "failed to restore service {}: {}",
+                    service.service_path, err
+                );
+
+                for (_, res_tx, _) in pending {
+                    let _ = res_tx.send(Err(anyhow!("user worker not available")));
+                }
+
+                return;
+            }
+        };
+
+        if let Some(opts) = worker_options.conf.as_user_worker_mut() {
+            opts.key = Some(service.key);
+        }
+
+        let key = service.key;
+        let service_path = service.service_path.clone();
+        let worker_pool_msgs_tx = self.worker_pool_msgs_tx.clone();
+
+        let _ = self.restoring_services.insert(key, (service, pending));
+
+        drop(tokio::spawn(async move {
+            let (tx, rx) = oneshot::channel();
+            let maybe_key = match worker_pool_msgs_tx
+                .send_reliable(UserWorkerMsgs::Create(worker_options, tx))
+            {
+                Ok(()) => match rx.await {
+                    Ok(Ok(it)) => Some(it.key),
+                    Ok(Err(err)) => {
+                        error!("failed to restore service {}: {}", service_path, err);
+                        None
+                    }
+
+                    Err(_) => None,
+                },
+
+                Err(_) => None,
+            };
+
+            let _ = worker_pool_msgs_tx.send_reliable(UserWorkerMsgs::Restored(key, maybe_key));
         }));
     }
 
+    pub fn restored(&mut self, key: &Uuid, maybe_key: Option<Uuid>) {
+        let Some((service, pending)) = self.restoring_services.remove(key) else {
+            return;
+        };
+
+        match maybe_key {
+            // NOTE: The service may have been booted under another key if the
+            // control plane created it first.
+            Some(new_key) => {
+                for (req, res_tx, conn_token) in pending {
+                    self.send_request(&new_key, req, res_tx, conn_token);
+                }
+            }
+
+            None => {
+                for (_, res_tx, _) in pending {
+                    let _ = res_tx.send(Err(anyhow!("user worker not available")));
+                }
+
+                // Kept around so that the next request tries again.
+                let _ = self.dormant_services.insert(*key, service);
+            }
+        }
+    }
+
+    /// Writes the services registered in the pool to the state file, one
+    /// worker per service, for the next start to restore them.
+    pub fn persist_state(&self) {
+        if self.policy.restore_mode.is_none() {
+            return;
+        }
+
+        let mut service_paths = HashSet::new();
+        let mut state = PoolState::default();
+
+        let live = self
+            .user_workers
+            .iter()
+            .filter(|(_, profile)| !profile.status.is_retired.is_raised())
+            .filter_map(|(key, profile)| {
+                Some(PersistedService {
+                    key: *key,
+                    service_path: profile.service_path.clone(),
+                    options: profile.persisted_options.as_deref()?.clone(),
+                })
+            });
+
+        let not_booted = self
+            .dormant_services
+            .values()
+            .chain(self.restoring_services.values().map(|(it, _)| it))
+            .cloned();
+
+        for service in live.chain(not_booted) {
+            if service_paths.insert(service.service_path.clone()) {
+                state.services.push(service);
+            }
+        }
+
+        // NOTE: Written in place since the runtime is about to exit, and the
+        // file only holds a handful of options per service.
+        if let Err(err) = state.store(&PoolState::path()) {
+            error!("failed to persist the pool state: {}", err);
+        }
+    }
+
     pub fn add_user_worker(&mut self, key: Uuid, profile: UserWorkerProfile) {
         let registry = self
             .active_workers
-            .entry(profile.service_path.clone())
+            .entry(registry_key(&profile.service_path, &profile.labels))
             .or_insert_with(|| ActiveWorkerRegistry::new(self.policy.max_parallelism));
 
         registry
             .workers
             .insert(WorkerId(key, self.policy.supervisor_policy.is_per_worker()));
 
+        self.metric_src
+            .register_user_worker_requests(key.to_string(), profile.metrics.clone());
         self.user_workers.insert(key, profile);
         self.metric_src.incl_active_user_workers();
     }
 
     pub fn send_request(
-        &self,
+        &mut self,
         key: &Uuid,
         req: Request<Body>,
         res_tx: Sender<Result<SendRequestResult, Error>>,
         conn_token: Option<CancellationToken>,
     ) {
+        if !self.user_workers.contains_key(key) {
+            if let Some((_, pending)) = self.restoring_services.get_mut(key) {
+                pending.push((req, res_tx, conn_token));
+                return;
+            }
+
+            if let Some(service) = self.dormant_services.remove(key) {
+                self.restore(service, vec![(req, res_tx, conn_token)]);
+                return;
+            }
+
+            if self.remote_workers.contains_key(key) {
+                self.send_remote_request(key, req, res_tx, conn_token);
+                return;
+            }
+        }
+
         let _: Result<(), Error> = match self.user_workers.get(key) {
             Some(worker) => {
                 let policy = self.policy.supervisor_policy;
+                let server_timing = self.policy.server_timing;
+                let worker_key = HeaderValue::from_str(&key.to_string()).unwrap();
+                let middlewares = self.policy.middlewares.clone();
+                let maybe_jwt_verifier = self
+                    .policy
+                    .jwt_verifier
+                    .clone()
+                    .filter(|_| worker.verify_jwt);
+                let jwt_audience = worker.jwt_audience.clone();
+                let maybe_cors = worker.cors.clone();
+                let maybe_ip_access_list = worker.ip_access_list.clone();
+                let maybe_request_body = worker.request_body.clone();
+                let spilled_body_bytes = worker.spilled_body_bytes.clone();
+                let response_body_max_bytes = worker.response_body_max_bytes;
+                let events_msg_tx = self.worker_event_sender.clone();
+                let event_metadata = EventMetadata {
+                    service_path: Some(worker.service_path.clone()),
+                    execution_id: Some(*key),
+                    labels: worker.labels.clone(),
+                };
+                let metric_src = self.metric_src.clone();
+                let middleware_ctx = MiddlewareContext {
+                    worker_id: *key,
+                    service_path: worker.service_path.clone(),
+                };
                 let profile = worker.clone();
                 let exit = worker.exit.clone();
                 let cancel = worker.cancel.clone();
-                let (req_start_tx, req_end_tx) = profile.timing_tx_pair.clone();
+                let metrics = worker.metrics.clone();
+                let scheduler = self.scheduler.clone();
+                let ticket = Ticket::of(worker);
+                let (req_start_tx, mut req_end_tx) = profile.timing_tx_pair.clone();
+                let mut req = req.map({
+                    let metrics = metrics.clone();
+                    |body| count_body_bytes(body, move |len| metrics.add_bytes_in(len))
+                });
+
+                let arrived_at = std::time::Instant::now();
+                let maybe_boot_duration =
+                    metrics.take_cold_request().then_some(profile.boot_duration);
+                let mut dispatch_guard = metrics.enqueue();
+                let maybe_dispatched_rx = server_timing.then(|| dispatch_guard.on_dispatched());
+
+                req.extensions_mut().insert(dispatch_guard);
+
+                let request_id = RequestId::of(&mut req);
 
                 // Create a closure to handle the request and send the response
                 let request_handler = async move {
-                    if !policy.is_per_worker() {
+                    let maybe_dispatch_permit = scheduler.acquire_request(ticket).await?;
+
+                    if policy.is_per_worker() {
+                        // NOTE: The supervisor only uses this signal to track
+                        // the CPU time spent on each request. Requests may
+                        // complete in any order, so each one gets its own end
+                        // channel.
+                        let (tx, rx) = mpsc::unbounded_channel();
+
+                        req_end_tx = tx;
+
+                        let _ = req_start_tx.send(RequestStart {
+                            end_rx: Some(rx),
+                            request_id: Some(request_id),
+                            ..Default::default()
+                        });
+                    } else {
                         if cancel.is_cancelled() {
                             bail!(exit
                                 .error()
-                                .await
                                 .unwrap_or(anyhow!(WorkerError::RequestCancelledBySupervisor)))
                         }
 
                         let fence = Arc::new(Notify::const_new());
 
-                        if let Err(ex) = req_start_tx.send(fence.clone()) {
+                        if let Err(ex) = req_start_tx.send(RequestStart {
+                            fence: fence.clone(),
+                            end_rx: None,
+                            request_id: None,
+                        }) {
                             // NOTE(Nyannyacha): The only way to be trapped in
                             // this branch is if the supervisor associated with
                             // the isolate has been terminated for some reason,
@@ -526,23 +1402,143 @@ impl WorkerPool {
                             _ = cancel.cancelled() => {
                                 bail!(exit
                                     .error()
-                                    .await
                                     .unwrap_or(anyhow!(WorkerError::RequestCancelledBySupervisor)))
                             }
                         }
                     }
 
-                    let result = send_user_worker_request(
-                        profile.worker_request_msg_tx,
-                        req,
-                        cancel,
-                        exit,
-                        conn_token,
-                    )
-                    .await;
+                    let fence_passed_at = std::time::Instant::now();
+
+                    if let Some(acl) = maybe_ip_access_list.as_deref() {
+                        // NOTE: A request that didn't come through a listener
+                        // has no address to check, so it is denied rather than
+                        // let around the list.
+                        let maybe_client_ip = req
+                            .extensions()
+                            .get::<ConnectionInfo>()
+                            .map(|it| it.remote_addr.ip());
+
+                        if !maybe_client_ip.is_some_and(|ip| acl.is_allowed(ip)) {
+                            metric_src.incl_denied_requests();
+
+                            let res = Response::builder()
+                                .status(http::StatusCode::FORBIDDEN)
+                                .header(http::header::CONTENT_TYPE, "application/json")
+                                .body(Body::from(
+                                    serde_json::json!({ "msg": "client address is not allowed" })
+                                        .to_string(),
+                                ))
+                                .unwrap();
+
+                            return Ok((res, req_end_tx, fence_passed_at, maybe_dispatch_permit));
+                        }
+                    }
+
+                    if let Some(res) = maybe_cors
+                        .as_deref()
+                        .and_then(|it| cors::preflight(it, &req))
+                    {
+                        return Ok((res, req_end_tx, fence_passed_at, maybe_dispatch_permit));
+                    }
+
+                    let maybe_origin = req.headers().get(http::header::ORIGIN).cloned();
+                    let maybe_rejected = match maybe_jwt_verifier {
+                        Some(verifier) => verifier
+                            .authorize(&mut req, jwt_audience.as_deref())
+                            .await
+                            .err(),
+
+                        None => None,
+                    };
+
+                    // NOTE: Bodies are only read ahead of dispatching for the
+                    // requests that have been authorized.
+                    let body_limit_exceeded = Arc::<AtomicFlag>::default();
+                    let maybe_rejected = match (maybe_rejected, maybe_request_body) {
+                        (None, Some(policy)) => {
+                            match request_body::apply(
+                                &policy,
+                                &spilled_body_bytes,
+                                body_limit_exceeded.clone(),
+                                req,
+                            )
+                            .await
+                            {
+                                Ok(it) => {
+                                    req = it;
+                                    None
+                                }
+
+                                Err(mut res) => {
+                                    if let Some(policy) = maybe_cors.as_deref() {
+                                        cors::apply(policy, maybe_origin.as_ref(), &mut res);
+                                    }
+
+                                    return Ok((
+                                        res,
+                                        req_end_tx,
+                                        fence_passed_at,
+                                        maybe_dispatch_permit,
+                                    ));
+                                }
+                            }
+                        }
+
+                        (maybe_rejected, _) => maybe_rejected,
+                    };
+
+                    let result = match maybe_rejected {
+                        Some(res) => Ok(res),
+                        None => {
+                            middlewares
+                                .run(&middleware_ctx, req, |mut req| {
+                                    // NOTE: Signed last, so that headers set by
+                                    // the middlewares are covered as well.
+                                    signing::sign(&mut req);
+                                    send_user_worker_request(
+                                        profile.worker_request_msg_tx,
+                                        req,
+                                        cancel,
+                                        exit,
+                                        conn_token,
+                                    )
+                                })
+                                .await
+                        }
+                    };
+
+                    let result = match result {
+                        Err(_) if body_limit_exceeded.is_raised() => {
+                            Ok(request_body::exceeded_response())
+                        }
+
+                        result => result,
+                    };
+
+                    let result = result.map(|mut res| {
+                        if response_body_max_bytes > 0 {
+                            res = response_body::limit(response_body_max_bytes, res, move || {
+                                send_event_if_event_worker_available(
+                                    events_msg_tx,
+                                    WorkerEvents::ResponseBodyLimitExceeded(
+                                        ResponseBodyLimitExceededEvent {
+                                            max_bytes: response_body_max_bytes,
+                                        },
+                                    ),
+                                    event_metadata,
+                                );
+                            });
+                        }
+
+                        if let Some(policy) = maybe_cors.as_deref() {
+                            cors::apply(policy, maybe_origin.as_ref(), &mut res);
+                        }
+
+                        res
+                    });
 
                     match result {
-                        Ok(req) => Ok((req, req_end_tx)),
+                        Ok(req) => Ok((req, req_end_tx, fence_passed_at, maybe_dispatch_permit)),
                         Err(err) => {
                             let _ = req_end_tx.send(());
                             error!("failed to send request to user worker: {}", err.to_string());
@@ -553,7 +1549,51 @@ impl WorkerPool {
 
                 // Spawn the closure as an async task
                 tokio::task::spawn(async move {
-                    if res_tx.send(request_handler.await).is_err() {
+                    let started_at = Instant::now();
+                    let result = request_handler.await.map(
+                        |(res, req_end_tx, fence_passed_at, maybe_dispatch_permit)| {
+                            let mut res = res.map({
+                                let metrics = metrics.clone();
+                                |body| {
+                                    count_body_bytes(body, move |len| {
+                                        // NOTE: The dispatch slot is held
+                                        // until the response body has been
+                                        // fully streamed.
+                                        let _permit = &maybe_dispatch_permit;
+                                        metrics.add_bytes_out(len)
+                                    })
+                                }
+                            });
+
+                            if let Some(dispatched_at) =
+                                maybe_dispatched_rx.and_then(|mut it| it.try_recv().ok())
+                            {
+                                let value = server_timing_value(
+                                    maybe_boot_duration,
+                                    fence_passed_at - arrived_at,
+                                    dispatched_at.saturating_duration_since(fence_passed_at),
+                                    dispatched_at.elapsed(),
+                                );
+
+                                if let Ok(value) = HeaderValue::from_str(&value) {
+                                    res.headers_mut().append("server-timing", value);
+                                }
+                            }
+
+                            res.headers_mut().insert(WORKER_KEY_HEADER, worker_key);
+
+                            (res, req_end_tx)
+                        },
+                    );
+
+                    metrics.record_request(
+                        started_at.elapsed(),
+                        result
+                            .as_ref()
+                            .map_or(true, |(res, _)| res.status().is_server_error()),
+                    );
+
+                    if res_tx.send(result).is_err() {
                         error!("main worker receiver dropped")
                     }
                 });
@@ -574,23 +1614,130 @@ impl WorkerPool {
         };
     }
 
-    pub fn idle(&mut self, key: &Uuid) {
-        if let Some(registry) = self
-            .user_workers
-            .get_mut(key)
-            .and_then(|it| self.active_workers.get_mut(&it.service_path))
+    /// Hands out the key of a service booted on a peer, if there is one and
+    /// the service could be booted here from its options later on.
+    fn maybe_remote_worker(
+        &mut self,
+        service_path: &str,
+        worker_options: &WorkerContextInitOpts,
+    ) -> Option<Uuid> {
+        let opts = worker_options.conf.as_user_worker()?;
+
+        // NOTE: Workers restored from the pool state carry their key, and are
+        // always booted here.
+        if opts.force_create || opts.key.is_some() {
+            return None;
+        }
+
+        let options = opts.persisted_options.as_deref()?;
+        let peer = self.cluster.as_ref()?.warm_peer(service_path)?;
+
+        if let Some(key) = self
+            .remote_workers
+            .iter()
+            .find(|(_, it)| it.peer == peer && it.service.service_path == service_path)
+            .map(|(key, _)| *key)
         {
+            return Some(key);
+        }
+
+        let key = Uuid::new_v4();
+        let _ = self.remote_workers.insert(
+            key,
+            RemoteWorker {
+                peer,
+                service: PersistedService {
+                    key,
+                    service_path: service_path.to_string(),
+                    options: options.clone(),
+                },
+            },
+        );
+
+        Some(key)
+    }
+
+    fn send_remote_request(
+        &mut self,
+        key: &Uuid,
+        req: Request<Body>,
+        res_tx: Sender<Result<SendRequestResult, Error>>,
+        conn_token: Option<CancellationToken>,
+    ) {
+        let Some(cluster) = self.cluster.clone() else {
+            return;
+        };
+
+        let Some(remote) = self.remote_workers.get(key) else {
+            return;
+        };
+
+        // NOTE: A request forwarded by a peer is never forwarded again, so
+        // that two runtimes can't bounce it between them. The service is
+        // booted here instead, as it is when the peer no longer has it, or
+        // when there is no client address to vouch for to the peer.
+        if cluster::is_forwarded(&req)
+            || req.extensions().get::<ConnectionInfo>().is_none()
+            || !cluster.is_warm(remote.peer, &remote.service.service_path)
+        {
+            if let Some(remote) = self.remote_workers.remove(key) {
+                self.restore(remote.service, vec![(req, res_tx, conn_token)]);
+            }
+            return;
+        }
+
+        let peer = remote.peer;
+        let service_path = remote.service.service_path.clone();
+
+        drop(tokio::spawn(async move {
+            let result = cluster
+                .forward(peer, req)
+                .await
+                .map(|res| (res, mpsc::unbounded_channel().0))
+                .map_err(|err| {
+                    cluster.forget(peer, &service_path);
+                    err.context(format!("failed to forward request to {}", peer))
+                });
+
+            if res_tx.send(result).is_err() {
+                error!("main worker receiver dropped")
+            }
+        }));
+    }
+
+    pub fn idle(&mut self, key: &Uuid) {
+        if let Some(registry) = self.user_workers.get_mut(key).and_then(|it| {
+            self.active_workers
+                .get_mut(&registry_key(&it.service_path, &it.labels))
+        }) {
             registry.mark_idle(key, self.policy.supervisor_policy);
         }
     }
 
+    pub fn set_busy(&mut self, key: &Uuid, is_busy: bool) {
+        if let Some(profile) = self.user_workers.get_mut(key) {
+            profile.is_busy = is_busy;
+        }
+    }
+
     pub fn shutdown(&mut self, key: &Uuid) {
         self.retire(key);
+        self.metric_src
+            .unregister_user_worker_requests(&key.to_string());
+        self.exiting_workers.retain(|_, exit| !exit.is_finished());
+
+        let Some(profile) = self.user_workers.remove(key) else {
+            return;
+        };
+
+        self.unwatch_if_unused(&profile.service_path);
+        self.exited_workers
+            .push(worker_info(key, &profile), profile.exit.clone());
+        self.exiting_workers.insert(*key, profile.exit);
 
         let Some((notify_tx, _)) = self
-            .user_workers
-            .remove(key)
-            .and_then(|it| self.active_workers.get(&it.service_path))
+            .active_workers
+            .get(&registry_key(&profile.service_path, &profile.labels))
             .map(|it| it.notify_pair.clone())
         else {
             return;
@@ -601,11 +1748,158 @@ impl WorkerPool {
         self.metric_src.decl_active_user_workers();
     }
 
+    pub fn list(&self) -> Vec<UserWorkerInfo> {
+        self.lookup_by_labels(&HashMap::new())
+    }
+
+    /// Lists the workers carrying every label in `selector`, followed by the
+    /// ones that recently exited abnormally.
+    pub fn lookup_by_labels(&self, selector: &HashMap<String, String>) -> Vec<UserWorkerInfo> {
+        self.user_workers
+            .iter()
+            .filter(|(_, profile)| profile.matches_labels(selector))
+            .map(|(key, profile)| worker_info(key, profile))
+            .chain(self.exited_workers.abnormal().filter(|info| {
+                selector
+                    .iter()
+                    .all(|(k, v)| info.labels.get(k).map_or(false, |it| it == v))
+            }))
+            .collect()
+    }
+
+    /// Asks the supervisor of the worker to terminate it. The worker goes
+    /// through the same path as when the pool itself is terminating.
+    pub fn terminate(&mut self, key: &Uuid) -> bool {
+        if self.remote_workers.remove(key).is_some() {
+            return true;
+        }
+
+        let Some(termination) = self.user_workers.get(key).map(|it| it.termination.clone()) else {
+            return false;
+        };
+
+        self.retire(key);
+        termination.cancel();
+        true
+    }
+
+    /// Terminates every worker carrying all labels in `selector` and returns
+    /// how many were terminated. An empty selector terminates nothing rather
+    /// than every worker.
+    pub fn terminate_by_labels(&mut self, selector: &HashMap<String, String>) -> usize {
+        if selector.is_empty() {
+            return 0;
+        }
+
+        let keys = self
+            .user_workers
+            .iter()
+            .filter(|(_, profile)| profile.matches_labels(selector))
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+
+        keys.iter().filter(|it| self.terminate(it)).count()
+    }
+
+    /// Terminates every worker, and notifies `tx` once all of them have
+    /// exited.
+    pub fn drain(&mut self, tx: Sender<()>) {
+        let keys = self.user_workers.keys().copied().collect::<Vec<_>>();
+        let exits = keys
+            .iter()
+            .filter_map(|it| self.user_workers.get(it).map(|it| it.exit.clone()))
+            .collect::<Vec<_>>();
+
+        // NOTE: Retiring alone only stops routing new requests to a worker;
+        // it would keep its isolate alive until the wall clock limit, so
+        // draining could take as long as the longest worker timeout.
+        for key in keys.iter() {
+            self.terminate(key);
+        }
+
+        drop(tokio::spawn(async move {
+            for exit in exits {
+                exit.finished().await;
+            }
+
+            let _ = tx.send(());
+        }));
+    }
+
+    /// Stops booting new workers and signals the server to start draining.
+    /// Running workers keep serving until they exit on their own.
+    pub fn drain_runtime(&mut self) {
+        if !self.drain_token.is_cancelled() {
+            info!("runtime drain requested; no new user workers will be booted");
+            self.drain_token.cancel();
+        }
+    }
+
+    pub fn config(&self) -> UserWorkerPoolConfig {
+        UserWorkerPoolConfig {
+            supervisor_policy: self.policy.supervisor_policy.as_str().to_string(),
+            max_parallelism: self.policy.max_parallelism,
+            max_live_workers: self.policy.max_live_workers,
+            max_concurrent_boots: self.policy.max_concurrent_boots,
+            max_concurrent_requests: self.policy.max_concurrent_requests,
+            scheduling_policy: self.policy.scheduling_policy.as_str().to_string(),
+            request_wait_timeout_ms: self.policy.request_wait_timeout_ms,
+        }
+    }
+
+    pub fn usage_report_interval(&self) -> Option<Duration> {
+        self.policy
+            .usage_report_interval_ms
+            .map(Duration::from_millis)
+    }
+
+    pub fn accrue(&mut self, tenant: String, usage: WorkerUsage) {
+        self.accounting.accrue(tenant, usage);
+    }
+
+    pub fn usage(&self) -> Vec<TenantUsage> {
+        self.accounting.usage()
+    }
+
+    /// Emits a `UsageReport` event for every tenant that accrued usage since
+    /// the previous report.
+    pub fn report_usage(&mut self) {
+        for report in self.accounting.take_reports() {
+            let metadata = EventMetadata {
+                labels: HashMap::from([(TENANT_LABEL.to_string(), report.tenant.clone())]),
+                ..Default::default()
+            };
+
+            send_event_if_event_worker_available(
+                self.worker_event_sender.clone(),
+                WorkerEvents::UsageReport(report),
+                metadata,
+            );
+        }
+    }
+
+    /// Waits for the tasks of the workers that were shut down to be joined,
+    /// giving up after `timeout`.
+    pub async fn join_exiting_workers(&mut self, timeout: Duration) {
+        let exits = std::mem::take(&mut self.exiting_workers);
+        let join_all_fut = async {
+            for exit in exits.values() {
+                exit.finished().await;
+            }
+        };
+
+        if tokio::time::timeout(timeout, join_all_fut).await.is_err() {
+            let count = exits.values().filter(|it| !it.is_finished()).count();
+
+            warn!("{} user worker(s) did not finish in time", count);
+        }
+    }
+
     fn retire(&mut self, key: &Uuid) {
         if let Some(profile) = self.user_workers.get_mut(key) {
             let registry = self
                 .active_workers
-                .get_mut(&profile.service_path)
+                .get_mut(&registry_key(&profile.service_path, &profile.labels))
                 .expect("registry must be initialized at this point");
 
             let _ = profile.permit.take();
@@ -622,16 +1916,61 @@ impl WorkerPool {
         }
     }
 
-    fn maybe_active_worker(&mut self, service_path: &String, force_create: bool) -> Option<Uuid> {
+    /// Tells whether every worker of a service is throttled or marked busy
+    /// while no more of them can be booted, so that waiting for one is
+    /// pointless.
+    fn is_saturated_with_throttled_workers(&self, registry_key: &str) -> bool {
+        let Some(registry) = self.active_workers.get(registry_key) else {
+            return false;
+        };
+
+        registry.sem.available_permits() == 0
+            && !registry.workers.is_empty()
+            && registry.workers.iter().all(|WorkerId(key, _)| {
+                self.user_workers
+                    .get(key)
+                    .is_some_and(|it| it.is_busy || it.status.is_throttled.load(Ordering::Acquire))
+            })
+    }
+
+    fn maybe_active_worker(&mut self, registry_key: &String, force_create: bool) -> Option<Uuid> {
         if force_create {
             return None;
         }
 
-        let registry = self.active_workers.get_mut(service_path)?;
+        let registry = self.active_workers.get_mut(registry_key)?;
         let policy = self.policy.supervisor_policy;
+        let mut skipped = vec![];
+        let mut maybe_worker_uuid = None;
+
+        // NOTE: A worker under memory pressure, or one the supervisor has
+        // marked busy, keeps serving its in-flight requests, but new ones are
+        // routed to the other workers of the service, or to a fresh one.
+        for _ in 0..registry.workers.len() {
+            let Some(worker_uuid) = registry.mark_used_and_try_advance(policy).copied() else {
+                continue;
+            };
+
+            if self
+                .user_workers
+                .get(&worker_uuid)
+                .is_some_and(|it| it.is_busy || it.status.is_throttled.load(Ordering::Acquire))
+            {
+                skipped.push(worker_uuid);
+                continue;
+            }
+
+            maybe_worker_uuid = Some(worker_uuid);
+            break;
+        }
+
+        if policy.is_per_request() {
+            for key in skipped {
+                let _ = registry.workers.replace(WorkerId(key, true));
+            }
+        }
 
-        let mut advance_fn = move || registry.mark_used_and_try_advance(policy).copied();
-        let worker_uuid = advance_fn()?;
+        let worker_uuid = maybe_worker_uuid?;
 
         match self
             .user_workers
@@ -650,8 +1989,235 @@ impl WorkerPool {
 
             _ => {
                 self.retire(&worker_uuid);
-                self.maybe_active_worker(service_path, force_create)
+                self.maybe_active_worker(registry_key, force_create)
             }
         }
     }
 }
+
+fn worker_info(key: &Uuid, profile: &UserWorkerProfile) -> UserWorkerInfo {
+    UserWorkerInfo {
+        key: key.to_string(),
+        service_path: profile.service_path.clone(),
+        is_retired: profile.status.is_retired.is_raised(),
+        is_throttled: profile.status.is_throttled.load(Ordering::Acquire),
+        is_busy: profile.is_busy,
+        in_flight_requests: profile.status.demand.load(Ordering::Acquire),
+        boot_time_ms: profile.boot_duration.as_millis() as u64,
+        labels: profile.labels.clone(),
+        stats: profile.metrics.stats(),
+        exit_status: None,
+    }
+}
+
+/// Workers are only reused by creations asking for the same labels, so that
+/// the labels of a worker always are the ones it was created with.
+fn registry_key(service_path: &str, labels: &HashMap<String, String>) -> String {
+    if labels.is_empty() {
+        return service_path.to_string();
+    }
+
+    let labels = labels.iter().collect::<BTreeMap<_, _>>();
+
+    // NOTE: Paths can't contain a NUL, and the JSON keeps labels containing
+    // separators apart.
+    format!(
+        "{}\0{}",
+        service_path,
+        serde_json::to_string(&labels).unwrap_or_default()
+    )
+}
+
+/// Calls `f` once no change has come in for `period` since the last one, so
+/// that a burst of changes leads to a single reload. Returns once the sender
+/// is dropped.
+async fn debounce(mut change_rx: mpsc::UnboundedReceiver<()>, period: Duration, f: impl Fn()) {
+    while change_rx.recv().await.is_some() {
+        loop {
+            match tokio::time::timeout(period, change_rx.recv()).await {
+                Ok(Some(())) => continue,
+                Ok(None) => return,
+                Err(_) => break,
+            }
+        }
+
+        f();
+    }
+}
+
+/// Wraps the body so that `count_fn` is called with the length of every chunk
+/// passing through. Empty bodies are left as they are to keep their framing.
+fn count_body_bytes(body: Body, count_fn: impl Fn(usize) + Send + Sync + 'static) -> Body {
+    if body.is_end_stream() {
+        return body;
+    }
+
+    Body::wrap_stream(body.inspect_ok(move |chunk| count_fn(chunk.len())))
+}
+
+// NOTE: The boot of a worker is reported on the request that waited for it,
+// but it is not counted in the latencies of the worker.
+fn server_timing_value(
+    maybe_boot: Option<Duration>,
+    queue: Duration,
+    dispatch: Duration,
+    exec: Duration,
+) -> String {
+    let metric =
+        |name: &str, dur: Duration| format!("{};dur={:.1}", name, dur.as_secs_f64() * 1000.0);
+    let mut metrics = vec![];
+
+    if let Some(boot) = maybe_boot {
+        metrics.push(metric("boot", boot));
+    }
+
+    metrics.push(metric("queue", queue));
+    metrics.push(metric("dispatch", dispatch));
+    metrics.push(metric("exec", exec));
+    metrics.join(", ")
+}
+
+/// Copies the options a worker was created with so that a replacement can be
+/// booted from them. Workers created from an eszip or inline module code are
+/// not backed by files, so they can't be reloaded.
+fn reload_template(worker_options: &WorkerContextInitOpts) -> Option<WorkerContextInitOpts> {
+    if worker_options.maybe_eszip.is_some() || worker_options.maybe_module_code.is_some() {
+        return None;
+    }
+
+    Some(WorkerContextInitOpts {
+        service_path: worker_options.service_path.clone(),
+        no_module_cache: worker_options.no_module_cache,
+        import_map_path: worker_options.import_map_path.clone(),
+        env_vars: worker_options.env_vars.clone(),
+        events_rx: None,
+        timing: None,
+        conf: worker_options.conf.clone(),
+        maybe_eszip: None,
+        maybe_eszip_signature: None,
+        maybe_module_code: None,
+        maybe_entrypoint: worker_options.maybe_entrypoint.clone(),
+        maybe_decorator: worker_options.maybe_decorator,
+        static_patterns: worker_options.static_patterns.clone(),
+        maybe_jsx_import_source_config: worker_options.maybe_jsx_import_source_config.clone(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sb_workers::context::WorkerExitStatus;
+
+    fn info(key: &str) -> UserWorkerInfo {
+        UserWorkerInfo {
+            key: key.to_string(),
+            service_path: format!("./services/{key}"),
+            is_retired: true,
+            is_throttled: false,
+            is_busy: false,
+            in_flight_requests: 0,
+            boot_time_ms: 0,
+            labels: HashMap::new(),
+            stats: Default::default(),
+            exit_status: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_debounce_reloads_once_changes_settle() {
+        let (change_tx, change_rx) = mpsc::unbounded_channel();
+        let (reload_tx, mut reload_rx) = mpsc::unbounded_channel();
+        let handle = tokio::spawn(debounce(change_rx, Duration::from_millis(200), move || {
+            let _ = reload_tx.send(Instant::now());
+        }));
+
+        let started_at = Instant::now();
+
+        for _ in 0..5 {
+            change_tx.send(()).unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let reloaded_at = reload_rx.recv().await.unwrap();
+
+        // NOTE: The reload is pushed back by every change, rather than
+        // following the first one.
+        assert!(reloaded_at - started_at >= Duration::from_millis(600));
+        assert!(reload_rx.try_recv().is_err());
+
+        change_tx.send(()).unwrap();
+        assert!(reload_rx.recv().await.is_some());
+
+        drop(change_tx);
+        handle.await.unwrap();
+        assert!(reload_rx.recv().await.is_none());
+    }
+
+    #[test]
+    fn test_registry_key_tells_labels_apart() {
+        let labels = |it: &[(&str, &str)]| {
+            it.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+
+        assert_eq!(registry_key("./meow", &labels(&[])), "./meow");
+        assert_eq!(
+            registry_key("./meow", &labels(&[("a", "1"), ("b", "2")])),
+            registry_key("./meow", &labels(&[("b", "2"), ("a", "1")]))
+        );
+        assert_ne!(
+            registry_key("./meow", &labels(&[("tenant", "acme")])),
+            registry_key("./meow", &labels(&[("tenant", "other")]))
+        );
+        assert_ne!(
+            registry_key("./meow", &labels(&[("a", "b=c")])),
+            registry_key("./meow", &labels(&[("a=b", "c")]))
+        );
+    }
+
+    #[test]
+    fn test_exited_workers_report_panics_only() {
+        let mut exited = ExitedWorkers::default();
+        let normal = WorkerExit::default();
+        let panicked = WorkerExit::default();
+        let exiting = WorkerExit::default();
+
+        normal.mark_finished();
+        panicked.set(WorkerExitStatus::WithPanic("boom".to_string()));
+        panicked.mark_finished();
+
+        exited.push(info("normal"), normal);
+        exited.push(info("panicked"), panicked);
+        exited.push(info("exiting"), exiting.clone());
+
+        let reported = exited.abnormal().collect::<Vec<_>>();
+
+        assert_eq!(exited.0.len(), 2);
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].key, "panicked");
+        assert!(matches!(
+            reported[0].exit_status,
+            Some(WorkerExitStatus::WithPanic(ref msg)) if msg == "boom"
+        ));
+
+        exiting.set(WorkerExitStatus::WithPanic("late".to_string()));
+
+        assert_eq!(exited.abnormal().count(), 2);
+    }
+
+    #[test]
+    fn test_exited_workers_are_bounded() {
+        let mut exited = ExitedWorkers::default();
+
+        for i in 0..MAX_EXITED_WORKERS + 8 {
+            let exit = WorkerExit::default();
+
+            exit.set(WorkerExitStatus::WithPanic(i.to_string()));
+            exited.push(info(&i.to_string()), exit);
+        }
+
+        assert_eq!(exited.0.len(), MAX_EXITED_WORKERS);
+        assert_eq!(exited.abnormal().next().unwrap().key, "8");
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Error};
+use event_worker::events::WorkerEvents;
+use futures_util::Future;
+use hyper::{Body, Request};
+use log::{info, warn};
+use sb_workers::context::UserWorkerRuntimeOpts;
+use uuid::Uuid;
+
+use crate::rt_worker::worker_pool::WorkerPoolPolicy;
+use crate::testing::{ServiceSource, TestRuntime};
+
+/// How long each stage of the self-test may take before it is considered
+/// broken.
+const STAGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const CANARY_SERVICE: &str = "self-test-canary";
+const CANARY_MODULE_CODE: &str = "Deno.serve(() => new Response('ok'));";
+const CANARY_RESPONSE: &[u8] = b"ok";
+
+#[derive(Default)]
+struct Report {
+    stages: Vec<(&'static str, Result<Duration, String>)>,
+}
+
+impl Report {
+    async fn stage<T, F>(&mut self, name: &'static str, fut: F) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+    {
+        let started_at = Instant::now();
+        let result = match tokio::time::timeout(STAGE_TIMEOUT, fut).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("did not finish within {:?}", STAGE_TIMEOUT)),
+        };
+
+        self.stages.push((
+            name,
+            result
+                .as_ref()
+                .map(|_| started_at.elapsed())
+                .map_err(|err| format!("{:#}", err)),
+        ));
+
+        result
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, result) in self.stages.iter() {
+            match result {
+                Ok(dur) => writeln!(f, "  {:<10} ok ({:.1}ms)", name, dur.as_secs_f64() * 1000.0)?,
+                Err(err) => writeln!(f, "  {:<10} FAILED: {}", name, err)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Boots a trivial user worker in a pool of its own, built with the policy
+/// of the real one, sends it a request and shuts it down again, so that a
+/// broken environment shows up before any traffic arrives. The pool is torn
+/// down afterwards, taking the canary with it if a stage failed.
+pub(crate) async fn run(policy: WorkerPoolPolicy) -> Result<(), Error> {
+    run_canary(policy, CANARY_MODULE_CODE).await
+}
+
+async fn run_canary(policy: WorkerPoolPolicy, code: &str) -> Result<(), Error> {
+    let mut report = Report::default();
+    let mut rt = TestRuntime::builder()
+        .with_worker_pool_policy(policy.without_restore())
+        .with_user_runtime_opts(UserWorkerRuntimeOpts {
+            force_create: true,
+            ..Default::default()
+        })
+        .build()
+        .await?;
+
+    rt.register(CANARY_SERVICE, ServiceSource::code(code))?;
+
+    let result = run_stages(&mut rt, &mut report).await;
+
+    if let Err(err) = rt.shutdown(STAGE_TIMEOUT).await {
+        warn!("startup self-test: {:#}", err);
+    }
+
+    match result {
+        Ok(()) => {
+            info!("startup self-test passed\n{}", report);
+            Ok(())
+        }
+
+        Err(_) => bail!("startup self-test failed\n{}", report),
+    }
+}
+
+async fn run_stages(rt: &mut TestRuntime, report: &mut Report) -> Result<(), Error> {
+    let key = report.stage("boot", rt.worker(CANARY_SERVICE)).await?;
+
+    report
+        .stage(
+            "events",
+            wait_for_event(rt, key, |it| matches!(it, WorkerEvents::Boot(_))),
+        )
+        .await?;
+
+    let res = report
+        .stage("dispatch", async {
+            let req = Request::builder()
+                .uri("http://localhost/")
+                .body(Body::empty())?;
+            let res = rt.send_request(key, req).await?;
+
+            if !res.status.is_success() {
+                bail!("canary worker responded with {}", res.status);
+            }
+
+            Ok(res)
+        })
+        .await?;
+
+    report
+        .stage("response", async {
+            if res.body.as_ref() != CANARY_RESPONSE {
+                bail!("canary worker responded with an unexpected body");
+            }
+
+            Ok(())
+        })
+        .await?;
+
+    report
+        .stage("shutdown", async {
+            if !rt.terminate(key).await? {
+                bail!("canary worker was gone before it could be terminated");
+            }
+
+            wait_for_event(rt, key, |it| matches!(it, WorkerEvents::Shutdown(_))).await
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Waits for the canary to emit an event matching `predicate`.
+async fn wait_for_event(
+    rt: &mut TestRuntime,
+    key: Uuid,
+    predicate: impl Fn(&WorkerEvents) -> bool,
+) -> Result<(), Error> {
+    rt.wait_for_event(
+        |ev| ev.metadata.execution_id == Some(key) && predicate(&ev.event),
+        STAGE_TIMEOUT,
+    )
+    .await
+    .map(|_| ())
+    .ok_or_else(|| anyhow!("the canary did not report within {:?}", STAGE_TIMEOUT))
+}
+
+#[cfg(test)]
+mod test {
+    use serial_test::serial;
+
+    use super::*;
+
+    #[tokio::test]
+    #[serial]
+    async fn test_self_test_passes_with_working_canary() {
+        run_canary(WorkerPoolPolicy::default(), CANARY_MODULE_CODE)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_self_test_reports_failed_stage() {
+        let err = run_canary(
+            WorkerPoolPolicy::default(),
+            "Deno.serve(() => new Response('no', { status: 500 }));",
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.starts_with("startup self-test failed"), "{}", err);
+        assert!(err.contains("boot       ok"), "{}", err);
+        assert!(
+            err.contains("dispatch   FAILED: canary worker responded with 500"),
+            "{}",
+            err
+        );
+        assert!(!err.contains("response"), "{}", err);
+    }
+}
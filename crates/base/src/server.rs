@@ -1,29 +1,56 @@
+use crate::access_log::{PendingEntry, WORKER_KEY_HEADER};
+use crate::encoding;
+use crate::event_router::EventWorkerRoute;
 use crate::inspector_server::Inspector;
+use crate::listener::ListenerConfig;
+use crate::proxy_protocol;
+use crate::router::{Router, RoutingTable};
+use crate::rt_worker::cluster::{
+    self, ClusterServices, PeerRequest, CLUSTER_FORWARDED_HEADER, CLUSTER_SERVICES_PATH,
+};
+use crate::rt_worker::hibernation::{self, Reentry};
+use crate::rt_worker::main_worker::{MainWorker, MainWorkerOpts};
+use crate::rt_worker::pool_state::RestoreMode;
+use crate::rt_worker::scheduler::SchedulingPolicy;
 use crate::rt_worker::worker_ctx::{
-    create_events_worker, create_main_worker, create_user_worker_pool, TerminationToken,
+    create_events_worker, create_user_worker_pool, TerminationToken, WorkerConnectionConfig,
 };
 use crate::rt_worker::worker_pool::WorkerPoolPolicy;
 use crate::InspectorOption;
 use anyhow::{anyhow, bail, Context, Error};
 use deno_config::JsxImportSourceConfig;
-use event_worker::events::WorkerEventWithMetadata;
-use futures_util::future::{poll_fn, BoxFuture};
-use futures_util::{FutureExt, Stream};
-use hyper::{server::conn::Http, service::Service, Body, Request, Response};
+use deno_core::serde_json;
+use event_worker::events::ConnectionErrorKind;
+use futures_util::future::{poll_fn, select_all, BoxFuture};
+use futures_util::{stream, FutureExt, Stream, StreamExt};
+use http::{HeaderMap, HeaderValue};
+use hyper::service::{service_fn, Service};
+use hyper::{server::conn::Http, Body, Request, Response};
+use ipnet::IpNet;
 use log::{debug, error, info, trace, warn};
 use rustls_pemfile::read_one_from_slice;
 use rustls_pemfile::Item;
+use sb_core::conn_sync::{ConnectionInfo, TlsInfo};
+use sb_core::signing::SigningKey;
 use sb_core::SharedMetricSource;
 use sb_graph::DecoratorType;
-use sb_workers::context::{MainWorkerRuntimeOpts, WorkerRequestMsg};
+use sb_workers::context::{
+    IpAccessList, MainWorkerRuntimeOpts, RequestId, UserWorkerMsgs, UserWorkerMsgsSender,
+    WorkerRequestMsg,
+};
+use sb_workers::errors::{connection_error_kind, WorkerError};
+use std::collections::HashSet;
+use std::convert::Infallible;
 use std::future::{pending, Future};
+use std::io;
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::str;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::Poll;
 use std::time::Duration;
@@ -32,14 +59,55 @@ use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
 use tokio::pin;
 use tokio::sync::mpsc::{Sender, UnboundedSender};
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{broadcast, mpsc, oneshot, Notify, OwnedSemaphorePermit, Semaphore};
 use tokio::time::{sleep, timeout};
 use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
-use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::{ServerConfig, ServerConnection};
 use tokio_rustls::TlsAcceptor;
 use tokio_util::sync::CancellationToken;
 use url::Url;
 
+/// Carries the address of the client to the user worker pool. Any value sent
+/// by the client itself is overwritten by the listener.
+pub const CLIENT_ADDR_HEADER: &str = "x-sb-client-ip";
+
+/// Header the listener sets to the encoding a worker should compress its
+/// response in, out of those the client accepts. It is `identity` if the
+/// response should be sent as is.
+pub const ACCEPT_ENCODING_HEADER: &str = "x-sb-accept-encoding";
+
+/// Headers the listener sets from the GeoIP databases, when configured: the
+/// ISO code of the country and of its largest subdivision, the
+/// percent-encoded English name of the city and the autonomous system number
+/// of the client address. Headers the databases have no data for are left
+/// out.
+pub const GEO_COUNTRY_HEADER: &str = "x-sb-geo-country";
+pub const GEO_REGION_HEADER: &str = "x-sb-geo-region";
+pub const GEO_CITY_HEADER: &str = "x-sb-geo-city";
+pub const GEO_ASN_HEADER: &str = "x-sb-geo-asn";
+
+/// Headers starting with this prefix carry metadata set by the runtime, such
+/// as [`CLIENT_ADDR_HEADER`]. The listener drops any of them sent by clients.
+pub use sb_core::signing::INTERNAL_HEADER_PREFIX;
+
+/// Events kept for the admin clients that are slow to read them.
+const ADMIN_EVENTS_CAPACITY: usize = 1024;
+
+/// How long a connection has to send its PROXY protocol header, when the
+/// listener expects one.
+const PROXY_HEADER_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Headers that identify the client on behalf of a proxy. They are only
+/// trustworthy if a proxy in front of the runtime sets them.
+const FORWARDED_HEADERS: &[&str] = &[
+    "forwarded",
+    "x-forwarded-for",
+    "x-forwarded-host",
+    "x-forwarded-proto",
+    "x-real-ip",
+    "x-request-id",
+];
+
 mod signal {
     pub use tokio::signal::ctrl_c;
 
@@ -89,28 +157,31 @@ impl<S: Stream + Unpin> Stream for CancelOnDrop<S> {
 #[derive(Clone)]
 struct TerminationTokens {
     input: Option<TerminationToken>,
-    event: Option<TerminationToken>,
+    events: Vec<TerminationToken>,
     pool: TerminationToken,
-    main: TerminationToken,
 }
 
 impl TerminationTokens {
-    fn new(maybe_input: Option<TerminationToken>, with_event: bool) -> Self {
+    fn new(maybe_input: Option<TerminationToken>, events_worker_count: usize) -> Self {
         Self {
             input: maybe_input,
-            event: with_event.then(TerminationToken::new),
+            events: (0..events_worker_count)
+                .map(|_| TerminationToken::new())
+                .collect(),
             pool: TerminationToken::new(),
-            main: TerminationToken::new(),
         }
     }
 
-    async fn terminate(&self) {
-        if let Some(token) = self.event.as_ref() {
+    async fn terminate(&self, main_workers: &[MainWorker]) {
+        for token in &self.events {
             token.cancel_and_wait().await;
         }
 
         self.pool.cancel_and_wait().await;
-        self.main.cancel_and_wait().await;
+
+        for main_worker in main_workers {
+            main_worker.terminate().await;
+        }
 
         if let Some(token) = self.input.as_ref() {
             assert!(token.inbound.is_cancelled());
@@ -122,22 +193,133 @@ impl TerminationTokens {
     }
 }
 
+/// Answers the liveness and readiness probes on the listener itself, without
+/// involving the main worker.
+#[derive(Clone)]
+struct HealthProbe {
+    main_worker: MainWorker,
+    worker_pool_tx: UserWorkerMsgsSender,
+    drain_token: CancellationToken,
+}
+
+impl HealthProbe {
+    fn not_ready_reason(&self) -> Option<&'static str> {
+        if self.main_worker.is_closed() {
+            Some("main worker is not running")
+        } else if self.worker_pool_tx.is_closed() {
+            Some("user worker pool is not running")
+        } else if self.drain_token.is_cancelled() {
+            Some("runtime is draining")
+        } else {
+            None
+        }
+    }
+
+    fn respond(&self, req: &Request<Body>) -> Option<Response<Body>> {
+        let maybe_reason = match req.uri().path() {
+            "/health/live" => None,
+            "/health/ready" => self.not_ready_reason(),
+            _ => return None,
+        };
+
+        let (status, body) = match maybe_reason {
+            None => (http::StatusCode::OK, serde_json::json!({ "status": "ok" })),
+            Some(reason) => (
+                http::StatusCode::SERVICE_UNAVAILABLE,
+                serde_json::json!({ "status": "unavailable", "reason": reason }),
+            ),
+        };
+
+        Response::builder()
+            .status(status)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .ok()
+    }
+}
+
+/// Answers the polls of the peers of the runtime with the services booted
+/// here, and tells the requests they forward apart from those of clients.
+#[derive(Clone)]
+struct ClusterProbe {
+    peers: Arc<HashSet<IpAddr>>,
+    key: SigningKey,
+    worker_pool_tx: UserWorkerMsgsSender,
+}
+
+impl ClusterProbe {
+    /// Returns what a peer vouches for in the request, if it comes from one
+    /// and carries a valid signature.
+    fn authenticate(&self, ip: IpAddr, req: &Request<Body>) -> Option<PeerRequest> {
+        if !self.peers.contains(&ip.to_canonical()) {
+            return None;
+        }
+
+        cluster::authenticate(&self.key, req)
+    }
+
+    async fn respond(self) -> Response<Body> {
+        let (tx, rx) = oneshot::channel();
+        let workers = match self.worker_pool_tx.send(UserWorkerMsgs::List(tx)) {
+            Ok(()) => rx.await.unwrap_or_default(),
+            Err(_) => vec![],
+        };
+
+        let mut services = ClusterServices::default();
+        let mut seen = HashSet::new();
+
+        for worker in workers.into_iter().filter(|it| !it.is_retired) {
+            if seen.insert(worker.service_path.clone()) {
+                services.services.push(worker.service_path);
+            }
+        }
+
+        Response::builder()
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&services).unwrap()))
+            .unwrap()
+    }
+}
+
 struct WorkerService {
     metric_src: SharedMetricSource,
-    worker_req_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+    main_worker: MainWorker,
+    drain_token: CancellationToken,
+    health_probe: Option<HealthProbe>,
+    cluster_probe: Option<ClusterProbe>,
+    client_ip: IpAddr,
+    conn_info: ConnectionInfo,
+    strip_forwarded_headers: bool,
+    ip_access_list: Arc<IpAccessList>,
+    router: Option<Arc<Router>>,
     cancel: CancellationToken,
 }
 
 impl WorkerService {
     fn new(
         metric_src: SharedMetricSource,
-        worker_req_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+        main_worker: MainWorker,
+        drain_token: CancellationToken,
+        health_probe: Option<HealthProbe>,
+        cluster_probe: Option<ClusterProbe>,
+        conn_info: ConnectionInfo,
+        strip_forwarded_headers: bool,
+        ip_access_list: Arc<IpAccessList>,
+        router: Option<Arc<Router>>,
     ) -> (Self, CancellationToken) {
         let cancel = CancellationToken::new();
         (
             Self {
                 metric_src,
-                worker_req_tx,
+                main_worker,
+                drain_token,
+                health_probe,
+                cluster_probe,
+                client_ip: conn_info.remote_addr.ip(),
+                conn_info,
+                strip_forwarded_headers,
+                ip_access_list,
+                router,
                 cancel: cancel.clone(),
             },
             cancel,
@@ -145,6 +327,266 @@ impl WorkerService {
     }
 }
 
+/// Drops the headers a client must not be able to set, so that they can't be
+/// mistaken for ones set by the runtime. Peers of the runtime may still mark
+/// the requests they forward.
+fn strip_untrusted_headers(headers: &mut HeaderMap, strip_forwarded: bool, from_peer: bool) {
+    let names = headers
+        .keys()
+        .filter(|it| {
+            (it.as_str().starts_with(INTERNAL_HEADER_PREFIX)
+                && !(from_peer && it.as_str() == CLUSTER_FORWARDED_HEADER))
+                || (strip_forwarded && FORWARDED_HEADERS.contains(&it.as_str()))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    for name in names {
+        headers.remove(name);
+    }
+}
+
+fn insert_geo_headers(headers: &mut HeaderMap, info: &sb_geoip::GeoInfo) {
+    let values = [
+        (GEO_COUNTRY_HEADER, info.country.clone()),
+        (GEO_REGION_HEADER, info.region.clone()),
+        (
+            GEO_CITY_HEADER,
+            info.city
+                .as_deref()
+                .map(|it| urlencoding::encode(it).into_owned()),
+        ),
+        (GEO_ASN_HEADER, info.asn.map(|it| it.to_string())),
+    ];
+
+    for (name, maybe_value) in values {
+        if let Some(value) = maybe_value.and_then(|it| HeaderValue::from_str(&it).ok()) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+/// Keeps track of the requests of a single connection, to tell when the
+/// connection should be closed.
+#[derive(Default)]
+struct ConnectionState {
+    in_flight: AtomicUsize,
+    served: AtomicUsize,
+    changed: Notify,
+    /// Cancelled to close the connection right away, without waiting for
+    /// the request in flight.
+    abort: CancellationToken,
+}
+
+#[derive(Debug, Clone)]
+struct ConnectionPolicy {
+    keep_alive_timeout_dur: Option<Duration>,
+    max_requests: Option<usize>,
+    maybe_request_limiter: Option<OverloadLimiter>,
+    body_idle_timeout_dur: Option<Duration>,
+}
+
+/// Bounds how many connections or requests the listener takes on at once.
+#[derive(Debug, Clone)]
+struct OverloadLimiter {
+    semaphore: Arc<Semaphore>,
+    /// How long to wait for a slot once the limit is reached. Without it,
+    /// anything over the limit is turned away right away.
+    maybe_queue_timeout_dur: Option<Duration>,
+}
+
+impl OverloadLimiter {
+    fn new(limit: usize, maybe_queue_timeout_dur: Option<Duration>) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            maybe_queue_timeout_dur,
+        }
+    }
+
+    fn is_queued(&self) -> bool {
+        self.maybe_queue_timeout_dur.is_some()
+    }
+
+    async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        match self.maybe_queue_timeout_dur {
+            Some(dur) => timeout(dur, self.semaphore.clone().acquire_owned())
+                .await
+                .ok()?
+                .ok(),
+
+            None => self.semaphore.clone().try_acquire_owned().ok(),
+        }
+    }
+}
+
+/// Fails `body` once the client has sent nothing of it for `dur` while it is
+/// being read, and calls `on_timeout` the first time it does.
+fn with_idle_timeout<F>(body: Body, dur: Duration, on_timeout: F) -> Body
+where
+    F: FnOnce() + Send + 'static,
+{
+    Body::wrap_stream(stream::unfold(
+        (body, Some(on_timeout)),
+        move |(mut body, mut maybe_on_timeout)| async move {
+            match timeout(dur, body.next()).await {
+                Ok(Some(chunk)) => Some((
+                    chunk.map_err(|err| io::Error::new(io::ErrorKind::Other, err)),
+                    (body, maybe_on_timeout),
+                )),
+
+                Ok(None) => None,
+                Err(_) => {
+                    if let Some(on_timeout) = maybe_on_timeout.take() {
+                        on_timeout();
+                    }
+
+                    Some((
+                        Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "request body read timed out",
+                        )),
+                        (body, maybe_on_timeout),
+                    ))
+                }
+            }
+        },
+    ))
+}
+
+/// Tells whether the connection failed because the request header was not
+/// read within the read timeout.
+fn is_header_read_timeout(err: &hyper::Error) -> bool {
+    std::error::Error::source(err)
+        .and_then(|it| it.downcast_ref::<io::Error>())
+        .is_some_and(|it| it.kind() == io::ErrorKind::TimedOut)
+}
+
+fn overloaded_response() -> Response<Body> {
+    Response::builder()
+        .status(http::StatusCode::SERVICE_UNAVAILABLE)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({ "msg": "server is overloaded" }).to_string(),
+        ))
+        .unwrap()
+}
+
+impl ConnectionState {
+    /// Resolves once the connection has served as many requests as it may,
+    /// or has been idle for longer than the keep-alive timeout.
+    async fn expired(&self, policy: &ConnectionPolicy) {
+        loop {
+            let changed = self.changed.notified();
+
+            if policy
+                .max_requests
+                .is_some_and(|it| self.served.load(Ordering::Acquire) >= it)
+            {
+                return;
+            }
+
+            match policy.keep_alive_timeout_dur {
+                Some(dur) if self.in_flight.load(Ordering::Acquire) == 0 => {
+                    tokio::select! {
+                        _ = sleep(dur) => return,
+                        _ = changed => {}
+                    }
+                }
+
+                _ => changed.await,
+            }
+        }
+    }
+}
+
+struct ConnectionTracker<S> {
+    inner: S,
+    state: Arc<ConnectionState>,
+    client_ip: IpAddr,
+    maybe_request_limiter: Option<OverloadLimiter>,
+    maybe_body_idle_timeout_dur: Option<Duration>,
+    metric_src: SharedMetricSource,
+}
+
+impl<S> Service<Request<Body>> for ConnectionTracker<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = anyhow::Error>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = anyhow::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let state = self.state.clone();
+        let maybe_log_entry = PendingEntry::begin(&mut req, self.client_ip);
+
+        if state.served.fetch_add(1, Ordering::AcqRel) > 0 {
+            self.metric_src.incl_reused_connection_requests();
+        }
+
+        state.in_flight.fetch_add(1, Ordering::AcqRel);
+        state.changed.notify_waiters();
+
+        let req = match self.maybe_body_idle_timeout_dur {
+            Some(dur) => {
+                let state = state.clone();
+                let metric_src = self.metric_src.clone();
+
+                req.map(|body| {
+                    with_idle_timeout(body, dur, move || {
+                        metric_src.incl_timed_out_body_reads();
+                        state.abort.cancel();
+                    })
+                })
+            }
+
+            None => req,
+        };
+
+        let maybe_limiter = self.maybe_request_limiter.clone();
+        let metric_src = self.metric_src.clone();
+        let fut = self.inner.call(req);
+
+        Box::pin(async move {
+            let _in_flight_guard = scopeguard::guard(state, |it| {
+                it.in_flight.fetch_sub(1, Ordering::AcqRel);
+                it.changed.notify_waiters();
+            });
+
+            let mut res = async move {
+                // NOTE: The slot is held until the head of the response is
+                // ready.
+                let _permit = match maybe_limiter {
+                    Some(limiter) => match limiter.acquire().await {
+                        Some(permit) => Some(permit),
+                        None => {
+                            metric_src.incl_overloaded_requests();
+                            return Ok(overloaded_response());
+                        }
+                    },
+
+                    None => None,
+                };
+
+                fut.await
+            }
+            .await?;
+
+            let maybe_worker_key = res.headers_mut().remove(WORKER_KEY_HEADER);
+
+            Ok(match maybe_log_entry {
+                Some(entry) => entry.finish(res, maybe_worker_key),
+                None => res,
+            })
+        })
+    }
+}
+
 impl Service<Request<Body>> for WorkerService {
     type Response = Response<Body>;
     type Error = anyhow::Error;
@@ -154,22 +596,116 @@ impl Service<Request<Body>> for WorkerService {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, req: Request<Body>) -> Self::Future {
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        if !self.ip_access_list.is_allowed(self.client_ip) {
+            self.metric_src.incl_denied_requests();
+
+            let res = Response::builder()
+                .status(http::StatusCode::FORBIDDEN)
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    serde_json::json!({ "msg": "client address is not allowed" }).to_string(),
+                ))
+                .unwrap();
+
+            return Box::pin(async move { Ok(res) });
+        }
+
+        if let Some(res) = self.health_probe.as_ref().and_then(|it| it.respond(&req)) {
+            return Box::pin(async move { Ok(res) });
+        }
+
+        let maybe_peer = self.cluster_probe.as_ref().and_then(|probe| {
+            probe
+                .authenticate(self.client_ip, &req)
+                .map(|peer_req| (probe, peer_req))
+        });
+
+        if let Some((probe, _)) = maybe_peer
+            .as_ref()
+            .filter(|_| req.uri().path() == CLUSTER_SERVICES_PATH)
+        {
+            let probe = (*probe).clone();
+            return Box::pin(async move { Ok(probe.respond().await) });
+        }
+
+        // NOTE: A request forwarded by a peer is served as if it came from the
+        // client the peer vouches for, rather than from the peer.
+        let mut client_ip = self.client_ip;
+        let mut conn_info = self.conn_info.clone();
+
+        if let Some(addr) = maybe_peer.as_ref().and_then(|(_, it)| it.client_addr) {
+            client_ip = addr.ip();
+            conn_info.remote_addr = addr;
+        }
+
+        strip_untrusted_headers(
+            req.headers_mut(),
+            self.strip_forwarded_headers,
+            maybe_peer.is_some(),
+        );
+        req.headers_mut().insert(
+            CLIENT_ADDR_HEADER,
+            HeaderValue::from_str(&client_ip.to_canonical().to_string()).unwrap(),
+        );
+        if let Some(info) = sb_geoip::lookup(client_ip) {
+            insert_geo_headers(req.headers_mut(), &info);
+        }
+        req.headers_mut().insert(
+            ACCEPT_ENCODING_HEADER,
+            HeaderValue::from_static(encoding::negotiate(req.headers())),
+        );
+        req.extensions_mut().insert(conn_info);
+
         // create a response in a future.
         let cancel = self.cancel.child_token();
         let metric_src = self.metric_src.clone();
-        let worker_req_tx = self.worker_req_tx.clone();
+        let main_worker = self.main_worker.clone();
+        let drain_token = self.drain_token.clone();
+        let maybe_route = self
+            .router
+            .as_ref()
+            .and_then(|router| router.route(&req).map(|route| (router.clone(), route)));
+
         let fut = async move {
             let (res_tx, res_rx) = oneshot::channel::<Result<Response<Body>, hyper::Error>>();
 
             let req_uri = req.uri().clone();
-            let msg = WorkerRequestMsg {
-                req,
-                res_tx,
-                conn_token: Some(cancel.clone()),
-            };
+            let request_id = RequestId::of(&mut req);
+            let mut maybe_in_flight = None;
+
+            if let Some((router, route)) = maybe_route {
+                let conn_token = Some(cancel.clone());
+
+                req.extensions_mut().insert(Reentry::Route {
+                    router: router.clone(),
+                    route: route.clone(),
+                    uri: req_uri.clone(),
+                });
+
+                tokio::spawn(async move {
+                    let _ = res_tx.send(Ok(router.dispatch(route, req, conn_token).await));
+                });
+            } else {
+                let (worker_req_tx, in_flight) = main_worker.sender();
+                let msg = WorkerRequestMsg {
+                    req,
+                    res_tx,
+                    conn_token: Some(cancel.clone()),
+                };
+
+                maybe_in_flight = Some(in_flight);
+
+                if let Err(err) = worker_req_tx.send(msg) {
+                    if err.is_full() {
+                        metric_src.incl_overloaded_requests();
+                        return Ok(overloaded_response());
+                    }
+
+                    return Err(err.into());
+                }
+            }
 
-            worker_req_tx.send(msg)?;
             metric_src.incl_received_requests();
 
             tokio::spawn({
@@ -177,6 +713,10 @@ impl Service<Request<Body>> for WorkerService {
                 let cancel = cancel.clone();
 
                 async move {
+                    // NOTE: Keeps the main worker the request was handed to
+                    // from being retired before the response is done.
+                    let _in_flight = maybe_in_flight;
+
                     tokio::select! {
                         _ = cancel.cancelled() => {
                             metric_src_inner.incl_handled_requests();
@@ -196,7 +736,7 @@ impl Service<Request<Body>> for WorkerService {
                 }
             };
 
-            let res = match res {
+            let mut res = match res {
                 Ok(res) => {
                     let (parts, body) = res.into_parts();
                     Response::from_parts(
@@ -209,23 +749,38 @@ impl Service<Request<Body>> for WorkerService {
                 }
 
                 Err(e) => {
+                    let err = WorkerError::connection(&request_id, &e);
+
                     error!(
-                        "request failed (uri: {:?} reason: {:?})",
+                        "request failed (uri: {:?} reason: {})",
                         req_uri.to_string(),
-                        e
+                        err
                     );
 
-                    // FIXME: add an error body
+                    // NOTE: A worker that went away mid-request is a bad
+                    // gateway, while anything else is a bug of the runtime.
+                    let status = match connection_error_kind(&e) {
+                        ConnectionErrorKind::WorkerReset => http::StatusCode::BAD_GATEWAY,
+                        ConnectionErrorKind::Runtime => http::StatusCode::INTERNAL_SERVER_ERROR,
+                    };
+
                     Response::builder()
-                        .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+                        .status(status)
                         .body(Body::wrap_stream(CancelOnDrop {
-                            inner: Body::empty(),
+                            inner: Body::from(err.to_string()),
                             cancel: Some(cancel),
                         }))
                         .unwrap()
                 }
             };
 
+            if drain_token.is_cancelled() {
+                // NOTE: Asks the client (or the load balancer in front of us)
+                // not to reuse this connection while the runtime is draining.
+                res.headers_mut()
+                    .insert(http::header::CONNECTION, HeaderValue::from_static("close"));
+            }
+
             Ok(res)
         };
 
@@ -239,16 +794,88 @@ pub struct WorkerEntrypoints {
     pub events: Option<String>,
 }
 
+/// SHA-256 digest of a bearer token. Only the digest is kept, so that the
+/// token isn't held in memory, and compared, as is.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct TokenDigest([u8; 32]);
+
+impl TokenDigest {
+    pub fn of(token: &str) -> Self {
+        let digest = ring::digest::digest(&ring::digest::SHA256, token.as_bytes());
+
+        Self(digest.as_ref().try_into().unwrap())
+    }
+}
+
+impl std::fmt::Debug for TokenDigest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TokenDigest(..)")
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy)]
 pub struct ServerFlags {
     pub no_module_cache: bool,
     pub allow_main_inspector: bool,
     pub tcp_nodelay: bool,
+    pub server_timing: bool,
+    pub watch: bool,
+    pub health_endpoints: bool,
+    pub startup_self_test: bool,
+    /// Keeps `Forwarded`, `X-Forwarded-*`, `X-Real-IP` and `X-Request-Id`
+    /// sent by clients, which are dropped otherwise. Only turn it on behind a
+    /// proxy that sets them.
+    pub trust_forwarded_headers: bool,
+    /// Hands requests to user workers through ops instead of writing them
+    /// out as HTTP for the worker to parse again.
+    pub direct_request_bridge: bool,
     pub graceful_exit_deadline_sec: u64,
     pub graceful_exit_keepalive_deadline_ms: Option<u64>,
     pub request_wait_timeout_ms: Option<u64>,
     pub request_idle_timeout_ms: Option<u64>,
     pub request_read_timeout_ms: Option<u64>,
+    /// Closes a connection once its request body has had nothing new for
+    /// this long while the worker waits on it.
+    pub request_body_idle_timeout_ms: Option<u64>,
+    /// Closes a connection once it has had no request in flight for this
+    /// long.
+    pub keep_alive_timeout_ms: Option<u64>,
+    /// Closes a connection once it has served this many requests.
+    pub max_requests_per_connection: Option<usize>,
+    /// Connections accepted while this many are open are answered with 503
+    /// and closed, unless `overload_queue_timeout_ms` is set.
+    pub max_connections: Option<usize>,
+    /// Requests the listener serves at once, across all connections. Those
+    /// over the limit are answered with 503, unless
+    /// `overload_queue_timeout_ms` is set.
+    pub max_inflight_requests: Option<usize>,
+    /// Queues what is over `max_connections` or `max_inflight_requests`
+    /// instead of turning it away. Requests wait up to this long for a slot,
+    /// and new connections are left in the accept backlog until one frees up.
+    pub overload_queue_timeout_ms: Option<u64>,
+    pub max_live_workers_per_core: Option<usize>,
+    pub max_concurrent_boots: Option<usize>,
+    pub boot_queue_size: Option<usize>,
+    pub boot_queue_timeout_ms: Option<u64>,
+    pub max_concurrent_requests: Option<usize>,
+    pub request_queue_size: Option<usize>,
+    pub request_queue_timeout_ms: Option<u64>,
+    /// Order in which user workers waiting to boot and requests waiting to
+    /// be dispatched get a slot once the limits above are reached.
+    pub scheduling_policy: Option<SchedulingPolicy>,
+    /// Emits a `UsageReport` event per tenant at this interval.
+    pub usage_report_interval_ms: Option<u64>,
+    /// Persists the services of the user worker pool on shutdown, and boots
+    /// them again on startup as this says.
+    pub restore_services: Option<RestoreMode>,
+    /// Serves the operations of the user worker pool over gRPC on this
+    /// address. Requires the `grpc` feature.
+    pub grpc_addr: Option<SocketAddr>,
+    /// Bearer token gRPC clients must send. Without one, gRPC is only served
+    /// on loopback addresses.
+    pub grpc_token: Option<TokenDigest>,
+    /// Settings of the connection requests are written to workers over.
+    pub worker_connection: WorkerConnectionConfig,
 }
 
 #[derive(Debug)]
@@ -320,15 +947,35 @@ impl Tls {
     }
 }
 
+/// A listener served next to the main one. See [`ListenerConfig`].
+struct Listener {
+    addr: SocketAddr,
+    trusted_proxies: Vec<IpNet>,
+    main_worker: MainWorker,
+    ip_access_list: Arc<IpAccessList>,
+    router: Option<Arc<Router>>,
+}
+
 pub struct Server {
     ip: Ipv4Addr,
     port: u16,
     tls: Option<Tls>,
-    main_worker_req_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+    main_worker: MainWorker,
+    listeners: Vec<Listener>,
+    /// Every distinct main worker, that of the main listener first.
+    main_workers: Vec<MainWorker>,
     callback_tx: Option<Sender<ServerHealth>>,
     termination_tokens: TerminationTokens,
+    drain_token: CancellationToken,
+    health_probe: Option<HealthProbe>,
+    cluster_probe: Option<ClusterProbe>,
     flags: ServerFlags,
     metric_src: SharedMetricSource,
+    ip_access_list: Arc<IpAccessList>,
+    /// Peers the main listener reads the PROXY protocol header from. Empty
+    /// unless the header is expected. Its TLS port never reads the header.
+    trusted_proxies: Vec<IpNet>,
+    router: Option<Arc<Router>>,
 }
 
 impl Server {
@@ -350,15 +997,32 @@ impl Server {
         inspector: Option<Inspector>,
         jsx_specifier: Option<String>,
         jsx_module: Option<String>,
+        ip_access_list: IpAccessList,
+        trusted_proxies: Vec<IpNet>,
+        maybe_routing_table: Option<RoutingTable>,
+        event_worker_routes: Vec<EventWorkerRoute>,
+        maybe_admin_socket_path: Option<PathBuf>,
+        listener_configs: Vec<ListenerConfig>,
     ) -> Result<Self, Error> {
-        let mut worker_events_tx: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>> = None;
+        // NOTE: The PROXY header comes before the TLS handshake, which the
+        // secure listener does before handing the connection over.
+        if tls.is_some() && !trusted_proxies.is_empty() {
+            bail!("the PROXY protocol can't be used together with TLS");
+        }
+
+        let mut maybe_default_events_tx = None;
+        let mut event_worker_metric_src = None;
         let maybe_events_entrypoint = entrypoints.events;
         let maybe_main_entrypoint = entrypoints.main;
-        let termination_tokens =
-            TerminationTokens::new(termination_token, maybe_events_service_path.is_some());
+        let termination_tokens = TerminationTokens::new(
+            termination_token,
+            maybe_events_service_path.iter().count() + event_worker_routes.len(),
+        );
+        let mut events_termination_tokens = termination_tokens.events.iter().cloned();
+        let drain_token = CancellationToken::new();
 
         // Create Event Worker
-        let event_worker_metric_src = if let Some(events_service_path) = maybe_events_service_path {
+        if let Some(events_service_path) = maybe_events_service_path {
             let events_path = Path::new(&events_service_path);
             let events_path_buf = events_path.to_path_buf();
 
@@ -368,15 +1032,39 @@ impl Server {
                 flags.no_module_cache,
                 maybe_events_entrypoint,
                 maybe_decorator,
-                Some(termination_tokens.event.clone().unwrap()),
+                events_termination_tokens.next(),
             )
             .await?;
 
-            worker_events_tx = Some(sender);
-            Some(ctx.metric)
-        } else {
-            None
-        };
+            maybe_default_events_tx = Some(sender);
+            event_worker_metric_src = Some(ctx.metric);
+        }
+
+        let mut event_routes = vec![];
+
+        for route in event_worker_routes {
+            let (ctx, sender) = create_events_worker(
+                Path::new(&route.service_path).to_path_buf(),
+                import_map_path.clone(),
+                flags.no_module_cache,
+                None,
+                maybe_decorator,
+                events_termination_tokens.next(),
+            )
+            .await?;
+
+            event_routes.push((route.event_types, sender));
+            event_worker_metric_src.get_or_insert(ctx.metric);
+        }
+
+        let maybe_admin_events_tx = maybe_admin_socket_path
+            .is_some()
+            .then(|| broadcast::channel(ADMIN_EVENTS_CAPACITY).0);
+        let worker_events_tx = crate::event_router::spawn(
+            event_routes,
+            maybe_default_events_tx,
+            maybe_admin_events_tx.clone(),
+        );
 
         let jsx_config = jsx_module.map(|jsx_mod| JsxImportSourceConfig {
             default_specifier: jsx_specifier,
@@ -384,32 +1072,83 @@ impl Server {
             base_url: Url::from_file_path(std::env::current_dir().unwrap()).unwrap(),
         });
 
+        let maybe_cluster = maybe_user_worker_policy
+            .as_ref()
+            .and_then(|it| it.cluster())
+            .map(|it| {
+                let peers = it
+                    .peers
+                    .iter()
+                    .map(|peer| peer.ip().to_canonical())
+                    .collect::<HashSet<_>>();
+
+                (peers, it.key.clone())
+            });
+
         // Create a user worker pool
+        let user_worker_policy = maybe_user_worker_policy.unwrap_or_default();
         let (shared_metric_src, worker_pool_tx) = create_user_worker_pool(
-            maybe_user_worker_policy.unwrap_or_default(),
+            user_worker_policy.clone(),
             worker_events_tx,
             Some(termination_tokens.pool.clone()),
             static_patterns,
             inspector.clone(),
             jsx_config.clone(),
             flags.request_idle_timeout_ms,
+            Some(drain_token.clone()),
         )
         .await?;
 
+        if let Some(addr) = flags.grpc_addr {
+            #[cfg(feature = "grpc")]
+            crate::grpc::serve(
+                addr,
+                flags.grpc_token,
+                worker_pool_tx.clone(),
+                shared_metric_src.clone(),
+                termination_tokens.pool.inbound.clone(),
+            )?;
+
+            #[cfg(not(feature = "grpc"))]
+            bail!(
+                "cannot serve gRPC on {}: the runtime was built without the `grpc` feature",
+                addr
+            );
+        }
+
+        if flags.startup_self_test {
+            crate::self_test::run(user_worker_policy).await?;
+        }
+
+        let maybe_worker_pool_tx = flags.health_endpoints.then(|| worker_pool_tx.clone());
+        let cluster_probe = maybe_cluster.map(|(peers, key)| ClusterProbe {
+            peers: Arc::new(peers),
+            key,
+            worker_pool_tx: worker_pool_tx.clone(),
+        });
+        let router = maybe_routing_table
+            .map(|table| Router::new(table, worker_pool_tx.clone(), flags.no_module_cache))
+            .transpose()?
+            .map(Arc::new);
+
         // create main worker
-        let main_worker_path = Path::new(&main_service_path).to_path_buf();
-        let main_worker_req_tx = create_main_worker(
-            main_worker_path,
-            import_map_path.clone(),
-            flags.no_module_cache,
-            MainWorkerRuntimeOpts {
-                worker_pool_tx,
+        let main_worker_opts = MainWorkerOpts {
+            service_path: Path::new(&main_service_path).to_path_buf(),
+            import_map_path: import_map_path.clone(),
+            no_module_cache: flags.no_module_cache,
+            runtime_opts: MainWorkerRuntimeOpts {
+                worker_pool_tx: worker_pool_tx.clone(),
                 shared_metric_src: Some(shared_metric_src.clone()),
                 event_worker_metric_src,
             },
-            maybe_main_entrypoint,
+            maybe_entrypoint: maybe_main_entrypoint,
             maybe_decorator,
-            Some(termination_tokens.main.clone()),
+            jsx: jsx_config,
+            conn_config: flags.worker_connection,
+            retire_deadline: Duration::from_secs(flags.graceful_exit_deadline_sec),
+        };
+        let main_worker = MainWorker::boot(
+            main_worker_opts.clone(),
             if flags.allow_main_inspector {
                 inspector.map(|it| Inspector {
                     option: InspectorOption::Inspect(it.option.socket_addr()),
@@ -418,31 +1157,125 @@ impl Server {
             } else {
                 None
             },
-            jsx_config,
         )
         .await?;
 
+        hibernation::set_main_worker(main_worker.clone());
+
+        let mut listeners = vec![];
+        let mut main_workers = vec![main_worker.clone()];
+
+        for config in listener_configs {
+            let main_worker = match config.maybe_main_service_path {
+                Some(path) => {
+                    let main_worker = MainWorker::boot(
+                        MainWorkerOpts {
+                            service_path: PathBuf::from(path),
+                            maybe_entrypoint: None,
+                            ..main_worker_opts.clone()
+                        },
+                        None,
+                    )
+                    .await?;
+
+                    main_workers.push(main_worker.clone());
+                    main_worker
+                }
+
+                None => main_worker.clone(),
+            };
+
+            let router = config
+                .maybe_routes_path
+                .map(RoutingTable::from_file)
+                .transpose()?
+                .map(|table| Router::new(table, worker_pool_tx.clone(), flags.no_module_cache))
+                .transpose()?
+                .map(Arc::new);
+
+            listeners.push(Listener {
+                addr: config.addr,
+                trusted_proxies: config.trusted_proxies,
+                main_worker,
+                ip_access_list: Arc::new(config.ip_access_list),
+                router,
+            });
+        }
+
+        if let Some((path, events_tx)) = maybe_admin_socket_path.zip(maybe_admin_events_tx) {
+            #[cfg(unix)]
+            crate::admin::serve(
+                path,
+                worker_pool_tx,
+                main_workers.clone(),
+                shared_metric_src.clone(),
+                events_tx,
+                termination_tokens.pool.inbound.clone(),
+            )?;
+
+            #[cfg(not(unix))]
+            bail!(
+                "cannot serve the admin console on {}: Unix sockets are not supported",
+                path.display()
+            );
+        }
+
         let ip = Ipv4Addr::from_str(ip)?;
+        let health_probe = maybe_worker_pool_tx.map(|worker_pool_tx| HealthProbe {
+            main_worker: main_worker.clone(),
+            worker_pool_tx,
+            drain_token: drain_token.clone(),
+        });
 
         Ok(Self {
             ip,
             port,
             tls,
-            main_worker_req_tx,
+            main_worker,
+            listeners,
+            main_workers,
             callback_tx,
             termination_tokens,
+            drain_token,
+            health_probe,
+            cluster_probe,
             flags,
             metric_src: shared_metric_src,
+            ip_access_list: Arc::new(ip_access_list),
+            trusted_proxies,
+            router,
         })
     }
 
     pub async fn terminate(&self) {
-        self.termination_tokens.terminate().await;
+        self.termination_tokens.terminate(&self.main_workers).await;
+    }
+
+    /// Boots the main workers again and swaps them in for the current ones,
+    /// which are terminated once the requests they were serving are done.
+    pub async fn reload_main_workers(&self) -> Result<(), Error> {
+        for main_worker in &self.main_workers {
+            main_worker.reload().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts draining the runtime. See [`Server::listen`] for what happens
+    /// afterwards.
+    pub fn drain(&self) {
+        self.drain_token.cancel();
     }
 
+    /// Serves connections until the runtime is terminated or drained. Once
+    /// draining, no more connections are accepted, and the runtime exits as
+    /// soon as the requests in flight and the user workers are done, or the
+    /// graceful exit deadline passes.
     pub async fn listen(&mut self) -> Result<(), Error> {
         let addr = SocketAddr::new(IpAddr::V4(self.ip), self.port);
         let non_secure_listener = TcpListener::bind(&addr).await?;
+        let non_secure_addr = non_secure_listener.local_addr()?;
+        let mut non_secure_listener = Some(non_secure_listener);
         let mut secure_listener = if let Some(tls) = self.tls.take() {
             let addr = SocketAddr::new(IpAddr::V4(self.ip), tls.port);
             Some((
@@ -453,24 +1286,41 @@ impl Server {
             None
         };
 
+        let mut other_listeners = vec![];
+
+        for listener in &self.listeners {
+            let tcp_listener = TcpListener::bind(listener.addr).await?;
+            let addr = tcp_listener.local_addr()?;
+
+            other_listeners.push((tcp_listener, addr, listener));
+        }
+
         let metric_src = self.metric_src.clone();
         let termination_tokens = &self.termination_tokens;
+        let main_workers = &self.main_workers;
         let input_termination_token = termination_tokens.input.as_ref();
+        let drain_token = self.drain_token.clone();
+        let health_probe = self.health_probe.clone();
+        let cluster_probe = self.cluster_probe.clone();
+        let ip_access_list = self.ip_access_list.clone();
+        let trusted_proxies = &self.trusted_proxies;
+        let router = self.router.clone();
         let flags = self.flags;
 
         let mut can_receive_event = false;
         let mut interrupted = false;
         let (event_tx, event_rx) = mpsc::unbounded_channel();
 
-        debug!(
-            "edge-runtime is listening on {:?}",
-            non_secure_listener.local_addr()?
-        );
+        debug!("edge-runtime is listening on {:?}", non_secure_addr);
 
         if let Some((_, addr)) = secure_listener.as_ref() {
             debug!("edge-runtime is listening on {:?} (secure)", addr);
         }
 
+        for (_, addr, _) in &other_listeners {
+            debug!("edge-runtime is listening on {:?}", addr);
+        }
+
         if let Some(callback) = self.callback_tx.clone() {
             can_receive_event = true;
             let _ = callback
@@ -483,62 +1333,209 @@ impl Server {
 
         let ServerFlags {
             tcp_nodelay,
+            trust_forwarded_headers,
             request_read_timeout_ms,
+            request_body_idle_timeout_ms,
+            keep_alive_timeout_ms,
+            max_requests_per_connection,
+            max_connections,
+            max_inflight_requests,
+            overload_queue_timeout_ms,
             mut graceful_exit_deadline_sec,
             mut graceful_exit_keepalive_deadline_ms,
             ..
         } = flags;
 
+        let strip_forwarded_headers = !trust_forwarded_headers;
         let request_read_timeout_dur = request_read_timeout_ms.map(Duration::from_millis);
+        let overload_queue_timeout_dur = overload_queue_timeout_ms.map(Duration::from_millis);
+        let maybe_conn_limiter =
+            max_connections.map(|it| OverloadLimiter::new(it, overload_queue_timeout_dur));
+        let conn_policy = ConnectionPolicy {
+            keep_alive_timeout_dur: keep_alive_timeout_ms.map(Duration::from_millis),
+            max_requests: max_requests_per_connection,
+            maybe_request_limiter: max_inflight_requests
+                .map(|it| OverloadLimiter::new(it, overload_queue_timeout_dur)),
+            body_idle_timeout_dur: request_body_idle_timeout_ms.map(Duration::from_millis),
+        };
         let mut terminate_signal_fut = get_termination_signal();
+        let mut drain_signal_fut = get_drain_signal();
+        let mut reload_signal_fut = get_reload_signal();
+        let mut drained_fut = pending::<()>().boxed();
+        let mut draining = false;
 
         loop {
-            let main_worker_req_tx = self.main_worker_req_tx.clone();
+            let main_worker = self.main_worker.clone();
             let event_tx = event_tx.clone();
             let metric_src = metric_src.clone();
 
             tokio::select! {
-                msg = non_secure_listener.accept() => {
+                (msg, maybe_conn_permit) = async {
+                    let Some(listener) = non_secure_listener.as_ref() else {
+                        pending::<()>().await;
+                        unreachable!();
+                    };
+
+                    let maybe_conn_permit = wait_for_connection_slot(maybe_conn_limiter.as_ref()).await;
+
+                    (listener.accept().await, maybe_conn_permit)
+                }, if !draining => {
                     match msg {
-                        Ok((stream, _)) => {
+                        Ok((stream, client_addr)) => {
+                            let Ok(maybe_conn_permit) = take_connection_slot(
+                                maybe_conn_permit,
+                                maybe_conn_limiter.as_ref(),
+                            ) else {
+                                metric_src.incl_rejected_connections();
+                                reject_stream(stream);
+                                continue;
+                            };
+
                             if tcp_nodelay {
                                 let _ = stream.set_nodelay(true);
                             }
 
+                            let conn_info = ConnectionInfo {
+                                remote_addr: client_addr,
+                                local_addr: non_secure_addr,
+                                tls: None,
+                            };
+
                             accept_stream(
                                 stream,
-                                main_worker_req_tx,
+                                main_worker,
                                 event_tx,
                                 metric_src,
                                 graceful_exit_token.clone(),
-                                request_read_timeout_dur
+                                drain_token.clone(),
+                                health_probe.clone(),
+                                cluster_probe.clone(),
+                                conn_info,
+                                proxy_protocol::is_trusted(trusted_proxies, client_addr.ip()),
+                                strip_forwarded_headers,
+                                ip_access_list.clone(),
+                                router.clone(),
+                                request_read_timeout_dur,
+                                conn_policy.clone(),
+                                maybe_conn_permit
                             )
                         }
                         Err(e) => error!("socket error: {}", e)
                     }
                 }
 
-                msg = async {
-                    if let Some((listener, _addr)) = secure_listener.as_mut() {
-                        listener.accept()
-                    } else {
+                (msg, maybe_conn_permit, secure_addr) = async {
+                    let Some((listener, addr)) = secure_listener.as_mut() else {
                         pending::<()>().await;
                         unreachable!();
-                    }.await
-                } => {
+                    };
+
+                    let maybe_conn_permit = wait_for_connection_slot(maybe_conn_limiter.as_ref()).await;
+
+                    (listener.accept().await, maybe_conn_permit, *addr)
+                }, if !draining => {
                     match msg {
-                        Ok((stream, _)) => {
+                        Ok((stream, client_addr)) => {
+                            let Ok(maybe_conn_permit) = take_connection_slot(
+                                maybe_conn_permit,
+                                maybe_conn_limiter.as_ref(),
+                            ) else {
+                                metric_src.incl_rejected_connections();
+                                reject_stream(stream);
+                                continue;
+                            };
+
                             if tcp_nodelay {
                                 let _ = stream.get_ref().0.set_nodelay(true);
                             }
 
+                            let conn_info = ConnectionInfo {
+                                remote_addr: client_addr,
+                                local_addr: secure_addr,
+                                tls: Some(tls_info(stream.get_ref().1)),
+                            };
+
+                            accept_stream(
+                                stream,
+                                main_worker,
+                                event_tx,
+                                metric_src,
+                                graceful_exit_token.clone(),
+                                drain_token.clone(),
+                                health_probe.clone(),
+                                cluster_probe.clone(),
+                                conn_info,
+                                false,
+                                strip_forwarded_headers,
+                                ip_access_list.clone(),
+                                router.clone(),
+                                request_read_timeout_dur,
+                                conn_policy.clone(),
+                                maybe_conn_permit
+                            )
+                        }
+                        Err(e) => error!("socket error: {}", e)
+                    }
+                }
+
+                (msg, maybe_conn_permit, local_addr, listener) = async {
+                    if other_listeners.is_empty() {
+                        pending::<()>().await;
+                        unreachable!();
+                    }
+
+                    let maybe_conn_permit = wait_for_connection_slot(maybe_conn_limiter.as_ref()).await;
+                    let (msg, idx, _) = select_all(
+                        other_listeners.iter().map(|(it, _, _)| it.accept().boxed())
+                    ).await;
+                    let (_, addr, listener) = &other_listeners[idx];
+
+                    (msg, maybe_conn_permit, *addr, *listener)
+                }, if !draining => {
+                    match msg {
+                        Ok((stream, client_addr)) => {
+                            let Ok(maybe_conn_permit) = take_connection_slot(
+                                maybe_conn_permit,
+                                maybe_conn_limiter.as_ref(),
+                            ) else {
+                                metric_src.incl_rejected_connections();
+                                reject_stream(stream);
+                                continue;
+                            };
+
+                            if tcp_nodelay {
+                                let _ = stream.set_nodelay(true);
+                            }
+
+                            let conn_info = ConnectionInfo {
+                                remote_addr: client_addr,
+                                local_addr,
+                                tls: None,
+                            };
+
                             accept_stream(
                                 stream,
-                                main_worker_req_tx,
+                                listener.main_worker.clone(),
                                 event_tx,
                                 metric_src,
                                 graceful_exit_token.clone(),
-                                request_read_timeout_dur
+                                drain_token.clone(),
+                                health_probe.as_ref().map(|it| HealthProbe {
+                                    main_worker: listener.main_worker.clone(),
+                                    ..it.clone()
+                                }),
+                                cluster_probe.clone(),
+                                conn_info,
+                                proxy_protocol::is_trusted(
+                                    &listener.trusted_proxies,
+                                    client_addr.ip(),
+                                ),
+                                strip_forwarded_headers,
+                                listener.ip_access_list.clone(),
+                                listener.router.clone(),
+                                request_read_timeout_dur,
+                                conn_policy.clone(),
+                                maybe_conn_permit
                             )
                         }
                         Err(e) => error!("socket error: {}", e)
@@ -563,6 +1560,46 @@ impl Server {
                     break;
                 }
 
+                signum = &mut drain_signal_fut, if !draining => {
+                    info!("drain signal received: {}", signum);
+                    drain_token.cancel();
+                }
+
+                signum = &mut reload_signal_fut => {
+                    info!("reload signal received: {}", signum);
+                    reload_signal_fut = get_reload_signal();
+
+                    for main_worker in main_workers.iter().cloned() {
+                        drop(tokio::spawn(async move {
+                            if let Err(err) = main_worker.reload().await {
+                                error!("failed to reload the main worker: {:?}", err);
+                            }
+                        }));
+                    }
+                }
+
+                _ = drain_token.cancelled(), if !draining => {
+                    info!("runtime is draining");
+                    draining = true;
+
+                    // NOTE: Closing the listeners has new connections refused
+                    // right away instead of left in the backlog, so that a
+                    // load balancer can move them to another instance.
+                    non_secure_listener = None;
+                    secure_listener = None;
+                    other_listeners.clear();
+
+                    drained_fut = wait_for_drained(
+                        metric_src.clone(),
+                        graceful_exit_deadline_sec
+                    ).boxed();
+                }
+
+                _ = &mut drained_fut => {
+                    info!("runtime has been drained");
+                    break;
+                }
+
                 signum = &mut terminate_signal_fut => {
                     info!("shutdown signal received: {}", signum);
                     break;
@@ -640,7 +1677,7 @@ impl Server {
                     sleep(REQ_METRIC_CHECK_SLEEP_DUR).await;
                 }
 
-                termination_tokens.terminate().await;
+                termination_tokens.terminate(main_workers).await;
             };
 
             let timeout_fut = timeout(Duration::from_secs(graceful_exit_deadline_sec), wait_fut);
@@ -701,30 +1738,243 @@ fn get_termination_signal() -> BoxFuture<'static, i32> {
     pending().boxed()
 }
 
+#[cfg(unix)]
+fn get_drain_signal() -> BoxFuture<'static, i32> {
+    use signal::unix::signal;
+    use signal::unix::SignalKind;
+
+    let kind = SignalKind::user_defined2();
+    let mut signal = signal(kind).unwrap();
+
+    async move {
+        signal.recv().await;
+        kind.as_raw_value()
+    }
+    .boxed()
+}
+
+#[cfg(not(unix))]
+fn get_drain_signal() -> BoxFuture<'static, i32> {
+    pending().boxed()
+}
+
+#[cfg(unix)]
+fn get_reload_signal() -> BoxFuture<'static, i32> {
+    use signal::unix::signal;
+    use signal::unix::SignalKind;
+
+    let kind = SignalKind::hangup();
+    let mut signal = signal(kind).unwrap();
+
+    async move {
+        signal.recv().await;
+        kind.as_raw_value()
+    }
+    .boxed()
+}
+
+#[cfg(not(unix))]
+fn get_reload_signal() -> BoxFuture<'static, i32> {
+    pending().boxed()
+}
+
+/// How long a drain without a deadline waits for the requests in flight.
+const DRAIN_INFLIGHT_DEADLINE: Duration = Duration::from_secs(60);
+
+/// Resolves once every user worker has exited and no request is in flight,
+/// or once `deadline_sec` elapses. A zero deadline only waits for the
+/// in-flight requests, for up to [`DRAIN_INFLIGHT_DEADLINE`].
+async fn wait_for_drained(metric_src: SharedMetricSource, deadline_sec: u64) {
+    let wait_for_pool = deadline_sec > 0;
+    let deadline = if wait_for_pool {
+        Duration::from_secs(deadline_sec)
+    } else {
+        DRAIN_INFLIGHT_DEADLINE
+    };
+
+    if wait_until_drained(&metric_src, wait_for_pool, deadline).await {
+        return;
+    }
+
+    if wait_for_pool {
+        warn!(
+            "user workers did not exit within {} seconds while draining",
+            deadline_sec
+        );
+    } else {
+        warn!(
+            "requests in flight did not finish within {} seconds while draining",
+            deadline.as_secs()
+        );
+    }
+}
+
+/// Returns `false` if the runtime did not drain within `deadline`.
+async fn wait_until_drained(
+    metric_src: &SharedMetricSource,
+    wait_for_pool: bool,
+    deadline: Duration,
+) -> bool {
+    static DRAIN_CHECK_SLEEP_DUR: Duration = Duration::from_millis(100);
+
+    timeout(deadline, async {
+        loop {
+            let no_inflight = metric_src.received_requests() == metric_src.handled_requests();
+            let no_workers = !wait_for_pool || metric_src.active_user_workers() == 0;
+
+            if no_inflight && no_workers {
+                break;
+            }
+
+            sleep(DRAIN_CHECK_SLEEP_DUR).await;
+        }
+    })
+    .await
+    .is_ok()
+}
+
+/// Waits for a connection slot before accepting, if connections over the
+/// limit are to be queued.
+async fn wait_for_connection_slot(
+    maybe_limiter: Option<&OverloadLimiter>,
+) -> Option<OwnedSemaphorePermit> {
+    match maybe_limiter {
+        Some(limiter) if limiter.is_queued() => {
+            limiter.semaphore.clone().acquire_owned().await.ok()
+        }
+        _ => None,
+    }
+}
+
+/// Returns the slot of a connection that has just been accepted, or `Err` if
+/// there is none left.
+fn take_connection_slot(
+    maybe_permit: Option<OwnedSemaphorePermit>,
+    maybe_limiter: Option<&OverloadLimiter>,
+) -> Result<Option<OwnedSemaphorePermit>, ()> {
+    match (maybe_permit, maybe_limiter) {
+        (Some(permit), _) => Ok(Some(permit)),
+        (None, Some(limiter)) => limiter
+            .semaphore
+            .clone()
+            .try_acquire_owned()
+            .map(Some)
+            .map_err(|_| ()),
+
+        (None, None) => Ok(None),
+    }
+}
+
+/// Answers every request of `io` with 503 and closes it.
+fn reject_stream<I>(io: I)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::task::spawn(async move {
+        let conn_fut = Http::new().http1_keep_alive(false).serve_connection(
+            io,
+            service_fn(|_| async { Ok::<_, Infallible>(overloaded_response()) }),
+        );
+
+        let _ = timeout(Duration::from_secs(5), conn_fut).await;
+    });
+}
+
+fn tls_info(conn: &ServerConnection) -> TlsInfo {
+    TlsInfo {
+        server_name: conn.server_name().map(str::to_string),
+        protocol: conn.protocol_version().map(|it| format!("{:?}", it)),
+        alpn_protocol: conn
+            .alpn_protocol()
+            .map(|it| String::from_utf8_lossy(it).into_owned()),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn accept_stream<I>(
-    io: I,
-    req_tx: UnboundedSender<WorkerRequestMsg>,
+    mut io: I,
+    main_worker: MainWorker,
     event_tx: Option<UnboundedSender<ServerEvent>>,
     metric_src: SharedMetricSource,
     graceful_exit_token: CancellationToken,
+    drain_token: CancellationToken,
+    health_probe: Option<HealthProbe>,
+    cluster_probe: Option<ClusterProbe>,
+    mut conn_info: ConnectionInfo,
+    expect_proxy_header: bool,
+    strip_forwarded_headers: bool,
+    ip_access_list: Arc<IpAccessList>,
+    router: Option<Arc<Router>>,
     maybe_req_read_timeout_dur: Option<Duration>,
+    conn_policy: ConnectionPolicy,
+    maybe_conn_permit: Option<OwnedSemaphorePermit>,
 ) where
     I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     metric_src.incl_active_io();
+    metric_src.incl_accepted_connections();
     tokio::task::spawn({
         async move {
-            let (service, cancel) = WorkerService::new(metric_src.clone(), req_tx);
+            let _active_io_count_guard = scopeguard::guard(metric_src.clone(), |it| {
+                it.decl_active_io();
+            });
+
+            if expect_proxy_header {
+                match timeout(
+                    PROXY_HEADER_READ_TIMEOUT,
+                    proxy_protocol::read_header(&mut io),
+                )
+                .await
+                {
+                    Ok(Ok(maybe_client_addr)) => {
+                        if let Some(addr) = maybe_client_addr {
+                            conn_info.remote_addr = addr;
+                        }
+                    }
+
+                    Ok(Err(err)) => {
+                        debug!("invalid PROXY protocol header ({:?})", err);
+                        return;
+                    }
+
+                    Err(_) => {
+                        metric_src.incl_timed_out_header_reads();
+                        debug!("PROXY protocol header read timed out");
+                        return;
+                    }
+                }
+            }
+
+            let client_ip = conn_info.remote_addr.ip();
+            let (service, cancel) = WorkerService::new(
+                metric_src.clone(),
+                main_worker,
+                drain_token,
+                health_probe,
+                cluster_probe,
+                conn_info,
+                strip_forwarded_headers,
+                ip_access_list,
+                router,
+            );
             let (io, maybe_timeout_tx) = if let Some(timeout_dur) = maybe_req_read_timeout_dur {
                 crate::timeout::Stream::with_timeout(io, timeout_dur)
             } else {
                 crate::timeout::Stream::with_bypass(io)
             };
 
+            let _conn_permit = maybe_conn_permit;
+            let conn_state = Arc::new(ConnectionState::default());
+            let service = ConnectionTracker {
+                inner: service,
+                state: conn_state.clone(),
+                client_ip,
+                maybe_request_limiter: conn_policy.maybe_request_limiter.clone(),
+                maybe_body_idle_timeout_dur: conn_policy.body_idle_timeout_dur,
+                metric_src: metric_src.clone(),
+            };
+
             let _guard = cancel.drop_guard();
-            let _active_io_count_guard = scopeguard::guard(metric_src, |it| {
-                it.decl_active_io();
-            });
 
             let mut shutting_down = false;
             let conn_fut = Http::new()
@@ -736,10 +1986,21 @@ fn accept_stream<I>(
             let conn_result = loop {
                 tokio::select! {
                     res = conn_fut.as_mut() => break res,
+                    _ = conn_state.abort.cancelled() => {
+                        debug!("connection aborted");
+                        break Ok(());
+                    }
+
                     _ = graceful_exit_token.cancelled(), if !shutting_down => {
                         shutting_down = true;
                         conn_fut.as_mut().graceful_shutdown();
                     }
+
+                    // NOTE: An in-flight request is still served to the end.
+                    _ = conn_state.expired(&conn_policy), if !shutting_down => {
+                        shutting_down = true;
+                        conn_fut.as_mut().graceful_shutdown();
+                    }
                 }
             };
 
@@ -748,6 +2009,9 @@ fn accept_stream<I>(
                 // the connection before we could send a response
                 if e.is_incomplete_message() {
                     debug!("connection reset ({:?})", e);
+                } else if is_header_read_timeout(&e) {
+                    metric_src.incl_timed_out_header_reads();
+                    debug!("request header read timed out ({:?})", e);
                 } else {
                     error!("client connection error ({:?})", e);
                 }
@@ -759,3 +2023,31 @@ fn accept_stream<I>(
         }
     });
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drain_gives_up_on_requests_that_never_finish() {
+        let metric_src = SharedMetricSource::default();
+
+        metric_src.incl_received_requests();
+
+        assert!(!wait_until_drained(&metric_src, false, Duration::from_millis(300)).await);
+
+        metric_src.incl_handled_requests();
+
+        assert!(wait_until_drained(&metric_src, false, Duration::from_millis(300)).await);
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_user_workers_only_with_a_deadline() {
+        let metric_src = SharedMetricSource::default();
+
+        metric_src.incl_active_user_workers();
+
+        assert!(wait_until_drained(&metric_src, false, Duration::from_millis(300)).await);
+        assert!(!wait_until_drained(&metric_src, true, Duration::from_millis(300)).await);
+    }
+}
@@ -0,0 +1,475 @@
+//! Lets the path of a service be a `.tar.gz`, `.tgz` or `.zip` archive of
+//! it, as control planes ship them. The archive is extracted into a cache
+//! folder named after its checksum, so workers booted from the same archive
+//! share a single extraction.
+//!
+//! NOTE: Archives come from outside of the runtime, so the entries and bytes
+//! unpacked from a single one are capped, and entries or links leading out of
+//! the extraction are refused.
+//!
+//! NOTE: Extractions are never pruned, since workers may still import from an
+//! extraction long after they booted. The cache grows by one folder per
+//! distinct archive, and lives in the `service_archive_v1` folder of the
+//! `DENO_DIR`, which can be cleared while no runtime is running.
+
+use std::io::{Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Error};
+use flate2::read::GzDecoder;
+use log::debug;
+use sb_core::cache::deno_dir::DenoDir;
+use sb_core::util::checksum;
+
+const ARCHIVE_EXTENSIONS: &[&str] = &[".tar.gz", ".tgz", ".zip"];
+const MAX_UNPACKED_ENTRIES: usize = 10_000;
+const MAX_UNPACKED_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Whether the service at `service_path` is an archive.
+pub(crate) fn is_archive(service_path: &Path) -> bool {
+    service_path
+        .to_str()
+        .map(|it| ARCHIVE_EXTENSIONS.iter().any(|ext| it.ends_with(ext)))
+        .unwrap_or_default()
+}
+
+/// Extracts the archive at `archive_path`, unless it was already, and
+/// returns the folder to boot the service from.
+pub(crate) async fn extract(archive_path: &Path) -> Result<PathBuf, Error> {
+    let archive_path = archive_path.to_path_buf();
+
+    tokio::task::spawn_blocking(move || extract_blocking(&archive_path)).await?
+}
+
+fn extract_blocking(archive_path: &Path) -> Result<PathBuf, Error> {
+    let bin = std::fs::read(archive_path)
+        .with_context(|| format!("can't read service archive {}", archive_path.display()))?;
+
+    let cache_dir = DenoDir::new(None)
+        .map(|it| it.service_archive_folder_path())
+        .unwrap_or_else(|_| std::env::temp_dir().join("sb_service_archive"));
+
+    let dir = cache_dir.join(checksum::gen(&[&bin]));
+
+    if !dir.exists() {
+        // NOTE: The archive is extracted next to its final folder and moved
+        // in place once complete, so that a concurrent boot of the same
+        // archive never sees it half extracted.
+        let tmp_dir = cache_dir.join(format!(".tmp-{}", uuid::Uuid::new_v4()));
+        let result = unpack(archive_path, &bin, &tmp_dir, UnpackBudget::default())
+            .and_then(|_| std::fs::rename(&tmp_dir, &dir).map_err(Error::from));
+
+        if result.is_err() {
+            let _ = std::fs::remove_dir_all(&tmp_dir);
+        }
+
+        // Another boot may have moved its own extraction in place first.
+        if !dir.exists() {
+            result.with_context(|| {
+                format!("can't extract service archive {}", archive_path.display())
+            })?;
+        }
+    } else {
+        debug!("service archive is already extracted: {}", dir.display());
+    }
+
+    Ok(service_root(dir))
+}
+
+fn unpack(
+    archive_path: &Path,
+    bin: &[u8],
+    dir: &Path,
+    mut budget: UnpackBudget,
+) -> Result<(), Error> {
+    std::fs::create_dir_all(dir)?;
+
+    if archive_path.to_string_lossy().ends_with(".zip") {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bin))?;
+
+        for idx in 0..archive.len() {
+            budget.entry()?;
+
+            let file = archive.by_index(idx)?;
+            let Some(path) = file.enclosed_name().map(|it| dir.join(it)) else {
+                bail!("archive entry is outside of the archive: {}", file.name());
+            };
+
+            if file.is_dir() {
+                std::fs::create_dir_all(&path)?;
+            } else {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+
+                // NOTE: The sizes in the central directory can't be trusted,
+                // so one byte past the budget is read to tell it was exceeded.
+                let written = std::io::copy(
+                    &mut file.take(budget.remaining() + 1),
+                    &mut std::fs::File::create(&path)?,
+                )?;
+
+                budget.bytes(written)?;
+            }
+        }
+    } else {
+        let mut archive = tar::Archive::new(GzDecoder::new(bin));
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+
+            budget.entry()?;
+
+            // NOTE: Sparse entries unpack to more than their recorded size.
+            if entry.header().entry_type().is_gnu_sparse() {
+                bail!("archive entry is sparse: {}", entry.path()?.display());
+            }
+
+            let path = entry.path()?.into_owned();
+
+            if !is_enclosed(&path) {
+                bail!(
+                    "archive entry is outside of the archive: {}",
+                    path.display()
+                );
+            }
+
+            // NOTE: `unpack_in` creates links as they are, so one could let
+            // the worker read anything on the host. Symbolic links are
+            // relative to the folder of the entry, hard links to the root of
+            // the archive.
+            let entry_type = entry.header().entry_type();
+
+            if entry_type.is_symlink() || entry_type.is_hard_link() {
+                let Some(target) = entry.link_name()? else {
+                    bail!("archive link has no target: {}", path.display());
+                };
+
+                let resolved = if entry_type.is_symlink() {
+                    path.parent().unwrap_or(Path::new("")).join(&target)
+                } else {
+                    target.to_path_buf()
+                };
+
+                if !is_enclosed(&resolved) {
+                    bail!(
+                        "archive link points outside of the archive: {} -> {}",
+                        path.display(),
+                        target.display()
+                    );
+                }
+            }
+
+            budget.bytes(entry.size())?;
+            entry.unpack_in(dir)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `path`, relative to the root of the archive, stays inside of it.
+fn is_enclosed(path: &Path) -> bool {
+    let mut depth = 0usize;
+
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(it) => depth = it,
+                None => return false,
+            },
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+
+    true
+}
+
+struct UnpackBudget {
+    entries: usize,
+    bytes: u64,
+    max_entries: usize,
+    max_bytes: u64,
+}
+
+impl Default for UnpackBudget {
+    fn default() -> Self {
+        Self::new(MAX_UNPACKED_ENTRIES, MAX_UNPACKED_BYTES)
+    }
+}
+
+impl UnpackBudget {
+    fn new(max_entries: usize, max_bytes: u64) -> Self {
+        Self {
+            entries: 0,
+            bytes: 0,
+            max_entries,
+            max_bytes,
+        }
+    }
+
+    fn entry(&mut self) -> Result<(), Error> {
+        self.entries += 1;
+
+        if self.entries > self.max_entries {
+            bail!("archive has more than {} entries", self.max_entries);
+        }
+
+        Ok(())
+    }
+
+    fn bytes(&mut self, len: u64) -> Result<(), Error> {
+        self.bytes = self.bytes.saturating_add(len);
+
+        if self.bytes > self.max_bytes {
+            bail!("archive unpacks to more than {} bytes", self.max_bytes);
+        }
+
+        Ok(())
+    }
+
+    fn remaining(&self) -> u64 {
+        self.max_bytes.saturating_sub(self.bytes)
+    }
+}
+
+/// Archives often wrap the service in a single folder. Boots from that
+/// folder then.
+fn service_root(dir: PathBuf) -> PathBuf {
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return dir;
+    };
+
+    let entries = entries.flatten().collect::<Vec<_>>();
+
+    match entries.as_slice() {
+        [only] if only.path().is_dir() => only.path(),
+        _ => dir,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tar::EntryType;
+
+    use super::*;
+
+    /// Builds a `.tar.gz` of `(path, type, data or link target)` entries. The
+    /// paths are written as they are, even those `tar` would refuse.
+    fn tar_gz(entries: &[(&str, EntryType, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+
+        for (path, entry_type, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            let data = if entry_type.is_file() {
+                data.as_bytes()
+            } else {
+                header.set_link_name(data).unwrap();
+                &[]
+            };
+
+            header.as_gnu_mut().unwrap().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn zip(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+
+        for (path, data) in entries {
+            writer
+                .start_file(*path, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(data.as_bytes()).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Unpacks into a folder of its own inside a scratch folder, so that
+    /// anything escaping the extraction can be seen.
+    fn unpack_with(name: &str, bin: &[u8], budget: UnpackBudget) -> (Result<(), Error>, PathBuf) {
+        let scratch = std::env::temp_dir().join(format!("archive-{}", uuid::Uuid::new_v4()));
+        let result = unpack(Path::new(name), bin, &scratch.join("out"), budget);
+
+        (result, scratch)
+    }
+
+    #[tokio::test]
+    async fn test_extract_dedups_by_checksum() {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        let code = format!("// {}\nexport {{}}", uuid::Uuid::new_v4());
+        let mut header = tar::Header::new_gnu();
+
+        header.set_size(code.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "service/index.ts", code.as_bytes())
+            .unwrap();
+
+        let bin = builder.into_inner().unwrap().finish().unwrap();
+        let archive_dir = std::env::temp_dir().join(format!("archive-{}", uuid::Uuid::new_v4()));
+        let first = archive_dir.join("first.tar.gz");
+        let second = archive_dir.join("second.tgz");
+
+        std::fs::create_dir_all(&archive_dir).unwrap();
+        std::fs::File::create(&first)
+            .unwrap()
+            .write_all(&bin)
+            .unwrap();
+        std::fs::copy(&first, &second).unwrap();
+
+        assert!(is_archive(&first) && is_archive(&second));
+        assert!(!is_archive(Path::new("./examples/main")));
+
+        let dir = extract(&first).await.unwrap();
+
+        assert_eq!(dir, extract(&second).await.unwrap());
+        assert!(dir.ends_with("service"));
+        assert_eq!(std::fs::read_to_string(dir.join("index.ts")).unwrap(), code);
+
+        std::fs::remove_dir_all(&archive_dir).unwrap();
+        std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_unpack_caps_entries() {
+        let files = [("a.ts", "a"), ("b.ts", "b"), ("c.ts", "c")];
+        let tar_files = files.map(|(path, data)| (path, EntryType::Regular, data));
+
+        for (name, bin) in [
+            ("service.zip", zip(&files)),
+            ("service.tar.gz", tar_gz(&tar_files)),
+        ] {
+            let (result, scratch) = unpack_with(name, &bin, UnpackBudget::new(2, 1024));
+
+            assert!(
+                result
+                    .unwrap_err()
+                    .to_string()
+                    .contains("more than 2 entries"),
+                "{}",
+                name
+            );
+
+            std::fs::remove_dir_all(scratch).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_unpack_caps_bytes() {
+        for (name, bin) in [
+            ("service.zip", zip(&[("index.ts", "0123456789")])),
+            (
+                "service.tar.gz",
+                tar_gz(&[("index.ts", EntryType::Regular, "0123456789")]),
+            ),
+        ] {
+            let (result, scratch) = unpack_with(name, &bin, UnpackBudget::new(10, 8));
+
+            assert!(
+                result
+                    .unwrap_err()
+                    .to_string()
+                    .contains("more than 8 bytes"),
+                "{}",
+                name
+            );
+
+            std::fs::remove_dir_all(scratch).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_unpack_refuses_traversal() {
+        for (name, bin) in [
+            ("service.zip", zip(&[("../evil.ts", "evil")])),
+            (
+                "service.tar.gz",
+                tar_gz(&[("../evil.ts", EntryType::Regular, "evil")]),
+            ),
+        ] {
+            let (result, scratch) = unpack_with(name, &bin, UnpackBudget::default());
+
+            assert!(
+                result
+                    .unwrap_err()
+                    .to_string()
+                    .contains("outside of the archive"),
+                "{}",
+                name
+            );
+            assert!(!scratch.join("evil.ts").exists(), "{}", name);
+
+            std::fs::remove_dir_all(scratch).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_unpack_refuses_links_out_of_the_archive() {
+        for link in [
+            ("passwd", EntryType::Symlink, "/etc/passwd"),
+            ("sub/passwd", EntryType::Symlink, "../../passwd"),
+            ("passwd", EntryType::Link, "/etc/passwd"),
+        ] {
+            let bin = tar_gz(&[link]);
+            let (result, scratch) = unpack_with("service.tar.gz", &bin, UnpackBudget::default());
+
+            assert!(
+                result
+                    .unwrap_err()
+                    .to_string()
+                    .contains("points outside of the archive"),
+                "{:?}",
+                link
+            );
+
+            std::fs::remove_dir_all(scratch).unwrap();
+        }
+
+        let bin = tar_gz(&[
+            ("lib/mod.ts", EntryType::Regular, "export {}"),
+            ("sub/mod.ts", EntryType::Symlink, "../lib/mod.ts"),
+        ]);
+        let (result, scratch) = unpack_with("service.tar.gz", &bin, UnpackBudget::default());
+
+        result.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(scratch.join("out/sub/mod.ts")).unwrap(),
+            "export {}"
+        );
+
+        std::fs::remove_dir_all(scratch).unwrap();
+    }
+
+    #[test]
+    fn test_unpack_budget() {
+        let mut budget = UnpackBudget::default();
+
+        budget.bytes(MAX_UNPACKED_BYTES - 1).unwrap();
+        assert_eq!(budget.remaining(), 1);
+        budget.bytes(1).unwrap();
+        assert!(budget.bytes(1).is_err());
+
+        let mut budget = UnpackBudget::default();
+
+        for _ in 0..MAX_UNPACKED_ENTRIES {
+            budget.entry().unwrap();
+        }
+
+        assert!(budget.entry().is_err());
+    }
+}
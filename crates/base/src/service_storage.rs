@@ -0,0 +1,439 @@
+//! Fetches the eszip of a service from object storage when its path is an
+//! `s3://bucket/key` or `gs://bucket/key` URL, so that code doesn't have to be
+//! synced to the disk of every node beforehand.
+//!
+//! Objects are kept in a local cache along with their ETag. Later boots only
+//! download an object again if its ETag changed, and fall back to the cached
+//! copy if the storage can't be reached.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Error};
+use chrono::Utc;
+use log::{debug, warn};
+use once_cell::sync::OnceCell;
+use reqwest::header::{AUTHORIZATION, ETAG, IF_NONE_MATCH};
+use reqwest::{RequestBuilder, StatusCode};
+use ring::hmac;
+use sb_core::cache::deno_dir::DenoDir;
+use sb_core::util::checksum;
+
+static STORAGE: OnceCell<ServiceStorage> = OnceCell::new();
+
+#[derive(Debug, Clone)]
+pub struct S3Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    /// Endpoint of an S3 compatible storage. Buckets are addressed by path
+    /// on it rather than by host.
+    pub endpoint: Option<String>,
+    pub region: String,
+    /// Requests are sent unsigned without credentials.
+    pub credentials: Option<S3Credentials>,
+}
+
+impl S3Config {
+    /// Reads the configuration from the usual `AWS_*` environment variables.
+    pub fn from_env() -> Self {
+        let var = |name| {
+            std::env::var(name)
+                .ok()
+                .filter(|it: &String| !it.is_empty())
+        };
+
+        Self {
+            endpoint: var("AWS_ENDPOINT_URL"),
+            region: var("AWS_REGION")
+                .or_else(|| var("AWS_DEFAULT_REGION"))
+                .unwrap_or_else(|| "us-east-1".to_string()),
+            credentials: var("AWS_ACCESS_KEY_ID")
+                .zip(var("AWS_SECRET_ACCESS_KEY"))
+                .map(|(access_key_id, secret_access_key)| S3Credentials {
+                    access_key_id,
+                    secret_access_key,
+                    session_token: var("AWS_SESSION_TOKEN"),
+                }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GcsConfig {
+    /// OAuth 2.0 access token sent as a bearer token. Requests are sent
+    /// anonymously without it.
+    pub access_token: Option<String>,
+}
+
+impl GcsConfig {
+    pub fn from_env() -> Self {
+        Self {
+            access_token: std::env::var("GOOGLE_OAUTH_ACCESS_TOKEN")
+                .ok()
+                .filter(|it| !it.is_empty()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StorageConfig {
+    /// Where fetched objects are kept. Defaults to a folder of the Deno dir.
+    pub cache_dir: Option<PathBuf>,
+    pub s3: S3Config,
+    pub gcs: GcsConfig,
+}
+
+impl StorageConfig {
+    pub fn from_env() -> Self {
+        Self {
+            cache_dir: None,
+            s3: S3Config::from_env(),
+            gcs: GcsConfig::from_env(),
+        }
+    }
+}
+
+/// Builds the request that downloads an object from a kind of storage.
+pub trait StorageDriver: Send + Sync {
+    fn get(
+        &self,
+        client: &reqwest::Client,
+        bucket: &str,
+        key: &str,
+    ) -> Result<RequestBuilder, Error>;
+}
+
+impl StorageDriver for S3Config {
+    fn get(
+        &self,
+        client: &reqwest::Client,
+        bucket: &str,
+        key: &str,
+    ) -> Result<RequestBuilder, Error> {
+        let key = key
+            .split('/')
+            .map(|it| urlencoding::encode(it).into_owned())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let (host, path) = match self.endpoint.as_deref() {
+            Some(endpoint) => {
+                let url = url::Url::parse(endpoint).context("invalid S3 endpoint")?;
+                let Some(host) = url.host_str() else {
+                    bail!("invalid S3 endpoint: {}", endpoint);
+                };
+
+                let host = match url.port() {
+                    Some(port) => format!("{}:{}", host, port),
+                    None => host.to_string(),
+                };
+
+                (host, format!("/{}/{}", bucket, key))
+            }
+
+            None => (
+                format!("{}.s3.{}.amazonaws.com", bucket, self.region),
+                format!("/{}", key),
+            ),
+        };
+
+        let scheme = match self.endpoint.as_deref() {
+            Some(it) if it.starts_with("http://") => "http",
+            _ => "https",
+        };
+
+        let mut req = client.get(format!("{}://{}{}", scheme, host, path));
+
+        let Some(credentials) = self.credentials.as_ref() else {
+            return Ok(req);
+        };
+
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let date = &amz_date[..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let payload_hash = checksum::gen(&[b""]);
+
+        let mut headers = vec![
+            ("host", host.clone()),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+
+        if let Some(token) = credentials.session_token.as_ref() {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical_request = format!(
+            "GET\n{}\n\n{}\n{}\n{}",
+            path,
+            headers
+                .iter()
+                .map(|(name, value)| format!("{}:{}\n", name, value))
+                .collect::<String>(),
+            signed_headers,
+            payload_hash
+        );
+
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            checksum::gen(&[canonical_request])
+        );
+
+        let signing_key = [date, self.region.as_str(), "s3", "aws4_request"]
+            .iter()
+            .fold(
+                format!("AWS4{}", credentials.secret_access_key).into_bytes(),
+                |key, it| sign(&key, it),
+            );
+
+        let signature = hex(&sign(&signing_key, &string_to_sign));
+
+        for (name, value) in headers.into_iter().skip(1) {
+            req = req.header(name, value);
+        }
+
+        Ok(req.header(
+            AUTHORIZATION,
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                credentials.access_key_id, scope, signed_headers, signature
+            ),
+        ))
+    }
+}
+
+impl StorageDriver for GcsConfig {
+    fn get(
+        &self,
+        client: &reqwest::Client,
+        bucket: &str,
+        key: &str,
+    ) -> Result<RequestBuilder, Error> {
+        let req = client.get(format!(
+            "https://storage.googleapis.com/{}/{}",
+            bucket,
+            key.split('/')
+                .map(urlencoding::encode)
+                .collect::<Vec<_>>()
+                .join("/")
+        ));
+
+        Ok(match self.access_token.as_ref() {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        })
+    }
+}
+
+fn sign(key: &[u8], data: &str) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data.as_bytes())
+        .as_ref()
+        .to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|it| format!("{:02x}", it)).collect()
+}
+
+struct ServiceStorage {
+    cache_dir: PathBuf,
+    client: reqwest::Client,
+    s3: S3Config,
+    gcs: GcsConfig,
+}
+
+impl ServiceStorage {
+    fn new(config: StorageConfig) -> Self {
+        Self {
+            cache_dir: config.cache_dir.unwrap_or_else(|| {
+                DenoDir::new(None)
+                    .map(|it| it.service_storage_folder_path())
+                    .unwrap_or_else(|_| std::env::temp_dir().join("sb_service_storage"))
+            }),
+            client: reqwest::Client::new(),
+            s3: config.s3,
+            gcs: config.gcs,
+        }
+    }
+
+    fn driver(&self, scheme: &str) -> Option<&dyn StorageDriver> {
+        match scheme {
+            "s3" => Some(&self.s3),
+            "gs" => Some(&self.gcs),
+            _ => None,
+        }
+    }
+
+    /// Returns the folder the object at `url` is cached in, and its bytes.
+    async fn fetch(&self, url: &str) -> Result<(PathBuf, Vec<u8>), Error> {
+        let parsed =
+            url::Url::parse(url).with_context(|| format!("invalid service URL {}", url))?;
+        let Some(driver) = self.driver(parsed.scheme()) else {
+            bail!("unsupported service storage: {}", url);
+        };
+
+        let Some(bucket) = parsed.host_str() else {
+            bail!("service URL has no bucket: {}", url);
+        };
+
+        let key = urlencoding::decode(parsed.path())?;
+        let key = key.trim_start_matches('/');
+
+        if key.is_empty() {
+            bail!("service URL has no key: {}", url);
+        }
+
+        let dir = self.cache_dir.join(checksum::gen(&[url]));
+        let object_path = dir.join("service.eszip");
+        let etag_path = dir.join("service.etag");
+        let maybe_cached = std::fs::read(&object_path)
+            .ok()
+            .zip(std::fs::read_to_string(&etag_path).ok());
+
+        let mut req = driver.get(&self.client, bucket, key)?;
+
+        if let Some((_, etag)) = maybe_cached.as_ref() {
+            req = req.header(IF_NONE_MATCH, etag.as_str());
+        }
+
+        let res = match req.send().await {
+            Ok(it) => it,
+            Err(err) => {
+                let Some((bin, _)) = maybe_cached else {
+                    return Err(anyhow!("failed to fetch {}: {}", url, err));
+                };
+
+                warn!("failed to fetch {}, using the cached copy: {}", url, err);
+                return Ok((dir, bin));
+            }
+        };
+
+        match res.status() {
+            StatusCode::NOT_MODIFIED if maybe_cached.is_some() => {
+                debug!("service object is not modified: {}", url);
+                Ok((dir, maybe_cached.unwrap().0))
+            }
+
+            status if status.is_success() => {
+                let maybe_etag = res
+                    .headers()
+                    .get(ETAG)
+                    .and_then(|it| it.to_str().ok())
+                    .map(str::to_string);
+
+                let bin = res.bytes().await?.to_vec();
+
+                if let Err(err) = store(&dir, &bin, maybe_etag.as_deref()) {
+                    warn!("failed to cache {}: {}", url, err);
+                }
+
+                Ok((dir, bin))
+            }
+
+            status => bail!("failed to fetch {}: {}", url, status),
+        }
+    }
+}
+
+fn store(dir: &Path, bin: &[u8], maybe_etag: Option<&str>) -> Result<(), Error> {
+    let etag_path = dir.join("service.etag");
+
+    std::fs::create_dir_all(dir)?;
+
+    // NOTE: The ETag goes first so that a partial write never pairs a stale
+    // object with the ETag of a new one.
+    let _ = std::fs::remove_file(&etag_path);
+
+    std::fs::write(dir.join("service.eszip"), bin)?;
+
+    if let Some(etag) = maybe_etag {
+        std::fs::write(etag_path, etag)?;
+    }
+
+    Ok(())
+}
+
+/// Sets up how objects are fetched. Without it, the configuration is read
+/// from the environment on the first fetch. Can only be done once.
+pub fn configure(config: StorageConfig) -> Result<(), Error> {
+    STORAGE
+        .set(ServiceStorage::new(config))
+        .map_err(|_| anyhow!("service storage is already configured"))
+}
+
+/// Whether the service at `service_path` lives in object storage.
+pub(crate) fn is_remote(service_path: &str) -> bool {
+    service_path.starts_with("s3://") || service_path.starts_with("gs://")
+}
+
+/// Fetches the eszip of the service at `service_path` if it lives in object
+/// storage, along with the local folder to boot it from.
+pub(crate) async fn fetch(service_path: &Path) -> Result<Option<(PathBuf, Vec<u8>)>, Error> {
+    let Some(url) = service_path.to_str().filter(|it| is_remote(it)) else {
+        return Ok(None);
+    };
+
+    STORAGE
+        .get_or_init(|| ServiceStorage::new(StorageConfig::from_env()))
+        .fetch(url)
+        .await
+        .map(Some)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_s3_request_is_signed_for_the_bucket_host() {
+        let config = S3Config {
+            endpoint: None,
+            region: "eu-west-1".to_string(),
+            credentials: Some(S3Credentials {
+                access_key_id: "AKID".to_string(),
+                secret_access_key: "secret".to_string(),
+                session_token: None,
+            }),
+        };
+
+        let req = config
+            .get(
+                &reqwest::Client::new(),
+                "bucket",
+                "services/hello world.eszip",
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            req.url().as_str(),
+            "https://bucket.s3.eu-west-1.amazonaws.com/services/hello%20world.eszip"
+        );
+
+        let authorization = req.headers()[AUTHORIZATION].to_str().unwrap();
+
+        assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKID/"));
+        assert!(authorization
+            .contains("SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature="));
+    }
+
+    #[test]
+    fn test_remote_service_paths() {
+        assert!(is_remote("s3://bucket/service.eszip"));
+        assert!(is_remote("gs://bucket/service.eszip"));
+        assert!(!is_remote("./examples/main"));
+    }
+}
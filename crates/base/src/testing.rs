@@ -0,0 +1,312 @@
+//! Helpers for exercising user workers in-process from the tests of crates
+//! that embed the runtime. The startup self-test and `bench` run on them as
+//! well.
+//!
+//! ```ignore
+//! let mut rt = TestRuntime::builder().build().await?;
+//!
+//! rt.register("hello", ServiceSource::code("Deno.serve(() => new Response('hi'))"))?;
+//!
+//! let res = rt.request("hello", Request::get("/").body(Body::empty())?).await?;
+//! assert_eq!(res.body, "hi");
+//!
+//! rt.wait_for_event(|it| matches!(it.event, WorkerEvents::Boot(_)), Duration::from_secs(5))
+//!     .await
+//!     .unwrap();
+//! ```
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Error};
+use bytes::Bytes;
+use deno_core::FastString;
+use event_worker::events::WorkerEventWithMetadata;
+use http::{HeaderMap, StatusCode};
+use hyper::{Body, Request};
+use sb_core::channel::{self, ChannelKind};
+use sb_workers::context::{
+    UserWorkerMsgs, UserWorkerMsgsSender, UserWorkerRuntimeOpts, WorkerContextInitOpts,
+    WorkerRuntimeOpts,
+};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::timeout;
+use uuid::Uuid;
+
+use crate::rt_worker::worker_ctx::{create_user_worker_pool, TerminationToken};
+use crate::rt_worker::worker_pool::{SupervisorPolicy, WorkerPoolPolicy};
+use crate::server::ServerFlags;
+
+/// Where the code of a service comes from.
+pub enum ServiceSource {
+    /// A directory containing the entrypoint of the service.
+    Path(PathBuf),
+    /// The source of the entrypoint itself.
+    Code(String),
+}
+
+impl ServiceSource {
+    pub fn path<T: Into<PathBuf>>(path: T) -> Self {
+        Self::Path(path.into())
+    }
+
+    pub fn code<T: Into<String>>(code: T) -> Self {
+        Self::Code(code.into())
+    }
+}
+
+/// How a service is booted, besides its code.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceOptions {
+    pub env_vars: HashMap<String, String>,
+    pub import_map_path: Option<String>,
+    pub runtime_opts: UserWorkerRuntimeOpts,
+}
+
+struct Service {
+    service_path: PathBuf,
+    maybe_code: Option<String>,
+    opts: ServiceOptions,
+}
+
+/// A response whose body has been read to the end.
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TestResponse {
+    pub fn text(&self) -> Result<&str, Error> {
+        Ok(std::str::from_utf8(&self.body)?)
+    }
+}
+
+pub struct TestRuntimeBuilder {
+    policy: Option<WorkerPoolPolicy>,
+    runtime_opts: UserWorkerRuntimeOpts,
+    static_patterns: Vec<String>,
+}
+
+impl TestRuntimeBuilder {
+    pub fn with_worker_pool_policy(mut self, policy: WorkerPoolPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Sets the limits every service is registered with unless it is
+    /// registered through [`TestRuntime::register_with_opts`].
+    pub fn with_user_runtime_opts(mut self, runtime_opts: UserWorkerRuntimeOpts) -> Self {
+        self.runtime_opts = runtime_opts;
+        self
+    }
+
+    pub fn with_static_patterns(mut self, static_patterns: Vec<String>) -> Self {
+        self.static_patterns = static_patterns;
+        self
+    }
+
+    pub async fn build(self) -> Result<TestRuntime, Error> {
+        let (events_tx, events_rx) = channel::channel(ChannelKind::WorkerEvent);
+        let termination_token = TerminationToken::new();
+        let (_, worker_pool_tx) = create_user_worker_pool(
+            self.policy.unwrap_or_else(|| {
+                WorkerPoolPolicy::new(SupervisorPolicy::PerWorker, 1, ServerFlags::default())
+            }),
+            Some(events_tx),
+            Some(termination_token.clone()),
+            self.static_patterns,
+            None,
+            None,
+            None,
+            None,
+        )
+        .await?;
+
+        Ok(TestRuntime {
+            worker_pool_tx,
+            termination_token,
+            events_rx,
+            services: HashMap::new(),
+            runtime_opts: self.runtime_opts,
+            scratch_dir: std::env::temp_dir().join(format!("sb-testing-{}", Uuid::new_v4())),
+        })
+    }
+}
+
+/// A user worker pool running in-process, along with the events it emits.
+pub struct TestRuntime {
+    worker_pool_tx: UserWorkerMsgsSender,
+    termination_token: TerminationToken,
+    events_rx: mpsc::Receiver<WorkerEventWithMetadata>,
+    services: HashMap<String, Service>,
+    runtime_opts: UserWorkerRuntimeOpts,
+    scratch_dir: PathBuf,
+}
+
+impl TestRuntime {
+    pub fn builder() -> TestRuntimeBuilder {
+        TestRuntimeBuilder {
+            policy: None,
+            runtime_opts: UserWorkerRuntimeOpts::default(),
+            static_patterns: vec![],
+        }
+    }
+
+    /// Gives access to the pool for the messages not covered here.
+    pub fn worker_pool_tx(&self) -> UserWorkerMsgsSender {
+        self.worker_pool_tx.clone()
+    }
+
+    pub fn register(&mut self, name: &str, source: ServiceSource) -> Result<(), Error> {
+        let opts = ServiceOptions {
+            runtime_opts: self.runtime_opts.clone(),
+            ..Default::default()
+        };
+
+        self.register_with_opts(name, source, opts)
+    }
+
+    pub fn register_with_opts(
+        &mut self,
+        name: &str,
+        source: ServiceSource,
+        opts: ServiceOptions,
+    ) -> Result<(), Error> {
+        let (service_path, maybe_code) = match source {
+            ServiceSource::Path(path) => (path, None),
+            ServiceSource::Code(code) => {
+                // NOTE: Each in-memory service gets its own directory since
+                // the pool tells services apart by their path.
+                let path = self.scratch_dir.join(name);
+
+                std::fs::create_dir_all(&path)
+                    .with_context(|| format!("can't create a directory for `{}`", name))?;
+
+                (path, Some(code))
+            }
+        };
+
+        self.services.insert(
+            name.to_string(),
+            Service {
+                service_path,
+                maybe_code,
+                opts,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Returns the key of the worker serving `name`, booting one if needed.
+    pub async fn worker(&self, name: &str) -> Result<Uuid, Error> {
+        let Some(service) = self.services.get(name) else {
+            bail!("service `{}` is not registered", name);
+        };
+
+        let (tx, rx) = oneshot::channel();
+
+        self.worker_pool_tx
+            .send(UserWorkerMsgs::Create(
+                WorkerContextInitOpts {
+                    service_path: service.service_path.clone(),
+                    no_module_cache: false,
+                    import_map_path: service.opts.import_map_path.clone(),
+                    env_vars: service.opts.env_vars.clone(),
+                    events_rx: None,
+                    timing: None,
+                    conf: WorkerRuntimeOpts::UserWorker(service.opts.runtime_opts.clone()),
+                    maybe_eszip: None,
+                    maybe_eszip_signature: None,
+                    maybe_module_code: service.maybe_code.clone().map(FastString::from),
+                    maybe_entrypoint: None,
+                    maybe_decorator: None,
+                    static_patterns: vec![],
+                    maybe_jsx_import_source_config: None,
+                },
+                tx,
+            ))
+            .map_err(|_| anyhow!("user worker pool is not available"))?;
+
+        Ok(rx.await??.key)
+    }
+
+    /// Sends `req` to the worker serving `name` and reads the response to
+    /// the end.
+    pub async fn request(&self, name: &str, req: Request<Body>) -> Result<TestResponse, Error> {
+        self.send_request(self.worker(name).await?, req).await
+    }
+
+    /// Sends `req` to the worker with the given key and reads the response
+    /// to the end.
+    pub async fn send_request(&self, key: Uuid, req: Request<Body>) -> Result<TestResponse, Error> {
+        let (tx, rx) = oneshot::channel();
+
+        self.worker_pool_tx
+            .send(UserWorkerMsgs::SendRequest(key, req, tx, None))
+            .map_err(|_| anyhow!("user worker pool is not available"))?;
+
+        let (res, req_end_tx) = rx.await??;
+        let (parts, body) = res.into_parts();
+        let body = hyper::body::to_bytes(body).await;
+        let _ = req_end_tx.send(());
+
+        Ok(TestResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body: body?,
+        })
+    }
+
+    /// Terminates the worker with the given key. Returns `false` if it was
+    /// already gone.
+    pub async fn terminate(&self, key: Uuid) -> Result<bool, Error> {
+        let (tx, rx) = oneshot::channel();
+
+        self.worker_pool_tx
+            .send(UserWorkerMsgs::Terminate(key, tx))
+            .map_err(|_| anyhow!("user worker pool is not available"))?;
+
+        Ok(rx.await?)
+    }
+
+    /// Returns the next event emitted by any worker, or `None` if nothing
+    /// was emitted within `wait`.
+    pub async fn next_event(&mut self, wait: Duration) -> Option<WorkerEventWithMetadata> {
+        timeout(wait, self.events_rx.recv()).await.ok().flatten()
+    }
+
+    /// Skips events until one matches `predicate`, giving up after `wait`.
+    pub async fn wait_for_event<P>(
+        &mut self,
+        mut predicate: P,
+        wait: Duration,
+    ) -> Option<WorkerEventWithMetadata>
+    where
+        P: FnMut(&WorkerEventWithMetadata) -> bool,
+    {
+        let events_rx = &mut self.events_rx;
+        let find_fut = async move {
+            while let Some(ev) = events_rx.recv().await {
+                if predicate(&ev) {
+                    return Some(ev);
+                }
+            }
+
+            None
+        };
+
+        timeout(wait, find_fut).await.ok().flatten()
+    }
+
+    /// Terminates every worker and waits for the pool to exit.
+    pub async fn shutdown(self, wait: Duration) -> Result<(), Error> {
+        let result = timeout(wait, self.termination_token.cancel_and_wait()).await;
+        let _ = std::fs::remove_dir_all(&self.scratch_dir);
+
+        result.map_err(|_| anyhow!("the runtime did not exit within {:?}", wait))
+    }
+}
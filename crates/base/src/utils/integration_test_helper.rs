@@ -21,10 +21,11 @@ use futures_util::{future::BoxFuture, Future, FutureExt};
 use http::{Request, Response};
 use hyper::Body;
 use pin_project::pin_project;
+use sb_core::channel::BoundedSender;
 
 use sb_workers::context::{
-    MainWorkerRuntimeOpts, Timing, UserWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRequestMsg,
-    WorkerRuntimeOpts,
+    MainWorkerRuntimeOpts, RequestStart, Timing, UserWorkerRuntimeOpts, WorkerContextInitOpts,
+    WorkerRequestMsg, WorkerRuntimeOpts,
 };
 use scopeguard::ScopeGuard;
 use tokio::{
@@ -50,7 +51,7 @@ impl From<(WorkerContextInitOpts, SupervisorPolicy)> for CreateTestUserWorkerArg
 #[derive(Debug)]
 pub struct RequestScope {
     policy: SupervisorPolicy,
-    req_start_tx: mpsc::UnboundedSender<Arc<Notify>>,
+    req_start_tx: mpsc::UnboundedSender<RequestStart>,
     req_end_tx: mpsc::UnboundedSender<()>,
     termination_token: TerminationToken,
     conn_token: CancellationToken,
@@ -62,16 +63,35 @@ impl RequestScope {
     }
 
     pub async fn start_request(self) -> RequestScopeGuard {
+        let mut req_end_tx = self.req_end_tx.clone();
+
         if self.policy.is_per_request() {
             let fence = Arc::<Notify>::default();
 
-            self.req_start_tx.send(fence.clone()).unwrap();
+            self.req_start_tx
+                .send(RequestStart {
+                    fence: fence.clone(),
+                    end_rx: None,
+                    request_id: None,
+                })
+                .unwrap();
+
             fence.notified().await;
+        } else {
+            let (tx, rx) = mpsc::unbounded_channel();
+
+            req_end_tx = tx;
+            self.req_start_tx
+                .send(RequestStart {
+                    end_rx: Some(rx),
+                    ..Default::default()
+                })
+                .unwrap();
         }
 
         RequestScopeGuard {
             cancelled: false,
-            req_end_tx: self.req_end_tx.clone(),
+            req_end_tx,
             termination_token: Some(self.termination_token.clone()),
             conn_token: self.conn_token.clone(),
             inner: None,
@@ -218,6 +238,7 @@ impl TestBedBuilder {
                     None,
                     None,
                     self.request_idle_timeout,
+                    None,
                 )
                 .await
                 .unwrap(),
@@ -233,6 +254,7 @@ impl TestBedBuilder {
             events_rx: None,
             timing: None,
             maybe_eszip: None,
+            maybe_eszip_signature: None,
             maybe_entrypoint: None,
             maybe_decorator: None,
             maybe_module_code: None,
@@ -265,7 +287,7 @@ impl TestBedBuilder {
 pub struct TestBed {
     pool_termination_token: TerminationToken,
     main_termination_token: TerminationToken,
-    main_worker_msg_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+    main_worker_msg_tx: BoundedSender<WorkerRequestMsg>,
 }
 
 impl TestBed {
@@ -313,7 +335,7 @@ impl TestBed {
 
 pub async fn create_test_user_worker<Opt: Into<CreateTestUserWorkerArgs>>(
     opts: Opt,
-) -> Result<(mpsc::UnboundedSender<WorkerRequestMsg>, RequestScope), Error> {
+) -> Result<(BoundedSender<WorkerRequestMsg>, RequestScope), Error> {
     let CreateTestUserWorkerArgs(mut opts, maybe_policy) = opts.into();
     let (req_start_tx, req_start_rx) = mpsc::unbounded_channel();
     let (req_end_tx, req_end_rx) = mpsc::unbounded_channel();
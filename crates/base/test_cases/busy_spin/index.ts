@@ -0,0 +1,10 @@
+Deno.serve((req: Request) => {
+	const ms = Number(new URL(req.url).searchParams.get('ms') ?? '0');
+	const start = performance.now();
+
+	while (performance.now() - start < ms) {
+		// Burn CPU time.
+	}
+
+	return new Response(null, { status: 204 });
+});
@@ -0,0 +1,16 @@
+async function errorName(fn: () => unknown) {
+	try {
+		await fn();
+		return null;
+	} catch (e) {
+		return e.name;
+	}
+}
+
+Deno.serve(async () => {
+	return Response.json({
+		subprocess: await errorName(() => new Deno.Command('true').output()),
+		ffi: await errorName(() => Deno.dlopen('libc.so.6', {})),
+		rawNet: await errorName(() => Deno.connect({ hostname: '127.0.0.1', port: 1 })),
+	});
+});
@@ -0,0 +1,17 @@
+Deno.serve(() => {
+	const DateBase = Object.getPrototypeOf(Date);
+
+	return Response.json({
+		now: Date.now(),
+		date: new Date().getTime(),
+		monotonic: performance.now(),
+		sharedArrayBuffer: typeof SharedArrayBuffer,
+		bypass: {
+			dateBase: typeof DateBase.now === 'function' ? DateBase.now() : 0,
+			performancePrototype: Performance.prototype.now.call(performance),
+			mark: performance.mark('mark').startTime,
+			markConstructor: new PerformanceMark('markConstructor').startTime,
+			measure: performance.measure('measure').duration,
+		},
+	});
+});
@@ -0,0 +1,8 @@
+const bootId = crypto.randomUUID();
+
+Deno.serve((req: Request) => {
+	return Response.json({
+		bootId,
+		clientIp: req.headers.get("x-sb-client-ip"),
+	});
+});
@@ -0,0 +1,8 @@
+Deno.serve((req) => {
+	// NOTE: Preflight requests must never reach this point.
+	if (req.method === 'OPTIONS') {
+		return new Response(null, { status: 418 });
+	}
+
+	return new Response('meow');
+});
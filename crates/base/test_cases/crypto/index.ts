@@ -0,0 +1,18 @@
+async function errorOf(fn: () => Promise<unknown>) {
+	try {
+		await fn();
+		return null;
+	} catch (e) {
+		return e.name;
+	}
+}
+
+Deno.serve(async () => {
+	const hash = await Supabase.crypto.hashPassword('hunter2', { algorithm: 'bcrypt', cost: 4 });
+
+	return Response.json({
+		verified: await Supabase.crypto.verifyPassword('hunter2', hash),
+		overLimit: await errorOf(() => Supabase.crypto.verifyPassword('hunter2', hash)),
+		sign: await errorOf(() => Supabase.crypto.sign('not-granted', 'payload')),
+	});
+});
@@ -0,0 +1,8 @@
+Deno.serve(async () => {
+	try {
+		await Supabase.db.query('select 1 as one');
+		return Response.json({ error: null });
+	} catch (e) {
+		return Response.json({ error: e.name });
+	}
+});
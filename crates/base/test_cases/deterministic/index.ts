@@ -0,0 +1,13 @@
+Deno.serve(() => {
+	const bytes = crypto.getRandomValues(new Uint8Array(8));
+
+	return Response.json({
+		random: [Math.random(), Math.random(), Math.random()],
+		bytes: Array.from(bytes),
+		now: Date.now(),
+		date: new Date().toISOString(),
+		isDate: new Date() instanceof Date,
+		parsed: new Date('2000-01-01T00:00:00.000Z').getTime(),
+		monotonic: performance.now(),
+	});
+});
@@ -0,0 +1,3 @@
+Deno.serve((req: Request) => {
+	return Response.json(Object.fromEntries(req.headers.entries()));
+});
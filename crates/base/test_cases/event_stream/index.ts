@@ -0,0 +1,13 @@
+Deno.serve(() => {
+	// NOTE: The stream is never closed, so the connection stays open until
+	// the client goes away.
+	const body = new ReadableStream({
+		start(controller) {
+			controller.enqueue(new TextEncoder().encode('data: meow\n\n'));
+		},
+	});
+
+	return new Response(body, {
+		headers: { 'content-type': 'text/event-stream' },
+	});
+});
@@ -0,0 +1,7 @@
+Deno.serve(async (req: Request) => {
+	if (req.headers.has('x-reject')) {
+		return new Response(null, { status: 413 });
+	}
+
+	return new Response(String((await req.text()).length));
+});
@@ -0,0 +1,12 @@
+Deno.serve(async () => {
+	for (let i = 0; i < 2; i++) {
+		const resp = await fetch('http://localhost:8498/ping');
+
+		await resp.text();
+
+		// NOTE: Give the connection the time to go back to the pool.
+		await new Promise((resolve) => setTimeout(resolve, 100));
+	}
+
+	return new Response(null, { status: 204 });
+});
@@ -0,0 +1,7 @@
+export const handler = (req: Request) => new Response(`handler: ${new URL(req.url).pathname}`);
+
+export default {
+	fetch() {
+		return new Response('default export');
+	},
+};
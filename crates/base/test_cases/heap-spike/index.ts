@@ -0,0 +1,14 @@
+// Roughly 90MiB that stays alive for as long as the worker does.
+const kept: number[][] = [];
+
+for (let i = 0; i < 2250; i++) {
+    kept.push(new Array(10000).fill(i));
+}
+
+Deno.serve((_req) => {
+    // Pushes the heap past the 150MiB limit for a single allocation that is
+    // garbage as soon as it returns, so the GC pass brings it back under.
+    const spiked = new Array(20_000_000).fill(0).length;
+
+    return new Response(`recovered ${kept.length} ${spiked}`);
+});
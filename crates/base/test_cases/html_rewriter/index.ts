@@ -0,0 +1,21 @@
+Deno.serve(() => {
+	const encoder = new TextEncoder();
+	const body = new ReadableStream({
+		start(controller) {
+			for (const chunk of ['<html><he', 'ad></head><body><a hr', 'ef="/">home</a><p class="ad">buy</p></body></html>']) {
+				controller.enqueue(encoder.encode(chunk));
+			}
+
+			controller.close();
+		},
+	});
+
+	return Supabase.html.rewrite(
+		new Response(body, { headers: { 'content-type': 'text/html' } }),
+		[
+			{ selector: 'head', action: 'append', content: '<script src="/rum.js"></script>', html: true },
+			{ selector: 'a[href]', action: 'setAttribute', name: 'rel', value: 'nofollow' },
+			{ selector: '.ad', action: 'remove' },
+		],
+	);
+});
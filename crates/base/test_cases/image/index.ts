@@ -0,0 +1,27 @@
+// A red PNG of 4x2 pixels.
+const PNG = Uint8Array.from(
+	atob('iVBORw0KGgoAAAANSUhEUgAAAAQAAAACCAIAAADwyuo0AAAAEElEQVR4nGP4z8AARwzIHABvqgf5gNwAKAAAAABJRU5ErkJggg=='),
+	(c) => c.charCodeAt(0),
+);
+
+const transform = () =>
+	Supabase.image.transform(PNG, {
+		crop: { x: 0, y: 0, width: 2, height: 2 },
+		resize: { width: 1 },
+		format: 'jpeg',
+	});
+
+Deno.serve(async (req) => {
+	try {
+		const parallel = Number(new URL(req.url).searchParams.get('parallel') ?? 1);
+		const [out] = await Promise.all(Array.from({ length: parallel }, transform));
+
+		return Response.json({
+			error: null,
+			input: await Supabase.image.info(PNG),
+			output: await Supabase.image.info(out),
+		});
+	} catch (e) {
+		return Response.json({ error: e.name });
+	}
+});
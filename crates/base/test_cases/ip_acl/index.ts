@@ -0,0 +1 @@
+Deno.serve(() => new Response('meow'));
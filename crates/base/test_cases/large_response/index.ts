@@ -0,0 +1,25 @@
+Deno.serve((req: Request) => {
+	const url = new URL(req.url);
+	const size = Number(url.searchParams.get('size') ?? '0');
+
+	if (url.searchParams.get('mode') !== 'stream') {
+		return new Response('a'.repeat(size));
+	}
+
+	const chunk = new TextEncoder().encode('a'.repeat(256));
+	let remaining = size;
+
+	return new Response(
+		new ReadableStream({
+			pull(controller) {
+				if (remaining <= 0) {
+					controller.close();
+					return;
+				}
+
+				controller.enqueue(chunk.subarray(0, Math.min(remaining, chunk.length)));
+				remaining -= chunk.length;
+			},
+		}),
+	);
+});
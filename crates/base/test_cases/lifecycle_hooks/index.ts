@@ -0,0 +1,8 @@
+const booted = new Promise((res) => EdgeRuntime.onBoot(() => res(true)));
+
+EdgeRuntime.onShutdown(() => {});
+EdgeRuntime.onError(() => {});
+
+Deno.serve(async () => {
+	return Response.json({ booted: await booted });
+});
@@ -0,0 +1,16 @@
+console.log('main function started');
+
+Deno.serve((req: Request) => {
+	let error = null;
+
+	try {
+		EdgeRuntime.geoip.lookup('8.8.8.8');
+	} catch (e) {
+		error = e.name;
+	}
+
+	return Response.json({
+		error,
+		country: req.headers.get('x-sb-geo-country'),
+	});
+});
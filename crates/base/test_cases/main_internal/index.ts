@@ -0,0 +1 @@
+Deno.serve((req: Request) => new Response(`internal: ${new URL(req.url).pathname}`));
@@ -0,0 +1,5 @@
+console.log('main function started');
+
+Deno.serve((req: Request) => {
+	return Response.json(EdgeRuntime.getRequestContext(req));
+});
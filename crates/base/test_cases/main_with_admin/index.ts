@@ -0,0 +1,64 @@
+console.log('main function started');
+
+Deno.serve(async (req: Request) => {
+	const url = new URL(req.url);
+	const { pathname } = url;
+	const service_name = pathname.split('/')[1];
+	const servicePath = `./test_cases/${service_name}`;
+
+	try {
+		const worker = await EdgeRuntime.userWorkers.create({
+			servicePath,
+			memoryLimitMb: 150,
+			workerTimeoutMs: 60 * 1000,
+			noModuleCache: false,
+			importMapPath: null,
+			envVars: [],
+			labels: { tenant: 'acme' },
+		});
+
+		const resp = await worker.fetch(req);
+
+		await resp.text();
+
+		const workers = await EdgeRuntime.userWorkers.list();
+		const found = workers.find((it) => it.key === worker.key);
+		const labeled = await EdgeRuntime.userWorkers.listByLabels({ tenant: 'acme' });
+		const unlabeled = await EdgeRuntime.userWorkers.listByLabels({ tenant: 'other' });
+		const config = await EdgeRuntime.getPoolConfig();
+		const other = await EdgeRuntime.userWorkers.create({
+			servicePath,
+			memoryLimitMb: 150,
+			workerTimeoutMs: 60 * 1000,
+			noModuleCache: false,
+			importMapPath: null,
+			envVars: [],
+			labels: { tenant: 'other' },
+		});
+		const emptySelectorRejected = await EdgeRuntime.userWorkers
+			.terminateByLabels({})
+			.then(() => false, () => true);
+		const terminatedOther = await EdgeRuntime.userWorkers.terminateByLabels({ tenant: 'other' });
+		const terminated = await worker.terminate();
+
+		return Response.json({
+			found: !!found,
+			servicePath: found?.servicePath,
+			requestCount: found?.stats.request_count,
+			labels: found?.labels,
+			labeledCount: labeled.length,
+			unlabeledCount: unlabeled.length,
+			supervisorPolicy: config.supervisorPolicy,
+			reusedAcrossLabels: other.key === worker.key,
+			emptySelectorRejected,
+			terminatedOther,
+			terminated,
+		});
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
@@ -0,0 +1,28 @@
+Deno.serve(async (req: Request) => {
+	const url = new URL(req.url);
+	const { pathname } = url;
+	const service_name = pathname.split('/')[1];
+	const servicePath = `./test_cases/${service_name}`;
+
+	try {
+		const worker = await EdgeRuntime.userWorkers.create({
+			servicePath,
+			memoryLimitMb: 150,
+			workerTimeoutMs: 1000,
+			cpuTimeSoftLimitMs: 10 * 60 * 1000,
+			cpuTimeHardLimitMs: 10 * 60 * 1000,
+			noModuleCache: false,
+			importMapPath: null,
+			envVars: [],
+			billingMode: req.headers.get('x-billing-mode') ?? 'wallClock',
+		});
+
+		return await worker.fetch(req);
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
@@ -0,0 +1,52 @@
+console.log('main function started');
+
+function createWorker() {
+	return EdgeRuntime.userWorkers.create({
+		servicePath: './test_cases/busy_spin',
+		memoryLimitMb: 150,
+		workerTimeoutMs: 60 * 1000,
+		noModuleCache: false,
+		importMapPath: null,
+		envVars: [],
+		cpuTimeSoftLimitMs: 1000,
+		cpuTimeHardLimitMs: 2000,
+		cpuTimeBusyThresholdPct: 10,
+	});
+}
+
+async function isBusy(key: string) {
+	const workers = await EdgeRuntime.userWorkers.list();
+
+	return workers.some((it) => it.key === key && it.isBusy);
+}
+
+Deno.serve(async () => {
+	try {
+		const worker = await createWorker();
+		const reused = await createWorker();
+
+		// NOTE: Well past 10% of the soft limit.
+		await worker.fetch(new Request('http://localhost/?ms=300'));
+
+		let busy = false;
+
+		for (let i = 0; i < 20 && !busy; i++) {
+			await new Promise((resolve) => setTimeout(resolve, 100));
+			busy = await isBusy(worker.key);
+		}
+
+		const next = await createWorker();
+
+		return Response.json({
+			reusedWhileIdle: reused.key === worker.key,
+			busy,
+			reusedWhileBusy: next.key === worker.key,
+		});
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
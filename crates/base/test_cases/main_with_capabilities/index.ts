@@ -0,0 +1,36 @@
+console.log('main function started');
+
+async function runWith(capabilities: Record<string, boolean>) {
+	const worker = await EdgeRuntime.userWorkers.create({
+		servicePath: './test_cases/capabilities',
+		memoryLimitMb: 150,
+		workerTimeoutMs: 60 * 1000,
+		noModuleCache: false,
+		importMapPath: null,
+		envVars: [],
+		forceCreate: true,
+		...capabilities,
+	});
+
+	const resp = await worker.fetch(new Request('http://localhost/'));
+	const body = await resp.json();
+
+	await worker.terminate();
+
+	return body;
+}
+
+Deno.serve(async () => {
+	try {
+		return Response.json({
+			denied: await runWith({}),
+			granted: await runWith({ allowFfi: true, allowSubprocess: true, allowRawNet: true }),
+		});
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
@@ -0,0 +1,23 @@
+console.log('main function started');
+
+Deno.serve(async (req: Request) => {
+	try {
+		const worker = await EdgeRuntime.userWorkers.create({
+			servicePath: './test_cases/clock',
+			memoryLimitMb: 150,
+			workerTimeoutMs: 60 * 1000,
+			noModuleCache: false,
+			importMapPath: null,
+			envVars: [],
+			timerResolutionMs: 100,
+		});
+
+		return await worker.fetch(req);
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
@@ -0,0 +1,31 @@
+console.log('main function started');
+
+Deno.serve(async (req: Request) => {
+	const cors = new URL(req.url).pathname === '/wildcard-with-credentials'
+		? { allowedOrigins: ['*'], allowCredentials: true }
+		: {
+			allowedOrigins: ['https://example.com'],
+			allowedMethods: ['GET', 'POST'],
+			maxAgeSecs: 600,
+		};
+
+	try {
+		const worker = await EdgeRuntime.userWorkers.create({
+			servicePath: './test_cases/cors',
+			memoryLimitMb: 150,
+			workerTimeoutMs: 60 * 1000,
+			noModuleCache: false,
+			importMapPath: null,
+			envVars: [],
+			cors,
+		});
+
+		return await worker.fetch(req);
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
@@ -0,0 +1,38 @@
+console.log('main function started');
+
+async function runWith(deterministicSeed: number | null) {
+	const worker = await EdgeRuntime.userWorkers.create({
+		servicePath: './test_cases/deterministic',
+		memoryLimitMb: 150,
+		workerTimeoutMs: 60 * 1000,
+		noModuleCache: false,
+		importMapPath: null,
+		envVars: [],
+		forceCreate: true,
+		deterministicSeed,
+	});
+
+	const resp = await worker.fetch(new Request('http://localhost/'));
+	const body = await resp.json();
+
+	await worker.terminate();
+
+	return body;
+}
+
+Deno.serve(async () => {
+	try {
+		return Response.json({
+			first: await runWith(42),
+			second: await runWith(42),
+			other: await runWith(7),
+			unseeded: await runWith(null),
+		});
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
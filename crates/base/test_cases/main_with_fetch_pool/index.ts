@@ -0,0 +1,37 @@
+console.log('main function started');
+
+Deno.serve(async (req: Request) => {
+	if (new URL(req.url).pathname === '/ping') {
+		return new Response('pong');
+	}
+
+	try {
+		const worker = await EdgeRuntime.userWorkers.create({
+			servicePath: './test_cases/fetch_same_host',
+			memoryLimitMb: 150,
+			workerTimeoutMs: 60 * 1000,
+			noModuleCache: false,
+			importMapPath: null,
+			envVars: [],
+			forceCreate: true,
+		});
+
+		const before = (await EdgeRuntime.getRuntimeMetrics()).fetchPoolStats;
+
+		await worker.fetch(new Request('http://localhost/'));
+
+		const { fetchPoolStats } = await EdgeRuntime.getRuntimeMetrics();
+
+		return Response.json({
+			...fetchPoolStats,
+			newConnections: fetchPoolStats.openedConnectionsCount -
+				before.openedConnectionsCount,
+		});
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
@@ -0,0 +1,24 @@
+console.log('main function started');
+
+Deno.serve(async (req: Request) => {
+	try {
+		const worker = await EdgeRuntime.userWorkers.create({
+			servicePath: './test_cases/handler_export',
+			memoryLimitMb: 150,
+			workerTimeoutMs: 60 * 1000,
+			noModuleCache: false,
+			importMapPath: null,
+			envVars: [],
+			forceCreate: true,
+			handlerExport: req.headers.get('x-handler-export'),
+		});
+
+		return await worker.fetch(req);
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
@@ -0,0 +1,37 @@
+console.log('main function started');
+
+async function runWith(opts: Record<string, boolean | number>, parallel = 1) {
+	const worker = await EdgeRuntime.userWorkers.create({
+		servicePath: './test_cases/image',
+		memoryLimitMb: 150,
+		workerTimeoutMs: 60 * 1000,
+		noModuleCache: false,
+		importMapPath: null,
+		envVars: [],
+		forceCreate: true,
+		...opts,
+	});
+
+	const resp = await worker.fetch(new Request(`http://localhost/?parallel=${parallel}`));
+	const body = await resp.json();
+
+	await worker.terminate();
+
+	return body;
+}
+
+Deno.serve(async () => {
+	try {
+		return Response.json({
+			denied: await runWith({}),
+			granted: await runWith({ allowImageOps: true }),
+			overLimit: await runWith({ allowImageOps: true, imageTransformConcurrency: 1 }, 2),
+		});
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
@@ -0,0 +1,26 @@
+console.log('main function started');
+
+Deno.serve(async (req: Request) => {
+	try {
+		const worker = await EdgeRuntime.userWorkers.create({
+			servicePath: './test_cases/ip_acl',
+			memoryLimitMb: 150,
+			workerTimeoutMs: 60 * 1000,
+			noModuleCache: false,
+			importMapPath: null,
+			envVars: [],
+			ipAccessList: {
+				allow: ['10.0.0.0/8'],
+				deny: ['10.1.0.0/16'],
+			},
+		});
+
+		return await worker.fetch(req);
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
@@ -0,0 +1,32 @@
+Deno.serve(async (req: Request) => {
+	const count = parseInt(req.headers.get('x-worker-count') ?? '1');
+	const create = () =>
+		EdgeRuntime.userWorkers.create({
+			servicePath: './test_cases/std_user_worker',
+			memoryLimitMb: 150,
+			workerTimeoutMs: 60 * 1000,
+			noModuleCache: false,
+			importMapPath: null,
+			envVars: [],
+			forceCreate: true,
+		});
+
+	try {
+		for (let i = 0; i < count; i++) {
+			await create();
+		}
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+
+	try {
+		await create();
+		return Response.json({ saturated: false });
+	} catch (e) {
+		return Response.json({ saturated: true, msg: e.toString() });
+	}
+});
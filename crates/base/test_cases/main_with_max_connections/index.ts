@@ -0,0 +1,30 @@
+console.log('main function started');
+
+Deno.serve(async () => {
+	try {
+		const worker = await EdgeRuntime.userWorkers.create({
+			servicePath: './test_cases/event_stream',
+			memoryLimitMb: 150,
+			workerTimeoutMs: 60 * 1000,
+			noModuleCache: false,
+			importMapPath: null,
+			envVars: [],
+			forceCreate: true,
+			maxConnections: 1,
+		});
+
+		const first = await worker.fetch(new Request('http://localhost/'));
+		const second = await worker.fetch(new Request('http://localhost/'));
+
+		await first.body?.cancel();
+		await second.body?.cancel();
+
+		return Response.json({ first: first.status, second: second.status });
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
@@ -0,0 +1,25 @@
+console.log('main function started');
+
+Deno.serve(async () => {
+	try {
+		const worker = await EdgeRuntime.userWorkers.create({
+			servicePath: './test_cases/redis',
+			memoryLimitMb: 150,
+			workerTimeoutMs: 60 * 1000,
+			noModuleCache: false,
+			importMapPath: null,
+			envVars: [],
+			forceCreate: true,
+			redisCommandsPerSecond: 10,
+			redisNamespace: 'redis',
+		});
+
+		return await worker.fetch(new Request('http://localhost/'));
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
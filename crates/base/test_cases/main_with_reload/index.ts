@@ -0,0 +1,9 @@
+const bootId = crypto.randomUUID();
+
+Deno.serve(async (req: Request) => {
+	if (new URL(req.url).pathname === '/slow') {
+		await new Promise((resolve) => setTimeout(resolve, 1000));
+	}
+
+	return Response.json({ bootId });
+});
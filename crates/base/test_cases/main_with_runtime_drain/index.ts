@@ -0,0 +1,34 @@
+console.log('main function started');
+
+Deno.serve(async (req: Request) => {
+	const url = new URL(req.url);
+	const { pathname } = url;
+	const service_name = pathname.split('/')[1];
+	const servicePath = `./test_cases/${service_name}`;
+
+	try {
+		const worker = await EdgeRuntime.userWorkers.create({
+			servicePath,
+			memoryLimitMb: 150,
+			workerTimeoutMs: 60 * 1000,
+			noModuleCache: false,
+			importMapPath: null,
+			envVars: [],
+		});
+
+		const resp = await worker.fetch(req);
+		const body = await resp.text();
+
+		// NOTE: The user worker is kept alive, so the runtime stays draining
+		// until the deadline passes.
+		await EdgeRuntime.drain();
+
+		return new Response(body);
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
@@ -0,0 +1,28 @@
+console.log('main function started');
+
+Deno.serve(async (req: Request) => {
+	const url = new URL(req.url);
+	const { pathname } = url;
+	const service_name = pathname.split('/')[1];
+	const servicePath = `./test_cases/${service_name}`;
+
+	try {
+		const worker = await EdgeRuntime.userWorkers.create({
+			servicePath,
+			memoryLimitMb: 150,
+			workerTimeoutMs: 60 * 1000,
+			noModuleCache: false,
+			importMapPath: null,
+			envVars: [],
+			secrets: [['API_KEY', 'sk_test_123']],
+		});
+
+		return await worker.fetch(req);
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
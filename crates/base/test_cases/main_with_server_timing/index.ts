@@ -0,0 +1,32 @@
+console.log('main function started');
+
+Deno.serve(async () => {
+	try {
+		const worker = await EdgeRuntime.userWorkers.create({
+			servicePath: './test_cases/echo_headers',
+			memoryLimitMb: 150,
+			workerTimeoutMs: 60 * 1000,
+			noModuleCache: false,
+			importMapPath: null,
+			envVars: [],
+			forceCreate: true,
+		});
+
+		const cold = await worker.fetch(new Request('http://localhost/'));
+		const warm = await worker.fetch(new Request('http://localhost/'));
+
+		await cold.body?.cancel();
+		await warm.body?.cancel();
+
+		return Response.json({
+			cold: cold.headers.get('server-timing'),
+			warm: warm.headers.get('server-timing'),
+		});
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
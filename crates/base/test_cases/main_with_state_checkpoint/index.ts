@@ -0,0 +1,40 @@
+console.log('main function started');
+
+const stateCheckpointPath = `/tmp/sb-state-${crypto.randomUUID()}.json`;
+
+async function boot(stateCheckpointPath: string) {
+	const worker = await EdgeRuntime.userWorkers.create({
+		servicePath: './test_cases/state_checkpoint',
+		memoryLimitMb: 150,
+		workerTimeoutMs: 60 * 1000,
+		noModuleCache: false,
+		importMapPath: null,
+		envVars: [],
+		forceCreate: true,
+		stateCheckpointPath,
+	});
+
+	const res = await worker.fetch(new Request('http://localhost/'));
+	const { count } = await res.json();
+
+	return count;
+}
+
+Deno.serve(async () => {
+	try {
+		// NOTE: The second worker restores what the first one checkpointed.
+		const first = await boot(stateCheckpointPath);
+		const second = await boot(stateCheckpointPath);
+		// NOTE: The checkpoint can't be read through a file, so the worker
+		// starts from an empty state.
+		const unreadable = await boot('/dev/null/state.json');
+
+		return Response.json({ first, second, unreadable });
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
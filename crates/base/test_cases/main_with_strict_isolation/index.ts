@@ -0,0 +1,36 @@
+console.log('main function started');
+
+async function runWith(options: Record<string, boolean>) {
+	const worker = await EdgeRuntime.userWorkers.create({
+		servicePath: './test_cases/strict_isolation',
+		memoryLimitMb: 150,
+		workerTimeoutMs: 60 * 1000,
+		noModuleCache: false,
+		importMapPath: null,
+		envVars: [],
+		forceCreate: true,
+		...options,
+	});
+
+	const resp = await worker.fetch(new Request('http://localhost/'));
+	const body = await resp.json();
+
+	await worker.terminate();
+
+	return body;
+}
+
+Deno.serve(async () => {
+	try {
+		return Response.json({
+			relaxed: await runWith({}),
+			strict: await runWith({ strictIsolation: true }),
+		});
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
@@ -0,0 +1,44 @@
+console.log('main function started');
+
+Deno.serve(async (req: Request) => {
+	const url = new URL(req.url);
+	const { pathname } = url;
+	const service_name = pathname.split('/')[1];
+	const servicePath = `./test_cases/${service_name}`;
+
+	try {
+		const worker = await EdgeRuntime.userWorkers.create({
+			servicePath,
+			memoryLimitMb: 150,
+			workerTimeoutMs: 60 * 1000,
+			noModuleCache: false,
+			importMapPath: null,
+			envVars: [],
+			labels: { tenant: 'acme' },
+		});
+
+		const resp = await worker.fetch(req);
+
+		await resp.text();
+		await worker.terminate();
+
+		// Usage is accrued once the worker has exited.
+		let usage;
+		for (let i = 0; i < 50 && !usage; i++) {
+			const tenants = await EdgeRuntime.getUsage();
+			usage = tenants.find((it) => it.tenant === 'acme')?.usage;
+
+			if (!usage) {
+				await new Promise((res) => setTimeout(res, 100));
+			}
+		}
+
+		return Response.json({ usage: usage ?? null });
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
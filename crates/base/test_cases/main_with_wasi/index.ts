@@ -0,0 +1,37 @@
+console.log('main function started');
+
+async function runWith(wasiPermissions: Record<string, boolean>) {
+	const worker = await EdgeRuntime.userWorkers.create({
+		servicePath: './test_cases/wasi',
+		memoryLimitMb: 150,
+		workerTimeoutMs: 60 * 1000,
+		noModuleCache: false,
+		importMapPath: null,
+		envVars: [],
+		forceCreate: true,
+		wasiPermissions,
+	});
+
+	const resp = await worker.fetch(new Request('http://localhost/'));
+	const body = await resp.json();
+
+	await worker.terminate();
+
+	return body;
+}
+
+Deno.serve(async () => {
+	try {
+		return Response.json({
+			granted: await runWith({ clock: true, random: true }),
+			denied: await runWith({ clock: false, random: false }),
+			files: await runWith({ fs: true, clock: true, random: true }),
+		});
+	} catch (e) {
+		console.error(e);
+		return new Response(
+			JSON.stringify({ msg: e.toString() }),
+			{ status: 500, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+});
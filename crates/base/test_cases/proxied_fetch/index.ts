@@ -0,0 +1,8 @@
+Deno.serve(async () => {
+	try {
+		await fetch('http://example.com/');
+		return Response.json({ error: null });
+	} catch (e) {
+		return Response.json({ error: e.name });
+	}
+});
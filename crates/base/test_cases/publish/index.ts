@@ -0,0 +1,8 @@
+Deno.serve(async () => {
+	try {
+		await Supabase.publish('orders.created', { id: 1 }, { key: '1' });
+		return Response.json({ error: null });
+	} catch (e) {
+		return Response.json({ error: e.name });
+	}
+});
@@ -0,0 +1 @@
+Deno.serve((req) => new Response(`routed: ${new URL(req.url).pathname}`));
@@ -0,0 +1,7 @@
+Deno.serve(() => {
+	return Response.json({
+		secret: EdgeRuntime.getSecret('API_KEY'),
+		missing: EdgeRuntime.getSecret('MISSING') ?? null,
+		env: Deno.env.get('API_KEY') ?? null,
+	});
+});
@@ -0,0 +1,11 @@
+Deno.serve(async () => {
+	const path = new URL('./written.txt', import.meta.url).pathname;
+
+	try {
+		await Deno.writeTextFile(path, 'hello');
+
+		return Response.json({ path, text: await Deno.readTextFile(path) });
+	} catch (e) {
+		return Response.json({ msg: e.toString() }, { status: 500 });
+	}
+});
@@ -0,0 +1 @@
+Deno.serve((req) => new Response(`hello: ${new URL(req.url).pathname}`));
@@ -0,0 +1,9 @@
+const state = EdgeRuntime.state.declare({ count: 0 });
+
+Deno.serve(async () => {
+	state.count += 1;
+	EdgeRuntime.state.markDirty();
+	await EdgeRuntime.state.checkpoint();
+
+	return Response.json({ count: state.count });
+});
@@ -0,0 +1,27 @@
+// A module that only defines a shared memory of one page.
+const SHARED_MEMORY_MODULE = new Uint8Array([
+	0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+	0x05, 0x04, 0x01, 0x03, 0x01, 0x01,
+]);
+
+async function rejects(fn: () => unknown) {
+	try {
+		await fn();
+		return false;
+	} catch {
+		return true;
+	}
+}
+
+Deno.serve(async () => {
+	return Response.json({
+		sharedArrayBuffer: typeof globalThis.SharedArrayBuffer,
+		finalizationRegistry: typeof globalThis.FinalizationRegistry,
+		weakRef: typeof globalThis.WeakRef,
+		sharedMemoryRejected: await rejects(() =>
+			new WebAssembly.Memory({ initial: 1, maximum: 1, shared: true })
+		),
+		sharedModuleRejected: await rejects(() => WebAssembly.compile(SHARED_MEMORY_MODULE)),
+		memoryAllowed: !(await rejects(() => new WebAssembly.Memory({ initial: 1 }))),
+	});
+});
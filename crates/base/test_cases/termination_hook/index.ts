@@ -0,0 +1,8 @@
+addEventListener('beforeunload', (ev) => {
+  ev.waitUntil(new Promise((resolve) => {
+    setTimeout(() => {
+      globalThis.isCleanedUp = true;
+      resolve();
+    }, 100);
+  }));
+});
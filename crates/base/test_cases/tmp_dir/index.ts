@@ -0,0 +1,29 @@
+Deno.serve(async (req: Request) => {
+	const params = new URL(req.url).searchParams;
+	const size = Number(params.get('size') ?? '0');
+
+	try {
+		const path = await Deno.makeTempFile();
+
+		if (params.has('stream')) {
+			// NOTE: The file is opened while there is still room.
+			const file = await Deno.open(path, { write: true });
+
+			try {
+				for (let written = 0; written < size;) {
+					const chunk = new Uint8Array(Math.min(64 * 1024, size - written));
+
+					written += await file.write(chunk);
+				}
+			} finally {
+				file.close();
+			}
+		} else {
+			await Deno.writeFile(path, new Uint8Array(size));
+		}
+
+		return Response.json({ path });
+	} catch (e) {
+		return Response.json({ msg: e.toString() }, { status: 507 });
+	}
+});
@@ -0,0 +1,6 @@
+Deno.serve(async (req: Request) => {
+	const form = await req.formData();
+	const file = form.get('file') as File;
+
+	return new Response(String(file.size));
+});
@@ -0,0 +1,10 @@
+Deno.serve((req: Request) => {
+	const forged = new Headers(req.headers);
+
+	forged.set("x-sb-client-ip", "1.2.3.4");
+
+	return Response.json({
+		verified: EdgeRuntime.verifyRequest(req),
+		forged: EdgeRuntime.verifyRequest(new Request(req.url, { headers: forged })),
+	});
+});
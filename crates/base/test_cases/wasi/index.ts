@@ -0,0 +1,108 @@
+/*
+WAT sample
+    (module
+        (import "wasi_snapshot_preview1" "random_get" (func $random_get (param i32 i32) (result i32)))
+        (import "wasi_snapshot_preview1" "clock_time_get" (func $clock_time_get (param i32 i64 i32) (result i32)))
+        (memory (export "memory") 1)
+        (func (export "random") (result i32)
+            (call $random_get (i32.const 0) (i32.const 8)))
+        (func (export "clock") (result i32)
+            (call $clock_time_get (i32.const 0) (i64.const 1) (i32.const 16)))
+    )
+*/
+const bytes = new Uint8Array([
+	0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x12, 0x03, 0x60, 0x02, 0x7f, 0x7f, 0x01,
+	0x7f, 0x60, 0x03, 0x7f, 0x7e, 0x7f, 0x01, 0x7f, 0x60, 0x00, 0x01, 0x7f, 0x02, 0x4d, 0x02, 0x16,
+	0x77, 0x61, 0x73, 0x69, 0x5f, 0x73, 0x6e, 0x61, 0x70, 0x73, 0x68, 0x6f, 0x74, 0x5f, 0x70, 0x72,
+	0x65, 0x76, 0x69, 0x65, 0x77, 0x31, 0x0a, 0x72, 0x61, 0x6e, 0x64, 0x6f, 0x6d, 0x5f, 0x67, 0x65,
+	0x74, 0x00, 0x00, 0x16, 0x77, 0x61, 0x73, 0x69, 0x5f, 0x73, 0x6e, 0x61, 0x70, 0x73, 0x68, 0x6f,
+	0x74, 0x5f, 0x70, 0x72, 0x65, 0x76, 0x69, 0x65, 0x77, 0x31, 0x0e, 0x63, 0x6c, 0x6f, 0x63, 0x6b,
+	0x5f, 0x74, 0x69, 0x6d, 0x65, 0x5f, 0x67, 0x65, 0x74, 0x00, 0x01, 0x03, 0x03, 0x02, 0x02, 0x02,
+	0x05, 0x03, 0x01, 0x00, 0x01, 0x07, 0x1b, 0x03, 0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02,
+	0x00, 0x06, 0x72, 0x61, 0x6e, 0x64, 0x6f, 0x6d, 0x00, 0x02, 0x05, 0x63, 0x6c, 0x6f, 0x63, 0x6b,
+	0x00, 0x03, 0x0a, 0x15, 0x02, 0x08, 0x00, 0x41, 0x00, 0x41, 0x08, 0x10, 0x00, 0x0b, 0x0a, 0x00,
+	0x41, 0x00, 0x42, 0x01, 0x41, 0x10, 0x10, 0x01, 0x0b,
+]);
+
+const PREOPEN_FD = 3;
+
+// Calls the host functions the way a module would, through the memory of an
+// instance bound to a WASI with a preopened directory.
+async function readPreopened(path: string) {
+	const wasi = new Supabase.WASI({ preopens: { '/data': './test_cases/wasi' } });
+	const { instance } = await WebAssembly.instantiate(bytes, {
+		wasi_snapshot_preview1: wasi.exports,
+	});
+
+	wasi.initialize(instance);
+
+	const { memory } = instance.exports as { memory: WebAssembly.Memory };
+	const view = new DataView(memory.buffer);
+	const mem = new Uint8Array(memory.buffer);
+	const encoded = new TextEncoder().encode(path);
+
+	mem.set(encoded, 64);
+
+	const statErrno = wasi.exports.path_filestat_get(PREOPEN_FD, 0, 64, encoded.length, 128);
+	const openErrno = wasi.exports.path_open(PREOPEN_FD, 0, 64, encoded.length, 0, 0n, 0n, 0, 200);
+
+	let text = null;
+
+	if (openErrno === 0) {
+		// A single iovec of 256 bytes at 1024.
+		view.setUint32(208, 1024, true);
+		view.setUint32(212, 256, true);
+		wasi.exports.fd_read(view.getUint32(200, true), 208, 1, 216);
+		text = new TextDecoder().decode(mem.subarray(1024, 1024 + view.getUint32(216, true)));
+	}
+
+	return {
+		statErrno,
+		size: statErrno === 0 ? Number(view.getBigUint64(128 + 32, true)) : null,
+		openErrno,
+		text,
+	};
+}
+
+Deno.serve(async () => {
+	const wasi = new Supabase.WASI();
+	const { instance } = await WebAssembly.instantiate(bytes, {
+		wasi_snapshot_preview1: wasi.exports,
+	});
+
+	wasi.initialize(instance);
+
+	const { random, clock, memory } = instance.exports as {
+		random: () => number;
+		clock: () => number;
+		memory: WebAssembly.Memory;
+	};
+
+	const randomErrno = random();
+	const clockErrno = clock();
+	const view = new DataView(memory.buffer);
+
+	let preopenDenied = false;
+	let preopen = null;
+	let escape = null;
+
+	try {
+		new Supabase.WASI({ preopens: { '/data': './test_cases/wasi' } });
+	} catch {
+		preopenDenied = true;
+	}
+
+	if (!preopenDenied) {
+		preopen = await readPreopened('sub/../data.txt');
+		escape = await readPreopened('../wasi/data.txt');
+	}
+
+	return Response.json({
+		randomErrno,
+		clockErrno,
+		now: Number(view.getBigUint64(16, true) / 1000000n),
+		preopenDenied,
+		preopen,
+		escape,
+	});
+});
@@ -0,0 +1,7 @@
+EdgeRuntime.webSockets.onMessage((ws, data) => {
+	ws.send(`${ws.attachment.room}:${data}`);
+});
+
+Deno.serve((req: Request) => {
+	return EdgeRuntime.webSockets.accept(req, { attachment: { room: "meow" } });
+});
@@ -0,0 +1,8 @@
+Deno.serve(() => {
+	const info = EdgeRuntime.getInfo();
+
+	return Response.json({
+		...info,
+		hasUserWorkers: "userWorkers" in EdgeRuntime,
+	});
+});
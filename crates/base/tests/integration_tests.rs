@@ -6,8 +6,11 @@ use std::{
     collections::HashMap,
     io::{self, Cursor},
     net::{IpAddr, Ipv4Addr, SocketAddr},
-    path::Path,
-    sync::Arc,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
@@ -15,8 +18,16 @@ use anyhow::Context;
 use async_tungstenite::WebSocketStream;
 use base::{
     integration_test, integration_test_listen_fut, integration_test_with_server_flag,
-    rt_worker::worker_ctx::{create_user_worker_pool, create_worker, TerminationToken},
-    server::{ServerEvent, ServerFlags, ServerHealth, Tls},
+    listener::ListenerConfig,
+    router::RoutingTable,
+    rt_worker::{
+        cluster::{ClusterConfig, SigningKey},
+        jwt::{Algorithm, JwtConfig, JwtVerifier},
+        middleware::{Middleware, MiddlewareContext},
+        worker_ctx::{create_user_worker_pool, create_worker, TerminationToken},
+        worker_pool::{SupervisorPolicy, WorkerPoolPolicy},
+    },
+    server::{ServerEvent, ServerFlags, ServerHealth, Tls, WorkerEntrypoints, CLIENT_ADDR_HEADER},
     DecoratorType,
 };
 use deno_core::serde_json;
@@ -30,7 +41,7 @@ use reqwest::{
     Response,
 };
 use reqwest::{Certificate, Client, RequestBuilder};
-use sb_core::SharedMetricSource;
+use sb_core::{conn_sync::ConnectionInfo, SharedMetricSource};
 use sb_workers::context::{
     MainWorkerRuntimeOpts, WorkerContextInitOpts, WorkerRequestMsg, WorkerRuntimeOpts,
 };
@@ -85,6 +96,1971 @@ async fn test_custom_readable_stream_response() {
     );
 }
 
+#[tokio::test]
+#[serial]
+async fn test_user_worker_get_info() {
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "worker_info",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+            let remaining_ms = body["wallClockRemainingMs"].as_u64().unwrap();
+
+            assert!(!body["key"].as_str().unwrap().is_empty());
+            assert!(remaining_ms > 0 && remaining_ms <= 10 * 60 * 1000);
+            assert!(body["cpuTimeUsedMs"].is_u64());
+            assert!(body["memoryUsage"]["heapUsed"].as_u64().unwrap() > 0);
+            assert_eq!(body["hasUserWorkers"], false);
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_on_boot_hook() {
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "lifecycle_hooks",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["booted"], true);
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_main_worker_can_list_and_terminate_user_workers() {
+    integration_test!(
+        "./test_cases/main_with_admin",
+        NON_SECURE_PORT,
+        "readable-stream-resp",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["found"], true);
+            assert_eq!(body["servicePath"], "./test_cases/readable-stream-resp");
+            assert_eq!(body["requestCount"], 1);
+            assert_eq!(body["labels"]["tenant"], "acme");
+            assert_eq!(body["labeledCount"], 1);
+            assert_eq!(body["unlabeledCount"], 0);
+            assert!(body["supervisorPolicy"].is_string());
+            assert_eq!(body["reusedAcrossLabels"], false);
+            assert_eq!(body["emptySelectorRejected"], true);
+            assert_eq!(body["terminatedOther"], 1);
+            assert_eq!(body["terminated"], true);
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_main_worker_drain_terminates_user_workers() {
+    integration_test!(
+        "./test_cases/main_with_drain",
+        NON_SECURE_PORT,
+        "readable-stream-resp",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["remaining"], 0);
+            assert!(body["drainMs"].as_u64().unwrap() < 10 * 1000);
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_main_worker_can_query_usage_per_tenant() {
+    integration_test!(
+        "./test_cases/main_with_usage",
+        NON_SECURE_PORT,
+        "readable-stream-resp",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+            let usage = &body["usage"];
+
+            assert_eq!(usage["worker_count"], 1);
+            assert_eq!(usage["request_count"], 1);
+            assert!(usage["egress_bytes"].as_u64().unwrap() > 0);
+            assert!(usage["memory_gb_seconds"].as_f64().unwrap() > 0.0);
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_can_read_secrets() {
+    integration_test!(
+        "./test_cases/main_with_secrets",
+        NON_SECURE_PORT,
+        "secrets",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["secret"], "sk_test_123");
+            assert!(body["missing"].is_null());
+            assert!(body["env"].is_null());
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_health_endpoints_are_served_by_listener() {
+    for (path, status) in [
+        ("health/live", StatusCode::OK),
+        ("health/ready", StatusCode::OK),
+    ] {
+        integration_test_with_server_flag!(
+            ServerFlags {
+                health_endpoints: true,
+                ..Default::default()
+            },
+            "./test_cases/main",
+            NON_SECURE_PORT,
+            path,
+            None,
+            None,
+            None,
+            None,
+            (|resp| async move {
+                let res = resp.unwrap();
+
+                assert_eq!(res.status(), status);
+
+                let body = res.json::<serde_json::Value>().await.unwrap();
+
+                assert_eq!(body["status"], "ok");
+            }),
+            TerminationToken::new()
+        );
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_listener_strips_untrusted_headers() {
+    let client = Client::new();
+    let req = client
+        .request(
+            Method::GET,
+            format!("http://localhost:{}/echo_headers", NON_SECURE_PORT),
+        )
+        .header("x-sb-jwt-claims", "spoofed")
+        .header("x-sb-client-ip", "1.2.3.4")
+        .header("x-forwarded-for", "5.6.7.8")
+        .header("x-custom", "kept")
+        .build()
+        .unwrap();
+
+    let request_builder = Some(RequestBuilder::from_parts(client, req));
+
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        request_builder,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert!(body["x-sb-jwt-claims"].is_null());
+            assert_eq!(body["x-sb-client-ip"], "127.0.0.1");
+            assert!(body["x-forwarded-for"].is_null());
+            assert_eq!(body["x-custom"], "kept");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_listener_keeps_forwarded_headers_behind_trusted_proxy() {
+    let client = Client::new();
+    let req = client
+        .request(
+            Method::GET,
+            format!("http://localhost:{}/echo_headers", NON_SECURE_PORT),
+        )
+        .header("x-sb-jwt-claims", "spoofed")
+        .header("x-forwarded-for", "5.6.7.8")
+        .build()
+        .unwrap();
+
+    let request_builder = Some(RequestBuilder::from_parts(client, req));
+
+    integration_test_with_server_flag!(
+        ServerFlags {
+            trust_forwarded_headers: true,
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        request_builder,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert!(body["x-sb-jwt-claims"].is_null());
+            assert_eq!(body["x-forwarded-for"], "5.6.7.8");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_can_verify_request_signature() {
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "verify_signature",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["verified"], true);
+            assert_eq!(body["forged"], false);
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_direct_request_bridge_reads_request_body() {
+    let client = Client::new();
+    let req = client
+        .request(
+            Method::POST,
+            format!("http://localhost:{}/expect_continue", NON_SECURE_PORT),
+        )
+        .header("x-custom", "kept")
+        .body(vec![b'a'; 1024 * 64])
+        .build()
+        .unwrap();
+
+    let request_builder = Some(RequestBuilder::from_parts(client, req));
+
+    integration_test_with_server_flag!(
+        ServerFlags {
+            direct_request_bridge: true,
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        request_builder,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+            assert_eq!(res.text().await.unwrap(), (1024 * 64).to_string());
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_direct_request_bridge_streams_response_body() {
+    integration_test_with_server_flag!(
+        ServerFlags {
+            direct_request_bridge: true,
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "readable-stream-resp",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+            assert_eq!(res.text().await.unwrap(), "Hello world from streams");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_startup_self_test_passes() {
+    integration_test_with_server_flag!(
+        ServerFlags {
+            startup_self_test: true,
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "std_user_worker",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            assert_eq!(resp.unwrap().status().as_u16(), 200);
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_deterministic_seed() {
+    integration_test!(
+        "./test_cases/main_with_deterministic_seed",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+            let (first, second) = (&body["first"], &body["second"]);
+
+            assert_eq!(first["random"], second["random"]);
+            assert_eq!(first["bytes"], second["bytes"]);
+            assert_eq!(first["now"], second["now"]);
+            assert_eq!(first["date"], "2024-01-01T00:00:00.000Z");
+            assert_eq!(first["isDate"], true);
+            assert_eq!(first["parsed"], 946684800000_u64);
+            assert_eq!(first["monotonic"], 0);
+
+            assert_ne!(first["random"], body["other"]["random"]);
+            assert_ne!(first["bytes"], body["other"]["bytes"]);
+            assert_ne!(first["now"], body["unseeded"]["now"]);
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_coarse_timers() {
+    integration_test!(
+        "./test_cases/main_with_coarse_timers",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["now"].as_f64().unwrap() % 100.0, 0.0);
+            assert_eq!(body["date"].as_f64().unwrap() % 100.0, 0.0);
+            assert_eq!(body["monotonic"].as_f64().unwrap() % 100.0, 0.0);
+            assert_eq!(body["sharedArrayBuffer"], "undefined");
+
+            for (name, value) in body["bypass"].as_object().unwrap() {
+                assert_eq!(value.as_f64().unwrap() % 100.0, 0.0, "{}", name);
+            }
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_wasi_permissions() {
+    integration_test!(
+        "./test_cases/main_with_wasi",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["granted"]["randomErrno"], 0);
+            assert_eq!(body["granted"]["clockErrno"], 0);
+            assert!(body["granted"]["now"].as_u64().unwrap() > 0);
+            assert_eq!(body["granted"]["preopenDenied"], true);
+
+            // ENOTCAPABLE
+            assert_eq!(body["denied"]["randomErrno"], 76);
+            assert_eq!(body["denied"]["clockErrno"], 76);
+
+            assert_eq!(body["files"]["preopenDenied"], false);
+            assert_eq!(
+                body["files"]["preopen"],
+                serde_json::json!({
+                    "statErrno": 0,
+                    "size": 11,
+                    "openErrno": 0,
+                    "text": "hello wasi\n",
+                })
+            );
+            assert_eq!(
+                body["files"]["escape"],
+                serde_json::json!({
+                    "statErrno": 76,
+                    "size": null,
+                    "openErrno": 76,
+                    "text": null,
+                })
+            );
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_strict_isolation() {
+    integration_test!(
+        "./test_cases/main_with_strict_isolation",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            // Shared memories can't be created by any worker.
+            for mode in ["relaxed", "strict"] {
+                assert_eq!(body[mode]["sharedArrayBuffer"], "undefined");
+                assert_eq!(body[mode]["sharedMemoryRejected"], true);
+                assert_eq!(body[mode]["memoryAllowed"], true);
+            }
+
+            assert_eq!(body["relaxed"]["finalizationRegistry"], "function");
+            assert_eq!(body["relaxed"]["weakRef"], "function");
+            assert_eq!(body["relaxed"]["sharedModuleRejected"], false);
+
+            assert_eq!(body["strict"]["finalizationRegistry"], "undefined");
+            assert_eq!(body["strict"]["weakRef"], "undefined");
+            assert_eq!(body["strict"]["sharedModuleRejected"], true);
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_capability_toggles() {
+    integration_test!(
+        "./test_cases/main_with_capabilities",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["denied"]["subprocess"], "PermissionDenied");
+            assert_eq!(body["denied"]["ffi"], "PermissionDenied");
+            assert_eq!(body["denied"]["rawNet"], "PermissionDenied");
+
+            assert_eq!(body["granted"]["subprocess"], serde_json::Value::Null);
+            assert_eq!(body["granted"]["ffi"], "NotSupported");
+            assert_ne!(body["granted"]["rawNet"], "PermissionDenied");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_image_ops() {
+    integration_test!(
+        "./test_cases/main_with_image",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["denied"]["error"], "PermissionDenied");
+
+            assert_eq!(body["granted"]["error"], serde_json::Value::Null);
+            assert_eq!(
+                body["granted"]["input"],
+                serde_json::json!({ "width": 4, "height": 2, "format": "png" })
+            );
+            assert_eq!(
+                body["granted"]["output"],
+                serde_json::json!({ "width": 1, "height": 1, "format": "jpeg" })
+            );
+
+            assert_eq!(body["overLimit"]["error"], "RateLimited");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_crypto_ops() {
+    integration_test!(
+        "./test_cases/main_with_crypto",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["verified"], true);
+            assert_eq!(body["overLimit"], "RateLimited");
+            assert_eq!(body["sign"], "PermissionDenied");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_geoip_without_database() {
+    let client = Client::new();
+    let req = client
+        .request(
+            Method::GET,
+            format!("http://localhost:{}/", NON_SECURE_PORT),
+        )
+        .header("x-sb-geo-country", "XX")
+        .build()
+        .unwrap();
+
+    let request_builder = Some(RequestBuilder::from_parts(client, req));
+
+    integration_test!(
+        "./test_cases/main_geoip",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        request_builder,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["error"], "NotSupported");
+            assert!(body["country"].is_null());
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_html_rewriter() {
+    integration_test!(
+        "./test_cases/html_rewriter",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+            assert_eq!(res.headers().get("content-type").unwrap(), "text/html");
+            assert_eq!(
+                res.text().await.unwrap(),
+                concat!(
+                    "<html><head><script src=\"/rum.js\"></script></head><body>",
+                    "<a href=\"/\" rel=\"nofollow\">home</a></body></html>"
+                )
+            );
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_db_query_without_pool() {
+    integration_test!(
+        "./test_cases/main_with_db",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["error"], "NotSupported");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_redis_without_pool() {
+    integration_test!(
+        "./test_cases/main_with_redis",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["error"], "NotSupported");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_publish_without_broker() {
+    integration_test!(
+        "./test_cases/main_with_publish",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["error"], "NotSupported");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_state_checkpoint() {
+    integration_test!(
+        "./test_cases/main_with_state_checkpoint",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["first"], 1);
+            assert_eq!(body["second"], 2);
+            assert_eq!(body["unreadable"], 1);
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_max_connections() {
+    integration_test!(
+        "./test_cases/main_with_max_connections",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["first"], 200);
+            assert_eq!(body["second"], 503);
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_max_connections_with_direct_request_bridge() {
+    integration_test_with_server_flag!(
+        ServerFlags {
+            direct_request_bridge: true,
+            ..Default::default()
+        },
+        "./test_cases/main_with_max_connections",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert_eq!(body["first"], 200);
+            assert_eq!(body["second"], 503);
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_server_timing_reports_boot_on_cold_request_only() {
+    integration_test_with_server_flag!(
+        ServerFlags {
+            server_timing: true,
+            ..Default::default()
+        },
+        "./test_cases/main_with_server_timing",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+            let phases = |key: &str| {
+                body[key]
+                    .as_str()
+                    .unwrap()
+                    .split(", ")
+                    .map(|it| it.split(';').next().unwrap().to_string())
+                    .collect::<Vec<_>>()
+            };
+
+            assert_eq!(phases("cold"), ["boot", "queue", "dispatch", "exec"]);
+            assert_eq!(phases("warm"), ["queue", "dispatch", "exec"]);
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_workers_share_fetch_client() {
+    integration_test!(
+        "./test_cases/main_with_fetch_pool",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            // The main worker and the user worker it created.
+            assert!(body["activeWorkersCount"].as_u64().unwrap() >= 2);
+            assert!(body["attachedWorkersCount"].as_u64().unwrap() >= 2);
+
+            // Two requests to the same host over one kept-alive connection.
+            assert_eq!(body["newConnections"], 1);
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_http_proxy_override() {
+    integration_test!(
+        "./test_cases/main_with_http_proxy",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert!(body["invalidProxy"]
+                .as_str()
+                .unwrap()
+                .contains("invalid proxy url"));
+            assert_eq!(body["error"], "TypeError");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_fetch_goes_through_http_proxy() {
+    let proxy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_url = format!("http://user:pass@{}", proxy.local_addr().unwrap());
+    let (head_tx, head_rx) = oneshot::channel::<String>();
+
+    tokio::spawn(async move {
+        let (mut stream, _) = proxy.accept().await.unwrap();
+        let mut head = vec![];
+        let mut buf = [0u8; 1024];
+
+        while !head.windows(4).any(|it| it == b"\r\n\r\n") {
+            let n = stream.read(&mut buf).await.unwrap();
+
+            if n == 0 {
+                break;
+            }
+
+            head.extend_from_slice(&buf[..n]);
+        }
+
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let _ = head_tx.send(String::from_utf8_lossy(&head).into_owned());
+    });
+
+    let tb = TestBedBuilder::new("./test_cases/main_with_http_proxy")
+        .build()
+        .await;
+
+    let mut res = tb
+        .request(|| {
+            Request::builder()
+                .uri(format!("/?proxy={}", encode(&proxy_url)))
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(to_bytes(res.body_mut()).await.unwrap(), r#"{"error":null}"#);
+
+    let head = timeout(Duration::from_secs(5), head_rx)
+        .await
+        .unwrap()
+        .unwrap()
+        .to_ascii_lowercase();
+
+    // The request went to the proxy, with the credentials taken out of its
+    // url. `dXNlcjpwYXNz` is `user:pass`.
+    assert!(head.starts_with("get http://example.com/ http/1.1\r\n"));
+    assert!(head.contains("proxy-authorization: basic dxnlcjpwyxnz\r\n"));
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+struct RequireTokenMiddleware;
+
+#[async_trait::async_trait]
+impl Middleware for RequireTokenMiddleware {
+    async fn on_request(
+        &self,
+        _ctx: &MiddlewareContext,
+        req: &mut Request<Body>,
+    ) -> Result<Option<HttpResponse<Body>>, anyhow::Error> {
+        if req.headers().contains_key("x-token") {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            HttpResponse::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .body(Body::empty())?,
+        ))
+    }
+
+    async fn on_response(
+        &self,
+        ctx: &MiddlewareContext,
+        res: &mut HttpResponse<Body>,
+    ) -> Result<(), anyhow::Error> {
+        res.headers_mut()
+            .insert("x-service-path", ctx.service_path.parse()?);
+
+        Ok(())
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_worker_pool_middleware_chain() {
+    let tb = TestBedBuilder::new("./test_cases/main")
+        .with_worker_pool_policy(
+            WorkerPoolPolicy::new(SupervisorPolicy::PerWorker, 1, ServerFlags::default())
+                .with_middleware(RequireTokenMiddleware),
+        )
+        .build()
+        .await;
+
+    let res = tb
+        .request(|| {
+            Request::builder()
+                .uri("/empty-response")
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        res.headers().get("x-service-path").unwrap(),
+        "./test_cases/empty-response"
+    );
+
+    let res = tb
+        .request(|| {
+            Request::builder()
+                .uri("/empty-response")
+                .method("GET")
+                .header("x-token", "meow")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        res.headers().get("x-service-path").unwrap(),
+        "./test_cases/empty-response"
+    );
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_worker_pool_rejects_requests_without_valid_jwt() {
+    let tb = TestBedBuilder::new("./test_cases/main")
+        .with_worker_pool_policy(
+            WorkerPoolPolicy::new(SupervisorPolicy::PerWorker, 1, ServerFlags::default())
+                .with_jwt_verifier(JwtVerifier::new(JwtConfig {
+                    // NOTE: Malformed tokens are rejected before the key set
+                    // is ever fetched.
+                    jwks_url: "http://127.0.0.1:1/jwks.json".to_string(),
+                    algorithms: vec![Algorithm::RS256],
+                    issuer: None,
+                    audience: None,
+                    jwks_ttl: Duration::from_secs(600),
+                })),
+        )
+        .build()
+        .await;
+
+    // NOTE: Well formed, but signed with an algorithm that isn't allowed.
+    let hs256_token = format!(
+        "Bearer {}",
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::new(Algorithm::HS256),
+            &serde_json::json!({ "sub": "meow" }),
+            &jsonwebtoken::EncodingKey::from_secret(b"meow"),
+        )
+        .unwrap()
+    );
+
+    for maybe_token in [None, Some("Bearer meow"), Some(hs256_token.as_str())] {
+        let res = tb
+            .request(|| {
+                let mut builder = Request::builder().uri("/empty-response").method("GET");
+
+                if let Some(token) = maybe_token {
+                    builder = builder.header("authorization", token);
+                }
+
+                builder.body(Body::empty()).context("can't make request")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_busy_worker_gets_no_new_requests() {
+    let tb = TestBedBuilder::new("./test_cases/main_with_busy_worker")
+        .with_per_worker_policy(100000)
+        .build()
+        .await;
+
+    let mut res = tb
+        .request(|| {
+            Request::builder()
+                .uri("/")
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body = to_bytes(res.body_mut()).await.unwrap();
+    let body = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+
+    assert_eq!(body["reusedWhileIdle"], true);
+    assert_eq!(body["busy"], true);
+    assert_eq!(body["reusedWhileBusy"], false);
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_worker_pool_live_worker_budget() {
+    let available_parallelism = std::thread::available_parallelism()
+        .map(|it| it.get())
+        .unwrap_or(1);
+
+    let tb = TestBedBuilder::new("./test_cases/main_with_live_worker_budget")
+        .with_worker_pool_policy(WorkerPoolPolicy::new(
+            SupervisorPolicy::PerWorker,
+            1,
+            ServerFlags {
+                max_live_workers_per_core: Some(1),
+                request_wait_timeout_ms: Some(1000),
+                ..Default::default()
+            },
+        ))
+        .build()
+        .await;
+
+    let mut res = tb
+        .request(|| {
+            Request::builder()
+                .uri("/")
+                .method("GET")
+                .header("x-worker-count", available_parallelism.to_string())
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body = to_bytes(res.body_mut()).await.unwrap();
+    let body = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+
+    assert_eq!(body["saturated"], true);
+    assert!(body["msg"]
+        .as_str()
+        .unwrap()
+        .contains("saturated with live workers"));
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_failed_boot_wakes_up_waiting_creations() {
+    let tb = TestBedBuilder::new("./test_cases/main")
+        .with_per_worker_policy(30_000)
+        .build()
+        .await;
+
+    let request = || async {
+        tb.request(|| {
+            Request::builder()
+                .uri("/this-service-does-not-exist")
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap()
+    };
+
+    // NOTE: Both creations contend for the only permit of the service. The one
+    // left waiting must not sit out the whole wait timeout once the other
+    // fails to boot.
+    let started_at = std::time::Instant::now();
+    let (first, second) = join!(request(), request());
+
+    assert_eq!(first.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(second.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(started_at.elapsed() < Duration::from_secs(10));
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_handler_export() {
+    let tb = TestBedBuilder::new("./test_cases/main_with_handler_export")
+        .with_per_worker_policy(100000)
+        .build()
+        .await;
+
+    for (export_name, expected) in [("handler", "handler: /foo"), ("default", "default export")] {
+        let mut res = tb
+            .request(|| {
+                Request::builder()
+                    .uri("/foo")
+                    .method("GET")
+                    .header("x-handler-export", export_name)
+                    .body(Body::empty())
+                    .context("can't make request")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body = to_bytes(res.body_mut()).await.unwrap();
+
+        assert_eq!(body.as_ref(), expected.as_bytes());
+    }
+
+    let res = tb
+        .request(|| {
+            Request::builder()
+                .uri("/foo")
+                .method("GET")
+                .header("x-handler-export", "missing")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_request_body_policy() {
+    let tb = TestBedBuilder::new("./test_cases/main_with_request_body")
+        .with_per_worker_policy(100000)
+        .build()
+        .await;
+
+    let boundary = "sb-test-boundary";
+    let body = [
+        format!("--{}\r\n", boundary),
+        String::from("Content-Disposition: form-data; name=\"file\"; filename=\"meow.bin\"\r\n"),
+        String::from("Content-Type: application/octet-stream\r\n\r\n"),
+        "a".repeat(4096),
+        format!("\r\n--{}--\r\n", boundary),
+    ]
+    .concat();
+
+    for (policy, status, maybe_body) in [
+        (serde_json::json!(null), StatusCode::OK, Some("4096")),
+        (
+            serde_json::json!({ "maxBytes": 1024 }),
+            StatusCode::PAYLOAD_TOO_LARGE,
+            None,
+        ),
+        (
+            serde_json::json!({ "maxPartBytes": 1024 }),
+            StatusCode::PAYLOAD_TOO_LARGE,
+            None,
+        ),
+        (
+            serde_json::json!({ "spillThresholdBytes": 16 }),
+            StatusCode::OK,
+            Some("4096"),
+        ),
+        (
+            serde_json::json!({ "spillThresholdBytes": 16, "diskQuotaBytes": 64 }),
+            StatusCode::INSUFFICIENT_STORAGE,
+            None,
+        ),
+    ] {
+        let mut res = tb
+            .request(|| {
+                Request::builder()
+                    .uri("/")
+                    .method("POST")
+                    .header(
+                        "content-type",
+                        format!("multipart/form-data; boundary={}", boundary),
+                    )
+                    .header("content-length", body.len())
+                    .header("x-request-body-policy", policy.to_string())
+                    .body(Body::from(body.clone()))
+                    .context("can't make request")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), status, "policy: {}", policy);
+
+        if let Some(expected) = maybe_body {
+            let body = to_bytes(res.body_mut()).await.unwrap();
+
+            assert_eq!(body.as_ref(), expected.as_bytes());
+        }
+    }
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_tmp_dir_quota() {
+    let tb = TestBedBuilder::new("./test_cases/main_with_tmp_dir")
+        .with_per_worker_policy(100000)
+        .build()
+        .await;
+
+    for (size, stream, status) in [
+        (512 * 1024, false, StatusCode::OK),
+        (768 * 1024, false, StatusCode::INSUFFICIENT_STORAGE),
+        (256 * 1024, false, StatusCode::OK),
+        // Writes through an open file count too.
+        (768 * 1024, true, StatusCode::INSUFFICIENT_STORAGE),
+    ] {
+        let mut res = tb
+            .request(|| {
+                Request::builder()
+                    .uri(format!(
+                        "/?size={}{}",
+                        size,
+                        if stream { "&stream" } else { "" }
+                    ))
+                    .method("GET")
+                    .body(Body::empty())
+                    .context("can't make request")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), status, "size: {}", size);
+
+        if status == StatusCode::OK {
+            let body = to_bytes(res.body_mut()).await.unwrap();
+            let body = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+            let path = PathBuf::from(body["path"].as_str().unwrap());
+
+            assert!(path.starts_with(std::env::temp_dir()));
+            assert!(path
+                .parent()
+                .and_then(|it| it.file_name())
+                .is_some_and(|it| it.to_string_lossy().starts_with("sb-worker-")));
+        }
+    }
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_writes_to_service_dir_are_kept_apart() {
+    let tb = TestBedBuilder::new("./test_cases/main_with_service_overlay")
+        .with_per_worker_policy(100000)
+        .build()
+        .await;
+
+    let mut res = tb
+        .request(|| {
+            Request::builder()
+                .uri("/")
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body = to_bytes(res.body_mut()).await.unwrap();
+    let body = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+
+    assert_eq!(body["text"], "hello");
+    assert!(!PathBuf::from("./test_cases/service_overlay/written.txt").exists());
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_response_body_limit() {
+    let tb = TestBedBuilder::new("./test_cases/main_with_response_limit")
+        .with_per_worker_policy(100000)
+        .build()
+        .await;
+
+    let send = |uri: &'static str| {
+        tb.request(move || {
+            Request::builder()
+                .uri(uri)
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+    };
+
+    let mut res = send("/?size=512").await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(to_bytes(res.body_mut()).await.unwrap().len(), 512);
+
+    let res = send("/?size=4096").await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+
+    // The head is already on its way when the limit is hit, so the body is
+    // cut off instead.
+    let mut res = send("/?size=4096&mode=stream").await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(to_bytes(res.body_mut()).await.is_err());
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_expect_continue_holds_body_until_requested() {
+    let tb = TestBedBuilder::new("./test_cases/expect_continue")
+        .with_per_worker_policy(100000)
+        .build()
+        .await;
+
+    for (reject, status, body_sent) in [
+        (true, StatusCode::PAYLOAD_TOO_LARGE, false),
+        (false, StatusCode::OK, true),
+    ] {
+        let is_body_polled = Arc::new(AtomicBool::new(false));
+        let mut res = tb
+            .request(|| {
+                let is_body_polled = is_body_polled.clone();
+                let body = futures_util::stream::once(async move {
+                    is_body_polled.store(true, Ordering::Release);
+                    Ok::<_, io::Error>("meow".repeat(1024))
+                });
+
+                let mut builder = Request::builder()
+                    .uri("/")
+                    .method("POST")
+                    .header("expect", "100-continue")
+                    .header("content-length", 4096);
+
+                if reject {
+                    builder = builder.header("x-reject", "1");
+                }
+
+                builder
+                    .body(Body::wrap_stream(body))
+                    .context("can't make request")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), status);
+
+        if body_sent {
+            let body = to_bytes(res.body_mut()).await.unwrap();
+
+            assert_eq!(body.as_ref(), b"4096");
+        }
+
+        assert_eq!(is_body_polled.load(Ordering::Acquire), body_sent);
+    }
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_cors_preflight() {
+    let tb = TestBedBuilder::new("./test_cases/main_with_cors")
+        .with_per_worker_policy(100000)
+        .build()
+        .await;
+
+    let res = tb
+        .request(|| {
+            Request::builder()
+                .uri("/")
+                .method("OPTIONS")
+                .header("origin", "https://example.com")
+                .header("access-control-request-method", "POST")
+                .header("access-control-request-headers", "content-type")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    let headers = res.headers();
+
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        headers.get("access-control-allow-origin").unwrap(),
+        "https://example.com"
+    );
+    assert_eq!(
+        headers.get("access-control-allow-methods").unwrap(),
+        "GET, POST"
+    );
+    assert_eq!(
+        headers.get("access-control-allow-headers").unwrap(),
+        "content-type"
+    );
+    assert_eq!(headers.get("access-control-max-age").unwrap(), "600");
+
+    let res = tb
+        .request(|| {
+            Request::builder()
+                .uri("/")
+                .method("OPTIONS")
+                .header("origin", "https://evil.example")
+                .header("access-control-request-method", "POST")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert!(res.headers().get("access-control-allow-origin").is_none());
+
+    let res = tb
+        .request(|| {
+            Request::builder()
+                .uri("/")
+                .method("GET")
+                .header("origin", "https://example.com")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers().get("access-control-allow-origin").unwrap(),
+        "https://example.com"
+    );
+
+    let mut res = tb
+        .request(|| {
+            Request::builder()
+                .uri("/wildcard-with-credentials")
+                .method("GET")
+                .header("origin", "https://evil.example")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(
+        String::from_utf8_lossy(&to_bytes(res.body_mut()).await.unwrap())
+            .contains("must list its origins")
+    );
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_user_worker_ip_access_list() {
+    let tb = TestBedBuilder::new("./test_cases/main_with_ip_acl")
+        .with_per_worker_policy(100000)
+        .build()
+        .await;
+
+    for (maybe_client_ip, status) in [
+        (Some("10.0.0.1"), StatusCode::OK),
+        (Some("10.1.2.3"), StatusCode::FORBIDDEN),
+        (Some("192.168.0.1"), StatusCode::FORBIDDEN),
+        (Some("::ffff:10.0.0.1"), StatusCode::OK),
+        // Not through a listener, so the address is unknown.
+        (None, StatusCode::FORBIDDEN),
+    ] {
+        let res = tb
+            .request(|| {
+                let mut builder = Request::builder()
+                    .uri("/")
+                    .method("GET")
+                    // NOTE: Only the address of the connection counts.
+                    .header(CLIENT_ADDR_HEADER, "10.0.0.1");
+
+                if let Some(ip) = maybe_client_ip {
+                    builder = builder.extension(ConnectionInfo {
+                        remote_addr: SocketAddr::new(ip.parse().unwrap(), 56324),
+                        local_addr: SocketAddr::from(([127, 0, 0, 1], NON_SECURE_PORT)),
+                        tls: None,
+                    });
+                }
+
+                builder.body(Body::empty()).context("can't make request")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), status, "client ip: {:?}", maybe_client_ip);
+    }
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+async fn test_routing_table<F, R>(table: RoutingTable, test_fn: F)
+where
+    F: FnOnce(Box<dyn Fn(&str) -> RequestBuilder + Send>) -> R,
+    R: Future<Output = ()>,
+{
+    let token = TerminationToken::new();
+    let (health_tx, mut health_rx) = mpsc::channel(1);
+    let server = tokio::spawn(base::commands::start_server(
+        "0.0.0.0",
+        NON_SECURE_PORT,
+        None,
+        String::from("./test_cases/main"),
+        None,
+        None,
+        None,
+        None,
+        ServerFlags::default(),
+        Some(health_tx),
+        WorkerEntrypoints {
+            main: None,
+            events: None,
+        },
+        Some(token.clone()),
+        vec![],
+        None,
+        None,
+        None,
+        Default::default(),
+        vec![],
+        Some(table),
+        vec![],
+        None,
+        vec![],
+    ));
+
+    while !matches!(health_rx.recv().await, Some(ServerHealth::Listening(..))) {}
+
+    let client = Client::new();
+
+    test_fn(Box::new(move |path| {
+        client.get(format!("http://localhost:{}{}", NON_SECURE_PORT, path))
+    }))
+    .await;
+
+    if timeout(Duration::from_secs(10), token.cancel_and_wait())
+        .await
+        .is_err()
+    {
+        panic!("failed to terminate server within 10 seconds");
+    }
+
+    server.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_routing_table_bypasses_main_worker() {
+    let table = serde_json::from_value::<RoutingTable>(serde_json::json!({
+        "routes": [
+            {
+                "pathPrefix": "/routed",
+                "stripPrefix": true,
+                "servicePath": "./test_cases/routed"
+            },
+            {
+                "headers": { "x-tenant": "acme" },
+                "servicePath": "./test_cases/routed"
+            }
+        ]
+    }))
+    .unwrap();
+
+    test_routing_table(table, |get| async move {
+        let res = get("/routed/foo").send().await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.text().await.unwrap(), "routed: /foo");
+
+        let res = get("/ip_acl")
+            .header("x-tenant", "acme")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.text().await.unwrap(), "routed: /ip_acl");
+
+        // Falls back to the main worker.
+        let res = get("/ip_acl").send().await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.text().await.unwrap(), "meow");
+    })
+    .await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_services_dir_mounts_subdirectories() {
+    let table = RoutingTable {
+        services_dir: Some("./test_cases/services".into()),
+        ..Default::default()
+    };
+
+    test_routing_table(table, |get| async move {
+        let res = get("/hello/world").send().await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.text().await.unwrap(), "hello: /hello/world");
+
+        // Not a subdirectory of the services directory, so the main worker
+        // serves it.
+        let res = get("/ip_acl").send().await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.text().await.unwrap(), "meow");
+    })
+    .await;
+}
+
+async fn test_listeners<F, R>(listeners: &[&str], test_fn: F)
+where
+    F: FnOnce() -> R,
+    R: Future<Output = ()>,
+{
+    let token = TerminationToken::new();
+    let (health_tx, mut health_rx) = mpsc::channel(1);
+    let listeners = listeners
+        .iter()
+        .map(|it| it.parse::<ListenerConfig>().unwrap())
+        .collect::<Vec<_>>();
+    let server = tokio::spawn(base::commands::start_server(
+        "0.0.0.0",
+        NON_SECURE_PORT,
+        None,
+        String::from("./test_cases/main"),
+        None,
+        None,
+        None,
+        None,
+        ServerFlags::default(),
+        Some(health_tx),
+        WorkerEntrypoints {
+            main: None,
+            events: None,
+        },
+        Some(token.clone()),
+        vec![],
+        None,
+        None,
+        None,
+        Default::default(),
+        vec![],
+        None,
+        vec![],
+        None,
+        listeners,
+    ));
+
+    while !matches!(health_rx.recv().await, Some(ServerHealth::Listening(..))) {}
+
+    test_fn().await;
+
+    if timeout(Duration::from_secs(10), token.cancel_and_wait())
+        .await
+        .is_err()
+    {
+        panic!("failed to terminate server within 10 seconds");
+    }
+
+    server.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_listeners_have_their_own_main_worker_and_access_list() {
+    let listeners = [
+        "127.0.0.1:8499,main=./test_cases/main_internal",
+        "127.0.0.1:8500,deny-cidr=127.0.0.0/8",
+    ];
+
+    test_listeners(&listeners, || async {
+        let client = Client::new();
+        let get = |port: u16, path: &str| client.get(format!("http://127.0.0.1:{}{}", port, path));
+
+        let res = get(NON_SECURE_PORT, "/ip_acl").send().await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.text().await.unwrap(), "meow");
+
+        let res = get(8499, "/ip_acl").send().await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.text().await.unwrap(), "internal: /ip_acl");
+
+        let res = get(8500, "/ip_acl").send().await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    })
+    .await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_listener_reads_client_address_from_proxy_protocol_header() {
+    let listeners = [
+        "127.0.0.1:8499,proxy-protocol=true,trusted-proxy=127.0.0.0/8,deny-cidr=192.0.2.0/24",
+        "127.0.0.1:8500,proxy-protocol=true,trusted-proxy=10.0.0.0/8,deny-cidr=127.0.0.0/8",
+    ];
+
+    test_listeners(&listeners, || async {
+        let send = |port: u16, header: &'static str| async move {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            let mut res = String::new();
+
+            stream.write_all(header.as_bytes()).await.unwrap();
+            stream
+                .write_all(b"GET /ip_acl HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+
+            let _ = stream.read_to_string(&mut res).await;
+
+            res
+        };
+
+        let res = send(8499, "PROXY TCP4 198.51.100.7 127.0.0.1 56324 8499\r\n").await;
+
+        assert!(res.starts_with("HTTP/1.1 200"), "{}", res);
+        assert!(res.contains("meow"), "{}", res);
+
+        let res = send(8499, "PROXY TCP4 192.0.2.1 127.0.0.1 56324 8499\r\n").await;
+
+        assert!(res.starts_with("HTTP/1.1 403"), "{}", res);
+
+        // A connection without the header is closed without a response.
+        let res = send(8499, "").await;
+
+        assert!(res.is_empty(), "{}", res);
+
+        // The header is not read from a peer that isn't a trusted proxy, so
+        // the peer can't pass itself off as someone else.
+        let res = send(8500, "PROXY TCP4 198.51.100.7 127.0.0.1 56324 8500\r\n").await;
+
+        assert!(!res.starts_with("HTTP/1.1 200"), "{}", res);
+
+        let res = send(8500, "").await;
+
+        assert!(res.starts_with("HTTP/1.1 403"), "{}", res);
+    })
+    .await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_testing_runtime_serves_in_memory_service() {
+    use base::testing::{ServiceSource, TestRuntime};
+    use event_worker::events::WorkerEvents;
+
+    let mut rt = TestRuntime::builder()
+        .with_user_runtime_opts(test_user_runtime_opts())
+        .build()
+        .await
+        .unwrap();
+
+    rt.register(
+        "hello",
+        ServiceSource::code("Deno.serve(() => new Response('hello'));"),
+    )
+    .unwrap();
+
+    let res = rt
+        .request("hello", Request::get("/").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.text().unwrap(), "hello");
+
+    let boot_event = rt
+        .wait_for_event(
+            |it| matches!(it.event, WorkerEvents::Boot(_)),
+            Duration::from_secs(TESTBED_DEADLINE_SEC),
+        )
+        .await;
+
+    assert!(boot_event.is_some());
+
+    rt.shutdown(Duration::from_secs(TESTBED_DEADLINE_SEC))
+        .await
+        .unwrap();
+}
+
 #[tokio::test]
 #[serial]
 async fn test_import_map_file_path() {
@@ -182,6 +2158,7 @@ async fn test_not_trigger_pku_sigsegv_due_to_jit_compilation_non_cli() {
         None,
         None,
         None,
+        None,
     )
     .await
     .unwrap();
@@ -194,6 +2171,7 @@ async fn test_not_trigger_pku_sigsegv_due_to_jit_compilation_non_cli() {
         events_rx: None,
         timing: None,
         maybe_eszip: None,
+        maybe_eszip_signature: None,
         maybe_entrypoint: None,
         maybe_decorator: None,
         maybe_module_code: None,
@@ -341,6 +2319,7 @@ async fn test_main_worker_boot_error() {
         None,
         None,
         None,
+        None,
     )
     .await
     .unwrap();
@@ -353,6 +2332,7 @@ async fn test_main_worker_boot_error() {
         events_rx: None,
         timing: None,
         maybe_eszip: None,
+        maybe_eszip_signature: None,
         maybe_entrypoint: None,
         maybe_decorator: None,
         maybe_module_code: None,
@@ -467,6 +2447,7 @@ async fn test_main_worker_user_worker_mod_evaluate_exception() {
         None,
         None,
         None,
+        None,
     )
     .await
     .unwrap();
@@ -479,6 +2460,7 @@ async fn test_main_worker_user_worker_mod_evaluate_exception() {
         events_rx: None,
         timing: None,
         maybe_eszip: None,
+        maybe_eszip_signature: None,
         maybe_entrypoint: None,
         maybe_decorator: None,
         maybe_module_code: None,
@@ -574,6 +2556,67 @@ async fn test_main_worker_post_request_with_transfer_encoding_secure() {
     test_main_worker_post_request_with_transfer_encoding(new_localhost_tls(true)).await;
 }
 
+async fn test_main_worker_request_context(maybe_tls: Option<Tls>) {
+    let secure = maybe_tls.is_some();
+    let client = maybe_tls.client();
+    let req = client
+        .request(
+            Method::GET,
+            format!("{}://localhost:{}/", maybe_tls.schema(), maybe_tls.port(),),
+        )
+        .build()
+        .unwrap();
+
+    let request_builder = Some(RequestBuilder::from_parts(client, req));
+    let port = maybe_tls.port();
+
+    integration_test!(
+        "./test_cases/main_request_context",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        request_builder,
+        maybe_tls,
+        (|resp| async move {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 200);
+
+            let body = res.json::<serde_json::Value>().await.unwrap();
+
+            assert!(body["remoteAddr"]
+                .as_str()
+                .unwrap()
+                .starts_with("127.0.0.1:"));
+            assert!(body["localAddr"]
+                .as_str()
+                .unwrap()
+                .ends_with(&format!(":{}", port)));
+
+            if secure {
+                assert_eq!(body["tls"]["serverName"], "localhost");
+                assert!(body["tls"]["protocol"].as_str().unwrap().starts_with("TLS"));
+            } else {
+                assert!(body["tls"].is_null());
+            }
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_main_worker_request_context_non_secure() {
+    test_main_worker_request_context(new_localhost_tls(false)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_main_worker_request_context_secure() {
+    test_main_worker_request_context(new_localhost_tls(true)).await;
+}
+
 #[tokio::test]
 #[serial]
 async fn test_null_body_with_204_status() {
@@ -856,6 +2899,7 @@ async fn test_worker_boot_invalid_imports() {
         events_rx: None,
         timing: None,
         maybe_eszip: None,
+        maybe_eszip_signature: None,
         maybe_entrypoint: None,
         maybe_decorator: None,
         maybe_module_code: None,
@@ -924,7 +2968,95 @@ async fn req_failure_case_cpu_time_exhausted() {
     let mut res = tb
         .request(|| {
             Request::builder()
-                .uri("/slow_resp")
+                .uri("/slow_resp")
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    let buf = to_bytes(res.body_mut()).await.unwrap();
+
+    assert_eq!(
+        buf,
+        "{\"msg\":\"WorkerRequestCancelled: request has been cancelled by supervisor\"}"
+    );
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn req_failure_case_cpu_time_exhausted_2() {
+    let tb = TestBedBuilder::new("./test_cases/main_small_cpu_time")
+        .with_oneshot_policy(100000)
+        .build()
+        .await;
+
+    let mut res = tb
+        .request(|| {
+            Request::builder()
+                .uri("/cpu-sync")
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    let buf = to_bytes(res.body_mut()).await.unwrap();
+
+    assert_eq!(
+        buf,
+        "{\"msg\":\"WorkerRequestCancelled: request has been cancelled by supervisor\"}"
+    );
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn req_failure_case_wall_clock_reached() {
+    let tb = TestBedBuilder::new("./test_cases/main_small_wall_clock")
+        .with_oneshot_policy(100000)
+        .build()
+        .await;
+
+    let mut res = tb
+        .request(|| {
+            Request::builder()
+                .uri("/slow_resp")
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    let buf = to_bytes(res.body_mut()).await.unwrap();
+
+    assert!(
+        buf == "{\"msg\":\"InvalidWorkerResponse: user worker failed to respond\"}"
+            || buf
+                == "{\"msg\":\"WorkerRequestCancelled: request has been cancelled by supervisor\"}"
+    );
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn req_failture_case_memory_limit_1() {
+    let tb = TestBedBuilder::new("./test_cases/main")
+        .with_oneshot_policy(100000)
+        .build()
+        .await;
+
+    let mut res = tb
+        .request(|| {
+            Request::builder()
+                .uri("/array-alloc-sync")
                 .method("GET")
                 .body(Body::empty())
                 .context("can't make request")
@@ -944,8 +3076,8 @@ async fn req_failure_case_cpu_time_exhausted() {
 
 #[tokio::test]
 #[serial]
-async fn req_failure_case_cpu_time_exhausted_2() {
-    let tb = TestBedBuilder::new("./test_cases/main_small_cpu_time")
+async fn req_failture_case_memory_limit_2() {
+    let tb = TestBedBuilder::new("./test_cases/main")
         .with_oneshot_policy(100000)
         .build()
         .await;
@@ -953,7 +3085,7 @@ async fn req_failure_case_cpu_time_exhausted_2() {
     let mut res = tb
         .request(|| {
             Request::builder()
-                .uri("/cpu-sync")
+                .uri("/array-alloc")
                 .method("GET")
                 .body(Body::empty())
                 .context("can't make request")
@@ -973,8 +3105,8 @@ async fn req_failure_case_cpu_time_exhausted_2() {
 
 #[tokio::test]
 #[serial]
-async fn req_failure_case_wall_clock_reached() {
-    let tb = TestBedBuilder::new("./test_cases/main_small_wall_clock")
+async fn test_user_worker_recovers_from_transient_heap_spike() {
+    let tb = TestBedBuilder::new("./test_cases/main")
         .with_oneshot_policy(100000)
         .build()
         .await;
@@ -982,7 +3114,7 @@ async fn req_failure_case_wall_clock_reached() {
     let mut res = tb
         .request(|| {
             Request::builder()
-                .uri("/slow_resp")
+                .uri("/heap-spike")
                 .method("GET")
                 .body(Body::empty())
                 .context("can't make request")
@@ -990,21 +3122,19 @@ async fn req_failure_case_wall_clock_reached() {
         .await
         .unwrap();
 
+    assert_eq!(res.status(), StatusCode::OK);
+
     let buf = to_bytes(res.body_mut()).await.unwrap();
 
-    assert!(
-        buf == "{\"msg\":\"InvalidWorkerResponse: user worker failed to respond\"}"
-            || buf
-                == "{\"msg\":\"WorkerRequestCancelled: request has been cancelled by supervisor\"}"
-    );
+    assert_eq!(buf, "recovered 2250 20000000");
 
     tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
 }
 
 #[tokio::test]
 #[serial]
-async fn req_failture_case_memory_limit_1() {
-    let tb = TestBedBuilder::new("./test_cases/main")
+async fn test_cpu_time_billing_spares_workers_awaiting_upstreams() {
+    let tb = TestBedBuilder::new("./test_cases/main_with_billing_mode")
         .with_oneshot_policy(100000)
         .build()
         .await;
@@ -1012,49 +3142,31 @@ async fn req_failture_case_memory_limit_1() {
     let mut res = tb
         .request(|| {
             Request::builder()
-                .uri("/array-alloc-sync")
+                .uri("/sleep-5000ms")
                 .method("GET")
+                .header("x-billing-mode", "cpuTime")
                 .body(Body::empty())
                 .context("can't make request")
         })
         .await
         .unwrap();
 
-    let buf = to_bytes(res.body_mut()).await.unwrap();
-
-    assert_eq!(
-        buf,
-        "{\"msg\":\"WorkerRequestCancelled: request has been cancelled by supervisor\"}"
-    );
-
-    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
-}
-
-#[tokio::test]
-#[serial]
-async fn req_failture_case_memory_limit_2() {
-    let tb = TestBedBuilder::new("./test_cases/main")
-        .with_oneshot_policy(100000)
-        .build()
-        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(to_bytes(res.body_mut()).await.unwrap(), "meow");
 
-    let mut res = tb
+    let res = tb
         .request(|| {
             Request::builder()
-                .uri("/array-alloc")
+                .uri("/sleep-5000ms")
                 .method("GET")
+                .header("x-billing-mode", "wallClock")
                 .body(Body::empty())
                 .context("can't make request")
         })
         .await
         .unwrap();
 
-    let buf = to_bytes(res.body_mut()).await.unwrap();
-
-    assert_eq!(
-        buf,
-        "{\"msg\":\"WorkerRequestCancelled: request has been cancelled by supervisor\"}"
-    );
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
 
     tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
 }
@@ -1395,6 +3507,69 @@ async fn test_websocket_upgrade_node_secure() {
     test_websocket_upgrade(new_localhost_tls(true), true).await;
 }
 
+#[tokio::test]
+#[serial]
+async fn test_websocket_hibernation() {
+    let maybe_tls = new_localhost_tls(false);
+    let nonce = tungstenite::handshake::client::generate_key();
+    let client = maybe_tls.client();
+    let req = client
+        .request(
+            Method::GET,
+            format!(
+                "{}://localhost:{}/websocket-hibernate",
+                maybe_tls.schema(),
+                maybe_tls.port(),
+            ),
+        )
+        .header(header::CONNECTION, "upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_KEY, &nonce)
+        .header(header::SEC_WEBSOCKET_VERSION, "13")
+        .build()
+        .unwrap();
+
+    let original = RequestBuilder::from_parts(client, req);
+    let request_builder = Some(original);
+
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        request_builder,
+        maybe_tls,
+        (|resp| async {
+            let res = resp.unwrap();
+
+            assert_eq!(res.status().as_u16(), 101);
+            assert_eq!(get_upgrade_type(res.headers()).unwrap(), "websocket");
+
+            let upgraded = res.upgrade().await.unwrap();
+            let mut ws = WebSocketStream::from_raw_socket(
+                upgraded.compat(),
+                tungstenite::protocol::Role::Client,
+                None,
+            )
+            .await;
+
+            ws.send(Message::Text("first".into())).await.unwrap();
+            ws.send(Message::Text("second".into())).await.unwrap();
+
+            assert_eq!(
+                ws.next().await.unwrap().unwrap().into_text().unwrap(),
+                "meow:first"
+            );
+            assert_eq!(
+                ws.next().await.unwrap().unwrap().into_text().unwrap(),
+                "meow:second"
+            );
+        }),
+        TerminationToken::new()
+    );
+}
+
 async fn test_decorators(ty: Option<DecoratorType>) {
     let is_disabled = ty.is_none();
     let client = Client::new();
@@ -1452,94 +3627,284 @@ async fn test_decorators(ty: Option<DecoratorType>) {
     );
 }
 
-#[derive(Deserialize)]
-struct ErrorResponsePayload {
-    msg: String,
-}
+#[derive(Deserialize)]
+struct ErrorResponsePayload {
+    msg: String,
+}
+
+#[tokio::test]
+#[serial]
+async fn test_decorator_should_be_syntax_error() {
+    test_decorators(None).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_decorator_parse_tc39() {
+    test_decorators(Some(DecoratorType::Tc39)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_decorator_parse_typescript_experimental_with_metadata() {
+    test_decorators(Some(DecoratorType::TypescriptWithMetadata)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn send_partial_payload_into_closed_pipe_should_not_be_affected_worker_stability() {
+    let tb = TestBedBuilder::new("./test_cases/main")
+        .with_oneshot_policy(100000)
+        .build()
+        .await;
+
+    let mut resp1 = tb
+        .request(|| {
+            Request::builder()
+                .uri("/chunked-char-1000ms")
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(resp1.status().as_u16(), StatusCode::OK);
+
+    let resp1_body = resp1.body_mut();
+    let resp1_chunk1 = resp1_body.next().await.unwrap().unwrap();
+
+    assert_eq!(resp1_chunk1, "m");
+
+    drop(resp1);
+    sleep(Duration::from_secs(1)).await;
+
+    // NOTE(Nyannyacha): Before dc057b0, the statement below panics with the
+    // reason `connection closed before message completed`. This is the result
+    // of `Deno.serve` failing to properly handle an exception from a previous
+    // request.
+    let resp2 = tb
+        .request(|| {
+            Request::builder()
+                .uri("/empty-response")
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(resp2.status().as_u16(), StatusCode::NO_CONTENT);
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn oak_with_jsr_specifier() {
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "oak-with-jsr",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            assert_eq!(resp.unwrap().text().await.unwrap(), "meow");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_runtime_drain_stops_accepting_connections() {
+    let (health_tx, mut health_rx) = mpsc::channel(1);
+    let mut listen_fut = integration_test_listen_fut!(
+        NON_SECURE_PORT,
+        None::<Tls>,
+        "./test_cases/main_with_runtime_drain",
+        None,
+        None,
+        ServerFlags {
+            graceful_exit_deadline_sec: 3,
+            ..Default::default()
+        },
+        health_tx,
+        Some(TerminationToken::new())
+    );
+
+    let req_fut = async move {
+        loop {
+            if let Some(ServerHealth::Listening(..)) = health_rx.recv().await {
+                break;
+            }
+        }
+
+        let res = reqwest::get(format!(
+            "http://localhost:{}/readable-stream-resp",
+            NON_SECURE_PORT
+        ))
+        .await
+        .unwrap();
+
+        assert_eq!(res.status().as_u16(), 200);
+        assert_eq!(res.headers().get(header::CONNECTION).unwrap(), "close");
+        assert_eq!(res.text().await.unwrap(), "Hello world from streams");
+
+        // NOTE: The listeners are closed once the server loop picks up the
+        // drain, which may be a moment after the response above.
+        let refused = async {
+            loop {
+                match TcpStream::connect(("127.0.0.1", NON_SECURE_PORT)).await {
+                    Ok(_) => sleep(Duration::from_millis(10)).await,
+                    Err(err) => break err,
+                }
+            }
+        };
+
+        let err = timeout(Duration::from_secs(1), refused)
+            .await
+            .expect("connections were still accepted while the runtime was draining");
+
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    };
+
+    let started_at = std::time::Instant::now();
+    let (_, listen_res) = join!(
+        req_fut,
+        timeout(Duration::from_secs(TESTBED_DEADLINE_SEC), &mut listen_fut)
+    );
+
+    assert!(
+        listen_res.is_ok(),
+        "the runtime did not exit after draining"
+    );
+    assert!(started_at.elapsed() < Duration::from_secs(TESTBED_DEADLINE_SEC));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+#[serial]
+async fn test_main_worker_reload_keeps_serving_requests_in_flight() {
+    use tokio::io::AsyncBufReadExt;
+
+    let token = TerminationToken::new();
+    let (health_tx, mut health_rx) = mpsc::channel(1);
+    let admin_socket_path =
+        std::env::temp_dir().join(format!("edge-runtime-admin-{}.sock", std::process::id()));
+
+    let server = tokio::spawn(base::commands::start_server(
+        "0.0.0.0",
+        NON_SECURE_PORT,
+        None,
+        String::from("./test_cases/main_with_reload"),
+        None,
+        None,
+        None,
+        None,
+        ServerFlags {
+            graceful_exit_deadline_sec: 5,
+            ..Default::default()
+        },
+        Some(health_tx),
+        WorkerEntrypoints {
+            main: None,
+            events: None,
+        },
+        Some(token.clone()),
+        vec![],
+        None,
+        None,
+        None,
+        Default::default(),
+        vec![],
+        None,
+        vec![],
+        Some(admin_socket_path.clone()),
+        vec![],
+    ));
+
+    while !matches!(health_rx.recv().await, Some(ServerHealth::Listening(..))) {}
+
+    let client = Client::new();
+    let boot_id_of = |path: &'static str| {
+        let req = client.get(format!("http://localhost:{}{}", NON_SECURE_PORT, path));
 
-#[tokio::test]
-#[serial]
-async fn test_decorator_should_be_syntax_error() {
-    test_decorators(None).await;
-}
+        async move {
+            let res = req.send().await.unwrap();
 
-#[tokio::test]
-#[serial]
-async fn test_decorator_parse_tc39() {
-    test_decorators(Some(DecoratorType::Tc39)).await;
-}
+            assert_eq!(res.status().as_u16(), 200);
 
-#[tokio::test]
-#[serial]
-async fn test_decorator_parse_typescript_experimental_with_metadata() {
-    test_decorators(Some(DecoratorType::TypescriptWithMetadata)).await;
-}
+            res.json::<serde_json::Value>().await.unwrap()["bootId"].clone()
+        }
+    };
 
-#[tokio::test]
-#[serial]
-async fn send_partial_payload_into_closed_pipe_should_not_be_affected_worker_stability() {
-    let tb = TestBedBuilder::new("./test_cases/main")
-        .with_oneshot_policy(100000)
-        .build()
-        .await;
+    let booted_first = boot_id_of("/").await;
+    let in_flight = tokio::spawn(boot_id_of("/slow"));
 
-    let mut resp1 = tb
-        .request(|| {
-            Request::builder()
-                .uri("/chunked-char-1000ms")
-                .method("GET")
-                .body(Body::empty())
-                .context("can't make request")
-        })
+    sleep(Duration::from_millis(200)).await;
+
+    let mut admin = tokio::net::UnixStream::connect(&admin_socket_path)
         .await
         .unwrap();
 
-    assert_eq!(resp1.status().as_u16(), StatusCode::OK);
-
-    let resp1_body = resp1.body_mut();
-    let resp1_chunk1 = resp1_body.next().await.unwrap().unwrap();
+    admin.write_all(b"reload\n").await.unwrap();
 
-    assert_eq!(resp1_chunk1, "m");
-
-    drop(resp1);
-    sleep(Duration::from_secs(1)).await;
+    let mut reply = String::new();
 
-    // NOTE(Nyannyacha): Before dc057b0, the statement below panics with the
-    // reason `connection closed before message completed`. This is the result
-    // of `Deno.serve` failing to properly handle an exception from a previous
-    // request.
-    let resp2 = tb
-        .request(|| {
-            Request::builder()
-                .uri("/empty-response")
-                .method("GET")
-                .body(Body::empty())
-                .context("can't make request")
-        })
+    tokio::io::BufReader::new(&mut admin)
+        .read_line(&mut reply)
         .await
         .unwrap();
 
-    assert_eq!(resp2.status().as_u16(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&reply).unwrap()["reloaded"],
+        true
+    );
 
-    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+    // NOTE: The request the replaced main worker was serving is done by it,
+    // while new requests go to the one that was swapped in.
+    let booted_second = boot_id_of("/").await;
+
+    assert_ne!(booted_second, booted_first);
+    assert_eq!(in_flight.await.unwrap(), booted_first);
+
+    if timeout(Duration::from_secs(10), token.cancel_and_wait())
+        .await
+        .is_err()
+    {
+        panic!("failed to terminate server within 10 seconds");
+    }
+
+    server.await.unwrap().unwrap();
 }
 
 #[tokio::test]
 #[serial]
-async fn oak_with_jsr_specifier() {
-    integration_test!(
-        "./test_cases/main",
-        NON_SECURE_PORT,
-        "oak-with-jsr",
-        None,
-        None,
-        None,
-        None,
-        (|resp| async {
-            assert_eq!(resp.unwrap().text().await.unwrap(), "meow");
-        }),
-        TerminationToken::new()
-    );
+async fn test_bench_fires_the_requested_load() {
+    let report = base::bench::run(base::bench::BenchOptions {
+        service_path: PathBuf::from("./test_cases/readable-stream-resp"),
+        import_map_path: None,
+        env_vars: HashMap::new(),
+        memory_limit_mb: None,
+        requests: 20,
+        concurrency: 4,
+        method: "get".to_string(),
+        path: "/".to_string(),
+        body: None,
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(report.requests, 20);
+    assert_eq!(report.errors, 0);
+    assert_eq!(report.concurrency, 4);
+    assert!(report.boot_time_ms > 0.0);
+    assert!(report.latency.min_ms <= report.latency.p50_ms);
+    assert!(report.latency.p50_ms <= report.latency.max_ms);
+    assert!(report.cpu_time_used_ms.is_some());
 }
 
 async fn test_slowloris<F, R>(request_read_timeout_ms: u64, maybe_tls: Option<Tls>, test_fn: F)
@@ -2035,6 +4400,224 @@ async fn test_request_idle_timeout_websocket_node_secure() {
     test_request_idle_timeout_websocket_deno(new_localhost_tls(true), true).await;
 }
 
+async fn test_connection_flags<F, R>(flags: ServerFlags, test_fn: F)
+where
+    F: (FnOnce() -> R) + Send + 'static,
+    R: Future<Output = ()> + Send,
+{
+    let token = TerminationToken::new();
+
+    let (health_tx, mut health_rx) = mpsc::channel(1);
+    let (tx, rx) = oneshot::channel();
+
+    let mut listen_fut = integration_test_listen_fut!(
+        NON_SECURE_PORT,
+        None,
+        "./test_cases/main",
+        None,
+        None,
+        ServerFlags {
+            health_endpoints: true,
+            ..flags
+        },
+        health_tx,
+        Some(token.clone())
+    );
+
+    let req_fut = {
+        let token = token.clone();
+        async move {
+            test_fn().await;
+
+            if timeout(Duration::from_secs(10), token.cancel_and_wait())
+                .await
+                .is_err()
+            {
+                panic!("failed to terminate server within 10 seconds");
+            }
+
+            tx.send(()).unwrap();
+        }
+    };
+
+    let join_fut = tokio::spawn(async move {
+        while !matches!(health_rx.recv().await, Some(ServerHealth::Listening(..))) {}
+
+        req_fut.await;
+    });
+
+    tokio::select! {
+        _ = join_fut => {}
+        _ = &mut listen_fut => {}
+    };
+
+    if timeout(Duration::from_secs(10), rx).await.is_err() {
+        panic!("failed to check within 10 seconds");
+    }
+}
+
+/// Sends `count` pipelined requests over `io` and reads until the server
+/// closes the connection, returning the count of responses received.
+async fn pipeline_health_checks(io: &mut Box<dyn AsyncReadWrite>, count: usize) -> usize {
+    static REQUEST: &str = "GET /health/live HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+    // NOTE: The server may have closed the connection already.
+    let _ = io.write_all(REQUEST.repeat(count).as_bytes()).await;
+    let _ = io.flush().await;
+
+    let mut buf = vec![];
+    let _ = timeout(Duration::from_secs(5), io.read_to_end(&mut buf))
+        .await
+        .expect("connection was not closed by the server");
+
+    String::from_utf8_lossy(&buf)
+        .matches("HTTP/1.1 200 OK")
+        .count()
+}
+
+#[tokio::test]
+#[serial]
+async fn test_max_requests_per_connection() {
+    test_connection_flags(
+        ServerFlags {
+            max_requests_per_connection: Some(2),
+            ..Default::default()
+        },
+        || async {
+            let mut io = None::<Tls>.stream().await;
+
+            assert_eq!(pipeline_health_checks(&mut io, 3).await, 2);
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_keep_alive_timeout() {
+    test_connection_flags(
+        ServerFlags {
+            keep_alive_timeout_ms: Some(1000),
+            ..Default::default()
+        },
+        || async {
+            let mut io = None::<Tls>.stream().await;
+
+            assert_eq!(pipeline_health_checks(&mut io, 2).await, 2);
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_max_connections() {
+    test_connection_flags(
+        ServerFlags {
+            keep_alive_timeout_ms: Some(3000),
+            max_connections: Some(1),
+            ..Default::default()
+        },
+        || async {
+            let mut first = None::<Tls>.stream().await;
+
+            first
+                .write_all(b"GET /health/live HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .await
+                .unwrap();
+
+            let mut buf = vec![0; 1024];
+
+            assert!(first.read(&mut buf).await.unwrap() > 0);
+
+            // NOTE: The first connection is still open at this point, so this
+            // one is over the limit.
+            let mut second = None::<Tls>.stream().await;
+
+            assert_eq!(pipeline_health_checks(&mut second, 1).await, 0);
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_request_body_idle_timeout() {
+    test_connection_flags(
+        ServerFlags {
+            request_body_idle_timeout_ms: Some(1000),
+            ..Default::default()
+        },
+        || async {
+            let mut io = None::<Tls>.stream().await;
+
+            // NOTE: Only four bytes of the declared ten are ever sent.
+            io.write_all(
+                b"POST /slow_resp HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nmeow",
+            )
+            .await
+            .unwrap();
+
+            let mut buf = vec![];
+            let _ = timeout(Duration::from_secs(10), io.read_to_end(&mut buf))
+                .await
+                .expect("connection was not closed by the server");
+
+            assert!(!String::from_utf8_lossy(&buf).contains("HTTP/1.1 200 OK"));
+        },
+    )
+    .await;
+}
+
+async fn test_max_inflight_requests(overload_queue_timeout_ms: Option<u64>, expected: u16) {
+    test_connection_flags(
+        ServerFlags {
+            max_inflight_requests: Some(1),
+            overload_queue_timeout_ms,
+            ..Default::default()
+        },
+        move || async move {
+            let client = None::<Tls>.client();
+            let slow_req_fut = tokio::spawn({
+                let client = client.clone();
+                async move {
+                    client
+                        .get(format!("http://localhost:{}/sleep-5000ms", NON_SECURE_PORT))
+                        .send()
+                        .await
+                        .unwrap()
+                        .status()
+                }
+            });
+
+            // NOTE: Gives the slow request time to take the only slot.
+            sleep(Duration::from_secs(1)).await;
+
+            let res = client
+                .get(format!("http://localhost:{}/health/live", NON_SECURE_PORT))
+                .send()
+                .await
+                .unwrap();
+
+            assert_eq!(res.status().as_u16(), expected);
+            assert_eq!(slow_req_fut.await.unwrap().as_u16(), 200);
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_max_inflight_requests_rejected() {
+    test_max_inflight_requests(None, 503).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_max_inflight_requests_queued() {
+    test_max_inflight_requests(Some(8000), 200).await;
+}
+
 trait AsyncReadWrite: AsyncRead + AsyncWrite + Send + Unpin {}
 
 impl<T> AsyncReadWrite for T where T: AsyncRead + AsyncWrite + Send + Unpin {}
@@ -2127,3 +4710,99 @@ impl TlsExt for Option<Tls> {
 fn new_localhost_tls(secure: bool) -> Option<Tls> {
     secure.then(|| Tls::new(SECURE_PORT, TLS_LOCALHOST_KEY, TLS_LOCALHOST_CERT).unwrap())
 }
+
+#[tokio::test]
+#[serial]
+async fn test_cluster_forwards_request_to_peer_with_warm_service() {
+    let port_a = NON_SECURE_PORT;
+    let port_b = NON_SECURE_PORT + 1;
+    let key = SigningKey::new(b"cluster-test-key");
+    let policy = |peer_port: u16| {
+        WorkerPoolPolicy::new(SupervisorPolicy::PerWorker, 1, ServerFlags::default()).with_cluster(
+            ClusterConfig {
+                peers: vec![SocketAddr::from((Ipv4Addr::LOCALHOST, peer_port))],
+                poll_interval: Duration::from_millis(100),
+                key: key.clone(),
+            },
+        )
+    };
+
+    let token_a = TerminationToken::new();
+    let token_b = TerminationToken::new();
+    let (health_a_tx, mut health_a_rx) = mpsc::channel(1);
+    let (health_b_tx, mut health_b_rx) = mpsc::channel(1);
+
+    let listen_a = tokio::spawn(integration_test_listen_fut!(
+        port_a,
+        None::<Tls>,
+        "./test_cases/main",
+        Some(policy(port_b)),
+        None,
+        ServerFlags::default(),
+        health_a_tx,
+        Some(token_a.clone())
+    ));
+
+    let listen_b = tokio::spawn(integration_test_listen_fut!(
+        port_b,
+        None::<Tls>,
+        "./test_cases/main",
+        Some(policy(port_a)),
+        None,
+        ServerFlags::default(),
+        health_b_tx,
+        Some(token_b.clone())
+    ));
+
+    for health_rx in [&mut health_a_rx, &mut health_b_rx] {
+        loop {
+            if let Some(ServerHealth::Listening(..)) = health_rx.recv().await {
+                break;
+            }
+        }
+    }
+
+    let boot_id_of = |port: u16| async move {
+        let res = reqwest::get(format!("http://localhost:{}/cluster_boot_id", port))
+            .await
+            .unwrap();
+
+        assert_eq!(res.status().as_u16(), 200);
+
+        res.json::<serde_json::Value>().await.unwrap()
+    };
+
+    // Boots the service on B, and gives A time to poll B for it.
+    let booted_on_b = boot_id_of(port_b).await;
+
+    sleep(Duration::from_millis(500)).await;
+
+    let served_through_a = boot_id_of(port_a).await;
+
+    assert_eq!(served_through_a["bootId"], booted_on_b["bootId"]);
+    assert_eq!(served_through_a["clientIp"], "127.0.0.1");
+
+    // A poll that isn't signed with the cluster key is handled like any other
+    // request, even though it comes from the address of a peer.
+    let res = reqwest::get(format!(
+        "http://localhost:{}/_internal/cluster/services",
+        port_b
+    ))
+    .await
+    .unwrap();
+
+    let body = res.json::<serde_json::Value>().await.unwrap();
+
+    assert!(body.get("services").is_none());
+
+    for (token, listen) in [(token_a, listen_a), (token_b, listen_b)] {
+        if timeout(Duration::from_secs(10), token.cancel_and_wait())
+            .await
+            .is_err()
+        {
+            panic!("failed to terminate server within 10 seconds");
+        }
+
+        let _ = listen.await;
+    }
+}
@@ -0,0 +1,319 @@
+//! Bounded channels between the listener, the worker pool and the workers.
+//!
+//! Sending never waits for room. A full channel hands the message back in
+//! [`SendError::Full`], so that the caller can turn the request away, and the
+//! overflow is counted in the runtime metrics.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::Serialize;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelKind {
+    /// Requests sent to a worker.
+    WorkerRequest,
+    /// Streams a worker accepts requests from.
+    DuplexStream,
+    /// Requests handed to a worker through `sb_core::http_bridge`.
+    BridgedRequest,
+    /// Events of the workers, on their way to the event worker.
+    WorkerEvent,
+    /// Messages to the user worker pool, other than requests.
+    PoolMessage,
+    /// Requests sent to the user worker pool.
+    PoolRequest,
+    /// Messages a worker writes to a websocket connection the runtime holds.
+    HibernatedWebSocket,
+}
+
+impl ChannelKind {
+    fn capacity(self) -> usize {
+        match self {
+            Self::WorkerRequest => 1024,
+            Self::DuplexStream => 1024,
+            Self::BridgedRequest => 1024,
+            Self::WorkerEvent => 4096,
+            Self::PoolMessage => 1024,
+            Self::PoolRequest => 1024,
+            Self::HibernatedWebSocket => 256,
+        }
+    }
+
+    fn overflows(self) -> &'static AtomicUsize {
+        static OVERFLOWS: [AtomicUsize; 7] = [
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+            AtomicUsize::new(0),
+        ];
+
+        &OVERFLOWS[self as usize]
+    }
+}
+
+impl fmt::Display for ChannelKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::WorkerRequest => "worker request",
+            Self::DuplexStream => "duplex stream",
+            Self::BridgedRequest => "bridged request",
+            Self::WorkerEvent => "worker event",
+            Self::PoolMessage => "pool message",
+            Self::PoolRequest => "pool request",
+            Self::HibernatedWebSocket => "hibernated websocket",
+        })
+    }
+}
+
+pub enum SendError<T> {
+    /// The channel is at capacity.
+    Full(ChannelKind, T),
+    /// The receiver is gone.
+    Closed(T),
+}
+
+impl<T> SendError<T> {
+    pub fn is_full(&self) -> bool {
+        matches!(self, Self::Full(..))
+    }
+
+    pub fn into_inner(self) -> T {
+        match self {
+            Self::Full(_, value) | Self::Closed(value) => value,
+        }
+    }
+}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(kind, _) => f.debug_tuple("Full").field(kind).finish(),
+            Self::Closed(_) => f.write_str("Closed"),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(kind, _) => write!(f, "{} channel is full", kind),
+            Self::Closed(_) => f.write_str("channel closed"),
+        }
+    }
+}
+
+impl<T> std::error::Error for SendError<T> {}
+
+pub struct BoundedSender<T> {
+    tx: mpsc::Sender<T>,
+    kind: ChannelKind,
+    /// Messages sent with [`BoundedSender::send_reliable`] that are waiting
+    /// for room, in the order they were sent.
+    backlog: Arc<Mutex<VecDeque<T>>>,
+}
+
+impl<T> fmt::Debug for BoundedSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoundedSender")
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+impl<T> Clone for BoundedSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            kind: self.kind,
+            backlog: self.backlog.clone(),
+        }
+    }
+}
+
+impl<T> BoundedSender<T> {
+    /// Sends a message if there is room for it. While messages sent with
+    /// [`BoundedSender::send_reliable`] are waiting for room, the channel is
+    /// full, so that nothing sent later gets ahead of them.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let backlog = self.backlog();
+
+        if !backlog.is_empty() {
+            return Err(self.overflow(value));
+        }
+
+        self.try_send(value)
+    }
+
+    fn try_send(&self, value: T) -> Result<(), SendError<T>> {
+        self.tx.try_send(value).map_err(|err| match err {
+            TrySendError::Full(value) => self.overflow(value),
+            TrySendError::Closed(value) => SendError::Closed(value),
+        })
+    }
+
+    fn overflow(&self, value: T) -> SendError<T> {
+        self.kind.overflows().fetch_add(1, Ordering::Relaxed);
+        SendError::Full(self.kind, value)
+    }
+
+    fn backlog(&self) -> MutexGuard<'_, VecDeque<T>> {
+        self.backlog
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
+
+impl<T: Send + 'static> BoundedSender<T> {
+    /// Sends a message that must not be lost, such as the notice that a
+    /// worker has shut down. If the channel is full, the overflow is counted
+    /// and the message waits in a backlog until there is room again. Messages
+    /// in the backlog are delivered in the order they were sent, by a single
+    /// task that runs while the backlog isn't empty.
+    ///
+    /// NOTE: Only meant for messages bounded by the number of workers, so
+    /// that the backlog stays bounded as well.
+    pub fn send_reliable(&self, value: T) -> Result<(), SendError<T>> {
+        let mut backlog = self.backlog();
+
+        if !backlog.is_empty() {
+            self.kind.overflows().fetch_add(1, Ordering::Relaxed);
+            backlog.push_back(value);
+
+            return Ok(());
+        }
+
+        match self.try_send(value) {
+            Err(SendError::Full(kind, value)) => {
+                let Ok(handle) = tokio::runtime::Handle::try_current() else {
+                    return Err(SendError::Full(kind, value));
+                };
+
+                backlog.push_back(value);
+                drop(backlog);
+                drop(handle.spawn(self.clone().drain_backlog()));
+
+                Ok(())
+            }
+
+            result => result,
+        }
+    }
+
+    async fn drain_backlog(self) {
+        loop {
+            let Ok(permit) = self.tx.reserve().await else {
+                self.backlog().clear();
+                return;
+            };
+
+            // NOTE: The message is handed over before the lock is released,
+            // so that a message sent right after the backlog empties can't
+            // get ahead of it.
+            let mut backlog = self.backlog();
+            let Some(value) = backlog.pop_front() else {
+                return;
+            };
+
+            permit.send(value);
+
+            if backlog.is_empty() {
+                return;
+            }
+        }
+    }
+}
+
+pub fn channel<T>(kind: ChannelKind) -> (BoundedSender<T>, mpsc::Receiver<T>) {
+    let (tx, rx) = mpsc::channel(kind.capacity());
+
+    (
+        BoundedSender {
+            tx,
+            kind,
+            backlog: Arc::default(),
+        },
+        rx,
+    )
+}
+
+#[derive(Debug, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelStatistics {
+    worker_request_overflows_count: usize,
+    duplex_stream_overflows_count: usize,
+    bridged_request_overflows_count: usize,
+    worker_event_overflows_count: usize,
+    pool_message_overflows_count: usize,
+    pool_request_overflows_count: usize,
+    hibernated_websocket_overflows_count: usize,
+}
+
+impl ChannelStatistics {
+    pub fn get() -> Self {
+        let load = |kind: ChannelKind| kind.overflows().load(Ordering::Relaxed);
+
+        Self {
+            worker_request_overflows_count: load(ChannelKind::WorkerRequest),
+            duplex_stream_overflows_count: load(ChannelKind::DuplexStream),
+            bridged_request_overflows_count: load(ChannelKind::BridgedRequest),
+            worker_event_overflows_count: load(ChannelKind::WorkerEvent),
+            pool_message_overflows_count: load(ChannelKind::PoolMessage),
+            pool_request_overflows_count: load(ChannelKind::PoolRequest),
+            hibernated_websocket_overflows_count: load(ChannelKind::HibernatedWebSocket),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reliable_messages_keep_their_order() {
+        let (tx, mut rx) = channel::<usize>(ChannelKind::PoolMessage);
+        let capacity = ChannelKind::PoolMessage.capacity();
+
+        for i in 0..capacity {
+            tx.send(i).unwrap();
+        }
+
+        assert!(tx.send(capacity).unwrap_err().is_full());
+
+        tx.send_reliable(capacity + 1).unwrap();
+        tx.send_reliable(capacity + 2).unwrap();
+
+        // NOTE: Turned away even once there is room, since the backlog is
+        // still waiting to be delivered.
+        assert_eq!(rx.recv().await, Some(0));
+        assert!(tx.send(capacity + 3).unwrap_err().is_full());
+
+        tx.send_reliable(capacity + 4).unwrap();
+
+        let mut received = vec![];
+
+        while received.len() < capacity + 2 {
+            received.push(rx.recv().await.unwrap());
+        }
+
+        assert_eq!(
+            received,
+            (1..capacity)
+                .chain([capacity + 1, capacity + 2, capacity + 4])
+                .collect::<Vec<_>>()
+        );
+
+        tx.send(capacity + 5).unwrap();
+        assert_eq!(rx.recv().await, Some(capacity + 5));
+    }
+}
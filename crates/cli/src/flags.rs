@@ -1,5 +1,10 @@
 use std::{net::SocketAddr, path::PathBuf};
 
+use base::rt_worker::jwt::Algorithm;
+use base::rt_worker::websocket_close::{is_sendable_close_code, MAX_CLOSE_REASON_BYTES};
+
+use ipnet::IpNet;
+
 use clap::{
     arg,
     builder::{BoolishValueParser, FalseyValueParser, TypedValueParser},
@@ -38,6 +43,8 @@ pub(super) fn get_cli() -> Command {
         .subcommand(get_start_command())
         .subcommand(get_bundle_command())
         .subcommand(get_unbundle_command())
+        .subcommand(get_bench_command())
+        .subcommand(get_attach_command())
 }
 
 fn get_start_command() -> Command {
@@ -85,6 +92,25 @@ fn get_start_command() -> Command {
         )
         .arg(arg!(--"import-map" <Path>).help("Path to import map file"))
         .arg(arg!(--"event-worker" <Path>).help("Path to event worker directory"))
+        .arg(
+            arg!(--"event-worker-route" <ROUTE>)
+                .help("Run another event worker that receives only the given event types, e.g. Log,Shutdown=./path/to/worker. The rest go to --event-worker. Can be repeated")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"listener" <LISTENER>)
+                .help(concat!(
+                    "Serve another listener with a main worker, routing table and access list of its own, ",
+                    "e.g. 127.0.0.1:9001,main=./admin,routes=./admin.json,allow-cidr=10.0.0.0/8. ",
+                    "Without main, the main service serves it. proxy-protocol=true,trusted-proxy=10.0.0.0/8 ",
+                    "makes it expect the PROXY protocol header like --proxy-protocol. Can be repeated"
+                ))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"admin-socket" <Path>)
+                .help("Serve an admin console on this Unix socket, for `attach` to inspect and manage the running instance"),
+        )
         .arg(arg!(--"main-entrypoint" <Path>).help("Path to entrypoint in main service (only for eszips)"))
         .arg(arg!(--"events-entrypoint" <Path>).help("Path to entrypoint in events worker (only for eszips)"))
         .arg(
@@ -135,6 +161,80 @@ fn get_start_command() -> Command {
                     value_parser!(u32).range(1..9999).map(|it| -> usize { it as usize }),
                 ),
         )
+        .arg(
+            arg!(--"max-live-workers-per-core" <COUNT>)
+                .help("Maximum count of user workers that can be alive across all services per available core (unlimited by default)")
+                .value_parser(value_parser!(u32).range(1..9999).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"max-concurrent-boots" <COUNT>)
+                .help("Maximum count of user workers that can be booting simultaneously (unlimited by default)")
+                .value_parser(value_parser!(u32).range(1..9999).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"max-concurrent-requests" <COUNT>)
+                .help("Maximum count of requests dispatched to user workers simultaneously (unlimited by default)")
+                .value_parser(value_parser!(u32).range(1..).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"scheduling-policy" <POLICY>)
+                .help(concat!(
+                    "Order in which queued boots and requests get a slot once ",
+                    "`--max-concurrent-boots` or `--max-concurrent-requests` is reached: ",
+                    "by arrival, shared fairly across tenants, or by worker priority"
+                ))
+                .default_value("fair")
+                .value_parser(["fifo", "fair", "priority"]),
+        )
+        .arg(
+            arg!(--"usage-report-interval" <MILLISECONDS>)
+                .help("Interval in milliseconds at which the resources used by each tenant are reported to the events worker (disabled by default)")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            arg!(--"restore-services" <MODE>)
+                .help(concat!(
+                    "Persists the services registered in the user worker pool on shutdown and ",
+                    "boots them again on startup, either right away (`eager`) or on the first ",
+                    "request sent to them (`lazy`). Services created with secrets, an eszip or ",
+                    "inline module code are not persisted"
+                ))
+                .value_parser(["eager", "lazy"]),
+        )
+        .arg(
+            arg!(--"grpc-addr" <ADDR>)
+                .help("Serve the operations of the user worker pool over gRPC on this address (requires the `grpc` feature). Only loopback addresses can be served without --grpc-token-file")
+                .value_parser(value_parser!(SocketAddr)),
+        )
+        .arg(
+            arg!(--"grpc-token-file" <PATH>)
+                .help("File with the bearer token gRPC clients must send in their authorization header")
+                .env("EDGE_RUNTIME_GRPC_TOKEN_FILE")
+                .requires("grpc-addr")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"boot-queue-size" <COUNT>)
+                .help("Maximum count of user workers that can wait for a boot slot (unlimited by default)")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"boot-queue-timeout" <MILLISECONDS>)
+                .help("Maximum time in milliseconds that a user worker can wait for a boot slot")
+                .default_value("10000")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"request-queue-size" <COUNT>)
+                .help("Maximum count of requests that can wait for a dispatch slot (unlimited by default)")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"request-queue-timeout" <MILLISECONDS>)
+                .help("Maximum time in milliseconds that a request can wait for a dispatch slot")
+                .default_value("10000")
+                .value_parser(value_parser!(u64)),
+        )
         .arg(
             arg!(--"request-wait-timeout" <MILLISECONDS>)
                 .help("Maximum time in milliseconds that can wait to establish a connection with a worker")
@@ -146,11 +246,375 @@ fn get_start_command() -> Command {
                 .help("Maximum time in milliseconds that can be waited from when a worker takes over the request (disabled by default)")
                 .value_parser(value_parser!(u64)),
         )
+        .arg(
+            arg!(--"worker-conn-writev" [BOOL])
+                .help("Write requests to workers with vectored writes")
+                .num_args(0..=1)
+                .value_parser(BoolishValueParser::new())
+                .require_equals(true)
+                .default_value("true")
+                .default_missing_value("true"),
+        )
+        .arg(
+            arg!(--"worker-conn-title-case-headers")
+                .help("Write the header names of requests to workers in title case")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"worker-conn-max-buf-size" <BYTES>)
+                .help("Maximum size in bytes of the buffer the response head of a worker is read into, which bounds the size of its headers")
+                .value_parser(value_parser!(usize).range(8192..)),
+        )
+        .arg(
+            arg!(--"worker-conn-on-failure" <POLICY>)
+                .help("What is done with a request when the connection to its worker can't be set up: answer 502 right away, or set up a new one")
+                .default_value("fail-fast")
+                .value_parser(["fail-fast", "retry"]),
+        )
+        .arg(
+            arg!(--"worker-conn-retries" <COUNT>)
+                .help("How many more times the connection to a worker is set up with `--worker-conn-on-failure=retry`")
+                .default_value("2")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"worker-conn-ws-close-code" <CODE>)
+                .help("Status code of the close frame sent to the clients of a worker that goes away without closing their websocket connections")
+                .default_value("1001")
+                .value_parser(value_parser!(u16).try_map(|it| {
+                    if !is_sendable_close_code(it) {
+                        return Err(format!(
+                            "{} can't be sent in a close frame, use one of 1000-1003, 1007-1014 or 3000-4999",
+                            it
+                        ));
+                    }
+
+                    Ok(it)
+                })),
+        )
+        .arg(
+            arg!(--"worker-conn-ws-close-reason" <REASON>)
+                .help("Reason given in the close frame sent to the clients of a worker that goes away without closing their websocket connections")
+                .default_value("")
+                .value_parser(|it: &str| {
+                    if it.len() > MAX_CLOSE_REASON_BYTES {
+                        return Err(format!("must be at most {} bytes", MAX_CLOSE_REASON_BYTES));
+                    }
+
+                    Ok(it.to_string())
+                }),
+        )
         .arg(
             arg!(--"request-read-timeout" <MILLISECONDS>)
                 .help("Maximum time in milliseconds that can be waited from when the connection is accepted until the request body is fully read (disabled by default)")
                 .value_parser(value_parser!(u64)),
         )
+        .arg(
+            arg!(--"request-body-idle-timeout" <MILLISECONDS>)
+                .help("Maximum time in milliseconds that a request body may send nothing while it is being read before the connection is closed (disabled by default)")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"keep-alive-timeout" <MILLISECONDS>)
+                .help("Time in milliseconds after which a connection without any request in flight is closed (disabled by default)")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"max-requests-per-connection" <COUNT>)
+                .help("Maximum count of requests served over a single connection before it is closed (unlimited by default)")
+                .value_parser(value_parser!(u32).range(1..).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"max-connections" <COUNT>)
+                .help("Maximum count of open connections; connections accepted beyond it are answered with 503 and closed (unlimited by default)")
+                .value_parser(value_parser!(u32).range(1..).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"max-inflight-requests" <COUNT>)
+                .help("Maximum count of requests served at once across all connections; requests beyond it are answered with 503 (unlimited by default)")
+                .value_parser(value_parser!(u32).range(1..).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"overload-queue-timeout" <MILLISECONDS>)
+                .help("Queue connections and requests beyond their limits instead of answering with 503; requests wait up to this long in milliseconds for a slot (disabled by default)")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"geoip-db" <PATH>)
+                .help(concat!(
+                    "MaxMind City or Country database (MMDB) that requests are annotated from with ",
+                    "x-sb-geo-* headers of the client's country, region and city (disabled by default)"
+                ))
+                .env("EDGE_RUNTIME_GEOIP_DB")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"geoip-asn-db" <PATH>)
+                .help("MaxMind ASN database (MMDB) that requests are annotated from with the x-sb-geo-asn header")
+                .env("EDGE_RUNTIME_GEOIP_ASN_DB")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"access-log" <PATH>)
+                .help(concat!(
+                    "Write a line per request served to this file, or to stdout if it is -, ",
+                    "with the worker that served it, the status, duration and bytes sent (disabled by default)"
+                ))
+                .env("EDGE_RUNTIME_ACCESS_LOG"),
+        )
+        .arg(
+            arg!(--"access-log-format" <FORMAT>)
+                .help("Format of the lines written to `--access-log`: the combined log format, or a JSON object per line")
+                .default_value("combined")
+                .value_parser(["combined", "json"]),
+        )
+        .arg(
+            arg!(--"access-log-max-bytes" <BYTES>)
+                .help("Size past which the `--access-log` file is rotated (never rotated by default)")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            arg!(--"access-log-max-files" <COUNT>)
+                .help("Count of rotated `--access-log` files kept next to it, as <PATH>.1 to <PATH>.<COUNT>")
+                .default_value("5")
+                .value_parser(value_parser!(u32).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"db-url" <URL>)
+                .help("Postgres connection string of the database pool shared by user workers (disabled by default)")
+                .env("EDGE_RUNTIME_DB_URL"),
+        )
+        .arg(
+            arg!(--"db-max-connections" <COUNT>)
+                .help("Maximum count of connections opened by the database pool")
+                .default_value("10")
+                .value_parser(value_parser!(u32).range(1..).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"db-max-connections-per-worker" <COUNT>)
+                .help("Maximum count of database connections a single user worker can hold at once")
+                .default_value("2")
+                .value_parser(value_parser!(u32).range(1..).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"db-acquire-timeout" <MILLISECONDS>)
+                .help("Maximum time in milliseconds that a query can wait for a database connection")
+                .default_value("5000")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"redis-url" <URL>)
+                .help("Redis connection string of the connections shared by workers (disabled by default)")
+                .env("EDGE_RUNTIME_REDIS_URL"),
+        )
+        .arg(
+            arg!(--"redis-connections" <COUNT>)
+                .help("Count of multiplexed Redis connections that commands are spread across")
+                .default_value("4")
+                .value_parser(value_parser!(u32).range(1..).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"redis-max-subscriptions" <COUNT>)
+                .help("Maximum count of Redis subscriptions open at once, each taking a connection of its own")
+                .default_value("100")
+                .value_parser(value_parser!(u32).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"redis-commands-per-second-per-worker" <COUNT>)
+                .help("Maximum count of Redis commands a single user worker can send per second")
+                .default_value("100")
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            arg!(--"redis-connect-timeout" <MILLISECONDS>)
+                .help("Maximum time in milliseconds that a command can wait for a Redis connection to be established")
+                .default_value("5000")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"redis-command-timeout" <MILLISECONDS>)
+                .help("Maximum time in milliseconds that a command can wait for Redis to reply")
+                .default_value("5000")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"publish-url" <URL>)
+                .help(concat!(
+                    "Broker that messages of Supabase.publish are delivered to: nats://HOST:PORT, ",
+                    "kafka://HOST:PORT[,HOST:PORT...] or an http(s) webhook url (disabled by default)"
+                ))
+                .env("EDGE_RUNTIME_PUBLISH_URL"),
+        )
+        .arg(
+            arg!(--"publish-batch-size" <COUNT>)
+                .help("Maximum count of messages delivered to the broker in a single batch")
+                .default_value("100")
+                .value_parser(value_parser!(u32).range(1..).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"publish-batch-interval" <MILLISECONDS>)
+                .help("Maximum time in milliseconds that a message waits for others to join its batch")
+                .default_value("50")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"publish-max-retries" <COUNT>)
+                .help("Maximum count of times a failed batch is retried before its messages are dropped")
+                .default_value("5")
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            arg!(--"publish-queue-size" <COUNT>)
+                .help("Maximum count of messages waiting for delivery before workers have to wait to publish")
+                .default_value("10000")
+                .value_parser(value_parser!(u32).range(1..).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"publish-queue-size-per-worker" <COUNT>)
+                .help("Maximum count of messages a single user worker can have waiting for delivery")
+                .default_value("1000")
+                .value_parser(value_parser!(u32).range(1..).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"publish-enqueue-timeout" <MILLISECONDS>)
+                .help("Maximum time in milliseconds that a worker waits for room in a full publish queue")
+                .default_value("5000")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"publish-send-timeout" <MILLISECONDS>)
+                .help("Maximum time in milliseconds that a single attempt at delivering a batch can take")
+                .default_value("10000")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"crypto-key" <NAME_AND_PATH>)
+                .help(concat!(
+                    "Host key that workers can sign with through Supabase.crypto.sign, given as NAME=PATH to a ",
+                    "PEM encoded PKCS#8 RSA or P-256 private key. User workers need to be granted the key by name. ",
+                    "Can be repeated"
+                ))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"jwt-jwks-url" <URL>)
+                .help("URL of the JSON Web Key Set used to verify JWTs of requests before they reach user workers (disabled by default)")
+                .env("EDGE_RUNTIME_JWT_JWKS_URL"),
+        )
+        .arg(
+            arg!(--"jwt-algorithm" <ALGORITHM>)
+                .help("Algorithm that JWTs may be signed with, such as RS256 or ES256. Can be repeated")
+                .default_value("RS256")
+                .action(ArgAction::Append)
+                .value_parser(|it: &str| it.parse::<Algorithm>().map_err(|err| err.to_string())),
+        )
+        .arg(
+            arg!(--"jwt-issuer" <ISSUER>)
+                .help("Expected issuer of JWTs")
+                .requires("jwt-jwks-url"),
+        )
+        .arg(
+            arg!(--"jwt-audience" <AUDIENCE>)
+                .help("Expected audience of JWTs, unless a user worker specifies its own")
+                .requires("jwt-jwks-url"),
+        )
+        .arg(
+            arg!(--"jwt-jwks-ttl" <SECONDS>)
+                .help("Maximum time in seconds that a fetched JSON Web Key Set is cached")
+                .default_value("600")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"cluster-peer" <ADDR>)
+                .help(concat!(
+                    "Address of the listener of another runtime to share warm user workers with. ",
+                    "Requests for services not booted here are forwarded to a peer that has them ",
+                    "booted. Can be repeated"
+                ))
+                .value_parser(value_parser!(SocketAddr))
+                .action(ArgAction::Append)
+                .requires("cluster-key-file"),
+        )
+        .arg(
+            arg!(--"cluster-key-file" <PATH>)
+                .help("Path to a file holding the key the cluster peers sign the requests they send each other with")
+                .env("EDGE_RUNTIME_CLUSTER_KEY_FILE")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"cluster-poll-interval" <MILLISECONDS>)
+                .help("Interval in milliseconds between the polls of the services booted on the cluster peers")
+                .default_value("1000")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"diagnostics-dir" <DIR>)
+                .help(concat!(
+                    "Directory to write a diagnostic bundle to whenever a user worker is terminated ",
+                    "for hitting its wall clock, CPU time or memory limit"
+                ))
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"diagnostics-log-lines" <LINES>)
+                .help("How many of the last log lines of a user worker its diagnostic bundle holds")
+                .default_value("100")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"routes" <Path>)
+                .help("Path to a JSON routing table that sends matching requests straight to user workers, bypassing the main worker"),
+        )
+        .arg(
+            arg!(--"services-dir" <DIR>)
+                .help("Serve each subdirectory of this directory under its own path, e.g. /foo from <DIR>/foo. Requests for unknown services go to the main service"),
+        )
+        .arg(
+            arg!(--"allow-cidr" <CIDR>)
+                .help("Only accept connections from this network. Can be repeated")
+                .value_parser(value_parser!(IpNet))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"deny-cidr" <CIDR>)
+                .help("Refuse connections from this network. Takes precedence over --allow-cidr. Can be repeated")
+                .value_parser(value_parser!(IpNet))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"storage-cache-dir" <DIR>)
+                .help("Folder the eszips of services fetched from s3:// or gs:// URLs are cached in")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"s3-endpoint" <URL>)
+                .help("Endpoint of an S3 compatible storage to fetch s3:// services from (AWS_ENDPOINT_URL by default)"),
+        )
+        .arg(
+            arg!(--"s3-region" <REGION>)
+                .help("Region of the buckets of s3:// services (AWS_REGION by default)"),
+        )
+        .arg(
+            arg!(--"gcs-token-file" <PATH>)
+                .help("File with the OAuth 2.0 access token to fetch gs:// services with (GOOGLE_OAUTH_ACCESS_TOKEN by default)")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"trusted-bundle-key" <PATH>)
+                .help(concat!(
+                    "File with a base64 ed25519 public key that eszip bundles must be signed with. ",
+                    "Workers booted from an eszip without a detached signature of one of these keys, ",
+                    "or from their sources, fail to boot. Can be repeated"
+                ))
+                .value_parser(value_parser!(PathBuf))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"allow-unsigned-sources")
+                .help("Lets workers boot from their sources even when bundle keys are trusted")
+                .requires("trusted-bundle-key")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             arg!(--"inspect" [HOST_AND_PORT])
                 .help("Activate inspector on host:port")
@@ -188,6 +652,50 @@ fn get_start_command() -> Command {
             arg!(--"jsx-module" <Path> "A valid JSX module")
                 .value_parser(["jsx-runtime", "jsx-dev-runtime", "precompile", "react"]),
         )
+        .arg(
+            arg!(--"startup-self-test")
+                .help("Boot a canary user worker and send it a request before accepting traffic, failing fast if that does not work")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"trust-forwarded-headers")
+                .help("Keep the Forwarded, X-Forwarded-*, X-Real-IP and X-Request-Id headers sent by clients instead of dropping them; only use it behind a trusted proxy that sets them")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"proxy-protocol")
+                .help("Read the address of the client from the PROXY protocol header (v1 or v2) each connection to the main listener from a --trusted-proxy starts with")
+                .requires("trusted-proxy")
+                .conflicts_with("tls")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"trusted-proxy" <CIDR>)
+                .help("Load balancers in this network send the PROXY protocol header; connections from other peers are served with their own address. Can be repeated")
+                .requires("proxy-protocol")
+                .value_parser(value_parser!(IpNet))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"direct-request-bridge")
+                .help("Hand requests to user workers through ops instead of re-serializing them as HTTP")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"health-endpoints")
+                .help("Serve `/health/live` and `/health/ready` from the listener instead of the main worker")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"watch")
+                .help("Reload user workers when the files of their service change (for development only)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"server-timing")
+                .help("Attach a `Server-Timing` header to the responses of user workers")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             arg!(--"tcp-nodelay" [BOOL])
                 .help("Disables Nagle's algorithm")
@@ -234,3 +742,62 @@ fn get_unbundle_command() -> Command {
                 .required(true),
         )
 }
+
+fn get_bench_command() -> Command {
+    Command::new("bench")
+        .about("Boots a service in-process and measures how it performs under a synthetic load")
+        .arg(
+            arg!(--"service" <DIR>)
+                .help("Path to the service directory to benchmark")
+                .required(true),
+        )
+        .arg(arg!(--"import-map" <Path>).help("Path to import map file"))
+        .arg(
+            arg!(--"requests" <NUMBER>)
+                .help("Total number of requests to send")
+                .default_value("1000")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"concurrency" <NUMBER>)
+                .help("Maximum number of requests in flight at once")
+                .default_value("10")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"memory-limit" <MiB>)
+                .help("Memory limit of the user worker")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"method" <METHOD>)
+                .help("Request method")
+                .default_value("GET"),
+        )
+        .arg(
+            arg!(--"path" <PATH>)
+                .help("Request path, including the query")
+                .default_value("/"),
+        )
+        .arg(arg!(--"body" <BODY>).help("Request body"))
+        .arg(
+            arg!(--"json")
+                .help("Print the report as JSON")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+fn get_attach_command() -> Command {
+    Command::new("attach")
+        .about("Connects to the admin console of a running instance. Runs the given command, or reads commands from stdin (try `help`)")
+        .arg(
+            arg!(--"admin-socket" <Path>)
+                .help("Path to the admin socket of the instance")
+                .required(true),
+        )
+        .arg(
+            arg!([COMMAND] ...)
+                .id("command")
+                .help("Command to run, e.g. `workers` or `terminate <KEY>`"),
+        )
+}
@@ -4,12 +4,26 @@ mod flags;
 mod logger;
 
 use anyhow::{anyhow, bail, Error};
+use base::access_log::{self, AccessLogConfig, AccessLogFormat};
+use base::bench::{self, BenchOptions};
+use base::bundle_signature;
 use base::commands::start_server;
 use base::deno_runtime::MAYBE_DENO_VERSION;
+use base::event_router::EventWorkerRoute;
+use base::listener::ListenerConfig;
+use base::router::RoutingTable;
+use base::rt_worker::cluster::{ClusterConfig, SigningKey};
+use base::rt_worker::jwt::{Algorithm, JwtConfig, JwtVerifier};
+use base::rt_worker::pool_state::RestoreMode;
+use base::rt_worker::scheduler::SchedulingPolicy;
+use base::rt_worker::websocket_close::WebSocketClosePolicy;
+use base::rt_worker::worker_ctx::{WorkerConnectionConfig, WorkerConnectionFailurePolicy};
 use base::rt_worker::worker_pool::{SupervisorPolicy, WorkerPoolPolicy};
-use base::server::{ServerFlags, Tls, WorkerEntrypoints};
+use base::server::{ServerFlags, Tls, TokenDigest, WorkerEntrypoints};
+use base::service_storage::{self, StorageConfig};
 use base::{DecoratorType, InspectorOption};
 use clap::ArgMatches;
+use deno_core::serde_json;
 use deno_core::url::Url;
 use flags::get_cli;
 use log::warn;
@@ -18,11 +32,13 @@ use sb_graph::import_map::load_import_map;
 use sb_graph::{
     extract_from_file, generate_binary_eszip, include_glob_patterns_in_eszip, STATIC_FS_PREFIX,
 };
+use sb_workers::context::{DiagnosticsOptions, IpAccessList};
 use std::fs::File;
 use std::io::Write;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 fn main() -> Result<(), anyhow::Error> {
     MAYBE_DENO_VERSION.get_or_init(|| env!("DENO_VERSION").to_string());
@@ -135,12 +151,90 @@ fn main() -> Result<(), anyhow::Error> {
 
                 let maybe_max_parallelism =
                     sub_matches.get_one::<usize>("max-parallelism").cloned();
+                let maybe_max_live_workers_per_core = sub_matches
+                    .get_one::<usize>("max-live-workers-per-core")
+                    .cloned();
+                let maybe_max_concurrent_boots = sub_matches
+                    .get_one::<usize>("max-concurrent-boots")
+                    .cloned();
+                let maybe_max_concurrent_requests = sub_matches
+                    .get_one::<usize>("max-concurrent-requests")
+                    .cloned();
+                let maybe_scheduling_policy = sub_matches
+                    .get_one::<String>("scheduling-policy")
+                    .map(|it| it.parse::<SchedulingPolicy>().unwrap());
+                let maybe_restore_services = sub_matches
+                    .get_one::<String>("restore-services")
+                    .map(|it| it.parse::<RestoreMode>().unwrap());
+                let maybe_usage_report_interval =
+                    sub_matches.get_one::<u64>("usage-report-interval").cloned();
+                let maybe_boot_queue_size =
+                    sub_matches.get_one::<usize>("boot-queue-size").cloned();
+                let maybe_boot_queue_timeout =
+                    sub_matches.get_one::<u64>("boot-queue-timeout").cloned();
+                let maybe_request_queue_size =
+                    sub_matches.get_one::<usize>("request-queue-size").cloned();
+                let maybe_request_queue_timeout =
+                    sub_matches.get_one::<u64>("request-queue-timeout").cloned();
                 let maybe_request_wait_timeout =
                     sub_matches.get_one::<u64>("request-wait-timeout").cloned();
                 let maybe_request_idle_timeout =
                     sub_matches.get_one::<u64>("request-idle-timeout").cloned();
                 let maybe_request_read_timeout =
                     sub_matches.get_one::<u64>("request-read-timeout").cloned();
+                let maybe_request_body_idle_timeout = sub_matches
+                    .get_one::<u64>("request-body-idle-timeout")
+                    .cloned();
+                let maybe_keep_alive_timeout =
+                    sub_matches.get_one::<u64>("keep-alive-timeout").cloned();
+                let maybe_max_requests_per_connection = sub_matches
+                    .get_one::<usize>("max-requests-per-connection")
+                    .cloned();
+                let maybe_max_connections =
+                    sub_matches.get_one::<usize>("max-connections").cloned();
+                let maybe_max_inflight_requests = sub_matches
+                    .get_one::<usize>("max-inflight-requests")
+                    .cloned();
+                let maybe_overload_queue_timeout = sub_matches
+                    .get_one::<u64>("overload-queue-timeout")
+                    .cloned();
+                let worker_connection = WorkerConnectionConfig {
+                    writev: sub_matches
+                        .get_one::<bool>("worker-conn-writev")
+                        .cloned()
+                        .unwrap(),
+                    title_case_headers: sub_matches
+                        .get_one::<bool>("worker-conn-title-case-headers")
+                        .cloned()
+                        .unwrap(),
+                    max_buf_size: sub_matches
+                        .get_one::<usize>("worker-conn-max-buf-size")
+                        .cloned(),
+                    on_failure: match sub_matches
+                        .get_one::<String>("worker-conn-on-failure")
+                        .map(String::as_str)
+                    {
+                        Some("retry") => WorkerConnectionFailurePolicy::Retry(
+                            sub_matches
+                                .get_one::<usize>("worker-conn-retries")
+                                .cloned()
+                                .unwrap(),
+                        ),
+                        _ => WorkerConnectionFailurePolicy::FailFast,
+                    },
+                    websocket_close: WebSocketClosePolicy {
+                        code: sub_matches
+                            .get_one::<u16>("worker-conn-ws-close-code")
+                            .cloned()
+                            .unwrap(),
+                        // NOTE: Leaked once, so that the config stays `Copy`.
+                        reason: sub_matches
+                            .get_one::<String>("worker-conn-ws-close-reason")
+                            .cloned()
+                            .unwrap()
+                            .leak(),
+                    },
+                };
                 let static_patterns =
                     if let Some(val_ref) = sub_matches.get_many::<String>("static") {
                         val_ref.map(|s| s.as_str()).collect::<Vec<&str>>()
@@ -167,18 +261,346 @@ fn main() -> Result<(), anyhow::Error> {
                     None
                 };
 
+                let grpc_token = sub_matches
+                    .get_one::<PathBuf>("grpc-token-file")
+                    .map(|path| {
+                        let token = std::fs::read_to_string(path)?.trim().to_string();
+
+                        if token.is_empty() {
+                            bail!("gRPC token file is empty: {}", path.display());
+                        }
+
+                        Ok(TokenDigest::of(&token))
+                    })
+                    .transpose()?;
+
                 let tcp_nodelay = sub_matches.get_one::<bool>("tcp-nodelay").copied().unwrap();
+                let server_timing = sub_matches
+                    .get_one::<bool>("server-timing")
+                    .cloned()
+                    .unwrap();
+                let watch = sub_matches.get_one::<bool>("watch").cloned().unwrap();
+                let startup_self_test = sub_matches
+                    .get_one::<bool>("startup-self-test")
+                    .cloned()
+                    .unwrap();
+                let trust_forwarded_headers = sub_matches
+                    .get_one::<bool>("trust-forwarded-headers")
+                    .cloned()
+                    .unwrap();
+                let direct_request_bridge = sub_matches
+                    .get_one::<bool>("direct-request-bridge")
+                    .cloned()
+                    .unwrap();
+                let health_endpoints = sub_matches
+                    .get_one::<bool>("health-endpoints")
+                    .cloned()
+                    .unwrap();
+
+                if let Some(db_url) = sub_matches.get_one::<String>("db-url").cloned() {
+                    sb_db::init(sb_db::DbPoolConfig {
+                        url: db_url,
+                        max_connections: sub_matches
+                            .get_one::<usize>("db-max-connections")
+                            .cloned()
+                            .unwrap(),
+                        max_connections_per_worker: sub_matches
+                            .get_one::<usize>("db-max-connections-per-worker")
+                            .cloned()
+                            .unwrap(),
+                        acquire_timeout: Duration::from_millis(
+                            sub_matches
+                                .get_one::<u64>("db-acquire-timeout")
+                                .cloned()
+                                .unwrap(),
+                        ),
+                    })?;
+                }
+
+                if let Some(redis_url) = sub_matches.get_one::<String>("redis-url").cloned() {
+                    sb_redis::init(sb_redis::RedisPoolConfig {
+                        url: redis_url,
+                        connections: sub_matches
+                            .get_one::<usize>("redis-connections")
+                            .cloned()
+                            .unwrap(),
+                        max_subscriptions: sub_matches
+                            .get_one::<usize>("redis-max-subscriptions")
+                            .cloned()
+                            .unwrap(),
+                        commands_per_second_per_worker: sub_matches
+                            .get_one::<u32>("redis-commands-per-second-per-worker")
+                            .cloned()
+                            .unwrap(),
+                        connect_timeout: Duration::from_millis(
+                            sub_matches
+                                .get_one::<u64>("redis-connect-timeout")
+                                .cloned()
+                                .unwrap(),
+                        ),
+                        command_timeout: Duration::from_millis(
+                            sub_matches
+                                .get_one::<u64>("redis-command-timeout")
+                                .cloned()
+                                .unwrap(),
+                        ),
+                    })?;
+                }
+
+                if let Some(publish_url) = sub_matches.get_one::<String>("publish-url") {
+                    sb_publish::init(sb_publish::PublisherConfig {
+                        backend: sb_publish::BackendConfig::parse(publish_url)?,
+                        batch_size: sub_matches
+                            .get_one::<usize>("publish-batch-size")
+                            .cloned()
+                            .unwrap(),
+                        batch_interval: Duration::from_millis(
+                            sub_matches
+                                .get_one::<u64>("publish-batch-interval")
+                                .cloned()
+                                .unwrap(),
+                        ),
+                        max_retries: sub_matches
+                            .get_one::<u32>("publish-max-retries")
+                            .cloned()
+                            .unwrap(),
+                        queue_size: sub_matches
+                            .get_one::<usize>("publish-queue-size")
+                            .cloned()
+                            .unwrap(),
+                        queue_size_per_worker: sub_matches
+                            .get_one::<usize>("publish-queue-size-per-worker")
+                            .cloned()
+                            .unwrap(),
+                        enqueue_timeout: Duration::from_millis(
+                            sub_matches
+                                .get_one::<u64>("publish-enqueue-timeout")
+                                .cloned()
+                                .unwrap(),
+                        ),
+                        send_timeout: Duration::from_millis(
+                            sub_matches
+                                .get_one::<u64>("publish-send-timeout")
+                                .cloned()
+                                .unwrap(),
+                        ),
+                    })?;
+                }
+
+                let geoip_config = sb_geoip::GeoIpConfig {
+                    location_db: sub_matches.get_one::<PathBuf>("geoip-db").cloned(),
+                    asn_db: sub_matches.get_one::<PathBuf>("geoip-asn-db").cloned(),
+                };
+
+                if geoip_config.location_db.is_some() || geoip_config.asn_db.is_some() {
+                    sb_geoip::init(geoip_config)?;
+                }
+
+                if let Some(path) = sub_matches.get_one::<String>("access-log").cloned() {
+                    access_log::init(AccessLogConfig {
+                        format: sub_matches
+                            .get_one::<String>("access-log-format")
+                            .unwrap()
+                            .parse::<AccessLogFormat>()
+                            .unwrap(),
+                        path: Some(path).filter(|it| it != "-").map(PathBuf::from),
+                        max_bytes: sub_matches.get_one::<u64>("access-log-max-bytes").cloned(),
+                        max_files: sub_matches
+                            .get_one::<usize>("access-log-max-files")
+                            .cloned()
+                            .unwrap(),
+                    })?;
+                }
+
+                if let Some(keys) = sub_matches.get_many::<String>("crypto-key") {
+                    sb_crypto::init(
+                        keys.map(|it| sb_crypto::parse_key_arg(it))
+                            .collect::<Result<_, _>>()?,
+                    )?;
+                }
+
                 let flags = ServerFlags {
                     no_module_cache,
                     allow_main_inspector,
                     tcp_nodelay,
+                    server_timing,
+                    watch,
+                    health_endpoints,
+                    startup_self_test,
+                    trust_forwarded_headers,
+                    direct_request_bridge,
                     graceful_exit_deadline_sec,
                     graceful_exit_keepalive_deadline_ms,
                     request_wait_timeout_ms: maybe_request_wait_timeout,
                     request_idle_timeout_ms: maybe_request_idle_timeout,
                     request_read_timeout_ms: maybe_request_read_timeout,
+                    request_body_idle_timeout_ms: maybe_request_body_idle_timeout,
+                    keep_alive_timeout_ms: maybe_keep_alive_timeout,
+                    max_requests_per_connection: maybe_max_requests_per_connection,
+                    max_connections: maybe_max_connections,
+                    max_inflight_requests: maybe_max_inflight_requests,
+                    overload_queue_timeout_ms: maybe_overload_queue_timeout,
+                    max_live_workers_per_core: maybe_max_live_workers_per_core,
+                    max_concurrent_boots: maybe_max_concurrent_boots,
+                    boot_queue_size: maybe_boot_queue_size,
+                    boot_queue_timeout_ms: maybe_boot_queue_timeout,
+                    max_concurrent_requests: maybe_max_concurrent_requests,
+                    request_queue_size: maybe_request_queue_size,
+                    request_queue_timeout_ms: maybe_request_queue_timeout,
+                    scheduling_policy: maybe_scheduling_policy,
+                    usage_report_interval_ms: maybe_usage_report_interval,
+                    restore_services: maybe_restore_services,
+                    grpc_addr: sub_matches.get_one::<SocketAddr>("grpc-addr").cloned(),
+                    grpc_token,
+                    worker_connection,
+                };
+
+                let mut user_worker_policy = WorkerPoolPolicy::new(
+                    maybe_supervisor_policy,
+                    if let Some(true) = maybe_supervisor_policy
+                        .as_ref()
+                        .map(SupervisorPolicy::is_oneshot)
+                    {
+                        if let Some(parallelism) = maybe_max_parallelism {
+                            if parallelism == 0 || parallelism > 1 {
+                                warn!(
+                                    "{}",
+                                    concat!(
+                                        "if `oneshot` policy is enabled, the maximum ",
+                                        "parallelism is fixed to `1` as forcibly"
+                                    )
+                                );
+                            }
+                        }
+
+                        Some(1)
+                    } else {
+                        maybe_max_parallelism
+                    },
+                    flags,
+                );
+
+                if let Some(jwks_url) = sub_matches.get_one::<String>("jwt-jwks-url").cloned() {
+                    user_worker_policy =
+                        user_worker_policy.with_jwt_verifier(JwtVerifier::new(JwtConfig {
+                            jwks_url,
+                            algorithms: sub_matches
+                                .get_many::<Algorithm>("jwt-algorithm")
+                                .unwrap()
+                                .cloned()
+                                .collect(),
+                            issuer: sub_matches.get_one::<String>("jwt-issuer").cloned(),
+                            audience: sub_matches.get_one::<String>("jwt-audience").cloned(),
+                            jwks_ttl: Duration::from_secs(
+                                sub_matches.get_one::<u64>("jwt-jwks-ttl").cloned().unwrap(),
+                            ),
+                        }));
+                }
+
+                if let Some(peers) = sub_matches.get_many::<SocketAddr>("cluster-peer") {
+                    let key_path = sub_matches.get_one::<PathBuf>("cluster-key-file").unwrap();
+                    let key = std::fs::read_to_string(key_path)?.trim().to_string();
+
+                    if key.is_empty() {
+                        bail!("cluster key file is empty: {}", key_path.display());
+                    }
+
+                    user_worker_policy = user_worker_policy.with_cluster(ClusterConfig {
+                        peers: peers.cloned().collect(),
+                        poll_interval: Duration::from_millis(
+                            sub_matches
+                                .get_one::<u64>("cluster-poll-interval")
+                                .cloned()
+                                .unwrap(),
+                        ),
+                        key: SigningKey::new(key.as_bytes()),
+                    });
+                }
+
+                if let Some(dir) = sub_matches.get_one::<PathBuf>("diagnostics-dir").cloned() {
+                    user_worker_policy = user_worker_policy.with_diagnostics(DiagnosticsOptions {
+                        dir,
+                        log_lines: sub_matches
+                            .get_one::<usize>("diagnostics-log-lines")
+                            .cloned()
+                            .unwrap(),
+                    });
+                }
+
+                let ip_access_list = IpAccessList {
+                    allow: sub_matches
+                        .get_many::<ipnet::IpNet>("allow-cidr")
+                        .map(|it| it.cloned().collect())
+                        .unwrap_or_default(),
+                    deny: sub_matches
+                        .get_many::<ipnet::IpNet>("deny-cidr")
+                        .map(|it| it.cloned().collect())
+                        .unwrap_or_default(),
                 };
 
+                // NOTE: `--proxy-protocol` and `--trusted-proxy` require each
+                // other, so the header is never read from just anyone.
+                let trusted_proxies = sub_matches
+                    .get_many::<ipnet::IpNet>("trusted-proxy")
+                    .map(|it| it.cloned().collect())
+                    .unwrap_or_default();
+
+                let mut maybe_routing_table = sub_matches
+                    .get_one::<String>("routes")
+                    .map(RoutingTable::from_file)
+                    .transpose()?;
+
+                if let Some(services_dir) = sub_matches.get_one::<String>("services-dir") {
+                    maybe_routing_table
+                        .get_or_insert_with(RoutingTable::default)
+                        .services_dir = Some(PathBuf::from(services_dir));
+                }
+
+                let mut storage_config = StorageConfig::from_env();
+
+                storage_config.cache_dir =
+                    sub_matches.get_one::<PathBuf>("storage-cache-dir").cloned();
+
+                if let Some(endpoint) = sub_matches.get_one::<String>("s3-endpoint").cloned() {
+                    storage_config.s3.endpoint = Some(endpoint);
+                }
+
+                if let Some(region) = sub_matches.get_one::<String>("s3-region").cloned() {
+                    storage_config.s3.region = region;
+                }
+
+                if let Some(path) = sub_matches.get_one::<PathBuf>("gcs-token-file") {
+                    storage_config.gcs.access_token =
+                        Some(std::fs::read_to_string(path)?.trim().to_string());
+                }
+
+                service_storage::configure(storage_config)?;
+
+                if let Some(paths) = sub_matches.get_many::<PathBuf>("trusted-bundle-key") {
+                    bundle_signature::trust_keys(
+                        paths,
+                        sub_matches.get_flag("allow-unsigned-sources"),
+                    )?;
+                }
+
+                let event_worker_routes = sub_matches
+                    .get_many::<String>("event-worker-route")
+                    .map(|it| {
+                        it.map(|route| route.parse::<EventWorkerRoute>())
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                    .transpose()?
+                    .unwrap_or_default();
+
+                let listeners = sub_matches
+                    .get_many::<String>("listener")
+                    .map(|it| {
+                        it.map(|listener| listener.parse::<ListenerConfig>())
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                    .transpose()?
+                    .unwrap_or_default();
+
                 start_server(
                     ip.as_str(),
                     port,
@@ -186,30 +608,7 @@ fn main() -> Result<(), anyhow::Error> {
                     main_service_path,
                     event_service_manager_path,
                     get_decorator_option(sub_matches),
-                    Some(WorkerPoolPolicy::new(
-                        maybe_supervisor_policy,
-                        if let Some(true) = maybe_supervisor_policy
-                            .as_ref()
-                            .map(SupervisorPolicy::is_oneshot)
-                        {
-                            if let Some(parallelism) = maybe_max_parallelism {
-                                if parallelism == 0 || parallelism > 1 {
-                                    warn!(
-                                        "{}",
-                                        concat!(
-                                            "if `oneshot` policy is enabled, the maximum ",
-                                            "parallelism is fixed to `1` as forcibly"
-                                        )
-                                    );
-                                }
-                            }
-
-                            Some(1)
-                        } else {
-                            maybe_max_parallelism
-                        },
-                        flags,
-                    )),
+                    Some(user_worker_policy),
                     import_map_path,
                     flags,
                     None,
@@ -222,6 +621,14 @@ fn main() -> Result<(), anyhow::Error> {
                     maybe_inspector_option,
                     jsx_specifier,
                     jsx_module,
+                    ip_access_list,
+                    trusted_proxies,
+                    maybe_routing_table,
+                    event_worker_routes,
+                    sub_matches
+                        .get_one::<String>("admin-socket")
+                        .map(PathBuf::from),
+                    listeners,
                 )
                 .await?;
             }
@@ -304,6 +711,66 @@ fn main() -> Result<(), anyhow::Error> {
                     output_path.to_str().unwrap()
                 );
             }
+            Some(("bench", sub_matches)) => {
+                let service_path = sub_matches.get_one::<String>("service").cloned().unwrap();
+                let import_map_path = sub_matches.get_one::<String>("import-map").cloned();
+                let requests = sub_matches.get_one::<usize>("requests").copied().unwrap();
+                let concurrency = sub_matches
+                    .get_one::<usize>("concurrency")
+                    .copied()
+                    .unwrap();
+                let memory_limit_mb = sub_matches.get_one::<u64>("memory-limit").copied();
+                let method = sub_matches.get_one::<String>("method").cloned().unwrap();
+                let path = sub_matches.get_one::<String>("path").cloned().unwrap();
+                let body = sub_matches
+                    .get_one::<String>("body")
+                    .cloned()
+                    .map(String::into_bytes);
+                let json = sub_matches.get_flag("json");
+
+                let service_path = PathBuf::from(service_path.as_str());
+                if !service_path.exists() {
+                    bail!("service path does not exist ({})", service_path.display());
+                }
+
+                let report = bench::run(BenchOptions {
+                    service_path,
+                    import_map_path,
+                    env_vars: std::env::vars().collect(),
+                    memory_limit_mb,
+                    requests,
+                    concurrency,
+                    method,
+                    path,
+                    body,
+                })
+                .await?;
+
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                } else {
+                    println!("{}", report);
+                }
+            }
+            Some(("attach", sub_matches)) => {
+                let socket_path = sub_matches
+                    .get_one::<String>("admin-socket")
+                    .cloned()
+                    .unwrap();
+                let maybe_command = sub_matches
+                    .get_many::<String>("command")
+                    .map(|it| it.cloned().collect::<Vec<_>>().join(" "));
+
+                #[cfg(unix)]
+                base::admin::attach(&PathBuf::from(socket_path), maybe_command).await?;
+
+                #[cfg(not(unix))]
+                bail!(
+                    "attach is only supported on Unix ({}, {:?})",
+                    socket_path,
+                    maybe_command
+                );
+            }
             _ => {
                 // unrecognized command
             }
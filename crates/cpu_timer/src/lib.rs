@@ -1,9 +1,13 @@
+pub mod sampler;
 pub mod timerid;
 
 #[cfg(target_os = "linux")]
 use std::sync::Arc;
 
-use anyhow::Error;
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+use sampler::SamplingTimer;
 use tokio::sync::mpsc;
 
 #[cfg(target_os = "linux")]
@@ -18,11 +22,11 @@ mod linux {
     use once_cell::sync::Lazy;
     use tokio::sync::mpsc;
 
-    use crate::CPUTimer;
+    use crate::SignalTimer;
 
     pub enum SignalMsg {
         Alarm(usize),
-        Add((usize, CPUTimer)),
+        Add((usize, SignalTimer)),
         Remove(usize),
     }
 
@@ -56,14 +60,14 @@ unsafe impl Send for CPUTimerVal {}
 
 #[cfg(target_os = "linux")]
 #[derive(Clone)]
-pub struct CPUTimer {
+pub struct SignalTimer {
     id: usize,
     timer: Arc<linux::Mutex<CPUTimerVal>>,
     cpu_alarm_val: Arc<CPUAlarmVal>,
 }
 
 #[cfg(target_os = "linux")]
-impl Drop for CPUTimer {
+impl Drop for SignalTimer {
     fn drop(&mut self) {
         if Arc::strong_count(&self.timer) == 2 {
             linux::SIG_MSG_CHAN
@@ -77,9 +81,9 @@ impl Drop for CPUTimer {
 
 #[cfg(not(target_os = "linux"))]
 #[derive(Clone)]
-pub struct CPUTimer {}
+pub struct SignalTimer {}
 
-impl CPUTimer {
+impl SignalTimer {
     #[cfg(target_os = "linux")]
     pub fn start(
         initial_expiry: u64,
@@ -177,6 +181,73 @@ impl CPUTimer {
     }
 }
 
+/// Selects how CPU time limits of the workers are enforced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CPUTimerMode {
+    /// Relies on `SIGALRM` of the per-thread CPU timers. Only available on
+    /// Linux.
+    Signal,
+    /// Periodically samples the CPU clock of the worker threads.
+    Sampling,
+}
+
+impl Default for CPUTimerMode {
+    fn default() -> Self {
+        if cfg!(target_os = "linux") {
+            Self::Signal
+        } else {
+            Self::Sampling
+        }
+    }
+}
+
+impl FromStr for CPUTimerMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "signal" => Ok(Self::Signal),
+            "sampling" => Ok(Self::Sampling),
+            _ => bail!("invalid cpu timer mode: {}", s),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum CPUTimer {
+    Signal(SignalTimer),
+    Sampling(SamplingTimer),
+}
+
+impl CPUTimer {
+    /// Must be called on the thread whose CPU time should be measured.
+    pub fn start(
+        mode: CPUTimerMode,
+        initial_expiry: u64,
+        interval: u64,
+        cpu_alarm_val: CPUAlarmVal,
+    ) -> Result<Self, Error> {
+        Ok(match mode {
+            CPUTimerMode::Signal => {
+                Self::Signal(SignalTimer::start(initial_expiry, interval, cpu_alarm_val)?)
+            }
+
+            CPUTimerMode::Sampling => Self::Sampling(SamplingTimer::start(
+                initial_expiry,
+                interval,
+                cpu_alarm_val,
+            )?),
+        })
+    }
+
+    pub fn reset(&self) -> Result<(), Error> {
+        match self {
+            Self::Signal(it) => it.reset(),
+            Self::Sampling(it) => it.reset(),
+        }
+    }
+}
+
 pub fn get_thread_time() -> Result<i64, Error> {
     let mut time = libc::timespec {
         tv_sec: 0,
@@ -204,7 +275,7 @@ fn register_sigalrm() {
 
     let (sig_timer_id_tx, mut sig_timer_id_rx) = mpsc::unbounded_channel::<usize>();
 
-    let mut registry = HashMap::<usize, CPUTimer>::new();
+    let mut registry = HashMap::<usize, SignalTimer>::new();
 
     let sig_msg_tx = linux::SIG_MSG_CHAN.0.clone();
     let mut sig_msg_rx = linux::SIG_MSG_CHAN.1.lock().unwrap().take().unwrap();
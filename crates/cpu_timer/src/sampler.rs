@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Error};
+use log::debug;
+use once_cell::sync::Lazy;
+
+use crate::CPUAlarmVal;
+
+/// How often the sampler thread polls the CPU clocks of the registered
+/// threads.
+static SAMPLE_INTERVAL: Lazy<Duration> = Lazy::new(|| {
+    std::env::var("EDGE_RUNTIME_CPU_SAMPLE_INT")
+        .ok()
+        .and_then(|it| it.parse::<u64>().ok().map(Duration::from_millis))
+        .unwrap_or_else(|| Duration::from_millis(DEFAULT_SAMPLE_INT_MSEC))
+});
+
+const DEFAULT_SAMPLE_INT_MSEC: u64 = 5;
+
+static TIMER_COUNTER: AtomicUsize = AtomicUsize::new(0);
+static REGISTRY: Lazy<Mutex<HashMap<usize, SamplingTimer>>> = Lazy::new(|| {
+    std::thread::Builder::new()
+        .name("sb-cpu-sampler".into())
+        .spawn(sample_loop)
+        .unwrap();
+
+    Mutex::default()
+});
+
+/// A CPU clock of a particular thread that can be read from any other thread.
+#[derive(Clone, Copy)]
+struct ThreadClock {
+    #[cfg(target_os = "linux")]
+    clock_id: libc::clockid_t,
+    #[cfg(target_os = "macos")]
+    port: libc::mach_port_t,
+}
+
+impl ThreadClock {
+    #[cfg(target_os = "linux")]
+    fn current() -> Result<Self, Error> {
+        let mut clock_id: libc::clockid_t = 0;
+        let errno = unsafe { libc::pthread_getcpuclockid(libc::pthread_self(), &mut clock_id) };
+
+        if errno != 0 {
+            bail!(std::io::Error::from_raw_os_error(errno))
+        }
+
+        Ok(Self { clock_id })
+    }
+
+    #[cfg(target_os = "linux")]
+    fn now_ns(&self) -> Result<i64, Error> {
+        let mut time = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+
+        if unsafe { libc::clock_gettime(self.clock_id, &mut time) } == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(time.tv_sec * 1_000_000_000 + time.tv_nsec)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn current() -> Result<Self, Error> {
+        Ok(Self {
+            port: unsafe { libc::pthread_mach_thread_np(libc::pthread_self()) },
+        })
+    }
+
+    #[cfg(target_os = "macos")]
+    fn now_ns(&self) -> Result<i64, Error> {
+        let mut info: libc::thread_basic_info = unsafe { std::mem::zeroed() };
+        let mut count = libc::THREAD_BASIC_INFO_COUNT;
+
+        if unsafe {
+            libc::thread_info(
+                self.port,
+                libc::THREAD_BASIC_INFO as libc::thread_flavor_t,
+                &mut info as *mut libc::thread_basic_info as libc::thread_info_t,
+                &mut count,
+            )
+        } != libc::KERN_SUCCESS
+        {
+            bail!("failed to read the thread cpu clock");
+        }
+
+        let to_ns = |it: libc::time_value_t| {
+            (it.seconds as i64) * 1_000_000_000 + (it.microseconds as i64) * 1_000
+        };
+
+        Ok(to_ns(info.user_time) + to_ns(info.system_time))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    fn current() -> Result<Self, Error> {
+        bail!("CPU sampler: not supported on this platform")
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    fn now_ns(&self) -> Result<i64, Error> {
+        unreachable!()
+    }
+}
+
+struct SamplingTimerVal {
+    clock: ThreadClock,
+    initial_expiry_ns: i64,
+    interval_ns: i64,
+    deadline_ns: Option<i64>,
+}
+
+/// A CPU timer that periodically samples the CPU clock of the thread it was
+/// started on, instead of relying on timer signals.
+#[derive(Clone)]
+pub struct SamplingTimer {
+    id: usize,
+    timer: Arc<Mutex<SamplingTimerVal>>,
+    cpu_alarm_val: Arc<CPUAlarmVal>,
+}
+
+impl Drop for SamplingTimer {
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.timer) == 2 {
+            // NOTE: The removed timer must be dropped after the lock has been
+            // released since dropping it re-enters here.
+            let _removed = REGISTRY.lock().ok().and_then(|mut it| it.remove(&self.id));
+        }
+    }
+}
+
+impl SamplingTimer {
+    /// Must be called on the thread whose CPU time should be measured.
+    pub fn start(
+        initial_expiry: u64,
+        interval: u64,
+        cpu_alarm_val: CPUAlarmVal,
+    ) -> Result<Self, Error> {
+        let id = TIMER_COUNTER.fetch_add(1, Ordering::SeqCst);
+        let this = Self {
+            id,
+            timer: Arc::new(Mutex::new(SamplingTimerVal {
+                clock: ThreadClock::current()?,
+                initial_expiry_ns: (initial_expiry as i64) * 1_000_000,
+                interval_ns: (interval as i64) * 1_000_000,
+                deadline_ns: None,
+            })),
+            cpu_alarm_val: Arc::new(cpu_alarm_val),
+        };
+
+        this.reset()?;
+
+        REGISTRY
+            .lock()
+            .map_err(|_| anyhow::anyhow!("cpu sampler registry poisoned"))?
+            .insert(id, this.clone());
+
+        Ok(this)
+    }
+
+    pub fn reset(&self) -> Result<(), Error> {
+        let mut timer = self
+            .timer
+            .try_lock()
+            .ok()
+            .context("failed to get the lock")?;
+        let now_ns = timer.clock.now_ns()?;
+
+        timer.deadline_ns = Some(now_ns + timer.initial_expiry_ns);
+
+        Ok(())
+    }
+
+    /// Returns `false` if the timer can no longer be sampled.
+    fn sample(&self) -> bool {
+        let Ok(mut timer) = self.timer.try_lock() else {
+            // NOTE: The timer is being reset; check it on the next tick.
+            return true;
+        };
+
+        let Some(deadline_ns) = timer.deadline_ns else {
+            return true;
+        };
+
+        let Ok(now_ns) = timer.clock.now_ns() else {
+            // NOTE: The thread has gone away.
+            return false;
+        };
+
+        if now_ns < deadline_ns {
+            return true;
+        }
+
+        timer.deadline_ns = if timer.interval_ns > 0 {
+            Some(now_ns + timer.interval_ns)
+        } else {
+            None
+        };
+
+        if self.cpu_alarm_val.cpu_alarms_tx.send(()).is_err() {
+            debug!("failed to send cpu alarm to the provided channel");
+        }
+
+        true
+    }
+}
+
+fn sample_loop() {
+    loop {
+        std::thread::sleep(*SAMPLE_INTERVAL);
+
+        let timers = match REGISTRY.lock() {
+            Ok(registry) => registry.values().cloned().collect::<Vec<_>>(),
+            Err(_) => continue,
+        };
+
+        for timer in timers {
+            if !timer.sample() {
+                let _removed = REGISTRY.lock().ok().and_then(|mut it| it.remove(&timer.id));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    /// Keeps the current thread busy until an alarm arrives, or until `limit`
+    /// of wall clock time has passed.
+    fn spin_until_alarm(rx: &mut mpsc::UnboundedReceiver<()>, limit: Duration) -> bool {
+        let started_at = Instant::now();
+
+        while started_at.elapsed() < limit {
+            if rx.try_recv().is_ok() {
+                return true;
+            }
+
+            std::hint::black_box((0..10_000u64).sum::<u64>());
+        }
+
+        false
+    }
+
+    #[test]
+    fn test_sampling_timer_fires_once_cpu_time_is_spent() {
+        let (cpu_alarms_tx, mut cpu_alarms_rx) = mpsc::unbounded_channel();
+        let _timer = SamplingTimer::start(20, 0, CPUAlarmVal { cpu_alarms_tx }).unwrap();
+
+        assert!(spin_until_alarm(&mut cpu_alarms_rx, Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_sampling_timer_ignores_time_spent_sleeping() {
+        let (cpu_alarms_tx, mut cpu_alarms_rx) = mpsc::unbounded_channel();
+        let _timer = SamplingTimer::start(20, 0, CPUAlarmVal { cpu_alarms_tx }).unwrap();
+
+        std::thread::sleep(Duration::from_millis(200));
+
+        assert!(cpu_alarms_rx.try_recv().is_err());
+    }
+}
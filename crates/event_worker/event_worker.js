@@ -11,13 +11,13 @@ class SupabaseEventListener {
 
 			let value = undefined;
 			if (!done) {
-				const rawEvent = reqEvt['Event'];
-				const eventType = Object.keys(rawEvent.event)[0];
+				const envelope = reqEvt['Event'];
 				value = {
 					timestamp: new Date().toISOString(),
-					event_type: eventType,
-					event: rawEvent.event[eventType],
-					metadata: rawEvent.metadata,
+					schema_version: envelope.schema_version,
+					event_type: envelope.event_type,
+					event: envelope.event,
+					metadata: envelope.metadata,
 				};
 			}
 
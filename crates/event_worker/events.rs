@@ -1,13 +1,48 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::schema::EventEnvelope;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BootEvent {
+    /// Boot time in whole milliseconds. Kept for existing sinks; prefer
+    /// `boot_time_ms`.
     pub boot_time: usize,
+    #[serde(default)]
+    pub boot_time_ms: f64,
+    /// Whether the modules came from a prebuilt eszip or the module cache,
+    /// rather than being fetched again.
+    #[serde(default)]
+    pub module_cache_hit: bool,
+    #[serde(default)]
+    pub module_count: usize,
+    #[serde(default)]
+    pub used_snapshot: bool,
+    #[serde(default)]
+    pub service_path: Option<String>,
 }
+/// Why a worker failed to boot.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BootFailureReason {
+    #[default]
+    Error,
+    /// The eszip of the worker isn't signed by a trusted key.
+    SignatureMismatch,
+    /// The files of the service don't match its checksum manifest.
+    ChecksumMismatch,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BootFailureEvent {
     pub msg: String,
+    #[serde(default)]
+    pub reason: BootFailureReason,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -15,6 +50,48 @@ pub struct WorkerMemoryUsed {
     pub total: usize,
     pub heap: usize,
     pub external: usize,
+    /// Final heap size limit, including any low memory allowance granted.
+    pub heap_limit: usize,
+}
+
+/// Summary of the requests a user worker has served.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub struct WorkerRequestStats {
+    pub request_count: usize,
+    pub error_count: usize,
+    pub latency_p50_ms: u64,
+    pub latency_p95_ms: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// Time from a request arriving at the pool until it is handed over to
+    /// the worker.
+    pub dispatch_latency_p50_ms: u64,
+    pub dispatch_latency_p95_ms: u64,
+    /// Requests that have arrived but are not handed over to the worker yet.
+    pub queue_depth: usize,
+    /// Resources the worker held open as it finished its last request.
+    #[serde(default)]
+    pub open_resources: usize,
+    /// Async ops the worker was waiting on as its event loop last yielded.
+    #[serde(default)]
+    pub pending_ops: usize,
+    /// 95th percentile of how long a turn of the event loop of the worker
+    /// takes, or how long the current turn has been running if longer. A
+    /// high lag means the worker is busy on the CPU, whereas many pending
+    /// ops with little lag mean it is waiting on external resources.
+    #[serde(default)]
+    pub event_loop_lag_ms: u64,
+    /// Upgraded connections and event streams the worker is serving.
+    #[serde(default)]
+    pub active_connections: usize,
+    /// Connections refused because the worker was serving as many as it
+    /// may at once.
+    #[serde(default)]
+    pub rejected_connections: usize,
+    /// How long the worker took to boot. It is not counted in the latencies
+    /// of its requests.
+    #[serde(default)]
+    pub boot_time_ms: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -31,6 +108,22 @@ pub struct ShutdownEvent {
     pub reason: ShutdownReason,
     pub cpu_time_used: usize,
     pub memory_used: WorkerMemoryUsed,
+    #[serde(default)]
+    pub request_stats: WorkerRequestStats,
+    /// Where the diagnostic bundle of the worker was written, if the runtime
+    /// captures them and the worker was terminated for hitting a limit.
+    #[serde(default)]
+    pub diagnostics_path: Option<String>,
+    /// The request that went over the per-request CPU time limit, if that is
+    /// what the worker was shut down for.
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MemoryWarningEvent {
+    pub memory_used: usize,
+    pub memory_limit: usize,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -44,6 +137,94 @@ pub struct EventLoopCompletedEvent {
     pub cpu_time_used: usize,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReloadedEvent {
+    /// Workers that were serving the service before the reload.
+    pub replaced: Vec<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseBodyLimitExceededEvent {
+    pub max_bytes: u64,
+}
+
+/// Resources used by the user workers of a tenant.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct WorkerUsage {
+    pub cpu_time_ms: u64,
+    /// Memory limit of each worker multiplied by how long it was alive.
+    pub memory_gb_seconds: f64,
+    pub request_count: u64,
+    pub egress_bytes: u64,
+    /// Workers whose usage is included.
+    pub worker_count: u64,
+}
+
+impl std::ops::AddAssign for WorkerUsage {
+    fn add_assign(&mut self, rhs: Self) {
+        self.cpu_time_ms += rhs.cpu_time_ms;
+        self.memory_gb_seconds += rhs.memory_gb_seconds;
+        self.request_count += rhs.request_count;
+        self.egress_bytes += rhs.egress_bytes;
+        self.worker_count += rhs.worker_count;
+    }
+}
+
+/// Usage accrued by a tenant since its previous report.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UsageReportEvent {
+    pub tenant: String,
+    pub period_ms: u64,
+    pub usage: WorkerUsage,
+}
+
+/// The open resources of a user worker kept growing from one request to the
+/// next, which usually means it leaks them.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResourceLeakEvent {
+    /// Open resources by their name, as the worker finished the last request.
+    pub open_resources: BTreeMap<String, usize>,
+    /// Requests in a row after each of which the worker held more resources.
+    pub consecutive_requests: u64,
+}
+
+/// Whose side the connection a request was written to a worker over broke
+/// on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionErrorKind {
+    /// The worker closed or reset its end before it was done answering.
+    WorkerReset,
+    /// The runtime misused the connection, which is a bug of its own.
+    Runtime,
+}
+
+impl fmt::Display for ConnectionErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WorkerReset => write!(f, "reset by the worker"),
+            Self::Runtime => write!(f, "broken by the runtime"),
+        }
+    }
+}
+
+/// The connection a request was written to a worker over broke while the
+/// request was in flight.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ConnectionErrorEvent {
+    pub request_id: String,
+    pub kind: ConnectionErrorKind,
+    pub msg: String,
+}
+
+/// Websocket connections of a worker that went away without closing them,
+/// which the runtime closed in its place.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WebSocketsClosedEvent {
+    pub count: usize,
+    /// Status code of the close frames sent to the clients.
+    pub code: u16,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LogEvent {
     pub msg: String,
@@ -66,6 +247,13 @@ pub enum WorkerEvents {
     Shutdown(ShutdownEvent),
     EventLoopCompleted(EventLoopCompletedEvent),
     Log(LogEvent),
+    MemoryWarning(MemoryWarningEvent),
+    Reloaded(ReloadedEvent),
+    ResponseBodyLimitExceeded(ResponseBodyLimitExceededEvent),
+    UsageReport(UsageReportEvent),
+    ResourceLeak(ResourceLeakEvent),
+    ConnectionError(ConnectionErrorEvent),
+    WebSocketsClosed(WebSocketsClosedEvent),
 }
 
 impl WorkerEvents {
@@ -81,12 +269,125 @@ impl WorkerEvents {
 
         self
     }
+
+    /// Masks the values of `secrets` wherever the event carries free-form
+    /// text.
+    pub fn redact(&mut self, secrets: &WorkerSecrets) {
+        let text = match self {
+            Self::BootFailure(BootFailureEvent { msg, .. })
+            | Self::Log(LogEvent { msg, .. })
+            | Self::UncaughtException(UncaughtExceptionEvent { exception: msg, .. }) => msg,
+
+            _ => return,
+        };
+
+        let redacted = match secrets.redact(text) {
+            Cow::Owned(it) => it,
+            Cow::Borrowed(_) => return,
+        };
+
+        *text = redacted;
+    }
+
+    pub fn cpu_time_used(&self) -> Option<usize> {
+        match self {
+            Self::UncaughtException(UncaughtExceptionEvent { cpu_time_used, .. })
+            | Self::Shutdown(ShutdownEvent { cpu_time_used, .. })
+            | Self::EventLoopCompleted(EventLoopCompletedEvent { cpu_time_used }) => {
+                Some(*cpu_time_used)
+            }
+
+            _ => None,
+        }
+    }
+}
+
+/// Credentials provisioned for a user worker when it is created. Unlike
+/// environment variables, they are only handed out by name, their values
+/// never show up in `Debug` output and they are masked in the events of the
+/// worker.
+#[derive(Default, Clone)]
+pub struct WorkerSecrets(Arc<HashMap<String, String>>);
+
+impl WorkerSecrets {
+    pub const REDACTED: &'static str = "[REDACTED]";
+
+    pub fn new(secrets: HashMap<String, String>) -> Self {
+        Self(Arc::new(secrets))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Replaces every occurrence of a secret value in `text`. Borrows `text`
+    /// back if none was found.
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+
+        for value in self.0.values().filter(|it| !it.is_empty()) {
+            if text.contains(value.as_str()) {
+                text = Cow::Owned(text.replace(value.as_str(), Self::REDACTED));
+            }
+        }
+
+        text
+    }
+}
+
+impl fmt::Debug for WorkerSecrets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.keys().map(|name| (name, Self::REDACTED)))
+            .finish()
+    }
+}
+
+/// The last lines a user worker logged, kept in its op state for the
+/// diagnostic bundle of the supervisor.
+#[derive(Debug)]
+pub struct WorkerLogTail {
+    capacity: usize,
+    lines: RefCell<VecDeque<String>>,
+}
+
+impl WorkerLogTail {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            lines: RefCell::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn push(&self, level: &LogLevel, msg: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut lines = self.lines.borrow_mut();
+
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+
+        lines.push_back(format!("[{:?}] {}", level, msg));
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.borrow().iter().cloned().collect()
+    }
 }
 
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct EventMetadata {
     pub service_path: Option<String>,
     pub execution_id: Option<Uuid>,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -97,7 +398,7 @@ pub struct WorkerEventWithMetadata {
 
 #[derive(Serialize, Deserialize)]
 pub enum RawEvent {
-    Event(WorkerEventWithMetadata),
+    Event(EventEnvelope),
     Done,
 }
 
@@ -108,3 +409,41 @@ pub struct IncomingEvent {
     data: Option<Vec<u8>>,
     done: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_secrets_in_event_text() {
+        let secrets = WorkerSecrets::new(HashMap::from([(
+            "API_KEY".to_string(),
+            "sk_test_123".to_string(),
+        )]));
+
+        let mut event = WorkerEvents::Log(LogEvent {
+            msg: "calling with sk_test_123".to_string(),
+            level: LogLevel::Info,
+        });
+
+        event.redact(&secrets);
+
+        let WorkerEvents::Log(LogEvent { msg, .. }) = event else {
+            unreachable!()
+        };
+
+        assert_eq!(msg, "calling with [REDACTED]");
+        assert_eq!(format!("{:?}", secrets), r#"{"API_KEY": "[REDACTED]"}"#);
+    }
+
+    #[test]
+    fn log_tail_keeps_last_lines() {
+        let tail = WorkerLogTail::new(2);
+
+        tail.push(&LogLevel::Info, "first");
+        tail.push(&LogLevel::Info, "second");
+        tail.push(&LogLevel::Error, "third");
+
+        assert_eq!(tail.lines(), vec!["[Info] second", "[Error] third"]);
+    }
+}
@@ -1,10 +1,12 @@
-use crate::events::{EventMetadata, LogEvent, LogLevel, WorkerEvents};
+use crate::events::{
+    EventMetadata, LogEvent, LogLevel, WorkerEvents, WorkerLogTail, WorkerSecrets,
+};
 use crate::WorkerEventWithMetadata;
+use bounded_channel::BoundedSender;
 use deno_core::error::AnyError;
 use deno_core::op2;
 use deno_core::OpState;
 use log::error;
-use tokio::sync::mpsc;
 
 #[op2(fast)]
 fn op_user_worker_log(
@@ -12,7 +14,7 @@ fn op_user_worker_log(
     #[string] msg: &str,
     is_err: bool,
 ) -> Result<(), AnyError> {
-    let maybe_tx = state.try_borrow::<mpsc::UnboundedSender<WorkerEventWithMetadata>>();
+    let maybe_tx = state.try_borrow::<BoundedSender<WorkerEventWithMetadata>>();
     let mut level = LogLevel::Info;
     if is_err {
         level = LogLevel::Error;
@@ -25,14 +27,25 @@ fn op_user_worker_log(
             .clone();
 
         let metadata = EventMetadata { ..event_metadata };
+        let msg = match state.try_borrow::<WorkerSecrets>() {
+            Some(secrets) => secrets.redact(msg).into_owned(),
+            None => msg.to_string(),
+        };
 
-        tx.send(WorkerEventWithMetadata {
-            event: WorkerEvents::Log(LogEvent {
-                msg: msg.to_string(),
-                level,
-            }),
+        if let Some(tail) = state.try_borrow::<WorkerLogTail>() {
+            tail.push(&level, &msg);
+        }
+
+        // NOTE: Logs that don't fit are dropped rather than thrown at the
+        // user code. They are counted in the runtime metrics.
+        if let Err(err) = tx.send(WorkerEventWithMetadata {
+            event: WorkerEvents::Log(LogEvent { msg, level }),
             metadata,
-        })?;
+        }) {
+            if !err.is_full() {
+                return Err(err.into());
+            }
+        }
     } else {
         error!("[{:?}] {}", level, msg.to_string());
     }
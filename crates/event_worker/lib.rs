@@ -1,4 +1,5 @@
 use crate::events::{RawEvent, WorkerEventWithMetadata};
+use crate::schema::EventEnvelope;
 use anyhow::{bail, Error};
 use deno_core::op2;
 use deno_core::OpState;
@@ -8,13 +9,14 @@ use tokio::sync::mpsc;
 
 pub mod events;
 pub mod js_interceptors;
+pub mod schema;
 
 #[op2(async)]
 #[serde]
 async fn op_event_accept(state: Rc<RefCell<OpState>>) -> Result<RawEvent, Error> {
     let rx = {
         let mut op_state = state.borrow_mut();
-        op_state.try_take::<mpsc::UnboundedReceiver<WorkerEventWithMetadata>>()
+        op_state.try_take::<mpsc::Receiver<WorkerEventWithMetadata>>()
     };
     if rx.is_none() {
         bail!("events worker receiver not available")
@@ -24,10 +26,10 @@ async fn op_event_accept(state: Rc<RefCell<OpState>>) -> Result<RawEvent, Error>
     let data = rx.recv().await;
 
     let mut op_state = state.borrow_mut();
-    op_state.put::<mpsc::UnboundedReceiver<WorkerEventWithMetadata>>(rx);
+    op_state.put::<mpsc::Receiver<WorkerEventWithMetadata>>(rx);
 
     match data {
-        Some(event) => Ok(RawEvent::Event(event)),
+        Some(event) => Ok(RawEvent::Event(EventEnvelope::try_from(&event)?)),
         None => Ok(RawEvent::Done),
     }
 }
@@ -0,0 +1,191 @@
+//! The JSON form in which worker events leave the runtime.
+//!
+//! Sinks, including the events worker, should depend on this form rather than
+//! on the Rust types in [`crate::events`]. Within a schema version, event
+//! types are never renamed and fields are only ever added. Anything else
+//! bumps [`EVENT_SCHEMA_VERSION`].
+
+use anyhow::{bail, Error};
+use deno_core::serde_json::{self, Value};
+use serde::{Deserialize, Serialize};
+
+use crate::events::{EventMetadata, WorkerEventWithMetadata, WorkerEvents};
+
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// Every event type in the current schema version.
+pub const EVENT_TYPES: &[&str] = &[
+    "Boot",
+    "BootFailure",
+    "UncaughtException",
+    "Shutdown",
+    "EventLoopCompleted",
+    "Log",
+    "MemoryWarning",
+    "Reloaded",
+    "ResponseBodyLimitExceeded",
+    "UsageReport",
+    "ResourceLeak",
+    "ConnectionError",
+    "WebSocketsClosed",
+];
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EventEnvelope {
+    pub schema_version: u32,
+    pub event_type: String,
+    pub event: Value,
+    pub metadata: EventMetadata,
+}
+
+impl WorkerEvents {
+    /// The name of the event in the schema. It doesn't follow the name of the
+    /// variant, so renaming a variant doesn't break sinks.
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            Self::Boot(_) => "Boot",
+            Self::BootFailure(_) => "BootFailure",
+            Self::UncaughtException(_) => "UncaughtException",
+            Self::Shutdown(_) => "Shutdown",
+            Self::EventLoopCompleted(_) => "EventLoopCompleted",
+            Self::Log(_) => "Log",
+            Self::MemoryWarning(_) => "MemoryWarning",
+            Self::Reloaded(_) => "Reloaded",
+            Self::ResponseBodyLimitExceeded(_) => "ResponseBodyLimitExceeded",
+            Self::UsageReport(_) => "UsageReport",
+            Self::ResourceLeak(_) => "ResourceLeak",
+            Self::ConnectionError(_) => "ConnectionError",
+            Self::WebSocketsClosed(_) => "WebSocketsClosed",
+        }
+    }
+
+    fn payload(&self) -> Result<Value, serde_json::Error> {
+        match self {
+            Self::Boot(it) => serde_json::to_value(it),
+            Self::BootFailure(it) => serde_json::to_value(it),
+            Self::UncaughtException(it) => serde_json::to_value(it),
+            Self::Shutdown(it) => serde_json::to_value(it),
+            Self::EventLoopCompleted(it) => serde_json::to_value(it),
+            Self::Log(it) => serde_json::to_value(it),
+            Self::MemoryWarning(it) => serde_json::to_value(it),
+            Self::Reloaded(it) => serde_json::to_value(it),
+            Self::ResponseBodyLimitExceeded(it) => serde_json::to_value(it),
+            Self::UsageReport(it) => serde_json::to_value(it),
+            Self::ResourceLeak(it) => serde_json::to_value(it),
+            Self::ConnectionError(it) => serde_json::to_value(it),
+            Self::WebSocketsClosed(it) => serde_json::to_value(it),
+        }
+    }
+
+    fn from_payload(event_type: &str, payload: Value) -> Result<Self, Error> {
+        Ok(match event_type {
+            "Boot" => Self::Boot(serde_json::from_value(payload)?),
+            "BootFailure" => Self::BootFailure(serde_json::from_value(payload)?),
+            "UncaughtException" => Self::UncaughtException(serde_json::from_value(payload)?),
+            "Shutdown" => Self::Shutdown(serde_json::from_value(payload)?),
+            "EventLoopCompleted" => Self::EventLoopCompleted(serde_json::from_value(payload)?),
+            "Log" => Self::Log(serde_json::from_value(payload)?),
+            "MemoryWarning" => Self::MemoryWarning(serde_json::from_value(payload)?),
+            "Reloaded" => Self::Reloaded(serde_json::from_value(payload)?),
+            "ResponseBodyLimitExceeded" => {
+                Self::ResponseBodyLimitExceeded(serde_json::from_value(payload)?)
+            }
+            "UsageReport" => Self::UsageReport(serde_json::from_value(payload)?),
+            "ResourceLeak" => Self::ResourceLeak(serde_json::from_value(payload)?),
+            "ConnectionError" => Self::ConnectionError(serde_json::from_value(payload)?),
+            "WebSocketsClosed" => Self::WebSocketsClosed(serde_json::from_value(payload)?),
+
+            other => bail!("unknown event type: {}", other),
+        })
+    }
+}
+
+impl TryFrom<&WorkerEventWithMetadata> for EventEnvelope {
+    type Error = serde_json::Error;
+
+    fn try_from(value: &WorkerEventWithMetadata) -> Result<Self, Self::Error> {
+        Ok(Self {
+            schema_version: EVENT_SCHEMA_VERSION,
+            event_type: value.event.event_type().to_string(),
+            event: value.event.payload()?,
+            metadata: value.metadata.clone(),
+        })
+    }
+}
+
+impl TryFrom<EventEnvelope> for WorkerEventWithMetadata {
+    type Error = Error;
+
+    fn try_from(value: EventEnvelope) -> Result<Self, Self::Error> {
+        if value.schema_version != EVENT_SCHEMA_VERSION {
+            bail!(
+                "unsupported event schema version: {} (expected {})",
+                value.schema_version,
+                EVENT_SCHEMA_VERSION
+            );
+        }
+
+        Ok(Self {
+            event: WorkerEvents::from_payload(&value.event_type, value.event)?,
+            metadata: value.metadata,
+        })
+    }
+}
+
+pub fn to_json_string(event: &WorkerEventWithMetadata) -> Result<String, serde_json::Error> {
+    serde_json::to_string(&EventEnvelope::try_from(event)?)
+}
+
+pub fn from_json_str(s: &str) -> Result<WorkerEventWithMetadata, Error> {
+    serde_json::from_str::<EventEnvelope>(s)?.try_into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{LogEvent, LogLevel};
+
+    #[test]
+    fn round_trips_through_json() {
+        let event = WorkerEventWithMetadata {
+            event: WorkerEvents::Log(LogEvent {
+                msg: "meow".into(),
+                level: LogLevel::Info,
+            }),
+            metadata: EventMetadata::default(),
+        };
+
+        let json = to_json_string(&event).unwrap();
+        let value = serde_json::from_str::<Value>(&json).unwrap();
+
+        assert_eq!(value["schema_version"], EVENT_SCHEMA_VERSION);
+        assert_eq!(value["event_type"], "Log");
+        assert_eq!(value["event"]["msg"], "meow");
+
+        let WorkerEvents::Log(LogEvent { msg, .. }) = from_json_str(&json).unwrap().event else {
+            panic!("unexpected event type");
+        };
+
+        assert_eq!(msg, "meow");
+    }
+
+    #[test]
+    fn rejects_other_schema_versions() {
+        let json = r#"{"schema_version":0,"event_type":"Log","event":{"msg":"","level":"Info"},"metadata":{"service_path":null,"execution_id":null}}"#;
+
+        assert!(from_json_str(json).is_err());
+    }
+
+    #[test]
+    fn reads_boot_events_without_boot_metadata() {
+        let json = r#"{"schema_version":1,"event_type":"Boot","event":{"boot_time":12},"metadata":{"service_path":null,"execution_id":null}}"#;
+
+        let WorkerEvents::Boot(event) = from_json_str(json).unwrap().event else {
+            panic!("unexpected event type");
+        };
+
+        assert_eq!(event.boot_time, 12);
+        assert_eq!(event.module_count, 0);
+        assert!(event.service_path.is_none());
+    }
+}
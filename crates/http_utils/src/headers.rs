@@ -0,0 +1,64 @@
+use http::header::{HeaderName, InvalidHeaderName};
+use http::HeaderMap;
+
+/// Parses a lowercase header name, reusing a static name for the ones that
+/// go through the runtime on most requests.
+///
+/// `http` already avoids allocating for the standard names, but any other
+/// name is copied into a fresh buffer every time it is parsed.
+pub fn intern_name(name: &[u8]) -> Result<HeaderName, InvalidHeaderName> {
+    let name = match name {
+        b"apikey" => "apikey",
+        b"forwarded" => "forwarded",
+        b"x-client-info" => "x-client-info",
+        b"x-forwarded-for" => "x-forwarded-for",
+        b"x-forwarded-host" => "x-forwarded-host",
+        b"x-forwarded-port" => "x-forwarded-port",
+        b"x-forwarded-proto" => "x-forwarded-proto",
+        b"x-real-ip" => "x-real-ip",
+        b"x-request-id" => "x-request-id",
+        b"x-sb-client-ip" => "x-sb-client-ip",
+        b"x-sb-jwt-claims" => "x-sb-jwt-claims",
+        b"x-sb-signature" => "x-sb-signature",
+        _ => return HeaderName::from_bytes(name),
+    };
+
+    Ok(HeaderName::from_static(name))
+}
+
+/// Lists the headers as name and value pairs, without going through `str`,
+/// so that values with bytes outside of visible ASCII are kept as they are.
+pub fn to_pairs<'a, T>(headers: &'a HeaderMap) -> Vec<(T, T)>
+where
+    T: From<&'a [u8]>,
+{
+    headers
+        .iter()
+        .map(|(name, value)| (T::from(name.as_str().as_bytes()), T::from(value.as_bytes())))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_intern_name() {
+        assert_eq!(intern_name(b"x-request-id").unwrap(), "x-request-id");
+        assert_eq!(intern_name(b"x-custom").unwrap(), "x-custom");
+        assert!(intern_name(b"bad name").is_err());
+    }
+
+    #[test]
+    fn test_to_pairs_keeps_opaque_values() {
+        let mut headers = HeaderMap::new();
+
+        headers.insert("x-opaque", HeaderValue::from_bytes(b"caf\xe9").unwrap());
+
+        let pairs = to_pairs::<Vec<u8>>(&headers);
+
+        assert_eq!(pairs, vec![(b"x-opaque".to_vec(), b"caf\xe9".to_vec())]);
+    }
+}
@@ -1,2 +1,3 @@
+pub mod headers;
 pub mod io;
 pub mod utils;
@@ -113,6 +113,28 @@ impl DenoDir {
         self.root.join("registries")
     }
 
+    /// Path to the eszips built for the services of user workers.
+    pub fn service_eszip_folder_path(&self) -> PathBuf {
+        // bump this version name to invalidate the entire cache
+        self.root.join("service_eszip_v1")
+    }
+
+    /// Path to the eszips of services fetched from object storage.
+    pub fn service_storage_folder_path(&self) -> PathBuf {
+        self.root.join("service_storage_v1")
+    }
+
+    /// Path to the services extracted from archives.
+    pub fn service_archive_folder_path(&self) -> PathBuf {
+        self.root.join("service_archive_v1")
+    }
+
+    /// Path to the services of the user worker pool, as they were when the
+    /// runtime last shut down.
+    pub fn pool_state_file_path(&self) -> PathBuf {
+        self.root.join("pool_state_v1.json")
+    }
+
     /// Path to the dependencies cache folder.
     pub fn deps_folder_path(&self) -> PathBuf {
         self.root.join("deps")
@@ -1,7 +1,10 @@
+use std::net::SocketAddr;
+
 use deno_core::Resource;
+use serde::Serialize;
 use tokio_util::sync::CancellationToken;
 
-pub struct ConnWatcher(pub Option<CancellationToken>);
+pub struct ConnWatcher(pub Option<CancellationToken>, pub Option<ConnectionInfo>);
 
 impl Resource for ConnWatcher {
     fn name(&self) -> std::borrow::Cow<str> {
@@ -14,3 +17,23 @@ impl ConnWatcher {
         self.0.clone()
     }
 }
+
+/// What the listener knows about the connection a request to the main worker
+/// arrived on. The forwarded request itself doesn't carry it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionInfo {
+    pub remote_addr: SocketAddr,
+    /// Address of the listener that accepted the connection.
+    pub local_addr: SocketAddr,
+    pub tls: Option<TlsInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsInfo {
+    /// The SNI sent by the client.
+    pub server_name: Option<String>,
+    pub protocol: Option<String>,
+    pub alpn_protocol: Option<String>,
+}
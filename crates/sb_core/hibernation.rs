@@ -0,0 +1,105 @@
+//! WebSocket connections the runtime holds on behalf of user workers, so that
+//! a worker doesn't have to stay alive while its connections are idle.
+//!
+//! A worker hands a connection over by answering the upgrade request with
+//! [`HIBERNATE_HEADER`] instead of upgrading it itself. The runtime then
+//! completes the handshake and sends every message that arrives to the
+//! service as a request of its own, booting a worker for it if there is none.
+//! Workers write to the connection through the ops below, by id.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use bounded_channel::{BoundedSender, SendError};
+use bytes::Bytes;
+use deno_core::error::{custom_error, AnyError};
+use deno_core::{op2, JsBuffer};
+use once_cell::sync::Lazy;
+
+/// Set on the response of a worker that wants the runtime to hold the
+/// connection.
+pub const HIBERNATE_HEADER: &str = "x-sb-websocket-hibernate";
+
+/// Carries the value the worker attached to the connection, as it gave it.
+pub const ATTACHMENT_HEADER: &str = "x-sb-websocket-attachment";
+
+/// Identifies the connection an event came from.
+pub const ID_HEADER: &str = "x-sb-websocket-id";
+
+/// Kind of event a request carries: `text`, `binary` or `close`.
+pub const EVENT_HEADER: &str = "x-sb-websocket-event";
+
+/// Status code the client closed the connection with.
+pub const CLOSE_CODE_HEADER: &str = "x-sb-websocket-close-code";
+
+/// Largest attachment a worker may give a connection, since it is sent along
+/// with every event.
+pub const MAX_ATTACHMENT_BYTES: usize = 4096;
+
+#[derive(Debug)]
+pub enum OutgoingMessage {
+    Text(String),
+    Binary(Bytes),
+    Close(u16, String),
+}
+
+static CONNECTIONS: Lazy<Mutex<HashMap<String, BoundedSender<OutgoingMessage>>>> =
+    Lazy::new(Mutex::default);
+
+pub fn register(id: String, tx: BoundedSender<OutgoingMessage>) {
+    CONNECTIONS.lock().unwrap().insert(id, tx);
+}
+
+pub fn unregister(id: &str) {
+    CONNECTIONS.lock().unwrap().remove(id);
+}
+
+/// How many connections the runtime is holding.
+pub fn count() -> usize {
+    CONNECTIONS.lock().unwrap().len()
+}
+
+fn send(id: &str, msg: OutgoingMessage) -> Result<(), AnyError> {
+    let maybe_tx = CONNECTIONS.lock().unwrap().get(id).cloned();
+
+    match maybe_tx.map(|tx| tx.send(msg)) {
+        Some(Ok(())) => Ok(()),
+
+        // NOTE: The client is not reading what is written to it fast enough.
+        Some(Err(SendError::Full(..))) => Err(custom_error(
+            "Busy",
+            format!("websocket connection is not keeping up: {}", id),
+        )),
+
+        Some(Err(SendError::Closed(_))) | None => Err(custom_error(
+            "NotFound",
+            format!("websocket connection is closed: {}", id),
+        )),
+    }
+}
+
+#[op2]
+pub fn op_hibernated_websocket_send_text(
+    #[string] id: &str,
+    #[string] data: String,
+) -> Result<(), AnyError> {
+    send(id, OutgoingMessage::Text(data))
+}
+
+#[op2]
+pub fn op_hibernated_websocket_send_binary(
+    #[string] id: &str,
+    #[buffer] data: JsBuffer,
+) -> Result<(), AnyError> {
+    send(id, OutgoingMessage::Binary(data.to_vec().into()))
+}
+
+/// Closes the connection. Closing one that is already closed does nothing.
+#[op2]
+pub fn op_hibernated_websocket_close(
+    #[string] id: &str,
+    #[smi] code: u16,
+    #[string] reason: String,
+) {
+    let _ = send(id, OutgoingMessage::Close(code, reason));
+}
@@ -0,0 +1,279 @@
+//! Hands requests to a worker as they are, instead of writing them out over a
+//! duplex stream only for the worker to parse them again. The worker answers
+//! through the same resource, and the response goes back without being
+//! serialized either.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::rc::Rc;
+
+use deno_core::error::{custom_error, AnyError};
+use deno_core::futures::stream::Peekable;
+use deno_core::futures::{Stream, StreamExt};
+use deno_core::{
+    op2, AsyncRefCell, AsyncResult, BufView, ByteString, CancelFuture, CancelHandle,
+    CancelTryFuture, JsBuffer, OpState, RcRef, Resource, ResourceId,
+};
+use http_utils::headers::{intern_name, to_pairs};
+use hyper::header::{HeaderValue, CONTENT_LENGTH, HOST};
+use hyper::{Body, HeaderMap, Request, Response};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+
+/// A request sent into a worker without going through HTTP.
+pub struct BridgedRequest {
+    pub req: Request<Body>,
+    pub res_tx: oneshot::Sender<Response<Body>>,
+    /// Cancelled once the client is gone.
+    pub conn_token: Option<CancellationToken>,
+}
+
+type BytesStream = Pin<Box<dyn Stream<Item = Result<bytes::Bytes, std::io::Error>> + Unpin>>;
+
+struct BridgedStreamResource {
+    headers: HeaderMap,
+    reader: AsyncRefCell<Peekable<BytesStream>>,
+    res_tx: RefCell<Option<oneshot::Sender<Response<Body>>>>,
+    res_body_tx: RefCell<Option<hyper::body::Sender>>,
+    conn_token: Option<CancellationToken>,
+    cancel: CancelHandle,
+}
+
+impl Resource for BridgedStreamResource {
+    fn name(&self) -> Cow<str> {
+        "httpBridgedStream".into()
+    }
+
+    fn read(self: Rc<Self>, limit: usize) -> AsyncResult<BufView> {
+        Box::pin(async move {
+            let reader = RcRef::map(&self, |r| &r.reader).borrow_mut().await;
+
+            let fut = async move {
+                let mut reader = Pin::new(reader);
+                loop {
+                    match reader.as_mut().peek_mut().await {
+                        Some(Ok(chunk)) if !chunk.is_empty() => {
+                            let len = std::cmp::min(limit, chunk.len());
+                            let chunk = chunk.split_to(len);
+                            break Ok(chunk.into());
+                        }
+                        // NOTE: `peek_mut()` returned `Some`, so `next()` is
+                        // ready with the peeked value.
+                        Some(_) => match reader.as_mut().next().await.unwrap() {
+                            Ok(chunk) => assert!(chunk.is_empty()),
+                            Err(err) => break Err(http_error(err.to_string())),
+                        },
+                        None => break Ok(BufView::empty()),
+                    }
+                }
+            };
+
+            let cancel_handle = RcRef::map(self, |r| &r.cancel);
+            fut.try_or_cancel(cancel_handle).await
+        })
+    }
+
+    fn close(self: Rc<Self>) {
+        self.cancel.cancel();
+
+        // NOTE: A body that wasn't shut down properly is cut off, so that the
+        // client doesn't take a truncated response as a complete one.
+        if let Some(tx) = self.res_body_tx.borrow_mut().take() {
+            tx.abort();
+        }
+    }
+}
+
+fn http_error(message: impl Into<Cow<'static, str>>) -> AnyError {
+    custom_error("Http", message)
+}
+
+/// Builds the url the way `deno_http` does for requests it parses itself.
+fn request_url(req: &Request<Body>) -> String {
+    let host = req
+        .uri()
+        .authority()
+        .map(|it| it.as_str())
+        .or_else(|| req.headers().get(HOST).and_then(|it| it.to_str().ok()))
+        .unwrap_or("0.0.0.0:9999");
+    let path = req.uri().path_and_query().map_or("/", |it| it.as_str());
+
+    format!("http://{}{}", host, path)
+}
+
+/// Waits for the next bridged request. Resolves to `null` if the worker
+/// doesn't get any.
+#[op2(async)]
+#[serde]
+pub async fn op_http_bridge_accept(
+    state: Rc<RefCell<OpState>>,
+) -> Result<Option<(ResourceId, String, String)>, AnyError> {
+    // NOTE: The receiver is taken out of the op state while waiting, so that
+    // the op state isn't kept borrowed.
+    let Some(rx) = state
+        .borrow_mut()
+        .try_take::<mpsc::Receiver<BridgedRequest>>()
+    else {
+        return Ok(None);
+    };
+
+    let mut rx = scopeguard::guard(rx, {
+        let state = state.clone();
+        move |rx| state.borrow_mut().put(rx)
+    });
+
+    let Some(BridgedRequest {
+        req,
+        res_tx,
+        conn_token,
+    }) = rx.recv().await
+    else {
+        return Ok(None);
+    };
+
+    drop(rx);
+
+    let method = req.method().to_string();
+    let url = request_url(&req);
+    let (parts, body) = req.into_parts();
+    let reader: BytesStream = Box::pin(
+        body.map(|r| r.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))),
+    );
+
+    let rid = state
+        .borrow_mut()
+        .resource_table
+        .add(BridgedStreamResource {
+            headers: parts.headers,
+            reader: AsyncRefCell::new(reader.peekable()),
+            res_tx: RefCell::new(Some(res_tx)),
+            res_body_tx: RefCell::default(),
+            conn_token,
+            cancel: CancelHandle::default(),
+        });
+
+    Ok(Some((rid, method, url)))
+}
+
+/// Resolves to `true` once the client of the request is gone, and to `false`
+/// if the request is done with first.
+#[op2(async)]
+pub async fn op_http_bridge_wait_closed(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+) -> Result<bool, AnyError> {
+    let stream = state
+        .borrow()
+        .resource_table
+        .get::<BridgedStreamResource>(rid)?;
+
+    let Some(token) = stream.conn_token.clone() else {
+        return Ok(false);
+    };
+
+    Ok(token
+        .cancelled_owned()
+        .or_cancel(RcRef::map(&stream, |r| &r.cancel))
+        .await
+        .is_ok())
+}
+
+#[op2]
+#[serde]
+pub fn op_http_bridge_headers(
+    state: &mut OpState,
+    #[smi] rid: ResourceId,
+) -> Result<Vec<(ByteString, ByteString)>, AnyError> {
+    let stream = state.resource_table.get::<BridgedStreamResource>(rid)?;
+
+    Ok(to_pairs(&stream.headers))
+}
+
+/// Sends the head of the response. Without `body`, the body is written with
+/// `op_http_bridge_write` and finished with `op_http_bridge_shutdown`.
+#[op2]
+pub fn op_http_bridge_respond(
+    state: &mut OpState,
+    #[smi] rid: ResourceId,
+    status: u32,
+    #[serde] headers: Vec<(ByteString, ByteString)>,
+    #[serde] body: Option<JsBuffer>,
+) -> Result<(), AnyError> {
+    let stream = state.resource_table.get::<BridgedStreamResource>(rid)?;
+    let res_tx = stream
+        .res_tx
+        .borrow_mut()
+        .take()
+        .ok_or_else(|| http_error("response already completed"))?;
+
+    let mut builder = Response::builder().status(u16::try_from(status)?);
+
+    if let Some(header_map) = builder.headers_mut() {
+        header_map.reserve(headers.len());
+
+        for (name, value) in headers.iter() {
+            header_map.append(intern_name(name)?, HeaderValue::from_bytes(value)?);
+        }
+    }
+
+    let body = match body {
+        Some(buf) => {
+            // NOTE: Replaces whatever length the worker put in the headers.
+            if let Some(headers) = builder.headers_mut() {
+                headers.insert(CONTENT_LENGTH, buf.len().into());
+            }
+
+            Body::from(bytes::Bytes::from(BufView::from(buf)))
+        }
+        None => {
+            let (tx, body) = Body::channel();
+
+            *stream.res_body_tx.borrow_mut() = Some(tx);
+            body
+        }
+    };
+
+    res_tx
+        .send(builder.body(body)?)
+        .map_err(|_| http_error("connection closed before message completed"))
+}
+
+#[op2(async)]
+pub async fn op_http_bridge_write(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+    #[buffer] buf: JsBuffer,
+) -> Result<(), AnyError> {
+    let stream = state
+        .borrow()
+        .resource_table
+        .get::<BridgedStreamResource>(rid)?;
+
+    let mut tx = stream
+        .res_body_tx
+        .borrow_mut()
+        .take()
+        .ok_or_else(|| http_error("response body is closed"))?;
+
+    tx.send_data(BufView::from(buf).into())
+        .or_cancel(RcRef::map(&stream, |r| &r.cancel))
+        .await?
+        .map_err(|_| http_error("connection closed before message completed"))?;
+
+    *stream.res_body_tx.borrow_mut() = Some(tx);
+
+    Ok(())
+}
+
+#[op2(fast)]
+pub fn op_http_bridge_shutdown(
+    state: &mut OpState,
+    #[smi] rid: ResourceId,
+) -> Result<(), AnyError> {
+    let stream = state.resource_table.get::<BridgedStreamResource>(rid)?;
+
+    stream.res_body_tx.borrow_mut().take();
+
+    Ok(())
+}
@@ -10,9 +10,13 @@ use deno_core::ResourceId;
 use deno_http::http_create_conn_resource;
 use tokio_util::sync::CancellationToken;
 
-use crate::conn_sync::ConnWatcher;
+use crate::conn_sync::{ConnWatcher, ConnectionInfo};
 use crate::http::DuplexStream2;
-use crate::net::TokioDuplexResource;
+use crate::http_bridge::{
+    op_http_bridge_accept, op_http_bridge_headers, op_http_bridge_respond, op_http_bridge_shutdown,
+    op_http_bridge_wait_closed, op_http_bridge_write,
+};
+use crate::net::{PendingDuplexStreams, TokioDuplexResource};
 
 #[op2]
 #[serde]
@@ -28,9 +32,17 @@ fn op_http_start(
             .map_err(|_| bad_resource("Duplex stream is currently in use"))?;
 
         let (id, stream) = resource.into_inner();
+
+        if let Some(pending) = state.try_borrow_mut::<PendingDuplexStreams>() {
+            pending.remove(id);
+        }
+
         let token = state
             .borrow_mut::<HashMap<usize, CancellationToken>>()
             .remove(&id);
+        let maybe_conn_info = state
+            .borrow_mut::<HashMap<usize, ConnectionInfo>>()
+            .remove(&id);
 
         // set a hardcoded address
         let addr: std::net::SocketAddr = "0.0.0.0:9999".parse().unwrap();
@@ -41,7 +53,9 @@ fn op_http_start(
             "http",
         )?;
 
-        let conn_watcher = state.resource_table.add(ConnWatcher(token));
+        let conn_watcher = state
+            .resource_table
+            .add(ConnWatcher(token, maybe_conn_info));
 
         return Ok((conn, conn_watcher));
     }
@@ -49,4 +63,29 @@ fn op_http_start(
     Err(bad_resource_id())
 }
 
-deno_core::extension!(sb_core_http_start, ops = [op_http_start]);
+/// Returns the connection info of the request tagged with `watcher_rid`, if
+/// the request came through the listener.
+#[op2]
+#[serde]
+fn op_http_request_context(
+    state: &mut OpState,
+    #[smi] watcher_rid: ResourceId,
+) -> Result<Option<ConnectionInfo>, AnyError> {
+    let watcher = state.resource_table.get::<ConnWatcher>(watcher_rid)?;
+
+    Ok(watcher.1.clone())
+}
+
+deno_core::extension!(
+    sb_core_http_start,
+    ops = [
+        op_http_start,
+        op_http_request_context,
+        op_http_bridge_accept,
+        op_http_bridge_wait_closed,
+        op_http_bridge_headers,
+        op_http_bridge_respond,
+        op_http_bridge_write,
+        op_http_bridge_shutdown
+    ]
+);
@@ -21,6 +21,12 @@ import * as request from 'ext:deno_fetch/23_request.js';
 import * as globalInterfaces from 'ext:deno_web/04_global_interfaces.js';
 import { SUPABASE_ENV } from 'ext:sb_env/env.js';
 import ai from 'ext:sb_ai/ai.js';
+import { db } from 'ext:sb_db/db.js';
+import { image as hostImage } from 'ext:sb_image/image.js';
+import { crypto as hostCrypto } from 'ext:sb_crypto/crypto.js';
+import { redis } from 'ext:sb_redis/redis.js';
+import { publish } from 'ext:sb_publish/publish.js';
+import { html } from 'ext:sb_html/html.js';
 import { registerErrors } from 'ext:sb_core_main_js/js/errors.js';
 import {
 	formatException,
@@ -38,6 +44,10 @@ import {
 	setUserAgent,
 } from 'ext:sb_core_main_js/js/navigator.js';
 
+import { installCoarseClock } from 'ext:sb_core_main_js/js/clock.js';
+import { installDeterministicMode } from 'ext:sb_core_main_js/js/deterministic.js';
+import { installStrictIsolation } from 'ext:sb_core_main_js/js/isolation.js';
+import { setWasiPermissions, WASI } from 'ext:sb_core_main_js/js/wasi.js';
 import { promiseRejectMacrotaskCallback } from 'ext:sb_core_main_js/js/promises.js';
 import { denoOverrides, fsVars } from 'ext:sb_core_main_js/js/denoOverrides.js';
 import * as performance from 'ext:deno_web/15_performance.js';
@@ -55,16 +65,25 @@ import { core, internals, primordials } from 'ext:core/mod.js';
 const ops = core.ops;
 const {
 	Error,
+	ArrayBufferIsView,
 	ArrayPrototypePop,
+	ArrayPrototypePush,
 	ArrayPrototypeShift,
 	ObjectAssign,
 	ObjectKeys,
 	ObjectDefineProperty,
 	ObjectDefineProperties,
+	JSONParse,
+	JSONStringify,
+	NumberParseInt,
 	ObjectSetPrototypeOf,
+	PromisePrototypeThen,
+	PromiseResolve,
+	SafePromiseAllSettled,
 	SafeSet,
 	StringPrototypeIncludes,
 	StringPrototypeSplit,
+	StringPrototypeToLowerCase,
 	StringPrototypeTrim
 } = primordials;
 
@@ -399,6 +418,252 @@ const PATCH_DENO_API_LIST = {
 	'memoryUsage': () => ops.op_runtime_memory_usage(),
 };
 
+function installTerminationHook() {
+	const promise = ops.op_wait_termination_request();
+
+	// NOTE: The hook must not keep the event loop alive by itself.
+	core.unrefOpPromise(promise);
+	PromisePrototypeThen(promise, async hasHook => {
+		if (!hasHook) {
+			return;
+		}
+
+		const pending = [];
+		const ev = new event.Event('beforeunload');
+
+		ObjectDefineProperty(ev, 'waitUntil', {
+			value: maybePromise => ArrayPrototypePush(pending, maybePromise),
+		});
+
+		try {
+			globalThis.dispatchEvent(ev);
+			await SafePromiseAllSettled(pending);
+		} finally {
+			ops.op_complete_termination_hook();
+		}
+	});
+}
+
+async function runLifecycleHook(cb, ...args) {
+	try {
+		await cb(...args);
+	} catch (err) {
+		console.error('lifecycle hook failed:', err);
+	}
+}
+
+function installLifecycleHooks() {
+	const bootCallbacks = [];
+	let isBooted = false;
+
+	const promise = ops.op_wait_worker_boot();
+
+	// NOTE: The hook must not keep the event loop alive by itself.
+	core.unrefOpPromise(promise);
+	PromisePrototypeThen(promise, hasBooted => {
+		if (!hasBooted) {
+			return;
+		}
+
+		isBooted = true;
+
+		while (bootCallbacks.length > 0) {
+			runLifecycleHook(ArrayPrototypeShift(bootCallbacks));
+		}
+	});
+
+	return {
+		// Called once the main module has been evaluated.
+		onBoot: cb => {
+			if (isBooted) {
+				runLifecycleHook(cb);
+			} else {
+				ArrayPrototypePush(bootCallbacks, cb);
+			}
+		},
+
+		// Called before the worker is terminated, within its termination
+		// grace period.
+		onShutdown: cb => {
+			globalThis.addEventListener('beforeunload', ev => ev.waitUntil(runLifecycleHook(cb)));
+		},
+
+		// Called with uncaught exceptions and unhandled rejections. It
+		// doesn't stop them from terminating the worker.
+		onError: cb => {
+			globalThis.addEventListener('error', ev => runLifecycleHook(cb, ev.error));
+			globalThis.addEventListener('unhandledrejection', ev => runLifecycleHook(cb, ev.reason));
+		},
+	};
+}
+
+function installStateCheckpoint() {
+	const checkpoint = ops.op_state_checkpoint_open();
+
+	let value;
+	let isDeclared = false;
+	let isDirty = false;
+	let pending = PromiseResolve();
+
+	// NOTE: Writes are chained, so an older snapshot never lands after a
+	// newer one.
+	const checkpointNow = () => {
+		if (checkpoint === null || !isDirty) {
+			return pending;
+		}
+
+		const data = JSONStringify(value);
+
+		isDirty = false;
+		pending = PromisePrototypeThen(pending, async () => {
+			try {
+				await ops.op_state_checkpoint_write(data);
+			} catch (err) {
+				isDirty = true;
+				console.error('state checkpoint failed:', err);
+			}
+		});
+
+		return pending;
+	};
+
+	return {
+		// Returns the state restored from the last checkpoint, or `initial`
+		// if there is none. Only one state object can be declared.
+		declare: initial => {
+			if (isDeclared) {
+				throw new TypeError('state is already declared');
+			}
+
+			isDeclared = true;
+			value = initial;
+
+			if (checkpoint === null) {
+				return value;
+			}
+
+			if (checkpoint.restored !== null) {
+				try {
+					value = JSONParse(checkpoint.restored);
+				} catch (err) {
+					console.error('could not restore state checkpoint:', err);
+				}
+			}
+
+			// NOTE: The timer must not keep the event loop alive by itself.
+			timers.unrefTimer(timers.setInterval(checkpointNow, checkpoint.intervalMs));
+			globalThis.addEventListener('beforeunload', ev => ev.waitUntil(checkpointNow()));
+
+			return value;
+		},
+
+		// Has the state written at the next checkpoint.
+		markDirty: () => {
+			isDirty = true;
+		},
+
+		// Writes the state now if it is dirty.
+		checkpoint: checkpointNow,
+	};
+}
+
+function installWebSockets() {
+	const messageCallbacks = [];
+	const closeCallbacks = [];
+
+	const connectionOf = (id, attachment) => ({
+		id,
+		attachment,
+		send: data => {
+			if (typeof data === 'string') {
+				ops.op_hibernated_websocket_send_text(id, data);
+			} else {
+				ops.op_hibernated_websocket_send_binary(
+					id,
+					ArrayBufferIsView(data) ? data : new Uint8Array(data),
+				);
+			}
+		},
+		close: (code = 1000, reason = '') => ops.op_hibernated_websocket_close(id, code, reason),
+	});
+
+	// NOTE: Events are only taken from the runtime, never from clients
+	// sending the same headers.
+	internals.dispatchWebSocketEvent = req => {
+		const event = req.headers.get('x-sb-websocket-event');
+
+		if (event === null || !ops.op_verify_request_signature([...req.headers])) {
+			return void 0;
+		}
+
+		const attachment = req.headers.get('x-sb-websocket-attachment');
+		const ws = connectionOf(
+			req.headers.get('x-sb-websocket-id'),
+			attachment === null ? null : JSONParse(decodeURIComponent(attachment)),
+		);
+
+		return (async () => {
+			try {
+				if (event === 'close') {
+					const code = NumberParseInt(req.headers.get('x-sb-websocket-close-code'), 10);
+					const reason = await req.text();
+
+					for (const cb of closeCallbacks) {
+						await cb(ws, code, reason);
+					}
+				} else {
+					const data = event === 'text' ? await req.text() : new Uint8Array(await req.arrayBuffer());
+
+					for (const cb of messageCallbacks) {
+						await cb(ws, data);
+					}
+				}
+
+				return new Response(null, { status: 204 });
+			} catch (err) {
+				console.error('websocket event handler failed:', err);
+				return new Response(null, { status: 500 });
+			}
+		})();
+	};
+
+	return {
+		// Has the runtime accept the websocket upgrade `req` and hold the
+		// connection, so the worker doesn't have to stay up while it is
+		// idle. `attachment` is passed along with every event of the
+		// connection.
+		accept: (req, { protocol, attachment } = {}) => {
+			if (StringPrototypeToLowerCase(req.headers.get('upgrade') ?? '') !== 'websocket') {
+				throw new TypeError('request is not a websocket upgrade');
+			}
+
+			const headers = { 'x-sb-websocket-hibernate': '1' };
+
+			if (protocol !== void 0) {
+				headers['sec-websocket-protocol'] = protocol;
+			}
+			if (attachment !== void 0) {
+				headers['x-sb-websocket-attachment'] = encodeURIComponent(JSONStringify(attachment));
+			}
+
+			return new Response(null, { status: 200, headers });
+		},
+
+		// Returns a connection held by the runtime by its id.
+		get: id => connectionOf(id, null),
+
+		// Called with each message received on a held connection.
+		onMessage: cb => {
+			ArrayPrototypePush(messageCallbacks, cb);
+		},
+
+		// Called once a held connection is closed by the client.
+		onClose: cb => {
+			ArrayPrototypePush(closeCallbacks, cb);
+		},
+	};
+}
+
 globalThis.bootstrapSBEdge = opts => {
 	// We should delete this after initialization,
 	// Deleting it during bootstrapping can backfire
@@ -419,7 +684,11 @@ globalThis.bootstrapSBEdge = opts => {
 		3: edgeRuntimeVersion,
 		4: denoVersion,
 		5: shouldDisableDeprecatedApiWarning,
-		6: shouldUseVerboseDeprecatedApiWarning
+		6: shouldUseVerboseDeprecatedApiWarning,
+		7: deterministicSeed,
+		8: timerResolutionMs,
+		9: wasiPermissions,
+		10: strictIsolation,
 	} = opts;
 
 	deprecatedApiWarningDisabled = shouldDisableDeprecatedApiWarning;
@@ -456,6 +725,13 @@ globalThis.bootstrapSBEdge = opts => {
 		get() {
 			return {
 				ai,
+				crypto: hostCrypto,
+				db,
+				html,
+				image: hostImage,
+				publish,
+				redis,
+				WASI,
 			};
 		},
 	});
@@ -500,6 +776,20 @@ globalThis.bootstrapSBEdge = opts => {
 	if (isUserWorker) {
 		delete globalThis.EdgeRuntime;
 
+		// NOTE: User workers only get to look at themselves.
+		ObjectDefineProperties(globalThis, {
+			EdgeRuntime: nonEnumerable({
+				getInfo: () => ops.op_user_worker_info(),
+				getSecret: (name) => ops.op_user_worker_secret(name),
+				// Tells whether the `x-sb-*` headers of a request were set by
+				// the runtime.
+				verifyRequest: (req) => ops.op_verify_request_signature([...req.headers]),
+				...installLifecycleHooks(),
+				state: installStateCheckpoint(),
+				webSockets: installWebSockets(),
+			}),
+		});
+
 		// override console
 		ObjectDefineProperties(globalThis, {
 			console: nonEnumerable(
@@ -520,6 +810,22 @@ globalThis.bootstrapSBEdge = opts => {
 				Deno[name] = value;
 			}
 		}
+
+		installTerminationHook();
+
+		// NOTE: The deterministic clocks are coarser than any resolution
+		// worth asking for, so they take precedence.
+		if (deterministicSeed !== null) {
+			installDeterministicMode(deterministicSeed);
+		} else if (timerResolutionMs > 0) {
+			installCoarseClock(timerResolutionMs);
+		}
+
+		setWasiPermissions(wasiPermissions);
+
+		if (strictIsolation) {
+			installStrictIsolation();
+		}
 	}
 
 	if (isEventsWorker) {
@@ -0,0 +1,171 @@
+import { primordials } from 'ext:core/mod.js';
+
+const {
+	DateNow,
+	MathFloor,
+	ObjectDefineProperty,
+	ObjectGetPrototypeOf,
+	ReflectApply,
+	ReflectConstruct,
+} = primordials;
+
+function defineHidden(target, key, value) {
+	ObjectDefineProperty(target, key, {
+		value,
+		writable: true,
+		enumerable: false,
+		configurable: true,
+	});
+}
+
+/**
+ * Rounds `value` down to a multiple of `resolution`.
+ * @param {number} value
+ * @param {number} resolution
+ */
+function coarsen(value, resolution) {
+	return MathFloor(value / resolution) * resolution;
+}
+
+/**
+ * Fills in the default start time of a performance mark, which would
+ * otherwise be taken from the real clock.
+ * @param {unknown} options
+ * @param {() => number} startTime
+ */
+function markOptions(options, startTime) {
+	if (options?.startTime !== undefined) {
+		return options;
+	}
+
+	return { ...options, startTime: startTime() };
+}
+
+/**
+ * Fills in the default end of `performance.measure`, which would otherwise be
+ * taken from the real clock.
+ * @param {unknown} startOrMeasureOptions
+ * @param {() => number} end
+ */
+function measureOptions(startOrMeasureOptions, end) {
+	if (
+		typeof startOrMeasureOptions === 'object' && startOrMeasureOptions !== null
+	) {
+		if (
+			startOrMeasureOptions.end !== undefined ||
+			startOrMeasureOptions.duration !== undefined
+		) {
+			return startOrMeasureOptions;
+		}
+
+		return { ...startOrMeasureOptions, end: end() };
+	}
+
+	return { start: startOrMeasureOptions, end: end() };
+}
+
+/**
+ * Replaces the clocks observable by user code. `wallNow` backs `Date.now` and
+ * `new Date()`, `monotonicNow` backs `performance.now` and the default
+ * timestamps of performance marks and measures. Both receive the real
+ * monotonic time, in milliseconds since the worker started.
+ * @param {(real: number) => number} wallNow
+ * @param {(real: number) => number} monotonicNow
+ */
+function installClock(wallNow, monotonicNow) {
+	const OriginalDate = globalThis.Date;
+	const OriginalPerformanceMark = globalThis.PerformanceMark;
+	const performance = globalThis.performance;
+	const Performance = ObjectGetPrototypeOf(performance);
+	const realNow = Performance.now;
+	const realMark = Performance.mark;
+	const realMeasure = Performance.measure;
+	const real = () => ReflectApply(realNow, performance, []);
+	const now = () => wallNow(real());
+	const monotonic = () => monotonicNow(real());
+
+	function Date(...args) {
+		if (new.target === undefined) {
+			return new OriginalDate(now()).toString();
+		}
+
+		if (args.length === 0) {
+			return ReflectConstruct(OriginalDate, [now()], new.target);
+		}
+
+		return ReflectConstruct(OriginalDate, args, new.target);
+	}
+
+	// NOTE: The original constructor must not be reachable from the
+	// replacement, e.g. through its prototype chain, so the static methods are
+	// copied over instead of inherited.
+	Date.prototype = OriginalDate.prototype;
+
+	ObjectDefineProperty(Date, 'length', { value: 7, configurable: true });
+	defineHidden(Date, 'now', now);
+	defineHidden(Date, 'parse', OriginalDate.parse);
+	defineHidden(Date, 'UTC', OriginalDate.UTC);
+	defineHidden(OriginalDate, 'now', now);
+	defineHidden(OriginalDate.prototype, 'constructor', Date);
+	defineHidden(globalThis, 'Date', Date);
+
+	function PerformanceMark(name, options = {}) {
+		if (new.target === undefined) {
+			return OriginalPerformanceMark(name, options);
+		}
+
+		return ReflectConstruct(
+			OriginalPerformanceMark,
+			[name, markOptions(options, monotonic)],
+			new.target,
+		);
+	}
+
+	PerformanceMark.prototype = OriginalPerformanceMark.prototype;
+
+	defineHidden(OriginalPerformanceMark.prototype, 'constructor', PerformanceMark);
+	defineHidden(globalThis, 'PerformanceMark', PerformanceMark);
+
+	// NOTE: These are patched on the prototype rather than the instance, so
+	// that `Performance.prototype.now.call(performance)` can't reach the real
+	// clock either.
+	defineHidden(Performance, 'now', function now() {
+		ReflectApply(realNow, this, []);
+		return monotonic();
+	});
+	defineHidden(Performance, 'mark', function mark(name, options = {}) {
+		return ReflectApply(realMark, this, [name, markOptions(options, monotonic)]);
+	});
+	defineHidden(
+		Performance,
+		'measure',
+		function measure(name, startOrMeasureOptions, endMark) {
+			if (endMark !== undefined) {
+				return ReflectApply(realMeasure, this, [
+					name,
+					startOrMeasureOptions,
+					endMark,
+				]);
+			}
+
+			return ReflectApply(realMeasure, this, [
+				name,
+				measureOptions(startOrMeasureOptions, monotonic),
+			]);
+		},
+	);
+}
+
+/**
+ * Reduces the resolution of `Date.now`, `new Date()` and `performance.now`
+ * so that user code can't build a precise timer out of them.
+ * @param {number} resolutionMs
+ */
+function installCoarseClock(resolutionMs) {
+	installClock(
+		() => coarsen(DateNow(), resolutionMs),
+		real => coarsen(real, resolutionMs),
+	);
+}
+
+export { coarsen, installClock, installCoarseClock };
@@ -18,6 +18,7 @@ const osCallsVars = {
 	systemMemoryInfo: osCalls.systemMemoryInfo,
 	consoleSize: osCalls.consoleSize,
 	Command: osCalls.command,
+	dlopen: osCalls.dlopen,
 	version: osCalls.version,
 	networkInterfaces: osCalls.networkInterfaces,
 	execPath: () => '/bin/deno', // TODO: use a non-hardcoded path
@@ -0,0 +1,66 @@
+import { primordials } from 'ext:core/mod.js';
+import { coarsen, installClock } from 'ext:sb_core_main_js/js/clock.js';
+
+const {
+	MathFloor,
+	MathImul,
+	ObjectDefineProperty,
+} = primordials;
+
+// 2024-01-01T00:00:00.000Z
+const CLOCK_ORIGIN_MS = 1704067200000;
+
+// The clocks only advance in steps of this size, so that a test which runs a
+// little faster or slower still observes the same timestamps.
+const CLOCK_RESOLUTION_MS = 1000;
+
+/**
+ * Mulberry32. Small and fast, and good enough for code that only needs
+ * `Math.random` to be repeatable.
+ * @param {number} seed
+ */
+function createPrng(seed) {
+	let state = seed >>> 0;
+
+	return () => {
+		state = (state + 0x6d2b79f5) >>> 0;
+
+		let t = state;
+
+		t = MathImul(t ^ (t >>> 15), t | 1);
+		t ^= t + MathImul(t ^ (t >>> 7), t | 61);
+
+		return ((t ^ (t >>> 14)) >>> 0) / 4294967296;
+	};
+}
+
+/**
+ * Makes `Math.random`, `Date` and `performance.now` repeatable across runs.
+ * The WebCrypto RNG is seeded on the Rust side.
+ * @param {number} seed
+ */
+function installDeterministicMode(seed) {
+	// NOTE: Fold the high bits in so that seeds differing only above 2^32
+	// still produce different sequences.
+	const random = createPrng(seed ^ MathFloor(seed / 4294967296));
+
+	ObjectDefineProperty(globalThis.Math, 'random', {
+		value: random,
+		writable: true,
+		enumerable: false,
+		configurable: true,
+	});
+
+	let startedAt = null;
+	const elapsed = real => {
+		startedAt ??= real;
+		return coarsen(real - startedAt, CLOCK_RESOLUTION_MS);
+	};
+
+	installClock(
+		real => CLOCK_ORIGIN_MS + elapsed(real),
+		real => elapsed(real),
+	);
+}
+
+export { installDeterministicMode };
@@ -27,6 +27,7 @@ const buildDomErrorClass = (name) => class extends DOMException {
 const InvalidWorkerResponse = buildErrorClass("InvalidWorkerResponse");
 const InvalidWorkerCreation = buildErrorClass("InvalidWorkerCreation");
 const WorkerRequestCancelled = buildErrorClass("WorkerRequestCancelled");
+const WorkerBootThrottled = buildErrorClass("WorkerBootThrottled");
 const NotFound = buildErrorClass("NotFound");
 const PermissionDenied = buildErrorClass("PermissionDenied");
 const ConnectionRefused = buildErrorClass("ConnectionRefused");
@@ -45,6 +46,7 @@ const UnexpectedEof = buildErrorClass("UnexpectedEof");
 const Http = buildErrorClass("Http");
 const Busy = buildErrorClass("Busy");
 const NotSupported = buildErrorClass("NotSupported");
+const RateLimited = buildErrorClass("RateLimited");
 const DOMExceptionOperationError = buildDomErrorClass("OperationError");
 const DOMExceptionQuotaExceededError = buildDomErrorClass("QuotaExceededError");
 const DOMExceptionNotSupportedError = buildDomErrorClass("NotSupported");
@@ -57,6 +59,7 @@ function registerErrors() {
     core.registerErrorClass("InvalidWorkerResponse", InvalidWorkerResponse);
     core.registerErrorClass("InvalidWorkerCreation", InvalidWorkerCreation);
     core.registerErrorClass("WorkerRequestCancelled", WorkerRequestCancelled);
+    core.registerErrorClass("WorkerBootThrottled", WorkerBootThrottled);
     core.registerErrorClass("NotFound", NotFound);
     core.registerErrorClass("PermissionDenied", PermissionDenied);
     core.registerErrorClass("ConnectionRefused", ConnectionRefused);
@@ -76,6 +79,7 @@ function registerErrors() {
     core.registerErrorClass("Http", Http);
     core.registerErrorClass("Busy", Busy);
     core.registerErrorClass("NotSupported", NotSupported);
+    core.registerErrorClass("RateLimited", RateLimited);
     core.registerErrorClass(
         "DOMExceptionOperationError",
         DOMExceptionOperationError
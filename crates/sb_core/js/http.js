@@ -1,14 +1,34 @@
 import "ext:deno_http/01_http.js";
 
 import { core, internals, primordials } from "ext:core/mod.js";
-import { fromInnerResponse, newInnerResponse } from "ext:deno_fetch/23_response.js";
-import { RequestPrototype } from "ext:deno_fetch/23_request.js";
+import { InnerBody } from "ext:deno_fetch/22_body.js";
+import {
+	fromInnerResponse,
+	newInnerResponse,
+	ResponsePrototype,
+	toInnerResponse,
+} from "ext:deno_fetch/23_response.js";
+import {
+	fromInnerRequest,
+	newInnerRequest,
+	RequestPrototype,
+} from "ext:deno_fetch/23_request.js";
+import { AbortController } from "ext:deno_web/03_abort_signal.js";
+import {
+	readableStreamForRid,
+	ReadableStreamPrototype,
+} from "ext:deno_web/06_streams.js";
 import { HttpConn, upgradeWebSocket } from "ext:sb_core_main_js/js/01_http.js";
 
 const ops = core.ops;
 
 const { internalRidSymbol } = core;
-const { ObjectPrototypeIsPrototypeOf } = primordials;
+const {
+	ObjectPrototypeIsPrototypeOf,
+	PromisePrototypeThen,
+	TypedArrayPrototypeGetSymbolToStringTag,
+	TypeError,
+} = primordials;
 
 const HttpConnPrototypeNextRequest = HttpConn.prototype.nextRequest;
 const HttpConnPrototypeClose = HttpConn.prototype.close;
@@ -134,6 +154,10 @@ async function serve(args1, args2) {
 			port: options.port
 		});
 
+		// NOTE: Requests the runtime hands over directly don't come through
+		// the listener.
+		acceptBridgedRequests(options);
+
 		for await (const conn of listener) {
 			handleHttp(conn);
 		}
@@ -159,7 +183,11 @@ async function respond(requestEvent, httpConn, options) {
 	/** @type {Response} */
 	let response;
 	try {
-		response = await options["handler"](requestEvent.request, {
+		// NOTE: Events of websocket connections held by the runtime are
+		// handled by the callbacks the worker registered, not by its handler.
+		const wsEvent = internals.dispatchWebSocketEvent?.(requestEvent.request);
+
+		response = wsEvent !== void 0 ? await wsEvent : await options["handler"](requestEvent.request, {
 			remoteAddr: {
 				port: options.port,
 				hostname: options.hostname,
@@ -213,6 +241,148 @@ async function respond(requestEvent, httpConn, options) {
 	}
 }
 
+async function acceptBridgedRequests(options) {
+	while (true) {
+		let next;
+
+		try {
+			next = await ops.op_http_bridge_accept();
+		} catch {
+			return;
+		}
+
+		if (next === null) {
+			return;
+		}
+
+		respond(bridgedRequestEvent(next), null, options);
+	}
+}
+
+function bridgedRequestEvent(next) {
+	const { 0: rid, 1: method, 2: url } = next;
+
+	let body = null;
+	if (method !== "GET" && method !== "HEAD") {
+		body = readableStreamForRid(rid, false);
+	}
+
+	const innerRequest = newInnerRequest(
+		method,
+		url,
+		() => ops.op_http_bridge_headers(rid),
+		body !== null ? new InnerBody(body) : null,
+		false,
+	);
+
+	const abortController = new AbortController();
+	const request = fromInnerRequest(
+		innerRequest,
+		abortController.signal,
+		"immutable",
+		false,
+	);
+
+	// The signal of the request is aborted once its client goes away.
+	PromisePrototypeThen(
+		ops.op_http_bridge_wait_closed(rid),
+		(closed) => {
+			if (closed) {
+				abortController.abort();
+			}
+		},
+		() => {},
+	);
+
+	return {
+		request,
+		respondWith: (resp) => respondBridged(rid, abortController, resp),
+	};
+}
+
+async function respondBridged(rid, abortController, resp) {
+	try {
+		resp = await resp;
+
+		if (!ObjectPrototypeIsPrototypeOf(ResponsePrototype, resp)) {
+			throw new TypeError(
+				"First argument to respondWith must be a Response or a promise resolving to a Response.",
+			);
+		}
+
+		const innerResp = toInnerResponse(resp);
+
+		/** @type {ReadableStream<Uint8Array> | Uint8Array | string | null} */
+		let respBody = null;
+		if (innerResp.body !== null) {
+			if (innerResp.body.unusable()) {
+				throw new TypeError("Body is unusable.");
+			}
+			if (
+				ObjectPrototypeIsPrototypeOf(
+					ReadableStreamPrototype,
+					innerResp.body.streamOrStatic,
+				)
+			) {
+				respBody = innerResp.body.stream;
+			} else {
+				innerResp.body.streamOrStatic.consumed = true;
+				respBody = innerResp.body.streamOrStatic.body;
+			}
+		} else {
+			respBody = new Uint8Array(0);
+		}
+
+		if (typeof respBody === "string") {
+			respBody = core.encode(respBody);
+		}
+
+		const isStreamingResponseBody =
+			TypedArrayPrototypeGetSymbolToStringTag(respBody) !== "Uint8Array";
+
+		try {
+			ops.op_http_bridge_respond(
+				rid,
+				innerResp.status ?? 200,
+				innerResp.headerList,
+				isStreamingResponseBody ? null : respBody,
+			);
+		} catch (error) {
+			if (isStreamingResponseBody) {
+				await respBody.cancel(error);
+			}
+			throw error;
+		}
+
+		if (isStreamingResponseBody) {
+			const reader = respBody.getReader();
+
+			while (true) {
+				const { value, done } = await reader.read();
+				if (done) break;
+				if (TypedArrayPrototypeGetSymbolToStringTag(value) !== "Uint8Array") {
+					const error = new TypeError("Value not a Uint8Array");
+					await reader.cancel(error);
+					throw error;
+				}
+				try {
+					await ops.op_http_bridge_write(rid, value);
+				} catch (error) {
+					await reader.cancel(error);
+					throw error;
+				}
+			}
+
+			ops.op_http_bridge_shutdown(rid);
+		}
+	} catch (error) {
+		abortController.abort(error);
+		throw error;
+	} finally {
+		core.tryClose(rid);
+	}
+}
+
 function closeHttpConn(httpConn) {
 	try {
 		httpConn.close();
@@ -0,0 +1,201 @@
+import { primordials } from 'ext:core/mod.js';
+
+const {
+	ArrayBufferIsView,
+	ObjectDefineProperty,
+	ObjectSetPrototypeOf,
+	ReflectApply,
+	ReflectConstruct,
+	TypeError,
+	TypedArrayPrototypeGetBuffer,
+	TypedArrayPrototypeGetByteLength,
+	TypedArrayPrototypeGetByteOffset,
+	Uint8Array,
+} = primordials;
+
+const SECTION_IMPORT = 2;
+const SECTION_MEMORY = 5;
+const EXTERNAL_KIND_MEMORY = 2;
+const LIMITS_FLAG_SHARED = 0x02;
+
+function sharedMemoryError() {
+	return new TypeError('shared WebAssembly memory is disabled in strict isolation mode');
+}
+
+function defineHidden(target, key, value) {
+	ObjectDefineProperty(target, key, {
+		value,
+		writable: true,
+		enumerable: false,
+		configurable: true,
+	});
+}
+
+/** @param {BufferSource} source */
+function toBytes(source) {
+	if (ArrayBufferIsView(source)) {
+		return new Uint8Array(
+			TypedArrayPrototypeGetBuffer(source),
+			TypedArrayPrototypeGetByteOffset(source),
+			TypedArrayPrototypeGetByteLength(source),
+		);
+	}
+
+	return new Uint8Array(source);
+}
+
+/**
+ * Whether the WebAssembly module in `source` defines or imports a shared
+ * memory, which is what its threads are built on. Malformed modules are
+ * left for the engine to reject.
+ * @param {BufferSource} source
+ */
+function hasSharedMemory(source) {
+	const bytes = toBytes(source);
+	let pos = 8;
+
+	const readU32 = () => {
+		let result = 0;
+		let shift = 0;
+
+		while (pos < bytes.length) {
+			const byte = bytes[pos++];
+
+			result += (byte & 0x7f) * 2 ** shift;
+			shift += 7;
+
+			if ((byte & 0x80) === 0) {
+				break;
+			}
+		}
+
+		return result;
+	};
+
+	const skipName = () => {
+		pos += readU32();
+	};
+
+	const isSharedLimits = () => {
+		const flags = bytes[pos++];
+
+		readU32();
+
+		if (flags & 0x01) {
+			readU32();
+		}
+
+		return (flags & LIMITS_FLAG_SHARED) !== 0;
+	};
+
+	while (pos < bytes.length) {
+		const id = bytes[pos++];
+		const size = readU32();
+		const end = pos + size;
+
+		if (id === SECTION_MEMORY) {
+			for (let count = readU32(); count > 0 && pos < end; count--) {
+				if (isSharedLimits()) {
+					return true;
+				}
+			}
+		} else if (id === SECTION_IMPORT) {
+			for (let count = readU32(); count > 0 && pos < end; count--) {
+				skipName();
+				skipName();
+
+				const kind = bytes[pos++];
+
+				if (kind === EXTERNAL_KIND_MEMORY) {
+					if (isSharedLimits()) {
+						return true;
+					}
+				} else if (kind === 0) {
+					// function: type index
+					readU32();
+				} else if (kind === 1) {
+					// table: element type and limits
+					pos++;
+					isSharedLimits();
+				} else if (kind === 3) {
+					// global: value type and mutability
+					pos += 2;
+				} else {
+					// Unknown kinds end the scan of the section.
+					break;
+				}
+			}
+		}
+
+		pos = end;
+	}
+
+	return false;
+}
+
+function ensureNoSharedMemory(source) {
+	if (hasSharedMemory(source)) {
+		throw sharedMemoryError();
+	}
+}
+
+/**
+ * Takes away what user code could still build precise timers out of. Every
+ * worker already goes without `SharedArrayBuffer` and can't create a shared
+ * `WebAssembly.Memory`, so this refuses the WebAssembly modules that declare
+ * a shared memory (the base of WebAssembly threads) and removes the GC
+ * observability of `FinalizationRegistry` and `WeakRef`.
+ */
+function installStrictIsolation() {
+	delete globalThis.FinalizationRegistry;
+	delete globalThis.WeakRef;
+
+	const wasm = globalThis.WebAssembly;
+	const OriginalModule = wasm.Module;
+	const { compile, instantiate, validate } = wasm;
+
+	function Module(source) {
+		if (new.target === undefined) {
+			throw new TypeError("Constructor WebAssembly.Module requires 'new'");
+		}
+
+		ensureNoSharedMemory(source);
+
+		return ReflectConstruct(OriginalModule, [source], new.target);
+	}
+
+	Module.prototype = OriginalModule.prototype;
+
+	ObjectSetPrototypeOf(Module, OriginalModule);
+	defineHidden(OriginalModule.prototype, 'constructor', Module);
+	defineHidden(wasm, 'Module', Module);
+
+	defineHidden(wasm, 'validate', (source) => {
+		return !hasSharedMemory(source) && ReflectApply(validate, wasm, [source]);
+	});
+
+	defineHidden(wasm, 'compile', async (source) => {
+		ensureNoSharedMemory(source);
+		return await ReflectApply(compile, wasm, [source]);
+	});
+
+	defineHidden(wasm, 'instantiate', async (source, imports) => {
+		// NOTE: An already compiled module went through `compile` or
+		// `Module` above.
+		if (!(source instanceof OriginalModule)) {
+			ensureNoSharedMemory(source);
+		}
+
+		return await ReflectApply(instantiate, wasm, [source, imports]);
+	});
+
+	defineHidden(wasm, 'compileStreaming', async (source) => {
+		return await wasm.compile(await (await source).arrayBuffer());
+	});
+
+	defineHidden(wasm, 'instantiateStreaming', async (source, imports) => {
+		return await wasm.instantiate(await (await source).arrayBuffer(), imports);
+	});
+}
+
+export { hasSharedMemory, installStrictIsolation };
@@ -1,5 +1,5 @@
 import { SUPABASE_USER_WORKERS } from 'ext:sb_user_workers/user_workers.js';
-import { applySupabaseTag } from 'ext:sb_core_main_js/js/http.js';
+import { applySupabaseTag, getSupabaseTag } from 'ext:sb_core_main_js/js/http.js';
 import { core } from 'ext:core/mod.js';
 
 const ops = core.ops;
@@ -9,8 +9,22 @@ Object.defineProperty(globalThis, 'EdgeRuntime', {
 		return {
 			userWorkers: SUPABASE_USER_WORKERS,
 			getRuntimeMetrics: () => /* async */ ops.op_runtime_metrics(),
+			getPoolConfig: () => /* async */ ops.op_user_worker_pool_config(),
+			getUsage: () => /* async */ ops.op_user_worker_pool_usage(),
+			drain: () => /* async */ ops.op_runtime_drain(),
+			version: {
+				edgeRuntime: globalThis.SUPABASE_VERSION,
+				deno: globalThis.DENO_VERSION,
+			},
 			applySupabaseTag: (src, dest) => applySupabaseTag(src, dest),
+			getRequestContext: (req) => {
+				const tag = getSupabaseTag(req);
+				return tag ? ops.op_http_request_context(tag.watcherRid) : null;
+			},
 			systemMemoryInfo: () => ops.op_system_memory_info(),
+			geoip: {
+				lookup: (ip) => ops.op_geoip_lookup(String(ip)),
+			},
 		};
 	},
 	configurable: true,
@@ -0,0 +1,711 @@
+import * as fs from 'ext:deno_fs/30_fs.js';
+import { primordials } from 'ext:core/mod.js';
+
+const {
+	ArrayPrototypeJoin,
+	ArrayPrototypePop,
+	ArrayPrototypePush,
+	BigInt,
+	DataViewPrototypeGetUint32,
+	DataViewPrototypeSetBigUint64,
+	DataViewPrototypeSetUint16,
+	DataViewPrototypeSetUint32,
+	DataViewPrototypeSetUint8,
+	Error,
+	MathMin,
+	MathTrunc,
+	Number,
+	ObjectEntries,
+	ObjectKeys,
+	Proxy,
+	StringPrototypeReplace,
+	StringPrototypeSplit,
+	TypedArrayPrototypeSet,
+	TypedArrayPrototypeSubarray,
+	TypeError,
+	Uint8Array,
+} = primordials;
+
+const ERRNO_SUCCESS = 0;
+const ERRNO_BADF = 8;
+const ERRNO_INVAL = 28;
+const ERRNO_ISDIR = 31;
+const ERRNO_NOENT = 44;
+const ERRNO_NOSYS = 52;
+const ERRNO_NOTSUP = 58;
+const ERRNO_NOTCAPABLE = 76;
+
+const CLOCKID_REALTIME = 0;
+const CLOCKID_MONOTONIC = 1;
+const CLOCKID_PROCESS_CPUTIME_ID = 2;
+const CLOCKID_THREAD_CPUTIME_ID = 3;
+
+const FILETYPE_CHARACTER_DEVICE = 2;
+const FILETYPE_DIRECTORY = 3;
+const FILETYPE_REGULAR_FILE = 4;
+
+const OFLAGS_CREAT = 1;
+const OFLAGS_DIRECTORY = 2;
+const OFLAGS_EXCL = 4;
+const OFLAGS_TRUNC = 8;
+
+const RIGHTS_FD_WRITE = 1n << 6n;
+
+const WHENCE_SET = 0;
+const WHENCE_CUR = 1;
+const WHENCE_END = 2;
+
+const RANDOM_GET_CHUNK_SIZE = 65536;
+
+// NOTE: Workers that aren't restricted by a user worker config (the main and
+// event workers) get every capability.
+let permissions = {
+	fs: true,
+	clock: true,
+	random: true,
+};
+
+/**
+ * @param {{ fs: boolean, clock: boolean, random: boolean }} perms
+ */
+function setWasiPermissions(perms) {
+	permissions = perms;
+}
+
+class ExitStatus {
+	code;
+
+	constructor(code) {
+		this.code = code;
+	}
+}
+
+const encoder = new TextEncoder();
+const decoder = new TextDecoder();
+
+/**
+ * Resolves `path` against `root` without letting it escape `root`. Returns
+ * `null` if it would.
+ * @param {string} root
+ * @param {string} path
+ */
+function resolveWithin(root, path) {
+	const segments = [];
+
+	for (const segment of StringPrototypeSplit(path, '/')) {
+		if (segment === '' || segment === '.') {
+			continue;
+		}
+
+		if (segment === '..') {
+			if (segments.length === 0) {
+				return null;
+			}
+
+			ArrayPrototypePop(segments);
+			continue;
+		}
+
+		ArrayPrototypePush(segments, segment);
+	}
+
+	return segments.length === 0
+		? root
+		: `${root}/${ArrayPrototypeJoin(segments, '/')}`;
+}
+
+/**
+ * A WASI (snapshot preview1) host for modules instantiated in this worker.
+ * Files are exposed read-only through `preopens`, and standard output and
+ * error are forwarded to the console. Which capabilities are available is
+ * decided by the permission config of the worker.
+ *
+ * ```js
+ * const wasi = new Supabase.WASI({ args: ['main'], preopens: { '/data': './assets' } });
+ * const { instance } = await WebAssembly.instantiate(bytes, {
+ *   wasi_snapshot_preview1: wasi.exports,
+ * });
+ *
+ * const exitCode = wasi.start(instance);
+ * ```
+ */
+class WASI {
+	#args;
+	#env;
+	#fds = [];
+	#memory = null;
+	#output = {
+		stdout: { decoder: new TextDecoder(), pending: '', print: line => console.log(line) },
+		stderr: { decoder: new TextDecoder(), pending: '', print: line => console.error(line) },
+	};
+	#started = false;
+
+	exports;
+
+	constructor(options = {}) {
+		const { args = [], env = {}, preopens = {} } = options;
+
+		if (!permissions.fs && ObjectKeys(preopens).length > 0) {
+			throw new TypeError('Preopening directories is not permitted for this worker');
+		}
+
+		this.#args = args.map(it => encoder.encode(`${it}\0`));
+		this.#env = ObjectEntries(env).map(([k, v]) => encoder.encode(`${k}=${v}\0`));
+
+		ArrayPrototypePush(
+			this.#fds,
+			{ type: FILETYPE_CHARACTER_DEVICE },
+			{ type: FILETYPE_CHARACTER_DEVICE, stream: 'stdout' },
+			{ type: FILETYPE_CHARACTER_DEVICE, stream: 'stderr' },
+		);
+
+		for (const [guestPath, hostPath] of ObjectEntries(preopens)) {
+			ArrayPrototypePush(this.#fds, {
+				type: FILETYPE_DIRECTORY,
+				preopen: encoder.encode(guestPath),
+				hostPath: StringPrototypeReplace(hostPath, /\/+$/, '') || '/',
+			});
+		}
+
+		const imports = this.#createImports();
+
+		this.exports = new Proxy(imports, {
+			get(target, name) {
+				return target[name] ?? (() => ERRNO_NOSYS);
+			},
+		});
+	}
+
+	/**
+	 * Runs the `_start` export of a command module and returns its exit code.
+	 */
+	start(instance) {
+		this.#bind(instance);
+
+		const { _start } = instance.exports;
+
+		if (typeof _start !== 'function') {
+			throw new TypeError('WebAssembly.Instance export _start must be a function');
+		}
+
+		try {
+			_start();
+			return 0;
+		} catch (err) {
+			if (err instanceof ExitStatus) {
+				return err.code;
+			}
+
+			throw err;
+		} finally {
+			this.#flush();
+		}
+	}
+
+	/**
+	 * Prepares a reactor module, calling its `_initialize` export if any.
+	 */
+	initialize(instance) {
+		this.#bind(instance);
+
+		const { _initialize } = instance.exports;
+
+		if (typeof _initialize === 'function') {
+			_initialize();
+		}
+	}
+
+	#bind(instance) {
+		if (this.#started) {
+			throw new Error('WASI instance has already been started');
+		}
+
+		const { memory } = instance.exports;
+
+		if (!(memory instanceof WebAssembly.Memory)) {
+			throw new TypeError('WebAssembly.Instance export memory must be a WebAssembly.Memory');
+		}
+
+		this.#started = true;
+		this.#memory = memory;
+	}
+
+	#view() {
+		return new DataView(this.#memory.buffer);
+	}
+
+	#bytes() {
+		return new Uint8Array(this.#memory.buffer);
+	}
+
+	#write(stream, chunk) {
+		const output = this.#output[stream];
+		const lines = StringPrototypeSplit(
+			output.pending + output.decoder.decode(chunk, { stream: true }),
+			'\n',
+		);
+
+		output.pending = ArrayPrototypePop(lines);
+
+		for (const line of lines) {
+			output.print(line);
+		}
+	}
+
+	#flush() {
+		for (const output of [this.#output.stdout, this.#output.stderr]) {
+			const rest = output.pending + output.decoder.decode();
+
+			output.pending = '';
+
+			if (rest !== '') {
+				output.print(rest);
+			}
+		}
+	}
+
+	#readIovs(iovsPtr, iovsLen, data, offset) {
+		const view = this.#view();
+		const bytes = this.#bytes();
+		let read = 0;
+
+		for (let i = 0; i < iovsLen; i++) {
+			const ptr = DataViewPrototypeGetUint32(view, iovsPtr + i * 8, true);
+			const len = DataViewPrototypeGetUint32(view, iovsPtr + i * 8 + 4, true);
+			const chunk = TypedArrayPrototypeSubarray(
+				data,
+				offset + read,
+				MathMin(data.length, offset + read + len),
+			);
+
+			TypedArrayPrototypeSet(bytes, chunk, ptr);
+			read += chunk.length;
+
+			if (chunk.length < len) {
+				break;
+			}
+		}
+
+		return read;
+	}
+
+	#writeStrings(items, ptrsPtr, bufPtr) {
+		const view = this.#view();
+		const bytes = this.#bytes();
+
+		for (const item of items) {
+			DataViewPrototypeSetUint32(view, ptrsPtr, bufPtr, true);
+			TypedArrayPrototypeSet(bytes, item, bufPtr);
+
+			ptrsPtr += 4;
+			bufPtr += item.length;
+		}
+
+		return ERRNO_SUCCESS;
+	}
+
+	#writeSizes(items, countPtr, sizePtr) {
+		const view = this.#view();
+		let size = 0;
+
+		for (const item of items) {
+			size += item.length;
+		}
+
+		DataViewPrototypeSetUint32(view, countPtr, items.length, true);
+		DataViewPrototypeSetUint32(view, sizePtr, size, true);
+
+		return ERRNO_SUCCESS;
+	}
+
+	#writeFilestat(ptr, type, size) {
+		const view = this.#view();
+
+		for (let i = 0; i < 64; i += 8) {
+			DataViewPrototypeSetBigUint64(view, ptr + i, 0n, true);
+		}
+
+		DataViewPrototypeSetUint8(view, ptr + 16, type);
+		DataViewPrototypeSetBigUint64(view, ptr + 24, 1n, true);
+		DataViewPrototypeSetBigUint64(view, ptr + 32, BigInt(size), true);
+	}
+
+	#resolve(fd, pathPtr, pathLen) {
+		const dir = this.#fds[fd];
+
+		if (dir === undefined) {
+			return [ERRNO_BADF, null];
+		}
+
+		if (dir.type !== FILETYPE_DIRECTORY) {
+			return [ERRNO_NOTCAPABLE, null];
+		}
+
+		const path = decoder.decode(
+			TypedArrayPrototypeSubarray(this.#bytes(), pathPtr, pathPtr + pathLen),
+		);
+		const hostPath = resolveWithin(dir.hostPath, path);
+
+		if (hostPath === null) {
+			return [ERRNO_NOTCAPABLE, null];
+		}
+
+		return [ERRNO_SUCCESS, hostPath];
+	}
+
+	#createImports() {
+		return {
+			args_get: (argvPtr, argvBufPtr) => this.#writeStrings(this.#args, argvPtr, argvBufPtr),
+			args_sizes_get: (argcPtr, argvBufSizePtr) =>
+				this.#writeSizes(this.#args, argcPtr, argvBufSizePtr),
+			environ_get: (environPtr, environBufPtr) =>
+				this.#writeStrings(this.#env, environPtr, environBufPtr),
+			environ_sizes_get: (environcPtr, environBufSizePtr) =>
+				this.#writeSizes(this.#env, environcPtr, environBufSizePtr),
+
+			clock_res_get: (id, resolutionPtr) => {
+				if (!permissions.clock) {
+					return ERRNO_NOTCAPABLE;
+				}
+
+				if (id < CLOCKID_REALTIME || id > CLOCKID_THREAD_CPUTIME_ID) {
+					return ERRNO_INVAL;
+				}
+
+				DataViewPrototypeSetBigUint64(this.#view(), resolutionPtr, 1000000n, true);
+				return ERRNO_SUCCESS;
+			},
+
+			clock_time_get: (id, _precision, timePtr) => {
+				if (!permissions.clock) {
+					return ERRNO_NOTCAPABLE;
+				}
+
+				// NOTE: The clocks are read through the globals so that any
+				// coarsening applied to the worker applies here as well.
+				let ms;
+
+				switch (id) {
+					case CLOCKID_REALTIME:
+						ms = globalThis.Date.now();
+						break;
+
+					case CLOCKID_MONOTONIC:
+					case CLOCKID_PROCESS_CPUTIME_ID:
+					case CLOCKID_THREAD_CPUTIME_ID:
+						ms = globalThis.performance.now();
+						break;
+
+					default:
+						return ERRNO_INVAL;
+				}
+
+				DataViewPrototypeSetBigUint64(
+					this.#view(),
+					timePtr,
+					BigInt(MathTrunc(ms * 1000000)),
+					true,
+				);
+
+				return ERRNO_SUCCESS;
+			},
+
+			random_get: (bufPtr, bufLen) => {
+				if (!permissions.random) {
+					return ERRNO_NOTCAPABLE;
+				}
+
+				const bytes = this.#bytes();
+
+				for (let i = 0; i < bufLen; i += RANDOM_GET_CHUNK_SIZE) {
+					crypto.getRandomValues(
+						TypedArrayPrototypeSubarray(
+							bytes,
+							bufPtr + i,
+							bufPtr + MathMin(bufLen, i + RANDOM_GET_CHUNK_SIZE),
+						),
+					);
+				}
+
+				return ERRNO_SUCCESS;
+			},
+
+			fd_write: (fd, iovsPtr, iovsLen, nwrittenPtr) => {
+				const entry = this.#fds[fd];
+
+				if (entry === undefined) {
+					return ERRNO_BADF;
+				}
+
+				if (entry.stream === undefined) {
+					return ERRNO_NOTCAPABLE;
+				}
+
+				const view = this.#view();
+				const bytes = this.#bytes();
+				let written = 0;
+
+				for (let i = 0; i < iovsLen; i++) {
+					const ptr = DataViewPrototypeGetUint32(view, iovsPtr + i * 8, true);
+					const len = DataViewPrototypeGetUint32(view, iovsPtr + i * 8 + 4, true);
+
+					this.#write(entry.stream, TypedArrayPrototypeSubarray(bytes, ptr, ptr + len));
+					written += len;
+				}
+
+				DataViewPrototypeSetUint32(view, nwrittenPtr, written, true);
+				return ERRNO_SUCCESS;
+			},
+
+			fd_read: (fd, iovsPtr, iovsLen, nreadPtr) => {
+				const entry = this.#fds[fd];
+
+				if (entry === undefined) {
+					return ERRNO_BADF;
+				}
+
+				if (entry.type === FILETYPE_DIRECTORY) {
+					return ERRNO_ISDIR;
+				}
+
+				// NOTE: Standard input is always empty.
+				if (entry.data === undefined) {
+					DataViewPrototypeSetUint32(this.#view(), nreadPtr, 0, true);
+					return ERRNO_SUCCESS;
+				}
+
+				const read = this.#readIovs(iovsPtr, iovsLen, entry.data, entry.offset);
+
+				entry.offset += read;
+
+				DataViewPrototypeSetUint32(this.#view(), nreadPtr, read, true);
+				return ERRNO_SUCCESS;
+			},
+
+			fd_pread: (fd, iovsPtr, iovsLen, offset, nreadPtr) => {
+				const entry = this.#fds[fd];
+
+				if (entry === undefined) {
+					return ERRNO_BADF;
+				}
+
+				if (entry.data === undefined) {
+					return ERRNO_NOTSUP;
+				}
+
+				const read = this.#readIovs(iovsPtr, iovsLen, entry.data, Number(offset));
+
+				DataViewPrototypeSetUint32(this.#view(), nreadPtr, read, true);
+				return ERRNO_SUCCESS;
+			},
+
+			fd_seek: (fd, offset, whence, newOffsetPtr) => {
+				const entry = this.#fds[fd];
+
+				if (entry === undefined) {
+					return ERRNO_BADF;
+				}
+
+				if (entry.data === undefined) {
+					return ERRNO_NOTSUP;
+				}
+
+				let base;
+
+				switch (whence) {
+					case WHENCE_SET:
+						base = 0;
+						break;
+					case WHENCE_CUR:
+						base = entry.offset;
+						break;
+					case WHENCE_END:
+						base = entry.data.length;
+						break;
+					default:
+						return ERRNO_INVAL;
+				}
+
+				const next = base + Number(offset);
+
+				if (next < 0) {
+					return ERRNO_INVAL;
+				}
+
+				entry.offset = next;
+
+				DataViewPrototypeSetBigUint64(this.#view(), newOffsetPtr, BigInt(next), true);
+				return ERRNO_SUCCESS;
+			},
+
+			fd_tell: (fd, offsetPtr) => {
+				const entry = this.#fds[fd];
+
+				if (entry === undefined) {
+					return ERRNO_BADF;
+				}
+
+				if (entry.data === undefined) {
+					return ERRNO_NOTSUP;
+				}
+
+				DataViewPrototypeSetBigUint64(this.#view(), offsetPtr, BigInt(entry.offset), true);
+				return ERRNO_SUCCESS;
+			},
+
+			fd_close: fd => {
+				if (this.#fds[fd] === undefined) {
+					return ERRNO_BADF;
+				}
+
+				delete this.#fds[fd];
+				return ERRNO_SUCCESS;
+			},
+
+			fd_fdstat_get: (fd, statPtr) => {
+				const entry = this.#fds[fd];
+
+				if (entry === undefined) {
+					return ERRNO_BADF;
+				}
+
+				const view = this.#view();
+
+				DataViewPrototypeSetUint8(view, statPtr, entry.type);
+				DataViewPrototypeSetUint16(view, statPtr + 2, 0, true);
+				DataViewPrototypeSetBigUint64(
+					view,
+					statPtr + 8,
+					entry.stream === undefined ? ~RIGHTS_FD_WRITE & 0x1fffffffn : 0x1fffffffn,
+					true,
+				);
+				DataViewPrototypeSetBigUint64(view, statPtr + 16, ~RIGHTS_FD_WRITE & 0x1fffffffn, true);
+
+				return ERRNO_SUCCESS;
+			},
+
+			fd_filestat_get: (fd, bufPtr) => {
+				const entry = this.#fds[fd];
+
+				if (entry === undefined) {
+					return ERRNO_BADF;
+				}
+
+				this.#writeFilestat(bufPtr, entry.type, entry.data?.length ?? 0);
+				return ERRNO_SUCCESS;
+			},
+
+			fd_prestat_get: (fd, prestatPtr) => {
+				const entry = this.#fds[fd];
+
+				if (entry?.preopen === undefined) {
+					return ERRNO_BADF;
+				}
+
+				const view = this.#view();
+
+				DataViewPrototypeSetUint8(view, prestatPtr, 0);
+				DataViewPrototypeSetUint32(view, prestatPtr + 4, entry.preopen.length, true);
+
+				return ERRNO_SUCCESS;
+			},
+
+			fd_prestat_dir_name: (fd, pathPtr, pathLen) => {
+				const entry = this.#fds[fd];
+
+				if (entry?.preopen === undefined) {
+					return ERRNO_BADF;
+				}
+
+				TypedArrayPrototypeSet(
+					this.#bytes(),
+					TypedArrayPrototypeSubarray(entry.preopen, 0, pathLen),
+					pathPtr,
+				);
+
+				return ERRNO_SUCCESS;
+			},
+
+			path_open: (
+				fd,
+				_dirflags,
+				pathPtr,
+				pathLen,
+				oflags,
+				rightsBase,
+				_rightsInheriting,
+				_fdflags,
+				openedFdPtr,
+			) => {
+				// NOTE: Files are exposed read-only.
+				if (
+					(oflags & (OFLAGS_CREAT | OFLAGS_EXCL | OFLAGS_TRUNC)) !== 0 ||
+					(rightsBase & RIGHTS_FD_WRITE) !== 0n
+				) {
+					return ERRNO_NOTCAPABLE;
+				}
+
+				const [errno, hostPath] = this.#resolve(fd, pathPtr, pathLen);
+
+				if (errno !== ERRNO_SUCCESS) {
+					return errno;
+				}
+
+				let entry;
+
+				if ((oflags & OFLAGS_DIRECTORY) !== 0) {
+					entry = { type: FILETYPE_DIRECTORY, hostPath };
+				} else {
+					try {
+						entry = {
+							type: FILETYPE_REGULAR_FILE,
+							data: fs.readFileSync(hostPath),
+							offset: 0,
+						};
+					} catch {
+						return ERRNO_NOENT;
+					}
+				}
+
+				const openedFd = this.#fds.length;
+
+				this.#fds[openedFd] = entry;
+
+				DataViewPrototypeSetUint32(this.#view(), openedFdPtr, openedFd, true);
+				return ERRNO_SUCCESS;
+			},
+
+			path_filestat_get: (fd, _flags, pathPtr, pathLen, bufPtr) => {
+				const [errno, hostPath] = this.#resolve(fd, pathPtr, pathLen);
+
+				if (errno !== ERRNO_SUCCESS) {
+					return errno;
+				}
+
+				let info;
+
+				try {
+					info = fs.statSync(hostPath);
+				} catch {
+					return ERRNO_NOENT;
+				}
+
+				this.#writeFilestat(
+					bufPtr,
+					info.isDirectory ? FILETYPE_DIRECTORY : FILETYPE_REGULAR_FILE,
+					info.size,
+				);
+
+				return ERRNO_SUCCESS;
+			},
+
+			proc_exit: code => {
+				throw new ExitStatus(code);
+			},
+
+			sched_yield: () => ERRNO_SUCCESS,
+		};
+	}
+}
+
+export { setWasiPermissions, WASI };
@@ -1,21 +1,32 @@
 use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
 use std::rc::Rc;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use deno_core::error::AnyError;
 use deno_core::v8;
 use deno_core::OpState;
 use deno_core::{op2, JsRuntime};
 use enum_as_inner::EnumAsInner;
+use event_worker::events::{WorkerRequestStats, WorkerSecrets};
 use futures::task::AtomicWaker;
 use futures::FutureExt;
+use hibernation::{
+    op_hibernated_websocket_close, op_hibernated_websocket_send_binary,
+    op_hibernated_websocket_send_text,
+};
 use log::error;
 use serde::Serialize;
-use tokio::sync::oneshot;
+use state_checkpoint::{op_state_checkpoint_open, op_state_checkpoint_write};
+use tokio::sync::{oneshot, Notify};
+use tokio_util::sync::CancellationToken;
 
 mod upgrade;
 
+pub use bounded_channel as channel;
+
 pub mod auth_tokens;
 pub mod cache;
 pub mod cert;
@@ -23,12 +34,18 @@ pub mod conn_sync;
 pub mod emit;
 pub mod errors_rt;
 pub mod external_memory;
+pub mod fetch_pool;
 pub mod file_fetcher;
+pub mod hibernation;
 pub mod http;
+pub mod http_bridge;
 pub mod http_start;
 pub mod net;
 pub mod permissions;
 pub mod runtime;
+pub mod signing;
+pub mod state_checkpoint;
+pub mod tls_policy;
 pub mod transpiler;
 pub mod util;
 
@@ -40,13 +57,317 @@ impl From<Arc<AtomicWaker>> for MemCheckWaker {
     }
 }
 
+/// Coordinates the `beforeunload` hook that user workers get to run before the
+/// supervisor terminates the isolate.
+#[derive(Debug, Default, Clone)]
+pub struct TerminationHook {
+    requested: CancellationToken,
+    completed: CancellationToken,
+}
+
+impl TerminationHook {
+    pub fn request(&self) {
+        self.requested.cancel();
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.is_cancelled()
+    }
+
+    pub async fn completed(&self) {
+        self.completed.cancelled().await
+    }
+}
+
+/// Signals the `onBoot` hooks of a user worker once its main module has been
+/// evaluated.
+#[derive(Debug, Default, Clone)]
+pub struct BootSignal(CancellationToken);
+
+impl BootSignal {
+    pub fn complete(&self) {
+        self.0.cancel();
+    }
+}
+
+/// How many of the most recent request latencies are kept to estimate the
+/// percentiles of a user worker.
+const REQUEST_LATENCY_SAMPLES: usize = 1024;
+
+#[derive(Debug, Default)]
+struct WorkerRequestMetricsInner {
+    request_count: AtomicUsize,
+    error_count: AtomicUsize,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    queue_depth: AtomicUsize,
+    latencies_ms: Mutex<VecDeque<u64>>,
+    dispatch_latencies_ms: Mutex<VecDeque<u64>>,
+    pending_ops: AtomicUsize,
+    open_resources: AtomicUsize,
+    event_loop_lags_ms: Mutex<VecDeque<u64>>,
+    event_loop_turn_started_at: Mutex<Option<Instant>>,
+    active_connections: AtomicUsize,
+    rejected_connections: AtomicUsize,
+    connections_drained: Notify,
+    boot_time_ms: AtomicU64,
+    cold_request_taken: AtomicBool,
+}
+
+fn push_latency_sample(samples: &Mutex<VecDeque<u64>>, latency: Duration) {
+    if let Ok(mut samples) = samples.lock() {
+        if samples.len() >= REQUEST_LATENCY_SAMPLES {
+            samples.pop_front();
+        }
+
+        samples.push_back(latency.as_millis() as u64);
+    }
+}
+
+fn latency_percentiles(samples: &Mutex<VecDeque<u64>>) -> (u64, u64) {
+    match samples.lock() {
+        Ok(samples) if !samples.is_empty() => {
+            let mut sorted = samples.iter().copied().collect::<Vec<_>>();
+
+            sorted.sort_unstable();
+
+            let percentile = |pct: usize| sorted[(sorted.len() - 1) * pct / 100];
+
+            (percentile(50), percentile(95))
+        }
+
+        _ => (0, 0),
+    }
+}
+
+/// Marks a request as queued for a user worker until it is dispatched or
+/// dropped.
+#[derive(Debug)]
+pub struct RequestDispatchGuard {
+    metrics: WorkerRequestMetrics,
+    received_at: Instant,
+    dispatched_tx: Option<oneshot::Sender<Instant>>,
+}
+
+impl RequestDispatchGuard {
+    /// Returns a receiver that resolves with the instant the request was
+    /// handed over to the worker.
+    pub fn on_dispatched(&mut self) -> oneshot::Receiver<Instant> {
+        let (tx, rx) = oneshot::channel();
+
+        self.dispatched_tx = Some(tx);
+        rx
+    }
+
+    /// Records that the request has been handed over to the worker.
+    pub fn dispatched(mut self) {
+        let now = Instant::now();
+
+        push_latency_sample(
+            &self.metrics.0.dispatch_latencies_ms,
+            now - self.received_at,
+        );
+
+        if let Some(tx) = self.dispatched_tx.take() {
+            let _ = tx.send(now);
+        }
+    }
+}
+
+impl Drop for RequestDispatchGuard {
+    fn drop(&mut self) {
+        self.metrics.0.queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Keeps an upgraded connection or event stream of a user worker counted
+/// until it is dropped.
+#[derive(Debug)]
+pub struct ActiveConnectionGuard(Arc<WorkerRequestMetricsInner>);
+
+impl Drop for ActiveConnectionGuard {
+    fn drop(&mut self) {
+        if self.0.active_connections.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.connections_drained.notify_waiters();
+        }
+    }
+}
+
+/// Request statistics of a single user worker, shared between the pool and
+/// its supervisor.
+#[derive(Debug, Default, Clone)]
+pub struct WorkerRequestMetrics(Arc<WorkerRequestMetricsInner>);
+
+impl WorkerRequestMetrics {
+    pub fn record_request(&self, latency: Duration, is_error: bool) {
+        self.0.request_count.fetch_add(1, Ordering::Relaxed);
+
+        if is_error {
+            self.0.error_count.fetch_add(1, Ordering::Relaxed);
+        }
+
+        push_latency_sample(&self.0.latencies_ms, latency);
+    }
+
+    /// Records how long the worker took to boot, apart from the requests it
+    /// serves.
+    pub fn record_boot(&self, duration: Duration) {
+        self.0
+            .boot_time_ms
+            .store(duration.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Returns `true` for the first request that arrives at the worker, which
+    /// is the one that waited for it to boot.
+    pub fn take_cold_request(&self) -> bool {
+        !self.0.cold_request_taken.swap(true, Ordering::Relaxed)
+    }
+
+    /// Called once a request for this worker arrives at the pool.
+    pub fn enqueue(&self) -> RequestDispatchGuard {
+        self.0.queue_depth.fetch_add(1, Ordering::Relaxed);
+
+        RequestDispatchGuard {
+            metrics: self.clone(),
+            received_at: Instant::now(),
+            dispatched_tx: None,
+        }
+    }
+
+    pub fn request_count(&self) -> usize {
+        self.0.request_count.load(Ordering::Relaxed)
+    }
+
+    pub fn add_bytes_in(&self, len: usize) {
+        self.0.bytes_in.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_out(&self, len: usize) {
+        self.0.bytes_out.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// Called as the worker starts a turn of its event loop.
+    pub fn enter_event_loop(&self) {
+        if let Ok(mut started_at) = self.0.event_loop_turn_started_at.lock() {
+            *started_at = Some(Instant::now());
+        }
+    }
+
+    /// Called as the turn ends, with the async ops the worker is still
+    /// waiting on.
+    pub fn leave_event_loop(&self, pending_ops: usize) {
+        let maybe_started_at = self
+            .0
+            .event_loop_turn_started_at
+            .lock()
+            .ok()
+            .and_then(|mut it| it.take());
+
+        if let Some(started_at) = maybe_started_at {
+            push_latency_sample(&self.0.event_loop_lags_ms, started_at.elapsed());
+        }
+
+        self.0.pending_ops.store(pending_ops, Ordering::Relaxed);
+    }
+
+    /// Counts an upgraded connection or event stream of the worker until the
+    /// returned guard is dropped, unless the worker already serves `max` of
+    /// them. Zero means no limit.
+    pub fn open_connection(&self, max: u64) -> Option<ActiveConnectionGuard> {
+        let result =
+            self.0
+                .active_connections
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                    (max == 0 || (count as u64) < max).then_some(count + 1)
+                });
+
+        if result.is_err() {
+            self.0.rejected_connections.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        Some(ActiveConnectionGuard(self.0.clone()))
+    }
+
+    pub fn active_connections(&self) -> usize {
+        self.0.active_connections.load(Ordering::Acquire)
+    }
+
+    /// Resolves once the worker no longer serves any upgraded connection or
+    /// event stream.
+    pub async fn connections_drained(&self) {
+        loop {
+            // NOTE: Registered before checking, so that the last connection
+            // closing in between isn't missed.
+            let notified = self.0.connections_drained.notified();
+
+            if self.active_connections() == 0 {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+
+    pub fn set_open_resources(&self, count: usize) {
+        self.0.open_resources.store(count, Ordering::Relaxed);
+    }
+
+    /// How long the worker holds its event loop for in a turn, or has been
+    /// holding it for if the current turn has already taken longer.
+    fn event_loop_lag_ms(&self) -> u64 {
+        let (_, lag_p95_ms) = latency_percentiles(&self.0.event_loop_lags_ms);
+        let current_turn_ms = self
+            .0
+            .event_loop_turn_started_at
+            .lock()
+            .ok()
+            .and_then(|it| it.map(|started_at| started_at.elapsed().as_millis() as u64))
+            .unwrap_or_default();
+
+        lag_p95_ms.max(current_turn_ms)
+    }
+
+    pub fn stats(&self) -> WorkerRequestStats {
+        let (latency_p50_ms, latency_p95_ms) = latency_percentiles(&self.0.latencies_ms);
+        let (dispatch_latency_p50_ms, dispatch_latency_p95_ms) =
+            latency_percentiles(&self.0.dispatch_latencies_ms);
+
+        WorkerRequestStats {
+            request_count: self.0.request_count.load(Ordering::Relaxed),
+            error_count: self.0.error_count.load(Ordering::Relaxed),
+            latency_p50_ms,
+            latency_p95_ms,
+            bytes_in: self.0.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.0.bytes_out.load(Ordering::Relaxed),
+            dispatch_latency_p50_ms,
+            dispatch_latency_p95_ms,
+            queue_depth: self.0.queue_depth.load(Ordering::Relaxed),
+            pending_ops: self.0.pending_ops.load(Ordering::Relaxed),
+            open_resources: self.0.open_resources.load(Ordering::Relaxed),
+            event_loop_lag_ms: self.event_loop_lag_ms(),
+            active_connections: self.active_connections(),
+            rejected_connections: self.0.rejected_connections.load(Ordering::Relaxed),
+            boot_time_ms: self.0.boot_time_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct SharedMetricSource {
     active_user_workers: Arc<AtomicUsize>,
     retired_user_workers: Arc<AtomicUsize>,
     received_requests: Arc<AtomicUsize>,
     handled_requests: Arc<AtomicUsize>,
+    denied_requests: Arc<AtomicUsize>,
     active_io: Arc<AtomicUsize>,
+    accepted_connections: Arc<AtomicUsize>,
+    rejected_connections: Arc<AtomicUsize>,
+    reused_connection_requests: Arc<AtomicUsize>,
+    overloaded_requests: Arc<AtomicUsize>,
+    timed_out_header_reads: Arc<AtomicUsize>,
+    timed_out_body_reads: Arc<AtomicUsize>,
+    user_worker_requests: Arc<Mutex<HashMap<String, WorkerRequestMetrics>>>,
 }
 
 impl SharedMetricSource {
@@ -62,6 +383,10 @@ impl SharedMetricSource {
         self.handled_requests.load(Ordering::Relaxed)
     }
 
+    pub fn active_user_workers(&self) -> usize {
+        self.active_user_workers.load(Ordering::Relaxed)
+    }
+
     pub fn incl_active_user_workers(&self) {
         self.active_user_workers.fetch_add(1, Ordering::Relaxed);
     }
@@ -82,6 +407,10 @@ impl SharedMetricSource {
         self.handled_requests.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn incl_denied_requests(&self) {
+        self.denied_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn incl_active_io(&self) {
         self.active_io.fetch_add(1, Ordering::Relaxed);
     }
@@ -90,12 +419,73 @@ impl SharedMetricSource {
         self.active_io.fetch_sub(1, Ordering::Relaxed);
     }
 
+    pub fn incl_accepted_connections(&self) {
+        self.accepted_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn incl_rejected_connections(&self) {
+        self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a request that arrived on a connection which had already
+    /// served another one.
+    pub fn incl_reused_connection_requests(&self) {
+        self.reused_connection_requests
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a request that was turned away because the listener was
+    /// serving as many requests as it is allowed to.
+    pub fn incl_overloaded_requests(&self) {
+        self.overloaded_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a connection closed because its request header took too long
+    /// to arrive.
+    pub fn incl_timed_out_header_reads(&self) {
+        self.timed_out_header_reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a connection closed because its request body stalled.
+    pub fn incl_timed_out_body_reads(&self) {
+        self.timed_out_body_reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn register_user_worker_requests(&self, key: String, metrics: WorkerRequestMetrics) {
+        if let Ok(mut map) = self.user_worker_requests.lock() {
+            map.insert(key, metrics);
+        }
+    }
+
+    pub fn unregister_user_worker_requests(&self, key: &str) {
+        if let Ok(mut map) = self.user_worker_requests.lock() {
+            map.remove(key);
+        }
+    }
+
+    pub fn user_worker_request_stats(&self) -> HashMap<String, WorkerRequestStats> {
+        self.user_worker_requests
+            .lock()
+            .map(|map| map.iter().map(|(k, v)| (k.clone(), v.stats())).collect())
+            .unwrap_or_default()
+    }
+
     pub fn reset(&self) {
         self.active_user_workers.store(0, Ordering::Relaxed);
         self.retired_user_workers.store(0, Ordering::Relaxed);
         self.received_requests.store(0, Ordering::Relaxed);
         self.handled_requests.store(0, Ordering::Relaxed);
         self.active_io.store(0, Ordering::Relaxed);
+        self.accepted_connections.store(0, Ordering::Relaxed);
+        self.rejected_connections.store(0, Ordering::Relaxed);
+        self.reused_connection_requests.store(0, Ordering::Relaxed);
+        self.overloaded_requests.store(0, Ordering::Relaxed);
+        self.timed_out_header_reads.store(0, Ordering::Relaxed);
+        self.timed_out_body_reads.store(0, Ordering::Relaxed);
+
+        if let Ok(mut map) = self.user_worker_requests.lock() {
+            map.clear();
+        }
     }
 }
 
@@ -244,6 +634,15 @@ struct RuntimeSharedStatistics {
     retired_user_workers_count: usize,
     received_requests_count: usize,
     handled_requests_count: usize,
+    denied_requests_count: usize,
+    open_connections_count: usize,
+    accepted_connections_count: usize,
+    rejected_connections_count: usize,
+    reused_connection_requests_count: usize,
+    overloaded_requests_count: usize,
+    timed_out_header_reads_count: usize,
+    timed_out_body_reads_count: usize,
+    user_worker_requests: HashMap<String, WorkerRequestStats>,
 }
 
 impl RuntimeSharedStatistics {
@@ -253,6 +652,17 @@ impl RuntimeSharedStatistics {
             retired_user_workers_count: src.retired_user_workers.load(Ordering::Relaxed),
             received_requests_count: src.received_requests.load(Ordering::Relaxed),
             handled_requests_count: src.handled_requests.load(Ordering::Relaxed),
+            denied_requests_count: src.denied_requests.load(Ordering::Relaxed),
+            open_connections_count: src.active_io.load(Ordering::Relaxed),
+            accepted_connections_count: src.accepted_connections.load(Ordering::Relaxed),
+            rejected_connections_count: src.rejected_connections.load(Ordering::Relaxed),
+            reused_connection_requests_count: src
+                .reused_connection_requests
+                .load(Ordering::Relaxed),
+            overloaded_requests_count: src.overloaded_requests.load(Ordering::Relaxed),
+            timed_out_header_reads_count: src.timed_out_header_reads.load(Ordering::Relaxed),
+            timed_out_body_reads_count: src.timed_out_body_reads.load(Ordering::Relaxed),
+            user_worker_requests: src.user_worker_request_stats(),
         }
     }
 }
@@ -264,6 +674,9 @@ struct RuntimeMetrics {
     heap_stats: RuntimeHeapStatistics,
     #[serde(flatten)]
     shared_stats: RuntimeSharedStatistics,
+    fetch_pool_stats: fetch_pool::FetchPoolStatistics,
+    channel_stats: channel::ChannelStatistics,
+    duplex_stream_stats: net::DuplexStreamStatistics,
 }
 
 #[op2(fast)]
@@ -294,6 +707,9 @@ async fn op_runtime_metrics(state: Rc<RefCell<OpState>>) -> Result<RuntimeMetric
     runtime_metrics.heap_stats = runtime_metric_src.get_heap_statistics().await;
     runtime_metrics.shared_stats =
         RuntimeSharedStatistics::from_shared_metric_src(&runtime_metric_src.shared);
+    runtime_metrics.fetch_pool_stats = fetch_pool::FetchPoolStatistics::get();
+    runtime_metrics.channel_stats = channel::ChannelStatistics::get();
+    runtime_metrics.duplex_stream_stats = net::DuplexStreamStatistics::get();
 
     Ok(runtime_metrics)
 }
@@ -307,6 +723,35 @@ fn op_schedule_mem_check(state: &mut OpState) -> Result<(), AnyError> {
     Ok(())
 }
 
+#[op2(async)]
+async fn op_wait_termination_request(state: Rc<RefCell<OpState>>) -> Result<bool, AnyError> {
+    let Some(hook) = state.borrow().try_borrow::<TerminationHook>().cloned() else {
+        return Ok(false);
+    };
+
+    hook.requested.cancelled().await;
+    Ok(true)
+}
+
+#[op2(async)]
+async fn op_wait_worker_boot(state: Rc<RefCell<OpState>>) -> Result<bool, AnyError> {
+    let Some(signal) = state.borrow().try_borrow::<BootSignal>().cloned() else {
+        return Ok(false);
+    };
+
+    signal.0.cancelled().await;
+    Ok(true)
+}
+
+#[op2(fast)]
+fn op_complete_termination_hook(state: &mut OpState) -> Result<(), AnyError> {
+    if let Some(hook) = state.try_borrow::<TerminationHook>() {
+        hook.completed.cancel();
+    }
+
+    Ok(())
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 struct MemoryUsage {
@@ -319,6 +764,10 @@ struct MemoryUsage {
 #[op2]
 #[serde]
 fn op_runtime_memory_usage(scope: &mut v8::HandleScope) -> MemoryUsage {
+    memory_usage(scope)
+}
+
+fn memory_usage(scope: &mut v8::HandleScope) -> MemoryUsage {
     let mut s = v8::HeapStatistics::default();
 
     scope.get_heap_statistics(&mut s);
@@ -332,6 +781,93 @@ fn op_runtime_memory_usage(scope: &mut v8::HandleScope) -> MemoryUsage {
     }
 }
 
+/// Keeps track of the CPU time a user worker has spent, so that it can be read
+/// from inside the isolate.
+#[derive(Debug, Default)]
+pub struct CpuClock {
+    accumulated_ns: AtomicI64,
+    entered_at_ns: AtomicI64,
+}
+
+impl CpuClock {
+    /// Called by the runtime every time it enters the isolate, with the CPU
+    /// time accumulated so far and the current thread time.
+    pub fn enter(&self, accumulated_ns: i64, now_ns: i64) {
+        self.accumulated_ns.store(accumulated_ns, Ordering::Relaxed);
+        self.entered_at_ns.store(now_ns, Ordering::Relaxed);
+    }
+
+    // NOTE: Ops only run while the runtime is inside the isolate, on the
+    // thread that entered it.
+    fn used_ns(&self) -> i64 {
+        let accumulated_ns = self.accumulated_ns.load(Ordering::Relaxed);
+        let entered_at_ns = self.entered_at_ns.load(Ordering::Relaxed);
+
+        match cpu_timer::get_thread_time() {
+            Ok(now_ns) => accumulated_ns + (now_ns - entered_at_ns).max(0),
+            Err(_) => accumulated_ns,
+        }
+    }
+}
+
+/// What a user worker can learn about itself through `EdgeRuntime.getInfo()`.
+pub struct WorkerSelfInfo {
+    pub key: Option<String>,
+    pub started_at: Instant,
+    /// Zero when the worker has no wall clock limit.
+    pub wall_clock_limit_ms: u64,
+    pub cpu_clock: Arc<CpuClock>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WorkerInfo {
+    key: Option<String>,
+    wall_clock_remaining_ms: Option<u64>,
+    cpu_time_used_ms: u64,
+    memory_usage: MemoryUsage,
+}
+
+#[op2]
+#[serde]
+fn op_user_worker_info(
+    state: Rc<RefCell<OpState>>,
+    scope: &mut v8::HandleScope,
+) -> Result<WorkerInfo, AnyError> {
+    let state = state.borrow();
+    let Some(info) = state.try_borrow::<WorkerSelfInfo>() else {
+        return Err(anyhow::anyhow!("worker info is not available"));
+    };
+
+    let elapsed_ms = info.started_at.elapsed().as_millis() as u64;
+
+    Ok(WorkerInfo {
+        key: info.key.clone(),
+        wall_clock_remaining_ms: (info.wall_clock_limit_ms > 0)
+            .then(|| info.wall_clock_limit_ms.saturating_sub(elapsed_ms)),
+        cpu_time_used_ms: (info.cpu_clock.used_ns() / 1_000_000) as u64,
+        memory_usage: memory_usage(scope),
+    })
+}
+
+#[op2]
+#[string]
+fn op_user_worker_secret(state: &mut OpState, #[string] name: &str) -> Option<String> {
+    state
+        .try_borrow::<WorkerSecrets>()
+        .and_then(|it| it.get(name))
+        .map(str::to_string)
+}
+
+#[op2]
+fn op_verify_request_signature(#[serde] headers: Vec<(String, String)>) -> bool {
+    signing::verify(
+        headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str())),
+    )
+}
+
 #[op2]
 #[string]
 pub fn op_read_line_prompt(
@@ -356,7 +892,18 @@ deno_core::extension!(
         op_set_exit_code,
         op_runtime_metrics,
         op_schedule_mem_check,
-        op_runtime_memory_usage
+        op_runtime_memory_usage,
+        op_user_worker_info,
+        op_user_worker_secret,
+        op_verify_request_signature,
+        op_wait_termination_request,
+        op_complete_termination_hook,
+        op_wait_worker_boot,
+        op_state_checkpoint_open,
+        op_state_checkpoint_write,
+        op_hibernated_websocket_send_text,
+        op_hibernated_websocket_send_binary,
+        op_hibernated_websocket_close
     ],
     esm_entry_point = "ext:sb_core_main_js/js/bootstrap.js",
     esm = [
@@ -367,8 +914,103 @@ deno_core::extension!(
         "js/http.js",
         "js/denoOverrides.js",
         "js/navigator.js",
+        "js/clock.js",
+        "js/deterministic.js",
+        "js/isolation.js",
+        "js/wasi.js",
         "js/bootstrap.js",
         "js/main_worker.js",
         "js/01_http.js"
     ]
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_loop_lag_includes_current_turn() {
+        let metrics = WorkerRequestMetrics::default();
+
+        metrics.enter_event_loop();
+        metrics.leave_event_loop(3);
+
+        let stats = metrics.stats();
+
+        assert_eq!(stats.pending_ops, 3);
+        assert_eq!(stats.event_loop_lag_ms, 0);
+
+        metrics.enter_event_loop();
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(metrics.stats().event_loop_lag_ms >= 20);
+    }
+
+    #[test]
+    fn request_stats_are_reported_per_worker() {
+        let src = SharedMetricSource::default();
+        let metrics = WorkerRequestMetrics::default();
+
+        src.register_user_worker_requests("meow".to_string(), metrics.clone());
+
+        for latency_ms in [10, 20, 30, 40] {
+            metrics.record_request(Duration::from_millis(latency_ms), latency_ms == 40);
+        }
+
+        metrics.add_bytes_in(3);
+        metrics.add_bytes_out(5);
+
+        let stats = src.user_worker_request_stats()["meow"];
+
+        assert_eq!(stats.request_count, 4);
+        assert_eq!(stats.error_count, 1);
+        assert_eq!(stats.latency_p50_ms, 20);
+        assert_eq!(stats.latency_p95_ms, 30);
+        assert_eq!(stats.bytes_in, 3);
+        assert_eq!(stats.bytes_out, 5);
+
+        src.unregister_user_worker_requests("meow");
+
+        assert!(src.user_worker_request_stats().is_empty());
+    }
+
+    #[test]
+    fn boot_time_is_reported_apart_from_requests() {
+        let metrics = WorkerRequestMetrics::default();
+
+        metrics.record_boot(Duration::from_millis(120));
+        metrics.record_request(Duration::from_millis(10), false);
+
+        let stats = metrics.stats();
+
+        assert_eq!(stats.boot_time_ms, 120);
+        assert_eq!(stats.request_count, 1);
+        assert_eq!(stats.latency_p50_ms, 10);
+    }
+
+    #[tokio::test]
+    async fn queued_requests_are_counted_until_dispatched_or_dropped() {
+        let metrics = WorkerRequestMetrics::default();
+        let mut first = metrics.enqueue();
+        let second = metrics.enqueue();
+        let dispatched_rx = first.on_dispatched();
+
+        assert_eq!(metrics.stats().queue_depth, 2);
+
+        std::thread::sleep(Duration::from_millis(20));
+        first.dispatched();
+
+        let stats = metrics.stats();
+
+        assert!(dispatched_rx.await.is_ok());
+        assert_eq!(stats.queue_depth, 1);
+        assert!(stats.dispatch_latency_p50_ms >= 20);
+
+        drop(second);
+
+        let stats = metrics.stats();
+
+        assert_eq!(stats.queue_depth, 0);
+        assert_eq!(stats.dispatch_latency_p95_ms, stats.dispatch_latency_p50_ms);
+    }
+}
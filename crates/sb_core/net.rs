@@ -9,16 +9,21 @@ use deno_core::OpState;
 use deno_core::Resource;
 use deno_core::ResourceId;
 use deno_net::ops::IpAddr;
+use log::warn;
+use serde::Serialize;
 use std::borrow::Cow;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
 use tokio::io;
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
+use crate::conn_sync::ConnectionInfo;
+
 pub struct TokioDuplexResource {
     id: usize,
     rw: AsyncRefCell<io::DuplexStream>,
@@ -74,6 +79,87 @@ impl Resource for TokioDuplexResource {
     }
 }
 
+/// How long a stream the worker has accepted may go without an HTTP
+/// connection being started on it before it is reaped.
+const STALE_DUPLEX_STREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+static REAPED_DUPLEX_STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplexStreamStatistics {
+    /// Count of streams that were closed because the worker accepted them
+    /// but never started an HTTP connection on them.
+    reaped_streams_count: usize,
+}
+
+impl DuplexStreamStatistics {
+    pub fn get() -> Self {
+        Self {
+            reaped_streams_count: REAPED_DUPLEX_STREAMS.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Streams the worker has accepted but not started an HTTP connection on
+/// yet, by their id.
+#[derive(Debug, Default)]
+pub struct PendingDuplexStreams(HashMap<usize, (ResourceId, Instant)>);
+
+impl PendingDuplexStreams {
+    fn insert(&mut self, id: usize, rid: ResourceId) {
+        let _ = self.0.insert(id, (rid, Instant::now()));
+    }
+
+    pub(crate) fn remove(&mut self, id: usize) {
+        let _ = self.0.remove(&id);
+    }
+
+    fn take_stale(&mut self, now: Instant) -> Vec<(usize, ResourceId)> {
+        let stale = self
+            .0
+            .iter()
+            .filter(|(_, (_, accepted_at))| now - *accepted_at >= STALE_DUPLEX_STREAM_TIMEOUT)
+            .map(|(id, (rid, _))| (*id, *rid))
+            .collect::<Vec<_>>();
+
+        for (id, _) in &stale {
+            let _ = self.0.remove(id);
+        }
+
+        stale
+    }
+}
+
+/// Closes the streams that the worker accepted but didn't start an HTTP
+/// connection on in time, such as those of requests that failed between
+/// being paired with the worker and the handshake. Closing them lets the
+/// other end find out that the request is gone.
+pub(crate) fn reap_stale_duplex_streams(state: &mut OpState) {
+    let Some(pending) = state.try_borrow_mut::<PendingDuplexStreams>() else {
+        return;
+    };
+
+    let stale = pending.take_stale(Instant::now());
+
+    if stale.is_empty() {
+        return;
+    }
+
+    for (id, rid) in &stale {
+        let _ = state.resource_table.close(*rid);
+        let _ = state
+            .borrow_mut::<HashMap<usize, CancellationToken>>()
+            .remove(id);
+        let _ = state
+            .borrow_mut::<HashMap<usize, ConnectionInfo>>()
+            .remove(id);
+    }
+
+    REAPED_DUPLEX_STREAMS.fetch_add(stale.len(), Ordering::Relaxed);
+    warn!("reaped {} stale duplex streams", stale.len());
+}
+
 #[op2]
 #[serde]
 pub fn op_net_listen(_state: &mut OpState) -> Result<(ResourceId, IpAddr), AnyError> {
@@ -98,8 +184,14 @@ pub async fn op_net_accept(
     // we need to add it back later after processing a message.
     let rx = {
         let mut op_state = state.borrow_mut();
-        op_state
-            .try_take::<mpsc::UnboundedReceiver<(io::DuplexStream, Option<CancellationToken>)>>()
+
+        reap_stale_duplex_streams(&mut op_state);
+
+        op_state.try_take::<mpsc::Receiver<(
+            io::DuplexStream,
+            Option<CancellationToken>,
+            Option<ConnectionInfo>,
+        )>>()
     };
 
     if rx.is_none() {
@@ -111,13 +203,15 @@ pub async fn op_net_accept(
         let state = state.clone();
         move |value| {
             let mut op_state = state.borrow_mut();
-            op_state.put::<mpsc::UnboundedReceiver<(io::DuplexStream, Option<CancellationToken>)>>(
-                value,
-            );
+            op_state.put::<mpsc::Receiver<(
+                io::DuplexStream,
+                Option<CancellationToken>,
+                Option<ConnectionInfo>,
+            )>>(value);
         }
     });
 
-    let Some((stream, conn_token)) = rx.recv().await else {
+    let Some((stream, conn_token, maybe_conn_info)) = rx.recv().await else {
         return Err(bad_resource("duplex stream channel is closed"));
     };
 
@@ -131,12 +225,22 @@ pub async fn op_net_accept(
     let mut op_state = state.borrow_mut();
     let rid = op_state.resource_table.add(resource);
 
+    if let Some(pending) = op_state.try_borrow_mut::<PendingDuplexStreams>() {
+        pending.insert(id, rid);
+    }
+
     if let Some(token) = conn_token {
         let _ = op_state
             .borrow_mut::<HashMap<usize, CancellationToken>>()
             .insert(id, token);
     }
 
+    if let Some(conn_info) = maybe_conn_info {
+        let _ = op_state
+            .borrow_mut::<HashMap<usize, ConnectionInfo>>()
+            .insert(id, conn_info);
+    }
+
     Ok((
         rid,
         IpAddr {
@@ -172,3 +276,27 @@ deno_core::extension!(
         _ => op,
     }
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_only_stale_streams() {
+        let mut pending = PendingDuplexStreams::default();
+
+        pending.insert(1, 10);
+        pending.insert(2, 20);
+        pending.remove(2);
+        pending.insert(3, 30);
+
+        let now = Instant::now();
+
+        assert!(pending.take_stale(now).is_empty());
+        assert_eq!(
+            pending.take_stale(now + STALE_DUPLEX_STREAM_TIMEOUT).len(),
+            2
+        );
+        assert!(pending.0.is_empty());
+    }
+}
@@ -3,23 +3,77 @@ use deno_core::url::Url;
 use deno_fs::OpenOptions;
 use std::path::Path;
 
+/// Capabilities granted to a worker on top of the web platform APIs. Every
+/// capability is granted by default, which is what the main worker gets.
+#[derive(Debug, Clone, Copy)]
+pub struct PermissionsOptions {
+    pub net_access_disabled: bool,
+    /// Whether `Deno.dlopen` may be called.
+    pub allow_ffi: bool,
+    /// Whether `Deno.Command` may spawn subprocesses.
+    pub allow_subprocess: bool,
+    /// Whether raw sockets may be opened through `Deno.connect`,
+    /// `Deno.connectTls`, `Deno.resolveDns` and `node:net`. `fetch` and
+    /// `WebSocket` are not affected.
+    pub allow_raw_net: bool,
+}
+
+impl Default for PermissionsOptions {
+    fn default() -> Self {
+        Self {
+            net_access_disabled: false,
+            allow_ffi: true,
+            allow_subprocess: true,
+            allow_raw_net: true,
+        }
+    }
+}
+
 pub struct Permissions {
     net_access_disabled: bool,
+    allow_ffi: bool,
+    allow_subprocess: bool,
+    allow_raw_net: bool,
 }
 
 impl Default for Permissions {
     fn default() -> Self {
-        Self::new(false)
+        Self::new(PermissionsOptions::default())
     }
 }
 
 impl Permissions {
-    pub fn new(net_access_disabled: bool) -> Self {
+    pub fn new(options: PermissionsOptions) -> Self {
         Self {
-            net_access_disabled,
+            net_access_disabled: options.net_access_disabled,
+            allow_ffi: options.allow_ffi,
+            allow_subprocess: options.allow_subprocess,
+            allow_raw_net: options.allow_raw_net,
         }
     }
 
+    pub fn check_ffi(&mut self, api_name: &str) -> Result<(), AnyError> {
+        if !self.allow_ffi {
+            return Err(custom_error(
+                "PermissionDenied",
+                format!("{} is disabled for the worker", api_name),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn check_run(&mut self, _cmd: &str, api_name: &str) -> Result<(), AnyError> {
+        if !self.allow_subprocess {
+            return Err(custom_error(
+                "PermissionDenied",
+                format!("{} is disabled for the worker", api_name),
+            ));
+        }
+
+        Ok(())
+    }
+
     pub fn check_env(&mut self, _var: &str) -> Result<(), AnyError> {
         Ok(())
     }
@@ -40,9 +94,9 @@ impl Permissions {
 
 deno_core::extension!(
     sb_core_permissions,
-    options = { net_access_disabled: bool },
+    options = { options: PermissionsOptions },
     state = |state, options| {
-        state.put::<Permissions>(Permissions::new(options.net_access_disabled));
+        state.put::<Permissions>(Permissions::new(options.options));
     }
 );
 
@@ -72,7 +126,7 @@ impl deno_net::NetPermissions for Permissions {
     fn check_net<T: AsRef<str>>(
         &mut self,
         _host: &(T, Option<u16>),
-        _api_name: &str,
+        api_name: &str,
     ) -> Result<(), AnyError> {
         if self.net_access_disabled {
             return Err(custom_error(
@@ -80,6 +134,14 @@ impl deno_net::NetPermissions for Permissions {
                 "net access disabled for the user worker",
             ));
         }
+
+        if !self.allow_raw_net {
+            return Err(custom_error(
+                "PermissionDenied",
+                format!("{} is disabled for the worker", api_name),
+            ));
+        }
+
         Ok(())
     }
 
@@ -0,0 +1,204 @@
+//! Signs the internal headers of requests sent into user workers, so that
+//! user code can tell they were set by the runtime rather than by a client.
+//!
+//! The key is generated when the process starts and never leaves it. The
+//! same scheme signs the requests runtimes of a cluster send each other, with
+//! a key they share instead.
+
+use http::{HeaderValue, Request};
+use once_cell::sync::Lazy;
+use ring::hmac;
+use ring::rand::SystemRandom;
+
+/// Headers starting with this prefix carry metadata set by the runtime.
+pub const INTERNAL_HEADER_PREFIX: &str = "x-sb-";
+
+/// Carries the signature over the other internal headers of a request,
+/// hex-encoded.
+pub const SIGNATURE_HEADER: &str = "x-sb-signature";
+
+/// A key to sign the internal headers of requests with.
+#[derive(Clone)]
+pub struct SigningKey(hmac::Key);
+
+impl SigningKey {
+    pub fn new(secret: &[u8]) -> Self {
+        Self(hmac::Key::new(hmac::HMAC_SHA256, secret))
+    }
+}
+
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SigningKey")
+    }
+}
+
+static KEY: Lazy<SigningKey> = Lazy::new(|| {
+    SigningKey(
+        hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+            .expect("failed to generate the request signing key"),
+    )
+});
+
+/// Lays out the internal headers in a canonical form. Names are compared in
+/// lowercase and sorted, so the order the headers come in doesn't matter.
+fn signed_payload<'a>(headers: impl Iterator<Item = (&'a str, &'a [u8])>) -> Vec<u8> {
+    let mut pairs = headers
+        .map(|(name, value)| (name.to_ascii_lowercase(), value))
+        .filter(|(name, _)| name.starts_with(INTERNAL_HEADER_PREFIX) && name != SIGNATURE_HEADER)
+        .collect::<Vec<_>>();
+
+    pairs.sort();
+
+    let mut payload = vec![];
+
+    for (name, value) in pairs {
+        payload.extend_from_slice(name.as_bytes());
+        payload.push(b':');
+        payload.extend_from_slice(value);
+        payload.push(b'\n');
+    }
+
+    payload
+}
+
+/// Signs the internal headers of `req`, replacing any signature it had.
+pub fn sign<B>(req: &mut Request<B>) {
+    sign_with(&KEY, req);
+}
+
+/// Same as [`sign`], with the given key.
+pub fn sign_with<B>(key: &SigningKey, req: &mut Request<B>) {
+    let payload = signed_payload(
+        req.headers()
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_bytes())),
+    );
+
+    let tag = hmac::sign(&key.0, &payload);
+    let value = HeaderValue::from_str(&faster_hex::hex_string(tag.as_ref())).unwrap();
+
+    req.headers_mut().insert(SIGNATURE_HEADER, value);
+}
+
+/// Tells whether the internal headers among `headers` were signed by this
+/// process and left untouched since.
+pub fn verify<'a>(headers: impl Iterator<Item = (&'a str, &'a str)> + Clone) -> bool {
+    verify_with(&KEY, headers)
+}
+
+/// Same as [`verify`], with the given key.
+pub fn verify_with<'a>(
+    key: &SigningKey,
+    headers: impl Iterator<Item = (&'a str, &'a str)> + Clone,
+) -> bool {
+    let Some(signature) = headers
+        .clone()
+        .find(|(name, _)| name.eq_ignore_ascii_case(SIGNATURE_HEADER))
+        .and_then(|(_, value)| {
+            let mut buf = vec![0; value.len() / 2];
+
+            faster_hex::hex_decode(value.as_bytes(), &mut buf)
+                .ok()
+                .map(|_| buf)
+        })
+    else {
+        return false;
+    };
+
+    let payload = signed_payload(headers.map(|(name, value)| (name, value.as_bytes())));
+
+    hmac::verify(&key.0, &payload, &signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers_of(req: &Request<()>) -> Vec<(String, String)> {
+        req.headers()
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_str().unwrap().to_string()))
+            .collect()
+    }
+
+    fn verify_pairs(pairs: &[(String, String)]) -> bool {
+        verify(
+            pairs
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        )
+    }
+
+    #[test]
+    fn verifies_signed_headers() {
+        let mut req = Request::builder()
+            .header("x-sb-client-ip", "127.0.0.1")
+            .header("x-other", "meow")
+            .body(())
+            .unwrap();
+
+        sign(&mut req);
+
+        let mut pairs = headers_of(&req);
+
+        assert!(verify_pairs(&pairs));
+
+        // Headers other than the internal ones aren't covered.
+        pairs.push(("x-another".into(), "meow".into()));
+        assert!(verify_pairs(&pairs));
+    }
+
+    #[test]
+    fn rejects_tampered_headers() {
+        let mut req = Request::builder()
+            .header("x-sb-client-ip", "127.0.0.1")
+            .body(())
+            .unwrap();
+
+        sign(&mut req);
+
+        let mut pairs = headers_of(&req);
+
+        for (name, value) in pairs.iter_mut() {
+            if name == "x-sb-client-ip" {
+                *value = "1.2.3.4".into();
+            }
+        }
+
+        assert!(!verify_pairs(&pairs));
+
+        let mut pairs = headers_of(&req);
+
+        pairs.push(("x-sb-jwt-claims".into(), "spoofed".into()));
+        assert!(!verify_pairs(&pairs));
+    }
+
+    #[test]
+    fn rejects_headers_signed_with_another_key() {
+        let mut req = Request::builder()
+            .header("x-sb-client-ip", "127.0.0.1")
+            .body(())
+            .unwrap();
+
+        sign_with(&SigningKey::new(b"cluster"), &mut req);
+
+        let pairs = headers_of(&req);
+        let headers = || {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+        };
+
+        assert!(verify_with(&SigningKey::new(b"cluster"), headers()));
+        assert!(!verify_with(&SigningKey::new(b"other"), headers()));
+        assert!(!verify_pairs(&pairs));
+    }
+
+    #[test]
+    fn rejects_unsigned_headers() {
+        let pairs = vec![("x-sb-client-ip".to_string(), "127.0.0.1".to_string())];
+
+        assert!(!verify_pairs(&pairs));
+    }
+}
@@ -0,0 +1,152 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Context;
+use deno_core::error::{custom_error, AnyError};
+use deno_core::{op2, OpState};
+use log::warn;
+use serde::Serialize;
+
+/// Largest checkpoint a worker may write.
+const MAX_CHECKPOINT_BYTES: usize = 8 * 1024 * 1024;
+
+/// Where a user worker checkpoints the state object it declared through
+/// `EdgeRuntime.state`. Workers without one in their op state keep their
+/// state in memory only.
+#[derive(Debug, Clone)]
+pub struct StateCheckpoint {
+    pub path: PathBuf,
+    pub interval_ms: u64,
+}
+
+impl StateCheckpoint {
+    /// A path next to the checkpoint that no other write uses, even one of
+    /// another worker or process sharing the checkpoint.
+    fn temp_path(&self) -> PathBuf {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        let mut path = self.path.clone().into_os_string();
+
+        path.push(format!(
+            ".{}.{}.tmp",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        path.into()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateCheckpointInfo {
+    interval_ms: u64,
+    /// The last checkpoint, if there is one.
+    restored: Option<String>,
+}
+
+/// Reads the last checkpoint at boot. Returns `None` if the worker has no
+/// checkpoint path. A checkpoint that can't be read is logged and the worker
+/// starts from an empty state, rather than failing to boot.
+#[op2]
+#[serde]
+pub fn op_state_checkpoint_open(
+    state: &mut OpState,
+) -> Result<Option<StateCheckpointInfo>, AnyError> {
+    let Some(checkpoint) = state.try_borrow::<StateCheckpoint>() else {
+        return Ok(None);
+    };
+
+    let restored = match std::fs::read_to_string(&checkpoint.path) {
+        Ok(it) => Some(it),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => {
+            warn!(
+                "could not read state checkpoint: {}: {}",
+                checkpoint.path.display(),
+                err
+            );
+
+            None
+        }
+    };
+
+    Ok(Some(StateCheckpointInfo {
+        interval_ms: checkpoint.interval_ms,
+        restored,
+    }))
+}
+
+/// Replaces the checkpoint with `data`. The data is written next to it first
+/// and renamed over it, so a worker killed halfway leaves the previous
+/// checkpoint in place.
+#[op2(async)]
+pub async fn op_state_checkpoint_write(
+    state: Rc<RefCell<OpState>>,
+    #[string] data: String,
+) -> Result<(), AnyError> {
+    let Some(checkpoint) = state.borrow().try_borrow::<StateCheckpoint>().cloned() else {
+        return Err(custom_error(
+            "NotSupported",
+            "worker has no state checkpoint path",
+        ));
+    };
+
+    if data.len() > MAX_CHECKPOINT_BYTES {
+        return Err(custom_error(
+            "RangeError",
+            format!(
+                "state checkpoint is larger than {} bytes",
+                MAX_CHECKPOINT_BYTES
+            ),
+        ));
+    }
+
+    let temp_path = checkpoint.temp_path();
+
+    if let Some(parent) = checkpoint.path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let result = async {
+        tokio::fs::write(&temp_path, data).await?;
+        tokio::fs::rename(&temp_path, &checkpoint.path).await
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+    }
+
+    result.with_context(|| {
+        format!(
+            "could not write state checkpoint: {}",
+            checkpoint.path.display()
+        )
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_temp_paths_are_unique() {
+        let checkpoint = StateCheckpoint {
+            path: PathBuf::from("/tmp/state.json"),
+            interval_ms: 1000,
+        };
+
+        let a = checkpoint.temp_path();
+        let b = checkpoint.temp_path();
+
+        assert_ne!(a, b);
+        assert_eq!(a.parent(), checkpoint.path.parent());
+        assert!(a
+            .to_string_lossy()
+            .starts_with(&format!("/tmp/state.json.{}.", std::process::id())));
+    }
+}
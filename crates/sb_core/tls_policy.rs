@@ -0,0 +1,187 @@
+//! TLS policy for the outbound requests of a user worker. It is enforced by
+//! the client that `fetch` goes through, so functions don't have to set up
+//! their own clients correctly.
+
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use deno_core::error::{type_error, AnyError};
+use deno_tls::rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use deno_tls::rustls::{
+    self, Certificate, ClientConfig, RootCertStore, ServerName, SupportedProtocolVersion,
+};
+use deno_tls::rustls_pemfile;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct OutboundTlsPolicy {
+    /// Lowest protocol version the worker may negotiate.
+    pub min_version: TlsVersion,
+    /// PEM bundles of CAs trusted in addition to those of the runtime, such
+    /// as the ones of internal services.
+    pub ca_certs: Vec<String>,
+    /// Base64 SHA-256 fingerprints of DER certificates. If not empty, a
+    /// server is only trusted if its chain has one of them, on top of being
+    /// valid.
+    pub pinned_certs: Vec<String>,
+}
+
+impl OutboundTlsPolicy {
+    /// Builds the TLS configuration of a client following the policy, on top
+    /// of the roots of the runtime.
+    pub fn client_config(&self, root_cert_store: &RootCertStore) -> Result<ClientConfig, AnyError> {
+        let mut root_cert_store = root_cert_store.clone();
+
+        for bundle in &self.ca_certs {
+            let certs = rustls_pemfile::certs(&mut BufReader::new(bundle.as_bytes()))
+                .map_err(|err| type_error(format!("invalid CA bundle: {}", err)))?;
+
+            if certs.is_empty() {
+                return Err(type_error("CA bundle has no certificates"));
+            }
+
+            let (_, ignored) = root_cert_store.add_parsable_certificates(&certs);
+
+            if ignored > 0 {
+                return Err(type_error("CA bundle has invalid certificates"));
+            }
+        }
+
+        let versions: &[&SupportedProtocolVersion] = match self.min_version {
+            TlsVersion::Tls12 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+            TlsVersion::Tls13 => &[&rustls::version::TLS13],
+        };
+
+        let pins = self
+            .pinned_certs
+            .iter()
+            .map(|it| match base64::decode(it) {
+                Ok(it) if it.len() == SHA256.output_len() => Ok(it),
+                _ => Err(type_error(format!(
+                    "invalid certificate fingerprint: {}",
+                    it
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut config = ClientConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(versions)?
+            .with_root_certificates(root_cert_store.clone())
+            .with_no_client_auth();
+
+        if !pins.is_empty() {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(PinnedCertVerifier {
+                    inner: WebPkiVerifier::new(root_cert_store, None),
+                    pins,
+                }));
+        }
+
+        config.alpn_protocols = vec!["h2".into(), "http/1.1".into()];
+
+        Ok(config)
+    }
+}
+
+/// Verifies the chain as usual, then requires one of its certificates to
+/// match a pin.
+struct PinnedCertVerifier {
+    inner: WebPkiVerifier,
+    pins: Vec<Vec<u8>>,
+}
+
+impl PinnedCertVerifier {
+    fn is_pinned(&self, cert: &Certificate) -> bool {
+        let fingerprint = digest(&SHA256, &cert.0);
+
+        self.pins.iter().any(|it| it == fingerprint.as_ref())
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+
+        if std::iter::once(end_entity)
+            .chain(intermediates)
+            .any(|it| self.is_pinned(it))
+        {
+            Ok(verified)
+        } else {
+            Err(rustls::Error::General(
+                "server certificate does not match any pin".to_string(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_malformed_pins_and_bundles() {
+        let roots = RootCertStore::empty();
+
+        assert!(OutboundTlsPolicy {
+            pinned_certs: vec!["not a fingerprint".to_string()],
+            ..Default::default()
+        }
+        .client_config(&roots)
+        .is_err());
+
+        assert!(OutboundTlsPolicy {
+            ca_certs: vec!["no certificates here".to_string()],
+            ..Default::default()
+        }
+        .client_config(&roots)
+        .is_err());
+
+        assert!(OutboundTlsPolicy {
+            min_version: TlsVersion::Tls13,
+            pinned_certs: vec![base64::encode([0u8; 32])],
+            ..Default::default()
+        }
+        .client_config(&roots)
+        .is_ok());
+    }
+
+    #[test]
+    fn deserializes_min_version() {
+        let policy: OutboundTlsPolicy =
+            deno_core::serde_json::from_str(r#"{ "minVersion": "1.3" }"#).unwrap();
+
+        assert_eq!(policy.min_version, TlsVersion::Tls13);
+        assert!(policy.ca_certs.is_empty());
+    }
+}
@@ -3,6 +3,8 @@ pub mod errors;
 pub mod fs;
 pub mod http_util;
 pub mod path;
+pub mod rate_limit;
+pub mod shared_backend;
 pub mod sync;
 pub mod text_encoding;
 pub mod versions_util;
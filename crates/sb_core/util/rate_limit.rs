@@ -0,0 +1,78 @@
+use std::time::Instant;
+
+/// Token bucket that lets a worker start `per_second` operations per second,
+/// in bursts of as many.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_second: u32,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: u32) -> Self {
+        Self {
+            per_second,
+            tokens: per_second as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    pub fn per_second(&self) -> u32 {
+        self.per_second
+    }
+
+    /// Takes a token if there is one left.
+    pub fn try_acquire(&mut self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        let capacity = self.per_second as f64;
+
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * capacity).min(capacity);
+        self.refilled_at = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_refills_over_time() {
+        let mut limiter = RateLimiter::new(2);
+        let start = limiter.refilled_at;
+
+        assert!(limiter.try_acquire_at(start));
+        assert!(limiter.try_acquire_at(start));
+        assert!(!limiter.try_acquire_at(start));
+
+        assert!(limiter.try_acquire_at(start + Duration::from_millis(500)));
+        assert!(!limiter.try_acquire_at(start + Duration::from_millis(500)));
+
+        // The bucket never holds more than a second worth of tokens.
+        let later = start + Duration::from_secs(10);
+
+        assert!(limiter.try_acquire_at(later));
+        assert!(limiter.try_acquire_at(later));
+        assert!(!limiter.try_acquire_at(later));
+    }
+
+    #[test]
+    fn test_rate_limiter_of_zero_denies_everything() {
+        let mut limiter = RateLimiter::new(0);
+
+        assert!(!limiter.try_acquire());
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::{anyhow, Error};
+use deno_core::error::{custom_error, AnyError};
+use once_cell::sync::OnceCell;
+
+/// A backend shared by every worker of the process, like a connection pool,
+/// which is set up once at startup. Ops fail until it has been set up.
+pub struct SharedBackend<T> {
+    name: &'static str,
+    cell: OnceCell<T>,
+}
+
+impl<T> SharedBackend<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            cell: OnceCell::new(),
+        }
+    }
+
+    pub fn init(&self, backend: T) -> Result<(), Error> {
+        self.cell
+            .set(backend)
+            .map_err(|_| anyhow!("{} is already initialized", self.name))
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.cell.get()
+    }
+
+    /// Returns the backend, or the error an op throws if it isn't set up.
+    pub fn require(&self) -> Result<&T, AnyError> {
+        self.get()
+            .ok_or_else(|| custom_error("NotSupported", format!("{} is not configured", self.name)))
+    }
+
+    /// Returns the quota a user worker gets out of `limit`. Zero falls back to
+    /// the per-worker limit of the backend.
+    pub fn quota_for_worker<N, Q>(
+        &self,
+        limit: N,
+        default_fn: impl FnOnce(&T) -> N,
+        quota_fn: impl FnOnce(N) -> Q,
+    ) -> Option<Q>
+    where
+        N: Default + PartialEq,
+    {
+        let backend = self.get()?;
+
+        Some(quota_fn(if limit == N::default() {
+            default_fn(backend)
+        } else {
+            limit
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_quota_for_worker_falls_back_to_backend_limit() {
+        let backend = SharedBackend::<usize>::new("meow pool");
+
+        assert!(backend.require().is_err());
+        assert_eq!(backend.quota_for_worker(0, |it| *it, Some), None);
+
+        backend.init(8).unwrap();
+
+        assert!(backend.init(16).is_err());
+        assert_eq!(backend.quota_for_worker(0, |it| *it, Some), Some(Some(8)));
+        assert_eq!(backend.quota_for_worker(2, |it| *it, Some), Some(Some(2)));
+    }
+}
@@ -0,0 +1,44 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+function toBytes(data) {
+	if (typeof data === 'string') {
+		return new TextEncoder().encode(data);
+	}
+
+	if (ArrayBuffer.isView(data)) {
+		return new Uint8Array(data.buffer, data.byteOffset, data.byteLength);
+	}
+
+	return new Uint8Array(data);
+}
+
+/**
+ * Expensive crypto primitives that run on the host instead of the worker's
+ * CPU budget. Each call counts against the rate limit of the worker.
+ *
+ * ```js
+ * const hash = await Supabase.crypto.hashPassword(password, { algorithm: 'argon2id' });
+ * const ok = await Supabase.crypto.verifyPassword(password, hash);
+ * const { algorithm, signature } = await Supabase.crypto.sign('jwt', payload);
+ * ```
+ */
+const crypto = {
+	hashPassword(password, opts = {}) {
+		return ops.op_crypto_hash_password(String(password), opts);
+	},
+
+	verifyPassword(password, hash) {
+		return ops.op_crypto_verify_password(String(password), String(hash));
+	},
+
+	/**
+	 * Signs the data with a key of the host, which the worker must have
+	 * been granted. RSA keys sign with RS256 and P-256 keys with ES256.
+	 */
+	sign(key, data) {
+		return ops.op_crypto_sign(String(key), toBytes(data));
+	},
+};
+
+export { crypto };
@@ -0,0 +1,81 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Error};
+use p256::ecdsa::signature::Signer;
+use p256::pkcs8::DecodePrivateKey;
+use rsa::pkcs1v15;
+use rsa::signature::SignatureEncoding;
+use rsa::RsaPrivateKey;
+use serde::Serialize;
+use sha2::Sha256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SigningAlgorithm {
+    /// RSASSA-PKCS1-v1_5 with SHA-256.
+    #[serde(rename = "RS256")]
+    Rs256,
+    /// ECDSA on P-256 with SHA-256. Signatures are the raw `r || s` bytes,
+    /// like the ones of WebCrypto.
+    #[serde(rename = "ES256")]
+    Es256,
+}
+
+/// A private key of the host that workers can sign with, without ever
+/// seeing it.
+pub enum HostKey {
+    Rsa(pkcs1v15::SigningKey<Sha256>),
+    EcdsaP256(p256::ecdsa::SigningKey),
+}
+
+impl HostKey {
+    /// Reads a PEM encoded PKCS#8 RSA or P-256 private key.
+    pub fn from_pkcs8_pem(pem: &str) -> Result<Self, Error> {
+        if let Ok(key) = RsaPrivateKey::from_pkcs8_pem(pem) {
+            return Ok(Self::Rsa(pkcs1v15::SigningKey::new(key)));
+        }
+
+        if let Ok(key) = p256::ecdsa::SigningKey::from_pkcs8_pem(pem) {
+            return Ok(Self::EcdsaP256(key));
+        }
+
+        bail!("expected a PKCS#8 RSA or P-256 private key")
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let pem = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read key file: {}", path.display()))?;
+
+        Self::from_pkcs8_pem(&pem).with_context(|| format!("invalid key file: {}", path.display()))
+    }
+
+    pub fn algorithm(&self) -> SigningAlgorithm {
+        match self {
+            Self::Rsa(_) => SigningAlgorithm::Rs256,
+            Self::EcdsaP256(_) => SigningAlgorithm::Es256,
+        }
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Rsa(key) => key.sign(data).to_vec(),
+            Self::EcdsaP256(key) => {
+                let signature: p256::ecdsa::Signature = key.sign(data);
+
+                signature.to_vec()
+            }
+        }
+    }
+}
+
+/// Parses a `NAME=PATH` pair of the `--crypto-key` flag.
+pub fn parse_key_arg(arg: &str) -> Result<(String, HostKey), Error> {
+    let Some((name, path)) = arg.split_once('=') else {
+        bail!("expected NAME=PATH: {}", arg);
+    };
+
+    if name.is_empty() {
+        bail!("key name must not be empty: {}", arg);
+    }
+
+    Ok((name.to_string(), HostKey::from_file(Path::new(path))?))
+}
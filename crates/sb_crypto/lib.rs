@@ -0,0 +1,159 @@
+mod keys;
+mod password;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use anyhow::Error;
+use deno_core::error::{custom_error, AnyError};
+use deno_core::unsync::spawn_blocking;
+use deno_core::{op2, JsBuffer, OpState, ToJsBuffer};
+use once_cell::sync::OnceCell;
+use sb_core::util::rate_limit::RateLimiter;
+use serde::Serialize;
+
+pub use crate::keys::{parse_key_arg, HostKey, SigningAlgorithm};
+pub use crate::password::{Algorithm, HashOptions, MAX_BCRYPT_COST};
+
+static HOST_KEYS: OnceCell<HashMap<String, HostKey>> = OnceCell::new();
+
+/// Sets up the host keys workers can sign with, by name.
+pub fn init(keys: HashMap<String, HostKey>) -> Result<(), Error> {
+    HOST_KEYS
+        .set(keys)
+        .map_err(|_| anyhow::anyhow!("host keys are already initialized"))
+}
+
+fn host_key(name: &str) -> Option<&'static HostKey> {
+    HOST_KEYS.get()?.get(name)
+}
+
+/// How much of the host crypto ops a worker may use. Workers without a
+/// policy in their op state, like the main worker, are not limited and may
+/// sign with every host key.
+#[derive(Debug)]
+pub struct CryptoPolicy {
+    pub limiter: RateLimiter,
+    pub keys: HashSet<String>,
+}
+
+impl CryptoPolicy {
+    pub fn new(ops_per_second: u32, keys: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            limiter: RateLimiter::new(ops_per_second),
+            keys: keys.into_iter().collect(),
+        }
+    }
+}
+
+fn acquire(state: &RefCell<OpState>, api_name: &str) -> Result<(), AnyError> {
+    let mut state = state.borrow_mut();
+    let Some(policy) = state.try_borrow_mut::<CryptoPolicy>() else {
+        return Ok(());
+    };
+
+    if policy.limiter.per_second() == 0 {
+        return Err(custom_error(
+            "PermissionDenied",
+            format!("{} is disabled for the worker", api_name),
+        ));
+    }
+
+    if !policy.limiter.try_acquire() {
+        return Err(custom_error(
+            "RateLimited",
+            format!(
+                "{} can be called {} times per second",
+                api_name,
+                policy.limiter.per_second()
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_key(state: &RefCell<OpState>, name: &str) -> Result<&'static HostKey, AnyError> {
+    if let Some(policy) = state.borrow().try_borrow::<CryptoPolicy>() {
+        if !policy.keys.contains(name) {
+            return Err(custom_error(
+                "PermissionDenied",
+                format!("host key is not granted to the worker: {}", name),
+            ));
+        }
+    }
+
+    host_key(name).ok_or_else(|| custom_error("NotFound", format!("host key not found: {}", name)))
+}
+
+fn crypto_error(err: Error) -> AnyError {
+    custom_error("TypeError", format!("{:#}", err))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignResult {
+    algorithm: SigningAlgorithm,
+    signature: ToJsBuffer,
+}
+
+/// The ops below run on the blocking pool, so the work is not billed to the
+/// CPU time of the worker. The rate limit of the worker bounds how much of
+/// the host it can take instead.
+#[op2(async)]
+#[serde]
+async fn op_crypto_hash_password(
+    state: Rc<RefCell<OpState>>,
+    #[string] password: String,
+    #[serde] opts: HashOptions,
+) -> Result<String, AnyError> {
+    acquire(&state, "Supabase.crypto.hashPassword()")?;
+
+    spawn_blocking(move || password::hash(&password, opts))
+        .await?
+        .map_err(crypto_error)
+}
+
+#[op2(async)]
+async fn op_crypto_verify_password(
+    state: Rc<RefCell<OpState>>,
+    #[string] password: String,
+    #[string] hash: String,
+) -> Result<bool, AnyError> {
+    acquire(&state, "Supabase.crypto.verifyPassword()")?;
+
+    spawn_blocking(move || password::verify(&password, &hash))
+        .await?
+        .map_err(crypto_error)
+}
+
+#[op2(async)]
+#[serde]
+async fn op_crypto_sign(
+    state: Rc<RefCell<OpState>>,
+    #[string] key: String,
+    #[buffer] data: JsBuffer,
+) -> Result<SignResult, AnyError> {
+    let key = check_key(&state, &key)?;
+
+    acquire(&state, "Supabase.crypto.sign()")?;
+
+    let signature = spawn_blocking(move || key.sign(&data)).await?;
+
+    Ok(SignResult {
+        algorithm: key.algorithm(),
+        signature: signature.into(),
+    })
+}
+
+deno_core::extension!(
+    sb_crypto,
+    ops = [
+        op_crypto_hash_password,
+        op_crypto_verify_password,
+        op_crypto_sign
+    ],
+    esm_entry_point = "ext:sb_crypto/crypto.js",
+    esm = ["crypto.js"]
+);
@@ -0,0 +1,132 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Error};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Argon2, Params};
+use bcrypt::HashParts;
+use rand::rngs::OsRng;
+use serde::Deserialize;
+
+pub const DEFAULT_BCRYPT_COST: u32 = 10;
+
+/// Highest bcrypt cost the host will hash or verify with.
+pub const MAX_BCRYPT_COST: u32 = 14;
+
+/// Highest argon2 memory cost, in KiB, the host will verify with.
+pub const MAX_ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+
+/// Highest argon2 iteration count and parallelism the host will verify with.
+pub const MAX_ARGON2_ITERATIONS: u32 = 10;
+pub const MAX_ARGON2_PARALLELISM: u32 = 4;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    #[default]
+    Argon2id,
+    Bcrypt,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HashOptions {
+    #[serde(default)]
+    pub algorithm: Algorithm,
+    /// Cost of bcrypt hashes. Argon2id hashes always use the default
+    /// parameters of the `argon2` crate.
+    pub cost: Option<u32>,
+}
+
+/// Hashes the password into a PHC string for argon2id or a modular crypt
+/// string for bcrypt.
+pub fn hash(password: &str, opts: HashOptions) -> Result<String, Error> {
+    match opts.algorithm {
+        Algorithm::Argon2id => {
+            let salt = SaltString::generate(&mut OsRng);
+
+            Ok(Argon2::default()
+                .hash_password(password.as_bytes(), &salt)
+                .map_err(|err| anyhow!("{}", err))?
+                .to_string())
+        }
+
+        Algorithm::Bcrypt => {
+            let cost = opts.cost.unwrap_or(DEFAULT_BCRYPT_COST);
+
+            if cost > MAX_BCRYPT_COST {
+                bail!("bcrypt cost must not be higher than {}", MAX_BCRYPT_COST);
+            }
+
+            Ok(bcrypt::hash(password, cost)?)
+        }
+    }
+}
+
+/// Checks the password against a hash made by [`hash`] or by another
+/// argon2 or bcrypt implementation. Hashes asking for more work than the
+/// host allows are refused instead of verified.
+pub fn verify(password: &str, hash: &str) -> Result<bool, Error> {
+    if hash.starts_with("$2") {
+        let parts = HashParts::from_str(hash)?;
+
+        if parts.get_cost() > MAX_BCRYPT_COST {
+            bail!("bcrypt cost must not be higher than {}", MAX_BCRYPT_COST);
+        }
+
+        return Ok(bcrypt::verify(password, hash)?);
+    }
+
+    let parsed = PasswordHash::new(hash).map_err(|err| anyhow!("{}", err))?;
+    let params = Params::try_from(&parsed).map_err(|err| anyhow!("{}", err))?;
+
+    if params.m_cost() > MAX_ARGON2_MEMORY_KIB
+        || params.t_cost() > MAX_ARGON2_ITERATIONS
+        || params.p_cost() > MAX_ARGON2_PARALLELISM
+    {
+        bail!("argon2 parameters of the hash are higher than the host allows");
+    }
+
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &parsed)
+        .is_ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash_and_verify() {
+        for algorithm in [Algorithm::Argon2id, Algorithm::Bcrypt] {
+            let hash = hash(
+                "hunter2",
+                HashOptions {
+                    algorithm,
+                    cost: Some(4),
+                },
+            )
+            .unwrap();
+
+            assert!(verify("hunter2", &hash).unwrap());
+            assert!(!verify("hunter3", &hash).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_verify_refuses_expensive_hashes() {
+        let hash = "$argon2id$v=19$m=1048576,t=2,p=1$c29tZXNhbHQ$RdescudvJCsgt3ub+b+dWRWJTmaaJObG";
+
+        assert!(verify("password", hash).is_err());
+        assert!(hash_with_cost(MAX_BCRYPT_COST + 1).is_err());
+    }
+
+    fn hash_with_cost(cost: u32) -> Result<String, Error> {
+        hash(
+            "password",
+            HashOptions {
+                algorithm: Algorithm::Bcrypt,
+                cost: Some(cost),
+            },
+        )
+    }
+}
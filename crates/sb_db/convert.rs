@@ -0,0 +1,104 @@
+use std::error::Error as StdError;
+
+use bytes::BytesMut;
+use deno_core::serde_json::{Map, Number, Value};
+use tokio_postgres::types::{to_sql_checked, IsNull, ToSql, Type};
+use tokio_postgres::Row;
+
+type BoxError = Box<dyn StdError + Sync + Send>;
+
+/// A query parameter coming from JS. It is encoded according to the type
+/// Postgres inferred for its placeholder.
+#[derive(Debug)]
+pub(crate) struct JsonParam(pub Value);
+
+impl ToSql for JsonParam {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, BoxError> {
+        let value = &self.0;
+
+        if value.is_null() {
+            return Ok(IsNull::Yes);
+        }
+
+        match *ty {
+            Type::BOOL => expect(value.as_bool(), value, ty)?.to_sql(ty, out),
+            Type::INT2 => i16::try_from(expect(value.as_i64(), value, ty)?)?.to_sql(ty, out),
+            Type::INT4 => i32::try_from(expect(value.as_i64(), value, ty)?)?.to_sql(ty, out),
+            Type::INT8 => match value {
+                // NOTE: Large integers don't survive the trip through a JS
+                // number, so they may also be passed as strings.
+                Value::String(s) => s.parse::<i64>()?.to_sql(ty, out),
+                _ => expect(value.as_i64(), value, ty)?.to_sql(ty, out),
+            },
+            Type::OID => u32::try_from(expect(value.as_u64(), value, ty)?)?.to_sql(ty, out),
+            Type::FLOAT4 => (expect(value.as_f64(), value, ty)? as f32).to_sql(ty, out),
+            Type::FLOAT8 => expect(value.as_f64(), value, ty)?.to_sql(ty, out),
+            Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN => {
+                expect(value.as_str(), value, ty)?.to_sql(ty, out)
+            }
+            Type::JSON | Type::JSONB => value.to_sql(ty, out),
+            _ => Err(format!("parameters of type {} are not supported", ty).into()),
+        }
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+fn expect<T>(value: Option<T>, raw: &Value, ty: &Type) -> Result<T, BoxError> {
+    value.ok_or_else(|| format!("cannot encode {} as {}", raw, ty).into())
+}
+
+/// Converts a row into a JSON object keyed by column name.
+pub(crate) fn row_to_json(row: &Row) -> Result<Map<String, Value>, BoxError> {
+    let mut map = Map::with_capacity(row.len());
+
+    for (idx, column) in row.columns().iter().enumerate() {
+        let ty = column.type_();
+        let value = match *ty {
+            Type::BOOL => row.try_get::<_, Option<bool>>(idx)?.map(Value::Bool),
+            Type::INT2 => row
+                .try_get::<_, Option<i16>>(idx)?
+                .map(|it| Value::from(it as i64)),
+            Type::INT4 => row
+                .try_get::<_, Option<i32>>(idx)?
+                .map(|it| Value::from(it as i64)),
+            // NOTE: Same as the parameters, `int8` is returned as a string so
+            // that it keeps its precision in JS.
+            Type::INT8 => row
+                .try_get::<_, Option<i64>>(idx)?
+                .map(|it| Value::String(it.to_string())),
+            Type::OID => row.try_get::<_, Option<u32>>(idx)?.map(Value::from),
+            Type::FLOAT4 => row
+                .try_get::<_, Option<f32>>(idx)?
+                .and_then(|it| Number::from_f64(it as f64))
+                .map(Value::Number),
+            Type::FLOAT8 => row
+                .try_get::<_, Option<f64>>(idx)?
+                .and_then(Number::from_f64)
+                .map(Value::Number),
+            Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN => {
+                row.try_get::<_, Option<String>>(idx)?.map(Value::String)
+            }
+            Type::JSON | Type::JSONB => row.try_get::<_, Option<Value>>(idx)?,
+            Type::BYTEA => row
+                .try_get::<_, Option<Vec<u8>>>(idx)?
+                .map(|it| Value::Array(it.into_iter().map(Value::from).collect())),
+            _ => {
+                return Err(format!(
+                    "column `{}` has unsupported type {}; cast it to text in the query",
+                    column.name(),
+                    ty
+                )
+                .into())
+            }
+        };
+
+        map.insert(column.name().to_string(), value.unwrap_or(Value::Null));
+    }
+
+    Ok(map)
+}
@@ -0,0 +1,22 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+/**
+ * Runs queries through the connection pool of the runtime, which is shared
+ * by every worker.
+ *
+ * ```js
+ * const { rows } = await Supabase.db.query('select * from todos where id = $1', [id]);
+ * ```
+ */
+const db = {
+	query(sql, params = []) {
+		return ops.op_db_query(String(sql), params);
+	},
+
+	metrics() {
+		return ops.op_db_metrics();
+	},
+};
+
+export { db };
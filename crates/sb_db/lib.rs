@@ -0,0 +1,126 @@
+mod convert;
+mod pool;
+mod tls;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::Error;
+use deno_core::error::{custom_error, AnyError};
+use deno_core::serde_json::{Map, Value};
+use deno_core::{op2, OpState};
+use sb_core::util::shared_backend::SharedBackend;
+use serde::Serialize;
+use tokio::time::Instant;
+use tokio_postgres::types::ToSql;
+
+use crate::convert::{row_to_json, JsonParam};
+
+pub use crate::pool::{DbPool, DbPoolConfig, DbPoolMetrics, DbQuota, DbQuotaMetrics};
+
+static DB_POOL: SharedBackend<DbPool> = SharedBackend::new("database pool");
+
+/// Sets up the pool shared by every worker of the process. Workers can only
+/// query the database once this has been called.
+pub fn init(config: DbPoolConfig) -> Result<(), Error> {
+    DB_POOL.init(DbPool::new(config)?)
+}
+
+pub fn pool() -> Option<&'static DbPool> {
+    DB_POOL.get()
+}
+
+pub fn quota_for_worker(max_connections: usize) -> Option<DbQuota> {
+    DB_POOL.quota_for_worker(
+        max_connections,
+        |it| it.max_connections_per_worker,
+        DbQuota::new,
+    )
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryResult {
+    rows: Vec<Map<String, Value>>,
+    row_count: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DbMetrics {
+    pool: DbPoolMetrics,
+    worker: Option<DbQuotaMetrics>,
+}
+
+#[op2(async)]
+#[serde]
+async fn op_db_query(
+    state: Rc<RefCell<OpState>>,
+    #[string] sql: String,
+    #[serde] params: Vec<Value>,
+) -> Result<QueryResult, AnyError> {
+    let pool = DB_POOL.require()?;
+    let maybe_quota = state.borrow().try_borrow::<DbQuota>().cloned();
+
+    // NOTE: The acquire timeout bounds the whole wait, for the quota of the
+    // worker and for the pool alike.
+    let deadline = Instant::now() + pool.acquire_timeout();
+    let _quota_permit = match maybe_quota {
+        Some(quota) => Some(quota.acquire(deadline).await?),
+        None => None,
+    };
+
+    let client = pool.acquire(deadline).await?;
+    let result = async {
+        let stmt = client.prepare(&sql).await?;
+        let params = params.into_iter().map(JsonParam).collect::<Vec<_>>();
+        let params = params
+            .iter()
+            .map(|it| it as &(dyn ToSql + Sync))
+            .collect::<Vec<_>>();
+
+        if stmt.columns().is_empty() {
+            let row_count = client.execute(&stmt, &params).await?;
+
+            return Ok::<_, AnyError>(QueryResult {
+                rows: vec![],
+                row_count,
+            });
+        }
+
+        let rows = client
+            .query(&stmt, &params)
+            .await?
+            .iter()
+            .map(row_to_json)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| custom_error("TypeError", err.to_string()))?;
+
+        Ok(QueryResult {
+            row_count: rows.len() as u64,
+            rows,
+        })
+    }
+    .await;
+
+    pool.record_query(result.is_err());
+    result
+}
+
+#[op2]
+#[serde]
+fn op_db_metrics(state: &mut OpState) -> Result<DbMetrics, AnyError> {
+    let pool = DB_POOL.require()?;
+
+    Ok(DbMetrics {
+        pool: pool.metrics(),
+        worker: state.try_borrow::<DbQuota>().map(DbQuota::metrics),
+    })
+}
+
+deno_core::extension!(
+    sb_db,
+    ops = [op_db_query, op_db_metrics],
+    esm_entry_point = "ext:sb_db/db.js",
+    esm = ["db.js"]
+);
@@ -0,0 +1,270 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Error};
+use log::error;
+use serde::Serialize;
+use tokio::runtime::Runtime;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use tokio_postgres::Client;
+
+use crate::tls::MakeRustlsConnect;
+
+#[derive(Debug, Clone)]
+pub struct DbPoolConfig {
+    pub url: String,
+    /// Upper bound of the connections opened across every worker.
+    pub max_connections: usize,
+    /// How many connections a single user worker may hold at once, unless
+    /// the worker was created with its own quota.
+    pub max_connections_per_worker: usize,
+    /// How long a query may wait for a connection before it fails.
+    pub acquire_timeout: Duration,
+}
+
+#[derive(Debug, Default)]
+struct DbPoolMetricsInner {
+    open: AtomicUsize,
+    in_use: AtomicUsize,
+    waiting: AtomicUsize,
+    queries: AtomicUsize,
+    errors: AtomicUsize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DbPoolMetrics {
+    pub max_connections: usize,
+    pub open: usize,
+    pub idle: usize,
+    pub in_use: usize,
+    pub waiting: usize,
+    pub queries: usize,
+    pub errors: usize,
+}
+
+pub struct DbPool {
+    config: tokio_postgres::Config,
+    tls: MakeRustlsConnect,
+    max_connections: usize,
+    pub(crate) max_connections_per_worker: usize,
+    acquire_timeout: Duration,
+    permits: Arc<Semaphore>,
+    idle: Mutex<Vec<Client>>,
+    metrics: DbPoolMetricsInner,
+
+    // NOTE: Connections are driven by a runtime of their own since the pool
+    // outlives the runtime of any worker that happens to open them.
+    rt: Runtime,
+}
+
+impl DbPool {
+    pub fn new(config: DbPoolConfig) -> Result<Self, Error> {
+        let pg_config = config
+            .url
+            .parse::<tokio_postgres::Config>()
+            .context("invalid database url")?;
+
+        let mut roots = rustls::RootCertStore::empty();
+
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+        let tls_config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("sb-db-pool")
+            .enable_all()
+            .build()?;
+
+        Ok(Self {
+            config: pg_config,
+            tls: MakeRustlsConnect::new(tls_config),
+            max_connections: config.max_connections,
+            max_connections_per_worker: config.max_connections_per_worker,
+            acquire_timeout: config.acquire_timeout,
+            permits: Arc::new(Semaphore::new(config.max_connections)),
+            idle: Mutex::default(),
+            metrics: DbPoolMetricsInner::default(),
+            rt,
+        })
+    }
+
+    pub fn metrics(&self) -> DbPoolMetrics {
+        DbPoolMetrics {
+            max_connections: self.max_connections,
+            open: self.metrics.open.load(Ordering::Relaxed),
+            idle: self.idle.lock().unwrap().len(),
+            in_use: self.metrics.in_use.load(Ordering::Relaxed),
+            waiting: self.metrics.waiting.load(Ordering::Relaxed),
+            queries: self.metrics.queries.load(Ordering::Relaxed),
+            errors: self.metrics.errors.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn acquire_timeout(&self) -> Duration {
+        self.acquire_timeout
+    }
+
+    pub(crate) fn record_query(&self, is_err: bool) {
+        self.metrics.queries.fetch_add(1, Ordering::Relaxed);
+
+        if is_err {
+            self.metrics.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Checks out a connection, reusing an idle one if possible. Fails if
+    /// none is available by `deadline`, including the time it takes to open a
+    /// new one.
+    pub(crate) async fn acquire(&'static self, deadline: Instant) -> Result<PooledClient, Error> {
+        self.metrics.waiting.fetch_add(1, Ordering::Relaxed);
+
+        let permit = tokio::time::timeout_at(deadline, self.permits.clone().acquire_owned()).await;
+
+        self.metrics.waiting.fetch_sub(1, Ordering::Relaxed);
+
+        let permit = permit
+            .map_err(|_| anyhow!("timed out waiting for a database connection"))?
+            .expect("the semaphore is never closed");
+
+        let maybe_idle = loop {
+            let Some(client) = self.idle.lock().unwrap().pop() else {
+                break None;
+            };
+
+            if !client.is_closed() {
+                break Some(client);
+            }
+
+            self.metrics.open.fetch_sub(1, Ordering::Relaxed);
+        };
+
+        let client = match maybe_idle {
+            Some(client) => client,
+            None => tokio::time::timeout_at(deadline, self.connect())
+                .await
+                .map_err(|_| anyhow!("timed out connecting to the database"))??,
+        };
+
+        self.metrics.in_use.fetch_add(1, Ordering::Relaxed);
+
+        Ok(PooledClient {
+            pool: self,
+            client: Some(client),
+            permit: Some(permit),
+        })
+    }
+
+    async fn connect(&'static self) -> Result<Client, Error> {
+        let config = self.config.clone();
+        let tls = self.tls.clone();
+        let client = self
+            .rt
+            .spawn(async move {
+                let (client, connection) = config.connect(tls).await?;
+
+                tokio::spawn(async move {
+                    if let Err(err) = connection.await {
+                        error!("database connection closed with an error: {}", err);
+                    }
+                });
+
+                Ok::<_, tokio_postgres::Error>(client)
+            })
+            .await??;
+
+        self.metrics.open.fetch_add(1, Ordering::Relaxed);
+
+        Ok(client)
+    }
+}
+
+/// A connection checked out of the pool. It is reset and returned to the
+/// pool once dropped, unless it has been closed in the meantime.
+pub(crate) struct PooledClient {
+    pool: &'static DbPool,
+    client: Option<Client>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl std::ops::Deref for PooledClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Self::Target {
+        self.client.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        let client = self.client.take().unwrap();
+        let permit = self.permit.take();
+        let pool = self.pool;
+
+        pool.metrics.in_use.fetch_sub(1, Ordering::Relaxed);
+
+        // NOTE: Whatever a worker left behind in the session (settings,
+        // prepared statements, temporary tables, an open transaction) must not
+        // leak to the next worker, so the connection is reset before it goes
+        // back to the pool, and closed if that fails. The permit is held until
+        // then so that the connection still counts as checked out.
+        drop(pool.rt.spawn(async move {
+            let _permit = permit;
+
+            if client.is_closed() || client.batch_execute("DISCARD ALL").await.is_err() {
+                pool.metrics.open.fetch_sub(1, Ordering::Relaxed);
+                return;
+            }
+
+            pool.idle.lock().unwrap().push(client);
+        }));
+    }
+}
+
+/// Bounds the number of connections a single worker may hold at once.
+#[derive(Clone)]
+pub struct DbQuota {
+    max: usize,
+    permits: Arc<Semaphore>,
+    queries: Arc<AtomicUsize>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DbQuotaMetrics {
+    pub max_connections: usize,
+    pub in_use: usize,
+    pub queries: usize,
+}
+
+impl DbQuota {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            permits: Arc::new(Semaphore::new(max)),
+            queries: Arc::default(),
+        }
+    }
+
+    pub(crate) async fn acquire(&self, deadline: Instant) -> Result<OwnedSemaphorePermit, Error> {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+
+        tokio::time::timeout_at(deadline, self.permits.clone().acquire_owned())
+            .await
+            .map_err(|_| anyhow!("worker exceeded its database connection quota"))?
+            .map_err(Error::from)
+    }
+
+    pub fn metrics(&self) -> DbQuotaMetrics {
+        DbQuotaMetrics {
+            max_connections: self.max,
+            in_use: self.max - self.permits.available_permits(),
+            queries: self.queries.load(Ordering::Relaxed),
+        }
+    }
+}
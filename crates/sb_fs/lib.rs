@@ -13,6 +13,7 @@ use std::sync::Arc;
 
 pub mod file_system;
 pub mod static_fs;
+pub mod tmp_fs;
 pub mod virtual_fs;
 
 pub struct VfsOpts {
@@ -0,0 +1,813 @@
+use deno_core::{normalize_path, BufMutView, BufView, ResourceHandleFd, WriteOutcome};
+use deno_fs::{FileSystem, FsDirEntry, FsFileType, OpenOptions, RealFs};
+use deno_io::fs::{File, FsError, FsResult, FsStat};
+use log::error;
+use std::fmt::Debug;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A temporary directory owned by a single worker. The directory is removed
+/// along with everything in it once the last reference is dropped.
+#[derive(Debug)]
+pub struct TmpDir {
+    path: PathBuf,
+    quota_bytes: u64,
+    usage_bytes: AtomicU64,
+}
+
+impl TmpDir {
+    pub fn create(path: PathBuf, quota_bytes: u64) -> io::Result<Self> {
+        std::fs::create_dir_all(&path)?;
+
+        Ok(Self {
+            path,
+            quota_bytes,
+            usage_bytes: AtomicU64::new(0),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn usage_bytes(&self) -> u64 {
+        self.usage_bytes.load(Ordering::Acquire)
+    }
+
+    /// Measures the directory again. The usage is otherwise only estimated
+    /// from the writes that go through [`TmpDirFs::write_file_sync`] and
+    /// friends, or through the files it opens, so this is expected to be
+    /// called periodically.
+    pub fn rescan(&self) {
+        self.usage_bytes
+            .store(dir_size(&self.path), Ordering::Release);
+    }
+
+    fn ensure_room(&self, len: u64) -> FsResult<()> {
+        if self.quota_bytes > 0 && self.usage_bytes() + len > self.quota_bytes {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "temporary directory quota of the worker exceeded",
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    fn resolve(&self, path: &Path) -> Option<PathBuf> {
+        let path = normalize_path(path);
+
+        path.starts_with(&self.path).then_some(path)
+    }
+
+    fn add_usage(&self, len: u64) {
+        self.usage_bytes.fetch_add(len, Ordering::AcqRel);
+    }
+}
+
+impl Drop for TmpDir {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_dir_all(&self.path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                error!(
+                    "failed to remove temporary directory {}: {}",
+                    self.path.display(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+fn grows(options: &OpenOptions) -> bool {
+    options.write || options.append || options.create || options.create_new || options.truncate
+}
+
+/// A file opened for writing in a [`TmpDir`]. Every write is checked against
+/// the quota of the directory and counted, since a file that is opened while
+/// there is still room could otherwise be grown past it.
+struct QuotaFile {
+    inner: Rc<dyn File>,
+    dir: Arc<TmpDir>,
+}
+
+impl QuotaFile {
+    fn wrap(inner: Rc<dyn File>, dir: Arc<TmpDir>) -> Rc<dyn File> {
+        Rc::new(Self { inner, dir })
+    }
+
+    fn growth(&self, stat: FsResult<FsStat>, len: u64) -> FsResult<u64> {
+        let growth = len.saturating_sub(stat?.size);
+
+        self.dir.ensure_room(growth)?;
+
+        Ok(growth)
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl File for QuotaFile {
+    fn read_sync(self: Rc<Self>, buf: &mut [u8]) -> FsResult<usize> {
+        self.inner.clone().read_sync(buf)
+    }
+    async fn read_byob(self: Rc<Self>, buf: BufMutView) -> FsResult<(usize, BufMutView)> {
+        self.inner.clone().read_byob(buf).await
+    }
+
+    fn write_sync(self: Rc<Self>, buf: &[u8]) -> FsResult<usize> {
+        self.dir.ensure_room(buf.len() as u64)?;
+
+        let nwritten = self.inner.clone().write_sync(buf)?;
+
+        self.dir.add_usage(nwritten as u64);
+
+        Ok(nwritten)
+    }
+    async fn write(self: Rc<Self>, buf: BufView) -> FsResult<WriteOutcome> {
+        self.dir.ensure_room(buf.len() as u64)?;
+
+        let outcome = self.inner.clone().write(buf).await?;
+        let nwritten = match &outcome {
+            WriteOutcome::Partial { nwritten, .. } | WriteOutcome::Full { nwritten } => *nwritten,
+        };
+
+        self.dir.add_usage(nwritten as u64);
+
+        Ok(outcome)
+    }
+
+    fn write_all_sync(self: Rc<Self>, buf: &[u8]) -> FsResult<()> {
+        self.dir.ensure_room(buf.len() as u64)?;
+        self.inner.clone().write_all_sync(buf)?;
+        self.dir.add_usage(buf.len() as u64);
+
+        Ok(())
+    }
+    async fn write_all(self: Rc<Self>, buf: BufView) -> FsResult<()> {
+        let len = buf.len() as u64;
+
+        self.dir.ensure_room(len)?;
+        self.inner.clone().write_all(buf).await?;
+        self.dir.add_usage(len);
+
+        Ok(())
+    }
+
+    fn read_all_sync(self: Rc<Self>) -> FsResult<Vec<u8>> {
+        self.inner.clone().read_all_sync()
+    }
+    async fn read_all_async(self: Rc<Self>) -> FsResult<Vec<u8>> {
+        self.inner.clone().read_all_async().await
+    }
+
+    fn chmod_sync(self: Rc<Self>, pathmode: u32) -> FsResult<()> {
+        self.inner.clone().chmod_sync(pathmode)
+    }
+    async fn chmod_async(self: Rc<Self>, mode: u32) -> FsResult<()> {
+        self.inner.clone().chmod_async(mode).await
+    }
+
+    fn seek_sync(self: Rc<Self>, pos: SeekFrom) -> FsResult<u64> {
+        self.inner.clone().seek_sync(pos)
+    }
+    async fn seek_async(self: Rc<Self>, pos: SeekFrom) -> FsResult<u64> {
+        self.inner.clone().seek_async(pos).await
+    }
+
+    fn datasync_sync(self: Rc<Self>) -> FsResult<()> {
+        self.inner.clone().datasync_sync()
+    }
+    async fn datasync_async(self: Rc<Self>) -> FsResult<()> {
+        self.inner.clone().datasync_async().await
+    }
+
+    fn sync_sync(self: Rc<Self>) -> FsResult<()> {
+        self.inner.clone().sync_sync()
+    }
+    async fn sync_async(self: Rc<Self>) -> FsResult<()> {
+        self.inner.clone().sync_async().await
+    }
+
+    fn stat_sync(self: Rc<Self>) -> FsResult<FsStat> {
+        self.inner.clone().stat_sync()
+    }
+    async fn stat_async(self: Rc<Self>) -> FsResult<FsStat> {
+        self.inner.clone().stat_async().await
+    }
+
+    fn lock_sync(self: Rc<Self>, exclusive: bool) -> FsResult<()> {
+        self.inner.clone().lock_sync(exclusive)
+    }
+    async fn lock_async(self: Rc<Self>, exclusive: bool) -> FsResult<()> {
+        self.inner.clone().lock_async(exclusive).await
+    }
+
+    fn unlock_sync(self: Rc<Self>) -> FsResult<()> {
+        self.inner.clone().unlock_sync()
+    }
+    async fn unlock_async(self: Rc<Self>) -> FsResult<()> {
+        self.inner.clone().unlock_async().await
+    }
+
+    fn truncate_sync(self: Rc<Self>, len: u64) -> FsResult<()> {
+        let growth = self.growth(self.inner.clone().stat_sync(), len)?;
+
+        self.inner.clone().truncate_sync(len)?;
+        self.dir.add_usage(growth);
+
+        Ok(())
+    }
+    async fn truncate_async(self: Rc<Self>, len: u64) -> FsResult<()> {
+        let growth = self.growth(self.inner.clone().stat_async().await, len)?;
+
+        self.inner.clone().truncate_async(len).await?;
+        self.dir.add_usage(growth);
+
+        Ok(())
+    }
+
+    fn utime_sync(
+        self: Rc<Self>,
+        atime_secs: i64,
+        atime_nanos: u32,
+        mtime_secs: i64,
+        mtime_nanos: u32,
+    ) -> FsResult<()> {
+        self.inner
+            .clone()
+            .utime_sync(atime_secs, atime_nanos, mtime_secs, mtime_nanos)
+    }
+    async fn utime_async(
+        self: Rc<Self>,
+        atime_secs: i64,
+        atime_nanos: u32,
+        mtime_secs: i64,
+        mtime_nanos: u32,
+    ) -> FsResult<()> {
+        self.inner
+            .clone()
+            .utime_async(atime_secs, atime_nanos, mtime_secs, mtime_nanos)
+            .await
+    }
+
+    // lower level functionality
+    fn as_stdio(self: Rc<Self>) -> FsResult<std::process::Stdio> {
+        self.inner.clone().as_stdio()
+    }
+    fn backing_fd(self: Rc<Self>) -> Option<ResourceHandleFd> {
+        self.inner.clone().backing_fd()
+    }
+    fn try_clone_inner(self: Rc<Self>) -> FsResult<Rc<dyn File>> {
+        Ok(Self::wrap(
+            self.inner.clone().try_clone_inner()?,
+            self.dir.clone(),
+        ))
+    }
+}
+
+/// Folder of the [`TmpDir`] that the writes to the service directory are
+/// redirected to.
+const SERVICE_OVERLAY_DIR: &str = ".service";
+
+/// Lets a worker write to its own [`TmpDir`] while every other path is still
+/// served by `inner`. Operations that could add data to the directory are
+/// refused once its quota is used up.
+///
+/// With a service overlay, the service directory stays read-only and writes
+/// to it land in the [`TmpDir`] instead, copying the file there first. The
+/// worker reads its own copies back, while the shared files and the next
+/// boots of the service are left untouched. Files of the service can't be
+/// removed, only the copies of the worker.
+#[derive(Debug, Clone)]
+pub struct TmpDirFs {
+    inner: Arc<dyn FileSystem>,
+    dir: Arc<TmpDir>,
+    maybe_service_dir: Option<PathBuf>,
+}
+
+impl TmpDirFs {
+    pub fn new(inner: Arc<dyn FileSystem>, dir: Arc<TmpDir>) -> Self {
+        Self {
+            inner,
+            dir,
+            maybe_service_dir: None,
+        }
+    }
+
+    pub fn with_service_overlay(mut self, service_dir: PathBuf) -> Self {
+        self.maybe_service_dir = Some(normalize_path(service_dir));
+        self
+    }
+
+    fn overlay_path(&self, path: &Path) -> Option<PathBuf> {
+        let service_dir = self.maybe_service_dir.as_ref()?;
+        let relative = normalize_path(path)
+            .strip_prefix(service_dir)
+            .ok()?
+            .to_path_buf();
+
+        Some(self.dir.path().join(SERVICE_OVERLAY_DIR).join(relative))
+    }
+
+    /// Where `path` is read from on the host, if not from `inner`.
+    fn resolve_read(&self, path: &Path) -> Option<PathBuf> {
+        self.dir.resolve(path).or_else(|| {
+            self.overlay_path(path)
+                .filter(|it| it.symlink_metadata().is_ok())
+        })
+    }
+
+    /// Where `path` is written to on the host, if not to `inner`. A file of
+    /// the service is copied to the overlay first.
+    fn resolve_write(&self, path: &Path) -> FsResult<Option<PathBuf>> {
+        if let Some(path) = self.dir.resolve(path) {
+            return Ok(Some(path));
+        }
+
+        let Some(overlay_path) = self.overlay_path(path) else {
+            return Ok(None);
+        };
+
+        if overlay_path.symlink_metadata().is_err() {
+            if let Some(parent) = overlay_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            if let Ok(data) = self.inner.read_file_sync(path) {
+                self.dir.ensure_room(data.len() as u64)?;
+                std::fs::write(&overlay_path, &data)?;
+                self.dir.add_usage(data.len() as u64);
+            }
+        }
+
+        Ok(Some(overlay_path))
+    }
+
+    fn resolve_open(&self, path: &Path, options: &OpenOptions) -> FsResult<Option<PathBuf>> {
+        if grows(options) {
+            self.resolve_write(path)
+        } else {
+            Ok(self.resolve_read(path))
+        }
+    }
+
+    /// Adds the entries the worker created in the overlay of a directory of
+    /// the service to those of the service itself.
+    fn with_overlay_entries(
+        &self,
+        path: &Path,
+        entries: FsResult<Vec<FsDirEntry>>,
+    ) -> FsResult<Vec<FsDirEntry>> {
+        let Some(overlay_entries) = self
+            .overlay_path(path)
+            .and_then(|it| RealFs.read_dir_sync(&it).ok())
+        else {
+            return entries;
+        };
+
+        let mut entries = entries.unwrap_or_default();
+
+        for entry in overlay_entries {
+            if entries.iter().all(|it| it.name != entry.name) {
+                entries.push(entry);
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn resolve_pair(&self, oldpath: &Path, newpath: &Path) -> FsResult<Option<(PathBuf, PathBuf)>> {
+        match (self.resolve_write(oldpath)?, self.resolve_write(newpath)?) {
+            (Some(old), Some(new)) => Ok(Some((old, new))),
+            (None, None) => Ok(None),
+            _ => Err(FsError::NotSupported),
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl FileSystem for TmpDirFs {
+    fn cwd(&self) -> FsResult<PathBuf> {
+        self.inner.cwd()
+    }
+
+    fn tmp_dir(&self) -> FsResult<PathBuf> {
+        Ok(self.dir.path().to_path_buf())
+    }
+
+    fn chdir(&self, path: &Path) -> FsResult<()> {
+        self.inner.chdir(path)
+    }
+
+    fn umask(&self, mask: Option<u32>) -> FsResult<u32> {
+        self.inner.umask(mask)
+    }
+
+    fn open_sync(&self, path: &Path, options: OpenOptions) -> FsResult<Rc<dyn File>> {
+        match self.resolve_open(path, &options)? {
+            Some(path) => {
+                if !grows(&options) {
+                    return RealFs.open_sync(&path, options);
+                }
+
+                self.dir.ensure_room(0)?;
+
+                Ok(QuotaFile::wrap(
+                    RealFs.open_sync(&path, options)?,
+                    self.dir.clone(),
+                ))
+            }
+
+            None => self.inner.open_sync(path, options),
+        }
+    }
+
+    async fn open_async(&self, path: PathBuf, options: OpenOptions) -> FsResult<Rc<dyn File>> {
+        match self.resolve_open(&path, &options)? {
+            Some(path) => {
+                if !grows(&options) {
+                    return RealFs.open_async(path, options).await;
+                }
+
+                self.dir.ensure_room(0)?;
+
+                Ok(QuotaFile::wrap(
+                    RealFs.open_async(path, options).await?,
+                    self.dir.clone(),
+                ))
+            }
+
+            None => self.inner.open_async(path, options).await,
+        }
+    }
+
+    fn mkdir_sync(&self, path: &Path, recursive: bool, mode: u32) -> FsResult<()> {
+        match self.resolve_write(path)? {
+            Some(path) => {
+                self.dir.ensure_room(0)?;
+                RealFs.mkdir_sync(&path, recursive, mode)
+            }
+
+            None => self.inner.mkdir_sync(path, recursive, mode),
+        }
+    }
+
+    async fn mkdir_async(&self, path: PathBuf, recursive: bool, mode: u32) -> FsResult<()> {
+        match self.resolve_write(&path)? {
+            Some(path) => {
+                self.dir.ensure_room(0)?;
+                RealFs.mkdir_async(path, recursive, mode).await
+            }
+
+            None => self.inner.mkdir_async(path, recursive, mode).await,
+        }
+    }
+
+    fn chmod_sync(&self, path: &Path, mode: u32) -> FsResult<()> {
+        match self.resolve_write(path)? {
+            Some(path) => RealFs.chmod_sync(&path, mode),
+            None => self.inner.chmod_sync(path, mode),
+        }
+    }
+
+    async fn chmod_async(&self, path: PathBuf, mode: u32) -> FsResult<()> {
+        match self.resolve_write(&path)? {
+            Some(path) => RealFs.chmod_async(path, mode).await,
+            None => self.inner.chmod_async(path, mode).await,
+        }
+    }
+
+    fn chown_sync(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> FsResult<()> {
+        match self.dir.resolve(path) {
+            Some(_) => Err(FsError::NotSupported),
+            None => self.inner.chown_sync(path, uid, gid),
+        }
+    }
+
+    async fn chown_async(&self, path: PathBuf, uid: Option<u32>, gid: Option<u32>) -> FsResult<()> {
+        match self.dir.resolve(&path) {
+            Some(_) => Err(FsError::NotSupported),
+            None => self.inner.chown_async(path, uid, gid).await,
+        }
+    }
+
+    fn remove_sync(&self, path: &Path, recursive: bool) -> FsResult<()> {
+        match self.resolve_read(path) {
+            // NOTE: The directory itself belongs to the runtime.
+            Some(path) if path == self.dir.path() => Err(FsError::NotSupported),
+            Some(path) => RealFs.remove_sync(&path, recursive),
+            None => self.inner.remove_sync(path, recursive),
+        }
+    }
+
+    async fn remove_async(&self, path: PathBuf, recursive: bool) -> FsResult<()> {
+        match self.resolve_read(&path) {
+            Some(path) if path == self.dir.path() => Err(FsError::NotSupported),
+            Some(path) => RealFs.remove_async(path, recursive).await,
+            None => self.inner.remove_async(path, recursive).await,
+        }
+    }
+
+    fn copy_file_sync(&self, oldpath: &Path, newpath: &Path) -> FsResult<()> {
+        match self.resolve_pair(oldpath, newpath)? {
+            Some((old, new)) => {
+                self.dir.ensure_room(RealFs.stat_sync(&old)?.size)?;
+                RealFs.copy_file_sync(&old, &new)
+            }
+
+            None => self.inner.copy_file_sync(oldpath, newpath),
+        }
+    }
+
+    async fn copy_file_async(&self, oldpath: PathBuf, newpath: PathBuf) -> FsResult<()> {
+        match self.resolve_pair(&oldpath, &newpath)? {
+            Some((old, new)) => {
+                self.dir.ensure_room(RealFs.stat_sync(&old)?.size)?;
+                RealFs.copy_file_async(old, new).await
+            }
+
+            None => self.inner.copy_file_async(oldpath, newpath).await,
+        }
+    }
+
+    fn cp_sync(&self, path: &Path, new_path: &Path) -> FsResult<()> {
+        match self.resolve_pair(path, new_path)? {
+            Some((old, new)) => {
+                self.dir.ensure_room(0)?;
+                RealFs.cp_sync(&old, &new)
+            }
+
+            None => self.inner.cp_sync(path, new_path),
+        }
+    }
+
+    async fn cp_async(&self, path: PathBuf, new_path: PathBuf) -> FsResult<()> {
+        match self.resolve_pair(&path, &new_path)? {
+            Some((old, new)) => {
+                self.dir.ensure_room(0)?;
+                RealFs.cp_async(old, new).await
+            }
+
+            None => self.inner.cp_async(path, new_path).await,
+        }
+    }
+
+    fn stat_sync(&self, path: &Path) -> FsResult<FsStat> {
+        match self.resolve_read(path) {
+            Some(path) => RealFs.stat_sync(&path),
+            None => self.inner.stat_sync(path),
+        }
+    }
+
+    async fn stat_async(&self, path: PathBuf) -> FsResult<FsStat> {
+        match self.resolve_read(&path) {
+            Some(path) => RealFs.stat_async(path).await,
+            None => self.inner.stat_async(path).await,
+        }
+    }
+
+    fn lstat_sync(&self, path: &Path) -> FsResult<FsStat> {
+        match self.resolve_read(path) {
+            Some(path) => RealFs.lstat_sync(&path),
+            None => self.inner.lstat_sync(path),
+        }
+    }
+
+    async fn lstat_async(&self, path: PathBuf) -> FsResult<FsStat> {
+        match self.resolve_read(&path) {
+            Some(path) => RealFs.lstat_async(path).await,
+            None => self.inner.lstat_async(path).await,
+        }
+    }
+
+    fn realpath_sync(&self, path: &Path) -> FsResult<PathBuf> {
+        match self.resolve_read(path) {
+            Some(path) => RealFs.realpath_sync(&path),
+            None => self.inner.realpath_sync(path),
+        }
+    }
+
+    async fn realpath_async(&self, path: PathBuf) -> FsResult<PathBuf> {
+        match self.resolve_read(&path) {
+            Some(path) => RealFs.realpath_async(path).await,
+            None => self.inner.realpath_async(path).await,
+        }
+    }
+
+    fn read_dir_sync(&self, path: &Path) -> FsResult<Vec<FsDirEntry>> {
+        match self.dir.resolve(path) {
+            Some(path) => RealFs.read_dir_sync(&path),
+            None => self.with_overlay_entries(path, self.inner.read_dir_sync(path)),
+        }
+    }
+
+    async fn read_dir_async(&self, path: PathBuf) -> FsResult<Vec<FsDirEntry>> {
+        match self.dir.resolve(&path) {
+            Some(path) => RealFs.read_dir_async(path).await,
+            None => {
+                let entries = self.inner.read_dir_async(path.clone()).await;
+
+                self.with_overlay_entries(&path, entries)
+            }
+        }
+    }
+
+    fn rename_sync(&self, oldpath: &Path, newpath: &Path) -> FsResult<()> {
+        match self.resolve_pair(oldpath, newpath)? {
+            Some((old, new)) => RealFs.rename_sync(&old, &new),
+            None => self.inner.rename_sync(oldpath, newpath),
+        }
+    }
+
+    async fn rename_async(&self, oldpath: PathBuf, newpath: PathBuf) -> FsResult<()> {
+        match self.resolve_pair(&oldpath, &newpath)? {
+            Some((old, new)) => RealFs.rename_async(old, new).await,
+            None => self.inner.rename_async(oldpath, newpath).await,
+        }
+    }
+
+    fn link_sync(&self, oldpath: &Path, newpath: &Path) -> FsResult<()> {
+        match self.resolve_pair(oldpath, newpath)? {
+            Some((old, new)) => RealFs.link_sync(&old, &new),
+            None => self.inner.link_sync(oldpath, newpath),
+        }
+    }
+
+    async fn link_async(&self, oldpath: PathBuf, newpath: PathBuf) -> FsResult<()> {
+        match self.resolve_pair(&oldpath, &newpath)? {
+            Some((old, new)) => RealFs.link_async(old, new).await,
+            None => self.inner.link_async(oldpath, newpath).await,
+        }
+    }
+
+    // NOTE: A symlink in the directory could point anywhere on the host, so
+    // creating one is never allowed there.
+    fn symlink_sync(
+        &self,
+        oldpath: &Path,
+        newpath: &Path,
+        file_type: Option<FsFileType>,
+    ) -> FsResult<()> {
+        match self.resolve_pair(oldpath, newpath)? {
+            Some(_) => Err(FsError::NotSupported),
+            None => self.inner.symlink_sync(oldpath, newpath, file_type),
+        }
+    }
+
+    async fn symlink_async(
+        &self,
+        oldpath: PathBuf,
+        newpath: PathBuf,
+        file_type: Option<FsFileType>,
+    ) -> FsResult<()> {
+        match self.resolve_pair(&oldpath, &newpath)? {
+            Some(_) => Err(FsError::NotSupported),
+            None => self.inner.symlink_async(oldpath, newpath, file_type).await,
+        }
+    }
+
+    fn read_link_sync(&self, path: &Path) -> FsResult<PathBuf> {
+        match self.resolve_read(path) {
+            Some(path) => RealFs.read_link_sync(&path),
+            None => self.inner.read_link_sync(path),
+        }
+    }
+
+    async fn read_link_async(&self, path: PathBuf) -> FsResult<PathBuf> {
+        match self.resolve_read(&path) {
+            Some(path) => RealFs.read_link_async(path).await,
+            None => self.inner.read_link_async(path).await,
+        }
+    }
+
+    fn truncate_sync(&self, path: &Path, len: u64) -> FsResult<()> {
+        match self.resolve_write(path)? {
+            Some(path) => {
+                self.dir.ensure_room(len)?;
+                RealFs.truncate_sync(&path, len)
+            }
+
+            None => self.inner.truncate_sync(path, len),
+        }
+    }
+
+    async fn truncate_async(&self, path: PathBuf, len: u64) -> FsResult<()> {
+        match self.resolve_write(&path)? {
+            Some(path) => {
+                self.dir.ensure_room(len)?;
+                RealFs.truncate_async(path, len).await
+            }
+
+            None => self.inner.truncate_async(path, len).await,
+        }
+    }
+
+    fn utime_sync(
+        &self,
+        path: &Path,
+        atime_secs: i64,
+        atime_nanos: u32,
+        mtime_secs: i64,
+        mtime_nanos: u32,
+    ) -> FsResult<()> {
+        match self.resolve_write(path)? {
+            Some(path) => {
+                RealFs.utime_sync(&path, atime_secs, atime_nanos, mtime_secs, mtime_nanos)
+            }
+            None => self
+                .inner
+                .utime_sync(path, atime_secs, atime_nanos, mtime_secs, mtime_nanos),
+        }
+    }
+
+    async fn utime_async(
+        &self,
+        path: PathBuf,
+        atime_secs: i64,
+        atime_nanos: u32,
+        mtime_secs: i64,
+        mtime_nanos: u32,
+    ) -> FsResult<()> {
+        match self.resolve_write(&path)? {
+            Some(path) => {
+                RealFs
+                    .utime_async(path, atime_secs, atime_nanos, mtime_secs, mtime_nanos)
+                    .await
+            }
+
+            None => {
+                self.inner
+                    .utime_async(path, atime_secs, atime_nanos, mtime_secs, mtime_nanos)
+                    .await
+            }
+        }
+    }
+
+    fn write_file_sync(&self, path: &Path, options: OpenOptions, data: &[u8]) -> FsResult<()> {
+        match self.resolve_write(path)? {
+            Some(path) => {
+                self.dir.ensure_room(data.len() as u64)?;
+                RealFs.write_file_sync(&path, options, data)?;
+                self.dir.add_usage(data.len() as u64);
+
+                Ok(())
+            }
+
+            None => self.inner.write_file_sync(path, options, data),
+        }
+    }
+
+    async fn write_file_async(
+        &self,
+        path: PathBuf,
+        options: OpenOptions,
+        data: Vec<u8>,
+    ) -> FsResult<()> {
+        match self.resolve_write(&path)? {
+            Some(path) => {
+                let len = data.len() as u64;
+
+                self.dir.ensure_room(len)?;
+                RealFs.write_file_async(path, options, data).await?;
+                self.dir.add_usage(len);
+
+                Ok(())
+            }
+
+            None => self.inner.write_file_async(path, options, data).await,
+        }
+    }
+
+    fn read_file_sync(&self, path: &Path) -> FsResult<Vec<u8>> {
+        match self.resolve_read(path) {
+            Some(path) => RealFs.read_file_sync(&path),
+            None => self.inner.read_file_sync(path),
+        }
+    }
+
+    async fn read_file_async(&self, path: PathBuf) -> FsResult<Vec<u8>> {
+        match self.resolve_read(&path) {
+            Some(path) => RealFs.read_file_async(path).await,
+            None => self.inner.read_file_async(path).await,
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Error};
+use deno_core::error::{custom_error, AnyError};
+use deno_core::op2;
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+
+static GEOIP: OnceCell<GeoIp> = OnceCell::new();
+
+#[derive(Debug, Clone, Default)]
+pub struct GeoIpConfig {
+    /// GeoIP2 or GeoLite2 City or Country database.
+    pub location_db: Option<PathBuf>,
+    /// GeoIP2 ISP or GeoLite2 ASN database.
+    pub asn_db: Option<PathBuf>,
+}
+
+/// What the databases know about an address. Fields the databases have no
+/// data for are left out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeoInfo {
+    /// ISO 3166-1 alpha-2 code of the country.
+    pub country: Option<String>,
+    /// ISO 3166-2 code of the largest subdivision, without the country
+    /// prefix.
+    pub region: Option<String>,
+    /// English name of the city.
+    pub city: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub asn: Option<u32>,
+    pub as_organization: Option<String>,
+}
+
+pub struct GeoIp {
+    location: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+fn open(path: &Path) -> Result<Reader<Vec<u8>>, Error> {
+    Reader::open_readfile(path)
+        .with_context(|| format!("could not open geoip database: {}", path.display()))
+}
+
+/// Looks `ip` up in `reader`, treating an address missing from the database
+/// as no data.
+fn lookup_in<'a, T: serde::Deserialize<'a>>(reader: &'a Reader<Vec<u8>>, ip: IpAddr) -> Option<T> {
+    match reader.lookup::<T>(ip) {
+        Ok(it) => Some(it),
+        Err(MaxMindDBError::AddressNotFoundError(_)) => None,
+        Err(err) => {
+            log::debug!("geoip lookup failed: {}: {}", ip, err);
+            None
+        }
+    }
+}
+
+impl GeoIp {
+    pub fn open(config: &GeoIpConfig) -> Result<Self, Error> {
+        Ok(Self {
+            location: config.location_db.as_deref().map(open).transpose()?,
+            asn: config.asn_db.as_deref().map(open).transpose()?,
+        })
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> GeoInfo {
+        let mut info = GeoInfo::default();
+
+        if let Some(city) = self
+            .location
+            .as_ref()
+            .and_then(|it| lookup_in::<geoip2::City>(it, ip))
+        {
+            info.country = city.country.and_then(|it| it.iso_code).map(str::to_string);
+            info.region = city
+                .subdivisions
+                .and_then(|it| it.into_iter().next())
+                .and_then(|it| it.iso_code)
+                .map(str::to_string);
+            info.city = city
+                .city
+                .and_then(|it| it.names)
+                .and_then(|it| it.get("en").copied())
+                .map(str::to_string);
+
+            if let Some(location) = city.location {
+                info.latitude = location.latitude;
+                info.longitude = location.longitude;
+            }
+        }
+
+        if let Some(asn) = self
+            .asn
+            .as_ref()
+            .and_then(|it| lookup_in::<geoip2::Asn>(it, ip))
+        {
+            info.asn = asn.autonomous_system_number;
+            info.as_organization = asn.autonomous_system_organization.map(str::to_string);
+        }
+
+        info
+    }
+}
+
+/// Opens the databases that client addresses are looked up in. Requests are
+/// only annotated once this has been called.
+pub fn init(config: GeoIpConfig) -> Result<(), Error> {
+    let geoip = GeoIp::open(&config)?;
+
+    GEOIP
+        .set(geoip)
+        .map_err(|_| anyhow::anyhow!("geoip databases are already initialized"))
+}
+
+/// Returns what is known about `ip`, or `None` if no database is configured.
+pub fn lookup(ip: IpAddr) -> Option<GeoInfo> {
+    GEOIP.get().map(|it| it.lookup(ip.to_canonical()))
+}
+
+#[op2]
+#[serde]
+fn op_geoip_lookup(#[string] ip: String) -> Result<GeoInfo, AnyError> {
+    let ip = ip
+        .parse::<IpAddr>()
+        .map_err(|err| custom_error("TypeError", format!("invalid address: {}: {}", ip, err)))?;
+
+    lookup(ip).ok_or_else(|| custom_error("NotSupported", "geoip database is not configured"))
+}
+
+deno_core::extension!(sb_geoip, ops = [op_geoip_lookup]);
@@ -0,0 +1,71 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+/**
+ * Makes a stream that rewrites the HTML going through it on the host, chunk
+ * by chunk, so the document is never held whole in the heap of the worker.
+ * Each rule applies an action to the elements its CSS selector matches.
+ *
+ * ```js
+ * return Supabase.html.rewrite(await fetch(origin), [
+ *   { selector: 'head', action: 'append', content: '<script src="/rum.js"></script>', html: true },
+ *   { selector: 'a[href^="http"]', action: 'setAttribute', name: 'rel', value: 'noopener' },
+ *   { selector: '.ad', action: 'remove' },
+ * ]);
+ * ```
+ *
+ * Actions are `before`, `after`, `prepend`, `append`, `replace` and
+ * `setInnerContent`, which take a `content` (escaped unless `html` is set),
+ * `setAttribute`, `removeAttribute`, `remove` and `removeAndKeepContent`.
+ */
+function rewriter(rules) {
+	const rid = ops.op_html_rewriter_create(rules);
+
+	return new TransformStream({
+		transform(chunk, controller) {
+			try {
+				const out = ops.op_html_rewriter_write(rid, chunk);
+
+				if (out.byteLength > 0) {
+					controller.enqueue(out);
+				}
+			} catch (e) {
+				core.tryClose(rid);
+				throw e;
+			}
+		},
+
+		flush(controller) {
+			const out = ops.op_html_rewriter_end(rid);
+
+			if (out.byteLength > 0) {
+				controller.enqueue(out);
+			}
+		},
+
+		cancel() {
+			core.tryClose(rid);
+		},
+	});
+}
+
+/**
+ * Returns a copy of the response with its body rewritten by `rules`. The
+ * `Content-Length` header is dropped, since the length of the rewritten body
+ * is not known in advance.
+ */
+function rewrite(response, rules) {
+	const headers = new Headers(response.headers);
+
+	headers.delete('content-length');
+
+	return new Response(response.body?.pipeThrough(rewriter(rules)) ?? null, {
+		status: response.status,
+		statusText: response.statusText,
+		headers,
+	});
+}
+
+const html = { rewriter, rewrite };
+
+export { html };
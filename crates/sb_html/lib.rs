@@ -0,0 +1,77 @@
+mod rewriter;
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use deno_core::error::{custom_error, AnyError};
+use deno_core::{op2, OpState, Resource, ResourceId, ToJsBuffer};
+
+pub use crate::rewriter::{Action, Rewriter, Rule};
+
+struct HtmlRewriterResource(RefCell<Option<Rewriter>>);
+
+impl Resource for HtmlRewriterResource {
+    fn name(&self) -> Cow<str> {
+        "htmlRewriter".into()
+    }
+}
+
+fn rewriter_error(err: anyhow::Error) -> AnyError {
+    custom_error("TypeError", format!("{:#}", err))
+}
+
+#[op2]
+#[smi]
+fn op_html_rewriter_create(
+    state: &mut OpState,
+    #[serde] rules: Vec<Rule>,
+) -> Result<ResourceId, AnyError> {
+    let rewriter = Rewriter::new(rules).map_err(rewriter_error)?;
+
+    Ok(state
+        .resource_table
+        .add(HtmlRewriterResource(RefCell::new(Some(rewriter)))))
+}
+
+#[op2]
+#[serde]
+fn op_html_rewriter_write(
+    state: &mut OpState,
+    #[smi] rid: ResourceId,
+    #[buffer] chunk: &[u8],
+) -> Result<ToJsBuffer, AnyError> {
+    let resource = state.resource_table.get::<HtmlRewriterResource>(rid)?;
+    let mut maybe_rewriter = resource.0.borrow_mut();
+    let rewriter = maybe_rewriter
+        .as_mut()
+        .ok_or_else(|| custom_error("TypeError", "rewriter has already ended"))?;
+
+    Ok(rewriter.write(chunk).map_err(rewriter_error)?.into())
+}
+
+#[op2]
+#[serde]
+fn op_html_rewriter_end(
+    state: &mut OpState,
+    #[smi] rid: ResourceId,
+) -> Result<ToJsBuffer, AnyError> {
+    let resource = state.resource_table.take::<HtmlRewriterResource>(rid)?;
+    let rewriter = resource
+        .0
+        .borrow_mut()
+        .take()
+        .ok_or_else(|| custom_error("TypeError", "rewriter has already ended"))?;
+
+    Ok(rewriter.end().map_err(rewriter_error)?.into())
+}
+
+deno_core::extension!(
+    sb_html,
+    ops = [
+        op_html_rewriter_create,
+        op_html_rewriter_write,
+        op_html_rewriter_end
+    ],
+    esm_entry_point = "ext:sb_html/html.js",
+    esm = ["html.js"]
+);
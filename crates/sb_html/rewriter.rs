@@ -0,0 +1,217 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::{anyhow, Context, Error};
+use lol_html::html_content::{ContentType, Element};
+use lol_html::{
+    ElementContentHandlers, HandlerResult, HtmlRewriter, OutputSink, Selector, Settings,
+};
+use serde::Deserialize;
+
+/// What to do with the elements a rule selects.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum Action {
+    Before {
+        content: String,
+        #[serde(default)]
+        html: bool,
+    },
+    After {
+        content: String,
+        #[serde(default)]
+        html: bool,
+    },
+    Prepend {
+        content: String,
+        #[serde(default)]
+        html: bool,
+    },
+    Append {
+        content: String,
+        #[serde(default)]
+        html: bool,
+    },
+    Replace {
+        content: String,
+        #[serde(default)]
+        html: bool,
+    },
+    SetInnerContent {
+        content: String,
+        #[serde(default)]
+        html: bool,
+    },
+    SetAttribute {
+        name: String,
+        value: String,
+    },
+    RemoveAttribute {
+        name: String,
+    },
+    Remove,
+    RemoveAndKeepContent,
+}
+
+/// A CSS selector and the action applied to every element it matches.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rule {
+    pub selector: String,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+fn content_type(html: bool) -> ContentType {
+    if html {
+        ContentType::Html
+    } else {
+        ContentType::Text
+    }
+}
+
+impl Action {
+    fn apply(&self, el: &mut Element) -> HandlerResult {
+        match self {
+            Self::Before { content, html } => el.before(content, content_type(*html)),
+            Self::After { content, html } => el.after(content, content_type(*html)),
+            Self::Prepend { content, html } => el.prepend(content, content_type(*html)),
+            Self::Append { content, html } => el.append(content, content_type(*html)),
+            Self::Replace { content, html } => el.replace(content, content_type(*html)),
+            Self::SetInnerContent { content, html } => {
+                el.set_inner_content(content, content_type(*html))
+            }
+            Self::SetAttribute { name, value } => el.set_attribute(name, value)?,
+            Self::RemoveAttribute { name } => el.remove_attribute(name),
+            Self::Remove => el.remove(),
+            Self::RemoveAndKeepContent => el.remove_and_keep_content(),
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default, Clone)]
+struct Sink(Rc<RefCell<Vec<u8>>>);
+
+impl OutputSink for Sink {
+    fn handle_chunk(&mut self, chunk: &[u8]) {
+        self.0.borrow_mut().extend_from_slice(chunk);
+    }
+}
+
+/// Rewrites an HTML document chunk by chunk. Only the parts of the document
+/// that a selector may still match are buffered.
+pub struct Rewriter {
+    inner: HtmlRewriter<'static, Sink>,
+    output: Sink,
+}
+
+impl Rewriter {
+    pub fn new(rules: Vec<Rule>) -> Result<Self, Error> {
+        let mut element_content_handlers = Vec::with_capacity(rules.len());
+
+        for Rule { selector, action } in rules {
+            let selector = selector
+                .parse::<Selector>()
+                .with_context(|| format!("invalid selector: {}", selector))?;
+
+            element_content_handlers.push((
+                Cow::Owned(selector),
+                ElementContentHandlers::default().element(move |el| action.apply(el)),
+            ));
+        }
+
+        let output = Sink::default();
+        let inner = HtmlRewriter::new(
+            Settings {
+                element_content_handlers,
+                ..Settings::default()
+            },
+            output.clone(),
+        );
+
+        Ok(Self { inner, output })
+    }
+
+    /// Feeds a chunk of the document and returns what is ready to be sent.
+    pub fn write(&mut self, chunk: &[u8]) -> Result<Vec<u8>, Error> {
+        self.inner.write(chunk).map_err(|err| anyhow!("{}", err))?;
+
+        Ok(self.output.0.take())
+    }
+
+    /// Ends the document and returns the rest of it.
+    pub fn end(self) -> Result<Vec<u8>, Error> {
+        self.inner.end().map_err(|err| anyhow!("{}", err))?;
+
+        Ok(self.output.0.take())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rule(selector: &str, action: Action) -> Rule {
+        Rule {
+            selector: selector.to_string(),
+            action,
+        }
+    }
+
+    #[test]
+    fn test_rewriter_applies_rules_across_chunks() {
+        let mut rewriter = Rewriter::new(vec![
+            rule(
+                "head",
+                Action::Append {
+                    content: "<script src=\"/a.js\"></script>".to_string(),
+                    html: true,
+                },
+            ),
+            rule(
+                "a[href]",
+                Action::SetAttribute {
+                    name: "rel".to_string(),
+                    value: "nofollow".to_string(),
+                },
+            ),
+            rule("#ad", Action::Remove),
+            rule(
+                "h1",
+                Action::SetInnerContent {
+                    content: "<Hi>".to_string(),
+                    html: false,
+                },
+            ),
+        ])
+        .unwrap();
+
+        let mut out = Vec::new();
+
+        for chunk in [
+            "<html><he",
+            "ad></head><body><h1>Hello</h1><a hr",
+            "ef=\"/\">home</a><div id=\"ad\">buy</div></body></html>",
+        ] {
+            out.extend(rewriter.write(chunk.as_bytes()).unwrap());
+        }
+
+        out.extend(rewriter.end().unwrap());
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "<html><head><script src=\"/a.js\"></script></head><body>",
+                "<h1>&lt;Hi&gt;</h1><a href=\"/\" rel=\"nofollow\">home</a></body></html>"
+            )
+        );
+    }
+
+    #[test]
+    fn test_rewriter_rejects_invalid_selectors() {
+        assert!(Rewriter::new(vec![rule("a[", Action::Remove)]).is_err());
+    }
+}
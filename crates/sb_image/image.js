@@ -0,0 +1,39 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+async function toBytes(source) {
+	if (source instanceof Blob) {
+		return new Uint8Array(await source.arrayBuffer());
+	}
+
+	if (ArrayBuffer.isView(source)) {
+		return new Uint8Array(source.buffer, source.byteOffset, source.byteLength);
+	}
+
+	return new Uint8Array(source);
+}
+
+/**
+ * Image transforms that run on the host instead of the worker's CPU budget.
+ * PNG, JPEG, GIF and WebP can be read, and PNG, JPEG and GIF written.
+ *
+ * ```js
+ * const thumb = await Supabase.image.transform(await req.blob(), {
+ *   crop: { x: 0, y: 0, width: 512, height: 512 },
+ *   resize: { width: 128, fit: 'cover' },
+ *   format: 'jpeg',
+ *   quality: 70,
+ * });
+ * ```
+ */
+const image = {
+	async info(source) {
+		return ops.op_image_info(await toBytes(source));
+	},
+
+	async transform(source, opts = {}) {
+		return await ops.op_image_transform(await toBytes(source), opts);
+	},
+};
+
+export { image };
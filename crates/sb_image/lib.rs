@@ -0,0 +1,116 @@
+mod transform;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use deno_core::error::{custom_error, AnyError};
+use deno_core::unsync::spawn_blocking;
+use deno_core::{op2, JsBuffer, OpState, ToJsBuffer};
+
+pub use crate::transform::{
+    Crop, Fit, Format, ImageInfo, Resize, TransformOptions, MAX_DECODE_ALLOC, MAX_DIMENSION,
+};
+
+/// Whether a worker may use the image ops, and how many transforms it may
+/// run at once. Workers without a policy in their op state, like the main
+/// worker, are not limited.
+#[derive(Debug)]
+pub struct ImageOpsPolicy {
+    pub enabled: bool,
+    pub max_concurrent_transforms: usize,
+    running_transforms: Rc<Cell<usize>>,
+}
+
+impl ImageOpsPolicy {
+    pub fn new(enabled: bool, max_concurrent_transforms: usize) -> Self {
+        Self {
+            enabled,
+            max_concurrent_transforms,
+            running_transforms: Rc::default(),
+        }
+    }
+}
+
+/// Holds one of the transforms a worker may run at once.
+struct TransformSlot(Rc<Cell<usize>>);
+
+impl Drop for TransformSlot {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+    }
+}
+
+fn check_enabled(state: &OpState, api_name: &str) -> Result<(), AnyError> {
+    if state
+        .try_borrow::<ImageOpsPolicy>()
+        .is_some_and(|it| !it.enabled)
+    {
+        return Err(custom_error(
+            "PermissionDenied",
+            format!("{} is disabled for the worker", api_name),
+        ));
+    }
+
+    Ok(())
+}
+
+fn acquire_transform_slot(state: &OpState) -> Result<Option<TransformSlot>, AnyError> {
+    check_enabled(state, "Supabase.image.transform()")?;
+
+    let Some(policy) = state.try_borrow::<ImageOpsPolicy>() else {
+        return Ok(None);
+    };
+
+    let running = policy.running_transforms.get();
+
+    if running >= policy.max_concurrent_transforms {
+        return Err(custom_error(
+            "RateLimited",
+            format!(
+                "Supabase.image.transform() can run {} times at once",
+                policy.max_concurrent_transforms
+            ),
+        ));
+    }
+
+    policy.running_transforms.set(running + 1);
+
+    Ok(Some(TransformSlot(policy.running_transforms.clone())))
+}
+
+fn image_error(err: anyhow::Error) -> AnyError {
+    custom_error("TypeError", format!("{:#}", err))
+}
+
+#[op2]
+#[serde]
+fn op_image_info(state: &mut OpState, #[buffer] bytes: &[u8]) -> Result<ImageInfo, AnyError> {
+    check_enabled(state, "Supabase.image.info()")?;
+    transform::info(bytes).map_err(image_error)
+}
+
+/// Decodes, transforms and encodes the image on the blocking pool, so the
+/// work is not billed to the CPU time of the worker. The concurrency limit of
+/// the worker bounds how much of the pool it can take instead.
+#[op2(async)]
+#[serde]
+async fn op_image_transform(
+    state: Rc<RefCell<OpState>>,
+    #[buffer] bytes: JsBuffer,
+    #[serde] opts: TransformOptions,
+) -> Result<ToJsBuffer, AnyError> {
+    let _slot = acquire_transform_slot(&state.borrow())?;
+
+    let out = spawn_blocking(move || transform::transform(&bytes, opts))
+        .await?
+        .map_err(image_error)?;
+
+    Ok(out.into())
+}
+
+deno_core::extension!(
+    sb_image,
+    ops = [op_image_info, op_image_transform],
+    esm_entry_point = "ext:sb_image/image.js",
+    esm = ["image.js"]
+);
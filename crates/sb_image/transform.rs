@@ -0,0 +1,255 @@
+use std::io::Cursor;
+
+use anyhow::{bail, Context, Error};
+use image::imageops::FilterType;
+use image::io::{Limits, Reader};
+use image::{DynamicImage, ImageFormat, ImageOutputFormat};
+use serde::{Deserialize, Serialize};
+
+/// Largest width or height of an image the ops will decode.
+pub const MAX_DIMENSION: u32 = 16_384;
+
+/// Largest amount of memory a single decode may allocate.
+pub const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+}
+
+impl Format {
+    fn from_image_format(format: ImageFormat) -> Option<Self> {
+        Some(match format {
+            ImageFormat::Png => Self::Png,
+            ImageFormat::Jpeg => Self::Jpeg,
+            ImageFormat::Gif => Self::Gif,
+            ImageFormat::WebP => Self::Webp,
+            _ => return None,
+        })
+    }
+
+    fn output_format(self, quality: Option<u8>) -> Result<ImageOutputFormat, Error> {
+        Ok(match self {
+            Self::Png => ImageOutputFormat::Png,
+            Self::Jpeg => ImageOutputFormat::Jpeg(quality.unwrap_or(80).clamp(1, 100)),
+            Self::Gif => ImageOutputFormat::Gif,
+            Self::Webp => bail!("encoding to webp is not supported"),
+        })
+    }
+}
+
+/// How a resized image fills the requested box.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Scales the image to fit inside the box, keeping its aspect ratio.
+    #[default]
+    Contain,
+    /// Scales the image to cover the box, keeping its aspect ratio, and crops
+    /// what overflows.
+    Cover,
+    /// Stretches the image to the box.
+    Fill,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Resize {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub fit: Fit,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The transforms to apply to an image. The crop is applied before the
+/// resize. The output keeps the format of the input unless `format` is set.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransformOptions {
+    pub crop: Option<Crop>,
+    pub resize: Option<Resize>,
+    pub format: Option<Format>,
+    /// Quality of lossy formats, from 1 to 100.
+    pub quality: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+    pub format: Format,
+}
+
+fn decode(bytes: &[u8]) -> Result<(DynamicImage, Format), Error> {
+    let mut reader = Reader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let format = reader
+        .format()
+        .and_then(Format::from_image_format)
+        .context("unsupported image format")?;
+    let mut limits = Limits::default();
+
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    reader.limits(limits);
+
+    Ok((reader.decode()?, format))
+}
+
+pub fn info(bytes: &[u8]) -> Result<ImageInfo, Error> {
+    let reader = Reader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let format = reader
+        .format()
+        .and_then(Format::from_image_format)
+        .context("unsupported image format")?;
+    let (width, height) = reader.into_dimensions()?;
+
+    Ok(ImageInfo {
+        width,
+        height,
+        format,
+    })
+}
+
+pub fn transform(bytes: &[u8], opts: TransformOptions) -> Result<Vec<u8>, Error> {
+    let (mut image, input_format) = decode(bytes)?;
+
+    if let Some(crop) = opts.crop {
+        let x_end = crop.x.checked_add(crop.width);
+        let y_end = crop.y.checked_add(crop.height);
+
+        if crop.width == 0
+            || crop.height == 0
+            || x_end.map_or(true, |it| it > image.width())
+            || y_end.map_or(true, |it| it > image.height())
+        {
+            bail!("crop is out of the bounds of the image");
+        }
+
+        image = image.crop_imm(crop.x, crop.y, crop.width, crop.height);
+    }
+
+    if let Some(resize) = opts.resize {
+        image = resize_image(&image, resize)?;
+    }
+
+    let mut buf = Cursor::new(Vec::new());
+
+    image.write_to(
+        &mut buf,
+        opts.format
+            .unwrap_or(input_format)
+            .output_format(opts.quality)?,
+    )?;
+
+    Ok(buf.into_inner())
+}
+
+fn resize_image(image: &DynamicImage, resize: Resize) -> Result<DynamicImage, Error> {
+    let (width, height) = match (resize.width, resize.height) {
+        (None, None) => bail!("resize needs a width or a height"),
+        (Some(width), Some(height)) => (width, height),
+        // NOTE: A missing side follows the aspect ratio of the image.
+        (Some(width), None) => (width, scale(image.height(), width, image.width())),
+        (None, Some(height)) => (scale(image.width(), height, image.height()), height),
+    };
+
+    if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        bail!(
+            "resize must be between 1 and {} pixels wide and high",
+            MAX_DIMENSION
+        );
+    }
+
+    Ok(match resize.fit {
+        Fit::Contain => image.resize(width, height, FilterType::Lanczos3),
+        Fit::Cover => image.resize_to_fill(width, height, FilterType::Lanczos3),
+        Fit::Fill => image.resize_exact(width, height, FilterType::Lanczos3),
+    })
+}
+
+fn scale(side: u32, numerator: u32, denominator: u32) -> u32 {
+    ((side as u64 * numerator as u64) / denominator.max(1) as u64).clamp(1, u32::MAX as u64) as u32
+}
+
+#[cfg(test)]
+mod test {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut buf = Cursor::new(Vec::new());
+
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([255, 0, 0])))
+            .write_to(&mut buf, ImageOutputFormat::Png)
+            .unwrap();
+
+        buf.into_inner()
+    }
+
+    #[test]
+    fn test_transform_crops_then_resizes() {
+        let out = transform(
+            &png(40, 20),
+            TransformOptions {
+                crop: Some(Crop {
+                    x: 10,
+                    y: 0,
+                    width: 20,
+                    height: 20,
+                }),
+                resize: Some(Resize {
+                    width: Some(5),
+                    height: None,
+                    fit: Fit::Contain,
+                }),
+                format: Some(Format::Jpeg),
+                quality: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            info(&out).unwrap(),
+            ImageInfo {
+                width: 5,
+                height: 5,
+                format: Format::Jpeg,
+            }
+        );
+    }
+
+    #[test]
+    fn test_transform_rejects_crop_out_of_bounds() {
+        let err = transform(
+            &png(4, 4),
+            TransformOptions {
+                crop: Some(Crop {
+                    x: 2,
+                    y: 2,
+                    width: 4,
+                    height: 1,
+                }),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("out of the bounds"));
+    }
+}
@@ -20,4 +20,6 @@ pub struct RuntimeProviders {
     pub static_files: EszipStaticFiles,
     pub npm_snapshot: Option<ValidSerializedNpmResolutionSnapshot>,
     pub vfs_path: PathBuf,
+    /// Modules in the eszip, not counting npm packages and static files.
+    pub module_count: usize,
 }
@@ -92,6 +92,13 @@ pub async fn create_module_loader_for_eszip(
 
     let snapshot = eszip.take_npm_snapshot();
     let static_files = extract_static_files_from_eszip(&eszip).await;
+
+    // NOTE: Internal keys and static files are not URLs.
+    let module_count = eszip
+        .specifiers()
+        .iter()
+        .filter(|it| Url::parse(it).is_ok())
+        .count();
     let vfs_root_dir_path = npm_cache_dir.registry_folder(&npm_registry_url);
 
     let (fs, vfs) = {
@@ -198,6 +205,7 @@ pub async fn create_module_loader_for_eszip(
         static_files,
         npm_snapshot: snapshot,
         vfs_path: vfs_root_dir_path,
+        module_count,
     })
 }
 
@@ -1,5 +1,11 @@
-use deno_core::op2;
+use deno_core::error::{custom_error, AnyError};
+use deno_core::{op2, OpState, ToJsBuffer};
+use sb_core::permissions::Permissions;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::process::Stdio;
+use std::rc::Rc;
 
 pub type EnvVars = HashMap<String, String>;
 
@@ -49,9 +55,85 @@ fn op_system_memory_info() -> Option<MemInfo> {
     Some(mem_info)
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpawnArgs {
+    cmd: String,
+    args: Vec<String>,
+    cwd: Option<String>,
+    clear_env: bool,
+    env: Vec<(String, String)>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpawnOutput {
+    code: i32,
+    signal: Option<i32>,
+    stdout: ToJsBuffer,
+    stderr: ToJsBuffer,
+}
+
+#[op2(async)]
+#[serde]
+async fn op_spawn_output(
+    state: Rc<RefCell<OpState>>,
+    #[serde] args: SpawnArgs,
+) -> Result<SpawnOutput, AnyError> {
+    state
+        .borrow_mut()
+        .borrow_mut::<Permissions>()
+        .check_run(&args.cmd, "Deno.Command().output()")?;
+
+    let mut command = tokio::process::Command::new(&args.cmd);
+
+    command
+        .args(&args.args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    if let Some(cwd) = args.cwd {
+        command.current_dir(cwd);
+    }
+
+    if args.clear_env {
+        command.env_clear();
+    }
+
+    command.envs(args.env);
+
+    let output = command.output().await?;
+
+    #[cfg(unix)]
+    let signal = std::os::unix::process::ExitStatusExt::signal(&output.status);
+    #[cfg(not(unix))]
+    let signal = None;
+
+    Ok(SpawnOutput {
+        code: output.status.code().unwrap_or(1),
+        signal,
+        stdout: output.stdout.into(),
+        stderr: output.stderr.into(),
+    })
+}
+
+#[op2]
+fn op_ffi_dlopen(state: &mut OpState, #[string] _path: &str) -> Result<(), AnyError> {
+    state
+        .borrow_mut::<Permissions>()
+        .check_ffi("Deno.dlopen()")?;
+
+    Err(custom_error(
+        "NotSupported",
+        "FFI is not supported by this runtime",
+    ))
+}
+
 deno_core::extension!(
     sb_os,
-    ops = [op_system_memory_info],
+    ops = [op_system_memory_info, op_spawn_output, op_ffi_dlopen],
     esm_entry_point = "ext:sb_os/os.js",
     esm = ["os.js"]
 );
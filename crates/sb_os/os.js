@@ -1,11 +1,33 @@
+import { core } from 'ext:core/mod.js';
+
+const ops = core.ops;
+
 class DenoCommand {
 	constructor(command, options) {
 		this.command = command;
 		this.options = options;
 	}
 
+	// NOTE: Whether this is allowed is decided by the permissions of the
+	// worker. User workers are denied unless they were created with
+	// `allowSubprocess`.
 	async output() {
-		throw new Error('Spawning subprocesses is not allowed on Supabase Edge Runtime.');
+		const { args = [], cwd, clearEnv = false, env = {} } = this.options ?? {};
+		const { code, signal, stdout, stderr } = await ops.op_spawn_output({
+			cmd: String(this.command),
+			args: args.map(String),
+			cwd: cwd === undefined ? null : String(cwd),
+			clearEnv,
+			env: Object.entries(env).map(([k, v]) => [k, String(v)]),
+		});
+
+		return {
+			success: code === 0,
+			code,
+			signal,
+			stdout,
+			stderr,
+		};
 	}
 
 	outputSync() {
@@ -17,6 +39,10 @@ class DenoCommand {
 	}
 }
 
+function dlopen(path, _symbols) {
+	return ops.op_ffi_dlopen(String(path));
+}
+
 const os_start_time = Date.now();
 
 const osCalls = {
@@ -37,6 +63,7 @@ const osCalls = {
 	}),
 	consoleSize: () => ({ columns: 80, rows: 24 }),
 	command: DenoCommand,
+	dlopen,
 	networkInterfaces: () => [
 		{
 			'family': 'IPv4',
@@ -0,0 +1,257 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Error};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::ClientConfig;
+use serde::Serialize;
+
+/// A message a worker published, waiting to be delivered.
+#[derive(Debug, Clone, Serialize)]
+pub struct Message {
+    pub topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub payload: String,
+}
+
+/// Where messages are delivered to, picked by the scheme of the url:
+///
+/// - `nats://host:port` (or `tls://`) publishes each message to the subject
+///   named after its topic. NATS has no keys, so they are left out.
+/// - `kafka://host:port,host:port` produces each message to its topic, on
+///   the partition its key hashes to.
+/// - `http://` and `https://` POST each batch as JSON to the url.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendConfig {
+    Nats { url: String },
+    Kafka { brokers: Vec<String> },
+    Webhook { url: String },
+}
+
+impl BackendConfig {
+    pub fn parse(url: &str) -> Result<Self, Error> {
+        let Some((scheme, rest)) = url.split_once("://") else {
+            bail!("publisher url has no scheme: {}", url);
+        };
+
+        Ok(match scheme {
+            "nats" | "tls" => Self::Nats {
+                url: url.to_string(),
+            },
+            "kafka" => {
+                let brokers = rest
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|it| !it.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>();
+
+                if brokers.is_empty() {
+                    bail!("publisher url has no kafka brokers: {}", url);
+                }
+
+                Self::Kafka { brokers }
+            }
+            "http" | "https" => Self::Webhook {
+                url: url.to_string(),
+            },
+            _ => bail!("unsupported publisher scheme: {}", scheme),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookBody<'a> {
+    messages: &'a [Message],
+}
+
+/// Connection to the configured backend. It is established on the first
+/// batch and again after a batch failed.
+pub(crate) struct Backend {
+    kind: BackendKind,
+    send_timeout: Duration,
+}
+
+enum BackendKind {
+    Nats {
+        url: String,
+        client: Option<async_nats::Client>,
+    },
+    Kafka {
+        brokers: Vec<String>,
+        producer: Option<FutureProducer>,
+    },
+    Webhook {
+        url: String,
+        client: reqwest::Client,
+    },
+}
+
+impl Backend {
+    pub(crate) fn new(config: BackendConfig, send_timeout: Duration) -> Result<Self, Error> {
+        let kind = match config {
+            BackendConfig::Nats { url } => BackendKind::Nats { url, client: None },
+            BackendConfig::Kafka { brokers } => BackendKind::Kafka {
+                brokers,
+                producer: None,
+            },
+            BackendConfig::Webhook { url } => BackendKind::Webhook {
+                url,
+                client: reqwest::Client::builder()
+                    .connect_timeout(send_timeout)
+                    .timeout(send_timeout)
+                    .build()?,
+            },
+        };
+
+        Ok(Self { kind, send_timeout })
+    }
+
+    /// Delivers the whole batch. A batch that fails may have been delivered
+    /// in part, so retrying it gives at-least-once delivery.
+    pub(crate) async fn send(&mut self, batch: &[Message]) -> Result<(), Error> {
+        let send_timeout = self.send_timeout;
+        let send = async {
+            match &mut self.kind {
+                BackendKind::Nats { url, client } => send_nats(url, client, batch).await,
+                BackendKind::Kafka { brokers, producer } => {
+                    send_kafka(brokers, producer, batch, send_timeout).await
+                }
+                BackendKind::Webhook { url, client } => send_webhook(url, client, batch).await,
+            }
+        };
+
+        // NOTE: A broker that stopped answering would otherwise hold the
+        // batch, and every message queued behind it, forever.
+        let result = tokio::time::timeout(send_timeout, send)
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("timed out after {:?}", send_timeout)));
+
+        if result.is_err() {
+            self.reset();
+        }
+
+        result
+    }
+
+    fn reset(&mut self) {
+        match &mut self.kind {
+            BackendKind::Nats { client, .. } => *client = None,
+            BackendKind::Kafka { producer, .. } => *producer = None,
+            BackendKind::Webhook { .. } => {}
+        }
+    }
+}
+
+async fn send_nats(
+    url: &str,
+    client: &mut Option<async_nats::Client>,
+    batch: &[Message],
+) -> Result<(), Error> {
+    if client.is_none() {
+        *client = Some(
+            async_nats::connect(url)
+                .await
+                .context("could not connect to nats")?,
+        );
+    }
+
+    let client = client.as_ref().unwrap();
+
+    for msg in batch {
+        client
+            .publish(msg.topic.clone(), msg.payload.clone().into())
+            .await?;
+    }
+
+    Ok(client.flush().await?)
+}
+
+async fn send_kafka(
+    brokers: &[String],
+    producer: &mut Option<FutureProducer>,
+    batch: &[Message],
+    send_timeout: Duration,
+) -> Result<(), Error> {
+    if producer.is_none() {
+        // NOTE: The producer gives up on the messages of a batch once it timed
+        // out, rather than still delivering them alongside the retry.
+        *producer = Some(
+            ClientConfig::new()
+                .set("bootstrap.servers", brokers.join(","))
+                .set(
+                    "message.timeout.ms",
+                    send_timeout.as_millis().max(1).to_string(),
+                )
+                .create()
+                .context("could not create the kafka producer")?,
+        );
+    }
+
+    let producer = producer.as_ref().unwrap();
+    let mut deliveries = Vec::with_capacity(batch.len());
+
+    // NOTE: The whole batch is queued before waiting on any delivery. The
+    // default partitioner hashes keys, and messages of a partition are sent in
+    // the order they were queued in.
+    for msg in batch {
+        let mut record = FutureRecord::<str, str>::to(&msg.topic).payload(&msg.payload);
+
+        if let Some(key) = msg.key.as_deref() {
+            record = record.key(key);
+        }
+
+        deliveries.push(producer.send_result(record).map_err(|(err, _)| err)?);
+    }
+
+    for delivery in deliveries {
+        delivery
+            .await
+            .context("kafka producer went away")?
+            .map_err(|(err, _)| err)?;
+    }
+
+    Ok(())
+}
+
+async fn send_webhook(url: &str, client: &reqwest::Client, batch: &[Message]) -> Result<(), Error> {
+    client
+        .post(url)
+        .json(&WebhookBody { messages: batch })
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backend_config_is_picked_by_scheme() {
+        assert_eq!(
+            BackendConfig::parse("nats://localhost:4222").unwrap(),
+            BackendConfig::Nats {
+                url: "nats://localhost:4222".to_string()
+            }
+        );
+        assert_eq!(
+            BackendConfig::parse("kafka://a:9092, b:9092").unwrap(),
+            BackendConfig::Kafka {
+                brokers: vec!["a:9092".to_string(), "b:9092".to_string()]
+            }
+        );
+        assert_eq!(
+            BackendConfig::parse("https://example.com/events").unwrap(),
+            BackendConfig::Webhook {
+                url: "https://example.com/events".to_string()
+            }
+        );
+
+        assert!(BackendConfig::parse("kafka://").is_err());
+        assert!(BackendConfig::parse("amqp://localhost").is_err());
+        assert!(BackendConfig::parse("localhost:4222").is_err());
+    }
+}
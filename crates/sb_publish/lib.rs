@@ -0,0 +1,129 @@
+mod backend;
+mod publisher;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::Error;
+use deno_core::error::{custom_error, AnyError};
+use deno_core::{op2, OpState};
+use sb_core::util::shared_backend::SharedBackend;
+use serde::Deserialize;
+
+use crate::backend::Message;
+use crate::publisher::EnqueueError;
+
+pub use crate::backend::BackendConfig;
+pub use crate::publisher::{PublishQuota, Publisher, PublisherConfig, PublisherMetrics};
+
+static PUBLISHER: SharedBackend<Publisher> = SharedBackend::new("publisher");
+
+/// Starts delivering the messages workers publish. Workers can only publish
+/// once this has been called.
+pub fn init(config: PublisherConfig) -> Result<(), Error> {
+    PUBLISHER.init(Publisher::new(config)?)
+}
+
+pub fn publisher() -> Option<&'static Publisher> {
+    PUBLISHER.get()
+}
+
+pub fn quota_for_worker(max_queued: usize) -> Option<PublishQuota> {
+    PUBLISHER.quota_for_worker(max_queued, |it| it.queue_size_per_worker, PublishQuota::new)
+}
+
+/// Topics a user worker may publish to. A topic ending with `*` grants every
+/// topic starting with what comes before it. Workers without a policy in
+/// their op state, like the main worker, may publish to any topic.
+#[derive(Debug, Clone, Default)]
+pub struct PublishPolicy {
+    pub topics: Vec<String>,
+}
+
+impl PublishPolicy {
+    pub fn allows(&self, topic: &str) -> bool {
+        self.topics.iter().any(|it| match it.strip_suffix('*') {
+            Some(prefix) => topic.starts_with(prefix),
+            None => it == topic,
+        })
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishOptions {
+    /// Messages sharing a key are kept in order, where the backend supports
+    /// it.
+    key: Option<String>,
+}
+
+/// Resolves once the message is queued, not once it is delivered. Delivery
+/// is retried by the runtime.
+#[op2(async)]
+async fn op_publish(
+    state: Rc<RefCell<OpState>>,
+    #[string] topic: String,
+    #[string] payload: String,
+    #[serde] opts: PublishOptions,
+) -> Result<(), AnyError> {
+    let publisher = PUBLISHER.require()?;
+
+    if let Some(policy) = state.borrow().try_borrow::<PublishPolicy>() {
+        if !policy.allows(&topic) {
+            return Err(custom_error(
+                "PermissionDenied",
+                format!("topic is not granted to the worker: {}", topic),
+            ));
+        }
+    }
+
+    let maybe_quota = state.borrow().try_borrow::<PublishQuota>().cloned();
+    let msg = Message {
+        topic,
+        key: opts.key,
+        payload,
+    };
+
+    publisher
+        .enqueue(msg, maybe_quota)
+        .await
+        .map_err(|err| match err {
+            EnqueueError::Full => custom_error("Busy", "publisher queue is full"),
+            EnqueueError::QuotaExceeded(max) => custom_error(
+                "Busy",
+                format!("worker has {} messages waiting for delivery already", max),
+            ),
+            EnqueueError::Closed => custom_error("Interrupted", "publisher is not running"),
+        })
+}
+
+#[op2]
+#[serde]
+fn op_publish_metrics() -> Result<PublisherMetrics, AnyError> {
+    Ok(PUBLISHER.require()?.metrics())
+}
+
+deno_core::extension!(
+    sb_publish,
+    ops = [op_publish, op_publish_metrics],
+    esm_entry_point = "ext:sb_publish/publish.js",
+    esm = ["publish.js"]
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_publish_policy_matches_topics() {
+        let policy = PublishPolicy {
+            topics: vec!["orders".to_string(), "audit.*".to_string()],
+        };
+
+        assert!(policy.allows("orders"));
+        assert!(policy.allows("audit.login"));
+        assert!(!policy.allows("orders.created"));
+        assert!(!policy.allows("audit"));
+        assert!(!PublishPolicy::default().allows("orders"));
+    }
+}
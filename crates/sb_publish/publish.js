@@ -0,0 +1,22 @@
+const ops = globalThis.Deno.core.ops;
+
+/**
+ * Publishes messages to the broker the runtime was configured with. The
+ * runtime batches and retries the delivery, so a resolved call means the
+ * message was queued. Payloads that are not strings are sent as JSON.
+ *
+ * ```js
+ * await Supabase.publish('orders.created', order, { key: order.id });
+ * ```
+ */
+function publish(topic, payload, opts = {}) {
+	return ops.op_publish(
+		String(topic),
+		typeof payload === 'string' ? payload : JSON.stringify(payload),
+		opts,
+	);
+}
+
+publish.metrics = () => ops.op_publish_metrics();
+
+export { publish };
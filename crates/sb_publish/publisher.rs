@@ -0,0 +1,275 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Error;
+use log::{error, warn};
+use serde::Serialize;
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+use crate::backend::{Backend, BackendConfig, Message};
+
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct PublisherConfig {
+    pub backend: BackendConfig,
+    /// Most messages delivered in a single batch.
+    pub batch_size: usize,
+    /// How long the first message of a batch waits for more to join it.
+    pub batch_interval: Duration,
+    /// How many times a failed batch is retried before it is dropped.
+    pub max_retries: u32,
+    /// Messages that may wait for delivery across every worker. Workers
+    /// publishing into a full queue wait for room.
+    pub queue_size: usize,
+    /// How many messages a single user worker may have waiting for
+    /// delivery, unless the worker was created with its own quota.
+    pub queue_size_per_worker: usize,
+    /// How long a worker may wait for room in the queue.
+    pub enqueue_timeout: Duration,
+    /// How long a single attempt at delivering a batch may take.
+    pub send_timeout: Duration,
+}
+
+#[derive(Debug, Default)]
+struct PublisherMetricsInner {
+    published: AtomicUsize,
+    dropped: AtomicUsize,
+    batches: AtomicUsize,
+    retries: AtomicUsize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublisherMetrics {
+    pub queue_size: usize,
+    pub queued: usize,
+    pub published: usize,
+    pub dropped: usize,
+    pub batches: usize,
+    pub retries: usize,
+}
+
+/// A message in the queue, along with the room it takes in the quota of the
+/// worker that published it.
+struct Queued {
+    msg: Message,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+pub struct Publisher {
+    tx: mpsc::Sender<Queued>,
+    queue_size: usize,
+    pub(crate) queue_size_per_worker: usize,
+    enqueue_timeout: Duration,
+    metrics: Arc<PublisherMetricsInner>,
+
+    // NOTE: Batches are delivered on a runtime of their own, so delivery
+    // goes on regardless of the workers that published the messages.
+    _rt: Runtime,
+}
+
+pub(crate) enum EnqueueError {
+    Full,
+    QuotaExceeded(usize),
+    Closed,
+}
+
+impl Publisher {
+    pub fn new(config: PublisherConfig) -> Result<Self, Error> {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("sb-publisher")
+            .enable_all()
+            .build()?;
+
+        let queue_size = config.queue_size.max(1);
+        let (tx, rx) = mpsc::channel(queue_size);
+        let metrics = Arc::<PublisherMetricsInner>::default();
+
+        rt.spawn(run(
+            Backend::new(config.backend.clone(), config.send_timeout)?,
+            rx,
+            config.clone(),
+            metrics.clone(),
+        ));
+
+        Ok(Self {
+            tx,
+            queue_size,
+            queue_size_per_worker: config.queue_size_per_worker,
+            enqueue_timeout: config.enqueue_timeout,
+            metrics,
+            _rt: rt,
+        })
+    }
+
+    /// Queues a message for delivery, waiting for room if the queue or the
+    /// quota of the worker is full.
+    pub(crate) async fn enqueue(
+        &self,
+        msg: Message,
+        maybe_quota: Option<PublishQuota>,
+    ) -> Result<(), EnqueueError> {
+        let deadline = Instant::now() + self.enqueue_timeout;
+        let permit = match maybe_quota {
+            Some(quota) => Some(quota.acquire(deadline).await?),
+            None => None,
+        };
+
+        let queued = Queued { msg, permit };
+
+        match tokio::time::timeout_at(deadline, self.tx.send(queued)).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(EnqueueError::Closed),
+            Err(_) => Err(EnqueueError::Full),
+        }
+    }
+
+    pub fn metrics(&self) -> PublisherMetrics {
+        PublisherMetrics {
+            queue_size: self.queue_size,
+            queued: self.queue_size - self.tx.capacity(),
+            published: self.metrics.published.load(Ordering::Relaxed),
+            dropped: self.metrics.dropped.load(Ordering::Relaxed),
+            batches: self.metrics.batches.load(Ordering::Relaxed),
+            retries: self.metrics.retries.load(Ordering::Relaxed),
+        }
+    }
+}
+
+async fn run(
+    mut backend: Backend,
+    mut rx: mpsc::Receiver<Queued>,
+    config: PublisherConfig,
+    metrics: Arc<PublisherMetricsInner>,
+) {
+    let batch_size = config.batch_size.max(1);
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut permits = Vec::with_capacity(batch_size);
+
+    while let Some(queued) = rx.recv().await {
+        let deadline = Instant::now() + config.batch_interval;
+
+        batch.push(queued.msg);
+        permits.push(queued.permit);
+
+        while batch.len() < batch_size {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(queued)) => {
+                    batch.push(queued.msg);
+                    permits.push(queued.permit);
+                }
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        deliver(&mut backend, &batch, config.max_retries, &metrics).await;
+
+        // NOTE: Workers get their room back once their messages are delivered
+        // or dropped, not once they leave the queue.
+        batch.clear();
+        permits.clear();
+    }
+}
+
+/// Sends a batch until it goes through or runs out of retries. The queue
+/// fills up in the meantime, which is what holds publishing workers back.
+async fn deliver(
+    backend: &mut Backend,
+    batch: &[Message],
+    max_retries: u32,
+    metrics: &PublisherMetricsInner,
+) {
+    let mut attempt = 0;
+
+    metrics.batches.fetch_add(1, Ordering::Relaxed);
+
+    loop {
+        match backend.send(batch).await {
+            Ok(()) => {
+                metrics.published.fetch_add(batch.len(), Ordering::Relaxed);
+                return;
+            }
+
+            Err(err) if attempt < max_retries => {
+                let delay = RETRY_BASE_DELAY
+                    .saturating_mul(2u32.saturating_pow(attempt))
+                    .min(RETRY_MAX_DELAY);
+
+                attempt += 1;
+                metrics.retries.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "could not publish {} messages, retrying in {:?}: {:#}",
+                    batch.len(),
+                    delay,
+                    err
+                );
+
+                tokio::time::sleep(delay).await;
+            }
+
+            Err(err) => {
+                metrics.dropped.fetch_add(batch.len(), Ordering::Relaxed);
+                error!(
+                    "dropped {} messages after {} retries: {:#}",
+                    batch.len(),
+                    max_retries,
+                    err
+                );
+
+                return;
+            }
+        }
+    }
+}
+
+/// Bounds the messages a single worker may have waiting for delivery, so
+/// that one worker can't take the whole queue.
+#[derive(Clone)]
+pub struct PublishQuota {
+    max: usize,
+    permits: Arc<Semaphore>,
+}
+
+impl PublishQuota {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            permits: Arc::new(Semaphore::new(max)),
+        }
+    }
+
+    async fn acquire(&self, deadline: Instant) -> Result<OwnedSemaphorePermit, EnqueueError> {
+        match tokio::time::timeout_at(deadline, self.permits.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            Ok(Err(_)) => Err(EnqueueError::Closed),
+            Err(_) => Err(EnqueueError::QuotaExceeded(self.max)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_publish_quota_is_returned_once_dropped() {
+        let quota = PublishQuota::new(1);
+        let deadline = || Instant::now() + Duration::from_millis(10);
+        let permit = quota.acquire(deadline()).await.ok().unwrap();
+
+        assert!(matches!(
+            quota.acquire(deadline()).await,
+            Err(EnqueueError::QuotaExceeded(1))
+        ));
+
+        drop(permit);
+
+        assert!(quota.acquire(deadline()).await.is_ok());
+    }
+}
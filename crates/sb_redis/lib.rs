@@ -0,0 +1,330 @@
+mod pool;
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::{bail, Error};
+use deno_core::error::{custom_error, AnyError};
+use deno_core::{
+    op2, AsyncRefCell, CancelFuture, CancelHandle, OpState, RcRef, Resource, ResourceId,
+};
+use sb_core::util::shared_backend::SharedBackend;
+use serde::{Deserialize, Serialize};
+
+use crate::pool::Subscription;
+
+pub use crate::pool::{
+    RedisPool, RedisPoolConfig, RedisPoolMetrics, RedisQuota, RedisQuotaMetrics,
+    SubscriptionMessage,
+};
+
+static REDIS_POOL: SharedBackend<RedisPool> = SharedBackend::new("redis pool");
+
+/// Sets up the pool shared by every worker of the process. Workers can only
+/// send commands once this has been called.
+pub fn init(config: RedisPoolConfig) -> Result<(), Error> {
+    REDIS_POOL.init(RedisPool::new(config)?)
+}
+
+pub fn pool() -> Option<&'static RedisPool> {
+    REDIS_POOL.get()
+}
+
+pub fn quota_for_worker(commands_per_second: u32) -> Option<RedisQuota> {
+    REDIS_POOL.quota_for_worker(
+        commands_per_second,
+        |it| it.commands_per_second_per_worker,
+        RedisQuota::new,
+    )
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedisMetrics {
+    pool: RedisPoolMetrics,
+    worker: Option<RedisQuotaMetrics>,
+}
+
+/// Prefixes the keys and channels of a user worker, so that workers can't
+/// reach each other's. User workers without a namespace can't send commands.
+pub struct RedisNamespace(Option<String>);
+
+impl RedisNamespace {
+    pub fn new(maybe_name: Option<String>) -> Result<Self, Error> {
+        if let Some(name) = maybe_name.as_deref() {
+            // NOTE: A separator in the name would let `a` reach the keys of
+            // `a:b` through the key `b:...`.
+            if name.is_empty() || name.contains(':') {
+                bail!("invalid redis namespace: {:?}", name);
+            }
+        }
+
+        Ok(Self(maybe_name))
+    }
+}
+
+/// The prefix the commands of a worker are scoped to. Workers without a
+/// namespace, like the main worker, reach every key.
+#[derive(Clone, Default)]
+struct Scope(Option<String>);
+
+impl Scope {
+    fn name(&self, name: String) -> String {
+        match self.0.as_deref() {
+            Some(prefix) => format!("{}:{}", prefix, name),
+            None => name,
+        }
+    }
+
+    fn strip(&self, name: String) -> String {
+        match self.0.as_deref() {
+            Some(prefix) => name
+                .strip_prefix(prefix)
+                .and_then(|it| it.strip_prefix(':'))
+                .map(str::to_string)
+                .unwrap_or(name),
+            None => name,
+        }
+    }
+}
+
+/// Takes a command out of the quota of the worker. Workers without a quota,
+/// like the main worker, are not limited.
+fn acquire(state: &RefCell<OpState>) -> Result<(&'static RedisPool, Scope), AnyError> {
+    let pool = REDIS_POOL.require()?;
+    let mut state = state.borrow_mut();
+    let scope = match state.try_borrow::<RedisNamespace>() {
+        Some(RedisNamespace(Some(prefix))) => Scope(Some(prefix.clone())),
+        Some(RedisNamespace(None)) => {
+            return Err(custom_error(
+                "PermissionDenied",
+                "user workers need a redis namespace to send redis commands",
+            ))
+        }
+        None => Scope::default(),
+    };
+
+    if let Some(quota) = state.try_borrow_mut::<RedisQuota>() {
+        if !quota.try_acquire() {
+            return Err(custom_error(
+                "RateLimited",
+                format!(
+                    "worker exceeded its quota of {} redis commands per second",
+                    quota.commands_per_second()
+                ),
+            ));
+        }
+    }
+
+    Ok((pool, scope))
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SetOptions {
+    /// Expires the key after this many seconds.
+    ex: Option<u64>,
+}
+
+#[op2(async)]
+#[serde]
+async fn op_redis_get(
+    state: Rc<RefCell<OpState>>,
+    #[string] key: String,
+) -> Result<Option<String>, AnyError> {
+    let (pool, scope) = acquire(&state)?;
+
+    Ok(pool
+        .query(redis::cmd("GET").arg(scope.name(key)).clone())
+        .await?)
+}
+
+#[op2(async)]
+async fn op_redis_set(
+    state: Rc<RefCell<OpState>>,
+    #[string] key: String,
+    #[string] value: String,
+    #[serde] opts: SetOptions,
+) -> Result<(), AnyError> {
+    let (pool, scope) = acquire(&state)?;
+    let mut cmd = redis::cmd("SET");
+
+    cmd.arg(scope.name(key)).arg(value);
+
+    if let Some(ex) = opts.ex {
+        cmd.arg("EX").arg(ex);
+    }
+
+    Ok(pool.query(cmd).await?)
+}
+
+#[op2(async)]
+#[serde]
+async fn op_redis_del(
+    state: Rc<RefCell<OpState>>,
+    #[serde] keys: Vec<String>,
+) -> Result<u64, AnyError> {
+    let (pool, scope) = acquire(&state)?;
+    let keys = keys
+        .into_iter()
+        .map(|it| scope.name(it))
+        .collect::<Vec<_>>();
+
+    Ok(pool.query(redis::cmd("DEL").arg(keys).clone()).await?)
+}
+
+#[op2(async)]
+#[serde]
+async fn op_redis_incr(
+    state: Rc<RefCell<OpState>>,
+    #[string] key: String,
+    #[serde] by: i64,
+) -> Result<i64, AnyError> {
+    let (pool, scope) = acquire(&state)?;
+
+    Ok(pool
+        .query(redis::cmd("INCRBY").arg(scope.name(key)).arg(by).clone())
+        .await?)
+}
+
+#[op2(async)]
+async fn op_redis_expire(
+    state: Rc<RefCell<OpState>>,
+    #[string] key: String,
+    #[serde] seconds: u64,
+) -> Result<bool, AnyError> {
+    let (pool, scope) = acquire(&state)?;
+
+    Ok(pool
+        .query(
+            redis::cmd("EXPIRE")
+                .arg(scope.name(key))
+                .arg(seconds)
+                .clone(),
+        )
+        .await?)
+}
+
+#[op2(async)]
+#[serde]
+async fn op_redis_publish(
+    state: Rc<RefCell<OpState>>,
+    #[string] channel: String,
+    #[string] message: String,
+) -> Result<u64, AnyError> {
+    let (pool, scope) = acquire(&state)?;
+
+    Ok(pool
+        .query(
+            redis::cmd("PUBLISH")
+                .arg(scope.name(channel))
+                .arg(message)
+                .clone(),
+        )
+        .await?)
+}
+
+struct SubscriptionResource {
+    subscription: AsyncRefCell<Subscription>,
+    scope: Scope,
+    cancel: CancelHandle,
+}
+
+impl Resource for SubscriptionResource {
+    fn name(&self) -> Cow<str> {
+        "redisSubscription".into()
+    }
+
+    fn close(self: Rc<Self>) {
+        self.cancel.cancel();
+    }
+}
+
+#[op2(async)]
+#[smi]
+async fn op_redis_subscribe(
+    state: Rc<RefCell<OpState>>,
+    #[serde] channels: Vec<String>,
+) -> Result<ResourceId, AnyError> {
+    let (pool, scope) = acquire(&state)?;
+    let channels = channels.into_iter().map(|it| scope.name(it)).collect();
+    let subscription = pool.subscribe(channels).await?;
+
+    Ok(state.borrow_mut().resource_table.add(SubscriptionResource {
+        subscription: AsyncRefCell::new(subscription),
+        scope,
+        cancel: CancelHandle::default(),
+    }))
+}
+
+/// Waits for the next message of a subscription. Returns `None` once the
+/// subscription is closed.
+#[op2(async)]
+#[serde]
+async fn op_redis_next_message(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+) -> Result<Option<SubscriptionMessage>, AnyError> {
+    let resource = state
+        .borrow()
+        .resource_table
+        .get::<SubscriptionResource>(rid)?;
+    let mut subscription = RcRef::map(&resource, |it| &it.subscription)
+        .borrow_mut()
+        .await;
+    let cancel = RcRef::map(&resource, |it| &it.cancel);
+
+    let maybe_msg = subscription.next().or_cancel(cancel).await.ok().flatten();
+
+    Ok(maybe_msg.map(|it| SubscriptionMessage {
+        channel: resource.scope.strip(it.channel),
+        payload: it.payload,
+    }))
+}
+
+#[op2]
+#[serde]
+fn op_redis_metrics(state: &mut OpState) -> Result<RedisMetrics, AnyError> {
+    let pool = REDIS_POOL.require()?;
+
+    Ok(RedisMetrics {
+        pool: pool.metrics(),
+        worker: state.try_borrow::<RedisQuota>().map(RedisQuota::metrics),
+    })
+}
+
+deno_core::extension!(
+    sb_redis,
+    ops = [
+        op_redis_get,
+        op_redis_set,
+        op_redis_del,
+        op_redis_incr,
+        op_redis_expire,
+        op_redis_publish,
+        op_redis_subscribe,
+        op_redis_next_message,
+        op_redis_metrics
+    ],
+    esm_entry_point = "ext:sb_redis/redis.js",
+    esm = ["redis.js"]
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespace_scopes_names() {
+        assert!(RedisNamespace::new(None).is_ok());
+        assert!(RedisNamespace::new(Some("".into())).is_err());
+        assert!(RedisNamespace::new(Some("a:b".into())).is_err());
+
+        let scope = Scope(Some("a".into()));
+
+        assert_eq!(scope.name("key".into()), "a:key");
+        assert_eq!(scope.strip("a:events".into()), "events");
+        assert_eq!(Scope::default().name("key".into()), "key");
+    }
+}
@@ -0,0 +1,310 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Error};
+use futures_util::StreamExt;
+use log::error;
+use redis::aio::MultiplexedConnection;
+use redis::{Cmd, FromRedisValue, RedisError};
+use sb_core::util::rate_limit::RateLimiter;
+use serde::Serialize;
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+
+/// Messages of a subscription buffered for the worker. Messages arriving
+/// while the buffer is full are dropped.
+const SUBSCRIPTION_BUFFER: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct RedisPoolConfig {
+    pub url: String,
+    /// How many multiplexed connections commands are spread across.
+    pub connections: usize,
+    /// Upper bound of the subscriptions open across every worker. Each one
+    /// takes a connection of its own.
+    pub max_subscriptions: usize,
+    /// How many commands a single user worker may send per second, unless
+    /// the worker was created with its own quota.
+    pub commands_per_second_per_worker: u32,
+    /// How long a command may wait for a connection to be established.
+    pub connect_timeout: Duration,
+    /// How long a command may wait for its reply once sent.
+    pub command_timeout: Duration,
+}
+
+#[derive(Debug, Default)]
+struct RedisPoolMetricsInner {
+    open: AtomicUsize,
+    subscriptions: AtomicUsize,
+    commands: AtomicUsize,
+    errors: AtomicUsize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedisPoolMetrics {
+    pub connections: usize,
+    pub open: usize,
+    pub max_subscriptions: usize,
+    pub subscriptions: usize,
+    pub commands: usize,
+    pub errors: usize,
+}
+
+pub struct RedisPool {
+    client: redis::Client,
+    connections: Vec<Mutex<Option<MultiplexedConnection>>>,
+    next: AtomicUsize,
+    max_subscriptions: usize,
+    subscription_permits: Arc<Semaphore>,
+    pub(crate) commands_per_second_per_worker: u32,
+    connect_timeout: Duration,
+    command_timeout: Duration,
+    metrics: RedisPoolMetricsInner,
+
+    // NOTE: Connections are driven by a runtime of their own since the pool
+    // outlives the runtime of any worker that happens to open them.
+    rt: Runtime,
+}
+
+impl RedisPool {
+    pub fn new(config: RedisPoolConfig) -> Result<Self, Error> {
+        let client = redis::Client::open(config.url.as_str()).context("invalid redis url")?;
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("sb-redis-pool")
+            .enable_all()
+            .build()?;
+
+        Ok(Self {
+            client,
+            connections: (0..config.connections.max(1))
+                .map(|_| Mutex::default())
+                .collect(),
+            next: AtomicUsize::new(0),
+            max_subscriptions: config.max_subscriptions,
+            subscription_permits: Arc::new(Semaphore::new(config.max_subscriptions)),
+            commands_per_second_per_worker: config.commands_per_second_per_worker,
+            connect_timeout: config.connect_timeout,
+            command_timeout: config.command_timeout,
+            metrics: RedisPoolMetricsInner::default(),
+            rt,
+        })
+    }
+
+    pub fn metrics(&self) -> RedisPoolMetrics {
+        RedisPoolMetrics {
+            connections: self.connections.len(),
+            open: self.metrics.open.load(Ordering::Relaxed),
+            max_subscriptions: self.max_subscriptions,
+            subscriptions: self.metrics.subscriptions.load(Ordering::Relaxed),
+            commands: self.metrics.commands.load(Ordering::Relaxed),
+            errors: self.metrics.errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns one of the connections of the pool in turn, connecting it
+    /// first if needed.
+    async fn connection(&'static self) -> Result<(usize, MultiplexedConnection), Error> {
+        let idx = self.next.fetch_add(1, Ordering::Relaxed) % self.connections.len();
+
+        if let Some(conn) = self.connections[idx].lock().unwrap().clone() {
+            return Ok((idx, conn));
+        }
+
+        let client = self.client.clone();
+        let conn = tokio::time::timeout(
+            self.connect_timeout,
+            self.rt
+                .spawn(async move { client.get_multiplexed_tokio_connection().await }),
+        )
+        .await
+        .map_err(|_| anyhow!("timed out connecting to redis"))???;
+
+        let mut slot = self.connections[idx].lock().unwrap();
+
+        // NOTE: Another command may have connected the slot in the meantime.
+        if let Some(conn) = slot.clone() {
+            return Ok((idx, conn));
+        }
+
+        self.metrics.open.fetch_add(1, Ordering::Relaxed);
+        *slot = Some(conn.clone());
+
+        Ok((idx, conn))
+    }
+
+    /// Sends a command over one of the connections. A connection that broke
+    /// is dropped, so the next command using its slot reconnects.
+    pub(crate) async fn query<T: FromRedisValue>(&'static self, cmd: Cmd) -> Result<T, Error> {
+        self.metrics.commands.fetch_add(1, Ordering::Relaxed);
+
+        let result = async {
+            let (idx, mut conn) = self.connection().await?;
+
+            tokio::time::timeout(self.command_timeout, cmd.query_async::<_, T>(&mut conn))
+                .await
+                .map_err(|_| anyhow!("timed out waiting for redis to reply"))?
+                .map_err(|err| self.on_error(idx, err))
+        }
+        .await;
+
+        if result.is_err() {
+            self.metrics.errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        result
+    }
+
+    fn on_error(&self, idx: usize, err: RedisError) -> Error {
+        let is_broken = err.is_connection_dropped() || err.is_io_error() || err.is_timeout();
+
+        if is_broken && self.connections[idx].lock().unwrap().take().is_some() {
+            self.metrics.open.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        err.into()
+    }
+
+    /// Subscribes to `channels` on a connection of its own. Messages are
+    /// forwarded until the subscription is dropped.
+    pub(crate) async fn subscribe(
+        &'static self,
+        channels: Vec<String>,
+    ) -> Result<Subscription, Error> {
+        let permit = self
+            .subscription_permits
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| anyhow!("too many redis subscriptions are open"))?;
+
+        let client = self.client.clone();
+        let mut pubsub = tokio::time::timeout(
+            self.connect_timeout,
+            self.rt.spawn(async move {
+                let mut pubsub = client.get_async_connection().await?.into_pubsub();
+
+                for channel in channels {
+                    pubsub.subscribe(channel).await?;
+                }
+
+                Ok::<_, RedisError>(pubsub)
+            }),
+        )
+        .await
+        .map_err(|_| anyhow!("timed out connecting to redis"))???;
+
+        let (tx, rx) = mpsc::channel(SUBSCRIPTION_BUFFER);
+        let task = self.rt.spawn(async move {
+            let mut messages = pubsub.on_message();
+
+            while let Some(msg) = messages.next().await {
+                let payload = match msg.get_payload::<String>() {
+                    Ok(it) => it,
+                    Err(err) => {
+                        error!("dropped a redis message that is not utf-8: {}", err);
+                        continue;
+                    }
+                };
+
+                let msg = SubscriptionMessage {
+                    channel: msg.get_channel_name().to_string(),
+                    payload,
+                };
+
+                if tx.try_send(msg).is_err() && tx.is_closed() {
+                    break;
+                }
+            }
+        });
+
+        self.metrics.subscriptions.fetch_add(1, Ordering::Relaxed);
+
+        Ok(Subscription {
+            pool: self,
+            rx,
+            task,
+            _permit: permit,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubscriptionMessage {
+    pub channel: String,
+    pub payload: String,
+}
+
+/// Messages of the channels a worker subscribed to. The connection is closed
+/// once this is dropped.
+pub(crate) struct Subscription {
+    pool: &'static RedisPool,
+    rx: mpsc::Receiver<SubscriptionMessage>,
+    task: JoinHandle<()>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Subscription {
+    pub(crate) async fn next(&mut self) -> Option<SubscriptionMessage> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.pool
+            .metrics
+            .subscriptions
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Bounds the rate of the commands a single worker may send.
+pub struct RedisQuota {
+    limiter: RateLimiter,
+    commands: usize,
+    rejected: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedisQuotaMetrics {
+    pub commands_per_second: u32,
+    pub commands: usize,
+    pub rejected: usize,
+}
+
+impl RedisQuota {
+    pub fn new(commands_per_second: u32) -> Self {
+        Self {
+            limiter: RateLimiter::new(commands_per_second),
+            commands: 0,
+            rejected: 0,
+        }
+    }
+
+    pub(crate) fn try_acquire(&mut self) -> bool {
+        if self.limiter.try_acquire() {
+            self.commands += 1;
+            true
+        } else {
+            self.rejected += 1;
+            false
+        }
+    }
+
+    pub fn commands_per_second(&self) -> u32 {
+        self.limiter.per_second()
+    }
+
+    pub fn metrics(&self) -> RedisQuotaMetrics {
+        RedisQuotaMetrics {
+            commands_per_second: self.limiter.per_second(),
+            commands: self.commands,
+            rejected: self.rejected,
+        }
+    }
+}
@@ -0,0 +1,86 @@
+const core = globalThis.Deno.core;
+const ops = core.ops;
+
+/**
+ * Sends commands through the Redis connections of the runtime, which are
+ * shared by every worker. Keys, values and messages are strings. The keys and
+ * channels of a user worker are scoped to the `redisNamespace` it was created
+ * with.
+ *
+ * ```js
+ * const hits = await Supabase.redis.incr(`hits:${path}`);
+ * await Supabase.redis.set('session', token, { ex: 60 });
+ *
+ * const sub = await Supabase.redis.subscribe(['events']);
+ * for await (const { channel, payload } of sub) {
+ *   console.log(channel, payload);
+ * }
+ * ```
+ */
+const redis = {
+	get(key) {
+		return ops.op_redis_get(String(key));
+	},
+
+	set(key, value, opts = {}) {
+		return ops.op_redis_set(String(key), String(value), opts);
+	},
+
+	del(...keys) {
+		return ops.op_redis_del(keys.map(String));
+	},
+
+	incr(key, by = 1) {
+		return ops.op_redis_incr(String(key), by);
+	},
+
+	expire(key, seconds) {
+		return ops.op_redis_expire(String(key), seconds);
+	},
+
+	publish(channel, message) {
+		return ops.op_redis_publish(String(channel), String(message));
+	},
+
+	/**
+	 * Subscribes to the channels on a connection of its own, until `close`
+	 * is called or the iteration stops.
+	 */
+	async subscribe(channels) {
+		const rid = await ops.op_redis_subscribe([channels].flat().map(String));
+		let closed = false;
+
+		const close = () => {
+			if (!closed) {
+				closed = true;
+				core.tryClose(rid);
+			}
+		};
+
+		return {
+			async *[Symbol.asyncIterator]() {
+				try {
+					while (!closed) {
+						const msg = await ops.op_redis_next_message(rid);
+
+						if (msg === null) {
+							return;
+						}
+
+						yield msg;
+					}
+				} finally {
+					close();
+				}
+			},
+
+			close,
+		};
+	},
+
+	metrics() {
+		return ops.op_redis_metrics();
+	},
+};
+
+export { redis };
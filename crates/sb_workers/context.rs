@@ -1,25 +1,45 @@
 use anyhow::{anyhow, Error};
 use deno_config::JsxImportSourceConfig;
-use deno_core::FastString;
+use deno_core::{serde_json, FastString};
 use enum_as_inner::EnumAsInner;
-use event_worker::events::{UncaughtExceptionEvent, WorkerEventWithMetadata};
+use event_worker::events::{
+    UncaughtExceptionEvent, WorkerEventWithMetadata, WorkerRequestStats, WorkerSecrets, WorkerUsage,
+};
 use hyper::{Body, Request, Response};
+use ipnet::IpNet;
+use sb_core::channel::{self, BoundedSender, ChannelKind, SendError};
+use sb_core::tls_policy::OutboundTlsPolicy;
 use sb_core::util::sync::AtomicFlag;
-use sb_core::{MetricSource, SharedMetricSource};
+use sb_core::{MetricSource, SharedMetricSource, WorkerRequestMetrics};
+use std::net::IpAddr;
 use std::path::PathBuf;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::sync::Mutex;
+use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::mpsc::unbounded_channel;
-use tokio::sync::{mpsc, oneshot, Mutex, Notify, OwnedSemaphorePermit};
+use tokio::sync::{mpsc, oneshot, Notify, OwnedSemaphorePermit};
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 use sb_graph::{DecoratorType, EszipPayloadKind};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", content = "detail")]
 pub enum WorkerExitStatus {
+    #[serde(rename = "normal")]
     Normal,
+    #[serde(rename = "uncaughtException")]
     WithUncaughtException(UncaughtExceptionEvent),
+    #[serde(rename = "panic")]
+    WithPanic(String),
+}
+
+impl WorkerExitStatus {
+    pub fn is_normal(&self) -> bool {
+        matches!(self, Self::Normal)
+    }
 }
 
 impl Default for WorkerExitStatus {
@@ -29,44 +49,324 @@ impl Default for WorkerExitStatus {
 }
 
 #[derive(Debug, Clone, Default)]
-pub struct WorkerExit(Arc<Mutex<WorkerExitStatus>>);
+pub struct WorkerExit(Arc<Mutex<WorkerExitStatus>>, CancellationToken);
 
 impl WorkerExit {
-    pub async fn error(&self) -> Option<anyhow::Error> {
-        match &*self.0.lock().await {
+    pub fn error(&self) -> Option<anyhow::Error> {
+        match &*self.0.lock().unwrap() {
             WorkerExitStatus::Normal => None,
             WorkerExitStatus::WithUncaughtException(UncaughtExceptionEvent {
                 exception, ..
             }) => Some(anyhow!("{exception}")),
+            WorkerExitStatus::WithPanic(msg) => Some(anyhow!("worker panicked: {msg}")),
         }
     }
 
-    pub async fn set(&self, exit_status: WorkerExitStatus) {
-        *self.0.lock().await = exit_status;
+    pub fn status(&self) -> WorkerExitStatus {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, exit_status: WorkerExitStatus) {
+        *self.0.lock().unwrap() = exit_status;
+    }
+
+    /// Returns `true` once the task running the worker has been joined.
+    pub fn is_finished(&self) -> bool {
+        self.1.is_cancelled()
+    }
+
+    /// Waits until the task running the worker has been joined.
+    pub async fn finished(&self) {
+        self.1.cancelled().await
+    }
+
+    pub fn mark_finished(&self) {
+        self.1.cancel();
+    }
+}
+
+/// Decides which limit a user worker is billed and enforced by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BillingMode {
+    /// The wall clock limit applies regardless of what the worker is doing.
+    #[default]
+    WallClock,
+    /// Only CPU time is enforced. The wall clock limit is merely used to
+    /// reclaim idle workers, so workers awaiting slow upstreams are kept
+    /// alive while they have requests in flight.
+    CpuTime,
+}
+
+/// Capabilities granted to the WASI modules a user worker instantiates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct WasiPermissions {
+    /// Whether directories may be preopened. Files are always read-only.
+    pub fs: bool,
+    pub clock: bool,
+    pub random: bool,
+}
+
+impl Default for WasiPermissions {
+    fn default() -> Self {
+        Self {
+            fs: false,
+            clock: true,
+            random: true,
+        }
+    }
+}
+
+/// CORS policy enforced by the runtime on behalf of a user worker. Preflight
+/// requests are answered without invoking the worker.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CorsPolicy {
+    /// Origins allowed to make requests, or `*` for any.
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    /// Headers allowed in requests. Empty allows the headers the browser
+    /// asks for.
+    pub allowed_headers: Vec<String>,
+    pub max_age_secs: Option<u64>,
+    pub allow_credentials: bool,
+}
+
+impl CorsPolicy {
+    /// Rejects a wildcard origin that allows credentials. Browsers refuse it,
+    /// and echoing any origin back instead would let every site make
+    /// requests with the credentials of the users.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.allow_credentials && self.allowed_origins.iter().any(|it| it == "*") {
+            return Err(anyhow!(
+                "cors policy allowing credentials must list its origins instead of `*`"
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Limits on the request bodies sent to a user worker. Zero disables a limit.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RequestBodyPolicy {
+    pub max_bytes: u64,
+    /// Maximum size of a single part of a `multipart/form-data` body,
+    /// including the headers of the part.
+    pub max_part_bytes: u64,
+    /// If set, bodies are read in full before the request is dispatched, so
+    /// that slow uploads don't hold the worker. Past this many bytes, the rest
+    /// of the body is kept in a temporary file.
+    pub spill_threshold_bytes: u64,
+    /// Maximum disk space taken by the temporary files of the worker at any
+    /// given time.
+    pub disk_quota_bytes: u64,
+}
+
+/// Allow and deny lists of CIDR blocks checked against the address of the
+/// client.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct IpAccessList {
+    /// If not empty, only the addresses in these blocks are allowed.
+    pub allow: Vec<IpNet>,
+    /// Addresses in these blocks are denied, even if they are also allowed.
+    pub deny: Vec<IpNet>,
+}
+
+impl IpAccessList {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+
+        if self.deny.iter().any(|it| it.contains(&ip)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|it| it.contains(&ip))
     }
 }
 
+/// Scheduling class applied to the worker thread while it runs a user worker.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WorkerPriority {
+    /// The default scheduling class of the host.
+    #[default]
+    Interactive,
+    /// CPU bound work that should not preempt interactive workers.
+    Batch,
+    /// Work that only runs when the core would otherwise be idle.
+    Background,
+}
+
+/// Where and how much the supervisor captures about a worker it terminates
+/// for hitting a limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiagnosticsOptions {
+    pub dir: PathBuf,
+    /// How many of the last log lines of the worker are kept for the bundle.
+    pub log_lines: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct UserWorkerRuntimeOpts {
     pub service_path: Option<String>,
     pub key: Option<Uuid>,
 
-    pub pool_msg_tx: Option<mpsc::UnboundedSender<UserWorkerMsgs>>,
-    pub events_msg_tx: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+    pub pool_msg_tx: Option<UserWorkerMsgsSender>,
+    pub events_msg_tx: Option<BoundedSender<WorkerEventWithMetadata>>,
     pub cancel: Option<CancellationToken>,
+    pub request_metrics: Option<WorkerRequestMetrics>,
+
+    /// Arbitrary key/value pairs attached by the creator of the worker. They
+    /// are included in every event emitted for the worker.
+    pub labels: HashMap<String, String>,
+    /// Credentials the worker reads with `EdgeRuntime.getSecret`. They are
+    /// kept out of `Deno.env` and masked in the events of the worker.
+    pub secrets: WorkerSecrets,
 
     pub memory_limit_mb: u64,
     pub low_memory_multiplier: u64,
+    /// Upper bound of the heap limit the low memory allowance may grow to.
+    /// Zero means no absolute bound.
+    pub low_memory_max_allowance_bytes: u64,
+    /// How many times the heap limit may be extended. Zero means unlimited.
+    pub low_memory_max_extensions: u64,
+
+    /// Memory thresholds as a percentage of `memory_limit_mb`. A warning event
+    /// is emitted at the first, a GC pass is triggered and new requests are
+    /// no longer routed to the worker at the second, and the worker is
+    /// terminated at the third. Zero disables the warning and throttle tiers.
+    pub memory_warning_threshold_pct: u64,
+    pub memory_throttle_threshold_pct: u64,
+    pub memory_termination_threshold_pct: u64,
 
     pub worker_timeout_ms: u64, // wall clock limit
+    pub billing_mode: BillingMode,
 
     pub cpu_time_soft_limit_ms: u64,
     pub cpu_time_hard_limit_ms: u64,
+    /// CPU time a single request may consume under the per-worker policy.
+    /// Zero disables the per-request ceiling.
+    pub cpu_time_per_request_limit_ms: u64,
+    /// Under the per-worker policy, new requests are no longer routed to the
+    /// worker while its CPU time is past this percentage of the soft limit,
+    /// or the oldest request in flight is past this percentage of the
+    /// per-request limit. Zero disables it.
+    pub cpu_time_busy_threshold_pct: u64,
+    /// Emits a `ResourceLeak` event once the open resources of the worker
+    /// have grown after this many requests in a row. Zero disables it.
+    pub resource_leak_threshold: u64,
+
+    /// How long the `beforeunload` hook may run before the supervisor
+    /// terminates the isolate. Zero disables the hook.
+    pub termination_grace_period_ms: u64,
+
+    pub priority: WorkerPriority,
+
+    /// Runs the worker in deterministic mode when set. The value seeds both
+    /// `Math.random` and the WebCrypto RNG, and the wall and monotonic clocks
+    /// start from a fixed origin and advance in coarse steps.
+    pub deterministic_seed: Option<u64>,
+    /// Rounds the values of `Date.now`, `new Date()` and `performance.now`
+    /// down to multiples of this many milliseconds, to make timing side
+    /// channels harder to exploit. Zero keeps the default resolution.
+    pub timer_resolution_ms: u64,
+    pub wasi_permissions: WasiPermissions,
+    /// Takes WebAssembly threads, `FinalizationRegistry` and `WeakRef` away
+    /// from the worker, trading features for isolation. No worker gets
+    /// `SharedArrayBuffer` to begin with.
+    pub strict_isolation: bool,
+    /// How many pooled database connections the worker may hold at once.
+    /// Zero falls back to the per-worker limit of the pool.
+    pub db_connection_quota: u64,
+    /// Size limit of the temporary directory the worker gets for itself.
+    /// Zero leaves the worker without one, so its file system stays
+    /// read-only.
+    pub tmp_dir_quota_mb: u64,
 
     pub force_create: bool,
     pub net_access_disabled: bool,
+    /// Capabilities that are off by default for user workers. See
+    /// `sb_core::permissions::PermissionsOptions`.
+    pub allow_ffi: bool,
+    pub allow_subprocess: bool,
+    pub allow_raw_net: bool,
+    /// Whether the worker may transform images through `Supabase.image`,
+    /// which runs on the host instead of the worker's CPU budget.
+    pub allow_image_ops: bool,
+    /// How many `Supabase.image.transform()` calls of the worker may run at
+    /// once.
+    pub image_transform_concurrency: u32,
+    /// How many `Supabase.crypto` calls the worker may start per second, in
+    /// bursts of as many. Zero takes the calls away from the worker.
+    pub crypto_ops_per_second: u32,
+    /// Names of the host keys the worker may sign with.
+    pub crypto_keys: Vec<String>,
+    /// How many `Supabase.redis` commands the worker may send per second.
+    /// Zero falls back to the per-worker limit of the pool.
+    pub redis_commands_per_second: u32,
+    /// Prefix of the Redis keys and channels of the worker. The worker can't
+    /// send `Supabase.redis` commands without one.
+    pub redis_namespace: Option<String>,
+    /// Topics the worker may publish to through `Supabase.publish`. A topic
+    /// ending with `*` grants every topic with that prefix.
+    pub publish_topics: Vec<String>,
+    /// How many published messages the worker may have waiting for delivery
+    /// at once. Zero falls back to the per-worker limit of the publisher.
+    pub publish_queue_quota: u64,
+    /// File the state declared through `EdgeRuntime.state` is checkpointed
+    /// to and restored from at boot. The state is kept in memory only when
+    /// this is not set.
+    pub state_checkpoint_path: Option<PathBuf>,
+    /// How often the state is checkpointed, if it was marked dirty.
+    pub state_checkpoint_interval_ms: u64,
+    /// Sends the outbound requests of the worker through this proxy instead
+    /// of the one configured for the runtime (`HTTP_PROXY`, `HTTPS_PROXY` and
+    /// `NO_PROXY`). Credentials can be given in the url.
+    pub http_proxy: Option<String>,
+    /// Minimum TLS version, extra trusted CAs and certificate pins for the
+    /// outbound requests of the worker.
+    pub tls_policy: Option<OutboundTlsPolicy>,
+    /// Whether requests to the worker must carry a valid JWT when the runtime
+    /// is configured to verify them.
+    pub verify_jwt: bool,
+    /// Expected `aud` claim of the JWTs, overriding the runtime default.
+    pub jwt_audience: Option<String>,
+    pub cors: Option<CorsPolicy>,
+    /// Checked before requests are dispatched to the worker, in addition to
+    /// the list of the listener.
+    pub ip_access_list: Option<IpAccessList>,
+    /// Serves the function (or the object with a `fetch` method) the main
+    /// module exports under this name, for modules that don't call
+    /// `Deno.serve` themselves. `default` selects the default export.
+    pub handler_export: Option<String>,
+    pub request_body: Option<RequestBodyPolicy>,
+    /// Largest response body the worker may send back. Zero means no limit.
+    pub response_body_max_bytes: u64,
+    /// How many upgraded connections and event streams the worker may serve
+    /// at once. Connections held by the runtime through
+    /// `EdgeRuntime.webSockets` don't count. Zero means no limit.
+    pub max_connections: u64,
+    /// Hands requests to the worker through ops instead of writing them out
+    /// as HTTP for the worker to parse again. Upgrades and requests expecting
+    /// `100 Continue` still go over HTTP.
+    pub direct_request_bridge: bool,
     pub custom_module_root: Option<String>,
     pub allow_remote_modules: bool,
+    /// The options the worker was created with, for the pool to boot its
+    /// service again after a restart. Left out for workers that can't be
+    /// written to disk as they are, such as those with secrets.
+    pub persisted_options: Option<Arc<serde_json::Value>>,
+    /// Writes a diagnostic bundle when the supervisor terminates the worker
+    /// for hitting its wall clock, CPU time or memory limit.
+    pub diagnostics: Option<DiagnosticsOptions>,
 }
 
 impl Default for UserWorkerRuntimeOpts {
@@ -74,40 +374,126 @@ impl Default for UserWorkerRuntimeOpts {
         UserWorkerRuntimeOpts {
             memory_limit_mb: 512,
             worker_timeout_ms: 5 * 60 * 1000,
+            billing_mode: BillingMode::default(),
+            priority: WorkerPriority::default(),
             low_memory_multiplier: 5,
+            low_memory_max_allowance_bytes: 0,
+            low_memory_max_extensions: 0,
+            memory_warning_threshold_pct: 70,
+            memory_throttle_threshold_pct: 85,
+            memory_termination_threshold_pct: 100,
             cpu_time_soft_limit_ms: 50,
             cpu_time_hard_limit_ms: 100,
+            cpu_time_per_request_limit_ms: 0,
+            cpu_time_busy_threshold_pct: 0,
+            resource_leak_threshold: 0,
+            termination_grace_period_ms: 0,
+            deterministic_seed: None,
+            timer_resolution_ms: 0,
+            wasi_permissions: WasiPermissions::default(),
+            strict_isolation: false,
+            db_connection_quota: 0,
+            tmp_dir_quota_mb: 64,
 
             force_create: false,
             key: None,
             pool_msg_tx: None,
             events_msg_tx: None,
             cancel: None,
+            request_metrics: None,
+            labels: HashMap::new(),
+            secrets: WorkerSecrets::default(),
             net_access_disabled: false,
+            allow_ffi: false,
+            allow_subprocess: false,
+            allow_raw_net: false,
+            allow_image_ops: false,
+            image_transform_concurrency: 2,
+            crypto_ops_per_second: 10,
+            crypto_keys: vec![],
+            redis_commands_per_second: 0,
+            redis_namespace: None,
+            publish_topics: vec![],
+            publish_queue_quota: 0,
+            state_checkpoint_path: None,
+            state_checkpoint_interval_ms: 5000,
+            http_proxy: None,
+            tls_policy: None,
+            verify_jwt: true,
+            jwt_audience: None,
+            cors: None,
+            ip_access_list: None,
+            handler_export: None,
+            request_body: None,
+            response_body_max_bytes: 0,
+            max_connections: 0,
+            direct_request_bridge: false,
             allow_remote_modules: true,
             custom_module_root: None,
+            persisted_options: None,
+            diagnostics: None,
             service_path: None,
         }
     }
 }
 
+impl UserWorkerRuntimeOpts {
+    /// Converts a percentage of `memory_limit_mb` into bytes. Returns `None`
+    /// if the percentage is zero.
+    pub fn memory_threshold_bytes(&self, pct: u64) -> Option<usize> {
+        if pct == 0 {
+            return None;
+        }
+
+        let limit_bytes = self.memory_limit_mb.saturating_mul(1024 * 1024);
+
+        Some((limit_bytes.saturating_mul(pct) / 100) as usize)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct UserWorkerProfile {
-    pub worker_request_msg_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+    pub worker_request_msg_tx: BoundedSender<WorkerRequestMsg>,
     pub timing_tx_pair: (
-        mpsc::UnboundedSender<Arc<Notify>>,
+        mpsc::UnboundedSender<RequestStart>,
         mpsc::UnboundedSender<()>,
     ),
     pub service_path: String,
     pub permit: Option<Arc<OwnedSemaphorePermit>>,
+    pub live_permit: Option<Arc<OwnedSemaphorePermit>>,
     pub cancel: CancellationToken,
     pub status: TimingStatus,
     pub exit: WorkerExit,
+    pub metrics: WorkerRequestMetrics,
+    pub boot_duration: Duration,
+    pub termination: CancellationToken,
+    pub labels: HashMap<String, String>,
+    pub verify_jwt: bool,
+    pub jwt_audience: Option<String>,
+    pub cors: Option<Arc<CorsPolicy>>,
+    pub ip_access_list: Option<Arc<IpAccessList>>,
+    pub request_body: Option<Arc<RequestBodyPolicy>>,
+    /// Bytes of request bodies the worker currently has spilled to disk.
+    pub spilled_body_bytes: Arc<AtomicU64>,
+    pub response_body_max_bytes: u64,
+    pub priority: WorkerPriority,
+    pub persisted_options: Option<Arc<serde_json::Value>>,
+    /// Raised by the supervisor while the worker is close to its limits.
+    pub is_busy: bool,
+}
+
+impl UserWorkerProfile {
+    /// Returns `true` if the worker carries every label in `selector`.
+    pub fn matches_labels(&self, selector: &HashMap<String, String>) -> bool {
+        selector
+            .iter()
+            .all(|(k, v)| self.labels.get(k).map_or(false, |it| it == v))
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct MainWorkerRuntimeOpts {
-    pub worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    pub worker_pool_tx: UserWorkerMsgsSender,
     pub shared_metric_src: Option<SharedMetricSource>,
     pub event_worker_metric_src: Option<MetricSource>,
 }
@@ -159,20 +545,33 @@ impl From<&WorkerRuntimeOpts> for WorkerKind {
 pub struct TimingStatus {
     pub demand: Arc<AtomicUsize>,
     pub is_retired: Arc<AtomicFlag>,
+    pub is_throttled: Arc<AtomicBool>,
+}
+
+/// Tells the supervisor that a request has been dispatched to the worker.
+#[derive(Debug, Default)]
+pub struct RequestStart {
+    /// Notified once the supervisor lets the request through.
+    pub fence: Arc<Notify>,
+    /// Receives, or closes, once this request is over. Requests sharing the
+    /// end channel of the worker leave it unset.
+    pub end_rx: Option<mpsc::UnboundedReceiver<()>>,
+    /// Reported if the request goes over the per-request CPU time limit.
+    pub request_id: Option<RequestId>,
 }
 
 #[derive(Debug)]
 pub struct Timing {
     pub status: TimingStatus,
     pub req: (
-        mpsc::UnboundedReceiver<Arc<Notify>>,
+        mpsc::UnboundedReceiver<RequestStart>,
         mpsc::UnboundedReceiver<()>,
     ),
 }
 
 impl Default for Timing {
     fn default() -> Self {
-        let (_, dumb_start_rx) = unbounded_channel::<Arc<Notify>>();
+        let (_, dumb_start_rx) = unbounded_channel::<RequestStart>();
         let (_, dumb_end_rx) = unbounded_channel::<()>();
 
         Self {
@@ -188,10 +587,12 @@ pub struct WorkerContextInitOpts {
     pub no_module_cache: bool,
     pub import_map_path: Option<String>,
     pub env_vars: HashMap<String, String>,
-    pub events_rx: Option<mpsc::UnboundedReceiver<WorkerEventWithMetadata>>,
+    pub events_rx: Option<mpsc::Receiver<WorkerEventWithMetadata>>,
     pub timing: Option<Timing>,
     pub conf: WorkerRuntimeOpts,
     pub maybe_eszip: Option<EszipPayloadKind>,
+    /// Base64 detached signature of `maybe_eszip`.
+    pub maybe_eszip_signature: Option<String>,
     pub maybe_module_code: Option<FastString>,
     pub maybe_entrypoint: Option<String>,
     pub maybe_decorator: Option<DecoratorType>,
@@ -206,6 +607,12 @@ pub enum UserWorkerMsgs {
         oneshot::Sender<Result<CreateUserWorkerResult, Error>>,
     ),
     Created(Uuid, UserWorkerProfile),
+    CreateFailed(String),
+    Reload(String),
+    Reloaded(String, Option<Uuid>),
+    /// Sent once a service read from the pool state has been booted again,
+    /// with the key of its worker, or `None` if it failed to boot.
+    Restored(Uuid, Option<Uuid>),
     SendRequest(
         Uuid,
         Request<Body>,
@@ -213,7 +620,153 @@ pub enum UserWorkerMsgs {
         Option<CancellationToken>,
     ),
     Idle(Uuid),
+    /// Sent by the supervisor when the worker starts or stops being close to
+    /// its limits.
+    Busy(Uuid, bool),
     Shutdown(Uuid),
+    List(oneshot::Sender<Vec<UserWorkerInfo>>),
+    Terminate(Uuid, oneshot::Sender<bool>),
+    LookupByLabels(
+        HashMap<String, String>,
+        oneshot::Sender<Vec<UserWorkerInfo>>,
+    ),
+    TerminateByLabels(HashMap<String, String>, oneshot::Sender<usize>),
+    Drain(oneshot::Sender<()>),
+    DrainRuntime(oneshot::Sender<()>),
+    Config(oneshot::Sender<UserWorkerPoolConfig>),
+    /// Sent once a worker exits, with the resources it used and the tenant
+    /// to bill them to.
+    Accrue(String, WorkerUsage),
+    Usage(oneshot::Sender<Vec<TenantUsage>>),
+}
+
+/// Sends messages to the user worker pool.
+///
+/// Requests go through a channel of their own, which the pool only reads
+/// once there are no other messages pending, so that a flood of requests
+/// does not hold back creating and shutting down workers.
+#[derive(Debug, Clone)]
+pub struct UserWorkerMsgsSender {
+    control_tx: BoundedSender<UserWorkerMsgs>,
+    request_tx: BoundedSender<UserWorkerMsgs>,
+}
+
+impl UserWorkerMsgsSender {
+    fn route(&self, msg: &UserWorkerMsgs) -> &BoundedSender<UserWorkerMsgs> {
+        match msg {
+            UserWorkerMsgs::SendRequest(..) => &self.request_tx,
+            _ => &self.control_tx,
+        }
+    }
+
+    pub fn send(&self, msg: UserWorkerMsgs) -> Result<(), SendError<UserWorkerMsgs>> {
+        self.route(&msg).send(msg)
+    }
+
+    pub fn send_reliable(&self, msg: UserWorkerMsgs) -> Result<(), SendError<UserWorkerMsgs>> {
+        self.route(&msg).send_reliable(msg)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.control_tx.is_closed()
+    }
+}
+
+/// How many messages other than requests the pool takes in a row while a
+/// request is waiting.
+const MAX_CONTROL_MSGS_PER_REQUEST: usize = 32;
+
+pub struct UserWorkerMsgsReceiver {
+    control_rx: mpsc::Receiver<UserWorkerMsgs>,
+    request_rx: mpsc::Receiver<UserWorkerMsgs>,
+    /// Messages other than requests received since the last request.
+    control_streak: usize,
+}
+
+impl UserWorkerMsgsReceiver {
+    /// Receives the next message, preferring anything other than a request,
+    /// but never more than [`MAX_CONTROL_MSGS_PER_REQUEST`] of them in a row
+    /// while a request is waiting. This is cancel safe.
+    pub async fn recv(&mut self) -> Option<UserWorkerMsgs> {
+        if self.control_streak >= MAX_CONTROL_MSGS_PER_REQUEST {
+            if let Ok(msg) = self.request_rx.try_recv() {
+                self.control_streak = 0;
+                return Some(msg);
+            }
+        }
+
+        tokio::select! {
+            biased;
+
+            Some(msg) = self.control_rx.recv() => {
+                self.control_streak += 1;
+                Some(msg)
+            }
+
+            Some(msg) = self.request_rx.recv() => {
+                self.control_streak = 0;
+                Some(msg)
+            }
+
+            else => None,
+        }
+    }
+}
+
+pub fn user_worker_msgs_channel() -> (UserWorkerMsgsSender, UserWorkerMsgsReceiver) {
+    let (control_tx, control_rx) = channel::channel(ChannelKind::PoolMessage);
+    let (request_tx, request_rx) = channel::channel(ChannelKind::PoolRequest);
+
+    (
+        UserWorkerMsgsSender {
+            control_tx,
+            request_tx,
+        },
+        UserWorkerMsgsReceiver {
+            control_rx,
+            request_rx,
+            control_streak: 0,
+        },
+    )
+}
+
+/// A snapshot of a user worker living in the pool, or of one that has
+/// recently left it without exiting normally.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserWorkerInfo {
+    pub key: String,
+    pub service_path: String,
+    pub is_retired: bool,
+    pub is_throttled: bool,
+    pub is_busy: bool,
+    pub in_flight_requests: usize,
+    pub boot_time_ms: u64,
+    pub labels: HashMap<String, String>,
+    pub stats: WorkerRequestStats,
+    /// How the worker exited, once it has left the pool.
+    pub exit_status: Option<WorkerExitStatus>,
+}
+
+/// Resources a tenant's workers have used since the runtime started.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TenantUsage {
+    pub tenant: String,
+    pub usage: WorkerUsage,
+}
+
+/// The policy the pool was configured with.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserWorkerPoolConfig {
+    pub supervisor_policy: String,
+    pub max_parallelism: usize,
+    pub max_live_workers: Option<usize>,
+    pub max_concurrent_boots: Option<usize>,
+    pub max_concurrent_requests: Option<usize>,
+    pub scheduling_policy: String,
+    pub request_wait_timeout_ms: u64,
 }
 
 pub type SendRequestResult = (Response<Body>, mpsc::UnboundedSender<()>);
@@ -223,9 +776,141 @@ pub struct CreateUserWorkerResult {
     pub key: Uuid,
 }
 
+/// Identifies a request in the errors and events about it. It is the
+/// `x-request-id` header of the request if it came with one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Returns the id of the request, and keeps it in the extensions of the
+    /// request for whoever handles it next to find the same one.
+    pub fn of(req: &mut Request<Body>) -> Self {
+        if let Some(it) = req.extensions().get::<Self>() {
+            return it.clone();
+        }
+
+        let id = Self(
+            req.headers()
+                .get("x-request-id")
+                .and_then(|it| it.to_str().ok())
+                .map(str::to_string)
+                .unwrap_or_else(|| Uuid::new_v4().to_string()),
+        );
+
+        let _ = req.extensions_mut().insert(id.clone());
+        id
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 #[derive(Debug)]
 pub struct WorkerRequestMsg {
     pub req: Request<Body>,
     pub res_tx: oneshot::Sender<Result<Response<Body>, hyper::Error>>,
     pub conn_token: Option<CancellationToken>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn receives_other_messages_before_requests() {
+        let (tx, mut rx) = user_worker_msgs_channel();
+        let key = Uuid::new_v4();
+
+        for _ in 0..3 {
+            let (res_tx, _) = oneshot::channel();
+
+            tx.send(UserWorkerMsgs::SendRequest(
+                key,
+                Request::default(),
+                res_tx,
+                None,
+            ))
+            .unwrap();
+        }
+
+        tx.send(UserWorkerMsgs::Shutdown(key)).unwrap();
+
+        assert!(matches!(rx.recv().await, Some(UserWorkerMsgs::Shutdown(_))));
+
+        for _ in 0..3 {
+            assert!(matches!(
+                rx.recv().await,
+                Some(UserWorkerMsgs::SendRequest(..))
+            ));
+        }
+
+        drop(tx);
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn requests_are_not_starved_by_other_messages() {
+        let (tx, mut rx) = user_worker_msgs_channel();
+        let key = Uuid::new_v4();
+        let (res_tx, _) = oneshot::channel();
+
+        tx.send(UserWorkerMsgs::SendRequest(
+            key,
+            Request::default(),
+            res_tx,
+            None,
+        ))
+        .unwrap();
+
+        for _ in 0..MAX_CONTROL_MSGS_PER_REQUEST * 2 {
+            tx.send(UserWorkerMsgs::Idle(key)).unwrap();
+        }
+
+        for _ in 0..MAX_CONTROL_MSGS_PER_REQUEST {
+            assert!(matches!(rx.recv().await, Some(UserWorkerMsgs::Idle(_))));
+        }
+
+        assert!(matches!(
+            rx.recv().await,
+            Some(UserWorkerMsgs::SendRequest(..))
+        ));
+
+        for _ in 0..MAX_CONTROL_MSGS_PER_REQUEST {
+            assert!(matches!(rx.recv().await, Some(UserWorkerMsgs::Idle(_))));
+        }
+    }
+
+    #[test]
+    fn request_id_is_kept_with_the_request() {
+        let mut req = Request::builder()
+            .header("x-request-id", "abc")
+            .body(Body::empty())
+            .unwrap();
+
+        assert_eq!(RequestId::of(&mut req).0, "abc");
+
+        let mut req = Request::new(Body::empty());
+        let id = RequestId::of(&mut req);
+
+        assert_eq!(RequestId::of(&mut req), id);
+    }
+
+    #[test]
+    fn worker_exit_reports_a_panic() {
+        let exit = WorkerExit::default();
+
+        assert!(exit.status().is_normal());
+        assert!(exit.error().is_none());
+
+        exit.set(WorkerExitStatus::WithPanic("oops".into()));
+
+        assert!(matches!(exit.status(), WorkerExitStatus::WithPanic(msg) if msg == "oops"));
+        assert_eq!(
+            serde_json::to_value(exit.status()).unwrap(),
+            serde_json::json!({ "kind": "panic", "detail": "oops" })
+        );
+    }
+}
@@ -1,7 +1,71 @@
+use std::error::Error as _;
+use std::io;
+
+use event_worker::events::ConnectionErrorKind;
 use thiserror::Error;
 
+use crate::context::RequestId;
+
 #[derive(Error, Debug)]
 pub enum WorkerError {
     #[error("request has been cancelled by supervisor")]
     RequestCancelledBySupervisor,
+    #[error("worker boot queue is full")]
+    BootQueueFull,
+    #[error("worker did not boot in time as the boot queue is congested")]
+    BootQueueTimedOut,
+    #[error("request queue is full")]
+    RequestQueueFull,
+    #[error("request was not dispatched in time as the request queue is congested")]
+    RequestQueueTimedOut,
+    #[error("runtime is draining and does not boot new workers")]
+    RuntimeDraining,
+    #[error("every worker of the service is throttled and no more can be booted")]
+    WorkersThrottled,
+    #[error("worker pool is saturated with live workers")]
+    LiveWorkersSaturated,
+    #[error("connection to the worker was {kind} while serving request {request_id}: {msg}")]
+    Connection {
+        request_id: String,
+        kind: ConnectionErrorKind,
+        msg: String,
+    },
+}
+
+impl WorkerError {
+    pub fn connection(request_id: &RequestId, err: &hyper::Error) -> Self {
+        Self::Connection {
+            request_id: request_id.to_string(),
+            kind: connection_error_kind(err),
+            msg: err.to_string(),
+        }
+    }
+}
+
+/// Tells whether the connection a request was written to a worker over was
+/// broken by the worker going away, or by the runtime misusing it.
+pub fn connection_error_kind(err: &hyper::Error) -> ConnectionErrorKind {
+    if err.is_incomplete_message() || err.is_closed() || err.is_canceled() {
+        return ConnectionErrorKind::WorkerReset;
+    }
+
+    let mut maybe_source = err.source();
+
+    while let Some(source) = maybe_source {
+        if let Some(err) = source.downcast_ref::<io::Error>() {
+            if matches!(
+                err.kind(),
+                io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::UnexpectedEof
+            ) {
+                return ConnectionErrorKind::WorkerReset;
+            }
+        }
+
+        maybe_source = source.source();
+    }
+
+    ConnectionErrorKind::Runtime
 }
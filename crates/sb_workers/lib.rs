@@ -2,29 +2,34 @@ pub mod context;
 pub mod errors;
 
 use crate::context::{
-    CreateUserWorkerResult, UserWorkerMsgs, UserWorkerRuntimeOpts, WorkerContextInitOpts,
+    BillingMode, CorsPolicy, CreateUserWorkerResult, IpAccessList, RequestBodyPolicy,
+    UserWorkerInfo, UserWorkerMsgs, UserWorkerMsgsSender, UserWorkerPoolConfig,
+    UserWorkerRuntimeOpts, WasiPermissions, WorkerContextInitOpts, WorkerPriority,
     WorkerRuntimeOpts,
 };
-use anyhow::Error;
+use anyhow::{bail, Error};
 use context::SendRequestResult;
 use deno_config::JsxImportSourceConfig;
 use deno_core::error::{custom_error, type_error, AnyError};
 use deno_core::futures::stream::Peekable;
 use deno_core::futures::{FutureExt, Stream, StreamExt};
-use deno_core::{op2, ModuleSpecifier};
+use deno_core::{op2, serde_json, ModuleSpecifier};
 use deno_core::{
     AsyncRefCell, AsyncResult, BufView, ByteString, CancelFuture, CancelHandle, CancelTryFuture,
     JsBuffer, OpState, RcRef, Resource, ResourceId, WriteOutcome,
 };
 use deno_http::{HttpRequestReader, HttpStreamResource};
 use errors::WorkerError;
+use event_worker::events::{ConnectionErrorKind, WorkerSecrets};
+use http_utils::headers;
 use http_utils::utils::get_upgrade_type;
 use hyper::body::HttpBody;
-use hyper::header::{HeaderName, HeaderValue, CONTENT_LENGTH};
+use hyper::header::{HeaderValue, CONTENT_LENGTH};
 use hyper::upgrade::OnUpgrade;
-use hyper::{Body, Method, Request};
+use hyper::{Body, HeaderMap, Method, Request};
 use log::error;
 use sb_core::conn_sync::ConnWatcher;
+use sb_core::tls_policy::OutboundTlsPolicy;
 use sb_graph::{DecoratorType, EszipPayloadKind};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
@@ -33,6 +38,7 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use tokio::sync::{mpsc, oneshot};
 use tokio_util::sync::CancellationToken;
@@ -44,6 +50,14 @@ deno_core::extension!(
         op_user_worker_create,
         op_user_worker_fetch_build,
         op_user_worker_fetch_send,
+        op_user_worker_list,
+        op_user_worker_terminate,
+        op_user_worker_lookup_by_labels,
+        op_user_worker_terminate_by_labels,
+        op_user_worker_pool_drain,
+        op_user_worker_pool_config,
+        op_user_worker_pool_usage,
+        op_runtime_drain,
     ],
     esm_entry_point = "ext:sb_user_workers/user_workers.js",
     esm = ["user_workers.js",]
@@ -64,56 +78,254 @@ pub struct UserWorkerCreateOptions {
     no_module_cache: bool,
     import_map_path: Option<String>,
     env_vars: Vec<(String, String)>,
+    secrets: Vec<(String, String)>,
     force_create: bool,
     allow_remote_modules: bool,
     net_access_disabled: bool,
+    allow_ffi: bool,
+    allow_subprocess: bool,
+    allow_raw_net: bool,
+    allow_image_ops: bool,
+    image_transform_concurrency: u32,
+    crypto_ops_per_second: u32,
+    crypto_keys: Vec<String>,
+    redis_commands_per_second: u32,
+    redis_namespace: Option<String>,
+    publish_topics: Vec<String>,
+    publish_queue_quota: u64,
+    state_checkpoint_path: Option<PathBuf>,
+    state_checkpoint_interval_ms: u64,
+    http_proxy: Option<String>,
+    tls_policy: Option<OutboundTlsPolicy>,
+    verify_jwt: bool,
+    jwt_audience: Option<String>,
+    cors: Option<CorsPolicy>,
+    ip_access_list: Option<IpAccessList>,
+    handler_export: Option<String>,
+    request_body: Option<RequestBodyPolicy>,
+    response_body_max_bytes: u64,
+    max_connections: u64,
     custom_module_root: Option<String>,
     maybe_eszip: Option<JsBuffer>,
+    maybe_eszip_signature: Option<String>,
     maybe_entrypoint: Option<String>,
     maybe_module_code: Option<String>,
 
     memory_limit_mb: u64,
     low_memory_multiplier: u64,
+    low_memory_max_allowance_bytes: u64,
+    low_memory_max_extensions: u64,
+    memory_warning_threshold_pct: u64,
+    memory_throttle_threshold_pct: u64,
+    memory_termination_threshold_pct: u64,
     worker_timeout_ms: u64,
+    billing_mode: BillingMode,
     cpu_time_soft_limit_ms: u64,
     cpu_time_hard_limit_ms: u64,
+    cpu_time_per_request_limit_ms: u64,
+    cpu_time_busy_threshold_pct: u64,
+    resource_leak_threshold: u64,
+    termination_grace_period_ms: u64,
+    priority: WorkerPriority,
+    labels: HashMap<String, String>,
+    deterministic_seed: Option<u64>,
+    timer_resolution_ms: u64,
+    wasi_permissions: WasiPermissions,
+    strict_isolation: bool,
+    db_connection_quota: u64,
+    tmp_dir_quota_mb: u64,
 
     jsx_import_source_config: Option<JsxImportBaseConfig>,
     decorator_type: Option<DecoratorType>,
 }
 
-#[op2(async)]
-#[string]
-pub async fn op_user_worker_create(
-    state: Rc<RefCell<OpState>>,
-    #[serde] opts: UserWorkerCreateOptions,
-) -> Result<String, AnyError> {
-    let result_rx = {
-        let op_state = state.borrow();
-        let tx = op_state.borrow::<mpsc::UnboundedSender<UserWorkerMsgs>>();
-        let (result_tx, result_rx) = oneshot::channel::<Result<CreateUserWorkerResult, Error>>();
+impl UserWorkerCreateOptions {
+    /// Reads the options from a JSON object, filling in those left out as
+    /// `UserWorker.create` in `user_workers.js` does.
+    pub fn from_partial(options: serde_json::Value) -> Result<Self, AnyError> {
+        let mut value = serde_json::json!({
+            "memoryLimitMb": 512,
+            "lowMemoryMultiplier": 5,
+            "lowMemoryMaxAllowanceBytes": 0,
+            "lowMemoryMaxExtensions": 3,
+            "memoryWarningThresholdPct": 70,
+            "memoryThrottleThresholdPct": 85,
+            "memoryTerminationThresholdPct": 100,
+            "workerTimeoutMs": 5 * 60 * 1000,
+            "billingMode": "wallClock",
+            "cpuTimeSoftLimitMs": 50,
+            "cpuTimeHardLimitMs": 100,
+            "cpuTimePerRequestLimitMs": 0,
+            "cpuTimeBusyThresholdPct": 0,
+            "resourceLeakThreshold": 0,
+            "terminationGracePeriodMs": 0,
+            "priority": "interactive",
+            "labels": {},
+            "deterministicSeed": null,
+            "timerResolutionMs": 0,
+            "wasiPermissions": { "fs": false, "clock": true, "random": true },
+            "strictIsolation": false,
+            "dbConnectionQuota": 0,
+            "tmpDirQuotaMb": 64,
+            "servicePath": "",
+            "noModuleCache": false,
+            "importMapPath": null,
+            "envVars": [],
+            "secrets": [],
+            "forceCreate": false,
+            "netAccessDisabled": false,
+            "allowFfi": false,
+            "allowSubprocess": false,
+            "allowRawNet": false,
+            "httpProxy": null,
+            "tlsPolicy": null,
+            "verifyJwt": true,
+            "jwtAudience": null,
+            "cors": null,
+            "ipAccessList": null,
+            "handlerExport": null,
+            "requestBody": null,
+            "responseBodyMaxBytes": 0,
+            "allowRemoteModules": true,
+            "customModuleRoot": "",
+            "maybeEszip": null,
+            "maybeEszipSignature": null,
+            "maybeEntrypoint": null,
+            "maybeModuleCode": null,
+            "jsxImportSourceConfig": null,
+            "decoratorType": null,
+        });
+
+        let serde_json::Value::Object(options) = options else {
+            return Err(type_error("worker options must be an object"));
+        };
+
+        value.as_object_mut().unwrap().extend(options);
+
+        let options = serde_json::from_value::<Self>(value)?;
+
+        if options.maybe_eszip.is_none() && options.service_path.is_empty() {
+            return Err(type_error("service path must be defined"));
+        }
+
+        if let Some(cors) = options.cors.as_ref() {
+            cors.validate().map_err(|err| type_error(err.to_string()))?;
+        }
+
+        Ok(options)
+    }
+
+    /// Rejects a warning or throttle threshold that isn't below the tiers
+    /// after it, as the worker would reach the later tier first and never act
+    /// on it. A termination threshold of zero stands for the memory limit.
+    fn validate_memory_thresholds(&self) -> Result<(), Error> {
+        let warning_pct = self.memory_warning_threshold_pct;
+        let throttle_pct = self.memory_throttle_threshold_pct;
+        let termination_pct = match self.memory_termination_threshold_pct {
+            0 => 100,
+            pct => pct,
+        };
+
+        if warning_pct != 0 && warning_pct >= termination_pct {
+            bail!("memoryWarningThresholdPct must be below memoryTerminationThresholdPct");
+        }
+        if throttle_pct != 0 && throttle_pct >= termination_pct {
+            bail!("memoryThrottleThresholdPct must be below memoryTerminationThresholdPct");
+        }
+        if warning_pct != 0 && throttle_pct != 0 && warning_pct >= throttle_pct {
+            bail!("memoryWarningThresholdPct must be below memoryThrottleThresholdPct");
+        }
+
+        Ok(())
+    }
+
+    /// Returns `false` for workers that can't be booted again from their
+    /// options alone, or whose options must not be written to disk.
+    fn is_persistable(&self) -> bool {
+        self.secrets.is_empty()
+            && self.maybe_eszip.is_none()
+            && self.maybe_module_code.is_none()
+            && self.jsx_import_source_config.is_none()
+    }
+
+    /// Turns the options into the ones of the worker context. The main
+    /// module, if any, is the base of the URL of the JSX import source.
+    pub fn into_worker_options(
+        self,
+        main_module: Option<&ModuleSpecifier>,
+    ) -> Result<WorkerContextInitOpts, AnyError> {
+        self.validate_memory_thresholds()
+            .map_err(|err| type_error(err.to_string()))?;
+
+        let persisted_options = self
+            .is_persistable()
+            .then(|| serde_json::to_value(&self).map(Arc::new))
+            .transpose()?;
 
         let UserWorkerCreateOptions {
             service_path,
             no_module_cache,
             import_map_path,
             env_vars,
+            secrets,
             force_create,
             net_access_disabled,
+            allow_ffi,
+            allow_subprocess,
+            allow_raw_net,
+            allow_image_ops,
+            image_transform_concurrency,
+            crypto_ops_per_second,
+            crypto_keys,
+            redis_commands_per_second,
+            redis_namespace,
+            publish_topics,
+            publish_queue_quota,
+            state_checkpoint_path,
+            state_checkpoint_interval_ms,
+            http_proxy,
+            tls_policy,
+            verify_jwt,
+            jwt_audience,
+            cors,
+            ip_access_list,
+            handler_export,
+            request_body,
+            response_body_max_bytes,
+            max_connections,
             allow_remote_modules,
             custom_module_root,
             maybe_eszip,
+            maybe_eszip_signature,
             maybe_entrypoint,
             maybe_module_code,
 
             memory_limit_mb,
             low_memory_multiplier,
+            low_memory_max_allowance_bytes,
+            low_memory_max_extensions,
+            memory_warning_threshold_pct,
+            memory_throttle_threshold_pct,
+            memory_termination_threshold_pct,
             worker_timeout_ms,
+            billing_mode,
             cpu_time_soft_limit_ms,
             cpu_time_hard_limit_ms,
+            cpu_time_per_request_limit_ms,
+            cpu_time_busy_threshold_pct,
+            resource_leak_threshold,
+            termination_grace_period_ms,
+            priority,
+            labels,
+            deterministic_seed,
+            timer_resolution_ms,
+            wasi_permissions,
+            strict_isolation,
+            db_connection_quota,
+            tmp_dir_quota_mb,
             jsx_import_source_config,
             decorator_type: maybe_decorator,
-        } = opts;
+        } = self;
 
         let mut env_vars_map = HashMap::new();
         for (key, value) in env_vars {
@@ -126,8 +338,14 @@ pub async fn op_user_worker_create(
                     default_specifier: jsx_import_source_config.default_specifier,
                     module: jsx_import_source_config.module,
                     base_url: {
-                        let main = op_state.borrow::<ModuleSpecifier>().to_string();
-                        deno_core::resolve_url_or_path(&main, std::env::current_dir()?.as_path())?
+                        let Some(main) = main_module else {
+                            bail!("the base url of the JSX import source is unknown");
+                        };
+
+                        deno_core::resolve_url_or_path(
+                            main.as_str(),
+                            std::env::current_dir()?.as_path(),
+                        )?
                     },
                 })
             } else {
@@ -135,7 +353,7 @@ pub async fn op_user_worker_create(
             }
         };
 
-        let user_worker_options = WorkerContextInitOpts {
+        Ok(WorkerContextInitOpts {
             service_path: PathBuf::from(service_path),
             no_module_cache,
             import_map_path,
@@ -143,28 +361,90 @@ pub async fn op_user_worker_create(
             events_rx: None,
             timing: None,
             maybe_eszip: maybe_eszip.map(EszipPayloadKind::JsBufferKind),
+            maybe_eszip_signature,
             maybe_entrypoint,
             maybe_module_code: maybe_module_code.map(|v| v.into()),
             maybe_decorator,
             conf: WorkerRuntimeOpts::UserWorker(UserWorkerRuntimeOpts {
                 memory_limit_mb,
                 low_memory_multiplier,
+                low_memory_max_allowance_bytes,
+                low_memory_max_extensions,
+                memory_warning_threshold_pct,
+                memory_throttle_threshold_pct,
+                memory_termination_threshold_pct,
                 worker_timeout_ms,
+                billing_mode,
                 cpu_time_soft_limit_ms,
                 cpu_time_hard_limit_ms,
+                cpu_time_per_request_limit_ms,
+                cpu_time_busy_threshold_pct,
+                resource_leak_threshold,
+                termination_grace_period_ms,
+                priority,
+                labels,
+                secrets: WorkerSecrets::new(secrets.into_iter().collect()),
+                deterministic_seed,
+                timer_resolution_ms,
+                wasi_permissions,
+                strict_isolation,
+                db_connection_quota,
+                tmp_dir_quota_mb,
                 force_create,
                 net_access_disabled,
+                allow_ffi,
+                allow_subprocess,
+                allow_raw_net,
+                allow_image_ops,
+                image_transform_concurrency,
+                crypto_ops_per_second,
+                crypto_keys,
+                redis_commands_per_second,
+                redis_namespace,
+                publish_topics,
+                publish_queue_quota,
+                state_checkpoint_path,
+                state_checkpoint_interval_ms,
+                http_proxy,
+                tls_policy,
+                verify_jwt,
+                jwt_audience,
+                cors,
+                ip_access_list,
+                handler_export,
+                request_body,
+                response_body_max_bytes,
+                max_connections,
+                direct_request_bridge: false,
                 allow_remote_modules,
                 custom_module_root,
+                persisted_options,
+                diagnostics: None,
                 key: None,
                 pool_msg_tx: None,
                 events_msg_tx: None,
                 cancel: None,
+                request_metrics: None,
                 service_path: None,
             }),
             static_patterns: vec![],
             maybe_jsx_import_source_config: jsx_import_conf,
-        };
+        })
+    }
+}
+
+#[op2(async)]
+#[string]
+pub async fn op_user_worker_create(
+    state: Rc<RefCell<OpState>>,
+    #[serde] opts: UserWorkerCreateOptions,
+) -> Result<String, AnyError> {
+    let result_rx = {
+        let op_state = state.borrow();
+        let tx = op_state.borrow::<UserWorkerMsgsSender>();
+        let (result_tx, result_rx) = oneshot::channel::<Result<CreateUserWorkerResult, Error>>();
+        let user_worker_options =
+            opts.into_worker_options(op_state.try_borrow::<ModuleSpecifier>())?;
 
         tx.send(UserWorkerMsgs::Create(user_worker_options, result_tx))?;
         result_rx
@@ -181,17 +461,121 @@ pub async fn op_user_worker_create(
     // channel returns a Result<T, E>, we need to unwrap it first;
     let result = result.unwrap();
     match result {
-        Err(e) => Err(custom_error("InvalidWorkerCreation", e.to_string())),
+        Err(e) => match e.downcast_ref() {
+            Some(
+                err @ (WorkerError::BootQueueFull
+                | WorkerError::BootQueueTimedOut
+                | WorkerError::RuntimeDraining
+                | WorkerError::WorkersThrottled
+                | WorkerError::LiveWorkersSaturated),
+            ) => Err(custom_error("WorkerBootThrottled", err.to_string())),
+
+            _ => Err(custom_error("InvalidWorkerCreation", e.to_string())),
+        },
         Ok(res) => Ok(res.key.to_string()),
     }
 }
 
+fn send_pool_msg<T>(
+    state: &Rc<RefCell<OpState>>,
+    msg_fn: impl FnOnce(oneshot::Sender<T>) -> UserWorkerMsgs,
+) -> Result<oneshot::Receiver<T>, AnyError> {
+    let op_state = state.borrow();
+    let tx = op_state.borrow::<UserWorkerMsgsSender>();
+    let (result_tx, result_rx) = oneshot::channel::<T>();
+
+    tx.send(msg_fn(result_tx))
+        .map_err(|_| type_error("user worker pool is not available"))?;
+
+    Ok(result_rx)
+}
+
+#[op2(async)]
+#[serde]
+pub async fn op_user_worker_list(
+    state: Rc<RefCell<OpState>>,
+) -> Result<Vec<UserWorkerInfo>, AnyError> {
+    let result_rx = send_pool_msg(&state, UserWorkerMsgs::List)?;
+
+    Ok(result_rx.await?)
+}
+
+#[op2(async)]
+pub async fn op_user_worker_terminate(
+    state: Rc<RefCell<OpState>>,
+    #[string] key: String,
+) -> Result<bool, AnyError> {
+    let key_parsed = Uuid::try_parse(key.as_str())?;
+    let result_rx = send_pool_msg(&state, |tx| UserWorkerMsgs::Terminate(key_parsed, tx))?;
+
+    Ok(result_rx.await?)
+}
+
+#[op2(async)]
+#[serde]
+pub async fn op_user_worker_lookup_by_labels(
+    state: Rc<RefCell<OpState>>,
+    #[serde] selector: HashMap<String, String>,
+) -> Result<Vec<UserWorkerInfo>, AnyError> {
+    let result_rx = send_pool_msg(&state, |tx| UserWorkerMsgs::LookupByLabels(selector, tx))?;
+
+    Ok(result_rx.await?)
+}
+
+#[op2(async)]
+pub async fn op_user_worker_terminate_by_labels(
+    state: Rc<RefCell<OpState>>,
+    #[serde] selector: HashMap<String, String>,
+) -> Result<u32, AnyError> {
+    if selector.is_empty() {
+        return Err(type_error("labels must not be empty"));
+    }
+
+    let result_rx = send_pool_msg(&state, |tx| UserWorkerMsgs::TerminateByLabels(selector, tx))?;
+
+    Ok(result_rx.await? as u32)
+}
+
+#[op2(async)]
+pub async fn op_user_worker_pool_drain(state: Rc<RefCell<OpState>>) -> Result<(), AnyError> {
+    let result_rx = send_pool_msg(&state, UserWorkerMsgs::Drain)?;
+
+    Ok(result_rx.await?)
+}
+
+#[op2(async)]
+#[serde]
+pub async fn op_user_worker_pool_config(
+    state: Rc<RefCell<OpState>>,
+) -> Result<UserWorkerPoolConfig, AnyError> {
+    let result_rx = send_pool_msg(&state, UserWorkerMsgs::Config)?;
+
+    Ok(result_rx.await?)
+}
+
+#[op2(async)]
+#[serde]
+pub async fn op_user_worker_pool_usage(
+    state: Rc<RefCell<OpState>>,
+) -> Result<Vec<TenantUsage>, AnyError> {
+    let result_rx = send_pool_msg(&state, UserWorkerMsgs::Usage)?;
+
+    Ok(result_rx.await?)
+}
+
+#[op2(async)]
+pub async fn op_runtime_drain(state: Rc<RefCell<OpState>>) -> Result<(), AnyError> {
+    let result_rx = send_pool_msg(&state, UserWorkerMsgs::DrainRuntime)?;
+
+    Ok(result_rx.await?)
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct UserWorkerRequest {
     method: ByteString,
     url: String,
-    headers: Vec<(String, String)>,
+    headers: Vec<(ByteString, ByteString)>,
     has_body: bool,
 }
 
@@ -369,11 +753,13 @@ pub fn op_user_worker_fetch_build(
     }
 
     // set the request headers
+    let mut header_map = HeaderMap::with_capacity(req.headers.len());
+
     for (key, value) in req.headers {
         if !key.is_empty() {
-            let header_name = HeaderName::try_from(key).unwrap();
+            let header_name = headers::intern_name(&key)?;
             let mut header_value =
-                HeaderValue::try_from(value).unwrap_or(HeaderValue::from_static(""));
+                HeaderValue::from_bytes(&value).unwrap_or(HeaderValue::from_static(""));
 
             // if request has no body explicitly set the content-length to 0
             if !req.has_body
@@ -383,11 +769,14 @@ pub fn op_user_worker_fetch_build(
                 header_value = HeaderValue::from(0);
             }
 
-            builder = builder.header(header_name, header_value);
+            header_map.append(header_name, header_value);
         }
     }
 
-    let req = builder.body(body)?;
+    let mut req = builder.body(body)?;
+
+    *req.headers_mut() = header_map;
+
     let request_rid = state.resource_table.add(UserWorkerRequestResource(req));
 
     Ok(UserWorkerBuiltRequest {
@@ -406,12 +795,10 @@ pub async fn op_user_worker_fetch_send(
     #[smi] stream_rid: ResourceId,
     #[smi] watcher_rid: Option<ResourceId>,
 ) -> Result<UserWorkerResponse, AnyError> {
-    let (tx, req) = {
+    let (tx, mut req) = {
         let (tx, mut req) = {
             let mut op_state = state.borrow_mut();
-            let tx = op_state
-                .borrow::<mpsc::UnboundedSender<UserWorkerMsgs>>()
-                .clone();
+            let tx = op_state.borrow::<UserWorkerMsgsSender>().clone();
 
             let req = Rc::try_unwrap(
                 op_state
@@ -456,7 +843,15 @@ pub async fn op_user_worker_fetch_send(
         .map(Rc::try_unwrap);
 
     let conn_token = match conn_token {
-        Some(Ok(it)) => it.get(),
+        Some(Ok(it)) => {
+            // NOTE: The pool checks the access list of the worker against the
+            // address of the connection, not against a header.
+            if let Some(conn_info) = it.1.clone() {
+                req.0.extensions_mut().insert(conn_info);
+            }
+
+            it.get()
+        }
         Some(Err(_)) => {
             error!("failed to unwrap connection watcher");
             None
@@ -498,7 +893,16 @@ pub async fn op_user_worker_fetch_send(
                     return Err(custom_error("WorkerRequestCancelled", err.to_string()));
                 }
 
-                None => {
+                Some(
+                    err @ WorkerError::Connection {
+                        kind: ConnectionErrorKind::WorkerReset,
+                        ..
+                    },
+                ) => {
+                    return Err(custom_error("ConnectionReset", err.to_string()));
+                }
+
+                _ => {
                     return Err(custom_error("InvalidWorkerResponse", err.to_string()));
                 }
             }
@@ -507,14 +911,7 @@ pub async fn op_user_worker_fetch_send(
 
     drop(request_body_guard);
 
-    let mut headers = vec![];
-    for (key, value) in res.headers().iter() {
-        headers.push((
-            ByteString::from(key.as_str()),
-            ByteString::from(value.to_str().unwrap_or_default()),
-        ));
-    }
-
+    let headers = headers::to_pairs(res.headers());
     let status = res.status().as_u16();
     let status_text = res
         .status()
@@ -559,3 +956,31 @@ impl Stream for BodyStream {
         self.0.poll_recv(cx)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn create_with_thresholds(warning: u64, throttle: u64, termination: u64) -> bool {
+        UserWorkerCreateOptions::from_partial(serde_json::json!({
+            "servicePath": "./meow",
+            "memoryWarningThresholdPct": warning,
+            "memoryThrottleThresholdPct": throttle,
+            "memoryTerminationThresholdPct": termination,
+        }))
+        .and_then(|it| it.into_worker_options(None))
+        .is_ok()
+    }
+
+    #[test]
+    fn test_rejects_soft_memory_thresholds_not_below_hard_ones() {
+        assert!(create_with_thresholds(70, 85, 100));
+        assert!(create_with_thresholds(0, 0, 100));
+        assert!(create_with_thresholds(70, 0, 0));
+
+        assert!(!create_with_thresholds(90, 85, 100));
+        assert!(!create_with_thresholds(70, 100, 100));
+        assert!(!create_with_thresholds(80, 0, 80));
+        assert!(!create_with_thresholds(0, 120, 0));
+    }
+}
@@ -14,6 +14,11 @@ const {
 const {
 	op_user_worker_fetch_send,
 	op_user_worker_create,
+	op_user_worker_list,
+	op_user_worker_terminate,
+	op_user_worker_lookup_by_labels,
+	op_user_worker_terminate_by_labels,
+	op_user_worker_pool_drain,
 } = core.ensureFastOps();
 
 const NO_SUPABASE_TAG_WARN_MSG = `Unable to find the supabase tag from the request instance.\n\
@@ -130,17 +135,60 @@ class UserWorker {
 		const readyOptions = {
 			memoryLimitMb: 512,
 			lowMemoryMultiplier: 5,
+			lowMemoryMaxAllowanceBytes: 0,
+			lowMemoryMaxExtensions: 0,
+			memoryWarningThresholdPct: 70,
+			memoryThrottleThresholdPct: 85,
+			memoryTerminationThresholdPct: 100,
 			workerTimeoutMs: 5 * 60 * 1000,
+			billingMode: 'wallClock',
 			cpuTimeSoftLimitMs: 50,
 			cpuTimeHardLimitMs: 100,
+			cpuTimePerRequestLimitMs: 0,
+			cpuTimeBusyThresholdPct: 0,
+			resourceLeakThreshold: 0,
+			terminationGracePeriodMs: 0,
+			priority: 'interactive',
+			labels: {},
+			deterministicSeed: null,
+			timerResolutionMs: 0,
+			wasiPermissions: { fs: false, clock: true, random: true },
+			strictIsolation: false,
+			dbConnectionQuota: 0,
+			tmpDirQuotaMb: 64,
 			noModuleCache: false,
 			importMapPath: null,
 			envVars: [],
+			secrets: [],
 			forceCreate: false,
 			netAccessDisabled: false,
+			allowFfi: false,
+			allowSubprocess: false,
+			allowRawNet: false,
+			allowImageOps: false,
+			imageTransformConcurrency: 2,
+			cryptoOpsPerSecond: 10,
+			cryptoKeys: [],
+			redisCommandsPerSecond: 0,
+			redisNamespace: null,
+			publishTopics: [],
+			publishQueueQuota: 0,
+			stateCheckpointPath: null,
+			stateCheckpointIntervalMs: 5000,
+			httpProxy: null,
+			tlsPolicy: null,
+			verifyJwt: true,
+			jwtAudience: null,
+			cors: null,
+			ipAccessList: null,
+			handlerExport: null,
+			requestBody: null,
+			responseBodyMaxBytes: 0,
+			maxConnections: 0,
 			allowRemoteModules: true,
 			customModuleRoot: '',
 			maybeEszip: null,
+			maybeEszipSignature: null,
 			maybeEntrypoint: null,
 			maybeModuleCode: null,
 			...opts,
@@ -156,6 +204,30 @@ class UserWorker {
 
 		return new UserWorker(key);
 	}
+
+	async terminate() {
+		return await op_user_worker_terminate(this.key);
+	}
+
+	static async list() {
+		return await op_user_worker_list();
+	}
+
+	static async terminate(key) {
+		return await op_user_worker_terminate(key);
+	}
+
+	static async listByLabels(labels) {
+		return await op_user_worker_lookup_by_labels(labels ?? {});
+	}
+
+	static async terminateByLabels(labels) {
+		return await op_user_worker_terminate_by_labels(labels ?? {});
+	}
+
+	static async drain() {
+		await op_user_worker_pool_drain();
+	}
 }
 
 const SUPABASE_USER_WORKERS = UserWorker;